@@ -0,0 +1,137 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+/*
+ * C API for running touchHLE as a library. Link against the touchHLE shared
+ * library (libtouchHLE.so, libtouchHLE.dylib or touchHLE.dll).
+ *
+ * This is implemented in src/c_api.rs, which must be kept in sync with this
+ * file. See also src/embedding.rs.
+ *
+ * Typical use:
+ *
+ *     touchHLE_Emulator *emu = touchHLE_create();
+ *     touchHLE_set_option(emu, "--offscreen");
+ *     touchHLE_load_app(emu, "Some.app");
+ *     while (touchHLE_run_frame(emu) == TOUCHHLE_OK) {
+ *         uint32_t width, height;
+ *         const uint8_t *pixels = touchHLE_get_framebuffer(emu, &width,
+ *                                                          &height);
+ *         ...
+ *     }
+ *     touchHLE_destroy(emu);
+ *
+ * Functions taking a touchHLE_Emulator* must not be called concurrently for
+ * the same emulator. If the emulator is NULL, they return TOUCHHLE_ERROR or
+ * NULL. Strings are UTF-8 and null-terminated.
+ *
+ * Options files (e.g. touchHLE_default_options.txt) are not applied: only
+ * options set with touchHLE_set_option() are used.
+ *
+ * Only one app can run at a time in a process. touchHLE_run_frame() fails if
+ * another emulator's app is still running.
+ */
+
+#ifndef TOUCHHLE_H
+#define TOUCHHLE_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Return values. On TOUCHHLE_ERROR, see touchHLE_get_last_error(). */
+#define TOUCHHLE_OK 0
+#define TOUCHHLE_EXITED 1
+#define TOUCHHLE_TIMED_OUT 2
+#define TOUCHHLE_ERROR (-1)
+
+/* Values for touchHLE_InputEvent.type. */
+#define TOUCHHLE_EVENT_TOUCH_DOWN 0
+#define TOUCHHLE_EVENT_TOUCH_MOVE 1
+#define TOUCHHLE_EVENT_TOUCH_UP 2
+#define TOUCHHLE_EVENT_ACCELERATION 3
+#define TOUCHHLE_EVENT_QUIT 4
+
+typedef struct touchHLE_Emulator touchHLE_Emulator;
+
+/*
+ * For touch events, finger identifies the touch, and x and y are in points,
+ * with the origin at the top-left of the screen in portrait orientation.
+ * For acceleration events, x, y and z are in units of g-force, and stay in
+ * effect until the next such event.
+ */
+typedef struct {
+    int type;
+    uint32_t finger;
+    float x;
+    float y;
+    float z;
+} touchHLE_InputEvent;
+
+/* Create an emulator. No app is loaded yet, so options can be set first. */
+touchHLE_Emulator *touchHLE_create(void);
+/* Destroy an emulator. If an app is running, it is asked to quit. */
+void touchHLE_destroy(touchHLE_Emulator *emulator);
+
+/*
+ * Get a description of the last error. The string remains valid until the
+ * next call that fails. Returns NULL if emulator is NULL.
+ */
+const char *touchHLE_get_last_error(const touchHLE_Emulator *emulator);
+
+/*
+ * Set an option, using the same syntax as the command line, e.g.
+ * "--scale-hack=2". This only affects apps loaded afterwards.
+ */
+int touchHLE_set_option(touchHLE_Emulator *emulator, const char *option);
+
+/*
+ * Start running the app at path (an .app directory or .ipa file). Only one
+ * app can be loaded per emulator.
+ */
+int touchHLE_load_app(touchHLE_Emulator *emulator, const char *path);
+
+/*
+ * Let the app run until it presents its next frame. Returns TOUCHHLE_OK if
+ * there is a new frame, TOUCHHLE_EXITED if the app exited (see
+ * touchHLE_get_exit_code()), or TOUCHHLE_ERROR if it couldn't be started or
+ * touchHLE crashed. This waits however long that takes.
+ */
+int touchHLE_run_frame(touchHLE_Emulator *emulator);
+/*
+ * Like touchHLE_run_frame(), but gives up waiting after timeout_ms
+ * milliseconds and returns TOUCHHLE_TIMED_OUT. The app keeps running, and the
+ * next call continues waiting for the same frame.
+ */
+int touchHLE_run_frame_timeout(touchHLE_Emulator *emulator,
+                               uint32_t timeout_ms);
+
+/*
+ * Get the app's exit code, after touchHLE_run_frame() returned
+ * TOUCHHLE_EXITED. Returns TOUCHHLE_ERROR if emulator is NULL.
+ */
+int touchHLE_get_exit_code(const touchHLE_Emulator *emulator);
+
+/* Queue an input event, to be delivered on the next touchHLE_run_frame(). */
+int touchHLE_push_event(touchHLE_Emulator *emulator,
+                        const touchHLE_InputEvent *event);
+
+/*
+ * Get the pixels of the frame from the last successful touchHLE_run_frame(),
+ * as RGBA in top-to-bottom row order, and write its size to width and height
+ * (either may be NULL). Returns NULL if there is no frame yet or emulator is
+ * NULL. The pointer remains valid until the next call to touchHLE_run_frame()
+ * or touchHLE_destroy().
+ */
+const uint8_t *touchHLE_get_framebuffer(const touchHLE_Emulator *emulator,
+                                        uint32_t *width, uint32_t *height);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TOUCHHLE_H */
@@ -14,6 +14,7 @@
 use crate::cpu::Cpu;
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead};
 use crate::Environment;
+use std::fmt::Debug;
 
 /// The register number of the frame pointer in Apple's ABI.
 pub const FRAME_POINTER: usize = 7;
@@ -170,7 +171,21 @@ impl GuestFunction {
 /// See also [GuestFunction::call] and
 /// [GuestFunction::call_without_pushing_stack_frame].
 pub trait CallFromGuest {
-    fn call_from_guest(&self, env: &mut Environment);
+    fn call_from_guest(&self, env: &mut Environment) {
+        self.call_from_guest_traced(env, None)
+    }
+    /// Like [Self::call_from_guest], but the decoded arguments and return value
+    /// are passed to `tracer`, if there is one.
+    fn call_from_guest_traced(&self, env: &mut Environment, tracer: Option<&mut dyn CallTracer>);
+}
+
+/// Receives the arguments and return value of a [CallFromGuest] call, in the
+/// form of their [Debug] representation. This is used for tracing.
+pub trait CallTracer {
+    /// Called after the arguments have been read, before the call.
+    fn trace_args(&mut self, env: &mut Environment, args: &[&dyn Debug]);
+    /// Called after the call, before the return value is written.
+    fn trace_return(&mut self, env: &mut Environment, retval: &dyn Debug);
 }
 
 macro_rules! impl_CallFromGuest {
@@ -179,7 +194,11 @@ macro_rules! impl_CallFromGuest {
             where R: GuestRet, $($P: GuestArg,)* {
             // ignore warnings for the zero-argument case
             #[allow(unused_variables, unused_mut, clippy::unused_unit)]
-            fn call_from_guest(&self, env: &mut Environment) {
+            fn call_from_guest_traced(
+                &self,
+                env: &mut Environment,
+                mut tracer: Option<&mut dyn CallTracer>,
+            ) {
                 let mut reg_offset = 0;
                 let regs = env.cpu.regs();
                 let retval_ptr = R::SIZE_IN_MEM.map(|_| {
@@ -189,8 +208,14 @@ macro_rules! impl_CallFromGuest {
                     ($(read_next_arg::<$P>(&mut reg_offset, regs, Ptr::from_bits(regs[Cpu::SP]), &env.mem),)*)
                 };
                 log_dbg!("CallFromGuest {:?}", args);
+                if let Some(ref mut tracer) = tracer {
+                    tracer.trace_args(env, &[$(&args.$p as &dyn Debug),*]);
+                }
                 let retval = self(env, $(args.$p),*);
                 log_dbg!("CallFromGuest => {:?}", retval);
+                if let Some(tracer) = tracer {
+                    tracer.trace_return(env, &retval);
+                }
                 if let Some(retval_ptr) = retval_ptr {
                     retval.to_mem(retval_ptr, &mut env.mem);
                 } else {
@@ -202,7 +227,11 @@ macro_rules! impl_CallFromGuest {
             where R: GuestRet, $($P: GuestArg,)* {
             // ignore warnings for the zero-argument case
             #[allow(unused_variables, unused_mut, clippy::unused_unit)]
-            fn call_from_guest(&self, env: &mut Environment) {
+            fn call_from_guest_traced(
+                &self,
+                env: &mut Environment,
+                mut tracer: Option<&mut dyn CallTracer>,
+            ) {
                 let mut reg_offset = 0;
                 let regs = env.cpu.regs();
                 let retval_ptr = R::SIZE_IN_MEM.map(|_| {
@@ -216,8 +245,14 @@ macro_rules! impl_CallFromGuest {
                     stack_pointer: Ptr::from_bits(regs[Cpu::SP])
                 });
                 log_dbg!("CallFromGuest {:?}, ...{:?}", args, va_list);
+                if let Some(ref mut tracer) = tracer {
+                    tracer.trace_args(env, &[$(&args.$p as &dyn Debug,)* &va_list]);
+                }
                 let retval = self(env, $(args.$p,)* va_list);
                 log_dbg!("CallFromGuest => {:?}", retval);
+                if let Some(tracer) = tracer {
+                    tracer.trace_return(env, &retval);
+                }
                 if let Some(retval_ptr) = retval_ptr {
                     retval.to_mem(retval_ptr, &mut env.mem);
                 } else {
@@ -35,9 +35,9 @@ use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
-struct AppInfo {
-    path: PathBuf,
-    display_name: String,
+pub struct AppInfo {
+    pub path: PathBuf,
+    pub display_name: String,
     icon: Option<Image>,
     /// `NSString*`
     display_name_ns_string: Option<id>,
@@ -74,7 +74,7 @@ pub fn app_picker(options: Options) -> Result<(PathBuf, Environment), String> {
     show_app_picker_gui(options, apps)
 }
 
-fn enumerate_apps(apps_dir: &Path) -> Result<Vec<AppInfo>, std::io::Error> {
+pub fn enumerate_apps(apps_dir: &Path) -> Result<Vec<AppInfo>, std::io::Error> {
     let mut apps = Vec::new();
     for app in std::fs::read_dir(apps_dir)? {
         let app_path = app?.path();
@@ -3,7 +3,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-//! Audio file decoding and OpenAL bindings.
+//! Audio file decoding, OpenAL bindings and audio output.
 //!
 //! The audio file decoding support is an abstraction over various libraries
 //! (currently [caf], [hound], and dr_mp3), usage of which should be confined to
@@ -14,13 +14,15 @@
 
 mod aac;
 mod ima4;
+pub mod output;
 
 pub use ima4::decode_ima4;
 use touchHLE_dr_mp3_wrapper as dr_mp3;
 pub use touchHLE_openal_soft_wrapper as openal;
 
-use crate::fs::{Fs, GuestPath};
-use std::io::Cursor;
+use crate::fs::{Fs, GuestFile, GuestPath};
+use std::io::{BufReader, Seek};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum AudioFormat {
@@ -44,49 +46,87 @@ pub struct AudioDescription {
     pub bits_per_channel: u32,
 }
 
+/// An MPEG-4 movie file (e.g. `.m4v` or `.mp4`). Decoding of video isn't
+/// supported, so this only provides the duration and the soundtrack.
+pub struct Movie {
+    pub duration: Duration,
+    /// The AAC-LC soundtrack, if there is one. Like any [AudioFile], it is
+    /// decoded on demand.
+    pub soundtrack: Option<AudioFile>,
+}
+
+impl Movie {
+    pub fn open_for_reading<P: AsRef<GuestPath>>(path: P, fs: &Fs) -> Result<Self, ()> {
+        let file = fs.open(path.as_ref())?;
+        let Ok((duration, soundtrack)) = aac::open_movie(file) else {
+            log!(
+                "Could not open movie file at path {:?}, likely an unimplemented file format.",
+                path.as_ref()
+            );
+            return Err(());
+        };
+        Ok(Movie {
+            duration,
+            soundtrack: soundtrack.map(|decoder| AudioFile(AudioFileInner::Aac(decoder))),
+        })
+    }
+}
+
+/// An audio file that is read from and decoded on demand, so that long files
+/// (e.g. background music) don't have to be loaded all at once.
 pub struct AudioFile(AudioFileInner);
 enum AudioFileInner {
-    Wave(hound::WavReader<Cursor<Vec<u8>>>),
-    Caf(caf::CafPacketReader<Cursor<Vec<u8>>>),
-    Mp3(dr_mp3::Mp3DecodedToPcm),
-    Aac(aac::AacDecodedToPcm),
+    Wave(hound::WavReader<BufReader<GuestFile>>),
+    Caf(caf::CafPacketReader<BufReader<GuestFile>>),
+    Mp3(dr_mp3::Mp3Decoder),
+    Aac(aac::AacDecoder),
 }
 
 impl AudioFile {
     pub fn open_for_reading<P: AsRef<GuestPath>>(path: P, fs: &Fs) -> Result<Self, ()> {
-        // TODO: it would be better not to load the whole file at once
-        let bytes = fs.read(path.as_ref())?;
+        let mut file = BufReader::new(fs.open(path.as_ref())?);
 
         // Both WavReader::new() and CafPacketReader::new() consume the reader
-        // (in this case, a Cursor) passed to them. This is a bit annoying
-        // considering we don't know which is appropriate for the file without
-        // trying both. This is worked around here by using temporary readers
-        // for checking if the file is the supported format, then recreating the
-        // reader if that works.
-
-        if hound::WavReader::new(Cursor::new(&bytes)).is_ok() {
-            let reader = hound::WavReader::new(Cursor::new(bytes)).unwrap();
-            Ok(AudioFile(AudioFileInner::Wave(reader)))
-        } else if caf::CafPacketReader::new(Cursor::new(&bytes), vec![]).is_ok() {
-            let reader = caf::CafPacketReader::new(Cursor::new(bytes), vec![]).unwrap();
-            Ok(AudioFile(AudioFileInner::Caf(reader)))
-        // TODO: Real MP3 container handling. Currently we are immediately
-        // decoding the entire file to PCM and acting as if it's a PCM file,
-        // simply because because this is easier. Full MP3 support would require
-        // a lot of changes in Audio Toolbox.
-        } else if let Ok(pcm) = dr_mp3::decode_mp3_to_pcm(&bytes) {
-            Ok(AudioFile(AudioFileInner::Mp3(pcm)))
-        // TODO: Real MP4 container handling for AAC. The situation is the same
-        // as for MP3.
-        } else if let Ok(pcm) = aac::decode_aac_to_pcm(Cursor::new(bytes)) {
-            Ok(AudioFile(AudioFileInner::Aac(pcm)))
-        } else {
-            log!(
-                "Could not decode audio file at path {:?}, likely an unimplemented file format.",
-                path.as_ref()
-            );
-            Err(())
+        // passed to them. This is a bit annoying considering we don't know
+        // which is appropriate for the file without trying both. This is
+        // worked around here by using temporary readers that borrow the file
+        // for checking if the file is the supported format, then rewinding the
+        // file and creating the real reader if that works.
+
+        let is_wave = hound::WavReader::new(&mut file).is_ok();
+        file.rewind().map_err(|_| ())?;
+        if is_wave {
+            let reader = hound::WavReader::new(file).map_err(|_| ())?;
+            return Ok(AudioFile(AudioFileInner::Wave(reader)));
+        }
+
+        let is_caf = caf::CafPacketReader::new(&mut file, vec![]).is_ok();
+        file.rewind().map_err(|_| ())?;
+        if is_caf {
+            let reader = caf::CafPacketReader::new(file, vec![]).map_err(|_| ())?;
+            return Ok(AudioFile(AudioFileInner::Caf(reader)));
+        }
+
+        // TODO: Real MP4 container and MP3 handling. Currently we are decoding
+        // the file to PCM and acting as if it's a PCM file, simply because this
+        // is easier. Full support would require a lot of changes in Audio
+        // Toolbox.
+        // MP4 is tried first because it's recognized by its header, whereas
+        // dr_mp3 looks for MP3 frames anywhere in the file.
+        if let Ok(decoder) = aac::AacDecoder::new(file.into_inner()) {
+            return Ok(AudioFile(AudioFileInner::Aac(decoder)));
+        }
+        // The AAC decoder took the file, so it has to be opened again.
+        let file = BufReader::new(fs.open(path.as_ref())?);
+        if let Ok(decoder) = dr_mp3::Mp3Decoder::new(file) {
+            return Ok(AudioFile(AudioFileInner::Mp3(decoder)));
         }
+
+        log!(
+            "Could not decode audio file at path {:?}, likely an unimplemented file format.",
+            path.as_ref()
+        );
+        Err(())
     }
 
     pub fn audio_description(&self) -> AudioDescription {
@@ -155,12 +195,12 @@ impl AudioFile {
                     bits_per_channel,
                 }
             }
-            AudioFileInner::Mp3(dr_mp3::Mp3DecodedToPcm {
+            AudioFileInner::Mp3(dr_mp3::Mp3Decoder {
                 sample_rate,
                 channels,
                 ..
             })
-            | AudioFileInner::Aac(aac::AacDecodedToPcm {
+            | AudioFileInner::Aac(aac::AacDecoder {
                 sample_rate,
                 channels,
                 ..
@@ -202,16 +242,22 @@ impl AudioFile {
                 // variable size not implemented
                 u64::from(self.packet_size_fixed()) * self.packet_count()
             }
-            AudioFileInner::Mp3(dr_mp3::Mp3DecodedToPcm { ref bytes, .. })
-            | AudioFileInner::Aac(aac::AacDecodedToPcm { ref bytes, .. }) => bytes.len() as u64,
+            AudioFileInner::Mp3(dr_mp3::Mp3Decoder {
+                frame_count,
+                channels,
+                ..
+            })
+            | AudioFileInner::Aac(aac::AacDecoder {
+                frame_count,
+                channels,
+                ..
+            }) => frame_count * u64::from(channels) * 2,
         }
     }
 
     pub fn packet_count(&self) -> u64 {
         match self.0 {
-            AudioFileInner::Wave(_)
-            | AudioFileInner::Mp3(dr_mp3::Mp3DecodedToPcm { .. })
-            | AudioFileInner::Aac(aac::AacDecodedToPcm { .. }) => {
+            AudioFileInner::Wave(_) | AudioFileInner::Mp3(_) | AudioFileInner::Aac(_) => {
                 // never variable-size
                 self.byte_count() / u64::from(self.packet_size_fixed())
             }
@@ -304,14 +350,223 @@ impl AudioFile {
                 }
                 Ok(byte_offset)
             }
-            AudioFileInner::Mp3(dr_mp3::Mp3DecodedToPcm { ref bytes, .. })
-            | AudioFileInner::Aac(aac::AacDecodedToPcm { ref bytes, .. }) => {
-                let bytes = bytes.get(offset as usize..).ok_or(())?;
-                let bytes_to_read = buffer.len().min(bytes.len());
-                let bytes = &bytes[..bytes_to_read];
-                buffer[..bytes_to_read].copy_from_slice(bytes);
-                Ok(bytes_to_read)
+            AudioFileInner::Mp3(_) | AudioFileInner::Aac(_) => {
+                if offset > self.byte_count() {
+                    return Err(());
+                }
+                match self.0 {
+                    AudioFileInner::Mp3(ref mut decoder) => {
+                        read_pcm_bytes(decoder.channels, offset, buffer, |first_frame, samples| {
+                            decoder.read_frames(first_frame, samples)
+                        })
+                    }
+                    AudioFileInner::Aac(ref mut decoder) => {
+                        read_pcm_bytes(decoder.channels, offset, buffer, |first_frame, samples| {
+                            decoder.read_frames(first_frame, samples)
+                        })
+                    }
+                    _ => unreachable!(),
+                }
             }
         }
     }
 }
+
+/// Read 16-bit PCM data from a decoder into `buffer`, starting at byte offset
+/// `offset`, which need not be at the start of a frame. `read_frames` has the
+/// same signature as the decoders' `read_frames` methods.
+fn read_pcm_bytes(
+    channels: u32,
+    offset: u64,
+    buffer: &mut [u8],
+    mut read_frames: impl FnMut(u64, &mut [i16]) -> Result<u64, ()>,
+) -> Result<usize, ()> {
+    let frame_size = channels as usize * 2;
+    let first_frame = offset / frame_size as u64;
+    let skip = (offset % frame_size as u64) as usize;
+    let frame_count = (skip + buffer.len()).div_ceil(frame_size);
+
+    let mut samples = vec![0i16; frame_count * channels as usize];
+    let frames_read = read_frames(first_frame, &mut samples)?;
+    let bytes: Vec<u8> = samples[..frames_read as usize * channels as usize]
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect();
+
+    let bytes = bytes.get(skip..).unwrap_or(&[]);
+    let bytes_to_read = buffer.len().min(bytes.len());
+    buffer[..bytes_to_read].copy_from_slice(&bytes[..bytes_to_read]);
+    Ok(bytes_to_read)
+}
+
+/// Decoder for a stream of compressed audio packets that are provided one at a
+/// time, as is done with Audio Queue Services, rather than read from a file.
+pub enum PacketDecoder {
+    Mp3(dr_mp3::Mp3PacketDecoder),
+    Aac(aac::AacPacketDecoder),
+}
+
+impl PacketDecoder {
+    pub fn new_mp3() -> Self {
+        PacketDecoder::Mp3(dr_mp3::Mp3PacketDecoder::new())
+    }
+
+    /// `magic_cookie` is the codec configuration (an `esds` descriptor or an
+    /// `AudioSpecificConfig`), if there is one.
+    pub fn new_aac(
+        sample_rate: u32,
+        channels: u32,
+        magic_cookie: Option<&[u8]>,
+    ) -> Result<Self, ()> {
+        aac::AacPacketDecoder::new(sample_rate, channels, magic_cookie).map(PacketDecoder::Aac)
+    }
+
+    /// Decode a packet, appending interleaved 16-bit PCM samples with
+    /// `channels` channels to `samples`. For MP3, the packet boundaries don't
+    /// matter and incomplete frames are kept until the next call.
+    pub fn decode_packet(
+        &mut self,
+        packet: &[u8],
+        channels: u32,
+        samples: &mut Vec<i16>,
+    ) -> Result<(), ()> {
+        match self {
+            PacketDecoder::Mp3(decoder) => {
+                decoder.decode(packet, channels, samples);
+                Ok(())
+            }
+            PacketDecoder::Aac(decoder) => decoder.decode_packet(packet, samples),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Make a mono 48kHz 64kbps MPEG-1 Layer III file. Each frame has a few
+    /// non-zero spectral lines at a different volume, so decoding from the
+    /// wrong position gives different samples.
+    fn make_mp3(frame_count: u32) -> Vec<u8> {
+        const FRAME_SIZE: usize = 192; // 144 * 64000 / 48000
+        const BIG_VALUES: u32 = 20;
+
+        fn push_bits(bits: &mut Vec<bool>, value: u32, count: u32) {
+            bits.extend((0..count).rev().map(|i| (value >> i) & 1 != 0));
+        }
+
+        let mut mp3 = Vec::new();
+        for frame in 0..frame_count {
+            let mut bits = Vec::new();
+            // Header: no CRC, bitrate index 5, sample rate index 1, mono.
+            push_bits(&mut bits, 0xfffb54c0, 32);
+            // Side info: main_data_begin, private_bits and scfsi.
+            push_bits(&mut bits, 0, 9 + 5 + 4);
+            for granule in 0..2 {
+                push_bits(&mut bits, BIG_VALUES * 3, 12); // part2_3_length
+                push_bits(&mut bits, BIG_VALUES, 9);
+                push_bits(&mut bits, 180 + (frame * 2 + granule) % 20, 8); // gain
+                push_bits(&mut bits, 0, 4 + 1); // scalefac_compress, no blocks
+                push_bits(&mut bits, 0b00001_00001_00001, 15); // Huffman table 1
+                push_bits(&mut bits, 0, 4 + 3 + 1 + 1 + 1);
+            }
+            // Main data: the pair (1, 0) is coded as 01 followed by the sign.
+            for granule in 0..2 {
+                for pair in 0..BIG_VALUES {
+                    push_bits(&mut bits, 0b010 | ((pair + granule) % 3 == 0) as u32, 3);
+                }
+            }
+            let start = mp3.len();
+            mp3.extend(bits.chunks(8).map(|byte| {
+                byte.iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (i, &bit)| acc | ((bit as u8) << (7 - i)))
+            }));
+            mp3.resize(start + FRAME_SIZE, 0);
+        }
+        mp3
+    }
+
+    #[test]
+    fn mp3_chunked_and_seeking_reads() {
+        let mp3 = make_mp3(20);
+
+        let mut decoder = dr_mp3::Mp3Decoder::new(Cursor::new(mp3.clone())).unwrap();
+        assert_eq!(decoder.channels, 1);
+        assert_eq!(decoder.sample_rate, 48000);
+        assert_eq!(decoder.frame_count, 20 * 1152);
+        let byte_count = decoder.frame_count as usize * 2;
+        let mut whole = vec![0u8; byte_count];
+        assert_eq!(
+            read_pcm_bytes(1, 0, &mut whole, |first_frame, samples| {
+                decoder.read_frames(first_frame, samples)
+            }),
+            Ok(byte_count)
+        );
+        assert!(whole.iter().any(|&byte| byte != 0));
+
+        // Reading sequentially in chunks that don't line up with PCM frames or
+        // MP3 frames gives the same data.
+        let mut decoder = dr_mp3::Mp3Decoder::new(Cursor::new(mp3.clone())).unwrap();
+        let mut chunked = Vec::new();
+        let mut buffer = [0u8; 999];
+        loop {
+            let bytes_read = read_pcm_bytes(1, chunked.len() as u64, &mut buffer, |f, s| {
+                decoder.read_frames(f, s)
+            })
+            .unwrap();
+            if bytes_read == 0 {
+                break;
+            }
+            chunked.extend_from_slice(&buffer[..bytes_read]);
+        }
+        assert_eq!(chunked, whole);
+
+        // So does seeking backwards and forwards.
+        let mut decoder = dr_mp3::Mp3Decoder::new(Cursor::new(mp3)).unwrap();
+        let mut buffer = [0u8; 2000];
+        for offset in [30001, 5, 1152 * 2 * 7, 45000, 1] {
+            let bytes_read = read_pcm_bytes(1, offset as u64, &mut buffer, |f, s| {
+                decoder.read_frames(f, s)
+            })
+            .unwrap();
+            let expected = &whole[offset..(offset + buffer.len()).min(byte_count)];
+            assert_eq!(&buffer[..bytes_read], expected);
+        }
+    }
+
+    #[test]
+    fn mp3_packet_decoder_matches_file_decoder() {
+        let mp3 = make_mp3(20);
+
+        let mut decoder = dr_mp3::Mp3Decoder::new(Cursor::new(mp3.clone())).unwrap();
+        let mut expected = vec![0i16; decoder.frame_count as usize];
+        assert_eq!(
+            decoder.read_frames(0, &mut expected),
+            Ok(decoder.frame_count)
+        );
+
+        // Packets don't have to line up with MP3 frames. Incomplete frames must
+        // be kept until the rest arrives, rather than decoded truncated.
+        for chunk_size in [1, 100, 191, 193, 333, 1000] {
+            let mut decoder = PacketDecoder::new_mp3();
+            let mut samples = Vec::new();
+            for chunk in mp3.chunks(chunk_size) {
+                decoder.decode_packet(chunk, 1, &mut samples).unwrap();
+            }
+            assert_eq!(samples, expected, "chunk size {}", chunk_size);
+        }
+
+        // Garbage before the first frame is skipped.
+        let mut decoder = PacketDecoder::new_mp3();
+        let mut samples = Vec::new();
+        decoder
+            .decode_packet(&[0xff, 0x00, 0x12], 1, &mut samples)
+            .unwrap();
+        decoder.decode_packet(&mp3[..150], 1, &mut samples).unwrap();
+        assert!(samples.is_empty());
+        decoder.decode_packet(&mp3[150..], 1, &mut samples).unwrap();
+        assert_eq!(samples, expected);
+    }
+}
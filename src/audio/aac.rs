@@ -3,30 +3,330 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-//! Quick-and-dirty AAC-LC decoding.
+//! Quick-and-dirty AAC-LC decoding (of files or individual packets), and
+//! probing of MPEG-4 movie files.
 //!
 //! This should be the only module in touchHLE that makes use of [symphonia].
 //! Only the LC profile and MPEG-4 container format are supported (see feature
 //! list in Cargo.toml).
 
-use std::io::Cursor;
-use symphonia::core::audio::{RawSampleBuffer, SignalSpec};
-use symphonia::core::codecs::CODEC_TYPE_AAC;
-use symphonia::core::io::MediaSourceStream;
+use crate::fs::GuestFile;
+use std::collections::VecDeque;
+use std::time::Duration;
+use symphonia::core::audio::{Channels, SampleBuffer};
+use symphonia::core::codecs::{CodecParameters, Decoder, CODEC_TYPE_AAC};
+use symphonia::core::formats::{FormatReader, Packet, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::units::TimeBase;
 
-/// PCM data decoded from an AAC file.
-pub struct AacDecodedToPcm {
-    /// 16-bit little-endian PCM samples, grouped in frames (one sample per
-    /// channel in each frame).
-    pub bytes: Vec<u8>,
+/// Decoder for the AAC track of an MPEG-4 file that decodes on demand, so that
+/// long files don't have to be decoded all at once.
+pub struct AacDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: TimeBase,
     /// Sample rate in Hz.
     pub sample_rate: u32,
     /// Channel count.
     pub channels: u32,
+    /// Total number of PCM frames (one sample per channel in each frame).
+    pub frame_count: u64,
+    sample_buffer: Option<SampleBuffer<i16>>,
+    /// Samples that have been decoded but not yet returned.
+    pending: VecDeque<i16>,
+    /// Index of the frame at the start of [Self::pending].
+    position: u64,
 }
 
-pub fn decode_aac_to_pcm(file: Cursor<Vec<u8>>) -> Result<AacDecodedToPcm, ()> {
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+impl AacDecoder {
+    pub fn new(file: GuestFile) -> Result<Self, ()> {
+        Self::from_format(probe(file)?)
+    }
+
+    fn from_format(format: Box<dyn FormatReader>) -> Result<Self, ()> {
+        // If this failed, no AAC audio track was found.
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec == CODEC_TYPE_AAC)
+            .ok_or(())?;
+        let track_id = track.id;
+        let params = &track.codec_params;
+        let time_base = params.time_base.ok_or(())?;
+        let sample_rate = params.sample_rate.ok_or(())?;
+        let channels = params.channels.ok_or(())?.count().try_into().unwrap();
+        let frame_count = ts_to_frame(params.n_frames.ok_or(())?, time_base, sample_rate);
+
+        // Not sure why this would fail, maybe an unusual AAC track.
+        let decoder = symphonia::default::get_codecs()
+            .make(params, &Default::default())
+            .map_err(|_| ())?;
+
+        Ok(AacDecoder {
+            format,
+            decoder,
+            track_id,
+            time_base,
+            sample_rate,
+            channels,
+            frame_count,
+            sample_buffer: None,
+            pending: VecDeque::new(),
+            position: 0,
+        })
+    }
+
+    /// Decode the next packet of the track into [Self::pending]. Returns
+    /// [false] at the end of the track.
+    fn decode_packet(&mut self) -> Result<bool, ()> {
+        let packet = loop {
+            match self.format.next_packet() {
+                Ok(packet) if packet.track_id() == self.track_id => break packet,
+                Ok(_) => continue,
+                // Assume I/O errors can only mean end-of-file.
+                Err(symphonia::core::errors::Error::IoError(_)) => return Ok(false),
+                Err(_) => return Err(()),
+            }
+        };
+        let Ok(decoded_packet) = self.decoder.decode(&packet) else {
+            return Ok(false);
+        };
+
+        // For some reason, the "signal spec" (number of channels etc)
+        // is reported per-packet? This is weird because it must be the same
+        // for all of them.
+        assert_eq!(
+            decoded_packet.spec().channels.count(),
+            self.channels as usize
+        );
+
+        // Note that this assumes every packet's buffer's capacity is the
+        // same, which is a dubious assumption, but Symphonia's own example
+        // code does it, so maybe it's fine?
+        let sample_buffer = self.sample_buffer.get_or_insert_with(|| {
+            SampleBuffer::new(decoded_packet.capacity() as _, *decoded_packet.spec())
+        });
+        sample_buffer.copy_interleaved_ref(decoded_packet);
+        self.pending.extend(sample_buffer.samples());
+        Ok(true)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<(), ()> {
+        let seeked_to = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts: frame_to_ts(frame, self.time_base, self.sample_rate),
+                    track_id: self.track_id,
+                },
+            )
+            .map_err(|_| ())?;
+        self.decoder.reset();
+        self.pending.clear();
+        self.position = ts_to_frame(seeked_to.actual_ts, self.time_base, self.sample_rate);
+
+        // Seeking goes to the start of a packet, so some frames may need to be
+        // skipped.
+        let channels = self.channels as usize;
+        while self.position < frame {
+            if self.pending.is_empty() && !self.decode_packet()? {
+                break;
+            }
+            let skip = (self.pending.len() / channels).min((frame - self.position) as usize);
+            self.pending.drain(..skip * channels);
+            self.position += skip as u64;
+        }
+        Ok(())
+    }
+
+    /// Decode 16-bit PCM frames starting from frame `first_frame` into
+    /// `samples`, whose length must be a multiple of the channel count.
+    /// Returns the number of frames decoded, which is smaller than requested
+    /// at the end of the file.
+    pub fn read_frames(&mut self, first_frame: u64, samples: &mut [i16]) -> Result<u64, ()> {
+        let channels = self.channels as usize;
+        assert!(samples.len().is_multiple_of(channels));
+        if first_frame != self.position {
+            self.seek(first_frame)?;
+        }
+        let mut samples_read = 0;
+        while samples_read < samples.len() {
+            if self.pending.is_empty() && !self.decode_packet()? {
+                break;
+            }
+            let count = self.pending.len().min(samples.len() - samples_read);
+            for (dest, src) in samples[samples_read..][..count]
+                .iter_mut()
+                .zip(self.pending.drain(..count))
+            {
+                *dest = src;
+            }
+            samples_read += count;
+        }
+        // The pending samples are always whole frames, so this is too.
+        let frames_read = (samples_read / channels) as u64;
+        self.position += frames_read;
+        Ok(frames_read)
+    }
+}
+
+/// Decoder for a stream of raw AAC-LC packets that are provided one at a time,
+/// e.g. by an audio queue, rather than read from a file.
+pub struct AacPacketDecoder {
+    decoder: Box<dyn Decoder>,
+    channels: u32,
+    sample_buffer: Option<SampleBuffer<i16>>,
+}
+
+impl AacPacketDecoder {
+    /// `magic_cookie` is the codec configuration, if the app provided one.
+    pub fn new(sample_rate: u32, channels: u32, magic_cookie: Option<&[u8]>) -> Result<Self, ()> {
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_AAC)
+            .with_sample_rate(sample_rate);
+        params.with_channels(match channels {
+            1 => Channels::FRONT_LEFT,
+            2 => Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+            _ => return Err(()),
+        });
+        if let Some(magic_cookie) = magic_cookie {
+            let config = audio_specific_config_from_magic_cookie(magic_cookie);
+            params.with_extra_data(config.into());
+        }
+        // This fails for profiles other than LC, amongst other things.
+        let decoder = symphonia::default::get_codecs()
+            .make(&params, &Default::default())
+            .map_err(|_| ())?;
+        Ok(AacPacketDecoder {
+            decoder,
+            channels,
+            sample_buffer: None,
+        })
+    }
+
+    /// Decode a single packet, appending the interleaved 16-bit PCM samples to
+    /// `samples`.
+    pub fn decode_packet(&mut self, packet: &[u8], samples: &mut Vec<i16>) -> Result<(), ()> {
+        let packet = Packet::new_from_slice(0, 0, 0, packet);
+        let decoded_packet = self.decoder.decode(&packet).map_err(|_| ())?;
+        if decoded_packet.spec().channels.count() != self.channels as usize {
+            return Err(());
+        }
+        let sample_buffer = self.sample_buffer.get_or_insert_with(|| {
+            SampleBuffer::new(decoded_packet.capacity() as _, *decoded_packet.spec())
+        });
+        sample_buffer.copy_interleaved_ref(decoded_packet);
+        samples.extend_from_slice(sample_buffer.samples());
+        Ok(())
+    }
+}
+
+/// Read an MPEG-4 descriptor (ISO/IEC 14496-1), returning its tag, its
+/// content, and the data following it.
+fn read_descriptor(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, mut data) = data.split_first()?;
+    // The size is encoded in 7-bit groups, with the top bit set on all groups
+    // but the last.
+    let mut size = 0usize;
+    for _ in 0..4 {
+        let (&byte, rest) = data.split_first()?;
+        data = rest;
+        size = (size << 7) | usize::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    (size <= data.len()).then(|| (tag, &data[..size], &data[size..]))
+}
+
+/// Find the `AudioSpecificConfig` in an `esds` ES descriptor.
+fn audio_specific_config_from_esds(esds: &[u8]) -> Option<&[u8]> {
+    const ES_DESCRIPTOR_TAG: u8 = 0x03;
+    const DECODER_CONFIG_DESCRIPTOR_TAG: u8 = 0x04;
+    const DECODER_SPECIFIC_INFO_TAG: u8 = 0x05;
+
+    let (tag, es_descriptor, _) = read_descriptor(esds)?;
+    if tag != ES_DESCRIPTOR_TAG {
+        return None;
+    }
+    // ES_ID, then flags that indicate which optional fields are present.
+    let flags = *es_descriptor.get(2)?;
+    let mut offset = 3;
+    if flags & 0x80 != 0 {
+        offset += 2; // dependsOn_ES_ID
+    }
+    if flags & 0x40 != 0 {
+        offset += 1 + usize::from(*es_descriptor.get(offset)?); // URL
+    }
+    if flags & 0x20 != 0 {
+        offset += 2; // OCR_ES_Id
+    }
+
+    let (tag, decoder_config, _) = read_descriptor(es_descriptor.get(offset..)?)?;
+    if tag != DECODER_CONFIG_DESCRIPTOR_TAG {
+        return None;
+    }
+    // Skip the object type, stream type, buffer size and bitrates.
+    let (tag, decoder_specific_info, _) = read_descriptor(decoder_config.get(13..)?)?;
+    if tag != DECODER_SPECIFIC_INFO_TAG {
+        return None;
+    }
+    Some(decoder_specific_info)
+}
+
+/// Apps get AAC magic cookies from Audio File Services, which provides the
+/// content of the `esds` atom, sometimes still with its version and flags
+/// fields. Some apps might provide a bare `AudioSpecificConfig` though, which
+/// can't be confused with a descriptor since it never starts with `0x03`.
+fn audio_specific_config_from_magic_cookie(magic_cookie: &[u8]) -> &[u8] {
+    audio_specific_config_from_esds(magic_cookie)
+        .or_else(|| audio_specific_config_from_esds(magic_cookie.get(4..)?))
+        .unwrap_or(magic_cookie)
+}
+
+fn ts_to_frame(ts: u64, time_base: TimeBase, sample_rate: u32) -> u64 {
+    (u128::from(ts) * u128::from(time_base.numer) * u128::from(sample_rate)
+        / u128::from(time_base.denom)) as u64
+}
+
+fn frame_to_ts(frame: u64, time_base: TimeBase, sample_rate: u32) -> u64 {
+    (u128::from(frame) * u128::from(time_base.denom)
+        / (u128::from(time_base.numer) * u128::from(sample_rate))) as u64
+}
+
+/// Get the duration of an MPEG-4 movie file (that of its longest track, which
+/// is usually the video track) and a decoder for its AAC-LC soundtrack, if it
+/// has one. The video track can't be decoded.
+pub fn open_movie(file: GuestFile) -> Result<(Duration, Option<AacDecoder>), ()> {
+    let format = probe(file)?;
+    let duration = format
+        .tracks()
+        .iter()
+        .filter_map(|track| {
+            let params = &track.codec_params;
+            let time = params.time_base?.calc_time(params.n_frames?);
+            Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+        })
+        .max()
+        .ok_or(())?;
+    let soundtrack = AacDecoder::from_format(format).ok();
+    Ok((duration, soundtrack))
+}
+
+fn probe(file: GuestFile) -> Result<Box<dyn FormatReader>, ()> {
+    // Symphonia requires a source that can be sent between threads, which not
+    // all kinds of guest file are. Files in an IPA are in memory anyway, so
+    // their contents can be used directly. touchHLE's own resource files are
+    // never audio or movies.
+    let source: Box<dyn MediaSource> = match file {
+        GuestFile::File(file) => Box::new(file),
+        GuestFile::IpaBundleFile(file) => Box::new(file.into_cursor()),
+        GuestFile::ResourceFile(_) => return Err(()),
+    };
+    let mss = MediaSourceStream::new(source, Default::default());
 
     // If this failed, the container format is not supported.
     let probed = symphonia::default::get_probe()
@@ -37,64 +337,86 @@ pub fn decode_aac_to_pcm(file: Cursor<Vec<u8>>) -> Result<AacDecodedToPcm, ()> {
             &Default::default(),
         )
         .map_err(|_| ())?;
+    Ok(probed.format)
+}
 
-    // If this failed, no AAC audio track was found.
-    let mut format = probed.format;
-    let track = format
-        .tracks()
-        .iter()
-        .find(|t| t.codec_params.codec == CODEC_TYPE_AAC)
-        .ok_or(())?;
-    let track_id = track.id;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Not sure why this would fail, maybe an unusual AAC track.
-    let mut decoder = symphonia::default::get_codecs()
-        .make(&track.codec_params, &Default::default())
-        .map_err(|_| ())?;
+    #[test]
+    fn timestamp_conversion() {
+        // Usually the time base is the reciprocal of the sample rate.
+        let time_base = TimeBase::new(1, 44100);
+        assert_eq!(ts_to_frame(1024, time_base, 44100), 1024);
+        assert_eq!(frame_to_ts(1024, time_base, 44100), 1024);
 
-    let mut out_pcm = Vec::<u8>::new();
-    let mut signal_spec: Option<SignalSpec> = None;
-    {
-        let mut tmp_raw_s16_buf: Option<RawSampleBuffer<i16>> = None;
-        loop {
-            let packet = match format.next_packet() {
-                Ok(packet) => packet,
-                // Assume I/O errors can only mean end-of-file, because the
-                // entire file is in-memory.
-                Err(symphonia::core::errors::Error::IoError(_)) => break,
-                Err(_) => return Err(()),
-            };
+        let time_base = TimeBase::new(1, 600);
+        assert_eq!(ts_to_frame(600, time_base, 22050), 22050);
+        assert_eq!(frame_to_ts(22050, time_base, 22050), 600);
+        assert_eq!(frame_to_ts(22049, time_base, 22050), 599);
+    }
 
-            if packet.track_id() != track_id {
-                continue;
-            }
-            let Ok(decoded_packet) = decoder.decode(&packet) else {
-                break;
-            };
-
-            // For some reason, the "signal spec" (number of channels etc)
-            // is reported per-packet? This is weird because it must be the same
-            // for all of them.
-            let signal_spec = *signal_spec.get_or_insert_with(|| *decoded_packet.spec());
-            assert_eq!(signal_spec, *decoded_packet.spec());
-
-            // Note that this assumes every packet's buffer's capacity is the
-            // same, which is a dubious assumption, but Symphonia's own example
-            // code does it, so maybe it's fine?
-            let tmp_raw_s16_buf = tmp_raw_s16_buf.get_or_insert_with(|| {
-                RawSampleBuffer::new(decoded_packet.capacity() as _, signal_spec)
-            });
-            tmp_raw_s16_buf.clear();
-            tmp_raw_s16_buf.copy_interleaved_ref(decoded_packet);
-
-            out_pcm.extend_from_slice(tmp_raw_s16_buf.as_bytes());
-        }
+    #[test]
+    fn magic_cookie() {
+        let config = [0x12, 0x10]; // AAC-LC, 44100Hz, stereo
+        assert_eq!(audio_specific_config_from_magic_cookie(&config), &config);
+
+        let esds = [
+            0x03, 0x19, // ES descriptor
+            0x00, 0x00, 0x00, // ES_ID, flags
+            0x04, 0x11, // decoder config descriptor
+            0x40, 0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
+            0x02, 0x12, 0x10, // decoder specific info
+            0x06, 0x01, 0x02, // SL config descriptor
+        ];
+        assert_eq!(audio_specific_config_from_magic_cookie(&esds), &config);
+
+        let mut versioned_esds = vec![0x00, 0x00, 0x00, 0x00];
+        versioned_esds.extend_from_slice(&esds);
+        assert_eq!(
+            audio_specific_config_from_magic_cookie(&versioned_esds),
+            &config
+        );
+
+        // Descriptor sizes can be padded to four bytes.
+        let padded_esds = [
+            0x03, 0x80, 0x80, 0x80, 0x1c, // ES descriptor
+            0x00, 0x00, 0x00, // ES_ID, flags
+            0x04, 0x80, 0x80, 0x80, 0x14, // decoder config descriptor
+            0x40, 0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
+            0x80, 0x80, 0x80, 0x02, 0x12, 0x10, // decoder specific info
+        ];
+        assert_eq!(
+            audio_specific_config_from_magic_cookie(&padded_esds),
+            &config
+        );
+
+        // A truncated descriptor is not mistaken for anything else.
+        let truncated_esds = &esds[..esds.len() - 8];
+        assert_eq!(
+            audio_specific_config_from_magic_cookie(truncated_esds),
+            truncated_esds
+        );
     }
-    let signal_spec = signal_spec.ok_or(())?;
 
-    Ok(AacDecodedToPcm {
-        bytes: out_pcm,
-        sample_rate: signal_spec.rate,
-        channels: signal_spec.channels.count().try_into().unwrap(),
-    })
+    #[test]
+    fn packet_decoding() {
+        assert!(AacPacketDecoder::new(44100, 3, None).is_err());
+
+        let config = [0x12, 0x08]; // AAC-LC, 44100Hz, mono
+        let mut decoder = AacPacketDecoder::new(44100, 1, Some(&config)).unwrap();
+        // A single channel element with no spectral data, then the end.
+        let silent_packet = [0x00, 0xc8, 0x00, 0x07];
+        let mut samples = Vec::new();
+        for _ in 0..3 {
+            decoder.decode_packet(&silent_packet, &mut samples).unwrap();
+        }
+        assert_eq!(samples, vec![0; 3 * 1024]);
+
+        // A stereo packet is rejected, since the decoder was set up for mono.
+        let stereo_packet = [0x20, 0x64, 0x00, 0x01, 0x90, 0x00, 0x0e];
+        assert!(decoder.decode_packet(&stereo_packet, &mut samples).is_err());
+        assert_eq!(samples.len(), 3 * 1024);
+    }
 }
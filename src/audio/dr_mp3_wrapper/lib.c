@@ -10,20 +10,64 @@
 #include <stdint.h>
 #include <stdlib.h>
 
-int16_t *touchHLE_decode_mp3_to_pcm(const uint8_t *data, size_t data_size,
-                                    uint32_t *channels, uint32_t *sample_rate,
-                                    uint64_t *frame_count) {
-  drmp3_config config;
-  int16_t *samples = drmp3_open_memory_and_read_pcm_frames_s16(
-      data, data_size, &config, frame_count,
-      /* pAllocationCallbacks: */ NULL);
-  if (samples) {
-    *channels = config.channels;
-    *sample_rate = config.sampleRate;
+// The file is read with `on_read` and `on_seek`, which are passed `user_data`
+// and must be usable until the decoder is closed.
+drmp3 *touchHLE_open_mp3_decoder(drmp3_read_proc on_read,
+                                 drmp3_seek_proc on_seek, void *user_data,
+                                 uint32_t *channels, uint32_t *sample_rate,
+                                 uint64_t *frame_count) {
+  drmp3 *mp3 = malloc(sizeof(drmp3));
+  if (!mp3) {
+    return NULL;
   }
-  return samples;
+  if (!drmp3_init(mp3, on_read, on_seek, user_data,
+                  /* pAllocationCallbacks: */ NULL)) {
+    free(mp3);
+    return NULL;
+  }
+  // This has to scan the whole file, but it only decodes the frame headers.
+  *frame_count = drmp3_get_pcm_frame_count(mp3);
+  *channels = mp3->channels;
+  *sample_rate = mp3->sampleRate;
+  return mp3;
+}
+
+uint64_t touchHLE_read_mp3_frames(drmp3 *mp3, uint64_t frame_count,
+                                  int16_t *samples) {
+  return drmp3_read_pcm_frames_s16(mp3, frame_count, samples);
+}
+
+int touchHLE_seek_mp3_to_frame(drmp3 *mp3, uint64_t frame_index) {
+  return drmp3_seek_to_pcm_frame(mp3, frame_index) ? 1 : 0;
+}
+
+void touchHLE_close_mp3_decoder(drmp3 *mp3) {
+  drmp3_uninit(mp3);
+  free(mp3);
+}
+
+// The decoder for individual MP3 frames (packets), rather than a whole file.
+
+drmp3dec *touchHLE_new_mp3_packet_decoder(void) {
+  drmp3dec *dec = malloc(sizeof(drmp3dec));
+  if (dec) {
+    drmp3dec_init(dec);
+  }
+  return dec;
 }
 
-void touchHLE_free_decoded_mp3_pcm(int16_t *samples) {
-  drmp3_free(samples, /* pAllocationCallbacks: */ NULL);
+// `samples` must have space for DRMP3_MAX_SAMPLES_PER_FRAME samples. Returns
+// the number of frames decoded, which is zero if no MP3 frame was found.
+// `frame_bytes` is set to the number of bytes consumed, which is zero if more
+// data is needed.
+int touchHLE_decode_mp3_packet(drmp3dec *dec, const uint8_t *data,
+                               size_t data_size, int16_t *samples,
+                               uint32_t *frame_bytes, uint32_t *channels) {
+  drmp3dec_frame_info info = {0};
+  int frames = drmp3dec_decode_frame(dec, data, (int)data_size, samples, &info);
+  *frame_bytes = info.frame_bytes;
+  *channels = info.channels;
+  return frames;
 }
+
+void touchHLE_free_mp3_packet_decoder(drmp3dec *dec) { free(dec); }
@@ -10,59 +10,325 @@
 // This also allows items in the crate to have non-snake-case names.
 #![allow(non_snake_case)]
 
+use std::ffi::{c_int, c_void};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Opaque type for dr_mp3's `drmp3`.
+#[repr(C)]
+struct drmp3 {
+    _private: [u8; 0],
+}
+/// Opaque type for dr_mp3's `drmp3dec`.
+#[repr(C)]
+struct drmp3dec {
+    _private: [u8; 0],
+}
+
+/// dr_mp3's `DRMP3_MAX_SAMPLES_PER_FRAME`.
+const MAX_SAMPLES_PER_FRAME: usize = 1152 * 2;
+
+/// dr_mp3's `drmp3_read_proc`.
+type ReadProc = extern "C" fn(user_data: *mut c_void, buffer: *mut c_void, size: usize) -> usize;
+/// dr_mp3's `drmp3_seek_proc`. The origin is a `drmp3_seek_origin`.
+type SeekProc = extern "C" fn(user_data: *mut c_void, offset: c_int, origin: c_int) -> u32;
+/// dr_mp3's `drmp3_seek_origin_start`.
+const SEEK_ORIGIN_START: c_int = 0;
+
 // See build.rs and lib.c
 extern "C" {
-    fn touchHLE_decode_mp3_to_pcm(
-        data: *const u8,
-        data_size: usize,
+    fn touchHLE_open_mp3_decoder(
+        on_read: ReadProc,
+        on_seek: SeekProc,
+        user_data: *mut c_void,
         channels: *mut u32,
         sample_rate: *mut u32,
         frame_count: *mut u64,
-    ) -> *mut i16;
-    fn touchHLE_free_decoded_mp3_pcm(samples: *mut i16);
+    ) -> *mut drmp3;
+    fn touchHLE_read_mp3_frames(mp3: *mut drmp3, frame_count: u64, samples: *mut i16) -> u64;
+    fn touchHLE_seek_mp3_to_frame(mp3: *mut drmp3, frame_index: u64) -> c_int;
+    fn touchHLE_close_mp3_decoder(mp3: *mut drmp3);
+    fn touchHLE_new_mp3_packet_decoder() -> *mut drmp3dec;
+    fn touchHLE_decode_mp3_packet(
+        dec: *mut drmp3dec,
+        data: *const u8,
+        data_size: usize,
+        samples: *mut i16,
+        frame_bytes: *mut u32,
+        channels: *mut u32,
+    ) -> c_int;
+    fn touchHLE_free_mp3_packet_decoder(dec: *mut drmp3dec);
 }
 
-/// PCM data decoded from an MP3 file.
-pub struct Mp3DecodedToPcm {
-    /// 16-bit little-endian PCM samples, grouped in frames (one sample per
-    /// channel in each frame).
-    pub bytes: Vec<u8>,
+/// Anything an MP3 file can be read from.
+trait Source: Read + Seek {}
+impl<T: Read + Seek> Source for T {}
+
+extern "C" fn read_source(user_data: *mut c_void, buffer: *mut c_void, size: usize) -> usize {
+    let source = unsafe { &mut *(user_data as *mut Box<dyn Source>) };
+    let buffer = unsafe { std::slice::from_raw_parts_mut(buffer as *mut u8, size) };
+    // dr_mp3 treats reading fewer bytes than requested as the end of the file.
+    let mut bytes_read = 0;
+    while bytes_read < size {
+        match source.read(&mut buffer[bytes_read..]) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => bytes_read += count,
+        }
+    }
+    bytes_read
+}
+
+extern "C" fn seek_source(user_data: *mut c_void, offset: c_int, origin: c_int) -> u32 {
+    let source = unsafe { &mut *(user_data as *mut Box<dyn Source>) };
+    let pos = if origin == SEEK_ORIGIN_START {
+        let Ok(offset) = u64::try_from(offset) else {
+            return 0;
+        };
+        SeekFrom::Start(offset)
+    } else {
+        SeekFrom::Current(offset.into())
+    };
+    source.seek(pos).is_ok().into()
+}
+
+/// Decoder for an MP3 file that decodes on demand, so that long files don't
+/// have to be decoded all at once. The file is also read on demand.
+pub struct Mp3Decoder {
+    mp3: *mut drmp3,
+    /// Referenced by `mp3`, so it must not be dropped before it. The extra box
+    /// is so that dr_mp3 gets a thin pointer that doesn't move.
+    _source: Box<Box<dyn Source>>,
     /// Sample rate in Hz.
     pub sample_rate: u32,
     /// Channel count.
     pub channels: u32,
+    /// Total number of PCM frames (one sample per channel in each frame).
+    pub frame_count: u64,
+    /// Index of the next frame [Mp3Decoder::read_frames] will decode.
+    position: u64,
 }
 
-#[allow(clippy::result_unit_err)]
-pub fn decode_mp3_to_pcm(data: &[u8]) -> Result<Mp3DecodedToPcm, ()> {
-    let mut channels = 0;
-    let mut sample_rate = 0;
-    let mut frame_count = 0;
-    let samples_ptr = unsafe {
-        touchHLE_decode_mp3_to_pcm(
-            data.as_ptr(),
-            data.len(),
-            &mut channels,
-            &mut sample_rate,
-            &mut frame_count,
-        )
-    };
-    if samples_ptr.is_null() {
-        return Err(());
+impl Mp3Decoder {
+    #[allow(clippy::result_unit_err)]
+    pub fn new<R: Read + Seek + 'static>(source: R) -> Result<Mp3Decoder, ()> {
+        let mut source: Box<Box<dyn Source>> = Box::new(Box::new(source));
+        let mut channels = 0;
+        let mut sample_rate = 0;
+        let mut frame_count = 0;
+        let mp3 = unsafe {
+            touchHLE_open_mp3_decoder(
+                read_source,
+                seek_source,
+                &mut *source as *mut Box<dyn Source> as *mut c_void,
+                &mut channels,
+                &mut sample_rate,
+                &mut frame_count,
+            )
+        };
+        if mp3.is_null() {
+            return Err(());
+        }
+        let decoder = Mp3Decoder {
+            mp3,
+            _source: source,
+            sample_rate,
+            channels,
+            frame_count,
+            position: 0,
+        };
+        // dr_mp3 will accept files that merely contain something that looks
+        // like an MP3 frame somewhere, so this is needed to reject non-MP3s.
+        if decoder.frame_count == 0 {
+            return Err(());
+        }
+        Ok(decoder)
+    }
+
+    /// Decode 16-bit PCM frames starting from frame `first_frame` into
+    /// `samples`, whose length must be a multiple of the channel count.
+    /// Returns the number of frames decoded, which is smaller than requested
+    /// at the end of the file.
+    #[allow(clippy::result_unit_err)]
+    pub fn read_frames(&mut self, first_frame: u64, samples: &mut [i16]) -> Result<u64, ()> {
+        assert!(samples.len().is_multiple_of(self.channels as usize));
+        if first_frame != self.position {
+            if unsafe { touchHLE_seek_mp3_to_frame(self.mp3, first_frame) } == 0 {
+                return Err(());
+            }
+            self.position = first_frame;
+        }
+        let frame_count = (samples.len() / self.channels as usize) as u64;
+        let frames_read =
+            unsafe { touchHLE_read_mp3_frames(self.mp3, frame_count, samples.as_mut_ptr()) };
+        self.position += frames_read;
+        Ok(frames_read)
+    }
+}
+
+impl Drop for Mp3Decoder {
+    fn drop(&mut self) {
+        unsafe { touchHLE_close_mp3_decoder(self.mp3) }
+    }
+}
+
+/// Decoder for a stream of MP3 frames (packets) that are provided piece by
+/// piece, e.g. by an audio queue, rather than as a whole file.
+pub struct Mp3PacketDecoder {
+    dec: *mut drmp3dec,
+    /// Data that has been provided but not yet decoded, because it doesn't
+    /// contain a whole frame.
+    pending: Vec<u8>,
+}
+
+impl Default for Mp3PacketDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mp3PacketDecoder {
+    pub fn new() -> Mp3PacketDecoder {
+        let dec = unsafe { touchHLE_new_mp3_packet_decoder() };
+        assert!(!dec.is_null());
+        Mp3PacketDecoder {
+            dec,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Decode as many whole frames from `data` (and any incomplete frame left
+    /// over from the previous call) as possible, appending the interleaved
+    /// 16-bit PCM samples to `samples`. The channel count must be `channels`,
+    /// frames with a different channel count are skipped.
+    pub fn decode(&mut self, data: &[u8], channels: u32, samples: &mut Vec<i16>) {
+        self.pending.extend_from_slice(data);
+        let mut consumed = 0;
+        let mut frame_samples = [0i16; MAX_SAMPLES_PER_FRAME];
+        // dr_mp3 will decode a truncated frame if that's all it's given, and
+        // skips garbage without saying so, so the frames are found here.
+        loop {
+            let Some(offset) = find_frame_header(&self.pending[consumed..], None) else {
+                // Keep anything that might be the start of a header.
+                consumed = consumed.max(self.pending.len().saturating_sub(3));
+                break;
+            };
+            consumed += offset;
+            let frame = &self.pending[consumed..];
+            let frame_size = match parse_frame_header(frame) {
+                Some(Some(size)) => size,
+                // Free-format frames end where the next frame begins, and
+                // dr_mp3 needs to see that next header to find the end.
+                _ => match find_frame_header(&frame[4..], Some(frame)) {
+                    Some(_) => frame.len(),
+                    None => break,
+                },
+            };
+            if frame_size > frame.len() {
+                break;
+            }
+
+            let mut frame_bytes = 0;
+            let mut frame_channels = 0;
+            let frames = unsafe {
+                touchHLE_decode_mp3_packet(
+                    self.dec,
+                    frame.as_ptr(),
+                    frame_size,
+                    frame_samples.as_mut_ptr(),
+                    &mut frame_bytes,
+                    &mut frame_channels,
+                )
+            };
+            if frame_bytes == 0 {
+                // Not really a frame header, keep looking.
+                consumed += 1;
+                continue;
+            }
+            consumed += frame_bytes as usize;
+            if frames > 0 && frame_channels == channels {
+                let sample_count = frames as usize * channels as usize;
+                samples.extend_from_slice(&frame_samples[..sample_count]);
+            }
+        }
+        self.pending.drain(..consumed);
     }
+}
 
-    let bytes = unsafe {
-        std::slice::from_raw_parts(
-            samples_ptr as *const _,
-            std::mem::size_of::<i16>() * (frame_count as usize) * (channels as usize),
-        )
+/// Parse the MPEG audio frame header at the start of `data` and get the size
+/// of the frame in bytes, including the header. Returns [None] if there isn't
+/// a valid header there, or `Some(None)` for a free-format frame, whose size
+/// isn't in the header.
+fn parse_frame_header(data: &[u8]) -> Option<Option<usize>> {
+    let header = data.get(..4)?;
+    let (h0, h1, h2) = (header[0], header[1], header[2]);
+    if h0 != 0xff || ((h1 & 0xf0) != 0xf0 && (h1 & 0xfe) != 0xe2) {
+        return None;
+    }
+    // 1 is Layer III, 2 is Layer II and 3 is Layer I.
+    let layer = ((h1 >> 1) & 3) as usize;
+    let bitrate_index = (h2 >> 4) as usize;
+    let sample_rate_index = ((h2 >> 2) & 3) as usize;
+    if layer == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+    if bitrate_index == 0 {
+        return Some(None);
     }
-    .to_vec();
-    unsafe { touchHLE_free_decoded_mp3_pcm(samples_ptr) };
 
-    Ok(Mp3DecodedToPcm {
-        bytes,
-        sample_rate,
-        channels,
+    let mpeg1 = h1 & 0x08 != 0;
+    let mpeg2_5 = h1 & 0x10 == 0;
+    // In kbps, indexed by MPEG-1 or not, layer and bitrate index - 1.
+    const BITRATES: [[[u16; 14]; 3]; 2] = [
+        [
+            [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+            [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+            [
+                32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+            ],
+        ],
+        [
+            [
+                32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+            ],
+            [
+                32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+            ],
+            [
+                32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+            ],
+        ],
+    ];
+    let bitrate = BITRATES[mpeg1 as usize][layer - 1][bitrate_index - 1] as usize;
+    let sample_rate = [44100, 48000, 32000][sample_rate_index];
+    let sample_rate = match (mpeg1, mpeg2_5) {
+        (true, _) => sample_rate,
+        (false, false) => sample_rate / 2,
+        (false, true) => sample_rate / 4,
+    };
+    let padding = h2 & 2 != 0;
+    Some(Some(if layer == 3 {
+        // Layer I frames are made of 4-byte slots.
+        ((384 * bitrate * 125 / sample_rate) & !3) + if padding { 4 } else { 0 }
+    } else {
+        let samples = if layer == 1 && !mpeg1 { 576 } else { 1152 };
+        samples * bitrate * 125 / sample_rate + padding as usize
+    }))
+}
+
+/// Find the offset of the first valid frame header in `data`. If `like` is
+/// given, the header must also have the same version, layer, sample rate and
+/// bitrate index as the header at the start of `like`.
+fn find_frame_header(data: &[u8], like: Option<&[u8]>) -> Option<usize> {
+    (0..data.len().saturating_sub(3)).find(|&i| {
+        let header = &data[i..];
+        parse_frame_header(header).is_some()
+            && like.is_none_or(|like| {
+                header[1] & 0xfe == like[1] & 0xfe && header[2] & 0xfc == like[2] & 0xfc
+            })
     })
 }
+
+impl Drop for Mp3PacketDecoder {
+    fn drop(&mut self) {
+        unsafe { touchHLE_free_mp3_packet_decoder(self.dec) }
+    }
+}
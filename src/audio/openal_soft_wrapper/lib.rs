@@ -69,6 +69,20 @@ extern "C" {
     pub fn alcGetString(device: *mut ALCdevice, param: ALCenum) -> *const ALCchar;
 }
 
+// === alext.h ===
+
+// ALC_SOFT_loopback
+pub const ALC_FREQUENCY: ALCenum = 0x1007;
+pub const ALC_FORMAT_CHANNELS_SOFT: ALCenum = 0x1990;
+pub const ALC_FORMAT_TYPE_SOFT: ALCenum = 0x1991;
+pub const ALC_SHORT_SOFT: ALCenum = 0x1402;
+pub const ALC_STEREO_SOFT: ALCenum = 0x1501;
+
+extern "C" {
+    pub fn alcLoopbackOpenDeviceSOFT(deviceName: *const ALCchar) -> *mut ALCdevice;
+    pub fn alcRenderSamplesSOFT(device: *mut ALCdevice, buffer: *mut ALCvoid, samples: ALCsizei);
+}
+
 // === al.h ===
 
 #[allow(dead_code)]
@@ -93,7 +107,20 @@ use al_types::*;
 
 pub const AL_NO_ERROR: ALenum = 0;
 
+pub const AL_SOURCE_RELATIVE: ALenum = 0x202;
+
+pub const AL_CONE_INNER_ANGLE: ALenum = 0x1001;
+pub const AL_CONE_OUTER_ANGLE: ALenum = 0x1002;
+pub const AL_PITCH: ALenum = 0x1003;
+pub const AL_POSITION: ALenum = 0x1004;
+pub const AL_DIRECTION: ALenum = 0x1005;
+pub const AL_VELOCITY: ALenum = 0x1006;
+pub const AL_LOOPING: ALenum = 0x1007;
+pub const AL_BUFFER: ALenum = 0x1009;
+pub const AL_GAIN: ALenum = 0x100A;
+pub const AL_MIN_GAIN: ALenum = 0x100D;
 pub const AL_MAX_GAIN: ALenum = 0x100E;
+pub const AL_ORIENTATION: ALenum = 0x100F;
 
 pub const AL_SOURCE_STATE: ALenum = 0x1010;
 
@@ -105,16 +132,33 @@ pub const AL_STOPPED: ALenum = 0x1014;
 pub const AL_BUFFERS_QUEUED: ALenum = 0x1015;
 pub const AL_BUFFERS_PROCESSED: ALenum = 0x1016;
 
+pub const AL_REFERENCE_DISTANCE: ALenum = 0x1020;
+pub const AL_ROLLOFF_FACTOR: ALenum = 0x1021;
+pub const AL_CONE_OUTER_GAIN: ALenum = 0x1022;
+pub const AL_MAX_DISTANCE: ALenum = 0x1023;
+pub const AL_SAMPLE_OFFSET: ALenum = 0x1025;
+pub const AL_SOURCE_TYPE: ALenum = 0x1027;
+pub const AL_STATIC: ALenum = 0x1028;
+
 pub const AL_FORMAT_MONO8: ALenum = 0x1100;
 pub const AL_FORMAT_MONO16: ALenum = 0x1101;
 pub const AL_FORMAT_STEREO8: ALenum = 0x1102;
 pub const AL_FORMAT_STEREO16: ALenum = 0x1103;
 
+pub const AL_DOPPLER_FACTOR: ALenum = 0xC000;
+pub const AL_DOPPLER_VELOCITY: ALenum = 0xC001;
+pub const AL_SPEED_OF_SOUND: ALenum = 0xC003;
+
+pub const AL_DISTANCE_MODEL: ALenum = 0xD000;
+
 extern "C" {
     pub fn alGetError() -> ALenum;
 
     pub fn alDistanceModel(value: ALenum);
 
+    pub fn alGetInteger(param: ALenum) -> ALint;
+    pub fn alGetFloat(param: ALenum) -> ALfloat;
+
     pub fn alGetEnumValue(enumName: *const ALchar) -> ALenum;
 
     pub fn alIsBuffer(buffer: ALuint) -> ALboolean;
@@ -200,4 +244,5 @@ extern "C" {
 
     pub fn alDopplerFactor(dopplerFactor: ALfloat);
     pub fn alDopplerVelocity(dopplerVelocity: ALfloat);
+    pub fn alSpeedOfSound(speed: ALfloat);
 }
@@ -0,0 +1,194 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Opening of OpenAL devices, and capture of their mixed output.
+//!
+//! Normally, OpenAL Soft plays audio directly. If a sink is set with
+//! [set_capture_sink], devices are instead opened as loopback devices
+//! (`ALC_SOFT_loopback`), which don't play anything by themselves. Their output
+//! is rendered by [render_captured], mixed and passed to the sink.
+//!
+//! Because the rendering is driven by touchHLE's clock, this works with
+//! `--virtual-clock`, i.e. independently of real time.
+//!
+//! Besides the embedding API, capture is used for `--capture-audio=`, which
+//! writes the output to a WAV file, and `--null-audio`, which discards it.
+//!
+//! This state is thread-local rather than part of [crate::Environment], because
+//! devices get closed in places where there's no environment, such as `Drop`
+//! implementations.
+
+use super::openal as al;
+use super::openal::alc_types::*;
+use crate::options::Options;
+use std::cell::RefCell;
+use std::path::Path;
+use std::time::Duration;
+
+/// Sample rate of captured audio, in Hz.
+pub const CAPTURE_SAMPLE_RATE: u32 = 44100;
+/// Number of channels of captured audio (stereo).
+pub const CAPTURE_CHANNELS: u32 = 2;
+
+/// Receives captured audio as interleaved 16-bit samples, with
+/// [CAPTURE_CHANNELS] channels at [CAPTURE_SAMPLE_RATE].
+pub type CaptureSink = Box<dyn FnMut(&[i16])>;
+
+struct Capture {
+    sink: CaptureSink,
+    devices: Vec<*mut ALCdevice>,
+    /// Number of sample frames rendered so far.
+    frames_rendered: u64,
+}
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Capture>> = const { RefCell::new(None) };
+}
+
+/// Set or remove the sink for captured audio. This only affects devices opened
+/// afterwards, so it should be done before the app is started.
+pub fn set_capture_sink(sink: Option<CaptureSink>) {
+    CAPTURE.with_borrow_mut(|capture| {
+        *capture = sink.map(|sink| Capture {
+            sink,
+            devices: Vec::new(),
+            frames_rendered: 0,
+        });
+    });
+}
+
+/// Create a sink that writes captured audio to a WAV file. The file's header
+/// is updated every time audio is written, so that the file is valid even if
+/// touchHLE doesn't exit cleanly.
+pub fn wav_capture_sink(path: &Path) -> Result<CaptureSink, String> {
+    let spec = hound::WavSpec {
+        channels: CAPTURE_CHANNELS as u16,
+        sample_rate: CAPTURE_SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)
+        .map_err(|e| format!("Could not create {}: {}", path.display(), e))?;
+    let mut failed = false;
+    Ok(Box::new(move |samples| {
+        if failed {
+            return;
+        }
+        let mut sample_writer = writer.get_i16_writer(samples.len() as u32);
+        for &sample in samples {
+            sample_writer.write_sample(sample);
+        }
+        let result = sample_writer.flush().and_then(|()| writer.flush());
+        if let Err(e) = result {
+            echo!("Warning: Could not write captured audio: {}", e);
+            failed = true;
+        }
+    }))
+}
+
+/// Set up the capture sink requested by `--capture-audio=` or `--null-audio`,
+/// if any.
+pub fn set_capture_sink_from_options(options: &Options) -> Result<(), String> {
+    if let Some(ref path) = options.capture_audio_path {
+        set_capture_sink(Some(wav_capture_sink(path)?));
+        echo!("Capturing audio to {}.", path.display());
+    } else if options.null_audio {
+        set_capture_sink(Some(Box::new(|_| ())));
+    }
+    Ok(())
+}
+
+/// Use this instead of `alcOpenDevice(NULL)`.
+pub unsafe fn open_device() -> *mut ALCdevice {
+    CAPTURE.with_borrow_mut(|capture| {
+        let Some(capture) = capture else {
+            return al::alcOpenDevice(std::ptr::null());
+        };
+        let device = al::alcLoopbackOpenDeviceSOFT(std::ptr::null());
+        if !device.is_null() {
+            capture.devices.push(device);
+        }
+        device
+    })
+}
+
+/// Use this instead of `alcCloseDevice()` for devices opened with
+/// [open_device].
+pub unsafe fn close_device(device: *mut ALCdevice) -> ALCboolean {
+    CAPTURE.with_borrow_mut(|capture| {
+        if let Some(capture) = capture {
+            capture.devices.retain(|&other| other != device);
+        }
+    });
+    al::alcCloseDevice(device)
+}
+
+/// Use this instead of `alcCreateContext(device, NULL)` for devices opened
+/// with [open_device].
+pub unsafe fn create_context(device: *mut ALCdevice) -> *mut ALCcontext {
+    let is_loopback = CAPTURE.with_borrow(|capture| {
+        capture
+            .as_ref()
+            .is_some_and(|capture| capture.devices.contains(&device))
+    });
+    if !is_loopback {
+        return al::alcCreateContext(device, std::ptr::null());
+    }
+    // Loopback devices require the format to be specified.
+    let attributes = [
+        al::ALC_FORMAT_CHANNELS_SOFT,
+        al::ALC_STEREO_SOFT,
+        al::ALC_FORMAT_TYPE_SOFT,
+        al::ALC_SHORT_SOFT,
+        al::ALC_FREQUENCY,
+        CAPTURE_SAMPLE_RATE as ALCint,
+        0,
+    ];
+    al::alcCreateContext(device, attributes.as_ptr())
+}
+
+/// If audio is being captured, render all output up to `uptime` (the time
+/// since touchHLE started, see [crate::clock::Clock::uptime]) and pass it to
+/// the sink.
+pub fn render_captured(uptime: Duration) {
+    // Rendering is done in chunks to limit memory use if there's a lot to
+    // catch up on.
+    const CHUNK_FRAMES: u64 = 4096;
+
+    CAPTURE.with_borrow_mut(|capture| {
+        let Some(capture) = capture else {
+            return;
+        };
+        let target_frames = (uptime.as_secs_f64() * CAPTURE_SAMPLE_RATE as f64) as u64;
+        let channels = CAPTURE_CHANNELS as usize;
+        let mut device_samples = Vec::new();
+        let mut mixed = Vec::new();
+        while capture.frames_rendered < target_frames {
+            let frames = (target_frames - capture.frames_rendered).min(CHUNK_FRAMES);
+            let samples = frames as usize * channels;
+            mixed.clear();
+            mixed.resize(samples, 0i32);
+            device_samples.resize(samples, 0i16);
+            for &device in &capture.devices {
+                unsafe {
+                    al::alcRenderSamplesSOFT(
+                        device,
+                        device_samples.as_mut_ptr().cast(),
+                        frames as ALCsizei,
+                    );
+                }
+                for (mixed, &sample) in mixed.iter_mut().zip(&device_samples) {
+                    *mixed += i32::from(sample);
+                }
+            }
+            let output: Vec<i16> = mixed
+                .iter()
+                .map(|&sample| sample.clamp(i16::MIN.into(), i16::MAX.into()) as i16)
+                .collect();
+            (capture.sink)(&output);
+            capture.frames_rendered += frames;
+        }
+    });
+}
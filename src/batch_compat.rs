@@ -0,0 +1,312 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Batch compatibility runner (`--batch-compat=`).
+//!
+//! This runs every app in a directory, one at a time, each in a child touchHLE
+//! process with `--offscreen`. A child is killed if it's still running when its
+//! time budget runs out. Its output is then used to classify the result, and
+//! once all the apps have been run, a summary table is printed to standard
+//! output.
+//!
+//! The classification relies on the exact wording of touchHLE's errors and
+//! panic messages, e.g. the [crate::mach_o::MachO::load_from_bytes] error
+//! strings, so it needs updating if those change.
+
+use crate::app_picker::enumerate_apps;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// Logged by [crate::window::Window::note_app_frame_presented].
+pub const FIRST_FRAME_MESSAGE: &str = "The app presented its first frame.";
+
+/// Default time budget per app, in seconds.
+pub const DEFAULT_TIME_BUDGET_SECS: u64 = 30;
+
+/// Error strings from [crate::mach_o::MachO::load_from_bytes] that mean the
+/// app's binary is for an architecture touchHLE doesn't support.
+const UNSUPPORTED_ARCHITECTURE_ERRORS: &[&str] = &[
+    "No supported architecture in the fat binary",
+    "Unexpected Mach-O file kind: not an executable",
+    "Executable is not for an ARM CPU!",
+    "Executable is not little-endian!",
+    "Executable is not 32-bit!",
+];
+const ENCRYPTED_ERROR: &str = "The executable is encrypted.";
+const MISSING_SYMBOL_PANIC_PREFIX: &str = "Call to unimplemented function ";
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// The app was still running when the time budget ran out.
+    StillRunning,
+    /// The app exited by itself without an error.
+    Exited,
+    /// touchHLE panicked, with the given message.
+    Crashed(String),
+    /// The app called a function that touchHLE doesn't implement.
+    MissingSymbol(String),
+    Encrypted,
+    UnsupportedArchitecture(String),
+    /// touchHLE exited with an error, e.g. because the bundle is invalid.
+    Errored(String),
+}
+
+struct RunResult {
+    reached_first_frame: bool,
+    outcome: Outcome,
+}
+
+/// Run every app in `apps_dir` and print a summary. `option_args` are passed
+/// on to each child process.
+pub fn run(apps_dir: &Path, time_budget: Duration, option_args: &[String]) -> Result<(), String> {
+    let apps = enumerate_apps(apps_dir).map_err(|err| {
+        format!(
+            "Couldn't get list of apps in the {} directory: {}",
+            apps_dir.display(),
+            err
+        )
+    })?;
+    if apps.is_empty() {
+        return Err(format!(
+            "No apps were found in the {} directory.",
+            apps_dir.display()
+        ));
+    }
+
+    let exe = std::env::current_exe()
+        .map_err(|err| format!("Couldn't get path of the touchHLE executable: {}", err))?;
+
+    let mut results = Vec::new();
+    for (i, app) in apps.iter().enumerate() {
+        echo!(
+            "Running app {} of {}: {} ({})",
+            i + 1,
+            apps.len(),
+            app.display_name,
+            app.path.display()
+        );
+        let mut command = Command::new(&exe);
+        command
+            .arg(&app.path)
+            .arg("--offscreen")
+            .args(option_args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        let result = run_child(command, time_budget)?;
+        echo!(
+            "Result: {}{}",
+            describe_outcome(&result.outcome),
+            if result.reached_first_frame {
+                " (reached first frame)"
+            } else {
+                ""
+            }
+        );
+        echo!();
+        results.push((app.display_name.as_str(), result));
+    }
+
+    print_summary(&results);
+    Ok(())
+}
+
+fn run_child(mut command: Command, time_budget: Duration) -> Result<RunResult, String> {
+    let mut child = command
+        .spawn()
+        .map_err(|err| format!("Couldn't start touchHLE child process: {}", err))?;
+
+    // The output has to be read while the child is running, otherwise it could
+    // block on a full pipe.
+    let mut stderr = child.stderr.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stderr.read_to_end(&mut output);
+        output
+    });
+
+    let deadline = Instant::now() + time_budget;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+            Err(err) => return Err(format!("Couldn't wait for child process: {}", err)),
+        }
+    };
+
+    let output = reader.join().unwrap();
+    let output = String::from_utf8_lossy(&output);
+    Ok(classify(&output, status))
+}
+
+/// Classify the result of a run from the child's standard error output and
+/// exit status (or [None] if it was killed when the time budget ran out).
+fn classify(output: &str, status: Option<ExitStatus>) -> RunResult {
+    let reached_first_frame = output
+        .lines()
+        .any(|line| line.contains(FIRST_FRAME_MESSAGE));
+    let success = status.map(|status| status.success());
+    RunResult {
+        reached_first_frame,
+        outcome: classify_outcome(output, success),
+    }
+}
+
+fn classify_outcome(output: &str, success: Option<bool>) -> Outcome {
+    if let Some(message) = find_panic_message(output) {
+        return match message.strip_prefix(MISSING_SYMBOL_PANIC_PREFIX) {
+            Some(symbol) => Outcome::MissingSymbol(symbol.to_string()),
+            None => Outcome::Crashed(message),
+        };
+    }
+
+    // An Err returned from main() is printed by Rust as `Error: "..."`.
+    if let Some(error) = output
+        .lines()
+        .rev()
+        .find_map(|line| line.strip_prefix("Error: "))
+    {
+        if error.contains(ENCRYPTED_ERROR) {
+            return Outcome::Encrypted;
+        }
+        if let Some(&arch_error) = UNSUPPORTED_ARCHITECTURE_ERRORS
+            .iter()
+            .find(|&&arch_error| error.contains(arch_error))
+        {
+            return Outcome::UnsupportedArchitecture(arch_error.to_string());
+        }
+        let error = error
+            .strip_prefix('"')
+            .and_then(|error| error.strip_suffix('"'))
+            .unwrap_or(error);
+        return Outcome::Errored(error.to_string());
+    }
+
+    match success {
+        None => Outcome::StillRunning,
+        Some(true) => Outcome::Exited,
+        Some(false) => Outcome::Crashed("(no panic message)".to_string()),
+    }
+}
+
+/// Find the message of the first panic in the output, as printed by Rust's
+/// default panic hook, i.e. `thread '...' panicked at <location>:` followed by
+/// the message on the next line.
+fn find_panic_message(output: &str) -> Option<String> {
+    let mut lines = output.lines();
+    while let Some(line) = lines.next() {
+        let Some((_, rest)) = line.split_once(" panicked at ") else {
+            continue;
+        };
+        // Older Rust versions put the message on the same line:
+        // `panicked at 'message', location`
+        if let Some(rest) = rest.strip_prefix('\'') {
+            let message = rest.rsplit_once("', ").map_or(rest, |(message, _)| message);
+            return Some(message.to_string());
+        }
+        return Some(lines.next().unwrap_or("").to_string());
+    }
+    None
+}
+
+fn describe_outcome(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::StillRunning => "still running".to_string(),
+        Outcome::Exited => "exited".to_string(),
+        Outcome::Crashed(message) => format!("crashed: {}", message),
+        Outcome::MissingSymbol(symbol) => format!("missing symbol: {}", symbol),
+        Outcome::Encrypted => "encrypted binary".to_string(),
+        Outcome::UnsupportedArchitecture(error) => {
+            format!("unsupported architecture: {}", error)
+        }
+        Outcome::Errored(error) => format!("error: {}", error),
+    }
+}
+
+fn print_summary(results: &[(&str, RunResult)]) {
+    let name_width = results
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .chain(std::iter::once("App".len()))
+        .max()
+        .unwrap();
+
+    println!("Summary:");
+    println!("{:name_width$} | First frame | Result", "App");
+    println!("{:-<name_width$}-|-------------|-------", "");
+    for (name, result) in results {
+        println!(
+            "{:name_width$} | {:11} | {}",
+            name,
+            if result.reached_first_frame {
+                "yes"
+            } else {
+                "no"
+            },
+            describe_outcome(&result.outcome)
+        );
+    }
+
+    let first_frame_count = results
+        .iter()
+        .filter(|(_, result)| result.reached_first_frame)
+        .count();
+    println!();
+    println!(
+        "{} of {} apps reached their first frame.",
+        first_frame_count,
+        results.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        assert_eq!(
+            classify_outcome(
+                "thread 'main' panicked at src/dyld.rs:618:9:\nCall to unimplemented function _foo\nnote: ...",
+                Some(false)
+            ),
+            Outcome::MissingSymbol("_foo".to_string())
+        );
+        assert_eq!(
+            classify_outcome(
+                "thread 'main' panicked at src/foo.rs:1:1:\nassertion failed: x\n",
+                Some(false)
+            ),
+            Outcome::Crashed("assertion failed: x".to_string())
+        );
+        assert_eq!(
+            classify_outcome(
+                "Error: \"Could not load executable: The executable is encrypted. touchHLE can't run encrypted apps!\"",
+                Some(false)
+            ),
+            Outcome::Encrypted
+        );
+        assert_eq!(
+            classify_outcome(
+                "Error: \"Could not load executable: Executable is not 32-bit!\"",
+                Some(false)
+            ),
+            Outcome::UnsupportedArchitecture("Executable is not 32-bit!".to_string())
+        );
+        assert_eq!(
+            classify_outcome("Error: \"Application bundle error: foo\"", Some(false)),
+            Outcome::Errored("Application bundle error: foo".to_string())
+        );
+        assert_eq!(classify_outcome("", None), Outcome::StillRunning);
+        assert_eq!(classify_outcome("", Some(true)), Outcome::Exited);
+    }
+}
@@ -0,0 +1,288 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Breakpoints on Objective-C methods, e.g. `-[NSString initWithFormat:]`, and
+//! on functions imported by the app, e.g. `malloc`.
+//!
+//! Unlike GDB's breakpoints, these don't need to know any addresses: they are
+//! checked when `objc_msgSend` dispatches a message (see [check_method]) and
+//! when the app calls a host function through one of [crate::dyld]'s stubs
+//! (see [check_function]), so they also work for methods and functions
+//! implemented by touchHLE. They can be set with `--break-on=` or from the
+//! debug console (see [crate::debug_console]), and are handled by whichever
+//! debugger is in use.
+
+use crate::objc::{id, SEL};
+use crate::Environment;
+use std::fmt;
+
+/// A breakpoint on an Objective-C method. A breakpoint with a class name
+/// matches when that class's implementation of the method is called, so it
+/// also matches for subclasses that don't override the method, and for
+/// super-calls from ones that do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodBreakpoint {
+    /// `Some(true)` for class methods (`+`), `Some(false)` for instance
+    /// methods (`-`), [None] for either.
+    is_class_method: Option<bool>,
+    /// [None] matches any class.
+    class_name: Option<String>,
+    selector: String,
+}
+
+impl MethodBreakpoint {
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    /// `class_name` is the class whose implementation is being called.
+    fn matches(&self, class_name: &str, is_metaclass: bool, selector: &str) -> bool {
+        self.selector == selector
+            && self.is_class_method.is_none_or(|kind| kind == is_metaclass)
+            && self
+                .class_name
+                .as_deref()
+                .is_none_or(|name| name == class_name)
+    }
+}
+
+impl fmt::Display for MethodBreakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.class_name.is_none()
+            && self.is_class_method.is_none()
+            && self.selector.contains(':')
+        {
+            return write!(f, "{}", self.selector);
+        }
+        match self.is_class_method {
+            Some(true) => write!(f, "+")?,
+            Some(false) => write!(f, "-")?,
+            None => (),
+        }
+        let class_name = self.class_name.as_deref().unwrap_or("*");
+        write!(f, "[{} {}]", class_name, self.selector)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    Method(MethodBreakpoint),
+    /// A function imported by the app and implemented by touchHLE. The name
+    /// is as in C, i.e. without the leading underscore of Mach-O symbols.
+    Function(String),
+}
+
+impl Breakpoint {
+    /// Parse a breakpoint in one of these forms:
+    ///
+    /// - `-[NSString initWithFormat:]` (instance method of a class)
+    /// - `+[UIImage imageNamed:]` (class method of a class)
+    /// - `[NSObject init]` (either kind of method of a class)
+    /// - `-[* init]` (method of any class)
+    /// - `initWithFormat:` (method of any class, if the selector has a colon)
+    /// - `malloc` (function)
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        let (is_class_method, rest) = if let Some(rest) = spec.strip_prefix('-') {
+            (Some(false), rest)
+        } else if let Some(rest) = spec.strip_prefix('+') {
+            (Some(true), rest)
+        } else {
+            (None, spec)
+        };
+
+        let (class_name, selector) = if let Some(rest) = rest.strip_prefix('[') {
+            let rest = rest
+                .strip_suffix(']')
+                .ok_or_else(|| format!("Missing ']' in method {:?}", spec))?;
+            let (class_name, selector) = rest.trim().split_once(' ').ok_or_else(|| {
+                format!("Method {:?} should have a class name and a selector", spec)
+            })?;
+            let class_name = (class_name != "*").then(|| class_name.to_string());
+            (class_name, selector.trim())
+        } else if is_class_method.is_some() {
+            return Err(format!("Method {:?} is missing '['", spec));
+        } else if rest.contains(':') {
+            (None, rest)
+        } else {
+            if rest.is_empty()
+                || !rest
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+            {
+                return Err(format!("Invalid function name {:?}", spec));
+            }
+            return Ok(Breakpoint::Function(rest.to_string()));
+        };
+
+        if selector.is_empty() || selector.contains(char::is_whitespace) {
+            return Err(format!("Invalid selector in {:?}", spec));
+        }
+
+        Ok(Breakpoint::Method(MethodBreakpoint {
+            is_class_method,
+            class_name,
+            selector: selector.to_string(),
+        }))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Method(method) => write!(f, "{}", method),
+            Breakpoint::Function(name) => write!(f, "{}()", name),
+        }
+    }
+}
+
+/// The set of breakpoints. Each has a number, starting from 1, so that it can
+/// be referred to later.
+#[derive(Default)]
+pub struct Breakpoints {
+    list: Vec<(u32, Breakpoint)>,
+    next_number: u32,
+}
+
+impl Breakpoints {
+    /// Add a breakpoint and return its number.
+    pub fn add(&mut self, breakpoint: Breakpoint) -> u32 {
+        self.next_number += 1;
+        self.list.push((self.next_number, breakpoint));
+        self.next_number
+    }
+
+    /// Remove a breakpoint by number. Returns [false] if there's no such
+    /// breakpoint.
+    pub fn remove(&mut self, number: u32) -> bool {
+        let len_before = self.list.len();
+        self.list.retain(|&(n, _)| n != number);
+        self.list.len() != len_before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn list(&self) -> &[(u32, Breakpoint)] {
+        &self.list
+    }
+
+    fn match_method(&self, class_name: &str, is_metaclass: bool, selector: &str) -> Option<u32> {
+        self.list
+            .iter()
+            .find_map(|(number, breakpoint)| match breakpoint {
+                Breakpoint::Method(method)
+                    if method.matches(class_name, is_metaclass, selector) =>
+                {
+                    Some(*number)
+                }
+                _ => None,
+            })
+    }
+
+    /// `symbol` is a Mach-O symbol, e.g. `_malloc`.
+    fn match_function(&self, symbol: &str) -> Option<u32> {
+        let name = symbol.strip_prefix('_').unwrap_or(symbol);
+        self.list
+            .iter()
+            .find_map(|(number, breakpoint)| match breakpoint {
+                Breakpoint::Function(function) if function == name || function == symbol => {
+                    Some(*number)
+                }
+                _ => None,
+            })
+    }
+}
+
+/// Called by `objc_msgSend` before it calls `class_name`'s implementation of
+/// `selector`, to enter the debugger if there's a matching breakpoint. At that
+/// point the registers still contain the message's arguments.
+pub fn check_method(
+    env: &mut Environment,
+    receiver: id,
+    class_name: &str,
+    is_metaclass: bool,
+    selector: SEL,
+) {
+    let selector = selector.as_str(&env.mem);
+    let Some(number) = env
+        .breakpoints
+        .match_method(class_name, is_metaclass, selector)
+    else {
+        return;
+    };
+    let message = format!(
+        "Breakpoint {} hit: {}[{} {}], receiver {:?}.",
+        number,
+        if is_metaclass { '+' } else { '-' },
+        class_name,
+        selector,
+        receiver
+    );
+    env.enter_debugger_from_host(&message);
+}
+
+/// Called before the host function for `symbol` (a Mach-O symbol, e.g.
+/// `_malloc`) is called from the app, to enter the debugger if there's a
+/// matching breakpoint. At that point the registers still contain the
+/// function's arguments.
+pub fn check_function(env: &mut Environment, symbol: &str) {
+    let Some(number) = env.breakpoints.match_function(symbol) else {
+        return;
+    };
+    let message = format!(
+        "Breakpoint {} hit: {}(), called from {:#x}.",
+        number,
+        symbol.strip_prefix('_').unwrap_or(symbol),
+        env.cpu.regs()[crate::cpu::Cpu::LR]
+    );
+    env.enter_debugger_from_host(&message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_method(spec: &str) -> MethodBreakpoint {
+        match Breakpoint::parse(spec) {
+            Ok(Breakpoint::Method(method)) => method,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_and_match() {
+        let breakpoint = parse_method("-[NSString initWithFormat:]");
+        assert_eq!(breakpoint.to_string(), "-[NSString initWithFormat:]");
+        assert!(breakpoint.matches("NSString", false, "initWithFormat:"));
+        assert!(!breakpoint.matches("NSString", true, "initWithFormat:"));
+        assert!(!breakpoint.matches("NSArray", false, "initWithFormat:"));
+
+        let breakpoint = parse_method("[UIImage imageNamed:]");
+        assert!(breakpoint.matches("UIImage", true, "imageNamed:"));
+        assert!(breakpoint.matches("UIImage", false, "imageNamed:"));
+
+        let breakpoint = parse_method("+[* alloc]");
+        assert_eq!(breakpoint.to_string(), "+[* alloc]");
+        assert!(breakpoint.matches("NSObject", true, "alloc"));
+
+        let breakpoint = parse_method("addSubview:");
+        assert_eq!(breakpoint.to_string(), "addSubview:");
+        assert!(breakpoint.matches("UIView", false, "addSubview:"));
+
+        assert!(Breakpoint::parse("-[NSString]").is_err());
+        assert!(Breakpoint::parse("-[NSString init").is_err());
+        assert!(Breakpoint::parse("-init").is_err());
+        assert!(Breakpoint::parse("").is_err());
+        assert!(Breakpoint::parse("not a function").is_err());
+
+        let mut breakpoints = Breakpoints::default();
+        let number = breakpoints.add(Breakpoint::parse("malloc").unwrap());
+        assert_eq!(breakpoints.match_function("_malloc"), Some(number));
+        assert_eq!(breakpoints.match_function("_calloc"), None);
+        assert_eq!(breakpoints.match_method("NSObject", true, "malloc"), None);
+    }
+}
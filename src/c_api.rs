@@ -0,0 +1,341 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! C API for frontends, exported from the `cdylib`. This is a thin wrapper
+//! around [crate::embedding].
+//!
+//! The declarations are in `include/touchHLE.h`, which must be kept in sync
+//! with this file.
+//!
+//! All functions taking a `touchHLE_Emulator*` require it to be either null or
+//! a valid pointer returned by [touchHLE_create] and not yet passed to
+//! [touchHLE_destroy], and they must not be called concurrently for the same
+//! emulator. A null emulator makes them return `TOUCHHLE_ERROR` or null.
+//! Strings are UTF-8 and null-terminated.
+//!
+//! Like [crate::embedding], this doesn't apply options from options files
+//! (e.g. `touchHLE_default_options.txt`): only [touchHLE_set_option] does.
+
+// The names match the C header.
+#![allow(non_camel_case_types)]
+
+use crate::embedding::{Emulator, Frame, InputEvent, Options, Stopped};
+use std::ffi::{c_char, c_float, c_int, CStr, CString};
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const TOUCHHLE_OK: c_int = 0;
+pub const TOUCHHLE_EXITED: c_int = 1;
+pub const TOUCHHLE_TIMED_OUT: c_int = 2;
+pub const TOUCHHLE_ERROR: c_int = -1;
+
+pub const TOUCHHLE_EVENT_TOUCH_DOWN: c_int = 0;
+pub const TOUCHHLE_EVENT_TOUCH_MOVE: c_int = 1;
+pub const TOUCHHLE_EVENT_TOUCH_UP: c_int = 2;
+pub const TOUCHHLE_EVENT_ACCELERATION: c_int = 3;
+pub const TOUCHHLE_EVENT_QUIT: c_int = 4;
+
+/// Opaque to C code.
+pub struct touchHLE_Emulator {
+    options: Options,
+    emulator: Option<Emulator>,
+    frame: Option<Frame>,
+    exit_code: c_int,
+    last_error: CString,
+}
+
+/// Counterpart of [InputEvent]. Which fields are used depends on `type_`.
+#[repr(C)]
+pub struct touchHLE_InputEvent {
+    type_: c_int,
+    finger: u32,
+    x: c_float,
+    y: c_float,
+    z: c_float,
+}
+
+impl touchHLE_Emulator {
+    fn set_error(&mut self, error: String) -> c_int {
+        // Interior null bytes can't be represented, so they're dropped.
+        self.last_error = CString::new(error.replace('\0', "")).unwrap();
+        TOUCHHLE_ERROR
+    }
+}
+
+unsafe fn str_arg<'a>(emulator: &mut touchHLE_Emulator, s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        emulator.set_error("Unexpected null pointer".to_string());
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(_) => {
+            emulator.set_error("String is not valid UTF-8".to_string());
+            None
+        }
+    }
+}
+
+/// Create an emulator. No app is loaded yet, so options can be set first.
+#[no_mangle]
+pub extern "C" fn touchHLE_create() -> *mut touchHLE_Emulator {
+    Box::into_raw(Box::new(touchHLE_Emulator {
+        options: Options::default(),
+        emulator: None,
+        frame: None,
+        exit_code: 0,
+        last_error: CString::default(),
+    }))
+}
+
+/// Destroy an emulator. If an app is running, it is asked to quit.
+#[no_mangle]
+pub unsafe extern "C" fn touchHLE_destroy(emulator: *mut touchHLE_Emulator) {
+    if !emulator.is_null() {
+        drop(Box::from_raw(emulator));
+    }
+}
+
+/// Get a description of the last error. The string remains valid until the
+/// next call that fails. Returns null if `emulator` is null.
+#[no_mangle]
+pub unsafe extern "C" fn touchHLE_get_last_error(
+    emulator: *const touchHLE_Emulator,
+) -> *const c_char {
+    match emulator.as_ref() {
+        Some(emulator) => emulator.last_error.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// Set an option, using the same syntax as the command line, e.g.
+/// `"--scale-hack=2"`. This only affects apps loaded afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn touchHLE_set_option(
+    emulator: *mut touchHLE_Emulator,
+    option: *const c_char,
+) -> c_int {
+    let Some(emulator) = emulator.as_mut() else {
+        return TOUCHHLE_ERROR;
+    };
+    let Some(option) = str_arg(emulator, option) else {
+        return TOUCHHLE_ERROR;
+    };
+    match emulator.options.parse_argument(option) {
+        Ok(true) => TOUCHHLE_OK,
+        Ok(false) => emulator.set_error(format!("Unknown option {:?}", option)),
+        Err(err) => emulator.set_error(format!("Invalid option {:?}: {}", option, err)),
+    }
+}
+
+/// Start running the app at `path` (an `.app` directory or `.ipa` file).
+/// Only one app can be loaded per emulator.
+#[no_mangle]
+pub unsafe extern "C" fn touchHLE_load_app(
+    emulator: *mut touchHLE_Emulator,
+    path: *const c_char,
+) -> c_int {
+    let Some(emulator) = emulator.as_mut() else {
+        return TOUCHHLE_ERROR;
+    };
+    let Some(path) = str_arg(emulator, path) else {
+        return TOUCHHLE_ERROR;
+    };
+    if emulator.emulator.is_some() {
+        return emulator.set_error("An app has already been loaded".to_string());
+    }
+    emulator.emulator = Some(Emulator::new(
+        PathBuf::from(path),
+        std::mem::take(&mut emulator.options),
+        None,
+    ));
+    TOUCHHLE_OK
+}
+
+/// Let the app run until it presents its next frame. Returns `TOUCHHLE_OK` if
+/// there is a new frame, `TOUCHHLE_EXITED` if the app exited (see
+/// [touchHLE_get_exit_code]), or `TOUCHHLE_ERROR` if it couldn't be started
+/// or touchHLE crashed. This waits however long that takes.
+#[no_mangle]
+pub unsafe extern "C" fn touchHLE_run_frame(emulator: *mut touchHLE_Emulator) -> c_int {
+    run_frame(emulator, None)
+}
+
+/// Like [touchHLE_run_frame], but gives up waiting after `timeout_ms`
+/// milliseconds and returns `TOUCHHLE_TIMED_OUT`. The app keeps running, and
+/// the next call continues waiting for the same frame.
+#[no_mangle]
+pub unsafe extern "C" fn touchHLE_run_frame_timeout(
+    emulator: *mut touchHLE_Emulator,
+    timeout_ms: u32,
+) -> c_int {
+    run_frame(emulator, Some(Duration::from_millis(timeout_ms.into())))
+}
+
+unsafe fn run_frame(emulator: *mut touchHLE_Emulator, timeout: Option<Duration>) -> c_int {
+    let Some(emulator) = emulator.as_mut() else {
+        return TOUCHHLE_ERROR;
+    };
+    let Some(ref mut running) = emulator.emulator else {
+        return emulator.set_error("No app has been loaded".to_string());
+    };
+    let result = match timeout {
+        Some(timeout) => running.step_frame_timeout(timeout),
+        None => running.step_frame().map(Some),
+    };
+    match result {
+        Ok(Some(frame)) => {
+            emulator.frame = Some(frame);
+            TOUCHHLE_OK
+        }
+        Ok(None) => TOUCHHLE_TIMED_OUT,
+        Err(Stopped::Exited(exit_code)) => {
+            emulator.exit_code = exit_code;
+            TOUCHHLE_EXITED
+        }
+        Err(Stopped::Error(error)) => emulator.set_error(error),
+        Err(Stopped::Panicked(message)) => emulator.set_error(format!("Panic: {}", message)),
+    }
+}
+
+/// Get the app's exit code, after [touchHLE_run_frame] has returned
+/// `TOUCHHLE_EXITED`. Returns `TOUCHHLE_ERROR` if `emulator` is null.
+#[no_mangle]
+pub unsafe extern "C" fn touchHLE_get_exit_code(emulator: *const touchHLE_Emulator) -> c_int {
+    match emulator.as_ref() {
+        Some(emulator) => emulator.exit_code,
+        None => TOUCHHLE_ERROR,
+    }
+}
+
+/// Queue an input event, to be delivered on the next [touchHLE_run_frame].
+#[no_mangle]
+pub unsafe extern "C" fn touchHLE_push_event(
+    emulator: *mut touchHLE_Emulator,
+    event: *const touchHLE_InputEvent,
+) -> c_int {
+    let Some(emulator) = emulator.as_mut() else {
+        return TOUCHHLE_ERROR;
+    };
+    if event.is_null() {
+        return emulator.set_error("Unexpected null pointer".to_string());
+    }
+    let &touchHLE_InputEvent {
+        type_,
+        finger,
+        x,
+        y,
+        z,
+    } = &*event;
+    let event = match type_ {
+        TOUCHHLE_EVENT_TOUCH_DOWN => InputEvent::TouchDown { finger, x, y },
+        TOUCHHLE_EVENT_TOUCH_MOVE => InputEvent::TouchMove { finger, x, y },
+        TOUCHHLE_EVENT_TOUCH_UP => InputEvent::TouchUp { finger, x, y },
+        TOUCHHLE_EVENT_ACCELERATION => InputEvent::Acceleration { x, y, z },
+        TOUCHHLE_EVENT_QUIT => InputEvent::Quit,
+        _ => return emulator.set_error(format!("Unknown event type {}", type_)),
+    };
+    let Some(ref mut running) = emulator.emulator else {
+        return emulator.set_error("No app has been loaded".to_string());
+    };
+    running.push_event(event);
+    TOUCHHLE_OK
+}
+
+/// Get the pixels of the frame from the last successful [touchHLE_run_frame],
+/// as RGBA in top-to-bottom row order, and write its size to `width` and
+/// `height`. Returns null if there is no frame yet or `emulator` is null. The
+/// pointer remains valid until the next call to [touchHLE_run_frame] or
+/// [touchHLE_destroy].
+#[no_mangle]
+pub unsafe extern "C" fn touchHLE_get_framebuffer(
+    emulator: *const touchHLE_Emulator,
+    width: *mut u32,
+    height: *mut u32,
+) -> *const u8 {
+    let Some(touchHLE_Emulator {
+        frame: Some(ref frame),
+        ..
+    }) = emulator.as_ref()
+    else {
+        return std::ptr::null();
+    };
+    if !width.is_null() {
+        *width = frame.width;
+    }
+    if !height.is_null() {
+        *height = frame.height;
+    }
+    frame.pixels.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr::{null, null_mut};
+
+    fn last_error(emulator: *const touchHLE_Emulator) -> String {
+        unsafe { CStr::from_ptr(touchHLE_get_last_error(emulator)) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn create_set_option_destroy() {
+        let emulator = touchHLE_create();
+        assert!(!emulator.is_null());
+        unsafe {
+            assert_eq!(
+                touchHLE_set_option(emulator, c"--scale-hack=2".as_ptr()),
+                TOUCHHLE_OK
+            );
+            assert_eq!(
+                touchHLE_set_option(emulator, c"--no-such-option".as_ptr()),
+                TOUCHHLE_ERROR
+            );
+            assert_eq!(last_error(emulator), "Unknown option \"--no-such-option\"");
+            assert_eq!(touchHLE_set_option(emulator, null()), TOUCHHLE_ERROR);
+            assert_eq!(last_error(emulator), "Unexpected null pointer");
+
+            // Nothing can happen before an app is loaded.
+            assert_eq!(touchHLE_run_frame(emulator), TOUCHHLE_ERROR);
+            assert_eq!(last_error(emulator), "No app has been loaded");
+            let event = touchHLE_InputEvent {
+                type_: TOUCHHLE_EVENT_QUIT,
+                finger: 0,
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            };
+            assert_eq!(touchHLE_push_event(emulator, &event), TOUCHHLE_ERROR);
+            assert!(touchHLE_get_framebuffer(emulator, null_mut(), null_mut()).is_null());
+
+            touchHLE_destroy(emulator);
+        }
+    }
+
+    #[test]
+    fn null_emulator() {
+        let emulator = null_mut();
+        unsafe {
+            assert!(touchHLE_get_last_error(emulator).is_null());
+            assert_eq!(
+                touchHLE_set_option(emulator, c"--scale-hack=2".as_ptr()),
+                TOUCHHLE_ERROR
+            );
+            assert_eq!(
+                touchHLE_load_app(emulator, c"Some.app".as_ptr()),
+                TOUCHHLE_ERROR
+            );
+            assert_eq!(touchHLE_run_frame(emulator), TOUCHHLE_ERROR);
+            assert_eq!(touchHLE_run_frame_timeout(emulator, 0), TOUCHHLE_ERROR);
+            assert_eq!(touchHLE_get_exit_code(emulator), TOUCHHLE_ERROR);
+            assert_eq!(touchHLE_push_event(emulator, null()), TOUCHHLE_ERROR);
+            assert!(touchHLE_get_framebuffer(emulator, null_mut(), null_mut()).is_null());
+            touchHLE_destroy(emulator);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! The clock that all time observed by the app comes from.
+//!
+//! Normally this is just the host's clock. With `--virtual-clock`, it is
+//! instead an emulated clock that only moves forward when the guest executes
+//! instructions (CPU ticks) or when all threads are asleep, and which starts
+//! at a fixed date. Combined with `--replay-input=`, this makes it possible
+//! for two runs of an app to behave identically.
+//!
+//! Anything that the app can observe, directly (e.g. `mach_absolute_time()`)
+//! or indirectly (e.g. when an `NSTimer` fires), must use [Clock::now] or
+//! [Clock::system_time] rather than [Instant::now] or [SystemTime::now].
+//! Things the app can't observe, like FPS counters, should use the host clock.
+
+use crate::save_state::{Reader, SaveState, Writer};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How many CPU ticks (roughly: instructions) correspond to a second of
+/// virtual time. This is a guess at the speed of the original iPhone's CPU.
+pub const VIRTUAL_TICKS_PER_SECOND: u64 = 200_000_000;
+
+/// The date the virtual clock starts at: 2010-01-01 00:00:00 UTC.
+const VIRTUAL_EPOCH_UNIX_SECONDS: u64 = 1_262_304_000;
+
+pub struct Clock {
+    startup_time: Instant,
+    /// [None] if the host clock is in use, otherwise the current virtual time
+    /// relative to `startup_time`.
+    virtual_elapsed: Option<VirtualElapsed>,
+}
+
+#[derive(Copy, Clone)]
+struct VirtualElapsed {
+    /// Time accumulated from sleeps and from whole seconds' worth of ticks.
+    duration: Duration,
+    /// Ticks that haven't yet been converted to [Duration].
+    ticks: u64,
+}
+
+impl Clock {
+    pub fn new(use_virtual_clock: bool) -> Clock {
+        Clock {
+            startup_time: Instant::now(),
+            virtual_elapsed: use_virtual_clock.then_some(VirtualElapsed {
+                duration: Duration::ZERO,
+                ticks: 0,
+            }),
+        }
+    }
+
+    /// Time elapsed since touchHLE started (or the virtual equivalent).
+    pub fn uptime(&self) -> Duration {
+        match self.virtual_elapsed {
+            None => Instant::now().duration_since(self.startup_time),
+            Some(VirtualElapsed { duration, ticks }) => duration + ticks_to_duration(ticks),
+        }
+    }
+
+    /// Monotonic time, the equivalent of [Instant::now].
+    pub fn now(&self) -> Instant {
+        match self.virtual_elapsed {
+            None => Instant::now(),
+            Some(_) => self.startup_time.checked_add(self.uptime()).unwrap(),
+        }
+    }
+
+    /// Calendar time, the equivalent of [SystemTime::now].
+    pub fn system_time(&self) -> SystemTime {
+        match self.virtual_elapsed {
+            None => SystemTime::now(),
+            Some(_) => (SystemTime::UNIX_EPOCH + Duration::from_secs(VIRTUAL_EPOCH_UNIX_SECONDS))
+                .checked_add(self.uptime())
+                .unwrap(),
+        }
+    }
+
+    /// Save the clock's time. Only the virtual clock can be saved, since the
+    /// host clock can't be turned back.
+    pub fn save_state(&self, w: &mut Writer) {
+        self.virtual_elapsed
+            .map(|VirtualElapsed { duration, ticks }| (duration, ticks))
+            .save(w);
+    }
+
+    /// Get a copy of this clock with the time saved by [Self::save_state], if
+    /// both use the virtual clock.
+    pub fn restored_from_state(&self, r: &mut Reader) -> Result<Clock, String> {
+        let saved: Option<(Duration, u64)> = SaveState::restore(r)?;
+        Ok(Clock {
+            startup_time: self.startup_time,
+            virtual_elapsed: match (self.virtual_elapsed, saved) {
+                (Some(_), Some((duration, ticks))) => Some(VirtualElapsed { duration, ticks }),
+                (current, _) => current,
+            },
+        })
+    }
+
+    /// Account for CPU ticks spent executing guest code. This does nothing
+    /// when using the host clock.
+    pub fn add_ticks(&mut self, ticks: u64) {
+        if let Some(ref mut elapsed) = self.virtual_elapsed {
+            elapsed.ticks += ticks;
+            let whole_seconds = elapsed.ticks / VIRTUAL_TICKS_PER_SECOND;
+            elapsed.ticks %= VIRTUAL_TICKS_PER_SECOND;
+            elapsed.duration += Duration::from_secs(whole_seconds);
+        }
+    }
+
+    /// Wait until the clock reaches a particular time, because there is
+    /// nothing else to do.
+    ///
+    /// With the virtual clock, the time jumps forward immediately. The host
+    /// thread still sleeps if the virtual clock has gotten ahead of the host
+    /// clock, so that apps don't run faster than they would on a real device.
+    pub fn wait_until(&mut self, until: Instant) {
+        let Some(ref mut elapsed) = self.virtual_elapsed else {
+            std::thread::sleep(until.saturating_duration_since(Instant::now()));
+            return;
+        };
+
+        let until = until.saturating_duration_since(self.startup_time);
+        let current = elapsed.duration + ticks_to_duration(elapsed.ticks);
+        if until > current {
+            elapsed.duration = until;
+            elapsed.ticks = 0;
+        }
+
+        let host_uptime = Instant::now().duration_since(self.startup_time);
+        if let Some(ahead_by) = until.checked_sub(host_uptime) {
+            std::thread::sleep(ahead_by);
+        }
+    }
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks * 1_000_000_000 / VIRTUAL_TICKS_PER_SECOND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut clock = Clock::new(/* use_virtual_clock: */ true);
+        assert_eq!(clock.uptime(), Duration::ZERO);
+
+        clock.add_ticks(VIRTUAL_TICKS_PER_SECOND / 200);
+        assert_eq!(clock.uptime(), Duration::from_millis(5));
+        clock.add_ticks(VIRTUAL_TICKS_PER_SECOND / 100);
+        assert_eq!(clock.uptime(), Duration::from_millis(15));
+
+        // Waiting for a time in the past does nothing.
+        clock.wait_until(clock.now() - Duration::from_millis(100));
+        assert_eq!(clock.uptime(), Duration::from_millis(15));
+
+        let start = clock.now();
+        clock.wait_until(start + Duration::from_millis(10));
+        assert_eq!(clock.now(), start + Duration::from_millis(10));
+
+        assert_eq!(
+            clock
+                .system_time()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap(),
+            Duration::from_secs(VIRTUAL_EPOCH_UNIX_SECONDS) + Duration::from_millis(25)
+        );
+    }
+}
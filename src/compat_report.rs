@@ -0,0 +1,143 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Static compatibility report (`--compat-report`).
+//!
+//! This loads and links the app's binaries without running anything, and then
+//! lists what the app references that touchHLE doesn't implement:
+//!
+//! - Functions imported via symbol stubs (i.e. lazily-linked), which would
+//!   cause a panic when called.
+//! - Other imported symbols (non-lazy symbol pointers and external relocations)
+//!   that couldn't be linked. These are usually constants.
+//! - Objective-C classes that are referenced but not implemented.
+//! - Objective-C selectors referenced by the app that no host class implements,
+//!   excluding ones the app implements itself. Not all of these will actually
+//!   be sent to a host object, so this list is only a hint.
+//!
+//! The report is JSON, for example:
+//!
+//! ```json
+//! {
+//!   "bundle_identifier": "com.example.app",
+//!   "unimplemented_functions": ["_foo"],
+//!   "unimplemented_symbols": ["_kFooConstant"],
+//!   "unimplemented_classes": ["UIFoo"],
+//!   "unimplemented_selectors": ["fooWithBar:"]
+//! }
+//! ```
+
+use crate::bundle::Bundle;
+use crate::dyld::{has_host_constant, has_host_function, Dyld};
+use crate::environment::load_binaries;
+use crate::fs::Fs;
+use crate::mach_o::{MachO, SectionType};
+use crate::mem::Mem;
+use crate::objc::ObjC;
+use std::collections::BTreeSet;
+
+/// Produce the report for an app as a JSON string.
+pub fn generate(bundle: &Bundle, fs: &Fs) -> Result<String, String> {
+    let mut mem = Mem::new();
+    let bins = load_binaries(bundle, fs, &mut mem)?;
+
+    let is_exported = |symbol: &str| {
+        bins.iter()
+            .any(|bin| bin.exported_symbols.contains_key(symbol))
+    };
+
+    let mut functions = BTreeSet::new();
+    let mut symbols = BTreeSet::new();
+    let mut classes = BTreeSet::new();
+    for bin in &bins {
+        for symbol in indirect_symbols(bin, SectionType::SymbolStubs) {
+            if !has_host_function(symbol) && !is_exported(symbol) {
+                functions.insert(symbol.to_string());
+            }
+        }
+        for symbol in indirect_symbols(bin, SectionType::NonLazySymbolPointers) {
+            if !has_host_function(symbol) && !has_host_constant(symbol) && !is_exported(symbol) {
+                symbols.insert(symbol.to_string());
+            }
+        }
+        for (_, symbol) in &bin.external_relocations {
+            // These match the cases in Dyld::do_non_lazy_linking.
+            if let Some(class_name) = symbol
+                .strip_prefix("_OBJC_CLASS_$_")
+                .or_else(|| symbol.strip_prefix("_OBJC_METACLASS_$_"))
+            {
+                if !ObjC::has_host_class(class_name) {
+                    classes.insert(class_name.to_string());
+                }
+            } else if symbol != "___CFConstantStringClassReference"
+                && symbol != "__objc_empty_vtable"
+                && symbol != "__objc_empty_cache"
+                && !is_exported(symbol)
+            {
+                symbols.insert(symbol.clone());
+            }
+        }
+    }
+
+    // Selectors can only be checked once the app's classes are known, which
+    // requires linking.
+    let mut objc = ObjC::new();
+    Dyld::new().do_initial_linking(&bins, &mut mem, &mut objc);
+    let selectors = objc.find_unimplemented_selectors(&bins[0], &mem);
+
+    let fields = [
+        ("bundle_identifier", json_string(bundle.bundle_identifier())),
+        ("unimplemented_functions", json_string_array(functions)),
+        ("unimplemented_symbols", json_string_array(symbols)),
+        ("unimplemented_classes", json_string_array(classes)),
+        ("unimplemented_selectors", json_string_array(selectors)),
+    ];
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("  {}: {}", json_string(key), value))
+        .collect();
+    Ok(format!("{{\n{}\n}}", fields.join(",\n")))
+}
+
+fn indirect_symbols(bin: &MachO, type_: SectionType) -> impl Iterator<Item = &str> {
+    bin.get_section(type_)
+        .into_iter()
+        .flat_map(|section| {
+            &section
+                .dyld_indirect_symbol_info
+                .as_ref()
+                .unwrap()
+                .indirect_undef_symbols
+        })
+        .flat_map(|symbol| symbol.as_deref())
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn json_string_array<T: AsRef<str>>(items: impl IntoIterator<Item = T>) -> String {
+    let items: Vec<String> = items
+        .into_iter()
+        .map(|item| format!("    {}", json_string(item.as_ref())))
+        .collect();
+    if items.is_empty() {
+        "[]".to_string()
+    } else {
+        format!("[\n{}\n  ]", items.join(",\n"))
+    }
+}
@@ -18,11 +18,41 @@ use touchHLE_dynarmic_wrapper::*;
 
 type VAddr = u32;
 
+/// Passed to the memory access callbacks as `touchHLE_Mem`.
+struct MemAccessContext<'a> {
+    mem: &'a mut Mem,
+    watchpoints: &'a [Watchpoint],
+    /// The first watchpoint hit during execution, if any.
+    watchpoint_hit: Option<Watchpoint>,
+}
+
+impl MemAccessContext<'_> {
+    /// Check whether an access hits a watchpoint, and if so, record it.
+    fn check_watchpoints(&mut self, addr: VAddr, size: usize, is_write: bool) -> bool {
+        if self.watchpoints.is_empty() {
+            return false;
+        }
+        let Some(&hit) = self.watchpoints.iter().find(|watchpoint| {
+            watchpoint.overlaps(addr, size as GuestUSize)
+                && match watchpoint.kind {
+                    WatchpointKind::Write => is_write,
+                    WatchpointKind::Read => !is_write,
+                    WatchpointKind::Access => true,
+                }
+        }) else {
+            return false;
+        };
+        self.watchpoint_hit.get_or_insert(hit);
+        true
+    }
+}
+
 fn touchHLE_cpu_read_impl<T: SafeRead + Default>(
-    mem: *mut touchHLE_Mem,
+    context: *mut touchHLE_Mem,
     addr: VAddr,
     error: *mut bool,
 ) -> T {
+    let context = unsafe { &mut *context.cast::<MemAccessContext>() };
     // If a panic occurs (probably due to a null-pointer access), we can't let
     // it keep unwinding as it will hit non-Rust stack frames (dynarmic).
     // Instead we catch the unwind and then tell the C++ code a problem occurred
@@ -37,22 +67,33 @@ fn touchHLE_cpu_read_impl<T: SafeRead + Default>(
     // I'm not sure if this actually is unwind-safe, but considering
     // the emulator will crash anyway, maybe this is okay.
     let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        let mem = unsafe { &mut *mem.cast::<Mem>() };
         let ptr: ConstPtr<T> = Ptr::from_bits(addr);
-        mem.read(ptr)
+        context.mem.read(ptr)
     }));
+    // Hitting a watchpoint also halts execution, the same way.
+    let watchpoint_hit = context.check_watchpoints(addr, std::mem::size_of::<T>(), false);
     unsafe {
-        error.write(res.is_err());
+        error.write(res.is_err() || watchpoint_hit);
     }
     res.unwrap_or_default()
 }
 
-fn touchHLE_cpu_write_impl<T: SafeWrite>(mem: *mut touchHLE_Mem, addr: VAddr, value: T) -> bool {
+fn touchHLE_cpu_write_impl<T: SafeWrite>(
+    context: *mut touchHLE_Mem,
+    addr: VAddr,
+    value: T,
+) -> bool {
+    let context = unsafe { &mut *context.cast::<MemAccessContext>() };
+    // The instruction that hits a watchpoint is re-executed once execution
+    // resumes (see Cpu::run_or_step), so the write must not happen now, or it
+    // would happen twice.
+    if context.check_watchpoints(addr, std::mem::size_of::<T>(), true) {
+        return true;
+    }
     // See comments above about catch_unwind
     let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        let mem = unsafe { &mut *mem.cast::<Mem>() };
         let ptr: MutPtr<T> = Ptr::from_bits(addr);
-        mem.write(ptr, value)
+        context.mem.write(ptr, value)
     }));
     res.is_err()
 }
@@ -74,6 +115,20 @@ extern "C" fn touchHLE_cpu_read_u32(mem: *mut touchHLE_Mem, addr: VAddr, error:
 extern "C" fn touchHLE_cpu_read_u64(mem: *mut touchHLE_Mem, addr: VAddr, error: *mut bool) -> u64 {
     touchHLE_cpu_read_impl(mem, addr, error)
 }
+/// Instruction fetches happen when code is compiled rather than when it is
+/// executed, so they must not trigger watchpoints.
+#[no_mangle]
+extern "C" fn touchHLE_cpu_read_code(mem: *mut touchHLE_Mem, addr: VAddr, error: *mut bool) -> u32 {
+    let context = unsafe { &mut *mem.cast::<MemAccessContext>() };
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let ptr: ConstPtr<u32> = Ptr::from_bits(addr);
+        context.mem.read(ptr)
+    }));
+    unsafe {
+        error.write(res.is_err());
+    }
+    res.unwrap_or_default()
+}
 #[no_mangle]
 extern "C" fn touchHLE_cpu_write_u8(mem: *mut touchHLE_Mem, addr: VAddr, value: u8) -> bool {
     touchHLE_cpu_write_impl(mem, addr, value)
@@ -96,6 +151,10 @@ pub struct Cpu {
     /// Copy of the direct memory access pointer used to check it has not
     /// changed. If this is null, direct memory access is not in use.
     direct_memory_access_ptr: *const std::ffi::c_void,
+    watchpoints: Vec<Watchpoint>,
+    /// Set after a watchpoint is hit, so the instruction that hit it can be
+    /// re-executed without hitting it again.
+    step_over_watchpoint: bool,
 }
 
 impl Drop for Cpu {
@@ -121,6 +180,25 @@ impl Drop for CpuContext {
     }
 }
 
+/// Copy of the state of the CPU's registers. See [Cpu::save_registers].
+pub struct CpuRegisters {
+    regs: [u32; 16],
+    ext_regs: [u32; 64],
+    cpsr: u32,
+    fpscr: u32,
+}
+crate::impl_SaveState_for_struct!(CpuRegisters {
+    regs,
+    ext_regs,
+    cpsr,
+    fpscr
+});
+impl CpuRegisters {
+    pub fn regs(&self) -> &[u32; 16] {
+        &self.regs
+    }
+}
+
 /// Why CPU execution ended.
 #[derive(Debug)]
 pub enum CpuState {
@@ -133,6 +211,35 @@ pub enum CpuState {
     Error(CpuError),
 }
 
+/// Which kind of memory access a [Watchpoint] applies to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchpointKind {
+    Write,
+    Read,
+    /// Either read or write.
+    Access,
+}
+
+/// Stops execution when a range of memory is accessed by guest code. See
+/// [Cpu::add_watchpoint].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub kind: WatchpointKind,
+    pub addr: VAddr,
+    pub size: GuestUSize,
+}
+
+impl Watchpoint {
+    fn overlaps(&self, addr: VAddr, size: GuestUSize) -> bool {
+        let (start, end) = (
+            u64::from(self.addr),
+            u64::from(self.addr) + u64::from(self.size),
+        );
+        let (other_start, other_end) = (u64::from(addr), u64::from(addr) + u64::from(size));
+        start < other_end && other_start < end
+    }
+}
+
 /// A reason that can cause CPU execution to be interrupted.
 #[derive(Debug)]
 pub enum CpuError {
@@ -142,6 +249,9 @@ pub enum CpuError {
     UndefinedInstruction,
     /// Breakpoint (`bkpt` instruction).
     Breakpoint,
+    /// A [Watchpoint] was hit. The PC is at the instruction that accessed the
+    /// memory.
+    Watchpoint(Watchpoint),
 }
 
 impl Cpu {
@@ -184,6 +294,8 @@ impl Cpu {
         Cpu {
             dynarmic_wrapper,
             direct_memory_access_ptr,
+            watchpoints: Vec::new(),
+            step_over_watchpoint: false,
         }
     }
 
@@ -201,23 +313,31 @@ impl Cpu {
     }
 
     pub fn dump_regs(&self) {
+        let mut regs = String::new();
+        self.write_regs(&mut regs);
+        for line in regs.lines() {
+            echo!("{}", line);
+        }
+    }
+
+    /// Write the registers in the same format as [Cpu::dump_regs].
+    pub fn write_regs(&self, out: &mut String) {
+        use std::fmt::Write;
         let regs = self.regs();
         for row in 0..4 {
-            use std::fmt::Write;
-            let mut line = String::new();
             for col in 0..4 {
                 let reg_idx = row * 4 + col;
                 match reg_idx {
-                    Self::SP => write!(&mut line, "\t SP: "),
-                    Self::LR => write!(&mut line, "\t LR: "),
-                    Self::PC => write!(&mut line, "\t PC: "),
-                    _ if reg_idx <= 9 => write!(&mut line, "\t R{}: ", reg_idx),
-                    _ => write!(&mut line, "\tR{}: ", reg_idx),
+                    Self::SP => write!(out, "\t SP: "),
+                    Self::LR => write!(out, "\t LR: "),
+                    Self::PC => write!(out, "\t PC: "),
+                    _ if reg_idx <= 9 => write!(out, "\t R{}: ", reg_idx),
+                    _ => write!(out, "\tR{}: ", reg_idx),
                 }
                 .unwrap();
-                write!(&mut line, "{:#010x}", regs[reg_idx]).unwrap();
+                write!(out, "{:#010x}", regs[reg_idx]).unwrap();
             }
-            echo!("{}", line);
+            out.push('\n');
         }
     }
 
@@ -228,6 +348,44 @@ impl Cpu {
         unsafe { touchHLE_DynarmicWrapper_set_cpsr(self.dynarmic_wrapper, cpsr) }
     }
 
+    /// VFP registers (S0 to S31 and the upper halves of D16 to D31).
+    pub fn ext_regs(&self) -> &[u32; 64] {
+        unsafe {
+            let ptr = touchHLE_DynarmicWrapper_ext_regs_const(self.dynarmic_wrapper);
+            &*(ptr as *const [u32; 64])
+        }
+    }
+    pub fn ext_regs_mut(&mut self) -> &mut [u32; 64] {
+        unsafe {
+            let ptr = touchHLE_DynarmicWrapper_ext_regs_mut(self.dynarmic_wrapper);
+            &mut *(ptr as *mut [u32; 64])
+        }
+    }
+
+    pub fn fpscr(&self) -> u32 {
+        unsafe { touchHLE_DynarmicWrapper_fpscr(self.dynarmic_wrapper) }
+    }
+    pub fn set_fpscr(&mut self, fpscr: u32) {
+        unsafe { touchHLE_DynarmicWrapper_set_fpscr(self.dynarmic_wrapper, fpscr) }
+    }
+
+    /// Get a copy of all the registers, for save states.
+    pub fn save_registers(&self) -> CpuRegisters {
+        CpuRegisters {
+            regs: *self.regs(),
+            ext_regs: *self.ext_regs(),
+            cpsr: self.cpsr(),
+            fpscr: self.fpscr(),
+        }
+    }
+    /// Overwrite all the registers, for save states.
+    pub fn restore_registers(&mut self, registers: &CpuRegisters) {
+        *self.regs_mut() = registers.regs;
+        *self.ext_regs_mut() = registers.ext_regs;
+        self.set_cpsr(registers.cpsr);
+        self.set_fpscr(registers.fpscr);
+    }
+
     /// Swap the current state of the CPU (registers etc) with the state stored
     /// in the context object.
     pub fn swap_context(&mut self, context: &mut CpuContext) {
@@ -272,6 +430,52 @@ impl Cpu {
         }
     }
 
+    /// Clear dynarmic's entire instruction cache. This is needed when all of
+    /// memory is replaced, i.e. when loading a save state.
+    pub fn clear_cache(&mut self) {
+        unsafe { touchHLE_DynarmicWrapper_clear_cache(self.dynarmic_wrapper) }
+    }
+
+    /// Add a watchpoint. This only has an effect on memory accesses by guest
+    /// code.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+        self.update_page_direct_access(watchpoint);
+    }
+
+    /// Remove a watchpoint added with [Self::add_watchpoint]. Returns [false]
+    /// if there was no such watchpoint.
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let Some(index) = self.watchpoints.iter().position(|&w| w == watchpoint) else {
+            return false;
+        };
+        self.watchpoints.remove(index);
+        self.update_page_direct_access(watchpoint);
+        true
+    }
+
+    /// Direct memory access bypasses the memory access callbacks, where
+    /// watchpoints are checked, so it has to be disabled for pages that have
+    /// watchpoints.
+    fn update_page_direct_access(&mut self, watchpoint: Watchpoint) {
+        const PAGE_SIZE: GuestUSize = 0x1000;
+        let first_page = watchpoint.addr / PAGE_SIZE;
+        let last_page = watchpoint.addr.saturating_add(watchpoint.size.max(1) - 1) / PAGE_SIZE;
+        for page in first_page..=last_page {
+            let watched = self
+                .watchpoints
+                .iter()
+                .any(|w| w.overlaps(page * PAGE_SIZE, PAGE_SIZE));
+            unsafe {
+                touchHLE_DynarmicWrapper_set_page_direct_access(
+                    self.dynarmic_wrapper,
+                    page,
+                    !watched,
+                )
+            }
+        }
+    }
+
     /// Start CPU execution.
     ///
     /// If `ticks` is [Some], it is used as an abstract time limit. The value
@@ -289,16 +493,55 @@ impl Cpu {
             assert!(self.direct_memory_access_ptr == unsafe { mem.direct_memory_access_ptr() });
         }
 
+        if !self.step_over_watchpoint {
+            return self.run_or_step_inner(mem, ticks, true);
+        }
+
+        // Re-execute the instruction that hit a watchpoint, without
+        // watchpoints, and then carry on as normal.
+        self.step_over_watchpoint = false;
+        let state = self.run_or_step_inner(mem, None, false);
+        let Some(ticks) = ticks else {
+            return state;
+        };
+        *ticks = ticks.saturating_sub(1);
+        if !matches!(state, CpuState::Normal) || *ticks == 0 {
+            return state;
+        }
+        self.run_or_step_inner(mem, Some(ticks), true)
+    }
+
+    fn run_or_step_inner(
+        &mut self,
+        mem: &mut Mem,
+        ticks: Option<&mut u64>,
+        use_watchpoints: bool,
+    ) -> CpuState {
+        let mut context = MemAccessContext {
+            mem,
+            watchpoints: if use_watchpoints {
+                &self.watchpoints
+            } else {
+                &[]
+            },
+            watchpoint_hit: None,
+        };
         let res = unsafe {
             touchHLE_DynarmicWrapper_run_or_step(
                 self.dynarmic_wrapper,
-                mem as *mut Mem as *mut touchHLE_Mem,
+                &mut context as *mut MemAccessContext as *mut touchHLE_Mem,
                 ticks,
             )
         };
         match res {
             -1 => CpuState::Normal,
-            -2 => CpuState::Error(CpuError::MemoryError),
+            -2 => match context.watchpoint_hit {
+                Some(watchpoint) => {
+                    self.step_over_watchpoint = true;
+                    CpuState::Error(CpuError::Watchpoint(watchpoint))
+                }
+                None => CpuState::Error(CpuError::MemoryError),
+            },
             -3 => CpuState::Error(CpuError::UndefinedInstruction),
             -4 => CpuState::Error(CpuError::Breakpoint),
             _ if res < -4 => panic!("Unexpected CPU execution result"),
@@ -13,8 +13,9 @@
 /// Opaque type from C
 #[allow(non_camel_case_types)]
 pub type touchHLE_DynarmicWrapper = std::ffi::c_void;
-/// Opaque type from Rust (this is the `Mem` type from the main crate, but
-/// `c_void` is used here to avoid depending on it directly)
+/// Opaque type from Rust (this is the `MemAccessContext` type from the main
+/// crate, which contains a `Mem`, but `c_void` is used here to avoid depending
+/// on it directly)
 #[allow(non_camel_case_types)]
 pub type touchHLE_Mem = std::ffi::c_void;
 /// Opaque C++ type
@@ -33,8 +34,14 @@ extern "C" {
     pub fn touchHLE_DynarmicWrapper_delete(cpu: *mut touchHLE_DynarmicWrapper);
     pub fn touchHLE_DynarmicWrapper_regs_const(cpu: *const touchHLE_DynarmicWrapper) -> *const u32;
     pub fn touchHLE_DynarmicWrapper_regs_mut(cpu: *mut touchHLE_DynarmicWrapper) -> *mut u32;
+    pub fn touchHLE_DynarmicWrapper_ext_regs_const(
+        cpu: *const touchHLE_DynarmicWrapper,
+    ) -> *const u32;
+    pub fn touchHLE_DynarmicWrapper_ext_regs_mut(cpu: *mut touchHLE_DynarmicWrapper) -> *mut u32;
     pub fn touchHLE_DynarmicWrapper_cpsr(cpu: *const touchHLE_DynarmicWrapper) -> u32;
     pub fn touchHLE_DynarmicWrapper_set_cpsr(cpu: *mut touchHLE_DynarmicWrapper, cpsr: u32);
+    pub fn touchHLE_DynarmicWrapper_fpscr(cpu: *const touchHLE_DynarmicWrapper) -> u32;
+    pub fn touchHLE_DynarmicWrapper_set_fpscr(cpu: *mut touchHLE_DynarmicWrapper, fpscr: u32);
     pub fn touchHLE_DynarmicWrapper_swap_context(
         cpu: *mut touchHLE_DynarmicWrapper,
         context: *mut Dynarmic_A32_Context,
@@ -44,6 +51,12 @@ extern "C" {
         start: VAddr,
         size: u32,
     );
+    pub fn touchHLE_DynarmicWrapper_clear_cache(cpu: *mut touchHLE_DynarmicWrapper);
+    pub fn touchHLE_DynarmicWrapper_set_page_direct_access(
+        cpu: *mut touchHLE_DynarmicWrapper,
+        page: u32,
+        enabled: bool,
+    );
     pub fn touchHLE_DynarmicWrapper_run_or_step(
         cpu: *mut touchHLE_DynarmicWrapper,
         mem: *mut touchHLE_Mem,
@@ -0,0 +1,470 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Built-in debugger console (`--debug-console`).
+//!
+//! This is a simple line-oriented alternative to the GDB server (see
+//! [crate::gdb]) which needs no client beyond a terminal, or something like
+//! `nc` when it's used over TCP. Since it's part of touchHLE, it can do things
+//! GDB can't, like describing Objective-C objects and breaking on Objective-C
+//! methods and host functions (see [crate::breakpoints]).
+
+use crate::breakpoints::Breakpoint;
+use crate::cpu::Cpu;
+use crate::gdb::with_thread_regs;
+use crate::mem::{GuestUSize, Ptr};
+use crate::objc::{id, nil, IvarInfo, ObjC};
+use crate::Environment;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+
+const HELP: &str = "\
+Commands:
+  continue, c             Resume execution.
+  step, s                 Execute one instruction.
+  threads                 List threads. The current thread is marked with *.
+  regs [THREAD]           Print the registers of the current thread or THREAD.
+  bt                      Print a stack trace for the current thread.
+  x ADDR [COUNT]          Dump COUNT bytes of memory (default 64).
+  obj ADDR                Print an Objective-C object's class and ivars.
+  objects [CLASS]         Count objects by class, or list the objects of CLASS.
+  break WHERE, b WHERE    Break when a method or function is called. WHERE can
+                          be e.g. -[NSString initWithFormat:], +[* alloc] (any
+                          class), initWithFormat: (any class, if the selector
+                          has a colon), or malloc (a function implemented by
+                          touchHLE).
+  breakpoints             List breakpoints.
+  delete N                Delete breakpoint N.
+  help                    Show this message.
+ADDR and COUNT can be numbers (use 0x for hexadecimal) or registers (r0, sp...).
+";
+
+/// Limit for `x`, to avoid accidentally flooding the console.
+const MAX_DUMP_SIZE: GuestUSize = 4096;
+
+pub struct DebugConsole {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl DebugConsole {
+    /// Use the terminal (standard input and output).
+    pub fn new_stdio() -> Self {
+        DebugConsole {
+            input: Box::new(std::io::stdin().lock()),
+            output: Box::new(std::io::stdout()),
+        }
+    }
+
+    /// Wait for a TCP connection on one of `addrs` and use that.
+    pub fn listen(addrs: &[SocketAddr]) -> Result<Self, String> {
+        let listener = TcpListener::bind(addrs)
+            .map_err(|e| format!("Could not bind to {:?}: {}", addrs, e))?;
+        echo!(
+            "Waiting for debug console connection on {}...",
+            addrs
+                .iter()
+                .map(|a| format!("{}", a))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        let (client, client_addr) = listener
+            .accept()
+            .map_err(|e| format!("Could not accept connection: {}", e))?;
+        echo!("Debug console client connected on {}.", client_addr);
+        let output = client
+            .try_clone()
+            .map_err(|e| format!("Could not use connection: {}", e))?;
+        Ok(DebugConsole {
+            input: Box::new(BufReader::new(client)),
+            output: Box::new(output),
+        })
+    }
+
+    fn print(&mut self, text: &str) {
+        // There's nothing useful to do if the console has gone away.
+        let _ = self.output.write_all(text.as_bytes());
+        let _ = self.output.flush();
+    }
+
+    /// Print `message`, which explains why execution is paused, then handle
+    /// commands until execution should resume. Returns [true] if the CPU
+    /// should step and then call this again, or [false] if it should resume
+    /// normal execution. If `can_step` is [false], stepping is refused.
+    pub fn wait_for_debugger(
+        &mut self,
+        env: &mut Environment,
+        message: &str,
+        can_step: bool,
+    ) -> bool {
+        self.print(&format!(
+            "{}\nPC is {:#x} on thread {}. Type \"help\" for a list of commands.\n",
+            message,
+            env.cpu.pc_with_thumb_bit().addr_with_thumb_bit(),
+            env.current_thread
+        ));
+        loop {
+            self.print("(touchHLE) ");
+            let mut line = String::new();
+            if !matches!(self.input.read_line(&mut line), Ok(1..)) {
+                log!("Debug console input has ended, resuming execution.");
+                return false;
+            }
+            let mut words = line.split_whitespace();
+            let Some(command) = words.next() else {
+                continue;
+            };
+            let args: Vec<&str> = words.collect();
+            let result = match command {
+                "continue" | "c" => return false,
+                "step" | "s" if can_step => return true,
+                "step" | "s" => Err("Can't step here, because execution is paused inside a \
+                                     host function. Use \"continue\" instead."
+                    .to_string()),
+                "threads" => Ok(list_threads(env)),
+                "regs" => print_regs(env, &args),
+                "bt" => {
+                    let mut out = String::new();
+                    env.write_stack_trace(&mut out);
+                    Ok(out)
+                }
+                "x" => dump_memory(env, &args),
+                "obj" => match args.as_slice() {
+                    [addr] => parse_value(env, addr)
+                        .and_then(|addr| describe_object(env, Ptr::from_bits(addr))),
+                    _ => Err("Usage: obj ADDR".to_string()),
+                },
+                "objects" => match args.as_slice() {
+                    [] => Ok(list_objects(env, None)),
+                    [class_name] => Ok(list_objects(env, Some(class_name))),
+                    _ => Err("Usage: objects [CLASS]".to_string()),
+                },
+                "break" | "b" => add_breakpoint(env, &args.join(" ")),
+                "breakpoints" => Ok(list_breakpoints(env)),
+                "delete" => match args.as_slice() {
+                    [number] => delete_breakpoint(env, number),
+                    _ => Err("Usage: delete N".to_string()),
+                },
+                "help" => Ok(HELP.to_string()),
+                _ => Err(format!(
+                    "Unknown command {:?}. Type \"help\" for a list of commands.",
+                    command
+                )),
+            };
+            match result {
+                Ok(output) => self.print(&output),
+                Err(error) => self.print(&format!("{}\n", error)),
+            }
+        }
+    }
+}
+
+/// Parse a number (decimal, or hexadecimal with `0x`) or a register name.
+fn parse_value(env: &Environment, arg: &str) -> Result<u32, String> {
+    let arg = arg.to_ascii_lowercase();
+    let reg = match arg.as_str() {
+        "sp" => Some(Cpu::SP),
+        "lr" => Some(Cpu::LR),
+        "pc" => Some(Cpu::PC),
+        _ => arg
+            .strip_prefix('r')
+            .and_then(|n| n.parse().ok())
+            .filter(|&n: &usize| n < 16),
+    };
+    if let Some(reg) = reg {
+        return Ok(env.cpu.regs()[reg]);
+    }
+    if let Some(hex) = arg.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        arg.parse()
+    }
+    .map_err(|_| format!("{:?} is not a number or register", arg))
+}
+
+fn list_threads(env: &mut Environment) -> String {
+    let mut out = String::new();
+    for thread in 0..env.threads.len() {
+        let marker = if thread == env.current_thread {
+            '*'
+        } else {
+            ' '
+        };
+        let state = env.threads[thread].describe_state();
+        if env.threads[thread].active {
+            let pc = with_thread_regs(
+                &mut env.cpu,
+                &mut env.threads,
+                env.current_thread,
+                thread,
+                |cpu| cpu.pc_with_thumb_bit().addr_with_thumb_bit(),
+            );
+            writeln!(out, "{} {:3}  PC {:#010x}  {}", marker, thread, pc, state).unwrap();
+        } else {
+            writeln!(out, "{} {:3}  {}", marker, thread, state).unwrap();
+        }
+    }
+    out
+}
+
+fn print_regs(env: &mut Environment, args: &[&str]) -> Result<String, String> {
+    let thread = match args {
+        [] => env.current_thread,
+        [thread] => thread
+            .parse()
+            .ok()
+            .filter(|&thread: &usize| env.threads.get(thread).is_some_and(|t| t.active))
+            .ok_or_else(|| format!("There's no active thread {:?}", thread))?,
+        _ => return Err("Usage: regs [THREAD]".to_string()),
+    };
+    let mut out = String::new();
+    with_thread_regs(
+        &mut env.cpu,
+        &mut env.threads,
+        env.current_thread,
+        thread,
+        |cpu| cpu.write_regs(&mut out),
+    );
+    Ok(out)
+}
+
+fn dump_memory(env: &Environment, args: &[&str]) -> Result<String, String> {
+    let (addr, count) = match args {
+        [addr] => (parse_value(env, addr)?, 64),
+        [addr, count] => (parse_value(env, addr)?, parse_value(env, count)?),
+        _ => return Err("Usage: x ADDR [COUNT]".to_string()),
+    };
+    if count > MAX_DUMP_SIZE {
+        return Err(format!(
+            "Can't dump more than {} bytes at once",
+            MAX_DUMP_SIZE
+        ));
+    }
+    let bytes = env
+        .mem
+        .get_bytes_fallible(Ptr::from_bits(addr), count)
+        .ok_or_else(|| format!("Can't read {} bytes at {:#x}", count, addr))?;
+    Ok(hex_dump(addr, bytes))
+}
+
+/// Format memory as lines of 16 bytes, with an address at the start and the
+/// ASCII interpretation at the end.
+fn hex_dump(addr: u32, bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        write!(out, "{:#010x}:", addr.wrapping_add(i as u32 * 16)).unwrap();
+        for byte in line {
+            write!(out, " {:02x}", byte).unwrap();
+        }
+        out.push_str(&"   ".repeat(16 - line.len()));
+        out.push_str("  ");
+        out.extend(line.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        out.push('\n');
+    }
+    out
+}
+
+/// Get the class name of an object, if it's a known object.
+fn object_class_name(env: &Environment, object: id) -> Option<String> {
+    env.objc.describe_host_object(object)?;
+    let isa = ObjC::read_isa(object, &env.mem);
+    env.objc.try_get_class_name(isa).map(ToOwned::to_owned)
+}
+
+fn describe_object(env: &Environment, object: id) -> Result<String, String> {
+    let (refcount, host_type) = env
+        .objc
+        .describe_host_object(object)
+        .ok_or_else(|| format!("{:?} is not a known Objective-C object", object))?;
+    let isa = ObjC::read_isa(object, &env.mem);
+    let class_name = env.objc.try_get_class_name(isa).unwrap_or("(unknown)");
+
+    let mut out = String::new();
+    if env.objc.try_get_class_name(object).is_some() {
+        writeln!(out, "{:?}: class object, metaclass {}", object, class_name).unwrap();
+    } else {
+        writeln!(out, "{:?}: instance of {}", object, class_name).unwrap();
+    }
+    match refcount {
+        Some(refcount) => writeln!(out, "Refcount: {}", refcount).unwrap(),
+        None => writeln!(out, "Refcount: none (static object)").unwrap(),
+    }
+    writeln!(out, "Host object: {}", host_type).unwrap();
+
+    let mut hierarchy = vec![isa];
+    while let Some(superclass) = env.objc.try_get_superclass(*hierarchy.last().unwrap()) {
+        if superclass == nil {
+            break;
+        }
+        hierarchy.push(superclass);
+    }
+    let names: Vec<&str> = hierarchy
+        .iter()
+        .map(|&class| env.objc.try_get_class_name(class).unwrap_or("(unknown)"))
+        .collect();
+    writeln!(out, "Class hierarchy: {}", names.join(" : ")).unwrap();
+
+    // Ivars are listed starting from the root class, which is also the order
+    // of their offsets.
+    let mut has_guest_class = false;
+    for (&class, name) in hierarchy.iter().zip(&names).rev() {
+        let Some(ivars) = env.objc.guest_class_ivars(class, &env.bins, &env.mem) else {
+            continue;
+        };
+        has_guest_class = true;
+        if ivars.is_empty() {
+            continue;
+        }
+        writeln!(out, "Ivars of {}:", name).unwrap();
+        for ivar in ivars {
+            writeln!(
+                out,
+                "  +{:<4} {} ({}) = {}",
+                ivar.offset,
+                ivar.name,
+                ivar.type_encoding,
+                describe_ivar_value(env, object, &ivar)
+            )
+            .unwrap();
+        }
+    }
+    if !has_guest_class {
+        writeln!(
+            out,
+            "This class is implemented by touchHLE, so its data is in the host object."
+        )
+        .unwrap();
+    }
+    Ok(out)
+}
+
+fn describe_ivar_value(env: &Environment, object: id, ivar: &IvarInfo) -> String {
+    let addr = object.to_bits().wrapping_add(ivar.offset);
+    let Some(bytes) = env.mem.get_bytes_fallible(Ptr::from_bits(addr), ivar.size) else {
+        return "(unreadable)".to_string();
+    };
+    if !matches!(ivar.size, 1 | 2 | 4 | 8) {
+        let mut res: Vec<String> = bytes
+            .iter()
+            .take(16)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        if bytes.len() > 16 {
+            res.push("...".to_string());
+        }
+        return res.join(" ");
+    }
+    let value = bytes
+        .iter()
+        .rev()
+        .fold(0u64, |value, &byte| (value << 8) | u64::from(byte));
+    if ivar.type_encoding.starts_with('@') && ivar.size == 4 {
+        let value: id = Ptr::from_bits(value as u32);
+        if value == nil {
+            return "nil".to_string();
+        }
+        if let Some(class_name) = object_class_name(env, value) {
+            return format!("{:?} ({})", value, class_name);
+        }
+    }
+    format!("{:#x}", value)
+}
+
+fn list_objects(env: &Environment, class_filter: Option<&str>) -> String {
+    let mut out = String::new();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut total = 0;
+    for (object, refcount) in env.objc.all_objects() {
+        // Classes are objects too, but they're not interesting here.
+        if env.objc.try_get_class_name(object).is_some() {
+            continue;
+        }
+        let class_name = object_class_name(env, object).unwrap_or_else(|| "(unknown)".to_string());
+        match class_filter {
+            Some(filter) if filter != class_name => continue,
+            Some(_) => match refcount {
+                Some(refcount) => writeln!(out, "{:?}  refcount {}", object, refcount).unwrap(),
+                None => writeln!(out, "{:?}  static", object).unwrap(),
+            },
+            None => *counts.entry(class_name).or_default() += 1,
+        }
+        total += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a_name, a_count), (b_name, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_name.cmp(b_name))
+    });
+    for (class_name, count) in counts {
+        writeln!(out, "{:7}  {}", count, class_name).unwrap();
+    }
+    writeln!(out, "{} objects in total.", total).unwrap();
+    out
+}
+
+fn add_breakpoint(env: &mut Environment, spec: &str) -> Result<String, String> {
+    if spec.is_empty() {
+        return Err("Usage: break METHOD|FUNCTION".to_string());
+    }
+    let breakpoint = Breakpoint::parse(spec)?;
+    let mut out = String::new();
+    if let Breakpoint::Method(ref method) = breakpoint {
+        if env.objc.lookup_selector(method.selector()).is_none() {
+            writeln!(
+                out,
+                "Note: no selector {:?} is known yet, so it won't be hit unless the app registers it.",
+                method.selector()
+            )
+            .unwrap();
+        }
+    }
+    let description = breakpoint.to_string();
+    let number = env.breakpoints.add(breakpoint);
+    writeln!(out, "Breakpoint {}: {}", number, description).unwrap();
+    Ok(out)
+}
+
+fn list_breakpoints(env: &Environment) -> String {
+    if env.breakpoints.is_empty() {
+        return "No breakpoints.\n".to_string();
+    }
+    let mut out = String::new();
+    for (number, breakpoint) in env.breakpoints.list() {
+        writeln!(out, "{:3}  {}", number, breakpoint).unwrap();
+    }
+    out
+}
+
+fn delete_breakpoint(env: &mut Environment, number: &str) -> Result<String, String> {
+    let number: u32 = number
+        .parse()
+        .map_err(|_| format!("Invalid breakpoint number {:?}", number))?;
+    if env.breakpoints.remove(number) {
+        Ok(format!("Deleted breakpoint {}.\n", number))
+    } else {
+        Err(format!("There's no breakpoint {}", number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dump_format() {
+        assert_eq!(
+            hex_dump(0x1000, b"Hello, world!\n\0\xffABC"),
+            "0x00001000: 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a 00 ff  Hello, world!...\n\
+             0x00001010: 41 42 43                                         ABC\n"
+        );
+    }
+}
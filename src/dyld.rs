@@ -20,15 +20,18 @@
 //!
 //! See [crate::mach_o] for resources.
 
+pub mod call_trace;
 mod constant_lists;
 mod function_lists;
 
-use crate::abi::{CallFromGuest, GuestFunction};
+use crate::abi::{CallFromGuest, CallTracer, GuestFunction};
 use crate::cpu::Cpu;
 use crate::frameworks::foundation::ns_string;
 use crate::mach_o::{MachO, SectionType};
 use crate::mem::{ConstVoidPtr, GuestUSize, Mem, MutPtr, Ptr};
 use crate::objc::{nil, ObjC};
+use crate::options::{glob_match, Options};
+use crate::save_state::{restore_iter, save_str, Reader, SaveState, Writer};
 use crate::Environment;
 use std::collections::HashMap;
 
@@ -141,6 +144,18 @@ pub fn search_lists<T>(
         .find(|&(sym, _)| *sym == symbol)
 }
 
+/// Check whether there is a host implementation of a function with this
+/// (mangled) symbol name.
+pub fn has_host_function(symbol: &str) -> bool {
+    search_lists(function_lists::FUNCTION_LISTS, symbol).is_some()
+}
+
+/// Check whether there is a host implementation of a constant with this
+/// (mangled) symbol name.
+pub fn has_host_constant(symbol: &str) -> bool {
+    search_lists(constant_lists::CONSTANT_LISTS, symbol).is_some()
+}
+
 fn encode_a32_svc(imm: u32) -> u32 {
     assert!(imm & 0xff000000 == 0);
     imm | 0xef000000
@@ -177,6 +192,49 @@ pub struct Dyld {
     thread_exit_routine: Option<GuestFunction>,
     constants_to_link_later: Vec<(MutPtr<ConstVoidPtr>, &'static HostConstant)>,
     non_lazy_host_functions: HashMap<&'static str, GuestFunction>,
+    /// Stubs created for unimplemented functions that the options allow to be
+    /// stubbed (see [UnimplementedFunctionStub]).
+    stubbed_functions: HashMap<&'static str, HostFunction>,
+}
+
+/// Host function that an unimplemented function gets linked to if it matches
+/// `--stub-unimplemented-functions=`. It logs the call and returns zero, which
+/// is enough to get past calls to e.g. analytics libraries whose results the
+/// app doesn't care about.
+struct UnimplementedFunctionStub {
+    symbol: &'static str,
+}
+impl UnimplementedFunctionStub {
+    /// Get the stub for a symbol. [HostFunction] references are `'static`, so
+    /// stubs are leaked, but only one is ever created per symbol (loading a
+    /// save state would otherwise create new ones each time).
+    fn get(symbol: &str) -> (&'static str, HostFunction) {
+        static STUBS: std::sync::Mutex<Option<HashMap<String, &UnimplementedFunctionStub>>> =
+            std::sync::Mutex::new(None);
+        let mut stubs = STUBS.lock().unwrap();
+        let stub: &'static Self = stubs
+            .get_or_insert_with(HashMap::new)
+            .entry(symbol.to_string())
+            .or_insert_with(|| {
+                let symbol = Box::leak(symbol.to_string().into_boxed_str());
+                Box::leak(Box::new(Self { symbol }))
+            });
+        (stub.symbol, stub)
+    }
+}
+impl CallFromGuest for UnimplementedFunctionStub {
+    fn call_from_guest_traced(&self, env: &mut Environment, tracer: Option<&mut dyn CallTracer>) {
+        log!(
+            "Call to unimplemented function {}, returning 0 because it is stubbed.",
+            self.symbol
+        );
+        // The arguments are unknown, so none are traced.
+        if let Some(tracer) = tracer {
+            tracer.trace_args(env, &[]);
+            tracer.trace_return(env, &0);
+        }
+        env.cpu.regs_mut()[0..2].fill(0);
+    }
 }
 
 impl Dyld {
@@ -200,6 +258,7 @@ impl Dyld {
             thread_exit_routine: None,
             constants_to_link_later: Vec::new(),
             non_lazy_host_functions: HashMap::new(),
+            stubbed_functions: HashMap::new(),
         }
     }
 
@@ -451,19 +510,20 @@ impl Dyld {
         }
     }
 
-    /// Return a host function that can be called to handle an SVC instruction
-    /// encountered during CPU emulation. If `None` is returned, the execution
-    /// needs to resume at `svc_pc`.
+    /// Return a host function, and its symbol, that can be called to handle an
+    /// SVC instruction encountered during CPU emulation. If `None` is returned,
+    /// the execution needs to resume at `svc_pc`.
     pub fn get_svc_handler(
         &mut self,
         bins: &[MachO],
         mem: &mut Mem,
         cpu: &mut Cpu,
+        options: &Options,
         svc_pc: u32,
         svc: u32,
-    ) -> Option<HostFunction> {
+    ) -> Option<(&'static str, HostFunction)> {
         match svc {
-            Self::SVC_LAZY_LINK => self.do_lazy_link(bins, mem, cpu, svc_pc),
+            Self::SVC_LAZY_LINK => self.do_lazy_link(bins, mem, cpu, options, svc_pc),
             Self::SVC_THREAD_EXIT | Self::SVC_RETURN_TO_HOST => unreachable!(), // don't handle here
             Self::SVC_LINKED_FUNCTIONS_BASE.. => {
                 let f = self
@@ -473,7 +533,7 @@ impl Dyld {
                     panic!("Unexpected SVC #{} at {:#x}", svc, svc_pc);
                 };
                 log_dbg!("Call to host function, already linked: {}", symbol);
-                Some(f)
+                Some((symbol, f))
             }
         }
     }
@@ -483,8 +543,9 @@ impl Dyld {
         bins: &[MachO],
         mem: &mut Mem,
         cpu: &mut Cpu,
+        options: &Options,
         svc_pc: u32,
-    ) -> Option<HostFunction> {
+    ) -> Option<(&'static str, HostFunction)> {
         // Links by restoring the original stub function, then updating
         // __la_symbol_ptr to the appropriate function.
         fn link_by_restoring_stub(
@@ -560,7 +621,10 @@ impl Dyld {
             return None;
         }
 
-        if let Some(&(symbol, f)) = search_lists(function_lists::FUNCTION_LISTS, symbol) {
+        let host_function = search_lists(function_lists::FUNCTION_LISTS, symbol)
+            .copied()
+            .or_else(|| self.stub_unimplemented_function(options, symbol));
+        if let Some((symbol, f)) = host_function {
             // Allocate an SVC ID for this host function
             let idx: u32 = self.linked_host_functions.len().try_into().unwrap();
             let svc = idx + Self::SVC_LINKED_FUNCTIONS_BASE;
@@ -581,7 +645,7 @@ impl Dyld {
 
             // Return the host function so that we can call it now that we're
             // done.
-            return Some(f);
+            return Some((symbol, f));
         }
 
         for dylib in bins.iter() {
@@ -604,6 +668,32 @@ impl Dyld {
         panic!("Call to unimplemented function {}", symbol);
     }
 
+    /// Get the stub for an unimplemented function, if the options allow it to
+    /// be stubbed.
+    fn stub_unimplemented_function(
+        &mut self,
+        options: &Options,
+        symbol: &str,
+    ) -> Option<(&'static str, HostFunction)> {
+        if let Some((&symbol, &f)) = self.stubbed_functions.get_key_value(symbol) {
+            return Some((symbol, f));
+        }
+        if !options
+            .stub_unimplemented_functions
+            .iter()
+            .any(|glob| glob_match(glob, symbol))
+        {
+            return None;
+        }
+        log!(
+            "Warning: linking unimplemented function {} to a stub that returns 0.",
+            symbol
+        );
+        let (symbol, f) = UnimplementedFunctionStub::get(symbol);
+        self.stubbed_functions.insert(symbol, f);
+        Some((symbol, f))
+    }
+
     /// Creates a guest function that will call a host function with the name
     /// `symbol`. This can be used to implement "get proc address" functions.
     /// Note that no attempt is made to deduplicate or deallocate these, so
@@ -660,3 +750,74 @@ impl Dyld {
         GuestFunction::from_addr_with_thumb_bit(function_ptr.to_bits())
     }
 }
+
+/// Host functions are saved by name, and looked up again when restoring.
+impl SaveState for Dyld {
+    fn save(&self, w: &mut Writer) {
+        let Dyld {
+            linked_host_functions,
+            return_to_host_routine,
+            thread_exit_routine,
+            constants_to_link_later,
+            non_lazy_host_functions,
+            stubbed_functions,
+        } = self;
+        // This is only used during startup.
+        assert!(constants_to_link_later.is_empty());
+        // The stubs have to be recreated before the other lists can be
+        // restored, so they go first.
+        stubbed_functions.len().save(w);
+        for &symbol in stubbed_functions.keys() {
+            save_str(w, symbol);
+        }
+        linked_host_functions.len().save(w);
+        for &(symbol, _) in linked_host_functions {
+            save_str(w, symbol);
+        }
+        return_to_host_routine.save(w);
+        thread_exit_routine.save(w);
+        non_lazy_host_functions.len().save(w);
+        for (&symbol, function_ptr) in non_lazy_host_functions {
+            save_str(w, symbol);
+            function_ptr.save(w);
+        }
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        let stubbed_functions: HashMap<&'static str, HostFunction> =
+            restore_iter::<String, Vec<_>>(r)?
+                .iter()
+                .map(|symbol| UnimplementedFunctionStub::get(symbol))
+                .collect();
+
+        let find = |symbol: &str| -> Result<(&'static str, HostFunction), String> {
+            search_lists(function_lists::FUNCTION_LISTS, symbol)
+                .or_else(|| search_lists(function_lists::PRIVATE_FUNCTION_LISTS, symbol))
+                .copied()
+                .or_else(|| {
+                    stubbed_functions
+                        .get_key_value(symbol)
+                        .map(|(&symbol, &f)| (symbol, f))
+                })
+                .ok_or_else(|| format!("Unknown host function {:?} in save state", symbol))
+        };
+
+        let linked_host_functions = restore_iter::<String, Vec<_>>(r)?
+            .iter()
+            .map(|symbol| find(symbol))
+            .collect::<Result<_, _>>()?;
+        let return_to_host_routine = SaveState::restore(r)?;
+        let thread_exit_routine = SaveState::restore(r)?;
+        let non_lazy_host_functions = restore_iter::<(String, GuestFunction), Vec<_>>(r)?
+            .into_iter()
+            .map(|(symbol, function_ptr)| Ok((find(&symbol)?.0, function_ptr)))
+            .collect::<Result<_, String>>()?;
+        Ok(Dyld {
+            linked_host_functions,
+            return_to_host_routine,
+            thread_exit_routine,
+            constants_to_link_later: Vec::new(),
+            non_lazy_host_functions,
+            stubbed_functions,
+        })
+    }
+}
@@ -0,0 +1,172 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Tracing of calls from the app to host functions (`--trace-host-calls=`).
+//!
+//! Each call is written to the trace file as a line with the symbol, the
+//! arguments and the return value, e.g.:
+//!
+//! ```text
+//! _fopen(0x0012a3c0, 0x0012a3e0) => 0x00200010
+//! ```
+//!
+//! If the host function calls back into the app, and that causes more host
+//! function calls, these are written on indented lines between the call and
+//! its return value. Arguments and return values are shown using the Rust types
+//! of the host function's implementation.
+//!
+//! When the tracer is dropped, which should happen when touchHLE exits, the
+//! number of calls to each function is appended to the file, most-called
+//! first.
+
+use super::HostFunction;
+use crate::abi::CallTracer;
+use crate::environment::ThreadId;
+use crate::options::Options;
+use crate::Environment;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+
+/// Per-thread state of the tracer.
+#[derive(Default)]
+struct ThreadState {
+    /// Number of calls in progress.
+    depth: usize,
+    /// The line for the innermost call in progress, if it hasn't been written
+    /// yet. It's kept back so the return value can go on the same line, unless
+    /// another line has to be written first.
+    pending: Option<String>,
+}
+
+/// State for writing the host call trace.
+pub struct HostCallTracer {
+    /// Lines are written straight away, so that the trace is complete even if
+    /// touchHLE crashes.
+    file: LineWriter<File>,
+    threads: HashMap<ThreadId, ThreadState>,
+    call_counts: HashMap<&'static str, u64>,
+}
+
+impl HostCallTracer {
+    /// Set up tracing if the options request it.
+    pub fn new_from_options(options: &Options) -> Result<Option<Self>, String> {
+        let Some(ref path) = options.trace_host_calls_path else {
+            return Ok(None);
+        };
+        let file = File::create(path).map_err(|e| {
+            format!(
+                "Could not create host call trace file {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(Some(HostCallTracer {
+            file: LineWriter::new(file),
+            threads: HashMap::new(),
+            call_counts: HashMap::new(),
+        }))
+    }
+
+    fn write_line(&mut self, thread: ThreadId, line: &str, depth: usize) {
+        let thread_prefix = if thread == 0 {
+            String::new()
+        } else {
+            format!("[thread {}] ", thread)
+        };
+        // A failure to write the trace shouldn't stop the app.
+        let _ = writeln!(
+            self.file,
+            "{}{:indent$}{}",
+            thread_prefix,
+            "",
+            line,
+            indent = depth * 2
+        );
+    }
+
+    fn flush_pending(&mut self, thread: ThreadId) {
+        let state = self.threads.entry(thread).or_default();
+        if let Some(pending) = state.pending.take() {
+            let depth = state.depth - 1;
+            self.write_line(thread, &pending, depth);
+        }
+    }
+
+    fn begin(&mut self, thread: ThreadId, line: String) {
+        self.flush_pending(thread);
+        let state = self.threads.entry(thread).or_default();
+        state.pending = Some(line);
+        state.depth += 1;
+    }
+
+    fn end(&mut self, thread: ThreadId, retval: Option<&str>) {
+        let state = self.threads.entry(thread).or_default();
+        state.depth = state.depth.saturating_sub(1);
+        let depth = state.depth;
+        let line = match (state.pending.take(), retval) {
+            (Some(pending), Some(retval)) => format!("{} => {}", pending, retval),
+            (Some(pending), None) => pending,
+            (None, Some(retval)) => format!("=> {}", retval),
+            (None, None) => return,
+        };
+        self.write_line(thread, &line, depth);
+    }
+}
+
+impl Drop for HostCallTracer {
+    fn drop(&mut self) {
+        let threads: Vec<ThreadId> = self.threads.keys().copied().collect();
+        for thread in threads {
+            self.flush_pending(thread);
+        }
+
+        let mut call_counts: Vec<(&str, u64)> =
+            self.call_counts.iter().map(|(&k, &v)| (k, v)).collect();
+        call_counts.sort_by(|&(name_a, count_a), &(name_b, count_b)| {
+            count_b.cmp(&count_a).then(name_a.cmp(name_b))
+        });
+        let _ = writeln!(self.file, "\nCall counts:");
+        for (symbol, count) in call_counts {
+            let _ = writeln!(self.file, "{:>10} {}", count, symbol);
+        }
+    }
+}
+
+/// [CallTracer] for a single host function call.
+struct HostCall {
+    thread: ThreadId,
+    symbol: &'static str,
+}
+impl CallTracer for HostCall {
+    fn trace_args(&mut self, env: &mut Environment, args: &[&dyn Debug]) {
+        let args: Vec<String> = args.iter().map(|arg| format!("{:?}", arg)).collect();
+        let line = format!("{}({})", self.symbol, args.join(", "));
+        env.host_call_tracer
+            .as_mut()
+            .unwrap()
+            .begin(self.thread, line);
+    }
+    fn trace_return(&mut self, env: &mut Environment, retval: &dyn Debug) {
+        let retval = format!("{:?}", retval);
+        let retval = (retval != "()").then_some(retval.as_str());
+        env.host_call_tracer
+            .as_mut()
+            .unwrap()
+            .end(self.thread, retval);
+    }
+}
+
+/// Call a host function from the guest, tracing the call.
+pub fn call_traced(env: &mut Environment, symbol: &'static str, f: HostFunction) {
+    let tracer = env.host_call_tracer.as_mut().unwrap();
+    *tracer.call_counts.entry(symbol).or_insert(0) += 1;
+    let mut call = HostCall {
+        thread: env.current_thread,
+        symbol,
+    };
+    f.call_from_guest_traced(env, Some(&mut call));
+}
@@ -7,7 +7,8 @@
 //! very long and frequently-updated list.
 
 use crate::frameworks::{
-    audio_toolbox, core_foundation, core_graphics, dnssd, foundation, openal, opengles, uikit,
+    audio_toolbox, av_audio, core_foundation, core_graphics, dnssd, foundation, openal, opengles,
+    uikit,
 };
 use crate::libc;
 
@@ -48,9 +49,11 @@ pub const FUNCTION_LISTS: &[super::FunctionExports] = &[
     libc::wchar::FUNCTIONS,
     crate::objc::FUNCTIONS,
     audio_toolbox::audio_file::FUNCTIONS,
+    audio_toolbox::audio_file_stream::FUNCTIONS,
     audio_toolbox::audio_queue::FUNCTIONS,
     audio_toolbox::audio_services::FUNCTIONS,
     audio_toolbox::audio_session::FUNCTIONS,
+    audio_toolbox::ext_audio_file::FUNCTIONS,
     core_foundation::cf_array::FUNCTIONS,
     core_foundation::cf_bundle::FUNCTIONS,
     core_foundation::cf_data::FUNCTIONS,
@@ -77,3 +80,11 @@ pub const FUNCTION_LISTS: &[super::FunctionExports] = &[
     uikit::ui_geometry::FUNCTIONS,
     uikit::ui_graphics::FUNCTIONS,
 ];
+
+/// Lists of functions that aren't linked to the app, but which host code makes
+/// guest functions for with [super::Dyld::create_guest_function]. These are
+/// only searched when loading a save state.
+pub const PRIVATE_FUNCTION_LISTS: &[super::FunctionExports] = &[
+    av_audio::av_audio_player::PRIVATE_FUNCTIONS,
+    foundation::ns_thread::PRIVATE_FUNCTIONS,
+];
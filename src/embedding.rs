@@ -0,0 +1,356 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Public API for running touchHLE as a library, e.g. in a custom frontend or
+//! an automated test harness.
+//!
+//! An [Emulator] runs an app on its own thread, which stops each time the app
+//! presents a frame. [Emulator::step_frame] lets it continue until the next
+//! frame and returns that frame's pixels. Input events can be queued in
+//! between with [Emulator::push_event]. Audio output and log output can be
+//! redirected with an [AudioSink] and [set_log_hook] respectively.
+//!
+//! For example:
+//!
+//! ```ignore
+//! use touchHLE::embedding::{Emulator, InputEvent, Options};
+//!
+//! let mut options = Options::default();
+//! options.parse_argument("--offscreen").unwrap();
+//! let mut emulator = Emulator::new("Some.app".into(), options, None);
+//! for _ in 0..60 {
+//!     let frame = emulator.step_frame()?;
+//!     // ...
+//! }
+//! emulator.push_event(InputEvent::TouchDown { finger: 0, x: 160.0, y: 240.0 });
+//! let frame = emulator.step_frame()?;
+//! ```
+//!
+//! touchHLE still creates a window (use `--offscreen` to hide it), because it
+//! is needed for OpenGL ES. Note that on some platforms, notably macOS, SDL2
+//! only supports windows on the main thread, which the emulator thread is not.
+//!
+//! Only one [Emulator] can run at a time in a process, because SDL2 and the
+//! log hook are process-wide. Creating another one while the previous one's
+//! app is still running gives an [Emulator] that has already stopped with
+//! [Stopped::Error].
+//!
+//! [Emulator::step_frame] waits as long as it takes for the app to present a
+//! frame, which might be forever if the app is stuck. Use
+//! [Emulator::step_frame_timeout] if that's a problem.
+
+pub use crate::log::{set_hook as set_log_hook, Hook as LogHook};
+pub use crate::options::Options;
+
+use crate::audio::output::{self, CAPTURE_CHANNELS, CAPTURE_SAMPLE_RATE};
+use crate::environment::AppExit;
+use crate::Environment;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Sample rate of the audio passed to an [AudioSink], in Hz.
+pub const AUDIO_SAMPLE_RATE: u32 = CAPTURE_SAMPLE_RATE;
+/// Number of channels of the audio passed to an [AudioSink] (stereo).
+pub const AUDIO_CHANNELS: u32 = CAPTURE_CHANNELS;
+
+/// Receives the app's audio output as interleaved 16-bit samples, with
+/// [AUDIO_CHANNELS] channels at [AUDIO_SAMPLE_RATE]. When this is used,
+/// touchHLE doesn't play any audio itself.
+pub type AudioSink = Box<dyn FnMut(&[i16]) + Send>;
+
+/// Input that can be sent to the app with [Emulator::push_event].
+///
+/// Coordinates are in the app's screen coordinate space, i.e. points, with the
+/// origin at the top-left of the screen in portrait orientation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    TouchDown {
+        finger: u32,
+        x: f32,
+        y: f32,
+    },
+    TouchMove {
+        finger: u32,
+        x: f32,
+        y: f32,
+    },
+    TouchUp {
+        finger: u32,
+        x: f32,
+        y: f32,
+    },
+    /// Set the accelerometer reading, in units of g-force (see
+    /// [crate::frameworks::uikit::ui_accelerometer]). This stays in effect
+    /// until the next such event.
+    Acceleration {
+        x: f32,
+        y: f32,
+        z: f32,
+    },
+    /// Ask the app to quit, like closing the window would.
+    Quit,
+}
+
+/// A frame presented by the app.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA pixels, in top-to-bottom row order, as they were drawn to the
+    /// window (i.e. rotated and scaled if necessary).
+    pub pixels: Vec<u8>,
+}
+
+/// Why an [Emulator] stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stopped {
+    /// The app exited, with this exit code.
+    Exited(i32),
+    /// The app couldn't be started, e.g. because the bundle is invalid.
+    Error(String),
+    /// touchHLE panicked, with this message.
+    Panicked(String),
+}
+
+/// Whether an emulator thread is running. See the [module-level docs][self].
+static EMULATOR_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// An app running on its own thread. See the [module-level docs][self].
+pub struct Emulator {
+    thread: Option<JoinHandle<Stopped>>,
+    to_emulator: mpsc::Sender<Vec<InputEvent>>,
+    frames: mpsc::Receiver<Frame>,
+    pending_events: Vec<InputEvent>,
+    started: bool,
+    /// Set if the app has been told to continue, but hasn't presented a frame
+    /// yet (because [Self::step_frame_timeout] timed out).
+    waiting_for_frame: bool,
+    stopped: Option<Stopped>,
+}
+
+impl Emulator {
+    /// Start the app at `bundle_path` (an `.app` directory or `.ipa` file).
+    /// Unlike the touchHLE command-line interface, this does not apply options
+    /// from options files.
+    ///
+    /// The app starts running straight away, until it presents its first
+    /// frame, which is returned by the first call to [Self::step_frame].
+    pub fn new(bundle_path: PathBuf, options: Options, audio_sink: Option<AudioSink>) -> Emulator {
+        let (to_emulator, from_frontend) = mpsc::channel();
+        let (to_frontend, frames) = mpsc::channel();
+        if EMULATOR_RUNNING.swap(true, Ordering::SeqCst) {
+            return Emulator {
+                thread: None,
+                to_emulator,
+                frames,
+                pending_events: Vec::new(),
+                started: true,
+                waiting_for_frame: false,
+                stopped: Some(Stopped::Error(
+                    "Another emulator is already running in this process".to_string(),
+                )),
+            };
+        }
+        let link = EmbedderLink {
+            to_frontend,
+            from_frontend,
+            acceleration: None,
+        };
+        let thread = std::thread::Builder::new()
+            .name("touchHLE emulator".to_string())
+            .spawn(move || {
+                let stopped = run_emulator(bundle_path, options, audio_sink, link);
+                EMULATOR_RUNNING.store(false, Ordering::SeqCst);
+                stopped
+            })
+            .unwrap();
+        Emulator {
+            thread: Some(thread),
+            to_emulator,
+            frames,
+            pending_events: Vec::new(),
+            started: false,
+            waiting_for_frame: false,
+            stopped: None,
+        }
+    }
+
+    /// Queue an input event. Events are delivered to the app when
+    /// [Self::step_frame] is next called.
+    pub fn push_event(&mut self, event: InputEvent) {
+        self.pending_events.push(event);
+    }
+
+    /// Let the app run until it presents its next frame, and return that
+    /// frame. If the app stops instead, the reason is returned, and so is it
+    /// for any further calls.
+    ///
+    /// This blocks until the app presents a frame or stops, however long that
+    /// takes. See also [Self::step_frame_timeout].
+    pub fn step_frame(&mut self) -> Result<Frame, Stopped> {
+        self.step_frame_inner(None)
+            .map(|frame| frame.expect("recv() can't time out"))
+    }
+
+    /// Like [Self::step_frame], but gives up waiting after `timeout` and
+    /// returns `Ok(None)`. The app keeps running in that case, and the next
+    /// call to this or [Self::step_frame] continues waiting for the same
+    /// frame. Events pushed in the meantime are delivered after that frame.
+    pub fn step_frame_timeout(&mut self, timeout: Duration) -> Result<Option<Frame>, Stopped> {
+        self.step_frame_inner(Some(timeout))
+    }
+
+    fn step_frame_inner(&mut self, timeout: Option<Duration>) -> Result<Option<Frame>, Stopped> {
+        if let Some(ref stopped) = self.stopped {
+            return Err(stopped.clone());
+        }
+        if !self.waiting_for_frame {
+            let events = std::mem::take(&mut self.pending_events);
+            if self.started {
+                // If this fails, the thread has stopped, which is noticed
+                // below.
+                let _ = self.to_emulator.send(events);
+            } else {
+                // The app is already running towards its first frame.
+                self.started = true;
+                self.pending_events = events;
+            }
+            self.waiting_for_frame = true;
+        }
+        let result = match timeout {
+            Some(timeout) => self.frames.recv_timeout(timeout),
+            None => self
+                .frames
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        match result {
+            Ok(frame) => {
+                self.waiting_for_frame = false;
+                Ok(Some(frame))
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let stopped = self.thread.take().unwrap().join().unwrap();
+                self.stopped = Some(stopped.clone());
+                Err(stopped)
+            }
+        }
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        // The app is asked to quit, but the thread isn't waited for, in case
+        // the app doesn't cooperate.
+        if self.stopped.is_none() {
+            let _ = self.to_emulator.send(vec![InputEvent::Quit]);
+        }
+    }
+}
+
+/// The emulator thread's end of the connection to an [Emulator]. This belongs
+/// to the [crate::window::Window].
+pub(crate) struct EmbedderLink {
+    to_frontend: mpsc::Sender<Frame>,
+    from_frontend: mpsc::Receiver<Vec<InputEvent>>,
+    acceleration: Option<(f32, f32, f32)>,
+}
+
+impl EmbedderLink {
+    /// Hand a frame to the frontend and wait until it asks for the next one.
+    /// Returns the input events that should be delivered to the app.
+    pub(crate) fn present_frame(&mut self, frame: Frame) -> Vec<InputEvent> {
+        let events = if self.to_frontend.send(frame).is_ok() {
+            self.from_frontend.recv().ok()
+        } else {
+            None
+        };
+        // If the frontend has gone away, the app should quit.
+        let events = events.unwrap_or_else(|| vec![InputEvent::Quit]);
+        for event in &events {
+            if let &InputEvent::Acceleration { x, y, z } = event {
+                self.acceleration = Some((x, y, z));
+            }
+        }
+        events
+    }
+
+    /// The acceleration set by the frontend, if any.
+    pub(crate) fn acceleration(&self) -> Option<(f32, f32, f32)> {
+        self.acceleration
+    }
+}
+
+fn run_emulator(
+    bundle_path: PathBuf,
+    options: Options,
+    audio_sink: Option<AudioSink>,
+    link: EmbedderLink,
+) -> Stopped {
+    if options.headless {
+        return Stopped::Error("Headless mode can't be used when embedded.".to_string());
+    }
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let (bundle, fs) = crate::open_bundle(&bundle_path)?;
+        if let Some(audio_sink) = audio_sink {
+            output::set_capture_sink(Some(audio_sink));
+        }
+        let mut env = Environment::new(bundle, fs, options, None)?;
+        env.window_mut().set_embedder(link);
+        env.run();
+        Ok::<(), String>(())
+    }));
+    output::set_capture_sink(None);
+    match result {
+        // Environment::run() doesn't return normally.
+        Ok(Ok(())) => Stopped::Exited(0),
+        Ok(Err(e)) => Stopped::Error(e),
+        Err(payload) => {
+            if let Some(&AppExit(exit_code)) = payload.downcast_ref() {
+                Stopped::Exited(exit_code)
+            } else if let Some(message) = payload.downcast_ref::<&str>() {
+                Stopped::Panicked(message.to_string())
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                Stopped::Panicked(message.clone())
+            } else {
+                Stopped::Panicked("(non-string payload)".to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopped_emulator() {
+        // The app can't be started, and that's reported by every call.
+        let mut emulator = Emulator::new("nonexistent.app".into(), Options::default(), None);
+        let Err(Stopped::Error(error)) = emulator.step_frame() else {
+            panic!();
+        };
+        assert!(matches!(
+            emulator.step_frame_timeout(Duration::ZERO),
+            Err(Stopped::Error(e)) if e == error
+        ));
+        // The emulator thread has finished, so another one can be created.
+        assert!(!EMULATOR_RUNNING.load(Ordering::SeqCst));
+        drop(emulator);
+
+        // Pretend one is running.
+        EMULATOR_RUNNING.store(true, Ordering::SeqCst);
+        let mut emulator = Emulator::new("nonexistent.app".into(), Options::default(), None);
+        assert!(matches!(
+            emulator.step_frame(),
+            Err(Stopped::Error(e)) if e == "Another emulator is already running in this process"
+        ));
+        EMULATOR_RUNNING.store(false, Ordering::SeqCst);
+    }
+}
@@ -8,14 +8,16 @@
 //! Unlike its siblings, this module should be considered private and only used
 //! via the re-exports one level up.
 
+mod heap_diagnostics;
 mod mutex;
+mod save_state;
 
 use crate::abi::GuestRet;
 use crate::libc::semaphore::sem_t;
 use crate::mem::{MutPtr, MutVoidPtr};
 use crate::{
-    abi, bundle, cpu, dyld, frameworks, fs, gdb, image, libc, mach_o, mem, objc, options, stack,
-    window,
+    abi, audio, breakpoints, bundle, clock, cpu, debug_console, dyld, frameworks, fs, gdb, image,
+    input_recording, libc, mach_o, mem, objc, options, stack, window,
 };
 use std::net::TcpListener;
 use std::time::{Duration, Instant};
@@ -70,13 +72,38 @@ impl Thread {
     fn is_blocked(&self) -> bool {
         !matches!(self.blocked_by, ThreadBlock::NotBlocked)
     }
+
+    /// The thread's saved CPU state. This is absent for the current thread,
+    /// whose state is in the CPU.
+    pub fn context_mut(&mut self) -> Option<&mut cpu::CpuContext> {
+        self.context.as_mut()
+    }
+
+    /// Short description of what the thread is doing, for debugging purposes.
+    pub fn describe_state(&self) -> String {
+        if !self.active {
+            return "finished".to_string();
+        }
+        match self.blocked_by {
+            ThreadBlock::NotBlocked if self.in_host_function => {
+                "waiting for a host function".to_string()
+            }
+            ThreadBlock::NotBlocked => "runnable".to_string(),
+            ThreadBlock::Sleeping(_) => "sleeping".to_string(),
+            ThreadBlock::Mutex(mutex_id) => format!("waiting for mutex {}", mutex_id),
+            ThreadBlock::Semaphore(sem) => format!("waiting on semaphore {:?}", sem),
+            ThreadBlock::Joining(thread, _) => format!("joining thread {}", thread),
+            ThreadBlock::DeferredReturn => "waiting for a deferred return".to_string(),
+        }
+    }
 }
 
 /// The struct containing the entire emulator state. Methods are provided for
 /// execution and management of threads.
 pub struct Environment {
-    /// Reference point for various timing functions.
-    pub startup_time: Instant,
+    /// Source of all time observed by the app. Use this instead of
+    /// [Instant::now] or [std::time::SystemTime::now].
+    pub clock: clock::Clock,
     pub bundle: bundle::Bundle,
     pub fs: fs::Fs,
     /// The window is only absent when running in headless mode.
@@ -94,9 +121,25 @@ pub struct Environment {
     pub framework_state: frameworks::State,
     pub mutex_state: mutex::MutexState,
     pub options: options::Options,
+    /// Present when recording or replaying input.
+    pub input_recording: Option<input_recording::InputRecording>,
+    /// Present when tracing Objective-C messages.
+    pub message_tracer: Option<objc::message_trace::MessageTracer>,
+    /// Present when tracing host function calls.
+    pub host_call_tracer: Option<dyld::call_trace::HostCallTracer>,
+    /// Breakpoints on Objective-C methods and host functions, see
+    /// [breakpoints].
+    pub breakpoints: breakpoints::Breakpoints,
     gdb_server: Option<gdb::GdbServer>,
+    debug_console: Option<debug_console::DebugConsole>,
+    heap_diagnostics: Option<heap_diagnostics::HeapDiagnostics>,
 }
 
+/// Panic payload used by [Environment::exit] to unwind out of
+/// [Environment::run] when touchHLE is embedded (see [crate::embedding]). The
+/// value is the app's exit code.
+pub struct AppExit(pub i32);
+
 /// What to do next when executing this thread.
 enum ThreadNextAction {
     /// Continue CPU emulation.
@@ -125,6 +168,55 @@ enum ThreadBlock {
     // Deferred guest-to-host return
     DeferredReturn,
 }
+crate::impl_SaveState_for_enum!(ThreadBlock {
+    NotBlocked,
+    Sleeping(until),
+    Mutex(mutex_id),
+    Semaphore(sem),
+    Joining(thread_id, ptr),
+    DeferredReturn,
+});
+
+/// Load the app's executable and the dylibs it depends on that are bundled
+/// with touchHLE. The executable is always the first item in the result.
+pub fn load_binaries(
+    bundle: &bundle::Bundle,
+    fs: &fs::Fs,
+    mem: &mut mem::Mem,
+) -> Result<Vec<mach_o::MachO>, String> {
+    let executable = mach_o::MachO::load_from_file(bundle.executable_path(), fs, mem)
+        .map_err(|e| format!("Could not load executable: {}", e))?;
+
+    let mut dylibs = Vec::new();
+    for dylib in &executable.dynamic_libraries {
+        if dylib == "/usr/lib/libSystem.B.dylib" || dylib == "/usr/lib/libobjc.A.dylib" {
+            // We have host implementations of these
+            continue;
+        }
+
+        // There are some Free Software libraries bundled with touchHLE and
+        // exposed via the guest file system (see Fs::new()).
+        if fs.is_file(fs::GuestPath::new(dylib)) {
+            let dylib = mach_o::MachO::load_from_file(fs::GuestPath::new(dylib), fs, mem)
+                .map_err(|e| format!("Could not load bundled dylib: {}", e))?;
+            dylibs.push(dylib);
+        } else {
+            // System frameworks will have host implementations.
+            // TODO: warn about unimplemented frameworks?
+            if !dylib.starts_with("/System/Library/Frameworks/") {
+                log!(
+                    "Warning: app binary depends on unexpected dylib \"{}\"",
+                    dylib
+                );
+            }
+            continue;
+        };
+    }
+
+    let mut bins = dylibs;
+    bins.insert(0, executable);
+    Ok(bins)
+}
 
 impl Environment {
     /// Loads the binary and sets up the emulator.
@@ -141,7 +233,12 @@ impl Environment {
         options: options::Options,
         env_for_salvage: Option<Environment>,
     ) -> Result<Environment, String> {
-        let startup_time = Instant::now();
+        let clock = clock::Clock::new(options.virtual_clock);
+
+        let input_recording = input_recording::InputRecording::new_from_options(&options)?;
+        let message_tracer = objc::message_trace::MessageTracer::new_from_options(&options)?;
+        let host_call_tracer = dyld::call_trace::HostCallTracer::new_from_options(&options)?;
+        let heap_diagnostics = heap_diagnostics::HeapDiagnostics::new_from_options(&options);
 
         // Extract things to salvage from the old environment, and then drop it.
         // This needs to be done before creating a new window, because SDL2 only
@@ -192,36 +289,13 @@ impl Environment {
         } else {
             mem::Mem::new()
         };
-
-        let executable = mach_o::MachO::load_from_file(bundle.executable_path(), &fs, &mut mem)
-            .map_err(|e| format!("Could not load executable: {}", e))?;
-
-        let mut dylibs = Vec::new();
-        for dylib in &executable.dynamic_libraries {
-            if dylib == "/usr/lib/libSystem.B.dylib" || dylib == "/usr/lib/libobjc.A.dylib" {
-                // We have host implementations of these
-                continue;
-            }
-
-            // There are some Free Software libraries bundled with touchHLE and
-            // exposed via the guest file system (see Fs::new()).
-            if fs.is_file(fs::GuestPath::new(dylib)) {
-                let dylib = mach_o::MachO::load_from_file(fs::GuestPath::new(dylib), &fs, &mut mem)
-                    .map_err(|e| format!("Could not load bundled dylib: {}", e))?;
-                dylibs.push(dylib);
-            } else {
-                // System frameworks will have host implementations.
-                // TODO: warn about unimplemented frameworks?
-                if !dylib.starts_with("/System/Library/Frameworks/") {
-                    log!(
-                        "Warning: app binary depends on unexpected dylib \"{}\"",
-                        dylib
-                    );
-                }
-                continue;
-            };
+        if heap_diagnostics.is_some() {
+            mem.enable_heap_diagnostics();
         }
 
+        let bins = load_binaries(&bundle, &fs, &mut mem)?;
+        let executable = &bins[0];
+
         let entry_point_addr = executable.entry_point_pc.ok_or_else(|| {
             "Mach-O file does not specify an entry point PC, perhaps it is not an executable?"
                 .to_string()
@@ -230,9 +304,6 @@ impl Environment {
 
         log_dbg!("Address of start function: {:?}", entry_point_addr);
 
-        let mut bins = dylibs;
-        bins.insert(0, executable);
-
         let mut objc = objc::ObjC::new();
 
         let mut dyld = dyld::Dyld::new();
@@ -254,7 +325,7 @@ impl Environment {
         };
 
         let mut env = Environment {
-            startup_time,
+            clock,
             bundle,
             fs,
             window,
@@ -269,7 +340,13 @@ impl Environment {
             mutex_state: Default::default(),
             framework_state: Default::default(),
             options,
+            input_recording,
+            message_tracer,
+            host_call_tracer,
+            breakpoints: Default::default(),
             gdb_server: None,
+            debug_console: None,
+            heap_diagnostics,
         };
 
         dyld::Dyld::do_late_linking(&mut env);
@@ -286,6 +363,23 @@ impl Environment {
 
         env.cpu.set_cpsr(cpu::Cpu::CPSR_USER_MODE);
 
+        if let Some(ref path) = env.options.gdb_symbols_path {
+            gdb::symbol_file::write(path, &env.bins, &env.objc, &env.mem)?;
+        }
+
+        if env.options.debug_console && env.options.gdb_listen_addrs.is_some() {
+            return Err("--gdb= and --debug-console can't be used together".to_string());
+        }
+
+        if !env.options.break_on.is_empty() {
+            if !env.options.debug_console && env.options.gdb_listen_addrs.is_none() {
+                return Err("--break-on= needs --gdb= or --debug-console".to_string());
+            }
+            for breakpoint in std::mem::take(&mut env.options.break_on) {
+                env.breakpoints.add(breakpoint);
+            }
+        }
+
         if let Some(addrs) = env.options.gdb_listen_addrs.take() {
             let listener = TcpListener::bind(addrs.as_slice())
                 .map_err(|e| format!("Could not bind to {:?}: {}", addrs, e))?;
@@ -301,12 +395,27 @@ impl Environment {
                 .accept()
                 .map_err(|e| format!("Could not accept connection: {}", e))?;
             echo!("Debugger client connected on {}.", client_addr);
-            let mut gdb_server = gdb::GdbServer::new(client);
-            let step = gdb_server.wait_for_debugger(None, &mut env.cpu, &mut env.mem);
+            let mut gdb_server = gdb::GdbServer::new(client, &env.bins);
+            let step = gdb_server.wait_for_debugger(
+                None,
+                &mut env.cpu,
+                &mut env.mem,
+                &mut env.threads,
+                env.current_thread,
+            );
             assert!(!step, "Can't step right now!"); // TODO?
             env.gdb_server = Some(gdb_server);
         }
 
+        if env.options.debug_console {
+            let mut console = match env.options.debug_console_listen_addrs.take() {
+                Some(addrs) => debug_console::DebugConsole::listen(&addrs)?,
+                None => debug_console::DebugConsole::new_stdio(),
+            };
+            console.wait_for_debugger(&mut env, "The app is about to start.", false);
+            env.debug_console = Some(console);
+        }
+
         echo!("CPU emulation begins now.");
 
         // Static initializers for libraries must be run before the initializer
@@ -349,7 +458,7 @@ impl Environment {
         let bundle = bundle::Bundle::new_fake_bundle();
         let fs = fs::Fs::new_fake_fs();
 
-        let startup_time = Instant::now();
+        let clock = clock::Clock::new(options.virtual_clock);
 
         let icon = None;
         let launch_image = None;
@@ -387,7 +496,7 @@ impl Environment {
         };
 
         let mut env = Environment {
-            startup_time,
+            clock,
             bundle,
             fs,
             window,
@@ -402,7 +511,13 @@ impl Environment {
             mutex_state: Default::default(),
             framework_state: Default::default(),
             options,
+            input_recording: None,
+            message_tracer: None,
+            host_call_tracer: None,
+            breakpoints: Default::default(),
             gdb_server: None,
+            debug_console: None,
+            heap_diagnostics: None,
         };
 
         // Dyld::do_late_linking() would be called here, but it doesn't do
@@ -442,47 +557,62 @@ impl Environment {
     }
 
     fn stack_trace(&self) {
+        let mut trace = String::new();
+        self.write_stack_trace(&mut trace);
+        for line in trace.lines() {
+            echo!("{}", line);
+        }
+    }
+
+    /// Write a stack trace for the current thread, as printed when entering
+    /// the debugger.
+    pub fn write_stack_trace(&self, out: &mut String) {
+        use std::fmt::Write;
         if self.current_thread == 0 {
-            echo!("Attempting to produce stack trace for main thread:");
+            writeln!(out, "Attempting to produce stack trace for main thread:").unwrap();
         } else {
-            echo!(
+            writeln!(
+                out,
                 "Attempting to produce stack trace for thread {}:",
                 self.current_thread
-            );
+            )
+            .unwrap();
         }
         let stack_range = self.threads[self.current_thread].stack.clone().unwrap();
-        echo!(
+        writeln!(
+            out,
             " 0. {:#x} (PC)",
             self.cpu.pc_with_thumb_bit().addr_with_thumb_bit()
-        );
+        )
+        .unwrap();
         let regs = self.cpu.regs();
         let mut lr = regs[cpu::Cpu::LR];
         let return_to_host_routine_addr = self.dyld.return_to_host_routine().addr_with_thumb_bit();
         let thread_exit_routine_addr = self.dyld.thread_exit_routine().addr_with_thumb_bit();
         if lr == return_to_host_routine_addr {
-            echo!(" 1. [host function] (LR)");
+            writeln!(out, " 1. [host function] (LR)").unwrap();
         } else if lr == thread_exit_routine_addr {
-            echo!(" 1. [thread exit] (LR)");
+            writeln!(out, " 1. [thread exit] (LR)").unwrap();
             return;
         } else {
-            echo!(" 1. {:#x} (LR)", lr);
+            writeln!(out, " 1. {:#x} (LR)", lr).unwrap();
         }
         let mut i = 2;
         let mut fp: mem::ConstPtr<u8> = mem::Ptr::from_bits(regs[abi::FRAME_POINTER]);
         loop {
             if !stack_range.contains(&fp.to_bits()) {
-                echo!("Next FP ({:?}) is outside the stack.", fp);
+                writeln!(out, "Next FP ({:?}) is outside the stack.", fp).unwrap();
                 break;
             }
             lr = self.mem.read((fp + 4).cast());
             fp = self.mem.read(fp.cast());
             if lr == return_to_host_routine_addr {
-                echo!("{:2}. [host function]", i);
+                writeln!(out, "{:2}. [host function]", i).unwrap();
             } else if lr == thread_exit_routine_addr {
-                echo!("{:2}. [thread exit]", i);
+                writeln!(out, "{:2}. [thread exit]", i).unwrap();
                 return;
             } else {
-                echo!("{:2}. {:#x}", i, lr);
+                writeln!(out, "{:2}. {:#x}", i, lr).unwrap();
             }
             i += 1;
         }
@@ -545,7 +675,7 @@ impl Environment {
             self.current_thread,
             duration
         );
-        let until = Instant::now().checked_add(duration).unwrap();
+        let until = self.clock.now().checked_add(duration).unwrap();
         self.threads[self.current_thread].blocked_by = ThreadBlock::Sleeping(until);
         // For non tail-call sleeps (such as in NSRunLoop), we want to poll
         // other threads but can't return back to the run loop, since it would
@@ -678,6 +808,9 @@ impl Environment {
         // the emulator will crash anyway, maybe this is okay.
         let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.run_inner(true)));
         if let Err(e) = res {
+            if e.is::<AppExit>() {
+                std::panic::resume_unwind(e);
+            }
             echo!("Register state immediately after panic:");
             self.cpu.dump_regs();
             self.stack_trace();
@@ -685,6 +818,24 @@ impl Environment {
         }
     }
 
+    /// Exit the app. Normally this exits the process, but when touchHLE is
+    /// embedded, it instead unwinds out of [Self::run] with an [AppExit].
+    pub fn exit(&mut self, exit_code: i32) -> ! {
+        // Dropping the tracer writes the call counts.
+        self.host_call_tracer = None;
+        self.heap_report_at_exit();
+        if self
+            .window
+            .as_ref()
+            .is_some_and(|window| window.is_embedded())
+        {
+            std::panic::resume_unwind(Box::new(AppExit(exit_code)));
+        }
+        // Dropping the capture sink finishes the --capture-audio= file.
+        audio::output::set_capture_sink(None);
+        std::process::exit(exit_code);
+    }
+
     /// Run the emulator until the app returns control to the host. This is for
     /// host-to-guest function calls (see [abi::GuestFunction::call]).
     ///
@@ -735,7 +886,7 @@ impl Environment {
             self.cpu.regs_mut()[cpu::Cpu::PC] -= instruction_len;
         }
 
-        if self.gdb_server.is_none() {
+        if !self.is_debugging_enabled() {
             panic!("Error during CPU execution: {:?}", error);
         }
 
@@ -746,7 +897,7 @@ impl Environment {
     /// Used to check whether a debugger is connected, and therefore whether
     /// [Environment::enter_debugger] will do something.
     pub fn is_debugging_enabled(&self) -> bool {
-        self.gdb_server.is_some()
+        self.gdb_server.is_some() || self.debug_console.is_some()
     }
 
     /// Suspend execution and hand control to the connected debugger.
@@ -758,10 +909,42 @@ impl Environment {
         // GDB doesn't seem to manage to produce a useful stack trace, so
         // let's print our own.
         self.stack_trace();
-        self.gdb_server
-            .as_mut()
-            .unwrap()
-            .wait_for_debugger(reason, &mut self.cpu, &mut self.mem)
+        self.wait_for_debugger(reason)
+    }
+
+    /// Like [Environment::enter_debugger], but for use within a host function
+    /// (e.g. for a breakpoint on an Objective-C method), where stepping isn't
+    /// possible. `message` explains why the debugger is being invoked.
+    pub fn enter_debugger_from_host(&mut self, message: &str) {
+        echo!("{}", message);
+        self.stack_trace();
+        if let Some(mut console) = self.debug_console.take() {
+            console.wait_for_debugger(self, message, false);
+            self.debug_console = Some(console);
+        } else if self.wait_for_debugger(None) {
+            log!(
+                "The debugger requested a step, but that's not possible here. Continuing instead."
+            );
+        }
+    }
+
+    fn wait_for_debugger(&mut self, reason: Option<cpu::CpuError>) -> bool {
+        if let Some(mut console) = self.debug_console.take() {
+            let message = match reason {
+                Some(error) => format!("Execution paused due to CPU error: {:?}.", error),
+                None => "Execution paused.".to_string(),
+            };
+            let step = console.wait_for_debugger(self, &message, true);
+            self.debug_console = Some(console);
+            return step;
+        }
+        self.gdb_server.as_mut().unwrap().wait_for_debugger(
+            reason,
+            &mut self.cpu,
+            &mut self.mem,
+            &mut self.threads,
+            self.current_thread,
+        )
     }
 
     #[inline(always)]
@@ -832,17 +1015,25 @@ impl Environment {
                         }
                     }
                     dyld::Dyld::SVC_LAZY_LINK | dyld::Dyld::SVC_LINKED_FUNCTIONS_BASE.. => {
-                        if let Some(f) = self.dyld.get_svc_handler(
+                        if let Some((symbol, f)) = self.dyld.get_svc_handler(
                             &self.bins,
                             &mut self.mem,
                             &mut self.cpu,
+                            &self.options,
                             svc_pc,
                             svc,
                         ) {
+                            if !self.breakpoints.is_empty() {
+                                breakpoints::check_function(self, symbol);
+                            }
                             let was_in_host_function =
                                 self.threads[self.current_thread].in_host_function;
                             self.threads[self.current_thread].in_host_function = true;
-                            f.call_from_guest(self);
+                            if self.host_call_tracer.is_some() {
+                                dyld::call_trace::call_traced(self, symbol, f);
+                            } else {
+                                f.call_from_guest(self);
+                            }
                             self.threads[self.current_thread].in_host_function =
                                 was_in_host_function;
                             // Host function might have put the thread to sleep.
@@ -885,6 +1076,7 @@ impl Environment {
             };
             let mut step_and_debug = false;
             while ticks > 0 {
+                let ticks_before = ticks;
                 let state = self.cpu.run_or_step(
                     &mut self.mem,
                     if step_and_debug {
@@ -893,14 +1085,15 @@ impl Environment {
                         Some(&mut ticks)
                     },
                 );
+                self.clock.add_ticks(if step_and_debug {
+                    1
+                } else {
+                    ticks_before - ticks
+                });
                 match self.handle_cpu_state(state, initial_thread, root) {
                     ThreadNextAction::Continue => {
                         if step_and_debug {
-                            step_and_debug = self.gdb_server.as_mut().unwrap().wait_for_debugger(
-                                None,
-                                &mut self.cpu,
-                                &mut self.mem,
-                            );
+                            step_and_debug = self.wait_for_debugger(None);
                         }
                     }
                     ThreadNextAction::Yield => break,
@@ -920,6 +1113,8 @@ impl Environment {
             if let Some(ref mut window) = self.window {
                 window.poll_for_events(&self.options);
             }
+            audio::output::render_captured(self.clock.uptime());
+            self.heap_report_if_due();
 
             loop {
                 // Try to find a new thread to execute, starting with the thread
@@ -936,7 +1131,7 @@ impl Environment {
                     }
                     match candidate.blocked_by {
                         ThreadBlock::Sleeping(sleeping_until) => {
-                            if sleeping_until <= Instant::now() {
+                            if sleeping_until <= self.clock.now() {
                                 log_dbg!("Thread {} finished sleeping.", i);
                                 candidate.blocked_by = ThreadBlock::NotBlocked;
                                 suitable_thread = Some(i);
@@ -1027,9 +1222,11 @@ impl Environment {
                 // All suitable threads are blocked and at least one is asleep.
                 // Sleep until one of them wakes up.
                 } else if let Some(next_awakening) = next_awakening {
-                    let duration = next_awakening.duration_since(Instant::now());
-                    log_dbg!("All threads blocked/asleep, sleeping for {:?}.", duration);
-                    std::thread::sleep(duration);
+                    log_dbg!(
+                        "All threads blocked/asleep, sleeping for {:?}.",
+                        next_awakening.saturating_duration_since(self.clock.now())
+                    );
+                    self.clock.wait_until(next_awakening);
                     // Try again, there should be some thread awake now (or
                     // there will be soon, since timing is approximate).
                     continue;
@@ -0,0 +1,177 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Heap diagnostics (`--heap-diagnostics`).
+//!
+//! The bookkeeping is done by the allocator (see
+//! [crate::mem::Mem::enable_heap_diagnostics]). This module adds the parts
+//! that need the rest of the environment: call sites from the CPU, stack traces
+//! for bad frees, the periodic reports, and the report of Objective-C objects
+//! that are still alive when the app exits.
+
+use super::Environment;
+use crate::cpu::Cpu;
+use crate::mem::{AllocationState, FreedAllocation, Mem, MutVoidPtr};
+use crate::objc::ObjC;
+use crate::options::Options;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How many call sites or classes to list in a report.
+const REPORT_LENGTH: usize = 10;
+
+pub struct HeapDiagnostics {
+    report_interval: Option<Duration>,
+    /// Uptime (see [crate::clock::Clock::uptime]) at which the next periodic
+    /// report is due. This uses the app's clock so that reports line up with
+    /// guest time under `--virtual-clock`.
+    next_report: Duration,
+}
+
+impl HeapDiagnostics {
+    /// Set up heap diagnostics if the options request it.
+    pub fn new_from_options(options: &Options) -> Option<Self> {
+        if !options.heap_diagnostics {
+            return None;
+        }
+        let report_interval = options.heap_report_interval;
+        Some(HeapDiagnostics {
+            report_interval,
+            next_report: report_interval.unwrap_or_default(),
+        })
+    }
+}
+
+fn describe_call_site(call_site: Option<u32>) -> String {
+    match call_site {
+        Some(addr) => format!("{:#x}", addr),
+        None => "touchHLE".to_string(),
+    }
+}
+
+fn print_stats(mem: &Mem) {
+    let stats = mem.heap_stats();
+    echo!(
+        "Heap diagnostics: {} live allocations ({} bytes). {} allocations and {} frees so far. {} bytes free, largest free chunk is {} bytes.",
+        stats.live_allocations,
+        stats.live_bytes,
+        stats.alloc_count,
+        stats.free_count,
+        stats.free_bytes,
+        stats.largest_free_chunk,
+    );
+    if stats.call_sites.is_empty() {
+        return;
+    }
+    echo!("Call sites with the most live bytes:");
+    for &(call_site, count, bytes) in stats.call_sites.iter().take(REPORT_LENGTH) {
+        echo!(
+            "{:>12}: {} allocations, {} bytes",
+            describe_call_site(call_site),
+            count,
+            bytes
+        );
+    }
+}
+
+impl Environment {
+    /// Called by `malloc()` and friends after making an allocation for the
+    /// app, to record the call site.
+    pub fn heap_note_alloc(&mut self, ptr: MutVoidPtr) {
+        if self.heap_diagnostics.is_some() {
+            let call_site = self.cpu.regs()[Cpu::LR];
+            self.mem.set_alloc_call_site(ptr, call_site);
+        }
+    }
+
+    /// Called by `free()` and `realloc()` before they free `ptr` for the app.
+    /// Returns [false] if `ptr` is not an allocation (e.g. because it was
+    /// already freed), after reporting the problem. If heap diagnostics aren't
+    /// enabled, this always returns [true].
+    pub fn heap_check_free(&mut self, ptr: MutVoidPtr, function: &str) -> bool {
+        if self.heap_diagnostics.is_none() {
+            return true;
+        }
+        match self.mem.allocation_state(ptr.cast_const()) {
+            AllocationState::Allocated => return true,
+            AllocationState::Freed(FreedAllocation {
+                alloc_call_site,
+                free_call_site,
+            }) => echo!(
+                "Heap diagnostics: {}() of {:?}, which was already freed! It was allocated from {} and freed from {}.",
+                function,
+                ptr,
+                describe_call_site(alloc_call_site),
+                describe_call_site(free_call_site),
+            ),
+            AllocationState::Unknown => echo!(
+                "Heap diagnostics: {}() of {:?}, which is not an allocation!",
+                function,
+                ptr
+            ),
+        }
+        echo!("Called from {:#x}.", self.cpu.regs()[Cpu::LR]);
+        self.stack_trace();
+        false
+    }
+
+    /// Called by `free()` and `realloc()` after freeing `ptr` for the app, to
+    /// record the call site.
+    pub fn heap_note_free(&mut self, ptr: MutVoidPtr) {
+        if self.heap_diagnostics.is_some() {
+            let call_site = self.cpu.regs()[Cpu::LR];
+            self.mem.set_free_call_site(ptr, call_site);
+        }
+    }
+
+    /// Print the periodic report, if it's time for it.
+    pub(super) fn heap_report_if_due(&mut self) {
+        let Some(HeapDiagnostics {
+            report_interval: Some(interval),
+            ref mut next_report,
+        }) = self.heap_diagnostics
+        else {
+            return;
+        };
+        let now = self.clock.uptime();
+        if now < *next_report {
+            return;
+        }
+        *next_report = now + interval;
+        print_stats(&self.mem);
+    }
+
+    /// Print the final report, including the Objective-C objects that are
+    /// still alive. Not every one of them is a leak: apps don't usually bother
+    /// to release everything before exiting.
+    pub(super) fn heap_report_at_exit(&mut self) {
+        if self.heap_diagnostics.is_none() {
+            return;
+        }
+        print_stats(&self.mem);
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut total = 0;
+        for (object, refcount) in self.objc.all_objects() {
+            // Static objects, e.g. classes and constant strings, can't leak.
+            if refcount.is_none() {
+                continue;
+            }
+            let class = ObjC::read_isa(object, &self.mem);
+            let class_name = self.objc.try_get_class_name(class).unwrap_or("(unknown)");
+            *counts.entry(class_name).or_default() += 1;
+            total += 1;
+        }
+        let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        echo!(
+            "Heap diagnostics: {} Objective-C objects were not deallocated before exit.",
+            total
+        );
+        for (class_name, count) in counts.into_iter().take(REPORT_LENGTH) {
+            echo!("{:7}  {}", count, class_name);
+        }
+    }
+}
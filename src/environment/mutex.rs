@@ -10,6 +10,7 @@ use std::num::NonZeroU32;
 
 use super::{Environment, ThreadId};
 use crate::libc::errno::{EBUSY, EDEADLK, EPERM};
+use crate::save_state::{impl_SaveState_for_struct, Reader, SaveState, Writer};
 
 /// Stores and manages mutexes. Note that all the methods for locking and
 /// unlocking mutexes are on [Environment] instead, because they interact with
@@ -59,6 +60,24 @@ impl TryFrom<i32> for MutexType {
         }
     }
 }
+impl SaveState for MutexType {
+    fn save(&self, w: &mut Writer) {
+        (*self as i32).save(w)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        MutexType::try_from(i32::restore(r)?).map_err(|e| e.to_string())
+    }
+}
+impl_SaveState_for_struct!(Mutex {
+    type_,
+    waiting_count,
+    locked
+});
+impl_SaveState_for_struct!(MutexState {
+    mutexes,
+    mutex_count
+});
+
 pub const PTHREAD_MUTEX_DEFAULT: MutexType = MutexType::PTHREAD_MUTEX_NORMAL;
 
 impl MutexState {
@@ -0,0 +1,220 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Making and loading save states of the whole environment. The format and
+//! most of the serialization is in [crate::save_state].
+//!
+//! The host call stack can't be saved, so a save state can only be made or
+//! loaded when the host stack is in a known shape: the main thread is in its
+//! run loop (which is where the hotkeys are handled), and no other thread is
+//! waiting for a host function to return. When loading, the main thread must
+//! also be in the same host function call as when the state was saved,
+//! otherwise returning from it would resume the wrong guest code.
+
+use super::{Environment, Thread, ThreadBlock};
+use crate::cpu::{CpuContext, CpuRegisters};
+use crate::dyld::Dyld;
+use crate::fs::GuestPathBuf;
+use crate::mem::{Mem, MutVoidPtr};
+use crate::objc::ObjC;
+use crate::save_state::{
+    impl_SaveState_for_struct, read_file, save_str, write_file, Reader, SaveState, Writer,
+};
+use crate::{frameworks, libc, paths};
+use std::path::PathBuf;
+
+/// Registers that identify which host function call the main thread is in.
+const HOST_CALL_REGISTERS: [usize; 3] = [13, 14, 15]; // SP, LR, PC
+
+struct SavedThread {
+    active: bool,
+    blocked_by: ThreadBlock,
+    in_start_routine: bool,
+    return_value: Option<MutVoidPtr>,
+    in_host_function: bool,
+    registers: CpuRegisters,
+    stack: Option<(u32, u32)>,
+}
+impl_SaveState_for_struct!(SavedThread {
+    active,
+    blocked_by,
+    in_start_routine,
+    return_value,
+    in_host_function,
+    registers,
+    stack
+});
+
+impl Environment {
+    fn save_state_path(&self) -> PathBuf {
+        paths::user_data_base_path()
+            .join(paths::SAVE_STATES_DIR)
+            .join(format!("{}.touchHLEstate", self.bundle.bundle_identifier()))
+    }
+
+    /// Check the host stack is in a suitable shape (see the module docs).
+    fn check_can_use_save_states(&self) -> Result<(), String> {
+        if self.input_recording.is_some() {
+            return Err("Save states can't be used while recording or replaying input".to_string());
+        }
+        if self.current_thread != 0 {
+            return Err("Save states can only be used from the main thread".to_string());
+        }
+        for (thread_id, thread) in self.threads.iter().enumerate().skip(1) {
+            if thread.in_host_function || matches!(thread.blocked_by, ThreadBlock::DeferredReturn) {
+                return Err(format!(
+                    "Thread {} is waiting for a host function to return",
+                    thread_id
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Save the state of the app to a file. See [crate::save_state].
+    pub fn save_state(&mut self) -> Result<(), String> {
+        self.check_can_use_save_states()?;
+
+        frameworks::prepare_for_save_state(self);
+
+        let mut w = Writer::new(self.clock.now());
+        save_str(&mut w, self.bundle.bundle_identifier());
+        self.clock.save_state(&mut w);
+        self.fs.working_directory().to_owned().save(&mut w);
+
+        self.threads.len().save(&mut w);
+        for thread_id in 0..self.threads.len() {
+            let registers = if thread_id == self.current_thread {
+                self.cpu.save_registers()
+            } else {
+                let context = self.threads[thread_id].context.as_mut().unwrap();
+                self.cpu.swap_context(context);
+                let registers = self.cpu.save_registers();
+                self.cpu.swap_context(context);
+                registers
+            };
+            let thread = &self.threads[thread_id];
+            SavedThread {
+                active: thread.active,
+                blocked_by: thread.blocked_by.clone(),
+                in_start_routine: thread.in_start_routine,
+                return_value: thread.return_value,
+                in_host_function: thread.in_host_function,
+                registers,
+                stack: thread
+                    .stack
+                    .as_ref()
+                    .map(|stack| (*stack.start(), *stack.end())),
+            }
+            .save(&mut w);
+        }
+
+        self.dyld.save(&mut w);
+        self.objc.save_state(&self.mem, &mut w);
+        self.mutex_state.save(&mut w);
+        self.libc_state.save_state(&mut w);
+        self.framework_state.save_state(&mut w);
+        // This is last because it's by far the biggest part.
+        self.mem.save_state(&mut w);
+
+        let data = w.finish()?;
+        let path = self.save_state_path();
+        write_file(&path, &data)?;
+        echo!("Saved state to {}.", path.display());
+        Ok(())
+    }
+
+    /// Replace the state of the app with the one from the save state file.
+    /// Nothing is changed if the file can't be loaded.
+    pub fn load_state(&mut self) -> Result<(), String> {
+        self.check_can_use_save_states()?;
+
+        let path = self.save_state_path();
+        let data = read_file(&path)?;
+        let mut r = Reader::new(&data, self.clock.now())?;
+
+        let bundle_id = String::restore(&mut r)?;
+        if bundle_id != self.bundle.bundle_identifier() {
+            return Err(format!("Save state is for a different app, {}", bundle_id));
+        }
+        let clock = self.clock.restored_from_state(&mut r)?;
+        r.set_now(clock.now());
+        let working_directory: GuestPathBuf = SaveState::restore(&mut r)?;
+
+        let threads: Vec<SavedThread> = SaveState::restore(&mut r)?;
+        let current_registers = self.cpu.regs();
+        let saved_registers = &threads
+            .first()
+            .ok_or("Save state has no threads")?
+            .registers;
+        if HOST_CALL_REGISTERS
+            .iter()
+            .any(|&i| saved_registers.regs()[i] != current_registers[i])
+        {
+            return Err(
+                "The main thread isn't at the same point as when the state was saved".to_string(),
+            );
+        }
+
+        let dyld = Dyld::restore(&mut r)?;
+        let objc = ObjC::restore_state(&mut r)?;
+        let mutex_state = SaveState::restore(&mut r)?;
+        let libc_state = libc::State::restore_state(&mut r, &mut self.fs)?;
+        let mut framework_state = frameworks::State::restore_state(&mut r, &self.fs)?;
+        let mem_state = Mem::read_state(&mut r)?;
+        r.finish()?;
+
+        // Nothing can fail from here on. Memory is restored in-place, so it
+        // mustn't be touched before this point.
+        self.mem.restore_state(mem_state);
+
+        self.cpu.clear_cache();
+        // The main thread's registers are live rather than in a context.
+        let mut main_thread_registers = None;
+        self.threads = threads
+            .into_iter()
+            .enumerate()
+            .map(|(thread_id, saved)| {
+                let context = if thread_id == 0 {
+                    main_thread_registers = Some(saved.registers);
+                    None
+                } else {
+                    let mut context = CpuContext::new();
+                    self.cpu.swap_context(&mut context);
+                    self.cpu.restore_registers(&saved.registers);
+                    self.cpu.swap_context(&mut context);
+                    Some(context)
+                };
+                Thread {
+                    active: saved.active,
+                    blocked_by: saved.blocked_by,
+                    in_start_routine: saved.in_start_routine,
+                    return_value: saved.return_value,
+                    in_host_function: saved.in_host_function,
+                    context,
+                    stack: saved.stack.map(|(start, end)| start..=end),
+                }
+            })
+            .collect();
+        self.cpu.restore_registers(&main_thread_registers.unwrap());
+        self.current_thread = 0;
+
+        self.clock = clock;
+        self.fs.set_working_directory_unchecked(working_directory);
+        self.dyld = dyld;
+        self.objc = objc;
+        self.mutex_state = mutex_state;
+        self.libc_state = libc_state;
+        framework_state.take_window_state(&mut self.framework_state);
+        // The old state has to be dropped first, so its OpenAL devices etc are
+        // closed before new ones are created.
+        self.framework_state = framework_state;
+
+        frameworks::recreate_after_load_state(self);
+
+        echo!("Loaded state from {}.", path.display());
+        Ok(())
+    }
+}
@@ -20,6 +20,10 @@
 #![allow(non_upper_case_globals)] // Lots of Apple constants begin with "k"
 #![allow(clippy::too_many_arguments)] // It's not our fault!
 
+use crate::fs::Fs;
+use crate::save_state::{Reader, SaveState, Writer};
+use crate::Environment;
+
 pub mod audio_toolbox;
 pub mod av_audio;
 pub mod carbon_core;
@@ -46,3 +50,48 @@ pub struct State {
     opengles: opengles::State,
     uikit: uikit::State,
 }
+impl State {
+    /// Save the state of all frameworks. [prepare_for_save_state] must be
+    /// called first.
+    pub fn save_state(&self, w: &mut Writer) {
+        self.audio_toolbox.save_state(w);
+        self.foundation.save(w);
+        self.media_player.save(w);
+        self.openal.save_state(w);
+        self.opengles.save(w);
+        self.uikit.save(w);
+    }
+
+    /// Restore the state saved by [Self::save_state]. Once the new state is
+    /// in place, [recreate_after_load_state] must be called.
+    pub fn restore_state(r: &mut Reader, fs: &Fs) -> Result<State, String> {
+        Ok(State {
+            audio_toolbox: audio_toolbox::State::restore_state(r, fs)?,
+            core_animation: Default::default(),
+            foundation: SaveState::restore(r)?,
+            media_player: SaveState::restore(r)?,
+            openal: openal::State::restore_state(r)?,
+            opengles: SaveState::restore(r)?,
+            uikit: SaveState::restore(r)?,
+        })
+    }
+
+    /// Core Animation's state belongs to the window rather than the app, so it
+    /// is kept when loading a save state.
+    pub fn take_window_state(&mut self, old: &mut State) {
+        std::mem::swap(&mut self.core_animation, &mut old.core_animation);
+    }
+}
+
+/// Bring host-side state (e.g. OpenGL ES) up to date so it can be saved.
+pub fn prepare_for_save_state(env: &mut Environment) {
+    opengles::prepare_for_save_state(env);
+}
+
+/// Re-create host-side objects (OpenGL ES contexts, OpenAL sources, etc) after
+/// loading a save state.
+pub fn recreate_after_load_state(env: &mut Environment) {
+    openal::recreate_after_load(env);
+    audio_toolbox::audio_queue::recreate_after_load(env);
+    opengles::recreate_contexts_after_load(env);
+}
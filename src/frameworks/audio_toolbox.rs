@@ -21,13 +21,36 @@ macro_rules! return_if_null {
     };
 }
 
+use crate::fs::Fs;
+use crate::save_state::{Reader, Writer};
+
 pub mod audio_file;
+pub mod audio_file_stream;
 pub mod audio_queue;
 pub mod audio_services;
 pub mod audio_session;
+pub mod ext_audio_file;
 
 #[derive(Default)]
 pub struct State {
     audio_file: audio_file::State,
+    audio_file_stream: audio_file_stream::State,
     audio_queue: audio_queue::State,
+    ext_audio_file: ext_audio_file::State,
+}
+impl State {
+    pub fn save_state(&self, w: &mut Writer) {
+        self.audio_file.save_state(w);
+        self.audio_file_stream.save_state(w);
+        self.audio_queue.save_state(w);
+        self.ext_audio_file.save_state(w);
+    }
+    pub fn restore_state(r: &mut Reader, fs: &Fs) -> Result<State, String> {
+        Ok(State {
+            audio_file: audio_file::State::restore_state(r, fs)?,
+            audio_file_stream: audio_file_stream::State::restore_state(r)?,
+            audio_queue: audio_queue::State::restore_state(r)?,
+            ext_audio_file: ext_audio_file::State::restore_state(r, fs)?,
+        })
+    }
 }
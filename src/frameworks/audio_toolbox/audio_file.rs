@@ -15,7 +15,9 @@ use crate::frameworks::core_audio_types::{
 };
 use crate::frameworks::core_foundation::cf_url::CFURLRef;
 use crate::frameworks::foundation::ns_url::to_rust_path;
+use crate::fs::{Fs, GuestPathBuf};
 use crate::mem::{guest_size_of, GuestUSize, MutPtr, MutVoidPtr, SafeRead};
+use crate::save_state::{Reader, SaveState, Writer};
 use crate::Environment;
 use std::collections::HashMap;
 
@@ -27,10 +29,34 @@ impl State {
     pub fn get(framework_state: &mut crate::frameworks::State) -> &mut Self {
         &mut framework_state.audio_toolbox.audio_file
     }
+
+    pub fn save_state(&self, w: &mut Writer) {
+        self.audio_files.len().save(w);
+        for (audio_file_id, host_object) in &self.audio_files {
+            audio_file_id.save(w);
+            host_object.path.save(w);
+        }
+    }
+
+    /// Audio files are re-opened rather than saved, so they must still exist.
+    pub fn restore_state(r: &mut Reader, fs: &Fs) -> Result<State, String> {
+        let len = usize::restore(r)?;
+        let mut audio_files = HashMap::new();
+        for _ in 0..len {
+            let audio_file_id = SaveState::restore(r)?;
+            let path: GuestPathBuf = SaveState::restore(r)?;
+            let audio_file = audio::AudioFile::open_for_reading(&path, fs)
+                .map_err(|()| format!("Couldn't re-open audio file {:?}", path))?;
+            audio_files.insert(audio_file_id, AudioFileHostObject { audio_file, path });
+        }
+        Ok(State { audio_files })
+    }
 }
 
 struct AudioFileHostObject {
     audio_file: audio::AudioFile,
+    /// Absolute path, so the file can be re-opened when loading a save state.
+    path: GuestPathBuf,
 }
 
 #[repr(C, packed)]
@@ -41,7 +67,7 @@ unsafe impl SafeRead for OpaqueAudioFileID {}
 
 pub type AudioFileID = MutPtr<OpaqueAudioFileID>;
 
-const kAudioFileFileNotFoundError: OSStatus = -43;
+pub const kAudioFileFileNotFoundError: OSStatus = -43;
 const kAudioFileBadPropertySizeError: OSStatus = fourcc(b"!siz") as _;
 const kAudioFileUnsupportedProperty: OSStatus = fourcc(b"pty?") as _;
 
@@ -49,7 +75,7 @@ type AudioFilePermissions = i8;
 pub const kAudioFileReadPermission: AudioFilePermissions = 1;
 
 /// Usually a FourCC.
-type AudioFileTypeID = u32;
+pub type AudioFileTypeID = u32;
 
 /// Usually a FourCC.
 type AudioFilePropertyID = u32;
@@ -76,7 +102,7 @@ pub fn AudioFileOpenURL(
     assert!(in_file_type_hint == 0);
 
     let path = to_rust_path(env, in_file_ref);
-    let Ok(audio_file) = audio::AudioFile::open_for_reading(path, &env.fs) else {
+    let Ok(audio_file) = audio::AudioFile::open_for_reading(&path, &env.fs) else {
         log!(
             "Warning: AudioFileOpenURL() for path {:?} failed",
             in_file_ref
@@ -84,7 +110,8 @@ pub fn AudioFileOpenURL(
         return kAudioFileFileNotFoundError;
     };
 
-    let host_object = AudioFileHostObject { audio_file };
+    let path = env.fs.absolute_path(&path);
+    let host_object = AudioFileHostObject { audio_file, path };
 
     let guest_audio_file = env.mem.alloc_and_write(OpaqueAudioFileID { _filler: 0 });
     State::get(&mut env.framework_state)
@@ -166,53 +193,7 @@ pub fn AudioFileGetProperty(
 
     match in_property_id {
         kAudioFilePropertyDataFormat => {
-            let audio::AudioDescription {
-                sample_rate,
-                format,
-                bytes_per_packet,
-                frames_per_packet,
-                channels_per_frame,
-                bits_per_channel,
-            } = host_object.audio_file.audio_description();
-
-            let desc: AudioStreamBasicDescription = match format {
-                audio::AudioFormat::LinearPcm {
-                    is_float,
-                    is_little_endian,
-                } => {
-                    let is_packed = (bits_per_channel * channels_per_frame * frames_per_packet)
-                        == (bytes_per_packet * 8);
-                    let format_flags = (u32::from(is_float) * kAudioFormatFlagIsFloat)
-                        | (u32::from((!is_float) && matches!(bits_per_channel, 16 | 24))
-                            * kAudioFormatFlagIsSignedInteger)
-                        | (u32::from(is_packed) * kAudioFormatFlagIsPacked)
-                        | (u32::from(!is_little_endian) * kAudioFormatFlagIsBigEndian);
-                    AudioStreamBasicDescription {
-                        sample_rate,
-                        format_id: kAudioFormatLinearPCM,
-                        format_flags,
-                        bytes_per_packet,
-                        frames_per_packet,
-                        bytes_per_frame: bytes_per_packet / frames_per_packet,
-                        channels_per_frame,
-                        bits_per_channel,
-                        _reserved: 0,
-                    }
-                }
-                audio::AudioFormat::AppleIma4 => {
-                    AudioStreamBasicDescription {
-                        sample_rate,
-                        format_id: kAudioFormatAppleIMA4,
-                        format_flags: 0,
-                        bytes_per_packet,
-                        frames_per_packet,
-                        bytes_per_frame: 0, // compressed
-                        channels_per_frame,
-                        bits_per_channel,
-                        _reserved: 0,
-                    }
-                }
-            };
+            let desc = description_to_asbd(host_object.audio_file.audio_description());
             env.mem.write(out_property_data.cast(), desc);
         }
         kAudioFilePropertyAudioDataByteCount => {
@@ -234,6 +215,58 @@ pub fn AudioFileGetProperty(
     0 // success
 }
 
+/// Convert the description of an audio file's format to the Core Audio Types
+/// equivalent.
+pub fn description_to_asbd(description: audio::AudioDescription) -> AudioStreamBasicDescription {
+    let audio::AudioDescription {
+        sample_rate,
+        format,
+        bytes_per_packet,
+        frames_per_packet,
+        channels_per_frame,
+        bits_per_channel,
+    } = description;
+
+    match format {
+        audio::AudioFormat::LinearPcm {
+            is_float,
+            is_little_endian,
+        } => {
+            let is_packed = (bits_per_channel * channels_per_frame * frames_per_packet)
+                == (bytes_per_packet * 8);
+            let format_flags = (u32::from(is_float) * kAudioFormatFlagIsFloat)
+                | (u32::from((!is_float) && matches!(bits_per_channel, 16 | 24))
+                    * kAudioFormatFlagIsSignedInteger)
+                | (u32::from(is_packed) * kAudioFormatFlagIsPacked)
+                | (u32::from(!is_little_endian) * kAudioFormatFlagIsBigEndian);
+            AudioStreamBasicDescription {
+                sample_rate,
+                format_id: kAudioFormatLinearPCM,
+                format_flags,
+                bytes_per_packet,
+                frames_per_packet,
+                bytes_per_frame: bytes_per_packet / frames_per_packet,
+                channels_per_frame,
+                bits_per_channel,
+                _reserved: 0,
+            }
+        }
+        audio::AudioFormat::AppleIma4 => {
+            AudioStreamBasicDescription {
+                sample_rate,
+                format_id: kAudioFormatAppleIMA4,
+                format_flags: 0,
+                bytes_per_packet,
+                frames_per_packet,
+                bytes_per_frame: 0, // compressed
+                channels_per_frame,
+                bits_per_channel,
+                _reserved: 0,
+            }
+        }
+    }
+}
+
 fn AudioFileReadBytes(
     env: &mut Environment,
     in_audio_file: AudioFileID,
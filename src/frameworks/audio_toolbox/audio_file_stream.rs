@@ -0,0 +1,1447 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `AudioFileStream.h` (Audio File Stream Services)
+//!
+//! The audio libraries used by [crate::audio] can't parse a file that arrives
+//! piece by piece, so the parsing is done here. Only the formats that audio
+//! queues can play are supported: MP3, AAC in ADTS, and linear PCM or IMA4 in
+//! WAVE and CAF files.
+
+use super::audio_file::AudioFileTypeID;
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::carbon_core::OSStatus;
+use crate::frameworks::core_audio_types::{
+    debug_fourcc, fourcc, kAudioFormatFlagIsBigEndian, kAudioFormatFlagIsFloat,
+    kAudioFormatFlagIsPacked, kAudioFormatFlagIsSignedInteger, kAudioFormatLinearPCM,
+    kAudioFormatMPEG4AAC, kAudioFormatMPEGLayer3, AudioStreamBasicDescription,
+    AudioStreamPacketDescription,
+};
+use crate::mem::{guest_size_of, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead};
+use crate::save_state::{
+    impl_SaveState_for_enum, impl_SaveState_for_struct, Reader, SaveState, Writer,
+};
+use crate::Environment;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct State {
+    streams: HashMap<AudioFileStreamID, AudioFileStreamHostObject>,
+}
+impl State {
+    fn get(framework_state: &mut crate::frameworks::State) -> &mut Self {
+        &mut framework_state.audio_toolbox.audio_file_stream
+    }
+
+    pub fn save_state(&self, w: &mut Writer) {
+        self.streams.save(w);
+    }
+
+    pub fn restore_state(r: &mut Reader) -> Result<State, String> {
+        Ok(State {
+            streams: SaveState::restore(r)?,
+        })
+    }
+}
+
+struct AudioFileStreamHostObject {
+    client_data: MutVoidPtr,
+    property_listener_proc: AudioFileStreamPropertyListenerProc,
+    packets_proc: AudioFileStreamPacketsProc,
+    parser: Parser,
+}
+impl_SaveState_for_struct!(AudioFileStreamHostObject {
+    client_data,
+    property_listener_proc,
+    packets_proc,
+    parser,
+});
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Container {
+    Mp3,
+    Adts,
+    Wave,
+    Caf,
+}
+impl_SaveState_for_enum!(Container {
+    Mp3,
+    Adts,
+    Wave,
+    Caf
+});
+impl Container {
+    fn file_type(self) -> AudioFileTypeID {
+        match self {
+            Container::Mp3 => kAudioFileMP3Type,
+            Container::Adts => kAudioFileAAC_ADTSType,
+            Container::Wave => kAudioFileWAVEType,
+            Container::Caf => kAudioFileCAFType,
+        }
+    }
+}
+
+/// How much data to search for the start of an MP3 or ADTS stream before
+/// giving up.
+const MAX_JUNK: usize = 64 * 1024;
+/// The largest possible MP3 frame (320kbps at 32kHz, with padding).
+const MP3_MAX_FRAME_SIZE: u32 = 1441;
+/// The largest possible ADTS frame (the size field is 13 bits).
+const ADTS_MAX_FRAME_SIZE: u32 = (1 << 13) - 1;
+
+/// The header of an MP3 or ADTS frame.
+#[derive(Debug, PartialEq, Eq)]
+struct FrameHeader {
+    /// Offset of the packet within the frame. ADTS headers aren't part of the
+    /// packet.
+    packet_offset: usize,
+    /// Size of the frame, including the header.
+    frame_size: usize,
+    sample_rate: u32,
+    channels: u32,
+    frames_per_packet: u32,
+    /// For ADTS, the MPEG-4 audio object type and sampling frequency index.
+    aac_config: Option<(u8, u8)>,
+}
+impl FrameHeader {
+    fn data_format(&self) -> AudioStreamBasicDescription {
+        let (format_id, format_flags) = match self.aac_config {
+            Some((object_type, _)) => (kAudioFormatMPEG4AAC, object_type.into()),
+            None => (kAudioFormatMPEGLayer3, 0),
+        };
+        AudioStreamBasicDescription {
+            sample_rate: self.sample_rate.into(),
+            format_id,
+            format_flags,
+            bytes_per_packet: 0, // variable
+            frames_per_packet: self.frames_per_packet,
+            bytes_per_frame: 0, // compressed
+            channels_per_frame: self.channels,
+            bits_per_channel: 0,
+            _reserved: 0,
+        }
+    }
+
+    /// The `AudioSpecificConfig` for an ADTS stream.
+    fn magic_cookie(&self) -> Option<Vec<u8>> {
+        let (object_type, frequency_index) = self.aac_config?;
+        let channels = self.channels as u8;
+        Some(vec![
+            (object_type << 3) | (frequency_index >> 1),
+            ((frequency_index & 1) << 7) | (channels << 3),
+        ])
+    }
+
+    /// Check if a frame is from the same stream as this one. This helps avoid
+    /// mistaking other data for a frame.
+    fn is_compatible(&self, other: &FrameHeader) -> bool {
+        self.sample_rate == other.sample_rate
+            && self.channels == other.channels
+            && self.aac_config.is_some() == other.aac_config.is_some()
+    }
+}
+
+fn parse_mp3_frame_header(data: &[u8]) -> Option<FrameHeader> {
+    const BITRATES_V1: [u32; 15] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    const BITRATES_V2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+    let header = data.get(..4)?;
+    if header[0] != 0xff || (header[1] & 0xe0) != 0xe0 {
+        return None;
+    }
+    let version = (header[1] >> 3) & 3;
+    let layer = (header[1] >> 1) & 3;
+    let bitrate_index = usize::from(header[2] >> 4);
+    let frequency_index = usize::from((header[2] >> 2) & 3);
+    let padding = usize::from((header[2] >> 1) & 1);
+    // Only layer III is supported. A bitrate index of 0 means "free format",
+    // which isn't supported either.
+    if version == 1 || layer != 1 || bitrate_index == 0 || bitrate_index == 15 {
+        return None;
+    }
+    let (bitrate, sample_rates, frames_per_packet) = match version {
+        3 => (BITRATES_V1[bitrate_index], [44100, 48000, 32000], 1152),
+        2 => (BITRATES_V2[bitrate_index], [22050, 24000, 16000], 576),
+        0 => (BITRATES_V2[bitrate_index], [11025, 12000, 8000], 576),
+        _ => unreachable!(),
+    };
+    let sample_rate = *sample_rates.get(frequency_index)?;
+    let frame_size = (frames_per_packet / 8 * bitrate * 1000 / sample_rate) as usize + padding;
+    let channels = if (header[3] >> 6) == 3 { 1 } else { 2 };
+    Some(FrameHeader {
+        packet_offset: 0,
+        frame_size,
+        sample_rate,
+        channels,
+        frames_per_packet,
+        aac_config: None,
+    })
+}
+
+fn parse_adts_frame_header(data: &[u8]) -> Option<FrameHeader> {
+    const SAMPLE_RATES: [u32; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+
+    let header = data.get(..7)?;
+    // The layer is always 0.
+    if header[0] != 0xff || (header[1] & 0xf6) != 0xf0 {
+        return None;
+    }
+    let protection_absent = (header[1] & 1) == 1;
+    let object_type = (header[2] >> 6) + 1;
+    let frequency_index = (header[2] >> 2) & 0xf;
+    let channel_config = ((header[2] & 1) << 2) | (header[3] >> 6);
+    let frame_size = (usize::from(header[3] & 3) << 11)
+        | (usize::from(header[4]) << 3)
+        | usize::from(header[5] >> 5);
+    let raw_data_blocks = header[6] & 3;
+    let header_size = if protection_absent { 7 } else { 9 };
+
+    let sample_rate = *SAMPLE_RATES.get(usize::from(frequency_index))?;
+    // A channel configuration of 0 means it's specified in the stream, and
+    // frames with multiple blocks can't be decoded as a single packet. Neither
+    // of these are supported.
+    if !(1..=2).contains(&channel_config) || raw_data_blocks != 0 || frame_size <= header_size {
+        return None;
+    }
+    Some(FrameHeader {
+        packet_offset: header_size,
+        frame_size,
+        sample_rate,
+        channels: channel_config.into(),
+        frames_per_packet: 1024,
+        aac_config: Some((object_type, frequency_index)),
+    })
+}
+
+/// Information from the header of a file, which comes before the audio data.
+struct StreamHeader {
+    data_format: AudioStreamBasicDescription,
+    magic_cookie: Option<Vec<u8>>,
+    /// Size of the header in bytes.
+    header_size: usize,
+    /// Size of the audio data in bytes, if the header specifies it.
+    data_byte_count: Option<u64>,
+}
+
+/// Parse the header of a WAVE file. Returns [None] if more data is needed.
+fn parse_wave_header(data: &[u8]) -> Result<Option<StreamHeader>, OSStatus> {
+    if data.len() < 12 {
+        return Ok(None);
+    }
+    if &data[8..12] != b"WAVE" {
+        return Err(kAudioFileStreamError_InvalidFile);
+    }
+    let mut format = None;
+    let mut pos = 12;
+    loop {
+        let Some(chunk_header) = data.get(pos..pos + 8) else {
+            return Ok(None);
+        };
+        let chunk_size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap());
+        let body_start = pos + 8;
+        if &chunk_header[..4] == b"data" {
+            let data_format = format.ok_or(kAudioFileStreamError_InvalidFile)?;
+            // Streamed files might not have a real size.
+            let data_byte_count =
+                (chunk_size != 0 && chunk_size != u32::MAX).then_some(chunk_size.into());
+            return Ok(Some(StreamHeader {
+                data_format,
+                magic_cookie: None,
+                header_size: body_start,
+                data_byte_count,
+            }));
+        }
+        let body_end = body_start + chunk_size as usize;
+        let Some(body) = data.get(body_start..body_end) else {
+            return Ok(None);
+        };
+        if &chunk_header[..4] == b"fmt " {
+            format = Some(parse_wave_format(body)?);
+        }
+        // Chunks are padded to an even size.
+        pos = body_end + (body_end & 1);
+    }
+}
+
+fn parse_wave_format(fmt: &[u8]) -> Result<AudioStreamBasicDescription, OSStatus> {
+    const WAVE_FORMAT_PCM: u16 = 1;
+    const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+    const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+    if fmt.len() < 16 {
+        return Err(kAudioFileStreamError_InvalidFile);
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes(fmt[offset..offset + 2].try_into().unwrap());
+    let format_tag = match u16_at(0) {
+        // The real format tag is the start of the sub-format GUID.
+        WAVE_FORMAT_EXTENSIBLE if fmt.len() >= 26 => u16_at(24),
+        format_tag => format_tag,
+    };
+    let channels: u32 = u16_at(2).into();
+    let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+    let block_align: u32 = u16_at(12).into();
+    let bits: u32 = u16_at(14).into();
+
+    let is_float = match format_tag {
+        WAVE_FORMAT_PCM => false,
+        WAVE_FORMAT_IEEE_FLOAT => true,
+        _ => return Err(kAudioFileStreamError_UnsupportedDataFormat),
+    };
+    if channels == 0 || block_align == 0 {
+        return Err(kAudioFileStreamError_InvalidFile);
+    }
+    // 8-bit WAVE data is unsigned.
+    let format_flags = if is_float {
+        kAudioFormatFlagIsFloat
+    } else if bits > 8 {
+        kAudioFormatFlagIsSignedInteger
+    } else {
+        0
+    } | if bits / 8 * channels == block_align {
+        kAudioFormatFlagIsPacked
+    } else {
+        0
+    };
+    Ok(AudioStreamBasicDescription {
+        sample_rate: sample_rate.into(),
+        format_id: kAudioFormatLinearPCM,
+        format_flags,
+        bytes_per_packet: block_align,
+        frames_per_packet: 1,
+        bytes_per_frame: block_align,
+        channels_per_frame: channels,
+        bits_per_channel: bits,
+        _reserved: 0,
+    })
+}
+
+/// Parse the header of a CAF file. Returns [None] if more data is needed.
+fn parse_caf_header(data: &[u8]) -> Result<Option<StreamHeader>, OSStatus> {
+    let mut format = None;
+    let mut magic_cookie = None;
+    // The file header is the type, version and flags.
+    let mut pos = 8;
+    loop {
+        let Some(chunk_header) = data.get(pos..pos + 12) else {
+            return Ok(None);
+        };
+        let chunk_size = i64::from_be_bytes(chunk_header[4..12].try_into().unwrap());
+        let body_start = pos + 12;
+        if &chunk_header[..4] == b"data" {
+            let data_format = format.ok_or(kAudioFileStreamError_InvalidFile)?;
+            // The data starts with an edit count. The size is -1 if unknown.
+            if data.len() < body_start + 4 {
+                return Ok(None);
+            }
+            let data_byte_count = (chunk_size >= 4).then(|| chunk_size as u64 - 4);
+            return Ok(Some(StreamHeader {
+                data_format,
+                magic_cookie,
+                header_size: body_start + 4,
+                data_byte_count,
+            }));
+        }
+        let chunk_size: usize = chunk_size
+            .try_into()
+            .map_err(|_| kAudioFileStreamError_InvalidFile)?;
+        let Some(body) = data.get(body_start..body_start + chunk_size) else {
+            return Ok(None);
+        };
+        match &chunk_header[..4] {
+            b"desc" => format = Some(parse_caf_description(body)?),
+            b"kuki" => magic_cookie = Some(body.to_vec()),
+            _ => (),
+        }
+        pos = body_start + chunk_size;
+    }
+}
+
+fn parse_caf_description(desc: &[u8]) -> Result<AudioStreamBasicDescription, OSStatus> {
+    const CAF_LINEAR_PCM_FORMAT_FLAG_IS_FLOAT: u32 = 1 << 0;
+    const CAF_LINEAR_PCM_FORMAT_FLAG_IS_LITTLE_ENDIAN: u32 = 1 << 1;
+
+    if desc.len() < 32 {
+        return Err(kAudioFileStreamError_InvalidFile);
+    }
+    let u32_at = |offset: usize| u32::from_be_bytes(desc[offset..offset + 4].try_into().unwrap());
+    let sample_rate = f64::from_be_bytes(desc[..8].try_into().unwrap());
+    let format_id = u32_at(8);
+    let caf_format_flags = u32_at(12);
+    let bytes_per_packet = u32_at(16);
+    let frames_per_packet = u32_at(20);
+    let channels_per_frame = u32_at(24);
+    let bits_per_channel = u32_at(28);
+
+    // Variable-size packets would need the packet table chunk.
+    if bytes_per_packet == 0 || frames_per_packet == 0 {
+        return Err(kAudioFileStreamError_UnsupportedDataFormat);
+    }
+    let (format_flags, bytes_per_frame) = if format_id == kAudioFormatLinearPCM {
+        let is_float = (caf_format_flags & CAF_LINEAR_PCM_FORMAT_FLAG_IS_FLOAT) != 0;
+        let is_little_endian =
+            (caf_format_flags & CAF_LINEAR_PCM_FORMAT_FLAG_IS_LITTLE_ENDIAN) != 0;
+        let format_flags = if is_float {
+            kAudioFormatFlagIsFloat
+        } else {
+            kAudioFormatFlagIsSignedInteger
+        } | if is_little_endian {
+            0
+        } else {
+            kAudioFormatFlagIsBigEndian
+        } | kAudioFormatFlagIsPacked;
+        (format_flags, bytes_per_packet / frames_per_packet)
+    } else {
+        (caf_format_flags, 0)
+    };
+    Ok(AudioStreamBasicDescription {
+        sample_rate,
+        format_id,
+        format_flags,
+        bytes_per_packet,
+        frames_per_packet,
+        bytes_per_frame,
+        channels_per_frame,
+        bits_per_channel,
+        _reserved: 0,
+    })
+}
+
+/// Something the app needs to be told about after parsing.
+#[derive(Debug)]
+enum Event {
+    PropertyFound(AudioFileStreamPropertyID),
+    Packets {
+        data: Vec<u8>,
+        packet_count: u32,
+        /// Only provided for formats with variable-size packets.
+        packet_descriptions: Option<Vec<AudioStreamPacketDescription>>,
+    },
+}
+
+/// A property value, in a form that can be written to guest memory.
+enum PropertyValue {
+    U32(u32),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    DataFormat(AudioStreamBasicDescription),
+    Bytes(Vec<u8>),
+}
+impl PropertyValue {
+    fn size(&self) -> GuestUSize {
+        match self {
+            PropertyValue::U32(_) => guest_size_of::<u32>(),
+            PropertyValue::U64(_) => guest_size_of::<u64>(),
+            PropertyValue::I64(_) => guest_size_of::<i64>(),
+            PropertyValue::F64(_) => guest_size_of::<f64>(),
+            PropertyValue::DataFormat(_) => guest_size_of::<AudioStreamBasicDescription>(),
+            PropertyValue::Bytes(bytes) => bytes.len().try_into().unwrap(),
+        }
+    }
+
+    fn write(&self, mem: &mut Mem, ptr: MutVoidPtr) {
+        match *self {
+            PropertyValue::U32(value) => mem.write(ptr.cast(), value),
+            PropertyValue::U64(value) => mem.write(ptr.cast(), value),
+            PropertyValue::I64(value) => mem.write(ptr.cast(), value),
+            PropertyValue::F64(value) => mem.write(ptr.cast(), value),
+            PropertyValue::DataFormat(value) => mem.write(ptr.cast(), value),
+            PropertyValue::Bytes(ref bytes) => mem
+                .bytes_at_mut(ptr.cast(), self.size())
+                .copy_from_slice(bytes),
+        }
+    }
+}
+
+/// Incremental parser for an audio file.
+#[derive(Default)]
+struct Parser {
+    /// Data that has been provided but not yet parsed.
+    pending: Vec<u8>,
+    /// Offset in the file of the start of [Self::pending].
+    offset: u64,
+    /// Number of bytes of the data provided next that should be skipped (e.g.
+    /// because they are part of an ID3 tag).
+    skip: u64,
+    container: Option<Container>,
+    data_format: Option<AudioStreamBasicDescription>,
+    magic_cookie: Option<Vec<u8>>,
+    /// Offset in the file of the start of the audio data.
+    data_offset: u64,
+    data_byte_count: Option<u64>,
+    /// Offset of [Self::pending] within the audio data, once the header has
+    /// been parsed.
+    data_position: u64,
+    ready_to_produce_packets: bool,
+    // Statistics for variable-size packets.
+    packet_count: u64,
+    packet_bytes: u64,
+    /// Like [Self::packet_bytes], but including the frame headers that aren't
+    /// part of ADTS packets, for estimating where a packet is in the file.
+    frame_bytes: u64,
+    max_packet_size: u32,
+}
+impl_SaveState_for_struct!(Parser {
+    pending,
+    offset,
+    skip,
+    container,
+    data_format,
+    magic_cookie,
+    data_offset,
+    data_byte_count,
+    data_position,
+    ready_to_produce_packets,
+    packet_count,
+    packet_bytes,
+    frame_bytes,
+    max_packet_size,
+});
+
+impl Parser {
+    fn consume(&mut self, count: usize) {
+        self.pending.drain(..count);
+        self.offset += count as u64;
+        if self.ready_to_produce_packets {
+            self.data_position += count as u64;
+        }
+    }
+
+    fn is_variable_packet_size(&self) -> bool {
+        matches!(self.container, Some(Container::Mp3 | Container::Adts))
+    }
+
+    /// Parse some more data, returning the events the app needs to be told
+    /// about.
+    fn parse(&mut self, data: &[u8], discontinuity: bool) -> Result<Vec<Event>, OSStatus> {
+        if discontinuity {
+            // Whatever was left over doesn't continue into the new data.
+            self.pending.clear();
+        }
+        let skip = self.skip.min(data.len() as u64);
+        self.skip -= skip;
+        self.offset += skip;
+        self.pending.extend_from_slice(&data[skip as usize..]);
+
+        let mut events = Vec::new();
+        if !self.ready_to_produce_packets {
+            self.parse_header(&mut events)?;
+            if !self.ready_to_produce_packets {
+                return Ok(events);
+            }
+        }
+        self.parse_packets(&mut events);
+        Ok(events)
+    }
+
+    fn parse_header(&mut self, events: &mut Vec<Event>) -> Result<(), OSStatus> {
+        loop {
+            let header = match self.container {
+                None => {
+                    if self.pending.len() < 10 {
+                        return Ok(());
+                    }
+                    if self.pending.starts_with(b"ID3") {
+                        // The size excludes the header and is "synchsafe".
+                        let size = self.pending[6..10]
+                            .iter()
+                            .fold(0, |size, &byte| (size << 7) | u64::from(byte & 0x7f));
+                        let has_footer = (self.pending[5] & 0x10) != 0;
+                        let size = 10 + size + if has_footer { 10 } else { 0 };
+                        let available = size.min(self.pending.len() as u64);
+                        self.consume(available as usize);
+                        self.skip = size - available;
+                        continue;
+                    }
+                    if self.pending.starts_with(b"RIFF") {
+                        self.container = Some(Container::Wave);
+                    } else if self.pending.starts_with(b"caff") {
+                        self.container = Some(Container::Caf);
+                    } else {
+                        // Look for the first MP3 or ADTS frame.
+                        let found = (0..self.pending.len()).find_map(|i| {
+                            let data = &self.pending[i..];
+                            if parse_adts_frame_header(data).is_some() {
+                                Some((i, Container::Adts))
+                            } else if parse_mp3_frame_header(data).is_some() {
+                                Some((i, Container::Mp3))
+                            } else {
+                                None
+                            }
+                        });
+                        let Some((junk, container)) = found else {
+                            if self.pending.len() > MAX_JUNK {
+                                return Err(kAudioFileStreamError_UnsupportedFileType);
+                            }
+                            return Ok(());
+                        };
+                        self.consume(junk);
+                        self.container = Some(container);
+                    }
+                    events.push(Event::PropertyFound(kAudioFileStreamProperty_FileFormat));
+                    continue;
+                }
+                Some(Container::Wave) => parse_wave_header(&self.pending)?,
+                Some(Container::Caf) => parse_caf_header(&self.pending)?,
+                Some(Container::Mp3 | Container::Adts) => {
+                    let frame = if self.container == Some(Container::Mp3) {
+                        parse_mp3_frame_header(&self.pending)
+                    } else {
+                        parse_adts_frame_header(&self.pending)
+                    };
+                    frame.map(|frame| StreamHeader {
+                        data_format: frame.data_format(),
+                        magic_cookie: frame.magic_cookie(),
+                        header_size: 0,
+                        data_byte_count: None,
+                    })
+                }
+            };
+            let Some(header) = header else {
+                return Ok(());
+            };
+
+            log_dbg!(
+                "Audio file stream format: {:#?}, magic cookie: {:02x?}",
+                header.data_format,
+                header.magic_cookie
+            );
+            self.consume(header.header_size);
+            self.data_format = Some(header.data_format);
+            self.data_offset = self.offset;
+            self.data_byte_count = header.data_byte_count;
+            self.data_position = 0;
+            events.push(Event::PropertyFound(kAudioFileStreamProperty_DataFormat));
+            if header.magic_cookie.is_some() {
+                self.magic_cookie = header.magic_cookie;
+                events.push(Event::PropertyFound(
+                    kAudioFileStreamProperty_MagicCookieData,
+                ));
+            }
+            if self.data_byte_count.is_some() {
+                events.push(Event::PropertyFound(
+                    kAudioFileStreamProperty_AudioDataByteCount,
+                ));
+            }
+            events.push(Event::PropertyFound(kAudioFileStreamProperty_DataOffset));
+            self.ready_to_produce_packets = true;
+            events.push(Event::PropertyFound(
+                kAudioFileStreamProperty_ReadyToProducePackets,
+            ));
+            return Ok(());
+        }
+    }
+
+    fn parse_packets(&mut self, events: &mut Vec<Event>) {
+        let data_format = self.data_format.unwrap();
+
+        if !self.is_variable_packet_size() {
+            let mut available = self.pending.len() as u64;
+            if let Some(data_byte_count) = self.data_byte_count {
+                // Anything after the audio data isn't audio.
+                available = available.min(data_byte_count.saturating_sub(self.data_position));
+            }
+            let packet_size = u64::from(data_format.bytes_per_packet);
+            let packet_count = available / packet_size;
+            if packet_count == 0 {
+                return;
+            }
+            let byte_count = (packet_count * packet_size) as usize;
+            let data = self.pending[..byte_count].to_vec();
+            self.consume(byte_count);
+            events.push(Event::Packets {
+                data,
+                packet_count: packet_count.try_into().unwrap(),
+                packet_descriptions: None,
+            });
+            return;
+        }
+
+        let first_frame = if self.container == Some(Container::Mp3) {
+            parse_mp3_frame_header
+        } else {
+            parse_adts_frame_header
+        };
+        let stream_frame = FrameHeader {
+            packet_offset: 0,
+            frame_size: 0,
+            sample_rate: data_format.sample_rate as u32,
+            channels: data_format.channels_per_frame,
+            frames_per_packet: data_format.frames_per_packet,
+            aac_config: (data_format.format_id == kAudioFormatMPEG4AAC).then_some((0, 0)),
+        };
+
+        let mut data = Vec::new();
+        let mut packet_descriptions = Vec::new();
+        let mut pos = 0;
+        // An MP3 header is only 4 bytes, but this is simpler.
+        while self.pending.len() - pos >= 9 {
+            let frame = match first_frame(&self.pending[pos..]) {
+                Some(frame) if stream_frame.is_compatible(&frame) => frame,
+                // Skip anything that isn't a frame, e.g. an ID3v1 tag.
+                _ => {
+                    pos += 1;
+                    continue;
+                }
+            };
+            if self.pending.len() - pos < frame.frame_size {
+                break;
+            }
+            let packet = &self.pending[pos..][frame.packet_offset..frame.frame_size];
+            let packet_size: u32 = packet.len().try_into().unwrap();
+            packet_descriptions.push(AudioStreamPacketDescription {
+                start_offset: data.len().try_into().unwrap(),
+                variable_frames_in_packet: 0,
+                data_byte_size: packet_size,
+            });
+            data.extend_from_slice(packet);
+            pos += frame.frame_size;
+
+            self.packet_count += 1;
+            self.packet_bytes += u64::from(packet_size);
+            self.frame_bytes += frame.frame_size as u64;
+            self.max_packet_size = self.max_packet_size.max(packet_size);
+        }
+        self.consume(pos);
+        if !packet_descriptions.is_empty() {
+            events.push(Event::Packets {
+                data,
+                packet_count: packet_descriptions.len().try_into().unwrap(),
+                packet_descriptions: Some(packet_descriptions),
+            });
+        }
+    }
+
+    fn property(&self, property_id: AudioFileStreamPropertyID) -> Result<PropertyValue, OSStatus> {
+        if property_id == kAudioFileStreamProperty_ReadyToProducePackets {
+            return Ok(PropertyValue::U32(self.ready_to_produce_packets.into()));
+        }
+        if property_id == kAudioFileStreamProperty_FileFormat {
+            let container = self.container.ok_or(kAudioFileStreamError_ValueUnknown)?;
+            return Ok(PropertyValue::U32(container.file_type()));
+        }
+
+        let data_format = self.data_format.ok_or(kAudioFileStreamError_ValueUnknown)?;
+        let bytes_per_packet = data_format.bytes_per_packet;
+        let average_bytes_per_packet = if !self.is_variable_packet_size() {
+            Some(f64::from(bytes_per_packet))
+        } else if self.packet_count != 0 {
+            Some(self.packet_bytes as f64 / self.packet_count as f64)
+        } else {
+            None
+        };
+        let packet_size_upper_bound = match self.container.unwrap() {
+            Container::Mp3 => MP3_MAX_FRAME_SIZE,
+            Container::Adts => ADTS_MAX_FRAME_SIZE,
+            Container::Wave | Container::Caf => bytes_per_packet,
+        };
+
+        let value = match property_id {
+            kAudioFileStreamProperty_DataFormat => Some(PropertyValue::DataFormat(data_format)),
+            kAudioFileStreamProperty_MagicCookieData => {
+                self.magic_cookie.clone().map(PropertyValue::Bytes)
+            }
+            kAudioFileStreamProperty_DataOffset => {
+                Some(PropertyValue::I64(self.data_offset.try_into().unwrap()))
+            }
+            kAudioFileStreamProperty_AudioDataByteCount => {
+                self.data_byte_count.map(PropertyValue::U64)
+            }
+            kAudioFileStreamProperty_AudioDataPacketCount => self
+                .data_byte_count
+                .filter(|_| !self.is_variable_packet_size())
+                .map(|byte_count| PropertyValue::U64(byte_count / u64::from(bytes_per_packet))),
+            kAudioFileStreamProperty_AverageBytesPerPacket => {
+                average_bytes_per_packet.map(PropertyValue::F64)
+            }
+            kAudioFileStreamProperty_BitRate => average_bytes_per_packet.map(|bytes| {
+                let packets_per_second =
+                    data_format.sample_rate / f64::from(data_format.frames_per_packet);
+                PropertyValue::U32((bytes * 8.0 * packets_per_second) as u32)
+            }),
+            kAudioFileStreamProperty_PacketSizeUpperBound => {
+                Some(PropertyValue::U32(packet_size_upper_bound))
+            }
+            kAudioFileStreamProperty_MaximumPacketSize => Some(PropertyValue::U32(
+                if self.is_variable_packet_size() && self.max_packet_size != 0 {
+                    self.max_packet_size
+                } else {
+                    packet_size_upper_bound
+                },
+            )),
+            _ => return Err(kAudioFileStreamError_UnsupportedProperty),
+        };
+        value.ok_or(kAudioFileStreamError_ValueUnknown)
+    }
+
+    /// Find the offset within the audio data of a packet, and whether that
+    /// offset is only an estimate. The data from that offset onwards should be
+    /// provided next.
+    fn seek(&mut self, packet: u64) -> Result<(u64, bool), OSStatus> {
+        let data_format = self
+            .data_format
+            .ok_or(kAudioFileStreamError_DataUnavailable)?;
+        let (offset, is_estimated) = if !self.is_variable_packet_size() {
+            (packet * u64::from(data_format.bytes_per_packet), false)
+        } else if self.packet_count != 0 {
+            let average_bytes_per_frame = self.frame_bytes as f64 / self.packet_count as f64;
+            ((packet as f64 * average_bytes_per_frame) as u64, true)
+        } else {
+            return Err(kAudioFileStreamError_DataUnavailable);
+        };
+        if self.data_byte_count.is_some_and(|count| offset > count) {
+            return Err(kAudioFileStreamError_InvalidPacketOffset);
+        }
+        self.pending.clear();
+        self.skip = 0;
+        self.offset = self.data_offset + offset;
+        self.data_position = offset;
+        Ok((offset, is_estimated))
+    }
+}
+
+#[repr(C, packed)]
+pub struct OpaqueAudioFileStreamID {
+    _filler: u8,
+}
+unsafe impl SafeRead for OpaqueAudioFileStreamID {}
+
+pub type AudioFileStreamID = MutPtr<OpaqueAudioFileStreamID>;
+
+/// (*void)(void *in_client_data, AudioFileStreamID in_audio_file_stream,
+/// AudioFileStreamPropertyID in_property_id, UInt32 *io_flags)
+type AudioFileStreamPropertyListenerProc = GuestFunction;
+/// (*void)(void *in_client_data, UInt32 in_number_bytes,
+/// UInt32 in_number_packets, const void *in_input_data,
+/// AudioStreamPacketDescription *in_packet_descriptions)
+type AudioFileStreamPacketsProc = GuestFunction;
+
+const kAudioFileMP3Type: AudioFileTypeID = fourcc(b"MPG3");
+const kAudioFileAAC_ADTSType: AudioFileTypeID = fourcc(b"adts");
+const kAudioFileWAVEType: AudioFileTypeID = fourcc(b"WAVE");
+const kAudioFileCAFType: AudioFileTypeID = fourcc(b"caff");
+
+type AudioFileStreamPropertyID = u32;
+const kAudioFileStreamProperty_ReadyToProducePackets: AudioFileStreamPropertyID = fourcc(b"redy");
+const kAudioFileStreamProperty_FileFormat: AudioFileStreamPropertyID = fourcc(b"ffmt");
+const kAudioFileStreamProperty_DataFormat: AudioFileStreamPropertyID = fourcc(b"dfmt");
+const kAudioFileStreamProperty_MagicCookieData: AudioFileStreamPropertyID = fourcc(b"mgic");
+const kAudioFileStreamProperty_AudioDataByteCount: AudioFileStreamPropertyID = fourcc(b"bcnt");
+const kAudioFileStreamProperty_AudioDataPacketCount: AudioFileStreamPropertyID = fourcc(b"pcnt");
+const kAudioFileStreamProperty_MaximumPacketSize: AudioFileStreamPropertyID = fourcc(b"mxpk");
+const kAudioFileStreamProperty_DataOffset: AudioFileStreamPropertyID = fourcc(b"doff");
+const kAudioFileStreamProperty_BitRate: AudioFileStreamPropertyID = fourcc(b"brat");
+const kAudioFileStreamProperty_PacketSizeUpperBound: AudioFileStreamPropertyID = fourcc(b"pkub");
+const kAudioFileStreamProperty_AverageBytesPerPacket: AudioFileStreamPropertyID = fourcc(b"abpp");
+
+type AudioFileStreamParseFlags = u32;
+const kAudioFileStreamParseFlag_Discontinuity: AudioFileStreamParseFlags = 1;
+
+type AudioFileStreamSeekFlags = u32;
+const kAudioFileStreamSeekFlag_OffsetIsEstimated: AudioFileStreamSeekFlags = 1;
+
+const kAudioFileStreamError_UnsupportedFileType: OSStatus = fourcc(b"typ?") as _;
+const kAudioFileStreamError_UnsupportedDataFormat: OSStatus = fourcc(b"fmt?") as _;
+const kAudioFileStreamError_UnsupportedProperty: OSStatus = fourcc(b"pty?") as _;
+const kAudioFileStreamError_BadPropertySize: OSStatus = fourcc(b"!siz") as _;
+const kAudioFileStreamError_InvalidPacketOffset: OSStatus = fourcc(b"pck?") as _;
+const kAudioFileStreamError_InvalidFile: OSStatus = fourcc(b"dta?") as _;
+const kAudioFileStreamError_ValueUnknown: OSStatus = fourcc(b"unk?") as _;
+const kAudioFileStreamError_DataUnavailable: OSStatus = fourcc(b"more") as _;
+
+fn AudioFileStreamOpen(
+    env: &mut Environment,
+    in_client_data: MutVoidPtr,
+    in_property_listener_proc: AudioFileStreamPropertyListenerProc,
+    in_packets_proc: AudioFileStreamPacketsProc,
+    in_file_type_hint: AudioFileTypeID,
+    out_audio_file_stream: MutPtr<AudioFileStreamID>,
+) -> OSStatus {
+    return_if_null!(out_audio_file_stream);
+
+    // The file type is always detected from the data, so the hint isn't
+    // needed.
+    let host_object = AudioFileStreamHostObject {
+        client_data: in_client_data,
+        property_listener_proc: in_property_listener_proc,
+        packets_proc: in_packets_proc,
+        parser: Parser::default(),
+    };
+    let stream = env
+        .mem
+        .alloc_and_write(OpaqueAudioFileStreamID { _filler: 0 });
+    State::get(&mut env.framework_state)
+        .streams
+        .insert(stream, host_object);
+    env.mem.write(out_audio_file_stream, stream);
+
+    log_dbg!(
+        "AudioFileStreamOpen() with file type hint {}, new audio file stream: {:?}",
+        debug_fourcc(in_file_type_hint),
+        stream
+    );
+
+    0 // success
+}
+
+fn AudioFileStreamParseBytes(
+    env: &mut Environment,
+    in_audio_file_stream: AudioFileStreamID,
+    in_data_byte_size: u32,
+    in_data: ConstVoidPtr,
+    in_flags: AudioFileStreamParseFlags,
+) -> OSStatus {
+    return_if_null!(in_audio_file_stream);
+
+    let host_object = State::get(&mut env.framework_state)
+        .streams
+        .get_mut(&in_audio_file_stream)
+        .unwrap();
+
+    let data = if in_data_byte_size != 0 {
+        env.mem.bytes_at(in_data.cast(), in_data_byte_size)
+    } else {
+        &[]
+    };
+    let discontinuity = (in_flags & kAudioFileStreamParseFlag_Discontinuity) != 0;
+    let events = match host_object.parser.parse(data, discontinuity) {
+        Ok(events) => events,
+        Err(status) => {
+            log!(
+                "Warning: AudioFileStreamParseBytes() for {:?} failed: {}",
+                in_audio_file_stream,
+                debug_fourcc(status as u32)
+            );
+            return status;
+        }
+    };
+
+    let &mut AudioFileStreamHostObject {
+        client_data,
+        property_listener_proc,
+        packets_proc,
+        ..
+    } = host_object;
+
+    for event in events {
+        log_dbg!(
+            "Audio file stream {:?} event: {}",
+            in_audio_file_stream,
+            match event {
+                Event::PropertyFound(property_id) => debug_fourcc(property_id),
+                Event::Packets { packet_count, .. } => format!("{} packets", packet_count),
+            }
+        );
+        match event {
+            Event::PropertyFound(property_id) => {
+                let io_flags = env.mem.alloc_and_write(0u32);
+                let () = property_listener_proc.call_from_host(
+                    env,
+                    (client_data, in_audio_file_stream, property_id, io_flags),
+                );
+                env.mem.free(io_flags.cast());
+            }
+            Event::Packets {
+                data,
+                packet_count,
+                packet_descriptions,
+            } => {
+                let byte_count: GuestUSize = data.len().try_into().unwrap();
+                let data_ptr = env.mem.alloc(byte_count);
+                env.mem
+                    .bytes_at_mut(data_ptr.cast(), byte_count)
+                    .copy_from_slice(&data);
+                let descriptions_ptr: MutPtr<AudioStreamPacketDescription> =
+                    match packet_descriptions {
+                        Some(packet_descriptions) => {
+                            let ptr = env
+                                .mem
+                                .alloc(
+                                    packet_count * guest_size_of::<AudioStreamPacketDescription>(),
+                                )
+                                .cast();
+                            for (i, description) in packet_descriptions.into_iter().enumerate() {
+                                env.mem.write(ptr + i as GuestUSize, description);
+                            }
+                            ptr
+                        }
+                        None => Ptr::null(),
+                    };
+                let () = packets_proc.call_from_host(
+                    env,
+                    (
+                        client_data,
+                        byte_count,
+                        packet_count,
+                        data_ptr.cast_const(),
+                        descriptions_ptr,
+                    ),
+                );
+                env.mem.free(data_ptr);
+                if !descriptions_ptr.is_null() {
+                    env.mem.free(descriptions_ptr.cast());
+                }
+            }
+        }
+        // The app might have closed the stream in the callback.
+        if !State::get(&mut env.framework_state)
+            .streams
+            .contains_key(&in_audio_file_stream)
+        {
+            break;
+        }
+    }
+
+    0 // success
+}
+
+fn AudioFileStreamSeek(
+    env: &mut Environment,
+    in_audio_file_stream: AudioFileStreamID,
+    in_packet_offset: i64,
+    out_data_byte_offset: MutPtr<i64>,
+    io_flags: MutPtr<AudioFileStreamSeekFlags>,
+) -> OSStatus {
+    return_if_null!(in_audio_file_stream);
+
+    let host_object = State::get(&mut env.framework_state)
+        .streams
+        .get_mut(&in_audio_file_stream)
+        .unwrap();
+
+    let Ok(packet) = u64::try_from(in_packet_offset) else {
+        return kAudioFileStreamError_InvalidPacketOffset;
+    };
+    let (offset, is_estimated) = match host_object.parser.seek(packet) {
+        Ok(result) => result,
+        Err(status) => return status,
+    };
+    env.mem
+        .write(out_data_byte_offset, offset.try_into().unwrap());
+    if !io_flags.is_null() {
+        let flags = if is_estimated {
+            kAudioFileStreamSeekFlag_OffsetIsEstimated
+        } else {
+            0
+        };
+        env.mem.write(io_flags, flags);
+    }
+
+    0 // success
+}
+
+fn AudioFileStreamGetPropertyInfo(
+    env: &mut Environment,
+    in_audio_file_stream: AudioFileStreamID,
+    in_property_id: AudioFileStreamPropertyID,
+    out_property_data_size: MutPtr<u32>,
+    out_writable: MutPtr<bool>,
+) -> OSStatus {
+    return_if_null!(in_audio_file_stream);
+
+    let host_object = State::get(&mut env.framework_state)
+        .streams
+        .get_mut(&in_audio_file_stream)
+        .unwrap();
+
+    let value = match host_object.parser.property(in_property_id) {
+        Ok(value) => value,
+        Err(status) => {
+            if status == kAudioFileStreamError_UnsupportedProperty {
+                log!(
+                    "TODO: AudioFileStreamGetPropertyInfo({:?}, {})",
+                    in_audio_file_stream,
+                    debug_fourcc(in_property_id)
+                );
+            }
+            return status;
+        }
+    };
+    if !out_property_data_size.is_null() {
+        env.mem.write(out_property_data_size, value.size());
+    }
+    if !out_writable.is_null() {
+        env.mem.write(out_writable, false);
+    }
+    0 // success
+}
+
+fn AudioFileStreamGetProperty(
+    env: &mut Environment,
+    in_audio_file_stream: AudioFileStreamID,
+    in_property_id: AudioFileStreamPropertyID,
+    io_property_data_size: MutPtr<u32>,
+    out_property_data: MutVoidPtr,
+) -> OSStatus {
+    return_if_null!(in_audio_file_stream);
+
+    let host_object = State::get(&mut env.framework_state)
+        .streams
+        .get_mut(&in_audio_file_stream)
+        .unwrap();
+
+    let value = match host_object.parser.property(in_property_id) {
+        Ok(value) => value,
+        Err(status) => {
+            if status == kAudioFileStreamError_UnsupportedProperty {
+                log!(
+                    "TODO: AudioFileStreamGetProperty({:?}, {})",
+                    in_audio_file_stream,
+                    debug_fourcc(in_property_id)
+                );
+            }
+            return status;
+        }
+    };
+    let size = value.size();
+    if env.mem.read(io_property_data_size) < size {
+        log!("Warning: AudioFileStreamGetProperty() failed");
+        return kAudioFileStreamError_BadPropertySize;
+    }
+    value.write(&mut env.mem, out_property_data);
+    env.mem.write(io_property_data_size, size);
+    0 // success
+}
+
+fn AudioFileStreamSetProperty(
+    _env: &mut Environment,
+    in_audio_file_stream: AudioFileStreamID,
+    in_property_id: AudioFileStreamPropertyID,
+    in_property_data_size: u32,
+    in_property_data: ConstVoidPtr,
+) -> OSStatus {
+    return_if_null!(in_audio_file_stream);
+
+    log!(
+        "TODO: AudioFileStreamSetProperty({:?}, {}, {}, {:?})",
+        in_audio_file_stream,
+        debug_fourcc(in_property_id),
+        in_property_data_size,
+        in_property_data
+    );
+    kAudioFileStreamError_UnsupportedProperty
+}
+
+fn AudioFileStreamClose(
+    env: &mut Environment,
+    in_audio_file_stream: AudioFileStreamID,
+) -> OSStatus {
+    return_if_null!(in_audio_file_stream);
+
+    let _host_object = State::get(&mut env.framework_state)
+        .streams
+        .remove(&in_audio_file_stream)
+        .unwrap();
+    env.mem.free(in_audio_file_stream.cast());
+    log_dbg!(
+        "AudioFileStreamClose() destroyed audio file stream: {:?}",
+        in_audio_file_stream
+    );
+    0 // success
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(AudioFileStreamOpen(_, _, _, _, _)),
+    export_c_func!(AudioFileStreamParseBytes(_, _, _, _)),
+    export_c_func!(AudioFileStreamSeek(_, _, _, _)),
+    export_c_func!(AudioFileStreamGetPropertyInfo(_, _, _, _)),
+    export_c_func!(AudioFileStreamGetProperty(_, _, _, _)),
+    export_c_func!(AudioFileStreamSetProperty(_, _, _, _)),
+    export_c_func!(AudioFileStreamClose(_)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An MPEG-1 layer III frame header: 128kbps, 44100Hz, stereo.
+    const MP3_HEADER: [u8; 4] = [0xff, 0xfb, 0x90, 0x00];
+
+    fn mp3_frame(padding: bool) -> Vec<u8> {
+        let mut frame = MP3_HEADER.to_vec();
+        if padding {
+            frame[2] |= 0x02;
+        }
+        frame.resize(if padding { 418 } else { 417 }, 0x55);
+        frame
+    }
+
+    /// An ADTS frame with a 7-byte header: AAC-LC, 44100Hz, stereo.
+    fn adts_frame(payload_size: usize) -> Vec<u8> {
+        let frame_size = 7 + payload_size;
+        let mut frame = vec![
+            0xff,
+            0xf1,
+            0x50,
+            0x80 | (frame_size >> 11) as u8,
+            (frame_size >> 3) as u8,
+            ((frame_size & 7) << 5) as u8 | 0x1f,
+            0xfc,
+        ];
+        frame.resize(frame_size, 0xaa);
+        frame
+    }
+
+    fn properties(events: &[Event]) -> Vec<AudioFileStreamPropertyID> {
+        events
+            .iter()
+            .filter_map(|event| match *event {
+                Event::PropertyFound(property_id) => Some(property_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frame_headers() {
+        let frame = parse_mp3_frame_header(&MP3_HEADER).unwrap();
+        assert_eq!(frame.frame_size, 417);
+        assert_eq!(frame.sample_rate, 44100);
+        assert_eq!(frame.channels, 2);
+        assert_eq!(frame.frames_per_packet, 1152);
+        // MPEG-2, 64kbps, 22050Hz, padded, mono
+        let frame = parse_mp3_frame_header(&[0xff, 0xf3, 0x82, 0xc0]).unwrap();
+        assert_eq!(frame.frame_size, 209);
+        assert_eq!(frame.sample_rate, 22050);
+        assert_eq!(frame.channels, 1);
+        assert_eq!(frame.frames_per_packet, 576);
+        // Layer II isn't supported.
+        assert!(parse_mp3_frame_header(&[0xff, 0xfd, 0x90, 0x00]).is_none());
+        assert!(parse_mp3_frame_header(&[0xff, 0xfb, 0x90]).is_none());
+
+        let frame = parse_adts_frame_header(&adts_frame(100)).unwrap();
+        assert_eq!(frame.packet_offset, 7);
+        assert_eq!(frame.frame_size, 107);
+        assert_eq!(frame.sample_rate, 44100);
+        assert_eq!(frame.channels, 2);
+        assert_eq!(frame.magic_cookie(), Some(vec![0x12, 0x10]));
+        assert!(parse_mp3_frame_header(&adts_frame(100)).is_none());
+        assert!(parse_adts_frame_header(&MP3_HEADER).is_none());
+    }
+
+    #[test]
+    fn mp3_stream() {
+        let mut file = b"ID3\x04\x00\x00\x00\x00\x00\x05hello".to_vec();
+        file.extend_from_slice(&mp3_frame(false));
+        file.extend_from_slice(&mp3_frame(true));
+        file.extend_from_slice(&mp3_frame(false));
+        file.extend_from_slice(b"TAG");
+
+        let mut parser = Parser::default();
+        // The ID3 tag is split up, and the header of the first frame is split
+        // across two calls.
+        let events = parser.parse(&file[..12], false).unwrap();
+        assert!(events.is_empty());
+        let events = parser.parse(&file[12..17], false).unwrap();
+        assert!(events.is_empty());
+        let events = parser.parse(&file[17..450], false).unwrap();
+        assert_eq!(
+            properties(&events),
+            [
+                kAudioFileStreamProperty_FileFormat,
+                kAudioFileStreamProperty_DataFormat,
+                kAudioFileStreamProperty_DataOffset,
+                kAudioFileStreamProperty_ReadyToProducePackets,
+            ]
+        );
+        let Some(Event::Packets {
+            packet_count: 1,
+            packet_descriptions: Some(ref packet_descriptions),
+            ..
+        }) = events.last()
+        else {
+            panic!("{:?}", events.last());
+        };
+        assert_eq!({ packet_descriptions[0].data_byte_size }, 417);
+        assert!(matches!(
+            parser.property(kAudioFileStreamProperty_DataOffset),
+            Ok(PropertyValue::I64(15))
+        ));
+        let Ok(PropertyValue::DataFormat(data_format)) =
+            parser.property(kAudioFileStreamProperty_DataFormat)
+        else {
+            panic!();
+        };
+        assert_eq!({ data_format.format_id }, kAudioFormatMPEGLayer3);
+        assert_eq!({ data_format.sample_rate }, 44100.0);
+
+        let events = parser.parse(&file[450..], false).unwrap();
+        let [Event::Packets {
+            ref data,
+            packet_count: 2,
+            packet_descriptions: Some(ref packet_descriptions),
+        }] = events[..]
+        else {
+            panic!("{:?}", events);
+        };
+        assert_eq!(data.len(), 418 + 417);
+        assert_eq!({ packet_descriptions[1].start_offset }, 418);
+        assert_eq!({ packet_descriptions[1].data_byte_size }, 417);
+
+        let (offset, is_estimated) = parser.seek(2).unwrap();
+        assert!(is_estimated);
+        assert_eq!(offset, 834);
+    }
+
+    #[test]
+    fn adts_stream() {
+        let mut file = vec![0x00, 0x01, 0x02]; // junk
+        file.extend_from_slice(&adts_frame(100));
+        file.extend_from_slice(&adts_frame(200));
+
+        let mut parser = Parser::default();
+        let events = parser.parse(&file[..50], false).unwrap();
+        assert_eq!(
+            properties(&events),
+            [
+                kAudioFileStreamProperty_FileFormat,
+                kAudioFileStreamProperty_DataFormat,
+                kAudioFileStreamProperty_MagicCookieData,
+                kAudioFileStreamProperty_DataOffset,
+                kAudioFileStreamProperty_ReadyToProducePackets,
+            ]
+        );
+        let events = parser.parse(&file[50..], false).unwrap();
+        let [Event::Packets {
+            ref data,
+            packet_count: 2,
+            packet_descriptions: Some(ref packet_descriptions),
+        }] = events[..]
+        else {
+            panic!("{:?}", events);
+        };
+        // The headers aren't part of the packets.
+        assert_eq!(data.len(), 300);
+        assert_eq!({ packet_descriptions[1].start_offset }, 100);
+        assert_eq!({ packet_descriptions[1].data_byte_size }, 200);
+
+        // The estimate has to account for the frame headers.
+        assert_eq!(parser.seek(2), Ok((107 + 207, true)));
+    }
+
+    #[test]
+    fn wave_stream() {
+        let mut file = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        file.extend_from_slice(b"fmt \x10\x00\x00\x00");
+        file.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        file.extend_from_slice(&2u16.to_le_bytes()); // channels
+        file.extend_from_slice(&22050u32.to_le_bytes());
+        file.extend_from_slice(&(22050u32 * 4).to_le_bytes());
+        file.extend_from_slice(&4u16.to_le_bytes()); // block align
+        file.extend_from_slice(&16u16.to_le_bytes()); // bits
+        file.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00"); // padded
+        file.extend_from_slice(b"data\x0a\x00\x00\x00");
+        file.extend_from_slice(&[1; 10]);
+        file.extend_from_slice(b"junk");
+
+        // Feed it a byte at a time.
+        let mut parser = Parser::default();
+        let mut events = Vec::new();
+        for byte in &file {
+            events.extend(parser.parse(std::slice::from_ref(byte), false).unwrap());
+        }
+        assert_eq!(
+            properties(&events),
+            [
+                kAudioFileStreamProperty_FileFormat,
+                kAudioFileStreamProperty_DataFormat,
+                kAudioFileStreamProperty_AudioDataByteCount,
+                kAudioFileStreamProperty_DataOffset,
+                kAudioFileStreamProperty_ReadyToProducePackets,
+            ]
+        );
+        let packets: Vec<&Event> = events
+            .iter()
+            .filter(|event| matches!(event, Event::Packets { .. }))
+            .collect();
+        // Incomplete packets and data after the data chunk are left out.
+        assert_eq!(packets.len(), 2);
+        let Event::Packets {
+            data,
+            packet_count: 1,
+            packet_descriptions: None,
+        } = packets[0]
+        else {
+            panic!("{:?}", packets[0]);
+        };
+        assert_eq!(data, &[1; 4]);
+        assert!(matches!(
+            parser.property(kAudioFileStreamProperty_DataOffset),
+            Ok(PropertyValue::I64(56))
+        ));
+        assert!(matches!(
+            parser.property(kAudioFileStreamProperty_AudioDataPacketCount),
+            Ok(PropertyValue::U64(2))
+        ));
+        assert!(matches!(
+            parser.property(kAudioFileStreamProperty_BitRate),
+            Ok(PropertyValue::U32(705600))
+        ));
+
+        assert_eq!(parser.seek(1), Ok((4, false)));
+        let events = parser.parse(&file[60..], true).unwrap();
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn caf_stream() {
+        let mut file = b"caff\x00\x01\x00\x00".to_vec();
+        file.extend_from_slice(b"desc");
+        file.extend_from_slice(&32i64.to_be_bytes());
+        file.extend_from_slice(&44100f64.to_be_bytes());
+        file.extend_from_slice(b"ima4");
+        for value in [0u32, 34, 64, 1, 0] {
+            file.extend_from_slice(&value.to_be_bytes());
+        }
+        file.extend_from_slice(b"kuki");
+        file.extend_from_slice(&2i64.to_be_bytes());
+        file.extend_from_slice(&[0x12, 0x34]);
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&(-1i64).to_be_bytes());
+        file.extend_from_slice(&[0; 4]); // edit count
+        file.extend_from_slice(&[7; 34 * 3 + 10]);
+
+        let mut parser = Parser::default();
+        let events = parser.parse(&file, false).unwrap();
+        assert_eq!(
+            properties(&events),
+            [
+                kAudioFileStreamProperty_FileFormat,
+                kAudioFileStreamProperty_DataFormat,
+                kAudioFileStreamProperty_MagicCookieData,
+                kAudioFileStreamProperty_DataOffset,
+                kAudioFileStreamProperty_ReadyToProducePackets,
+            ]
+        );
+        let Some(Event::Packets {
+            data,
+            packet_count: 3,
+            packet_descriptions: None,
+        }) = events.last()
+        else {
+            panic!("{:?}", events.last());
+        };
+        assert_eq!(data.len(), 34 * 3);
+        assert!(matches!(
+            parser.property(kAudioFileStreamProperty_AudioDataByteCount),
+            Err(kAudioFileStreamError_ValueUnknown)
+        ));
+    }
+
+    #[test]
+    fn unsupported_stream() {
+        let mut parser = Parser::default();
+        assert!(parser.parse(&[0; 1000], false).unwrap().is_empty());
+        assert!(matches!(
+            parser.parse(&[0; MAX_JUNK], false),
+            Err(kAudioFileStreamError_UnsupportedFileType)
+        ));
+    }
+}
@@ -9,6 +9,7 @@
 //! Apple's implementation probably uses Core Audio instead.
 
 use crate::abi::{CallFromHost, GuestFunction};
+use crate::audio;
 use crate::audio::decode_ima4;
 use crate::audio::openal as al;
 use crate::audio::openal::al_types::*;
@@ -16,9 +17,10 @@ use crate::audio::openal::alc_types::*;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::frameworks::carbon_core::OSStatus;
 use crate::frameworks::core_audio_types::{
-    debug_fourcc, fourcc, kAudioFormatAppleIMA4, kAudioFormatFlagIsBigEndian,
-    kAudioFormatFlagIsFloat, kAudioFormatFlagIsPacked, kAudioFormatLinearPCM,
-    AudioStreamBasicDescription,
+    debug_fourcc, fourcc, kAudioFormatAppleIMA4, kAudioFormatFlagIsAlignedHigh,
+    kAudioFormatFlagIsBigEndian, kAudioFormatFlagIsFloat, kAudioFormatFlagIsNonInterleaved,
+    kAudioFormatFlagIsSignedInteger, kAudioFormatLinearPCM, kAudioFormatMPEG4AAC,
+    kAudioFormatMPEGLayer3, AudioStreamBasicDescription, AudioStreamPacketDescription,
 };
 use crate::frameworks::core_foundation::cf_run_loop::{
     kCFRunLoopCommonModes, CFRunLoopGetMain, CFRunLoopMode, CFRunLoopRef,
@@ -29,6 +31,7 @@ use crate::mem::{
     guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead,
 };
 use crate::objc::msg;
+use crate::save_state::{impl_SaveState_for_enum, Reader, SaveState, Writer};
 use crate::Environment;
 use std::collections::{HashMap, VecDeque};
 
@@ -43,9 +46,9 @@ impl State {
     }
     fn make_al_context_current(&mut self) -> ContextManager {
         if self.al_device_and_context.is_none() {
-            let device = unsafe { al::alcOpenDevice(std::ptr::null()) };
+            let device = unsafe { audio::output::open_device() };
             assert!(!device.is_null());
-            let context = unsafe { al::alcCreateContext(device, std::ptr::null()) };
+            let context = unsafe { audio::output::create_context(device) };
             assert!(!context.is_null());
             log_dbg!(
                 "New internal OpenAL device ({:?}) and context ({:?})",
@@ -61,6 +64,58 @@ impl State {
         // to the guest app, is restored once we're done.
         ContextManager::make_active(context)
     }
+
+    pub fn save_state(&self, w: &mut Writer) {
+        self.audio_queues.save(w);
+    }
+
+    /// Restore the state saved by [Self::save_state]. The OpenAL objects
+    /// aren't re-created until [recreate_after_load] is called.
+    pub fn restore_state(r: &mut Reader) -> Result<State, String> {
+        Ok(State {
+            audio_queues: SaveState::restore(r)?,
+            al_device_and_context: None,
+        })
+    }
+}
+impl Drop for State {
+    fn drop(&mut self) {
+        // This matters when a save state is loaded: the old audio queues are
+        // replaced, but the OpenAL device would otherwise stay open.
+        if let Some((device, context)) = self.al_device_and_context {
+            unsafe {
+                if al::alcGetCurrentContext() == context {
+                    al::alcMakeContextCurrent(std::ptr::null_mut());
+                }
+                al::alcDestroyContext(context);
+                audio::output::close_device(device);
+            }
+        }
+    }
+}
+
+/// Re-create the OpenAL sources for audio queues after loading a save state.
+/// Buffers that were partially played will start again from the beginning.
+pub fn recreate_after_load(env: &mut Environment) {
+    let mut queues: Vec<AudioQueueRef> = State::get(&mut env.framework_state)
+        .audio_queues
+        .iter()
+        .filter(|(_, host_object)| host_object.had_al_source)
+        .map(|(&aq_ref, _)| aq_ref)
+        .collect();
+    queues.sort();
+    for in_aq in queues {
+        let _context_manager = prime_audio_queue(env, in_aq, None);
+        let host_object = State::get(&mut env.framework_state)
+            .audio_queues
+            .get_mut(&in_aq)
+            .unwrap();
+        if let Some(al_source) = host_object.al_source {
+            if host_object.is_running != AudioQueueIsRunning::Stopped {
+                unsafe { al::alSourcePlay(al_source) };
+            }
+        }
+    }
 }
 
 #[must_use]
@@ -95,6 +150,55 @@ struct AudioQueueHostObject {
     al_unused_buffers: Vec<ALuint>,
     aq_is_running_proc: Option<AudioQueuePropertyListenerProc>,
     aq_is_running_user_data: Option<MutVoidPtr>,
+    /// Set with `kAudioQueueProperty_MagicCookie`.
+    magic_cookie: Option<Vec<u8>>,
+    /// Packet descriptions provided when enqueueing the buffers in
+    /// [Self::buffer_queue], for formats with variable-size packets.
+    packet_descriptions: HashMap<AudioQueueBufferRef, Vec<AudioStreamPacketDescription>>,
+    /// Decoder for compressed formats, created when the first buffer is
+    /// decoded. It's stateful, so buffers must be decoded in order. This is
+    /// [Err] if the decoder couldn't be created, so that's only reported once.
+    packet_decoder: Option<Result<audio::PacketDecoder, ()>>,
+    /// Set when restoring a save state if [Self::al_source] was [Some]
+    /// when saving.
+    had_al_source: bool,
+}
+impl SaveState for AudioQueueHostObject {
+    fn save(&self, w: &mut Writer) {
+        self.format.save(w);
+        self.callback_proc.save(w);
+        self.callback_user_data.save(w);
+        self.run_loop.save(w);
+        self.volume.save(w);
+        self.buffers.save(w);
+        self.buffer_queue.save(w);
+        self.is_running.save(w);
+        self.aq_is_running_proc.save(w);
+        self.aq_is_running_user_data.save(w);
+        self.magic_cookie.save(w);
+        self.packet_descriptions.save(w);
+        self.al_source.is_some().save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(AudioQueueHostObject {
+            format: SaveState::restore(r)?,
+            callback_proc: SaveState::restore(r)?,
+            callback_user_data: SaveState::restore(r)?,
+            run_loop: SaveState::restore(r)?,
+            volume: SaveState::restore(r)?,
+            buffers: SaveState::restore(r)?,
+            buffer_queue: SaveState::restore(r)?,
+            is_running: SaveState::restore(r)?,
+            al_source: None,
+            al_unused_buffers: Vec::new(),
+            aq_is_running_proc: SaveState::restore(r)?,
+            aq_is_running_user_data: SaveState::restore(r)?,
+            magic_cookie: SaveState::restore(r)?,
+            packet_descriptions: SaveState::restore(r)?,
+            packet_decoder: None,
+            had_al_source: SaveState::restore(r)?,
+        })
+    }
 }
 
 /// Track whether the audio queue is meant to be running, in order to handle
@@ -107,6 +211,11 @@ enum AudioQueueIsRunning {
     Stopping,
     Stopped,
 }
+impl_SaveState_for_enum!(AudioQueueIsRunning {
+    Running,
+    Stopping,
+    Stopped
+});
 
 #[repr(C, packed)]
 pub struct OpaqueAudioQueue {
@@ -122,11 +231,9 @@ pub struct AudioQueueBuffer {
     pub audio_data: MutVoidPtr,
     pub audio_data_byte_size: u32,
     user_data: MutVoidPtr,
-    _packet_description_capacity: u32,
-    /// Should be a `MutPtr<AudioStreamPacketDescription>`, but that's not
-    /// implemented yet.
-    _packet_descriptions: MutVoidPtr,
-    _packet_description_count: u32,
+    packet_description_capacity: u32,
+    packet_descriptions: MutPtr<AudioStreamPacketDescription>,
+    packet_description_count: u32,
 }
 unsafe impl SafeRead for AudioQueueBuffer {}
 
@@ -142,12 +249,14 @@ type AudioQueueParameterValue = f32;
 
 pub type AudioQueuePropertyID = u32;
 pub const kAudioQueueProperty_IsRunning: AudioQueuePropertyID = fourcc(b"aqrn");
+pub const kAudioQueueProperty_MagicCookie: AudioQueuePropertyID = fourcc(b"aqmc");
 
 /// (*void)(void *in_user_data, AudioQueueRef in_aq, AudioQueuePropertyID in_id)
 type AudioQueuePropertyListenerProc = GuestFunction;
 
 const kAudioQueueErr_InvalidBuffer: OSStatus = -66687;
 const kAudioQueueErr_InvalidPropertySize: OSStatus = -66683;
+const kAudioQueueErr_InvalidProperty: OSStatus = -66682;
 const kAudioQueueErr_BufferInQueue: OSStatus = -66679;
 
 pub fn AudioQueueNewOutput(
@@ -194,6 +303,10 @@ pub fn AudioQueueNewOutput(
         al_unused_buffers: Vec::new(),
         aq_is_running_proc: None,
         aq_is_running_user_data: None,
+        magic_cookie: None,
+        packet_descriptions: HashMap::new(),
+        packet_decoder: None,
+        had_al_source: false,
     };
 
     let aq_ref = env.mem.alloc_and_write(OpaqueAudioQueue { _filler: 0 });
@@ -265,6 +378,16 @@ pub fn AudioQueueAllocateBuffer(
     in_aq: AudioQueueRef,
     in_buffer_byte_size: GuestUSize,
     out_buffer: MutPtr<AudioQueueBufferRef>,
+) -> OSStatus {
+    AudioQueueAllocateBufferWithPacketDescriptions(env, in_aq, in_buffer_byte_size, 0, out_buffer)
+}
+
+fn AudioQueueAllocateBufferWithPacketDescriptions(
+    env: &mut Environment,
+    in_aq: AudioQueueRef,
+    in_buffer_byte_size: GuestUSize,
+    in_number_packet_descriptions: u32,
+    out_buffer: MutPtr<AudioQueueBufferRef>,
 ) -> OSStatus {
     return_if_null!(in_aq);
 
@@ -274,14 +397,21 @@ pub fn AudioQueueAllocateBuffer(
         .unwrap();
 
     let audio_data = env.mem.alloc(in_buffer_byte_size);
+    let packet_descriptions = if in_number_packet_descriptions != 0 {
+        env.mem
+            .alloc(in_number_packet_descriptions * guest_size_of::<AudioStreamPacketDescription>())
+            .cast()
+    } else {
+        Ptr::null()
+    };
     let buffer_ptr = env.mem.alloc_and_write(AudioQueueBuffer {
         audio_data_bytes_capacity: in_buffer_byte_size,
         audio_data,
         audio_data_byte_size: 0,
         user_data: Ptr::null(),
-        _packet_description_capacity: 0,
-        _packet_descriptions: Ptr::null(),
-        _packet_description_count: 0,
+        packet_description_capacity: in_number_packet_descriptions,
+        packet_descriptions,
+        packet_description_count: 0,
     });
     host_object.buffers.push(buffer_ptr);
     env.mem.write(out_buffer, buffer_ptr);
@@ -289,19 +419,26 @@ pub fn AudioQueueAllocateBuffer(
     0 // success
 }
 
+/// Free a buffer's memory. It must already have been removed from the audio
+/// queue's list of buffers.
+fn free_buffer(mem: &mut Mem, buffer_ref: AudioQueueBufferRef) {
+    let buffer = mem.read(buffer_ref);
+    mem.free(buffer.audio_data);
+    if !buffer.packet_descriptions.is_null() {
+        mem.free(buffer.packet_descriptions.cast());
+    }
+    mem.free(buffer_ref.cast());
+}
+
 pub fn AudioQueueEnqueueBuffer(
     env: &mut Environment,
     in_aq: AudioQueueRef,
     in_buffer: AudioQueueBufferRef,
-    _in_num_packet_descs: u32,
-    _in_packet_descs: MutVoidPtr,
+    in_num_packet_descs: u32,
+    in_packet_descs: ConstPtr<AudioStreamPacketDescription>,
 ) -> OSStatus {
     return_if_null!(in_aq);
 
-    // Variable packet size unimplemented (no formats supported that need it).
-    // We don't assert the count is 0 because we might get a useless one even
-    // for formats that don't need it.
-
     let host_object = State::get(&mut env.framework_state)
         .audio_queues
         .get_mut(&in_aq)
@@ -311,6 +448,28 @@ pub fn AudioQueueEnqueueBuffer(
         return kAudioQueueErr_InvalidBuffer;
     }
 
+    // Packet descriptions are only needed for formats with variable-size
+    // packets, but we might get useless ones for other formats too. They can
+    // be passed here, or be in the buffer if it was allocated with space for
+    // them.
+    let buffer = env.mem.read(in_buffer);
+    let (count, packet_descs) = if in_num_packet_descs != 0 && !in_packet_descs.is_null() {
+        (in_num_packet_descs, in_packet_descs)
+    } else {
+        (
+            buffer.packet_description_count,
+            buffer.packet_descriptions.cast_const(),
+        )
+    };
+    if count != 0 && !packet_descs.is_null() {
+        let packet_descs = (0..count).map(|i| env.mem.read(packet_descs + i)).collect();
+        host_object
+            .packet_descriptions
+            .insert(in_buffer, packet_descs);
+    } else {
+        host_object.packet_descriptions.remove(&in_buffer);
+    }
+
     host_object.buffer_queue.push_back(in_buffer);
     log_dbg!("New buffer enqueued: {:?}", in_buffer);
 
@@ -374,9 +533,16 @@ fn AudioQueueRemovePropertyListener(
     0 // success
 }
 
-fn property_size(property_id: AudioQueuePropertyID) -> GuestUSize {
+fn property_size(
+    host_object: &AudioQueueHostObject,
+    property_id: AudioQueuePropertyID,
+) -> GuestUSize {
     match property_id {
         kAudioQueueProperty_IsRunning => guest_size_of::<u32>(),
+        kAudioQueueProperty_MagicCookie => host_object
+            .magic_cookie
+            .as_ref()
+            .map_or(0, |cookie| cookie.len().try_into().unwrap()),
         _ => unimplemented!("Unimplemented property ID: {}", debug_fourcc(property_id)),
     }
 }
@@ -389,7 +555,13 @@ fn AudioQueueGetPropertySize(
 ) -> OSStatus {
     return_if_null!(in_aq);
 
-    env.mem.write(out_data_size, property_size(in_property_id));
+    let host_object = State::get(&mut env.framework_state)
+        .audio_queues
+        .get_mut(&in_aq)
+        .unwrap();
+
+    env.mem
+        .write(out_data_size, property_size(host_object, in_property_id));
     0 // success
 }
 
@@ -402,17 +574,21 @@ fn AudioQueueGetProperty(
 ) -> OSStatus {
     return_if_null!(in_aq);
 
-    let required_size = property_size(in_property_id);
-    if env.mem.read(io_data_size) != required_size {
-        log!("Warning: AudioQueueGetProperty() failed");
-        return kAudioQueueErr_InvalidPropertySize;
-    }
-
     let host_object = State::get(&mut env.framework_state)
         .audio_queues
         .get_mut(&in_aq)
         .unwrap();
 
+    let required_size = property_size(host_object, in_property_id);
+    let data_size = env.mem.read(io_data_size);
+    // The magic cookie can be read into a larger buffer.
+    if data_size != required_size
+        && !(in_property_id == kAudioQueueProperty_MagicCookie && data_size > required_size)
+    {
+        log!("Warning: AudioQueueGetProperty() failed");
+        return kAudioQueueErr_InvalidPropertySize;
+    }
+
     match in_property_id {
         kAudioQueueProperty_IsRunning => {
             let is_running: u32 = match host_object.is_running {
@@ -422,16 +598,62 @@ fn AudioQueueGetProperty(
             };
             env.mem.write(out_property_data.cast(), is_running);
         }
+        kAudioQueueProperty_MagicCookie => {
+            if let Some(cookie) = &host_object.magic_cookie {
+                env.mem
+                    .bytes_at_mut(out_property_data.cast(), required_size)
+                    .copy_from_slice(cookie);
+            }
+            env.mem.write(io_data_size, required_size);
+        }
         _ => unreachable!(),
     }
 
     0 // success
 }
 
+fn AudioQueueSetProperty(
+    env: &mut Environment,
+    in_aq: AudioQueueRef,
+    in_property_id: AudioQueuePropertyID,
+    in_property_data: ConstVoidPtr,
+    in_data_size: u32,
+) -> OSStatus {
+    return_if_null!(in_aq);
+
+    let host_object = State::get(&mut env.framework_state)
+        .audio_queues
+        .get_mut(&in_aq)
+        .unwrap();
+
+    match in_property_id {
+        kAudioQueueProperty_MagicCookie => {
+            let cookie = env.mem.bytes_at(in_property_data.cast(), in_data_size);
+            log_dbg!("Magic cookie for queue {:?}: {:02x?}", in_aq, cookie);
+            host_object.magic_cookie = Some(cookie.to_vec());
+            // The decoder has to be re-created with the new configuration.
+            host_object.packet_decoder = None;
+        }
+        _ => {
+            log!(
+                "TODO: AudioQueueSetProperty({:?}, {}, {:?}, {})",
+                in_aq,
+                debug_fourcc(in_property_id),
+                in_property_data,
+                in_data_size
+            );
+            return kAudioQueueErr_InvalidProperty;
+        }
+    }
+
+    0 // success
+}
+
 /// Check if the format of an audio queue is one we currently support.
 /// If not, we should skip trying to play it rather than crash.
 fn is_supported_audio_format(format: &AudioStreamBasicDescription) -> bool {
     let &AudioStreamBasicDescription {
+        sample_rate,
         format_id,
         format_flags,
         channels_per_frame,
@@ -439,31 +661,205 @@ fn is_supported_audio_format(format: &AudioStreamBasicDescription) -> bool {
         bytes_per_frame,
         ..
     } = format;
+    if channels_per_frame != 1 && channels_per_frame != 2 {
+        return false;
+    }
     match format_id {
-        kAudioFormatAppleIMA4 => (channels_per_frame == 1) || (channels_per_frame == 2),
+        kAudioFormatAppleIMA4 | kAudioFormatMPEGLayer3 => true,
+        kAudioFormatMPEG4AAC => sample_rate >= 1.0,
         kAudioFormatLinearPCM => {
-            // TODO: support more PCM formats
-            (channels_per_frame == 1 || channels_per_frame == 2)
-                && (bits_per_channel == 8 || bits_per_channel == 16)
-                && ((format_flags & kAudioFormatFlagIsPacked) != 0
-                    || ((bits_per_channel / 8) * channels_per_frame) == bytes_per_frame)
-                && (format_flags & kAudioFormatFlagIsBigEndian) == 0
-                && (format_flags & kAudioFormatFlagIsFloat) == 0
+            let is_float = (format_flags & kAudioFormatFlagIsFloat) != 0;
+            let bits_supported = if is_float {
+                bits_per_channel == 32 || bits_per_channel == 64
+            } else {
+                matches!(bits_per_channel, 8 | 16 | 24 | 32)
+            };
+            // Samples may be stored in larger containers, e.g. 24-bit
+            // samples in 32 bits, if the format isn't packed.
+            let container_size = bytes_per_frame / channels_per_frame;
+            bits_supported
+                && bytes_per_frame % channels_per_frame == 0
+                && (bits_per_channel / 8..=8).contains(&container_size)
+                && (channels_per_frame == 1
+                    || (format_flags & kAudioFormatFlagIsNonInterleaved) == 0)
         }
         _ => false,
     }
 }
 
+/// Convert linear PCM in any supported format to a format OpenAL can play:
+/// 8-bit unsigned or 16-bit signed little-endian integers.
+fn convert_lpcm(format: &AudioStreamBasicDescription, data: &[u8]) -> (ALenum, Vec<u8>) {
+    let &AudioStreamBasicDescription {
+        format_flags,
+        channels_per_frame,
+        bits_per_channel,
+        bytes_per_frame,
+        ..
+    } = format;
+    let is_float = (format_flags & kAudioFormatFlagIsFloat) != 0;
+    let is_big_endian = (format_flags & kAudioFormatFlagIsBigEndian) != 0;
+    let is_signed = (format_flags & kAudioFormatFlagIsSignedInteger) != 0;
+    let is_aligned_high = (format_flags & kAudioFormatFlagIsAlignedHigh) != 0;
+    let bytes_per_sample = (bits_per_channel / 8) as usize;
+    let container_size = (bytes_per_frame / channels_per_frame) as usize;
+
+    // The end of the data might be misaligned (this happens in Crash
+    // Bandicoot Nitro Kart 3D somehow).
+    let frame_size = bytes_per_frame as usize;
+    let data = &data[..data.len() - data.len() % frame_size];
+
+    let mono = channels_per_frame == 1;
+    let (al_format, bytes_per_output_sample) = if bits_per_channel == 8 && !is_float {
+        let al_format = if mono {
+            al::AL_FORMAT_MONO8
+        } else {
+            al::AL_FORMAT_STEREO8
+        };
+        (al_format, 1)
+    } else {
+        let al_format = if mono {
+            al::AL_FORMAT_MONO16
+        } else {
+            al::AL_FORMAT_STEREO16
+        };
+        (al_format, 2)
+    };
+    // OpenAL's 8-bit formats are unsigned and its 16-bit formats are signed.
+    let needs_sign_flip = !is_float && (is_signed == (bytes_per_output_sample == 1));
+    if container_size == bytes_per_output_sample && !is_float && !is_big_endian {
+        let data = if needs_sign_flip {
+            data.chunks_exact(container_size)
+                .flat_map(|sample| {
+                    let mut sample = sample.to_owned();
+                    *sample.last_mut().unwrap() ^= 0x80;
+                    sample
+                })
+                .collect()
+        } else {
+            data.to_owned()
+        };
+        return (al_format, data);
+    }
+
+    let data = data
+        .chunks_exact(container_size)
+        .flat_map(|container| {
+            // Put the bytes in big-endian order and find the ones that
+            // contain the sample, so the most significant byte is first.
+            let mut container = container.to_owned();
+            if !is_big_endian {
+                container.reverse();
+            }
+            let sample = if is_aligned_high {
+                &container[..bytes_per_sample]
+            } else {
+                &container[container_size - bytes_per_sample..]
+            };
+            if is_float {
+                let sample = match sample.len() {
+                    4 => f32::from_be_bytes(sample.try_into().unwrap()).into(),
+                    8 => f64::from_be_bytes(sample.try_into().unwrap()),
+                    _ => unreachable!(),
+                };
+                // This saturates if the sample is out of range.
+                return ((sample * 32768.0) as i16).to_le_bytes().to_vec();
+            }
+            // Only the most significant bytes are kept.
+            let mut msb = sample[0];
+            if needs_sign_flip {
+                msb ^= 0x80;
+            }
+            if bytes_per_output_sample == 1 {
+                vec![msb]
+            } else {
+                vec![sample[1], msb]
+            }
+        })
+        .collect();
+    (al_format, data)
+}
+
+/// Decode a buffer of MP3 or AAC packets to 16-bit PCM samples.
+fn decode_compressed_buffer(
+    host_object: &mut AudioQueueHostObject,
+    buffer_ref: AudioQueueBufferRef,
+    data: &[u8],
+) -> Vec<i16> {
+    let format = host_object.format;
+    let decoder = host_object.packet_decoder.get_or_insert_with(|| {
+        let decoder = if format.format_id == kAudioFormatMPEGLayer3 {
+            Ok(audio::PacketDecoder::new_mp3())
+        } else {
+            audio::PacketDecoder::new_aac(
+                format.sample_rate as u32,
+                format.channels_per_frame,
+                host_object.magic_cookie.as_deref(),
+            )
+        };
+        if decoder.is_err() {
+            log!(
+                "Warning: Could not create decoder for audio queue format {:#?}, magic cookie {:02x?}. Audio will be silent.",
+                format,
+                host_object.magic_cookie
+            );
+        }
+        decoder
+    });
+    let Ok(decoder) = decoder else {
+        return Vec::new();
+    };
+
+    let mut samples = Vec::new();
+    let Some(packet_descs) = host_object.packet_descriptions.get(&buffer_ref) else {
+        // MP3 frames can be found without packet descriptions, but AAC
+        // packets can't.
+        if format.format_id == kAudioFormatMPEGLayer3 {
+            let _ = decoder.decode_packet(data, format.channels_per_frame, &mut samples);
+        } else {
+            log!(
+                "Warning: Buffer {:?} has no packet descriptions, skipping it.",
+                buffer_ref
+            );
+        }
+        return samples;
+    };
+    for packet_desc in packet_descs {
+        let start = packet_desc.start_offset as usize;
+        let Some(packet) = data.get(start..start + packet_desc.data_byte_size as usize) else {
+            log!(
+                "Warning: Invalid packet description {:?} for buffer {:?}, skipping it.",
+                packet_desc,
+                buffer_ref
+            );
+            continue;
+        };
+        if decoder
+            .decode_packet(packet, format.channels_per_frame, &mut samples)
+            .is_err()
+        {
+            log_dbg!(
+                "Could not decode packet {:?} in buffer {:?}, skipping it.",
+                packet_desc,
+                buffer_ref
+            );
+        }
+    }
+    samples
+}
+
 /// Decode an [AudioQueueBuffer]'s content to raw PCM suitable for an OpenAL
 /// buffer.
 fn decode_buffer(
     mem: &Mem,
-    format: &AudioStreamBasicDescription,
-    buffer: &AudioQueueBuffer,
+    host_object: &mut AudioQueueHostObject,
+    buffer_ref: AudioQueueBufferRef,
 ) -> (ALenum, ALsizei, Vec<u8>) {
+    let format = host_object.format;
+    let buffer = mem.read(buffer_ref);
     let data_slice = mem.bytes_at(buffer.audio_data.cast(), buffer.audio_data_byte_size);
 
-    assert!(is_supported_audio_format(format));
+    assert!(is_supported_audio_format(&format));
 
     match format.format_id {
         kAudioFormatAppleIMA4 => {
@@ -501,24 +897,19 @@ fn decode_buffer(
                 )
             }
         }
-        kAudioFormatLinearPCM => {
-            // The end of the data might be misaligned (this happens in Crash
-            // Bandicoot Nitro Kart 3D somehow).
-            let misaligned_by = data_slice.len() % (format.bytes_per_frame as usize);
-            let data_slice = if misaligned_by != 0 {
-                &data_slice[..data_slice.len() - misaligned_by]
+        kAudioFormatMPEGLayer3 | kAudioFormatMPEG4AAC => {
+            let samples = decode_compressed_buffer(host_object, buffer_ref, data_slice);
+            let f = if format.channels_per_frame == 1 {
+                al::AL_FORMAT_MONO16
             } else {
-                data_slice
+                al::AL_FORMAT_STEREO16
             };
-
-            let f = match (format.channels_per_frame, format.bits_per_channel) {
-                (1, 8) => al::AL_FORMAT_MONO8,
-                (1, 16) => al::AL_FORMAT_MONO16,
-                (2, 8) => al::AL_FORMAT_STEREO8,
-                (2, 16) => al::AL_FORMAT_STEREO16,
-                _ => unreachable!(),
-            };
-            (f, format.sample_rate as ALsizei, data_slice.to_owned())
+            let out_pcm = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+            (f, format.sample_rate as ALsizei, out_pcm)
+        }
+        kAudioFormatLinearPCM => {
+            let (f, out_pcm) = convert_lpcm(&format, data_slice);
+            (f, format.sample_rate as ALsizei, out_pcm)
         }
         _ => unreachable!(),
     }
@@ -575,7 +966,6 @@ fn prime_audio_queue(
 
         let next_buffer_idx = al_buffers_queued;
         let next_buffer_ref = host_object.buffer_queue[next_buffer_idx];
-        let next_buffer = env.mem.read(next_buffer_ref);
 
         log_dbg!(
             "Decoding buffer {:?} for queue {:?}",
@@ -590,8 +980,7 @@ fn prime_audio_queue(
             al_buffer
         });
 
-        let (al_format, al_frequency, data) =
-            decode_buffer(&env.mem, &host_object.format, &next_buffer);
+        let (al_format, al_frequency, data) = decode_buffer(&env.mem, host_object, next_buffer_ref);
         unsafe {
             al::alBufferData(
                 next_al_buffer,
@@ -656,6 +1045,7 @@ pub fn handle_audio_queue(env: &mut Environment, in_aq: AudioQueueRef) {
     unqueue_buffers(al_source, |al_buffer| {
         host_object.al_unused_buffers.push(al_buffer);
         let buffer_ref = host_object.buffer_queue.pop_front().unwrap();
+        host_object.packet_descriptions.remove(&buffer_ref);
         buffers_to_reuse.push(buffer_ref);
     });
 
@@ -878,6 +1268,9 @@ fn AudioQueueReset(env: &mut Environment, in_aq: AudioQueueRef) -> OSStatus {
     }
 
     host_object.buffer_queue.clear();
+    host_object.packet_descriptions.clear();
+    // Whatever is enqueued next won't follow on from what was decoded before.
+    host_object.packet_decoder = None;
 
     0 // success
 }
@@ -903,9 +1296,7 @@ fn AudioQueueFreeBuffer(
 
         log_dbg!("Freeing buffer: {:?}", in_buffer);
 
-        let buffer = env.mem.read(in_buffer);
-        env.mem.free(buffer.audio_data);
-        env.mem.free(in_buffer.cast());
+        free_buffer(&mut env.mem, in_buffer);
 
         0 // success
     } else {
@@ -930,9 +1321,7 @@ pub fn AudioQueueDispose(
     env.mem.free(in_aq.cast());
 
     for buffer_ptr in host_object.buffers {
-        let buffer = env.mem.read(buffer_ptr);
-        env.mem.free(buffer.audio_data);
-        env.mem.free(buffer_ptr.cast());
+        free_buffer(&mut env.mem, buffer_ptr);
     }
 
     if let Some(al_source) = host_object.al_source {
@@ -966,11 +1355,13 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(AudioQueueGetParameter(_, _, _)),
     export_c_func!(AudioQueueSetParameter(_, _, _)),
     export_c_func!(AudioQueueAllocateBuffer(_, _, _)),
+    export_c_func!(AudioQueueAllocateBufferWithPacketDescriptions(_, _, _, _)),
     export_c_func!(AudioQueueEnqueueBuffer(_, _, _, _)),
     export_c_func!(AudioQueueAddPropertyListener(_, _, _, _)),
     export_c_func!(AudioQueueRemovePropertyListener(_, _, _, _)),
     export_c_func!(AudioQueueGetPropertySize(_, _, _)),
     export_c_func!(AudioQueueGetProperty(_, _, _, _)),
+    export_c_func!(AudioQueueSetProperty(_, _, _, _)),
     export_c_func!(AudioQueuePrime(_, _, _)),
     export_c_func!(AudioQueueStart(_, _)),
     export_c_func!(AudioQueuePause(_)),
@@ -979,3 +1370,143 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(AudioQueueFreeBuffer(_, _)),
     export_c_func!(AudioQueueDispose(_, _)),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frameworks::core_audio_types::kAudioFormatFlagIsPacked;
+
+    fn lpcm_format(format_flags: u32, channels: u32, bits: u32) -> AudioStreamBasicDescription {
+        AudioStreamBasicDescription {
+            sample_rate: 22050.0,
+            format_id: kAudioFormatLinearPCM,
+            format_flags: format_flags | kAudioFormatFlagIsPacked,
+            bytes_per_packet: channels * bits / 8,
+            frames_per_packet: 1,
+            bytes_per_frame: channels * bits / 8,
+            channels_per_frame: channels,
+            bits_per_channel: bits,
+            _reserved: 0,
+        }
+    }
+
+    #[test]
+    fn lpcm_conversion() {
+        let signed = kAudioFormatFlagIsSignedInteger;
+        let big_endian = kAudioFormatFlagIsBigEndian;
+        let float = kAudioFormatFlagIsFloat;
+
+        // Already playable, except for the misaligned end.
+        let format = lpcm_format(signed, 2, 16);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &[1, 2, 3, 4, 5]),
+            (al::AL_FORMAT_STEREO16, vec![1, 2, 3, 4])
+        );
+
+        let format = lpcm_format(0, 1, 8);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &[0x00, 0x80, 0xff]),
+            (al::AL_FORMAT_MONO8, vec![0x00, 0x80, 0xff])
+        );
+
+        let format = lpcm_format(signed, 1, 8);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &[0x80, 0x00, 0x7f]),
+            (al::AL_FORMAT_MONO8, vec![0x00, 0x80, 0xff])
+        );
+
+        let format = lpcm_format(signed | big_endian, 1, 16);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &[0x12, 0x34, 0xfe, 0xdc]),
+            (al::AL_FORMAT_MONO16, vec![0x34, 0x12, 0xdc, 0xfe])
+        );
+
+        let format = lpcm_format(signed, 1, 24);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &[0x56, 0x34, 0x12]),
+            (al::AL_FORMAT_MONO16, vec![0x34, 0x12])
+        );
+
+        let format = lpcm_format(float, 2, 32);
+        assert!(is_supported_audio_format(&format));
+        let data: Vec<u8> = [0.5f32, -1.0, 2.0, 0.0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let expected: Vec<u8> = [16384i16, -32768, 32767, 0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        assert_eq!(
+            convert_lpcm(&format, &data),
+            (al::AL_FORMAT_STEREO16, expected)
+        );
+
+        let format = lpcm_format(float | big_endian, 1, 64);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &(-0.25f64).to_be_bytes()),
+            (al::AL_FORMAT_MONO16, (-8192i16).to_le_bytes().to_vec())
+        );
+
+        // Unsigned samples have to be made signed.
+        let format = lpcm_format(0, 1, 16);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &[0x00, 0x00, 0xff, 0xff, 0x34, 0x92]),
+            (
+                al::AL_FORMAT_MONO16,
+                vec![0x00, 0x80, 0xff, 0x7f, 0x34, 0x12]
+            )
+        );
+        let format = lpcm_format(big_endian, 1, 24);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &[0x92, 0x34, 0x56]),
+            (al::AL_FORMAT_MONO16, vec![0x34, 0x12])
+        );
+        let format = lpcm_format(0, 1, 32);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &[0x78, 0x56, 0x34, 0x92]),
+            (al::AL_FORMAT_MONO16, vec![0x34, 0x12])
+        );
+
+        // 24-bit samples in 32-bit containers, with a misaligned end that's
+        // longer than a packed frame would be.
+        let unpacked = |format_flags| AudioStreamBasicDescription {
+            format_flags: format_flags | signed,
+            bytes_per_packet: 8,
+            bytes_per_frame: 8,
+            ..lpcm_format(0, 2, 24)
+        };
+        let format = unpacked(0);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(
+                &format,
+                &[0x56, 0x34, 0x12, 0, 0xdc, 0xfe, 0xff, 0, 1, 2, 3, 4]
+            ),
+            (al::AL_FORMAT_STEREO16, vec![0x34, 0x12, 0xfe, 0xff])
+        );
+        let format = unpacked(kAudioFormatFlagIsAlignedHigh);
+        assert!(is_supported_audio_format(&format));
+        assert_eq!(
+            convert_lpcm(&format, &[0, 0x56, 0x34, 0x12, 0, 0xdc, 0xfe, 0xff]),
+            (al::AL_FORMAT_STEREO16, vec![0x34, 0x12, 0xfe, 0xff])
+        );
+
+        assert!(!is_supported_audio_format(&lpcm_format(float, 1, 16)));
+        assert!(!is_supported_audio_format(&lpcm_format(signed, 3, 16)));
+        assert!(!is_supported_audio_format(&lpcm_format(
+            signed | kAudioFormatFlagIsNonInterleaved,
+            2,
+            16
+        )));
+    }
+}
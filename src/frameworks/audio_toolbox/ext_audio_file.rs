@@ -0,0 +1,822 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `ExtendedAudioFile.h` (Extended Audio File Services)
+//!
+//! Like Audio File Services, this is built on [crate::audio::AudioFile]. The
+//! audio is decoded and then converted to the app's "client" format, including
+//! the sample rate and channel count.
+
+use super::audio_file::{description_to_asbd, kAudioFileFileNotFoundError};
+use crate::audio; // Keep this module namespaced to avoid confusion
+use crate::audio::decode_ima4;
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::carbon_core::{paramErr, OSStatus};
+use crate::frameworks::core_audio_types::{
+    debug_fourcc, fourcc, kAudioFormatAppleIMA4, kAudioFormatFlagIsBigEndian,
+    kAudioFormatFlagIsFloat, kAudioFormatFlagIsNonInterleaved, kAudioFormatFlagIsSignedInteger,
+    kAudioFormatLinearPCM, kLinearPCMFormatFlagsSampleFractionMask,
+    kLinearPCMFormatFlagsSampleFractionShift, AudioBuffer, AudioBufferList,
+    AudioStreamBasicDescription,
+};
+use crate::frameworks::core_foundation::cf_url::CFURLRef;
+use crate::frameworks::foundation::ns_url::to_rust_path;
+use crate::fs::{Fs, GuestPathBuf};
+use crate::mem::{guest_size_of, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, SafeRead};
+use crate::save_state::{Reader, SaveState, Writer};
+use crate::Environment;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct State {
+    ext_audio_files: HashMap<ExtAudioFileRef, ExtAudioFileHostObject>,
+}
+impl State {
+    pub fn get(framework_state: &mut crate::frameworks::State) -> &mut Self {
+        &mut framework_state.audio_toolbox.ext_audio_file
+    }
+
+    pub fn save_state(&self, w: &mut Writer) {
+        self.ext_audio_files.len().save(w);
+        for (ext_audio_file, host_object) in &self.ext_audio_files {
+            ext_audio_file.save(w);
+            host_object.path.save(w);
+            host_object.client_format.save(w);
+            host_object.position.save(w);
+        }
+    }
+
+    /// Like audio files, these are re-opened rather than saved, so they must
+    /// still exist.
+    pub fn restore_state(r: &mut Reader, fs: &Fs) -> Result<State, String> {
+        let len = usize::restore(r)?;
+        let mut ext_audio_files = HashMap::new();
+        for _ in 0..len {
+            let ext_audio_file = SaveState::restore(r)?;
+            let path: GuestPathBuf = SaveState::restore(r)?;
+            let mut host_object = ExtAudioFileHostObject::open(path.clone(), fs)
+                .map_err(|()| format!("Couldn't re-open audio file {:?}", path))?;
+            host_object.client_format = SaveState::restore(r)?;
+            host_object.position = SaveState::restore(r)?;
+            ext_audio_files.insert(ext_audio_file, host_object);
+        }
+        Ok(State { ext_audio_files })
+    }
+}
+
+struct ExtAudioFileHostObject {
+    audio_file: audio::AudioFile,
+    /// Absolute path, so the file can be re-opened when loading a save state.
+    path: GuestPathBuf,
+    file_format: AudioStreamBasicDescription,
+    /// Length of the file in frames, at the file's sample rate.
+    file_frame_count: u64,
+    /// The format the app reads the audio in. This is initially the file's
+    /// format, which can only be read if it's linear PCM.
+    client_format: AudioStreamBasicDescription,
+    /// Read position in frames, at the client format's sample rate.
+    position: u64,
+}
+impl ExtAudioFileHostObject {
+    fn open(path: GuestPathBuf, fs: &Fs) -> Result<Self, ()> {
+        let audio_file = audio::AudioFile::open_for_reading(&path, fs)?;
+        let file_format = description_to_asbd(audio_file.audio_description());
+        let file_frame_count = match file_format.format_id {
+            kAudioFormatLinearPCM => {
+                audio_file.byte_count() / u64::from(file_format.bytes_per_frame)
+            }
+            kAudioFormatAppleIMA4 => {
+                audio_file.packet_count() * u64::from(file_format.frames_per_packet)
+            }
+            _ => unreachable!(),
+        };
+        Ok(ExtAudioFileHostObject {
+            audio_file,
+            path,
+            file_format,
+            file_frame_count,
+            client_format: file_format,
+            position: 0,
+        })
+    }
+
+    /// Length of the file in frames, at the client format's sample rate.
+    fn client_frame_count(&self) -> u64 {
+        (self.file_frame_count as f64 * self.client_format.sample_rate
+            / self.file_format.sample_rate)
+            .round() as u64
+    }
+
+    /// Read up to `frame_count` frames from the current position, converted to
+    /// the client format's sample rate and channel count. The result is
+    /// interleaved floating-point samples.
+    fn read(&mut self, frame_count: u64) -> Result<Vec<f32>, ()> {
+        let start = self.position;
+        let end = start
+            .saturating_add(frame_count)
+            .min(self.client_frame_count());
+        if start >= end {
+            return Ok(Vec::new());
+        }
+
+        let ratio = self.file_format.sample_rate / self.client_format.sample_rate;
+        let first_file_frame = (start as f64 * ratio) as u64;
+        // One more frame is needed for interpolating the last one.
+        let end_file_frame = ((((end - 1) as f64) * ratio) as u64 + 2).min(self.file_frame_count);
+        let samples = read_file_frames(
+            &mut self.audio_file,
+            &self.file_format,
+            first_file_frame,
+            end_file_frame.saturating_sub(first_file_frame),
+        )?;
+
+        let channels = self.client_format.channels_per_frame as usize;
+        let samples = convert_channels(
+            &samples,
+            self.file_format.channels_per_frame as usize,
+            channels,
+        );
+        let samples = resample(
+            &samples,
+            channels,
+            start as f64 * ratio - first_file_frame as f64,
+            ratio,
+            (end - start) as usize,
+        );
+        self.position = start + (samples.len() / channels) as u64;
+        Ok(samples)
+    }
+}
+
+/// Read up to `frame_count` frames from the file, starting at `first_frame`,
+/// as interleaved floating-point samples.
+fn read_file_frames(
+    audio_file: &mut audio::AudioFile,
+    format: &AudioStreamBasicDescription,
+    first_frame: u64,
+    frame_count: u64,
+) -> Result<Vec<f32>, ()> {
+    match format.format_id {
+        kAudioFormatLinearPCM => {
+            let bytes_per_frame = u64::from(format.bytes_per_frame);
+            let mut bytes = vec![0u8; (frame_count * bytes_per_frame) as usize];
+            let bytes_read = audio_file.read_bytes(first_frame * bytes_per_frame, &mut bytes)?;
+            bytes.truncate(bytes_read);
+            Ok(lpcm_to_f32(format, &bytes))
+        }
+        kAudioFormatAppleIMA4 => {
+            // Each packet contains 64 frames for one channel, and the packets
+            // for each channel are interleaved.
+            let channels = format.channels_per_frame as usize;
+            let packet_size = u64::from(format.bytes_per_packet);
+            let frames_per_packet = u64::from(format.frames_per_packet);
+            let first_packet = first_frame / frames_per_packet;
+            let skip = (first_frame % frames_per_packet) as usize;
+            let packet_count =
+                (first_frame + frame_count).div_ceil(frames_per_packet) - first_packet;
+
+            let mut bytes = vec![0u8; (packet_count * packet_size) as usize];
+            let bytes_read = audio_file.read_bytes(first_packet * packet_size, &mut bytes)?;
+            bytes.truncate(bytes_read);
+
+            let mut samples = Vec::new();
+            for packet in bytes.chunks_exact(packet_size as usize) {
+                let channel_samples: Vec<[i16; 64]> = packet
+                    .chunks_exact(34)
+                    .map(|channel_packet| decode_ima4(channel_packet.try_into().unwrap()))
+                    .collect();
+                for i in 0..64 {
+                    for channel in &channel_samples {
+                        samples.push(f32::from(channel[i]) / 32768.0);
+                    }
+                }
+            }
+            let samples = samples.get(skip * channels..).unwrap_or(&[]);
+            let len = samples.len().min(frame_count as usize * channels);
+            Ok(samples[..len].to_vec())
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Get the number of fractional bits of a linear PCM integer format.
+fn fraction_bits(format: &AudioStreamBasicDescription) -> u32 {
+    match (format.format_flags & kLinearPCMFormatFlagsSampleFractionMask)
+        >> kLinearPCMFormatFlagsSampleFractionShift
+    {
+        // Not fixed-point, so the range is -1 to 1.
+        0 => format.bits_per_channel - 1,
+        fraction_bits => fraction_bits,
+    }
+}
+
+/// Only 8-bit integer samples are unsigned, and only if they're not flagged as
+/// signed. This matches what [super::audio_file] reports for files.
+fn is_unsigned(format: &AudioStreamBasicDescription) -> bool {
+    format.bits_per_channel == 8 && (format.format_flags & kAudioFormatFlagIsSignedInteger) == 0
+}
+
+/// Convert linear PCM samples to floating-point.
+fn lpcm_to_f32(format: &AudioStreamBasicDescription, data: &[u8]) -> Vec<f32> {
+    let is_float = (format.format_flags & kAudioFormatFlagIsFloat) != 0;
+    let is_big_endian = (format.format_flags & kAudioFormatFlagIsBigEndian) != 0;
+    let is_unsigned = is_unsigned(format);
+    let bytes_per_sample = (format.bits_per_channel / 8) as usize;
+    // Integer samples are shifted to the top of an i32.
+    let scale = 2f64.powi(32 - format.bits_per_channel as i32 + fraction_bits(format) as i32);
+
+    data.chunks_exact(bytes_per_sample)
+        .map(|sample| {
+            if is_float {
+                return match (bytes_per_sample, is_big_endian) {
+                    (4, false) => f32::from_le_bytes(sample.try_into().unwrap()),
+                    (4, true) => f32::from_be_bytes(sample.try_into().unwrap()),
+                    (8, false) => f64::from_le_bytes(sample.try_into().unwrap()) as f32,
+                    (8, true) => f64::from_be_bytes(sample.try_into().unwrap()) as f32,
+                    _ => unreachable!(),
+                };
+            }
+            let mut bytes = [0u8; 4];
+            bytes[..bytes_per_sample].copy_from_slice(sample);
+            if !is_big_endian {
+                bytes[..bytes_per_sample].reverse();
+            }
+            if is_unsigned {
+                bytes[0] ^= 0x80;
+            }
+            (f64::from(i32::from_be_bytes(bytes)) / scale) as f32
+        })
+        .collect()
+}
+
+/// Convert floating-point samples to linear PCM.
+fn f32_to_lpcm(
+    format: &AudioStreamBasicDescription,
+    samples: impl Iterator<Item = f32>,
+) -> Vec<u8> {
+    let is_float = (format.format_flags & kAudioFormatFlagIsFloat) != 0;
+    let is_big_endian = (format.format_flags & kAudioFormatFlagIsBigEndian) != 0;
+    let is_unsigned = is_unsigned(format);
+    let bits = format.bits_per_channel;
+    let bytes_per_sample = (bits / 8) as usize;
+    let scale = 2f64.powi(fraction_bits(format) as i32);
+
+    let mut data = Vec::new();
+    for sample in samples {
+        match (is_float, bytes_per_sample, is_big_endian) {
+            (true, 4, false) => data.extend_from_slice(&sample.to_le_bytes()),
+            (true, 4, true) => data.extend_from_slice(&sample.to_be_bytes()),
+            (true, 8, false) => data.extend_from_slice(&f64::from(sample).to_le_bytes()),
+            (true, 8, true) => data.extend_from_slice(&f64::from(sample).to_be_bytes()),
+            (true, _, _) => unreachable!(),
+            (false, _, _) => {
+                let min = -(1i64 << (bits - 1));
+                let max = (1i64 << (bits - 1)) - 1;
+                let mut value = (f64::from(sample) * scale)
+                    .round()
+                    .clamp(min as f64, max as f64) as i64;
+                if is_unsigned {
+                    value -= min;
+                }
+                let bytes = value.to_le_bytes();
+                let bytes = &bytes[..bytes_per_sample];
+                if is_big_endian {
+                    data.extend(bytes.iter().rev());
+                } else {
+                    data.extend_from_slice(bytes);
+                }
+            }
+        }
+    }
+    data
+}
+
+/// Convert interleaved samples to a different channel count. Mono is copied
+/// to every channel, other formats are mixed down to mono, or have channels
+/// dropped or silent channels added.
+fn convert_channels(samples: &[f32], from: usize, to: usize) -> Vec<f32> {
+    if from == to {
+        return samples.to_vec();
+    }
+    samples
+        .chunks_exact(from)
+        .flat_map(|frame| {
+            (0..to).map(move |channel| {
+                if from == 1 {
+                    frame[0]
+                } else if to == 1 {
+                    frame.iter().sum::<f32>() / from as f32
+                } else {
+                    frame.get(channel).copied().unwrap_or(0.0)
+                }
+            })
+        })
+        .collect()
+}
+
+/// Resample interleaved samples by linear interpolation, which is crude but
+/// good enough for sound effects. The `n`th output frame is at position
+/// `first_position + n * ratio` in the input. Fewer than `frame_count` frames
+/// are returned if the input runs out.
+fn resample(
+    samples: &[f32],
+    channels: usize,
+    first_position: f64,
+    ratio: f64,
+    frame_count: usize,
+) -> Vec<f32> {
+    let available = samples.len() / channels;
+    let mut resampled = Vec::with_capacity(frame_count * channels);
+    for i in 0..frame_count {
+        let position = first_position + i as f64 * ratio;
+        let index = position as usize;
+        if index >= available {
+            break;
+        }
+        let next_index = (index + 1).min(available - 1);
+        let fraction = (position - index as f64) as f32;
+        for channel in 0..channels {
+            let a = samples[index * channels + channel];
+            let b = samples[next_index * channels + channel];
+            resampled.push(a + (b - a) * fraction);
+        }
+    }
+    resampled
+}
+
+/// Check if a client format is one we can convert to.
+fn is_supported_client_format(format: &AudioStreamBasicDescription) -> bool {
+    let &AudioStreamBasicDescription {
+        sample_rate,
+        format_id,
+        format_flags,
+        frames_per_packet,
+        bytes_per_frame,
+        channels_per_frame,
+        bits_per_channel,
+        ..
+    } = format;
+    let is_float = (format_flags & kAudioFormatFlagIsFloat) != 0;
+    let is_non_interleaved = (format_flags & kAudioFormatFlagIsNonInterleaved) != 0;
+    let bits_supported = if is_float {
+        bits_per_channel == 32 || bits_per_channel == 64
+    } else {
+        matches!(bits_per_channel, 8 | 16 | 24 | 32)
+    };
+    // For non-interleaved formats, the frame size is for a single channel.
+    let samples_per_frame = if is_non_interleaved {
+        1
+    } else {
+        channels_per_frame
+    };
+    format_id == kAudioFormatLinearPCM
+        && sample_rate >= 1.0
+        && channels_per_frame != 0
+        && frames_per_packet == 1
+        && bits_supported
+        && bytes_per_frame == (bits_per_channel / 8) * samples_per_frame
+}
+
+#[repr(C, packed)]
+pub struct OpaqueExtAudioFile {
+    _filler: u8,
+}
+unsafe impl SafeRead for OpaqueExtAudioFile {}
+
+pub type ExtAudioFileRef = MutPtr<OpaqueExtAudioFile>;
+
+type ExtAudioFilePropertyID = u32;
+const kExtAudioFileProperty_FileDataFormat: ExtAudioFilePropertyID = fourcc(b"ffmt");
+const kExtAudioFileProperty_ClientDataFormat: ExtAudioFilePropertyID = fourcc(b"cfmt");
+const kExtAudioFileProperty_FileLengthFrames: ExtAudioFilePropertyID = fourcc(b"#frm");
+
+const kExtAudioFileError_InvalidProperty: OSStatus = -66561;
+const kExtAudioFileError_InvalidPropertySize: OSStatus = -66562;
+const kExtAudioFileError_NonPCMClientFormat: OSStatus = -66563;
+const kExtAudioFileError_InvalidDataFormat: OSStatus = -66566;
+const kExtAudioFileError_InvalidSeek: OSStatus = -66568;
+
+fn ExtAudioFileOpenURL(
+    env: &mut Environment,
+    in_url: CFURLRef,
+    out_ext_audio_file: MutPtr<ExtAudioFileRef>,
+) -> OSStatus {
+    return_if_null!(in_url);
+
+    let path = to_rust_path(env, in_url);
+    let path = env.fs.absolute_path(&path);
+    let Ok(host_object) = ExtAudioFileHostObject::open(path, &env.fs) else {
+        log!(
+            "Warning: ExtAudioFileOpenURL() for path {:?} failed",
+            in_url
+        );
+        return kAudioFileFileNotFoundError;
+    };
+
+    let guest_ext_audio_file = env.mem.alloc_and_write(OpaqueExtAudioFile { _filler: 0 });
+    State::get(&mut env.framework_state)
+        .ext_audio_files
+        .insert(guest_ext_audio_file, host_object);
+
+    env.mem.write(out_ext_audio_file, guest_ext_audio_file);
+
+    log_dbg!(
+        "ExtAudioFileOpenURL() opened path {:?}, new extended audio file handle: {:?}",
+        in_url,
+        guest_ext_audio_file
+    );
+
+    0 // success
+}
+
+fn property_size(property_id: ExtAudioFilePropertyID) -> Option<GuestUSize> {
+    match property_id {
+        kExtAudioFileProperty_FileDataFormat | kExtAudioFileProperty_ClientDataFormat => {
+            Some(guest_size_of::<AudioStreamBasicDescription>())
+        }
+        kExtAudioFileProperty_FileLengthFrames => Some(guest_size_of::<i64>()),
+        _ => None,
+    }
+}
+
+fn ExtAudioFileGetPropertyInfo(
+    env: &mut Environment,
+    in_ext_audio_file: ExtAudioFileRef,
+    in_property_id: ExtAudioFilePropertyID,
+    out_size: MutPtr<u32>,
+    out_writable: MutPtr<bool>,
+) -> OSStatus {
+    return_if_null!(in_ext_audio_file);
+
+    let Some(size) = property_size(in_property_id) else {
+        log!(
+            "TODO: ExtAudioFileGetPropertyInfo({:?}, {})",
+            in_ext_audio_file,
+            debug_fourcc(in_property_id)
+        );
+        return kExtAudioFileError_InvalidProperty;
+    };
+    if !out_size.is_null() {
+        env.mem.write(out_size, size);
+    }
+    if !out_writable.is_null() {
+        env.mem.write(
+            out_writable,
+            in_property_id == kExtAudioFileProperty_ClientDataFormat,
+        );
+    }
+    0 // success
+}
+
+fn ExtAudioFileGetProperty(
+    env: &mut Environment,
+    in_ext_audio_file: ExtAudioFileRef,
+    in_property_id: ExtAudioFilePropertyID,
+    io_property_data_size: MutPtr<u32>,
+    out_property_data: MutVoidPtr,
+) -> OSStatus {
+    return_if_null!(in_ext_audio_file);
+
+    let Some(required_size) = property_size(in_property_id) else {
+        log!(
+            "TODO: ExtAudioFileGetProperty({:?}, {})",
+            in_ext_audio_file,
+            debug_fourcc(in_property_id)
+        );
+        return kExtAudioFileError_InvalidProperty;
+    };
+    if env.mem.read(io_property_data_size) != required_size {
+        log!("Warning: ExtAudioFileGetProperty() failed");
+        return kExtAudioFileError_InvalidPropertySize;
+    }
+
+    let host_object = State::get(&mut env.framework_state)
+        .ext_audio_files
+        .get_mut(&in_ext_audio_file)
+        .unwrap();
+
+    match in_property_id {
+        kExtAudioFileProperty_FileDataFormat => {
+            env.mem
+                .write(out_property_data.cast(), host_object.file_format);
+        }
+        kExtAudioFileProperty_ClientDataFormat => {
+            env.mem
+                .write(out_property_data.cast(), host_object.client_format);
+        }
+        kExtAudioFileProperty_FileLengthFrames => {
+            let frame_count: i64 = host_object.file_frame_count.try_into().unwrap();
+            env.mem.write(out_property_data.cast(), frame_count);
+        }
+        _ => unreachable!(),
+    }
+
+    0 // success
+}
+
+fn ExtAudioFileSetProperty(
+    env: &mut Environment,
+    in_ext_audio_file: ExtAudioFileRef,
+    in_property_id: ExtAudioFilePropertyID,
+    in_property_data_size: u32,
+    in_property_data: ConstVoidPtr,
+) -> OSStatus {
+    return_if_null!(in_ext_audio_file);
+
+    if in_property_id != kExtAudioFileProperty_ClientDataFormat {
+        log!(
+            "TODO: ExtAudioFileSetProperty({:?}, {}, {}, {:?})",
+            in_ext_audio_file,
+            debug_fourcc(in_property_id),
+            in_property_data_size,
+            in_property_data
+        );
+        return kExtAudioFileError_InvalidProperty;
+    }
+    if in_property_data_size != guest_size_of::<AudioStreamBasicDescription>() {
+        return kExtAudioFileError_InvalidPropertySize;
+    }
+
+    let client_format: AudioStreamBasicDescription = env.mem.read(in_property_data.cast());
+    log_dbg!(
+        "ExtAudioFileSetProperty({:?}): client format {:#?}",
+        in_ext_audio_file,
+        client_format
+    );
+    if client_format.format_id != kAudioFormatLinearPCM {
+        log!(
+            "Warning: ExtAudioFileSetProperty() with non-PCM client format {:#?}",
+            client_format
+        );
+        return kExtAudioFileError_NonPCMClientFormat;
+    }
+    if !is_supported_client_format(&client_format) {
+        log!(
+            "Warning: ExtAudioFileSetProperty() with unsupported client format {:#?}",
+            client_format
+        );
+        return kExtAudioFileError_InvalidDataFormat;
+    }
+
+    let host_object = State::get(&mut env.framework_state)
+        .ext_audio_files
+        .get_mut(&in_ext_audio_file)
+        .unwrap();
+    // The position is in terms of the client sample rate.
+    host_object.position = (host_object.position as f64 * client_format.sample_rate
+        / host_object.client_format.sample_rate) as u64;
+    host_object.client_format = client_format;
+
+    0 // success
+}
+
+fn ExtAudioFileRead(
+    env: &mut Environment,
+    in_ext_audio_file: ExtAudioFileRef,
+    io_number_frames: MutPtr<u32>,
+    io_data: MutPtr<AudioBufferList>,
+) -> OSStatus {
+    return_if_null!(in_ext_audio_file);
+
+    let host_object = State::get(&mut env.framework_state)
+        .ext_audio_files
+        .get_mut(&in_ext_audio_file)
+        .unwrap();
+
+    let client_format = host_object.client_format;
+    if client_format.format_id != kAudioFormatLinearPCM {
+        return kExtAudioFileError_NonPCMClientFormat;
+    }
+    if !is_supported_client_format(&client_format) {
+        return kExtAudioFileError_InvalidDataFormat;
+    }
+
+    let channels = client_format.channels_per_frame as usize;
+    let is_non_interleaved = (client_format.format_flags & kAudioFormatFlagIsNonInterleaved) != 0;
+    let buffer_count = if is_non_interleaved { channels } else { 1 };
+
+    let AudioBufferList { number_buffers, .. } = env.mem.read(io_data);
+    if number_buffers as usize != buffer_count {
+        log!(
+            "Warning: ExtAudioFileRead() got {} buffers, expected {}",
+            number_buffers,
+            buffer_count
+        );
+        return paramErr;
+    }
+    let buffers_ptr: MutPtr<AudioBuffer> = (io_data.cast::<u32>() + 1).cast();
+    let buffers: Vec<AudioBuffer> = (0..number_buffers)
+        .map(|i| env.mem.read(buffers_ptr + i))
+        .collect();
+
+    let bytes_per_frame = client_format.bytes_per_frame;
+    let frames_to_read = buffers
+        .iter()
+        .map(|buffer| buffer.data_byte_size / bytes_per_frame)
+        .fold(env.mem.read(io_number_frames), u32::min);
+
+    let Ok(samples) = host_object.read(frames_to_read.into()) else {
+        log!(
+            "Warning: ExtAudioFileRead() failed to read from {:?}",
+            host_object.path
+        );
+        env.mem.write(io_number_frames, 0);
+        return kExtAudioFileError_InvalidDataFormat;
+    };
+    let frames_read = (samples.len() / channels) as u32;
+
+    for (i, mut buffer) in buffers.into_iter().enumerate() {
+        let data = if is_non_interleaved {
+            f32_to_lpcm(
+                &client_format,
+                samples.iter().copied().skip(i).step_by(channels),
+            )
+        } else {
+            f32_to_lpcm(&client_format, samples.iter().copied())
+        };
+        let size: GuestUSize = data.len().try_into().unwrap();
+        env.mem
+            .bytes_at_mut(buffer.data.cast(), size)
+            .copy_from_slice(&data);
+        buffer.data_byte_size = size;
+        env.mem.write(buffers_ptr + i as GuestUSize, buffer);
+    }
+    env.mem.write(io_number_frames, frames_read);
+
+    0 // success
+}
+
+fn ExtAudioFileSeek(
+    env: &mut Environment,
+    in_ext_audio_file: ExtAudioFileRef,
+    in_frame_offset: i64,
+) -> OSStatus {
+    return_if_null!(in_ext_audio_file);
+
+    let host_object = State::get(&mut env.framework_state)
+        .ext_audio_files
+        .get_mut(&in_ext_audio_file)
+        .unwrap();
+
+    match u64::try_from(in_frame_offset) {
+        Ok(position) if position <= host_object.client_frame_count() => {
+            host_object.position = position;
+            0 // success
+        }
+        _ => kExtAudioFileError_InvalidSeek,
+    }
+}
+
+fn ExtAudioFileTell(
+    env: &mut Environment,
+    in_ext_audio_file: ExtAudioFileRef,
+    out_frame_offset: MutPtr<i64>,
+) -> OSStatus {
+    return_if_null!(in_ext_audio_file);
+
+    let host_object = State::get(&mut env.framework_state)
+        .ext_audio_files
+        .get_mut(&in_ext_audio_file)
+        .unwrap();
+
+    let position: i64 = host_object.position.try_into().unwrap();
+    env.mem.write(out_frame_offset, position);
+    0 // success
+}
+
+fn ExtAudioFileDispose(env: &mut Environment, in_ext_audio_file: ExtAudioFileRef) -> OSStatus {
+    return_if_null!(in_ext_audio_file);
+
+    let _host_object = State::get(&mut env.framework_state)
+        .ext_audio_files
+        .remove(&in_ext_audio_file)
+        .unwrap();
+    env.mem.free(in_ext_audio_file.cast());
+    log_dbg!(
+        "ExtAudioFileDispose() destroyed extended audio file handle: {:?}",
+        in_ext_audio_file
+    );
+    0 // success
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(ExtAudioFileOpenURL(_, _)),
+    export_c_func!(ExtAudioFileGetPropertyInfo(_, _, _, _)),
+    export_c_func!(ExtAudioFileGetProperty(_, _, _, _)),
+    export_c_func!(ExtAudioFileSetProperty(_, _, _, _)),
+    export_c_func!(ExtAudioFileRead(_, _, _)),
+    export_c_func!(ExtAudioFileSeek(_, _)),
+    export_c_func!(ExtAudioFileTell(_, _)),
+    export_c_func!(ExtAudioFileDispose(_)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frameworks::core_audio_types::kAudioFormatFlagIsPacked;
+
+    fn lpcm_format(format_flags: u32, channels: u32, bits: u32) -> AudioStreamBasicDescription {
+        let bytes_per_frame = if (format_flags & kAudioFormatFlagIsNonInterleaved) != 0 {
+            bits / 8
+        } else {
+            channels * bits / 8
+        };
+        AudioStreamBasicDescription {
+            sample_rate: 44100.0,
+            format_id: kAudioFormatLinearPCM,
+            format_flags: format_flags | kAudioFormatFlagIsPacked,
+            bytes_per_packet: bytes_per_frame,
+            frames_per_packet: 1,
+            bytes_per_frame,
+            channels_per_frame: channels,
+            bits_per_channel: bits,
+            _reserved: 0,
+        }
+    }
+
+    #[test]
+    fn lpcm_round_trip() {
+        let signed = kAudioFormatFlagIsSignedInteger;
+        let samples = [0.0, 0.5, -0.5, -1.0];
+        for format in [
+            lpcm_format(signed, 1, 16),
+            lpcm_format(signed | kAudioFormatFlagIsBigEndian, 1, 16),
+            lpcm_format(signed, 1, 24),
+            lpcm_format(signed, 1, 32),
+            lpcm_format(0, 1, 8),
+            lpcm_format(signed, 1, 8),
+            lpcm_format(kAudioFormatFlagIsFloat, 1, 32),
+            lpcm_format(kAudioFormatFlagIsFloat | kAudioFormatFlagIsBigEndian, 1, 64),
+            // The "8.24" fixed-point format used by audio units.
+            lpcm_format(
+                signed | (24 << kLinearPCMFormatFlagsSampleFractionShift),
+                1,
+                32,
+            ),
+        ] {
+            assert!(is_supported_client_format(&format));
+            let data = f32_to_lpcm(&format, samples.iter().copied());
+            assert_eq!(
+                data.len(),
+                samples.len() * (format.bits_per_channel / 8) as usize
+            );
+            assert_eq!(lpcm_to_f32(&format, &data), samples, "{:?}", format);
+        }
+
+        let format = lpcm_format(signed, 1, 16);
+        assert_eq!(
+            f32_to_lpcm(&format, [1.0, 2.0, -2.0].into_iter()),
+            [0xff, 0x7f, 0xff, 0x7f, 0x00, 0x80]
+        );
+        let format = lpcm_format(0, 1, 8);
+        assert_eq!(lpcm_to_f32(&format, &[0x80, 0xc0]), [0.0, 0.5]);
+        let format = lpcm_format(
+            signed | (24 << kLinearPCMFormatFlagsSampleFractionShift),
+            1,
+            32,
+        );
+        assert_eq!(
+            f32_to_lpcm(&format, [1.0].into_iter()),
+            (1i32 << 24).to_le_bytes()
+        );
+
+        assert!(is_supported_client_format(&lpcm_format(
+            signed | kAudioFormatFlagIsNonInterleaved,
+            2,
+            16
+        )));
+        assert!(!is_supported_client_format(&lpcm_format(
+            kAudioFormatFlagIsFloat,
+            2,
+            16
+        )));
+    }
+
+    #[test]
+    fn channel_conversion() {
+        assert_eq!(convert_channels(&[0.5, -0.5], 1, 2), [0.5, 0.5, -0.5, -0.5]);
+        assert_eq!(
+            convert_channels(&[0.5, -0.25, 1.0, 0.0], 2, 1),
+            [0.125, 0.5]
+        );
+        assert_eq!(convert_channels(&[0.5, -0.25], 2, 2), [0.5, -0.25]);
+    }
+
+    #[test]
+    fn resampling() {
+        let samples = [0.0, 1.0, 0.0, -1.0];
+        // Same rate
+        assert_eq!(resample(&samples, 1, 0.0, 1.0, 4), samples);
+        // Upsampling
+        assert_eq!(
+            resample(&samples, 1, 0.0, 0.5, 8),
+            [0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]
+        );
+        // Downsampling, starting part-way through
+        assert_eq!(resample(&samples, 1, 1.0, 2.0, 4), [1.0, -1.0]);
+        // Stereo
+        assert_eq!(resample(&[0.0, 1.0, 1.0, 0.0], 2, 0.5, 1.0, 1), [0.5, 0.5]);
+    }
+}
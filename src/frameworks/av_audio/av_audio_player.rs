@@ -7,7 +7,7 @@
 //!
 //! Implemented using Audio Queue Services based on [the PlayingAudio example](https://developer.apple.com/library/archive/documentation/MusicAudio/Conceptual/AudioQueueProgrammingGuide/AQPlayback/PlayingAudio.html)
 
-use crate::dyld::HostFunction;
+use crate::dyld::{export_c_func, FunctionExports, HostFunction};
 use crate::frameworks::audio_toolbox::audio_file::{
     kAudioFilePropertyDataFormat, kAudioFilePropertyPacketSizeUpperBound, kAudioFileReadPermission,
     AudioFileClose, AudioFileGetProperty, AudioFileID, AudioFileOpenURL, AudioFileReadPackets,
@@ -23,8 +23,12 @@ use crate::frameworks::core_foundation::cf_run_loop::kCFRunLoopCommonModes;
 use crate::frameworks::foundation::{ns_string, NSInteger};
 use crate::mem::{guest_size_of, GuestUSize, MutPtr, MutVoidPtr, Ptr};
 use crate::msg;
-use crate::objc::{id, nil, release, retain, Class, ClassExports, HostObject, NSZonePtr};
+use crate::objc::{id, nil, release, retain, Class, ClassExports, NSZonePtr};
 use crate::objc_classes;
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 use crate::Environment;
 
 const kNumberBuffers: usize = 3;
@@ -42,7 +46,23 @@ struct AVAudioPlayerHostObject {
     is_playing: bool,
     num_of_loops: NSInteger,
 }
-impl HostObject for AVAudioPlayerHostObject {}
+impl_HostObject_with_SaveState!(AVAudioPlayerHostObject);
+impl_SaveState_for_struct!(AVAudioPlayerHostObject {
+    audio_file_url,
+    output_callback,
+    audio_file_id,
+    audio_desc,
+    audio_queue,
+    audio_queue_buffers,
+    num_packets_to_read,
+    current_packet,
+    volume,
+    is_playing,
+    num_of_loops
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(AVAudioPlayerHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -363,3 +383,8 @@ fn _touchHLE_AVAudioPlayerOutputBufferHelper(
         }
     }
 }
+
+/// See [crate::dyld::function_lists::PRIVATE_FUNCTION_LISTS].
+pub const PRIVATE_FUNCTIONS: FunctionExports = &[export_c_func!(
+    _touchHLE_AVAudioPlayerOutputBufferHelper(_, _, _)
+)];
@@ -18,7 +18,11 @@ use crate::frameworks::core_graphics::cg_image::{
 };
 use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
 use crate::mem::{GuestUSize, Ptr};
-use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, ObjC};
+use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports, ObjC};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, HostObjectRestorers, Reader, SaveState,
+    Writer,
+};
 use std::collections::HashMap;
 
 pub(super) struct CALayerHostObject {
@@ -49,7 +53,52 @@ pub(super) struct CALayerHostObject {
     /// Internal state for compositor
     pub(super) gles_texture_is_up_to_date: bool,
 }
-impl HostObject for CALayerHostObject {}
+impl_HostObject_with_SaveState!(CALayerHostObject);
+/// The compositor's texture isn't saved, since it belongs to the window's
+/// OpenGL ES context rather than the app's, and it can be re-created from the
+/// other state.
+impl SaveState for CALayerHostObject {
+    fn save(&self, w: &mut Writer) {
+        self.delegate.save(w);
+        self.sublayers.save(w);
+        self.superlayer.save(w);
+        self.bounds.save(w);
+        self.position.save(w);
+        self.anchor_point.save(w);
+        self.hidden.save(w);
+        self.opaque.save(w);
+        self.opacity.save(w);
+        self.background_color.save(w);
+        self.needs_display.save(w);
+        self.contents.save(w);
+        self.drawable_properties.save(w);
+        self.presented_pixels.save(w);
+        self.cg_context.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(CALayerHostObject {
+            delegate: SaveState::restore(r)?,
+            sublayers: SaveState::restore(r)?,
+            superlayer: SaveState::restore(r)?,
+            bounds: SaveState::restore(r)?,
+            position: SaveState::restore(r)?,
+            anchor_point: SaveState::restore(r)?,
+            hidden: SaveState::restore(r)?,
+            opaque: SaveState::restore(r)?,
+            opacity: SaveState::restore(r)?,
+            background_color: SaveState::restore(r)?,
+            needs_display: SaveState::restore(r)?,
+            contents: SaveState::restore(r)?,
+            drawable_properties: SaveState::restore(r)?,
+            presented_pixels: SaveState::restore(r)?,
+            cg_context: SaveState::restore(r)?,
+            gles_texture: None,
+            gles_texture_is_up_to_date: false,
+        })
+    }
+}
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(CALayerHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
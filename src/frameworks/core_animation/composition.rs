@@ -14,6 +14,7 @@ use super::ca_layer::CALayerHostObject;
 use crate::frameworks::core_graphics::{
     cg_bitmap_context, cg_image, CGFloat, CGPoint, CGRect, CGSize,
 };
+use crate::frameworks::media_player::movie_player;
 use crate::frameworks::uikit::ui_color;
 use crate::gles::gles11_raw as gles11; // constants only
 use crate::gles::gles11_raw::types::*;
@@ -37,26 +38,33 @@ pub(super) struct State {
 ///
 /// Returns the time a recomposite is due, if any.
 pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
+    // A movie that is playing covers all windows.
+    let movie_background_color = movie_player::fullscreen_background_color(env);
+
     // Assumes the last window in the list is the one on top.
     // TODO: this is not correct once we support zPosition.
     // TODO: can there be windows smaller than the screen? If so we need to draw
     //       all of them.
-    let Some(&top_window) = env
+    let top_window = env
         .framework_state
         .uikit
         .ui_view
         .ui_window
         .visible_windows
         .last()
-    else {
-        log_dbg!("No visible window, skipping composition");
-        return None;
-    };
+        .copied();
+
+    if movie_background_color.is_none() {
+        if top_window.is_none() {
+            log_dbg!("No visible window, skipping composition");
+            return None;
+        }
 
-    if find_fullscreen_eagl_layer(env) != nil {
-        // No composition done, EAGLContext will present directly.
-        log_dbg!("Using CAEAGLLayer fast path, skipping composition");
-        return None;
+        if find_fullscreen_eagl_layer(env) != nil {
+            // No composition done, EAGLContext will present directly.
+            log_dbg!("Using CAEAGLLayer fast path, skipping composition");
+            return None;
+        }
     }
 
     if env.options.print_fps {
@@ -68,7 +76,7 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
             .count_frame(format_args!("Core Animation compositor"));
     }
 
-    let now = Instant::now();
+    let now = env.clock.now();
     let interval = 1.0 / 60.0; // 60Hz
     let new_recomposite_next = if let Some(recomposite_next) = env
         .framework_state
@@ -103,10 +111,15 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
         .composition
         .recomposite_next = new_recomposite_next;
 
-    let root_layer: id = msg![env; top_window layer];
+    let root_layer: Option<id> = match (movie_background_color, top_window) {
+        (None, Some(top_window)) => Some(msg![env; top_window layer]),
+        _ => None,
+    };
 
     // Ensure layer bitmaps are up to date.
-    display_layers(env, root_layer);
+    if let Some(root_layer) = root_layer {
+        display_layers(env, root_layer);
+    }
 
     let screen_bounds: CGRect = {
         let screen: id = msg_class![env; UIScreen mainScreen];
@@ -120,6 +133,7 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
         env.window().rotation_matrix(),
         env.window().virtual_cursor_visible_at(),
     );
+    let uptime = env.clock.uptime();
 
     // TODO: draw status bar if it's not hidden
 
@@ -209,18 +223,26 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
     }
 
     // Here's where the actual drawing happens
-    unsafe {
-        composite_layer_recursive(
-            gles,
-            &mut env.objc,
-            &env.mem,
-            root_layer,
-            origin,
-            clip_to,
-            opacity,
-            scale_hack,
-            fb_height,
-        );
+    if let Some(root_layer) = root_layer {
+        unsafe {
+            composite_layer_recursive(
+                gles,
+                &mut env.objc,
+                &env.mem,
+                root_layer,
+                origin,
+                clip_to,
+                opacity,
+                scale_hack,
+                fb_height,
+            );
+        }
+    } else if let Some((r, g, b, a)) = movie_background_color {
+        // TODO: draw the movie's frames once video decoding is supported.
+        unsafe {
+            gles.ClearColor(r, g, b, a);
+            gles.Clear(gles11::COLOR_BUFFER_BIT);
+        }
     }
 
     // Clean up some GL state
@@ -243,8 +265,10 @@ pub fn recomposite_if_necessary(env: &mut Environment) -> Option<Instant> {
             present_frame_args.1,
             present_frame_args.2,
         );
+        window.dump_internal_frame(Some(uptime));
+        window.app_frame_presented(None);
     }
-    env.window().swap_window();
+    window.swap_window();
 
     new_recomposite_next
 }
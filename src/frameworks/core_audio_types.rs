@@ -5,7 +5,8 @@
  */
 //! The Core Audio Types framework. (Yes, it's not part of Core Audio?)
 
-use crate::mem::SafeRead;
+use crate::mem::{MutVoidPtr, SafeRead};
+use crate::save_state::impl_SaveState_for_struct;
 
 // The audio frameworks love FourCC's, and we currently don't need these
 // anywhere else, so this is as good a place to put this as any.
@@ -38,6 +39,17 @@ pub struct AudioStreamBasicDescription {
     pub _reserved: u32,
 }
 unsafe impl SafeRead for AudioStreamBasicDescription {}
+impl_SaveState_for_struct!(packed AudioStreamBasicDescription {
+    sample_rate,
+    format_id,
+    format_flags,
+    bytes_per_packet,
+    frames_per_packet,
+    bytes_per_frame,
+    channels_per_frame,
+    bits_per_channel,
+    _reserved
+});
 impl std::fmt::Debug for AudioStreamBasicDescription {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let &AudioStreamBasicDescription {
@@ -68,6 +80,9 @@ impl std::fmt::Debug for AudioStreamBasicDescription {
                 if (format_flags & kAudioFormatFlagIsPacked) != 0 {
                     flags.push("kAudioFormatFlagIsPacked");
                 }
+                if (format_flags & kAudioFormatFlagIsAlignedHigh) != 0 {
+                    flags.push("kAudioFormatFlagIsAlignedHigh");
+                }
                 flags
             })
             .field("bytes_per_packet", &bytes_per_packet)
@@ -83,9 +98,54 @@ impl std::fmt::Debug for AudioStreamBasicDescription {
 pub type AudioFormatID = u32;
 pub const kAudioFormatLinearPCM: AudioFormatID = fourcc(b"lpcm");
 pub const kAudioFormatAppleIMA4: AudioFormatID = fourcc(b"ima4");
+pub const kAudioFormatMPEG4AAC: AudioFormatID = fourcc(b"aac ");
+pub const kAudioFormatMPEGLayer3: AudioFormatID = fourcc(b".mp3");
 
 pub type AudioFormatFlags = u32;
 pub const kAudioFormatFlagIsFloat: AudioFormatFlags = 1 << 0;
 pub const kAudioFormatFlagIsBigEndian: AudioFormatFlags = 1 << 1;
 pub const kAudioFormatFlagIsSignedInteger: AudioFormatFlags = 1 << 2;
 pub const kAudioFormatFlagIsPacked: AudioFormatFlags = 1 << 3;
+pub const kAudioFormatFlagIsAlignedHigh: AudioFormatFlags = 1 << 4;
+pub const kAudioFormatFlagIsNonInterleaved: AudioFormatFlags = 1 << 5;
+/// Mask for the number of fractional bits of fixed-point linear PCM, e.g. 24
+/// for the "8.24" format audio units use.
+pub const kLinearPCMFormatFlagsSampleFractionMask: AudioFormatFlags = 0x3f << 7;
+pub const kLinearPCMFormatFlagsSampleFractionShift: AudioFormatFlags = 7;
+
+/// Describes one packet in a buffer of packets of a variable-size format
+/// (e.g. AAC or MP3).
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct AudioStreamPacketDescription {
+    /// Offset of the packet in bytes from the start of the buffer.
+    pub start_offset: i64,
+    /// Zero if the number of frames per packet is constant.
+    pub variable_frames_in_packet: u32,
+    pub data_byte_size: u32,
+}
+unsafe impl SafeRead for AudioStreamPacketDescription {}
+impl_SaveState_for_struct!(packed AudioStreamPacketDescription {
+    start_offset,
+    variable_frames_in_packet,
+    data_byte_size
+});
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct AudioBuffer {
+    pub number_channels: u32,
+    pub data_byte_size: u32,
+    pub data: MutVoidPtr,
+}
+unsafe impl SafeRead for AudioBuffer {}
+
+/// This is a variable-size struct: there are `number_buffers` buffers, not
+/// just one.
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct AudioBufferList {
+    pub number_buffers: u32,
+    pub buffers: [AudioBuffer; 1],
+}
+unsafe impl SafeRead for AudioBufferList {}
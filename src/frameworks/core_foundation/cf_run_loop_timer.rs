@@ -15,8 +15,10 @@ use crate::frameworks::core_foundation::cf_run_loop::{CFRunLoopMode, CFRunLoopRe
 use crate::frameworks::core_foundation::time::{CFAbsoluteTime, CFTimeInterval};
 use crate::frameworks::core_foundation::CFIndex;
 use crate::mem::{MutPtr, MutVoidPtr, SafeRead};
-use crate::objc::{
-    id, msg, msg_class, nil, objc_classes, Class, ClassExports, HostObject, NSZonePtr,
+use crate::objc::{id, msg, msg_class, nil, objc_classes, Class, ClassExports, NSZonePtr};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 
@@ -106,7 +108,11 @@ struct CFTimerTargetHostObject {
     callout: GuestFunction,
     info: MutVoidPtr,
 }
-impl HostObject for CFTimerTargetHostObject {}
+impl_HostObject_with_SaveState!(CFTimerTargetHostObject);
+impl_SaveState_for_struct!(CFTimerTargetHostObject { callout, info });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(CFTimerTargetHostObject)];
 
 /// _touchHLE_CFTimerTarget serves as a convenience
 /// object for performing a callout from a timer.
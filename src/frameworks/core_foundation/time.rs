@@ -38,8 +38,9 @@ impl_GuestRet_for_large_struct!(CFGregorianDate);
 
 /// Absolute time is measured in seconds relative to the absolute reference date
 /// of Jan 1 2001 00:00:00 GMT.
-fn CFAbsoluteTimeGetCurrent(_env: &mut Environment) -> CFAbsoluteTime {
-    SystemTime::now()
+fn CFAbsoluteTimeGetCurrent(env: &mut Environment) -> CFAbsoluteTime {
+    env.clock
+        .system_time()
         .duration_since(apple_epoch())
         .unwrap()
         .as_secs_f64()
@@ -10,6 +10,7 @@ use crate::abi::{impl_GuestRet_for_large_struct, GuestArg};
 use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
 use crate::matrix::Matrix;
 use crate::mem::SafeRead;
+use crate::save_state::impl_SaveState_for_struct;
 use crate::Environment;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -25,6 +26,7 @@ pub struct CGAffineTransform {
     pub ty: CGFloat,
 }
 unsafe impl SafeRead for CGAffineTransform {}
+impl_SaveState_for_struct!(packed CGAffineTransform { a, b, c, d, tx, ty });
 impl GuestArg for CGAffineTransform {
     const REG_COUNT: usize = 6;
 
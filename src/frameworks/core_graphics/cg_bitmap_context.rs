@@ -21,6 +21,7 @@ use crate::dyld::{export_c_func, FunctionExports};
 use crate::image::{gamma_decode, gamma_encode, Image};
 use crate::mem::{GuestUSize, Mem, MutVoidPtr};
 use crate::objc::ObjC;
+use crate::save_state::impl_SaveState_for_struct;
 use crate::Environment;
 
 #[derive(Copy, Clone)]
@@ -34,6 +35,16 @@ pub(super) struct CGBitmapContextData {
     color_space: &'static str,
     alpha_info: CGImageAlphaInfo,
 }
+impl_SaveState_for_struct!(CGBitmapContextData {
+    data,
+    data_is_owned,
+    width,
+    height,
+    bits_per_component,
+    bytes_per_row,
+    color_space,
+    alpha_info
+});
 
 pub fn CGBitmapContextCreate(
     env: &mut Environment,
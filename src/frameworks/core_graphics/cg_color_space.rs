@@ -9,7 +9,11 @@ use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant}
 use crate::frameworks::core_foundation::cf_string::CFStringRef;
 use crate::frameworks::core_foundation::{CFRelease, CFRetain, CFTypeRef};
 use crate::frameworks::foundation::ns_string;
-use crate::objc::{msg, objc_classes, ClassExports, HostObject};
+use crate::objc::{msg, objc_classes, ClassExports};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 use crate::Environment;
 
 pub const CLASSES: ClassExports = objc_classes! {
@@ -43,7 +47,11 @@ pub const kCGColorSpaceModelPattern: CGColorSpaceModel = 6;
 pub(super) struct CGColorSpaceHostObject {
     pub(super) name: &'static str,
 }
-impl HostObject for CGColorSpaceHostObject {}
+impl_HostObject_with_SaveState!(CGColorSpaceHostObject);
+impl_SaveState_for_struct!(CGColorSpaceHostObject { name });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(CGColorSpaceHostObject)];
 
 pub type CGColorSpaceRef = CFTypeRef;
 
@@ -10,7 +10,11 @@ use super::cg_image::CGImageRef;
 use super::{cg_bitmap_context, CGFloat, CGRect};
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::frameworks::core_foundation::{CFRelease, CFRetain, CFTypeRef};
-use crate::objc::{objc_classes, ClassExports, HostObject};
+use crate::objc::{objc_classes, ClassExports};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_enum,
+    impl_SaveState_for_struct, HostObjectRestorers,
+};
 use crate::Environment;
 
 pub const CLASSES: ClassExports = objc_classes! {
@@ -42,7 +46,17 @@ pub(super) struct CGContextHostObject {
     /// Current transform.
     pub(super) transform: CGAffineTransform,
 }
-impl HostObject for CGContextHostObject {}
+impl_HostObject_with_SaveState!(CGContextHostObject);
+impl_SaveState_for_struct!(CGContextHostObject {
+    subclass,
+    rgb_fill_color,
+    transform
+});
+
+impl_SaveState_for_enum!(CGContextSubclass { CGBitmapContext(data) });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(CGContextHostObject)];
 
 pub(super) enum CGContextSubclass {
     CGBitmapContext(cg_bitmap_context::CGBitmapContextData),
@@ -14,7 +14,11 @@ use crate::frameworks::core_foundation::cf_data::{CFDataCreate, CFDataRef};
 use crate::frameworks::core_foundation::{CFRelease, CFRetain, CFTypeRef};
 use crate::frameworks::foundation::NSUInteger;
 use crate::mem::{ConstVoidPtr, GuestUSize, MutVoidPtr};
-use crate::objc::{id, msg, msg_class, objc_classes, ClassExports, HostObject};
+use crate::objc::{id, msg, msg_class, objc_classes, ClassExports};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_enum,
+    HostObjectRestorers,
+};
 use crate::Environment;
 
 pub type CGDataProviderRef = CFTypeRef;
@@ -38,7 +42,19 @@ enum CGDataProviderHostObject {
     // need a special variant for this.
     CGImage(CGImageRef),
 }
-impl HostObject for CGDataProviderHostObject {}
+impl_HostObject_with_SaveState!(CGDataProviderHostObject);
+impl_SaveState_for_enum!(CGDataProviderHostObject {
+    DataWithSize {
+        data,
+        size,
+        info,
+        release_callback
+    },
+    CGImage(image),
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(CGDataProviderHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -11,6 +11,7 @@ use super::CGFloat;
 use crate::abi::{impl_GuestRet_for_large_struct, GuestArg};
 use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
 use crate::mem::SafeRead;
+use crate::save_state::impl_SaveState_for_struct;
 use crate::Environment;
 
 fn parse_tuple(s: &str) -> Result<(f32, f32), ()> {
@@ -25,6 +26,7 @@ pub struct CGPoint {
     pub y: CGFloat,
 }
 unsafe impl SafeRead for CGPoint {}
+impl_SaveState_for_struct!(packed CGPoint { x, y });
 impl_GuestRet_for_large_struct!(CGPoint);
 impl GuestArg for CGPoint {
     const REG_COUNT: usize = 2;
@@ -68,6 +70,7 @@ pub struct CGSize {
     pub height: CGFloat,
 }
 unsafe impl SafeRead for CGSize {}
+impl_SaveState_for_struct!(packed CGSize { width, height });
 impl_GuestRet_for_large_struct!(CGSize);
 impl GuestArg for CGSize {
     const REG_COUNT: usize = 2;
@@ -117,6 +120,7 @@ pub struct CGRect {
     pub size: CGSize,
 }
 unsafe impl SafeRead for CGRect {}
+impl_SaveState_for_struct!(packed CGRect { origin, size });
 impl_GuestRet_for_large_struct!(CGRect);
 impl GuestArg for CGRect {
     const REG_COUNT: usize = 4;
@@ -13,7 +13,11 @@ use crate::frameworks::core_foundation::{CFRelease, CFRetain, CFTypeRef};
 use crate::frameworks::foundation::ns_string;
 use crate::image::Image;
 use crate::mem::{ConstPtr, GuestUSize};
-use crate::objc::{autorelease, nil, objc_classes, ClassExports, HostObject, ObjC};
+use crate::objc::{autorelease, nil, objc_classes, ClassExports, ObjC};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 use crate::Environment;
 
 pub type CGImageAlphaInfo = u32;
@@ -57,7 +61,10 @@ pub const CLASSES: ClassExports = objc_classes! {
 struct CGImageHostObject {
     image: Image,
 }
-impl HostObject for CGImageHostObject {}
+impl_HostObject_with_SaveState!(CGImageHostObject);
+impl_SaveState_for_struct!(CGImageHostObject { image });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(CGImageHostObject)];
 
 pub type CGImageRef = CFTypeRef;
 pub fn CGImageRelease(env: &mut Environment, c: CGImageRef) {
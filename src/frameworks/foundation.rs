@@ -11,6 +11,8 @@
 //! Being aware of this concept will make common types like `NSArray` and
 //! `NSString` easier to understand.
 
+use crate::save_state::impl_SaveState_for_struct;
+
 pub mod ns_array;
 pub mod ns_autorelease_pool;
 pub mod ns_bundle;
@@ -24,6 +26,7 @@ pub mod ns_enumerator;
 pub mod ns_error;
 pub mod ns_exception;
 pub mod ns_file_manager;
+pub mod ns_keyed_archiver;
 pub mod ns_keyed_unarchiver;
 pub mod ns_locale;
 pub mod ns_lock;
@@ -56,6 +59,17 @@ pub struct State {
     ns_string: ns_string::State,
     ns_user_defaults: ns_user_defaults::State,
 }
+impl_SaveState_for_struct!(State {
+    ns_autorelease_pool,
+    ns_bundle,
+    ns_file_manager,
+    ns_locale,
+    ns_notification_center,
+    ns_null,
+    ns_run_loop,
+    ns_string,
+    ns_user_defaults
+});
 
 pub type NSInteger = i32;
 pub type NSUInteger = u32;
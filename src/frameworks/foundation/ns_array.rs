@@ -6,26 +6,37 @@
 //! The `NSArray` class cluster, including `NSMutableArray`.
 
 use super::ns_enumerator::{fast_enumeration_helper, NSFastEnumerationState};
-use super::ns_property_list_serialization::deserialize_plist_from_file;
-use super::{ns_keyed_unarchiver, ns_string, ns_url, NSUInteger};
+use super::ns_property_list_serialization::{deserialize_plist_from_file, serialize_plist_to_file};
+use super::{ns_keyed_archiver, ns_keyed_unarchiver, ns_string, ns_url, NSUInteger};
 use crate::fs::GuestPath;
 use crate::mem::MutPtr;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, Class, ClassExports,
     NSZonePtr,
 };
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 use crate::Environment;
 
 struct ObjectEnumeratorHostObject {
     iterator: std::vec::IntoIter<id>,
 }
-impl HostObject for ObjectEnumeratorHostObject {}
+impl_HostObject_with_SaveState!(ObjectEnumeratorHostObject);
+impl_SaveState_for_struct!(ObjectEnumeratorHostObject { iterator });
 
 /// Belongs to _touchHLE_NSArray
 struct ArrayHostObject {
     array: Vec<id>,
 }
-impl HostObject for ArrayHostObject {}
+impl_HostObject_with_SaveState!(ArrayHostObject);
+impl_SaveState_for_struct!(ArrayHostObject { array });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[
+    host_object_restorer!(ObjectEnumeratorHostObject),
+    host_object_restorer!(ArrayHostObject),
+];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -98,6 +109,23 @@ pub const CLASSES: ClassExports = objc_classes! {
     retain(env, this)
 }
 
+// NSCoding implementation
+- (())encodeWithCoder:(id)coder {
+    // FIXME: What if it's not an NSKeyedArchiver?
+    let count: NSUInteger = msg![env; this count];
+    let objects: Vec<id> = (0..count)
+        .map(|i| msg![env; this objectAtIndex:i])
+        .collect();
+    ns_keyed_archiver::encode_current_array(env, coder, &objects)
+}
+
+// This probably comes from some category related to plists.
+- (bool)writeToFile:(id)path // NSString*
+         atomically:(bool)use_aux_file {
+    let path = ns_string::to_rust_string(env, path);
+    serialize_plist_to_file(env, this, GuestPath::new(&path), use_aux_file)
+}
+
 - (id)lastObject {
     let size: NSUInteger = msg![env; this count];
     if size == 0 {
@@ -128,7 +156,14 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 // NSCopying implementation
 - (id)copyWithZone:(NSZonePtr)_zone {
-    todo!(); // TODO: this should produce an immutable copy
+    let count: NSUInteger = msg![env; this count];
+    let objects: Vec<id> = (0..count)
+        .map(|i| {
+            let object: id = msg![env; this objectAtIndex:i];
+            retain(env, object)
+        })
+        .collect();
+    from_vec(env, objects)
 }
 
 @end
@@ -144,6 +179,10 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
 
+- (Class)classForCoder {
+    env.objc.get_known_class("NSArray", &mut env.mem)
+}
+
 // NSCoding implementation
 - (id)initWithCoder:(id)coder {
     // It seems that every NSArray item in an NSKeyedArchiver plist looks like:
@@ -228,6 +267,10 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
 
+- (Class)classForCoder {
+    env.objc.get_known_class("NSMutableArray", &mut env.mem)
+}
+
 // NSCoding implementation
 - (id)initWithCoder:(id)coder {
     let objects = ns_keyed_unarchiver::decode_current_array(env, coder);
@@ -5,7 +5,11 @@
  */
 //! `NSAutoreleasePool`.
 
-use crate::objc::{id, msg, objc_classes, release, ClassExports, HostObject, NSZonePtr};
+use crate::objc::{id, msg, objc_classes, release, ClassExports, NSZonePtr};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 use crate::{Environment, ThreadId};
 use std::collections::HashMap;
 
@@ -13,6 +17,7 @@ use std::collections::HashMap;
 pub struct State {
     pool_stacks: HashMap<ThreadId, Vec<id>>,
 }
+impl_SaveState_for_struct!(State { pool_stacks });
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
         &mut env.framework_state.foundation.ns_autorelease_pool
@@ -24,7 +29,14 @@ struct NSAutoreleasePoolHostObject {
     /// This is allowed to contain duplicates, which get released several times!
     objects: Vec<id>,
 }
-impl HostObject for NSAutoreleasePoolHostObject {}
+impl_HostObject_with_SaveState!(NSAutoreleasePoolHostObject);
+impl_SaveState_for_struct!(NSAutoreleasePoolHostObject {
+    original_thread,
+    objects
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(NSAutoreleasePoolHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -14,8 +14,10 @@ use crate::frameworks::foundation::ns_string::get_static_str;
 use crate::frameworks::foundation::ns_string::to_rust_string;
 use crate::frameworks::uikit::ui_nib::load_nib_file;
 use crate::fs::GuestPathBuf;
-use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, HostObject,
+use crate::objc::{autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers, Reader, SaveState, Writer,
 };
 use crate::Environment;
 use std::collections::HashSet;
@@ -40,6 +42,7 @@ const LANG_ID_TO_LANG_PROJ: &[(&str, &str)] = &[
 pub struct State {
     main_bundle: Option<id>,
 }
+impl_SaveState_for_struct!(State { main_bundle });
 
 pub struct NSBundleHostObject {
     /// If this is [None], this is the main bundle's NSBundle instance and the
@@ -52,7 +55,28 @@ pub struct NSBundleHostObject {
     /// `NSDictionary*` for the `Info.plist` content. [None] if not created yet.
     info_dictionary: Option<id>,
 }
-impl HostObject for NSBundleHostObject {}
+impl_HostObject_with_SaveState!(NSBundleHostObject);
+/// Only the main bundle's instance can be saved, see [Self::bundle].
+impl SaveState for NSBundleHostObject {
+    fn save(&self, w: &mut Writer) {
+        if self.bundle.is_some() {
+            w.unsupported("NSBundle for a bundle other than the main bundle");
+        }
+        self.bundle_path.save(w);
+        self.bundle_url.save(w);
+        self.info_dictionary.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(NSBundleHostObject {
+            bundle: None,
+            bundle_path: SaveState::restore(r)?,
+            bundle_url: SaveState::restore(r)?,
+            info_dictionary: SaveState::restore(r)?,
+        })
+    }
+}
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(NSBundleHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
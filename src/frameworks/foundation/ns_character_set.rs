@@ -6,8 +6,10 @@
 //! The `NSCharacterSet` class cluster, including `NSMutableCharacterSet`.
 
 use super::{ns_string, unichar};
-use crate::objc::{
-    autorelease, id, msg, msg_class, objc_classes, retain, ClassExports, HostObject, NSZonePtr,
+use crate::objc::{autorelease, id, msg, msg_class, objc_classes, retain, ClassExports, NSZonePtr};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use std::collections::HashSet;
 
@@ -15,7 +17,11 @@ use std::collections::HashSet;
 struct CharacterSetHostObject {
     set: HashSet<unichar>,
 }
-impl HostObject for CharacterSetHostObject {}
+impl_HostObject_with_SaveState!(CharacterSetHostObject);
+impl_SaveState_for_struct!(CharacterSetHostObject { set });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(CharacterSetHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -10,7 +10,11 @@ use super::{NSRange, NSUInteger};
 use crate::fs::GuestPath;
 use crate::mem::{ConstVoidPtr, MutPtr, MutVoidPtr, Ptr};
 use crate::objc::{
-    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::{msg_class, Environment};
 
@@ -18,7 +22,10 @@ struct NSDataHostObject {
     bytes: MutVoidPtr,
     length: NSUInteger,
 }
-impl HostObject for NSDataHostObject {}
+impl_HostObject_with_SaveState!(NSDataHostObject);
+impl_SaveState_for_struct!(NSDataHostObject { bytes, length });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(NSDataHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -112,11 +119,15 @@ pub const CLASSES: ClassExports = objc_classes! {
     this
 }
 
-// FIXME: writes should be atomic
 - (bool)writeToFile:(id)path // NSString*
-         atomically:(bool)_use_aux_file {
+         atomically:(bool)use_aux_file {
     let file = to_rust_string(env, path);
-    log_dbg!("[(NSData*){:?} writeToFile:{:?} atomically:_]", this, file);
+    log_dbg!(
+        "[(NSData*){:?} writeToFile:{:?} atomically:{}]",
+        this,
+        file,
+        use_aux_file
+    );
     let host_object = env.objc.borrow::<NSDataHostObject>(this);
     // Mem::bytes_at() panics when the pointer is NULL, but NSData's pointer can
     // be NULL if the length is 0.
@@ -125,7 +136,11 @@ pub const CLASSES: ClassExports = objc_classes! {
     } else {
         env.mem.bytes_at(host_object.bytes.cast(), host_object.length)
     };
-    env.fs.write(GuestPath::new(&file), slice).is_ok()
+    if use_aux_file {
+        env.fs.write_atomically(GuestPath::new(&file), slice).is_ok()
+    } else {
+        env.fs.write(GuestPath::new(&file), slice).is_ok()
+    }
 }
 
 - (())dealloc {
@@ -189,6 +204,29 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg![env; new initWithBytes:bytes length:length]
 }
 
+- (())appendBytes:(ConstVoidPtr)bytes length:(NSUInteger)add_len {
+    if add_len == 0 {
+        return;
+    }
+    let &NSDataHostObject { bytes: old_bytes, length, .. } = env.objc.borrow(this);
+    let new_len = length + add_len;
+    let new_bytes = if old_bytes.is_null() {
+        env.mem.alloc(new_len)
+    } else {
+        env.mem.realloc(old_bytes, new_len)
+    };
+    env.mem.memmove(new_bytes + length, bytes, add_len);
+    let host = env.objc.borrow_mut::<NSDataHostObject>(this);
+    host.length = new_len;
+    host.bytes = new_bytes;
+}
+
+- (())appendData:(id)other { // NSData*
+    let bytes: ConstVoidPtr = msg![env; other bytes];
+    let length: NSUInteger = msg![env; other length];
+    msg![env; this appendBytes:bytes length:length]
+}
+
 - (())increaseLengthBy:(NSUInteger)add_len {
     let &NSDataHostObject { bytes, length, .. } = env.objc.borrow(this);
     let new_len = length + add_len;
@@ -208,3 +246,13 @@ pub fn to_rust_slice(env: &mut Environment, data: id) -> &[u8] {
     env.mem
         .bytes_at(borrowed_data.bytes.cast(), borrowed_data.length)
 }
+
+/// Shortcut for host code, roughly equivalent to
+/// `[[NSData alloc] initWithBytes:length:]`.
+pub fn from_rust_slice(env: &mut Environment, bytes: &[u8]) -> id {
+    let length: NSUInteger = bytes.len().try_into().unwrap();
+    let alloc: MutPtr<u8> = env.mem.alloc(length).cast();
+    env.mem.bytes_at_mut(alloc, length).copy_from_slice(bytes);
+    let data: id = msg_class![env; NSData alloc];
+    msg![env; data initWithBytesNoCopy:(alloc.cast_void()) length:length]
+}
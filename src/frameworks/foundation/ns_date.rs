@@ -7,14 +7,19 @@
 
 use super::NSTimeInterval;
 use crate::frameworks::core_foundation::time::apple_epoch;
-use crate::objc::{autorelease, id, objc_classes, ClassExports, HostObject};
-
-use std::time::SystemTime;
+use crate::objc::{autorelease, id, objc_classes, ClassExports};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 
 struct NSDateHostObject {
     time_interval: NSTimeInterval,
 }
-impl HostObject for NSDateHostObject {}
+impl_HostObject_with_SaveState!(NSDateHostObject);
+impl_SaveState_for_struct!(NSDateHostObject { time_interval });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(NSDateHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -25,7 +30,9 @@ pub const CLASSES: ClassExports = objc_classes! {
 + (id)date {
     // "Date objects are immutable, representing an invariant time interval
     // relative to an absolute reference date (00:00:00 UTC on 1 January 2001)."
-    let time_interval = SystemTime::now()
+    let time_interval = env
+        .clock
+        .system_time()
         .duration_since(apple_epoch())
         .unwrap()
         .as_secs_f64();
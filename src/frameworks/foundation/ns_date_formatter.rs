@@ -11,12 +11,20 @@
 
 use crate::frameworks::core_foundation::time::CFAbsoluteTimeGetGregorianDate;
 use crate::frameworks::foundation::{ns_string, NSTimeInterval};
-use crate::objc::{id, msg, nil, objc_classes, ClassExports, HostObject, NSZonePtr};
+use crate::objc::{id, msg, nil, objc_classes, ClassExports, NSZonePtr};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 
 struct NSDateFormatterHostObject {
     date_format: Option<id>,
 }
-impl HostObject for NSDateFormatterHostObject {}
+impl_HostObject_with_SaveState!(NSDateFormatterHostObject);
+impl_SaveState_for_struct!(NSDateFormatterHostObject { date_format });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(NSDateFormatterHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
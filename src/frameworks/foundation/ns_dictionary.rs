@@ -5,14 +5,18 @@
  */
 //! The `NSDictionary` class cluster, including `NSMutableDictionary`.
 
-use super::ns_property_list_serialization::deserialize_plist_from_file;
-use super::{ns_string, ns_url, NSUInteger};
+use super::ns_property_list_serialization::{deserialize_plist_from_file, serialize_plist_to_file};
+use super::{ns_keyed_archiver, ns_keyed_unarchiver, ns_string, ns_url, NSUInteger};
 use crate::abi::VaList;
 use crate::fs::GuestPath;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, Class, ClassExports,
     NSZonePtr,
 };
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 use crate::Environment;
 use std::collections::HashMap;
 
@@ -31,7 +35,12 @@ pub(super) struct DictionaryHostObject {
     map: HashMap<Hash, Vec<(id, id)>>,
     pub(super) count: NSUInteger,
 }
-impl HostObject for DictionaryHostObject {}
+impl_HostObject_with_SaveState!(DictionaryHostObject);
+impl_SaveState_for_struct!(DictionaryHostObject { map, count });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(DictionaryHostObject)];
+
 impl DictionaryHostObject {
     pub(super) fn lookup(&self, env: &mut Environment, key: id) -> id {
         let hash: Hash = msg![env; key hash];
@@ -70,6 +79,29 @@ impl DictionaryHostObject {
         collisions.push((key, value));
         self.count += 1;
     }
+    pub(super) fn remove(&mut self, env: &mut Environment, key: id) {
+        let hash: Hash = msg![env; key hash];
+        let Some(collisions) = self.map.get_mut(&hash) else {
+            return;
+        };
+        let mut found = None;
+        for (i, &(candidate_key, _)) in collisions.iter().enumerate() {
+            if candidate_key == key || msg![env; candidate_key isEqualTo:key] {
+                found = Some(i);
+                break;
+            }
+        }
+        let Some(i) = found else {
+            return;
+        };
+        let (existing_key, existing_value) = collisions.remove(i);
+        if collisions.is_empty() {
+            self.map.remove(&hash);
+        }
+        self.count -= 1;
+        release(env, existing_key);
+        release(env, existing_value);
+    }
     pub(super) fn release(&mut self, env: &mut Environment) {
         for collisions in self.map.values() {
             for &(key, value) in collisions {
@@ -81,6 +113,9 @@ impl DictionaryHostObject {
     pub(super) fn iter_keys(&self) -> impl Iterator<Item = id> + '_ {
         self.map.values().flatten().map(|&(key, _value)| key)
     }
+    pub(super) fn iter_pairs(&self) -> impl Iterator<Item = (id, id)> + '_ {
+        self.map.values().flatten().copied()
+    }
 }
 
 /// Helper to enable sharing `dictionaryWithObjectsAndKeys:` and
@@ -180,6 +215,13 @@ pub const CLASSES: ClassExports = objc_classes! {
     deserialize_plist_from_file(env, &path, /* array_expected: */ false)
 }
 
+// This probably comes from some category related to plists.
+- (bool)writeToFile:(id)path // NSString*
+         atomically:(bool)use_aux_file {
+    let path = ns_string::to_rust_string(env, path);
+    serialize_plist_to_file(env, this, GuestPath::new(&path), use_aux_file)
+}
+
 // NSCopying implementation
 - (id)copyWithZone:(NSZonePtr)_zone {
     // TODO: override this once we have NSMutableString!
@@ -214,6 +256,34 @@ pub const CLASSES: ClassExports = objc_classes! {
     this
 }
 
+- (Class)classForCoder {
+    env.objc.get_known_class("NSDictionary", &mut env.mem)
+}
+
+// NSCoding implementation
+- (id)initWithCoder:(id)coder {
+    // FIXME: What if it's not an NSKeyedUnarchiver?
+    let pairs = ns_keyed_unarchiver::decode_current_dictionary(env, coder);
+    let mut host_object = <DictionaryHostObject as Default>::default();
+    for (key, object) in pairs {
+        host_object.insert(env, key, object, /* copy_key: */ true);
+        // keys and objects were retained by the Vec
+        release(env, key);
+        release(env, object);
+    }
+    *env.objc.borrow_mut(this) = host_object;
+    this
+}
+- (())encodeWithCoder:(id)coder {
+    // FIXME: What if it's not an NSKeyedArchiver?
+    let pairs: Vec<(id, id)> = env
+        .objc
+        .borrow::<DictionaryHostObject>(this)
+        .iter_pairs()
+        .collect();
+    ns_keyed_archiver::encode_current_dictionary(env, coder, &pairs)
+}
+
 // TODO: enumeration, more init methods, etc
 
 - (NSUInteger)count {
@@ -6,15 +6,26 @@
 
 use crate::dyld::{ConstantExports, HostConstant};
 use crate::frameworks::foundation::NSInteger;
-use crate::objc::{id, nil, release, retain, ClassExports, HostObject, NSZonePtr};
+use crate::objc::{id, nil, release, retain, ClassExports, NSZonePtr};
 use crate::objc_classes;
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 
 struct ErrorHostObject {
     domain: id,
     code: NSInteger,
     user_info: id,
 }
-impl HostObject for ErrorHostObject {}
+impl_HostObject_with_SaveState!(ErrorHostObject);
+impl_SaveState_for_struct!(ErrorHostObject {
+    domain,
+    code,
+    user_info
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(ErrorHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
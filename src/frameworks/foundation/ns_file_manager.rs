@@ -9,8 +9,10 @@ use super::{ns_array, ns_string, NSUInteger};
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::fs::{GuestPath, GuestPathBuf};
 use crate::mem::MutPtr;
-use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, HostObject,
+use crate::objc::{autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 
@@ -59,11 +61,16 @@ pub const FUNCTIONS: FunctionExports = &[
 pub struct State {
     default_manager: Option<id>,
 }
+impl_SaveState_for_struct!(State { default_manager });
 
 struct NSDirectoryEnumeratorHostObject {
     iterator: std::vec::IntoIter<GuestPathBuf>,
 }
-impl HostObject for NSDirectoryEnumeratorHostObject {}
+impl_HostObject_with_SaveState!(NSDirectoryEnumeratorHostObject);
+impl_SaveState_for_struct!(NSDirectoryEnumeratorHostObject { iterator });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(NSDirectoryEnumeratorHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
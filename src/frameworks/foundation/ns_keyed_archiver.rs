@@ -0,0 +1,313 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSKeyedArchiver` and serialization of its object graph format.
+//!
+//! This produces the same format that [super::ns_keyed_unarchiver] consumes,
+//! so see that module for resources about it.
+
+use super::ns_data;
+use super::ns_keyed_unarchiver::NSKeyedArchiveRootObjectKey;
+use super::ns_property_list_serialization::serialize_plist;
+use super::ns_string::{get_static_str, to_rust_string};
+use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
+use crate::frameworks::foundation::NSInteger;
+use crate::frameworks::uikit::ui_geometry::{
+    NSStringFromCGPoint, NSStringFromCGRect, NSStringFromCGSize,
+};
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, Class, ClassExports,
+    HostObject, NSZonePtr,
+};
+use crate::Environment;
+use plist::{Dictionary, Uid, Value};
+use std::collections::HashMap;
+
+struct NSKeyedArchiverHostObject {
+    /// `NSMutableData*` that the archive is appended to by `finishEncoding`.
+    data: id,
+    /// The `$top` dictionary, i.e. the scope of top-level objects.
+    top: Dictionary,
+    /// The `$objects` array. The first item is always `$null`, which is what
+    /// the uid 0 refers to.
+    objects: Vec<Value>,
+    /// The uid of the object currently being encoded, if any.
+    current_key: Option<Uid>,
+    /// Objects that have already been archived, so that an object referenced
+    /// several times is only archived once.
+    already_archived: HashMap<id, Uid>,
+    /// Like `already_archived`, but for class descriptions.
+    already_archived_classes: HashMap<Class, Uid>,
+}
+impl HostObject for NSKeyedArchiverHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSKeyedArchiver: NSCoder
+
++ (id)allocWithZone:(NSZonePtr)_zone { // struct _NSZone*
+    let archiver = Box::new(NSKeyedArchiverHostObject {
+        data: nil,
+        top: Dictionary::new(),
+        objects: vec![Value::String("$null".to_string())],
+        current_key: None,
+        already_archived: HashMap::new(),
+        already_archived_classes: HashMap::new(),
+    });
+    env.objc.alloc_object(this, archiver, &mut env.mem)
+}
+
++ (id)archivedDataWithRootObject:(id)object {
+    let data: id = msg_class![env; NSMutableData new];
+    let archiver: id = msg![env; this alloc];
+    let archiver: id = msg![env; archiver initForWritingWithMutableData:data];
+    let root_key = get_static_str(env, NSKeyedArchiveRootObjectKey);
+    let _: () = msg![env; archiver encodeObject:object forKey:root_key];
+    let _: () = msg![env; archiver finishEncoding];
+    release(env, archiver);
+    autorelease(env, data)
+}
+
++ (bool)archiveRootObject:(id)object
+                   toFile:(id)path { // NSString*
+    let data: id = msg![env; this archivedDataWithRootObject:object];
+    msg![env; data writeToFile:path atomically:true]
+}
+
+// TODO: other init methods, delegates, class name mapping.
+
+- (id)initForWritingWithMutableData:(id)data { // NSMutableData*
+    retain(env, data);
+    let host_obj = borrow_host_obj(env, this);
+    assert!(host_obj.data == nil);
+    host_obj.data = data;
+    this
+}
+
+- (())dealloc {
+    let data = borrow_host_obj(env, this).data;
+    release(env, data);
+
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (())finishEncoding {
+    let host_obj = borrow_host_obj(env, this);
+    let mut plist = Dictionary::new();
+    plist.insert("$version".to_string(), Value::Integer(100000.into()));
+    plist.insert(
+        "$archiver".to_string(),
+        Value::String("NSKeyedArchiver".to_string()),
+    );
+    plist.insert(
+        "$top".to_string(),
+        Value::Dictionary(std::mem::take(&mut host_obj.top)),
+    );
+    plist.insert(
+        "$objects".to_string(),
+        Value::Array(std::mem::take(&mut host_obj.objects)),
+    );
+    let data = host_obj.data;
+
+    let mut bytes = Vec::new();
+    Value::Dictionary(plist).to_writer_binary(&mut bytes).unwrap();
+
+    let new_data = ns_data::from_rust_slice(env, &bytes);
+    let _: () = msg![env; data appendData:new_data];
+    release(env, new_data);
+}
+
+// These methods drive the encoding. They get called in two cases:
+// - By the code that initiates the archival, e.g. `archivedDataWithRootObject:`
+//   to store top-level objects.
+// - By the object currently being archived, i.e. something that had
+//   `encodeWithCoder:` called on it, to store values in its scope.
+// They are all from the NSCoder abstract class.
+
+- (())encodeBool:(bool)value forKey:(id)key { // NSString *
+    set_value_to_encode_for_key(env, this, key, Value::Boolean(value))
+}
+
+- (())encodeDouble:(f64)value forKey:(id)key { // NSString *
+    set_value_to_encode_for_key(env, this, key, Value::Real(value))
+}
+
+- (())encodeFloat:(f32)value forKey:(id)key { // NSString *
+    set_value_to_encode_for_key(env, this, key, Value::Real(value.into()))
+}
+
+- (())encodeInteger:(NSInteger)value forKey:(id)key { // NSString *
+    set_value_to_encode_for_key(env, this, key, Value::Integer(value.into()))
+}
+
+- (())encodeInt:(i32)value forKey:(id)key { // NSString *
+    set_value_to_encode_for_key(env, this, key, Value::Integer(value.into()))
+}
+
+- (())encodeInt32:(i32)value forKey:(id)key { // NSString *
+    set_value_to_encode_for_key(env, this, key, Value::Integer(value.into()))
+}
+
+- (())encodeInt64:(i64)value forKey:(id)key { // NSString *
+    set_value_to_encode_for_key(env, this, key, Value::Integer(value.into()))
+}
+
+- (())encodeObject:(id)object forKey:(id)key { // NSString*
+    let uid = archive_object(env, this, object);
+    set_value_to_encode_for_key(env, this, key, Value::Uid(uid))
+}
+
+// TODO: add more encode methods
+
+// These come from a category in UIKit's UIGeometry.h
+- (())encodeCGPoint:(CGPoint)point forKey:(id)key { // NSString*
+    let string = NSStringFromCGPoint(env, point);
+    msg![env; this encodeObject:string forKey:key]
+}
+- (())encodeCGSize:(CGSize)size forKey:(id)key { // NSString*
+    let string = NSStringFromCGSize(env, size);
+    msg![env; this encodeObject:string forKey:key]
+}
+- (())encodeCGRect:(CGRect)rect forKey:(id)key { // NSString*
+    let string = NSStringFromCGRect(env, rect);
+    msg![env; this encodeObject:string forKey:key]
+}
+
+@end
+
+};
+
+fn borrow_host_obj(env: &mut Environment, archiver: id) -> &mut NSKeyedArchiverHostObject {
+    env.objc.borrow_mut(archiver)
+}
+
+fn set_value_to_encode_for_key(env: &mut Environment, archiver: id, key: id, value: Value) {
+    let key = to_rust_string(env, key).into_owned();
+    let host_obj = borrow_host_obj(env, archiver);
+    let scope = match host_obj.current_key {
+        Some(current_uid) => host_obj.objects[current_uid.get() as usize]
+            .as_dictionary_mut()
+            .unwrap(),
+        None => &mut host_obj.top,
+    };
+    scope.insert(key, value);
+}
+
+/// The core of the implementation: archive an object and get its uid.
+///
+/// This is recursive in practice: the `encodeWithCoder:` messages sent by this
+/// function will be received by objects which will then send
+/// `encodeXXX:forKey:` messages back to the archiver, which will then call this
+/// function (and so on).
+fn archive_object(env: &mut Environment, archiver: id, object: id) -> Uid {
+    if object == nil {
+        return Uid::new(0);
+    }
+    if let Some(&existing) = borrow_host_obj(env, archiver).already_archived.get(&object) {
+        return existing;
+    }
+
+    // Strings, numbers and data are stored directly as plist values.
+    let string_class: Class = msg_class![env; NSString class];
+    let number_class: Class = msg_class![env; NSNumber class];
+    let data_class: Class = msg_class![env; NSData class];
+    let is_plist_scalar = msg![env; object isKindOfClass:string_class]
+        || msg![env; object isKindOfClass:number_class]
+        || msg![env; object isKindOfClass:data_class];
+    let item = if is_plist_scalar {
+        serialize_plist(env, object).unwrap()
+    } else {
+        // The most general kind of item: a dictionary that contains the info
+        // needed to invoke `initWithCoder:` on a class implementing NSCoding.
+        Value::Dictionary(Dictionary::new())
+    };
+
+    let host_obj = borrow_host_obj(env, archiver);
+    let key = Uid::new(host_obj.objects.len().try_into().unwrap());
+    host_obj.objects.push(item);
+    host_obj.already_archived.insert(object, key);
+
+    if is_plist_scalar {
+        return key;
+    }
+
+    let class_key = archive_class(env, archiver, object);
+    let host_obj = borrow_host_obj(env, archiver); // reborrow
+    host_obj.objects[key.get() as usize]
+        .as_dictionary_mut()
+        .unwrap()
+        .insert("$class".to_string(), Value::Uid(class_key));
+
+    let old_current_key = host_obj.current_key;
+    host_obj.current_key = Some(key);
+
+    let _: () = msg![env; object encodeWithCoder:archiver];
+
+    let host_obj = borrow_host_obj(env, archiver); // reborrow
+    host_obj.current_key = old_current_key;
+
+    key
+}
+
+/// Archive the description of an object's class (name and superclass names)
+/// and get its uid.
+fn archive_class(env: &mut Environment, archiver: id, object: id) -> Uid {
+    let class: Class = msg![env; object classForKeyedArchiver];
+    if let Some(&existing) = borrow_host_obj(env, archiver)
+        .already_archived_classes
+        .get(&class)
+    {
+        return existing;
+    }
+
+    let mut class_names = Vec::new();
+    let mut next_class = class;
+    while next_class != nil {
+        let class_name = env.objc.get_class_name(next_class).to_string();
+        class_names.push(Value::String(class_name));
+        next_class = env.objc.get_superclass(next_class);
+    }
+
+    let mut class_dict = Dictionary::new();
+    class_dict.insert("$classname".to_string(), class_names[0].clone());
+    class_dict.insert("$classes".to_string(), Value::Array(class_names));
+
+    let host_obj = borrow_host_obj(env, archiver);
+    let key = Uid::new(host_obj.objects.len().try_into().unwrap());
+    host_obj.objects.push(Value::Dictionary(class_dict));
+    host_obj.already_archived_classes.insert(class, key);
+    key
+}
+
+/// Archive a list of objects as an array of uids stored under `key` in the
+/// current object's scope.
+fn encode_current_uid_array(env: &mut Environment, archiver: id, key: &str, objects: &[id]) {
+    let uids = objects
+        .iter()
+        .map(|&object| Value::Uid(archive_object(env, archiver, object)))
+        .collect();
+    let host_obj = borrow_host_obj(env, archiver);
+    let current_key = host_obj.current_key.unwrap();
+    host_obj.objects[current_key.get() as usize]
+        .as_dictionary_mut()
+        .unwrap()
+        .insert(key.to_string(), Value::Array(uids));
+}
+
+/// Shortcut for use by `[NSArray encodeWithCoder:]`. Counterpart of
+/// [super::ns_keyed_unarchiver::decode_current_array].
+pub fn encode_current_array(env: &mut Environment, archiver: id, objects: &[id]) {
+    encode_current_uid_array(env, archiver, "NS.objects", objects)
+}
+
+/// Shortcut for use by `[_touchHLE_NSDictionary encodeWithCoder:]`.
+/// Counterpart of [super::ns_keyed_unarchiver::decode_current_dictionary].
+pub fn encode_current_dictionary(env: &mut Environment, archiver: id, pairs: &[(id, id)]) {
+    let (keys, objects): (Vec<id>, Vec<id>) = pairs.iter().copied().unzip();
+    encode_current_uid_array(env, archiver, "NS.keys", &keys);
+    encode_current_uid_array(env, archiver, "NS.objects", &objects);
+}
@@ -11,6 +11,7 @@
 //!   plists, e.g. `plutil -p` or `println!("{:#?}", plist::Value::...);`.
 //! - Apple's [Archives and Serializations Programming Guide](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/Archiving/Articles/archives.html)
 
+use super::ns_property_list_serialization::deserialize_plist;
 use super::ns_string::{from_rust_string, get_static_str, to_rust_string};
 use crate::dyld::{ConstantExports, HostConstant};
 use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
@@ -225,6 +226,11 @@ fn get_value_to_decode_for_key(env: &mut Environment, unarchiver: id, key: id) -
 /// The object returned is retained only by the archiver. Remember to retain and
 /// possibly autorelease it as appropriate.
 fn unarchive_key(env: &mut Environment, unarchiver: id, key: Uid) -> id {
+    // The first item is always `$null`.
+    if key.get() == 0 {
+        return nil;
+    }
+
     let host_obj = borrow_host_obj(env, unarchiver);
     if let Some(existing) = host_obj.already_unarchived[key.get() as usize] {
         return existing;
@@ -275,6 +281,11 @@ fn unarchive_key(env: &mut Environment, unarchiver: id, key: Uid) -> id {
             let s = s.to_string();
             from_rust_string(env, s)
         }
+        // NSNumber and NSData are stored directly as plist values.
+        Value::Boolean(_) | Value::Integer(_) | Value::Real(_) | Value::Data(_) => {
+            let item = item.clone();
+            deserialize_plist(env, &item)
+        }
         _ => unimplemented!("Unarchive: {:#?}", item),
     };
 
@@ -283,17 +294,16 @@ fn unarchive_key(env: &mut Environment, unarchiver: id, key: Uid) -> id {
     new_object
 }
 
-/// Shortcut for use by `[_touchHLE_NSArray initWithCoder:]`.
+/// Unarchive an array of uids stored under `key` in the current object's
+/// scope.
 ///
 /// The objects are to be considered retained by the `Vec`.
-pub fn decode_current_array(env: &mut Environment, unarchiver: id) -> Vec<id> {
+fn decode_current_uid_array(env: &mut Environment, unarchiver: id, key: &str) -> Vec<id> {
     let keys: Vec<Uid> = {
         let host_obj = borrow_host_obj(env, unarchiver);
         let objects = host_obj.plist["$objects"].as_array().unwrap();
         let item = &objects[host_obj.current_key.unwrap().get() as usize];
-        let keys = item.as_dictionary().unwrap()["NS.objects"]
-            .as_array()
-            .unwrap();
+        let keys = item.as_dictionary().unwrap()[key].as_array().unwrap();
         keys.iter()
             .map(|value| value.as_uid().copied().unwrap())
             .collect()
@@ -307,3 +317,20 @@ pub fn decode_current_array(env: &mut Environment, unarchiver: id) -> Vec<id> {
         })
         .collect()
 }
+
+/// Shortcut for use by `[_touchHLE_NSArray initWithCoder:]`.
+///
+/// The objects are to be considered retained by the `Vec`.
+pub fn decode_current_array(env: &mut Environment, unarchiver: id) -> Vec<id> {
+    decode_current_uid_array(env, unarchiver, "NS.objects")
+}
+
+/// Shortcut for use by `[_touchHLE_NSDictionary initWithCoder:]`.
+///
+/// The keys and objects are to be considered retained by the `Vec`.
+pub fn decode_current_dictionary(env: &mut Environment, unarchiver: id) -> Vec<(id, id)> {
+    let keys = decode_current_uid_array(env, unarchiver, "NS.keys");
+    let objects = decode_current_uid_array(env, unarchiver, "NS.objects");
+    assert!(keys.len() == objects.len());
+    keys.into_iter().zip(objects).collect()
+}
@@ -7,8 +7,12 @@
 
 use super::{ns_array, ns_string};
 use crate::dyld::{ConstantExports, HostConstant};
-use crate::objc::{id, objc_classes, ClassExports, HostObject};
+use crate::objc::{id, objc_classes, ClassExports};
 use crate::options::Options;
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 use crate::Environment;
 use std::ffi::CStr;
 
@@ -24,6 +28,10 @@ pub struct State {
     current_locale: Option<id>,
     preferred_languages: Option<id>,
 }
+impl_SaveState_for_struct!(State {
+    current_locale,
+    preferred_languages
+});
 impl State {
     fn get(env: &mut Environment) -> &mut State {
         &mut env.framework_state.foundation.ns_locale
@@ -110,7 +118,10 @@ fn get_preferred_countries() -> Vec<String> {
 struct NSLocaleHostObject {
     country_code: id,
 }
-impl HostObject for NSLocaleHostObject {}
+impl_HostObject_with_SaveState!(NSLocaleHostObject);
+impl_SaveState_for_struct!(NSLocaleHostObject { country_code });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(NSLocaleHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
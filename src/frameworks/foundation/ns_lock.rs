@@ -12,14 +12,25 @@ use crate::libc::pthread::mutex::{
 };
 use crate::mem::{guest_size_of, MutPtr};
 use crate::msg;
-use crate::objc::{id, nil, objc_classes, ClassExports, HostObject};
+use crate::objc::{id, nil, objc_classes, ClassExports};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 
 struct NSLockHostObject {
     pthread_mutex_ptr: MutPtr<pthread_mutex_t>,
     name: id,
     locked_by: Option<ThreadId>,
 }
-impl HostObject for NSLockHostObject {}
+impl_HostObject_with_SaveState!(NSLockHostObject);
+impl_SaveState_for_struct!(NSLockHostObject {
+    pthread_mutex_ptr,
+    name,
+    locked_by
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(NSLockHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -6,7 +6,11 @@
 //! `NSNotification`.
 
 use crate::objc::{
-    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 
 /// `NSString*`
@@ -17,7 +21,15 @@ struct NSNotificationHostObject {
     object: id,
     user_info: id,
 }
-impl HostObject for NSNotificationHostObject {}
+impl_HostObject_with_SaveState!(NSNotificationHostObject);
+impl_SaveState_for_struct!(NSNotificationHostObject {
+    name,
+    object,
+    user_info
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(NSNotificationHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
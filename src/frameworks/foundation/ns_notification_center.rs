@@ -9,8 +9,11 @@ use super::ns_notification::NSNotificationName;
 use super::ns_string;
 
 use crate::objc::{
-    id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports, HostObject,
-    NSZonePtr, SEL,
+    id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports, NSZonePtr, SEL,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -19,6 +22,7 @@ use std::collections::HashMap;
 pub struct State {
     default_center: Option<id>,
 }
+impl_SaveState_for_struct!(State { default_center });
 
 #[derive(Clone)]
 struct Observer {
@@ -30,7 +34,17 @@ struct Observer {
 struct NSNotificationCenterHostObject {
     observers: HashMap<Cow<'static, str>, Vec<Observer>>,
 }
-impl HostObject for NSNotificationCenterHostObject {}
+impl_HostObject_with_SaveState!(NSNotificationCenterHostObject);
+impl_SaveState_for_struct!(NSNotificationCenterHostObject { observers });
+
+impl_SaveState_for_struct!(Observer {
+    observer,
+    selector,
+    object
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(NSNotificationCenterHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
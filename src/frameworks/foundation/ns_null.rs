@@ -6,11 +6,13 @@
 //! `NSNull`.
 
 use crate::objc::{id, objc_classes, ClassExports, TrivialHostObject};
+use crate::save_state::impl_SaveState_for_struct;
 
 #[derive(Default)]
 pub struct State {
     null: Option<id>,
 }
+impl_SaveState_for_struct!(State { null });
 
 pub const CLASSES: ClassExports = objc_classes! {
 
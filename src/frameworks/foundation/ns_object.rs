@@ -89,6 +89,14 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (Class)class {
     ObjC::read_isa(this, &env.mem)
 }
+// NSCoding helpers. Our private implementation classes override
+// `classForCoder` so that archives use the public class names.
+- (Class)classForCoder {
+    msg![env; this class]
+}
+- (Class)classForKeyedArchiver {
+    msg![env; this classForCoder]
+}
 - (bool)isMemberOfClass:(Class)class {
     let this_class: Class = msg![env; this class];
     class == this_class
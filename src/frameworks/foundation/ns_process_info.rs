@@ -7,7 +7,6 @@
 
 use super::NSTimeInterval;
 use crate::objc::{objc_classes, ClassExports};
-use std::time::Instant;
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -16,7 +15,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation NSProcessInfo: NSObject
 
 + (NSTimeInterval)systemUptime {
-    Instant::now().duration_since(env.startup_time).as_secs_f64()
+    env.clock.uptime().as_secs_f64()
 }
 
 @end
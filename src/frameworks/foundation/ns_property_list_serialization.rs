@@ -1,13 +1,54 @@
 //! `NSPropertyListSerialization`.
 
-use super::{ns_array, ns_dictionary, ns_string, NSUInteger};
+use super::ns_dictionary::DictionaryHostObject;
+use super::ns_value::NSNumberHostObject;
+use super::{ns_array, ns_data, ns_dictionary, ns_string, NSUInteger};
 use crate::fs::GuestPath;
 use crate::mem::MutPtr;
-use crate::objc::{id, msg, msg_class, nil, release};
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, release, Class, ClassExports,
+};
 use crate::Environment;
 use plist::Value;
 use std::io::Cursor;
 
+pub type NSPropertyListFormat = NSUInteger;
+pub const NSPropertyListOpenStepFormat: NSPropertyListFormat = 1;
+pub const NSPropertyListXMLFormat_v1_0: NSPropertyListFormat = 100;
+pub const NSPropertyListBinaryFormat_v1_0: NSPropertyListFormat = 200;
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSPropertyListSerialization: NSObject
+
++ (id)dataFromPropertyList:(id)plist
+                    format:(NSPropertyListFormat)format
+          errorDescription:(MutPtr<id>)error_string { // NSString**
+    match serialize_plist_to_bytes(env, plist, format) {
+        Ok(bytes) => {
+            let data = ns_data::from_rust_slice(env, &bytes);
+            autorelease(env, data)
+        }
+        Err(err) => {
+            log_dbg!("dataFromPropertyList: failed: {}", err);
+            if !error_string.is_null() {
+                let err = ns_string::from_rust_string(env, err);
+                let err = autorelease(env, err);
+                env.mem.write(error_string, err);
+            }
+            nil
+        }
+    }
+}
+
+// TODO: propertyListFromData:mutabilityOption:format:errorDescription:
+
+@end
+
+};
+
 // TODO: Implement reading of property lists other than Info.plist.
 // [NSDictionary contentsOfFile:] and [NSArray contentsOfFile:] in particular.
 
@@ -41,7 +82,7 @@ pub(super) fn deserialize_plist_from_file(
     deserialize_plist(env, &root)
 }
 
-fn deserialize_plist(env: &mut Environment, value: &Value) -> id {
+pub(super) fn deserialize_plist(env: &mut Environment, value: &Value) -> id {
     match value {
         Value::Array(array) => {
             let array = array
@@ -75,13 +116,7 @@ fn deserialize_plist(env: &mut Environment, value: &Value) -> id {
             let b: bool = *b;
             msg![env; number initWithBool:b]
         }
-        Value::Data(d) => {
-            let length: NSUInteger = d.len().try_into().unwrap();
-            let alloc: MutPtr<u8> = env.mem.alloc(length).cast();
-            env.mem.bytes_at_mut(alloc, length).copy_from_slice(d);
-            let data: id = msg_class![env; NSData alloc];
-            msg![env; data initWithBytesNoCopy:alloc length:length]
-        }
+        Value::Data(d) => ns_data::from_rust_slice(env, d),
         Value::Date(_) => {
             todo!("deserialize plist value: {:?}", value); // TODO
         }
@@ -114,3 +149,121 @@ fn deserialize_plist(env: &mut Environment, value: &Value) -> id {
         }
     }
 }
+
+/// Convert an object graph made of property list types (`NSString`,
+/// `NSNumber`, `NSData`, `NSArray` and `NSDictionary`) to a [Value], so it can
+/// be written out with the `plist` crate. Returns [Err] if some object isn't
+/// a property list type.
+pub(super) fn serialize_plist(env: &mut Environment, object: id) -> Result<Value, String> {
+    let string_class: Class = msg_class![env; NSString class];
+    let number_class: Class = msg_class![env; NSNumber class];
+    let data_class: Class = msg_class![env; NSData class];
+    let array_class: Class = msg_class![env; NSArray class];
+    let dictionary_class: Class = msg_class![env; NSDictionary class];
+
+    if msg![env; object isKindOfClass:string_class] {
+        Ok(Value::String(
+            ns_string::to_rust_string(env, object).into_owned(),
+        ))
+    } else if msg![env; object isKindOfClass:number_class] {
+        Ok(match *env.objc.borrow(object) {
+            NSNumberHostObject::Bool(value) => Value::Boolean(value),
+            NSNumberHostObject::UnsignedLongLong(value) => Value::Integer(value.into()),
+            NSNumberHostObject::LongLong(value) => Value::Integer(value.into()),
+            NSNumberHostObject::Float(value) => Value::Real(value.into()),
+            NSNumberHostObject::Double(value) => Value::Real(value),
+        })
+    } else if msg![env; object isKindOfClass:data_class] {
+        let length: NSUInteger = msg![env; object length];
+        if length == 0 {
+            Ok(Value::Data(Vec::new()))
+        } else {
+            Ok(Value::Data(ns_data::to_rust_slice(env, object).to_vec()))
+        }
+    } else if msg![env; object isKindOfClass:array_class] {
+        let count: NSUInteger = msg![env; object count];
+        let mut array = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let item: id = msg![env; object objectAtIndex:i];
+            array.push(serialize_plist(env, item)?);
+        }
+        Ok(Value::Array(array))
+    } else if msg![env; object isKindOfClass:dictionary_class] {
+        let pairs: Vec<(id, id)> = env
+            .objc
+            .borrow::<DictionaryHostObject>(object)
+            .iter_pairs()
+            .collect();
+        serialize_plist_dictionary(env, &pairs)
+    } else {
+        let class: Class = msg![env; object class];
+        Err(format!(
+            "{:?} ({}) is not a property list object",
+            object,
+            env.objc.get_class_name(class)
+        ))
+    }
+}
+
+/// Like [serialize_plist], but for a dictionary whose key-value pairs have
+/// already been retrieved.
+pub(super) fn serialize_plist_dictionary(
+    env: &mut Environment,
+    pairs: &[(id, id)],
+) -> Result<Value, String> {
+    let string_class: Class = msg_class![env; NSString class];
+    let mut dict = plist::Dictionary::new();
+    for &(key, value) in pairs {
+        if !msg![env; key isKindOfClass:string_class] {
+            return Err(format!("Dictionary key {:?} is not a string", key));
+        }
+        let key = ns_string::to_rust_string(env, key).into_owned();
+        dict.insert(key, serialize_plist(env, value)?);
+    }
+    Ok(Value::Dictionary(dict))
+}
+
+/// Serialize an object graph made of property list types to bytes in the
+/// requested format. See [serialize_plist].
+pub(super) fn serialize_plist_to_bytes(
+    env: &mut Environment,
+    object: id,
+    format: NSPropertyListFormat,
+) -> Result<Vec<u8>, String> {
+    let value = serialize_plist(env, object)?;
+    let mut bytes = Vec::new();
+    match format {
+        NSPropertyListXMLFormat_v1_0 => value.to_writer_xml(&mut bytes),
+        NSPropertyListBinaryFormat_v1_0 => value.to_writer_binary(&mut bytes),
+        // Apple's implementation can't write this format either.
+        NSPropertyListOpenStepFormat => {
+            return Err("Writing OpenStep format property lists is not supported".to_string())
+        }
+        _ => return Err(format!("Unknown property list format {}", format)),
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+/// Internals of `writeToFile:atomically:` on `NSArray` and `NSDictionary`.
+/// Returns `false` on failure.
+pub(super) fn serialize_plist_to_file(
+    env: &mut Environment,
+    object: id,
+    path: &GuestPath,
+    atomically: bool,
+) -> bool {
+    log_dbg!("Writing plist to {:?}.", path);
+    let bytes = match serialize_plist_to_bytes(env, object, NSPropertyListXMLFormat_v1_0) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            log_dbg!("Couldn't serialize plist ({}), returning false.", err);
+            return false;
+        }
+    };
+    if atomically {
+        env.fs.write_atomically(path, &bytes).is_ok()
+    } else {
+        env.fs.write(path, &bytes).is_ok()
+    }
+}
@@ -15,7 +15,11 @@ use crate::frameworks::core_foundation::cf_run_loop::{
     kCFRunLoopCommonModes, kCFRunLoopDefaultMode, CFRunLoopRef,
 };
 use crate::frameworks::{core_animation, media_player, uikit};
-use crate::objc::{id, msg, objc_classes, release, retain, ClassExports, HostObject};
+use crate::objc::{id, msg, objc_classes, release, retain, ClassExports};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 use crate::Environment;
 use std::time::{Duration, Instant};
 
@@ -40,6 +44,9 @@ pub const CONSTANTS: ConstantExports = &[
 pub struct State {
     main_thread_run_loop: Option<id>,
 }
+impl_SaveState_for_struct!(State {
+    main_thread_run_loop
+});
 
 struct NSRunLoopHostObject {
     /// Weak reference. Audio queue must remove itself when destroyed (TODO).
@@ -49,7 +56,14 @@ struct NSRunLoopHostObject {
     /// by the run loop. The timer must remove itself when invalidated.
     timers: Vec<id>,
 }
-impl HostObject for NSRunLoopHostObject {}
+impl_HostObject_with_SaveState!(NSRunLoopHostObject);
+impl_SaveState_for_struct!(NSRunLoopHostObject {
+    audio_queues,
+    timers
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(NSRunLoopHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -166,7 +180,7 @@ pub fn run_run_loop_single_iteration(env: &mut Environment, run_loop: id) {
     run_run_loop(env, run_loop, /* single_iteration: */ true)
 }
 
-fn run_run_loop(env: &mut Environment, run_loop: id, single_iteration: bool) {
+fn run_run_loop(env: &mut Environment, mut run_loop: id, single_iteration: bool) {
     if single_iteration {
         log_dbg!("Entering run loop {:?} (single iteration)", run_loop);
     } else {
@@ -195,6 +209,18 @@ fn run_run_loop(env: &mut Environment, run_loop: id, single_iteration: bool) {
         let next_due = uikit::handle_events(env);
         limit_sleep_time(&mut sleep_until, next_due);
 
+        // Loading a save state replaces all objects, including this one.
+        if env.current_thread == 0 {
+            if let Some(main_thread_run_loop) = env
+                .framework_state
+                .foundation
+                .ns_run_loop
+                .main_thread_run_loop
+            {
+                run_loop = main_thread_run_loop;
+            }
+        }
+
         let next_due = core_animation::recomposite_if_necessary(env);
         limit_sleep_time(&mut sleep_until, next_due);
 
@@ -235,7 +261,7 @@ fn run_run_loop(env: &mut Environment, run_loop: id, single_iteration: bool) {
         // apps can't do more than 60fps so this should be fine.
         let limit = Duration::from_millis(1000 / 60);
         env.sleep(
-            sleep_until.map_or(limit, |i| i.duration_since(Instant::now()).min(limit)),
+            sleep_until.map_or(limit, |i| i.duration_since(env.clock.now()).min(limit)),
             false,
         );
 
@@ -11,7 +11,11 @@ use super::ns_enumerator::{fast_enumeration_helper, NSFastEnumerationState};
 use super::NSUInteger;
 use crate::mem::MutPtr;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, retain, ClassExports, HostObject, NSZonePtr,
+    autorelease, id, msg, msg_class, nil, objc_classes, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 
 /// Belongs to _touchHLE_NSSet
@@ -19,7 +23,10 @@ use crate::objc::{
 struct SetHostObject {
     dict: DictionaryHostObject,
 }
-impl HostObject for SetHostObject {}
+impl_HostObject_with_SaveState!(SetHostObject);
+impl_SaveState_for_struct!(SetHostObject { dict });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(SetHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
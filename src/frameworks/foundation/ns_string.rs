@@ -12,8 +12,8 @@ mod path_algorithms;
 
 use super::ns_array;
 use super::{
-    NSComparisonResult, NSNotFound, NSOrderedAscending, NSOrderedDescending, NSOrderedSame,
-    NSRange, NSUInteger,
+    NSComparisonResult, NSInteger, NSNotFound, NSOrderedAscending, NSOrderedDescending,
+    NSOrderedSame, NSRange, NSUInteger,
 };
 use crate::abi::VaList;
 use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
@@ -24,8 +24,12 @@ use crate::fs::GuestPath;
 use crate::mach_o::MachO;
 use crate::mem::{guest_size_of, ConstPtr, Mem, MutPtr, Ptr, SafeRead};
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, retain, Class, ClassExports, HostObject,
-    NSZonePtr, ObjC,
+    autorelease, id, msg, msg_class, nil, objc_classes, retain, Class, ClassExports, NSZonePtr,
+    ObjC,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_enum,
+    impl_SaveState_for_struct, HostObjectRestorers,
 };
 use crate::Environment;
 use std::borrow::Cow;
@@ -56,6 +60,7 @@ pub const NSMaximumStringLength: NSUInteger = (i32::MAX - 1) as _;
 pub struct State {
     static_str_pool: HashMap<&'static str, id>,
 }
+impl_SaveState_for_struct!(State { static_str_pool });
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
         &mut env.framework_state.foundation.ns_string
@@ -81,7 +86,11 @@ enum StringHostObject {
     /// Not necessarily well-formed UTF-16: might contain unpaired surrogates.
     Utf16(Utf16String),
 }
-impl HostObject for StringHostObject {}
+impl_HostObject_with_SaveState!(StringHostObject);
+impl_SaveState_for_enum!(StringHostObject { Utf8(string), Utf16(string) });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(StringHostObject)];
+
 impl StringHostObject {
     fn decode(bytes: Cow<[u8]>, encoding: NSStringEncoding) -> StringHostObject {
         if bytes.len() == 0 {
@@ -886,6 +895,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (f32)floatValue {
+    let value: f64 = msg![env; this doubleValue];
+    value as f32
+}
+
+- (f64)doubleValue {
     let st = to_rust_string(env, this);
     let st = st.trim_start();
     let mut cutoff = st.len();
@@ -913,6 +927,11 @@ pub const CLASSES: ClassExports = objc_classes! {
     st[..cutoff].parse().unwrap_or(0)
 }
 
+- (NSInteger)integerValue {
+    let value: i32 = msg![env; this intValue];
+    value as NSInteger
+}
+
 @end
 
 // Our private subclass that is the single implementation of NSString for the
@@ -6,7 +6,7 @@
 //! `NSThread`.
 
 use super::NSTimeInterval;
-use crate::dyld::HostFunction;
+use crate::dyld::{export_c_func, FunctionExports, HostFunction};
 use crate::frameworks::core_foundation::CFTypeRef;
 use crate::libc::pthread::thread::{
     pthread_attr_init, pthread_attr_setdetachstate, pthread_attr_t, pthread_create, pthread_t,
@@ -15,8 +15,11 @@ use crate::libc::pthread::thread::{
 use crate::mem::{guest_size_of, MutPtr};
 use crate::msg;
 use crate::objc::{
-    id, msg_send, nil, objc_classes, release, retain, Class, ClassExports, HostObject, NSZonePtr,
-    SEL,
+    id, msg_send, nil, objc_classes, release, retain, Class, ClassExports, NSZonePtr, SEL,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 use std::time::Duration;
@@ -26,7 +29,14 @@ struct NSThreadHostObject {
     selector: Option<SEL>,
     object: id,
 }
-impl HostObject for NSThreadHostObject {}
+impl_HostObject_with_SaveState!(NSThreadHostObject);
+impl_SaveState_for_struct!(NSThreadHostObject {
+    target,
+    selector,
+    object
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(NSThreadHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -126,3 +136,7 @@ pub fn _touchHLE_NSThreadInvocationHelper(env: &mut Environment, ns_thread_obj:
 
     // TODO: NSThread exit
 }
+
+/// See [crate::dyld::function_lists::PRIVATE_FUNCTION_LISTS].
+pub const PRIVATE_FUNCTIONS: FunctionExports =
+    &[export_c_func!(_touchHLE_NSThreadInvocationHelper(_))];
@@ -10,7 +10,11 @@ use super::NSTimeInterval;
 use super::{ns_run_loop, ns_string};
 use crate::objc::{
     autorelease, id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports,
-    HostObject, SEL,
+    SEL,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 use std::time::{Duration, Instant};
@@ -29,7 +33,19 @@ struct NSTimerHostObject {
     /// Weak reference
     run_loop: id,
 }
-impl HostObject for NSTimerHostObject {}
+impl_HostObject_with_SaveState!(NSTimerHostObject);
+impl_SaveState_for_struct!(NSTimerHostObject {
+    ns_interval,
+    rust_interval,
+    target,
+    selector,
+    user_info,
+    repeats,
+    due_by,
+    run_loop
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(NSTimerHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -56,7 +72,7 @@ pub const CLASSES: ClassExports = objc_classes! {
         selector,
         user_info,
         repeats,
-        due_by: Some(Instant::now().checked_add(rust_interval).unwrap()),
+        due_by: Some(env.clock.now().checked_add(rust_interval).unwrap()),
         run_loop: nil,
     });
     let new = env.objc.alloc_object(this, host_object, &mut env.mem);
@@ -159,7 +175,7 @@ pub(super) fn handle_timer(env: &mut Environment, timer: id) -> Option<Instant>
     // invalidated timers should have already been removed from the run loop
     let due_by = due_by.unwrap();
 
-    let now = Instant::now();
+    let now = env.clock.now();
 
     if due_by > now {
         return Some(due_by);
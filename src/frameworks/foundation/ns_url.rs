@@ -10,7 +10,11 @@ use super::NSUInteger;
 use crate::fs::{GuestPath, GuestPathBuf};
 use crate::mem::MutPtr;
 use crate::objc::{
-    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_enum,
+    HostObjectRestorers,
 };
 use crate::Environment;
 use std::borrow::Cow;
@@ -31,7 +35,16 @@ enum NSURLHostObject {
     /// Non-file URL.
     OtherURL { ns_string: id },
 }
-impl HostObject for NSURLHostObject {}
+impl_HostObject_with_SaveState!(NSURLHostObject);
+impl_SaveState_for_enum!(NSURLHostObject {
+    FileURL {
+        ns_string,
+        working_directory
+    },
+    OtherURL { ns_string },
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(NSURLHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
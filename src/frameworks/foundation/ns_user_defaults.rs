@@ -5,45 +5,273 @@
  */
 //! `NSUserDefaults`.
 //!
+//! Only the standard user defaults object is supported. Its values come from
+//! two domains, searched in this order:
+//! - The app's persistent domain, which is loaded from and saved to
+//!   `Library/Preferences/<bundle id>.plist` in the app's sandbox.
+//! - The registration domain, which is set up by `registerDefaults:` and not
+//!   persisted. We also put `AppleLanguages` here, though on a real device it
+//!   would come from the global domain.
+//!
 //! References:
 //! - Apple's [Preferences and Settings Programming Guide](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/UserDefaults/AboutPreferenceDomains/AboutPreferenceDomains.html).
 
-use super::ns_dictionary::dict_from_keys_and_objects;
-use super::ns_string;
-use crate::objc::{id, msg_class, objc_classes, ClassExports};
+use super::ns_dictionary::DictionaryHostObject;
+use super::ns_property_list_serialization::{
+    deserialize_plist_from_file, serialize_plist_dictionary,
+};
+use super::{ns_string, NSInteger};
+use crate::fs::GuestPathBuf;
+use crate::objc::{id, msg, msg_class, nil, objc_classes, release, Class, ClassExports, NSZonePtr};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
+};
 use crate::Environment;
 
 #[derive(Default)]
 pub struct State {
-    /// `NSDictionary*`
+    /// `NSUserDefaults*`
     standard_defaults: Option<id>,
 }
+impl_SaveState_for_struct!(State { standard_defaults });
 impl State {
     fn get(env: &mut Environment) -> &mut State {
         &mut env.framework_state.foundation.ns_user_defaults
     }
 }
 
+#[derive(Default)]
+struct NSUserDefaultsHostObject {
+    persistent_domain: DictionaryHostObject,
+    registration_domain: DictionaryHostObject,
+}
+impl_HostObject_with_SaveState!(NSUserDefaultsHostObject);
+impl_SaveState_for_struct!(NSUserDefaultsHostObject {
+    persistent_domain,
+    registration_domain,
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(NSUserDefaultsHostObject)];
+
+/// Path of the plist the app's persistent domain is stored in.
+fn persistent_domain_path(env: &Environment) -> GuestPathBuf {
+    env.fs
+        .home_directory()
+        .join("Library/Preferences")
+        .join(format!("{}.plist", env.bundle.bundle_identifier()))
+}
+
+/// Look up a key in each domain in turn. Returns `nil` if it's not found.
+fn lookup(env: &mut Environment, this: id, key: id) -> id {
+    let host_obj: NSUserDefaultsHostObject = std::mem::take(env.objc.borrow_mut(this));
+    let mut res = host_obj.persistent_domain.lookup(env, key);
+    if res == nil {
+        res = host_obj.registration_domain.lookup(env, key);
+    }
+    *env.objc.borrow_mut(this) = host_obj;
+    res
+}
+
+/// Like [lookup], but returns `nil` if the value isn't of the expected class.
+fn lookup_of_class(env: &mut Environment, this: id, key: id, class: Class) -> id {
+    let res = lookup(env, this, key);
+    if res != nil && msg![env; res isKindOfClass:class] {
+        res
+    } else {
+        nil
+    }
+}
+
+/// Write the standard user defaults to disk, if the app has used them. This
+/// should be called before exiting.
+pub fn synchronize_standard_defaults(env: &mut Environment) {
+    if let Some(defaults) = State::get(env).standard_defaults {
+        let _: bool = msg![env; defaults synchronize];
+    }
+}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
 
 @implementation NSUserDefaults: NSObject
 
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::<NSUserDefaultsHostObject>::default();
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
 + (id)standardUserDefaults {
     if let Some(existing) = State::get(env).standard_defaults {
-        existing
-    } else {
-        // TODO: Are there other default keys we need to set?
-        let langs_value: id = msg_class![env; NSLocale preferredLanguages];
-        let langs_key: id = ns_string::get_static_str(env, "AppleLanguages");
-        let new = dict_from_keys_and_objects(env, &[(langs_key, langs_value)]);
-        State::get(env).standard_defaults = Some(new);
-        new
+        return existing;
     }
+
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new init];
+
+    // TODO: Are there other default keys we need to set?
+    let langs_value: id = msg_class![env; NSLocale preferredLanguages];
+    let langs_key: id = ns_string::get_static_str(env, "AppleLanguages");
+    let mut host_obj: NSUserDefaultsHostObject = std::mem::take(env.objc.borrow_mut(new));
+    host_obj
+        .registration_domain
+        .insert(env, langs_key, langs_value, /* copy_key: */ true);
+
+    let path = persistent_domain_path(env);
+    if env.fs.exists(&path) {
+        log_dbg!("Loading user defaults from {:?}.", path);
+        let dict = deserialize_plist_from_file(env, &path, /* array_expected: */ false);
+        if dict != nil {
+            // Steal the dictionary's contents, rather than copying them.
+            host_obj.persistent_domain = std::mem::take(env.objc.borrow_mut(dict));
+            release(env, dict);
+        } else {
+            log!(
+                "Warning: Couldn't read user defaults from {:?}, ignoring.",
+                path
+            );
+        }
+    }
+    *env.objc.borrow_mut(new) = host_obj;
+
+    State::get(env).standard_defaults = Some(new);
+    new
 }
 
-// TODO: plist methods etc
+- (())dealloc {
+    let mut host_obj: NSUserDefaultsHostObject = std::mem::take(env.objc.borrow_mut(this));
+    host_obj.persistent_domain.release(env);
+    host_obj.registration_domain.release(env);
+
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (())registerDefaults:(id)defaults { // NSDictionary*
+    let pairs: Vec<(id, id)> = env
+        .objc
+        .borrow::<DictionaryHostObject>(defaults)
+        .iter_pairs()
+        .collect();
+    let mut host_obj: NSUserDefaultsHostObject = std::mem::take(env.objc.borrow_mut(this));
+    for (key, value) in pairs {
+        host_obj
+            .registration_domain
+            .insert(env, key, value, /* copy_key: */ true);
+    }
+    *env.objc.borrow_mut(this) = host_obj;
+}
+
+- (bool)synchronize {
+    let pairs: Vec<(id, id)> = env
+        .objc
+        .borrow::<NSUserDefaultsHostObject>(this)
+        .persistent_domain
+        .iter_pairs()
+        .collect();
+    let plist = match serialize_plist_dictionary(env, &pairs) {
+        Ok(plist) => plist,
+        Err(err) => {
+            log!("Warning: Couldn't serialize user defaults: {}", err);
+            return false;
+        }
+    };
+    let mut bytes = Vec::new();
+    plist.to_writer_binary(&mut bytes).unwrap();
+
+    let path = persistent_domain_path(env);
+    log_dbg!("Writing user defaults to {:?}.", path);
+    // Written atomically so that a crash can't leave a truncated file behind.
+    if env.fs.write_atomically(&path, &bytes).is_err() {
+        log!("Warning: Couldn't write user defaults to {:?}.", path);
+        return false;
+    }
+    true
+}
+
+- (id)objectForKey:(id)key { // NSString*
+    lookup(env, this, key)
+}
+- (id)stringForKey:(id)key { // NSString*
+    let class: Class = msg_class![env; NSString class];
+    lookup_of_class(env, this, key, class)
+}
+- (id)arrayForKey:(id)key { // NSString*
+    let class: Class = msg_class![env; NSArray class];
+    lookup_of_class(env, this, key, class)
+}
+- (id)dictionaryForKey:(id)key { // NSString*
+    let class: Class = msg_class![env; NSDictionary class];
+    lookup_of_class(env, this, key, class)
+}
+- (id)dataForKey:(id)key { // NSString*
+    let class: Class = msg_class![env; NSData class];
+    lookup_of_class(env, this, key, class)
+}
+// The scalar getters work for both NSNumber and NSString values, since both
+// implement the corresponding accessors.
+- (bool)boolForKey:(id)key { // NSString*
+    let value = lookup(env, this, key);
+    value != nil && msg![env; value boolValue]
+}
+- (NSInteger)integerForKey:(id)key { // NSString*
+    let value = lookup(env, this, key);
+    if value == nil {
+        return 0;
+    }
+    msg![env; value integerValue]
+}
+- (f32)floatForKey:(id)key { // NSString*
+    let value = lookup(env, this, key);
+    if value == nil {
+        return 0.0;
+    }
+    msg![env; value floatValue]
+}
+- (f64)doubleForKey:(id)key { // NSString*
+    let value = lookup(env, this, key);
+    if value == nil {
+        return 0.0;
+    }
+    msg![env; value doubleValue]
+}
+
+- (())setObject:(id)object forKey:(id)key { // NSString*
+    if object == nil {
+        return msg![env; this removeObjectForKey:key];
+    }
+    // Values are copied so later changes to mutable objects have no effect.
+    let object: id = msg![env; object copy];
+    let mut host_obj: NSUserDefaultsHostObject = std::mem::take(env.objc.borrow_mut(this));
+    host_obj
+        .persistent_domain
+        .insert(env, key, object, /* copy_key: */ true);
+    *env.objc.borrow_mut(this) = host_obj;
+    release(env, object);
+}
+- (())removeObjectForKey:(id)key { // NSString*
+    let mut host_obj: NSUserDefaultsHostObject = std::mem::take(env.objc.borrow_mut(this));
+    host_obj.persistent_domain.remove(env, key);
+    *env.objc.borrow_mut(this) = host_obj;
+}
+- (())setBool:(bool)value forKey:(id)key { // NSString*
+    let number: id = msg_class![env; NSNumber numberWithBool:value];
+    msg![env; this setObject:number forKey:key]
+}
+- (())setInteger:(NSInteger)value forKey:(id)key { // NSString*
+    let value: i64 = value.into();
+    let number: id = msg_class![env; NSNumber numberWithLongLong:value];
+    msg![env; this setObject:number forKey:key]
+}
+- (())setFloat:(f32)value forKey:(id)key { // NSString*
+    let number: id = msg_class![env; NSNumber numberWithFloat:value];
+    msg![env; this setObject:number forKey:key]
+}
+- (())setDouble:(f64)value forKey:(id)key { // NSString*
+    let number: id = msg_class![env; NSNumber numberWithDouble:value];
+    msg![env; this setObject:number forKey:key]
+}
 
 @end
 
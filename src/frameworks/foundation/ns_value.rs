@@ -5,21 +5,33 @@
  */
 //! The `NSValue` class cluster, including `NSNumber`.
 
-use super::NSUInteger;
+use super::{NSInteger, NSUInteger};
 use crate::frameworks::foundation::ns_string::from_rust_string;
 use crate::objc::{
-    autorelease, id, msg, msg_class, objc_classes, retain, Class, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, id, msg, msg_class, objc_classes, retain, Class, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_enum,
+    HostObjectRestorers,
 };
 
-enum NSNumberHostObject {
+pub(super) enum NSNumberHostObject {
     Bool(bool),
     UnsignedLongLong(u64),
     LongLong(i64),
     Float(f32),
     Double(f64),
 }
-impl HostObject for NSNumberHostObject {}
+impl_HostObject_with_SaveState!(NSNumberHostObject);
+impl_SaveState_for_enum!(NSNumberHostObject {
+    Bool(value),
+    UnsignedLongLong(value),
+    LongLong(value),
+    Float(value),
+    Double(value),
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(NSNumberHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -143,7 +155,47 @@ pub const CLASSES: ClassExports = objc_classes! {
     a == b
 }
 
-// TODO: accessors etc
+- (bool)boolValue {
+    match *env.objc.borrow(this) {
+        NSNumberHostObject::Bool(value) => value,
+        NSNumberHostObject::UnsignedLongLong(value) => value != 0,
+        NSNumberHostObject::LongLong(value) => value != 0,
+        NSNumberHostObject::Float(value) => value != 0.0,
+        NSNumberHostObject::Double(value) => value != 0.0,
+    }
+}
+- (i32)intValue {
+    let value: i64 = msg![env; this longLongValue];
+    value as i32
+}
+- (NSInteger)integerValue {
+    let value: i64 = msg![env; this longLongValue];
+    value as NSInteger
+}
+- (i64)longLongValue {
+    match *env.objc.borrow(this) {
+        NSNumberHostObject::Bool(value) => value as i64,
+        NSNumberHostObject::UnsignedLongLong(value) => value as i64,
+        NSNumberHostObject::LongLong(value) => value,
+        NSNumberHostObject::Float(value) => value as i64,
+        NSNumberHostObject::Double(value) => value as i64,
+    }
+}
+- (f32)floatValue {
+    let value: f64 = msg![env; this doubleValue];
+    value as f32
+}
+- (f64)doubleValue {
+    match *env.objc.borrow(this) {
+        NSNumberHostObject::Bool(value) => value as i32 as f64,
+        NSNumberHostObject::UnsignedLongLong(value) => value as f64,
+        NSNumberHostObject::LongLong(value) => value as f64,
+        NSNumberHostObject::Float(value) => value as f64,
+        NSNumberHostObject::Double(value) => value,
+    }
+}
+
+// TODO: more accessors
 
 @end
 
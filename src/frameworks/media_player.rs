@@ -5,6 +5,8 @@
  */
 //! The Media Player framework.
 
+use crate::save_state::impl_SaveState_for_struct;
+
 pub mod movie_player;
 pub mod music_player;
 
@@ -12,6 +14,7 @@ pub mod music_player;
 pub struct State {
     movie_player: movie_player::State,
 }
+impl_SaveState_for_struct!(State { movie_player });
 
 /// For use by `NSRunLoop`: check media players' status, send notifications if
 /// necessary.
@@ -4,12 +4,36 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `MPMoviePlayerController` etc.
+//!
+//! A movie plays fullscreen on top of the app, like on iPhone OS 2 and 3.
+//! While it plays, the compositor draws it instead of the app's layers (see
+//! [fullscreen_background_color]) and touches go to it rather than the app
+//! (see [handle_touch_event]).
+//!
+//! The movie's AAC-LC soundtrack is played (decoded as it plays) and the movie
+//! lasts as long as the file says, but the video track isn't decoded: touchHLE
+//! has no H.264 decoder, so the movie's background color is shown instead.
+//! For the same reason, `scalingMode` is stored but has no effect.
 
+use crate::audio;
+use crate::audio::openal as al;
+use crate::audio::openal::al_types::*;
+use crate::audio::openal::alc_types::*;
+use crate::audio::Movie;
 use crate::dyld::{ConstantExports, HostConstant};
 use crate::frameworks::foundation::{ns_string, ns_url, NSInteger};
-use crate::objc::{id, msg, msg_class, objc_classes, release, retain, ClassExports};
+use crate::frameworks::uikit::ui_color;
+use crate::objc::{
+    id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers, Reader, SaveState, Writer,
+};
+use crate::window::Event;
 use crate::Environment;
 use std::collections::VecDeque;
+use std::time::Instant;
 
 #[derive(Default)]
 pub struct State {
@@ -20,6 +44,25 @@ pub struct State {
     /// delay such notifications until the app next returns to the run loop,
     /// which seems to be late enough.
     pending_notifications: VecDeque<(&'static str, id)>,
+    /// The movie that is currently playing, if any. This can be [None] while
+    /// there's an active player, because playback may have finished or failed.
+    playback: Option<Playback>,
+}
+impl SaveState for State {
+    fn save(&self, w: &mut Writer) {
+        if self.playback.is_some() {
+            w.unsupported("Movie playback");
+        }
+        self.active_player.save(w);
+        self.pending_notifications.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(State {
+            active_player: SaveState::restore(r)?,
+            pending_notifications: SaveState::restore(r)?,
+            playback: None,
+        })
+    }
 }
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
@@ -27,7 +70,215 @@ impl State {
     }
 }
 
+struct Playback {
+    player: id,
+    ends_at: Instant,
+    /// Dropping the playback stops the sound.
+    soundtrack: Option<Soundtrack>,
+}
+
+/// How many OpenAL buffers a movie's soundtrack is streamed through.
+const SOUNDTRACK_BUFFER_COUNT: usize = 3;
+/// Each OpenAL buffer holds 1/[SOUNDTRACK_BUFFERS_PER_SECOND] seconds of the
+/// soundtrack.
+const SOUNDTRACK_BUFFERS_PER_SECOND: u32 = 4;
+
+/// OpenAL objects for playing a movie's soundtrack. Like the audio queue
+/// implementation, this uses its own device and context, so it doesn't
+/// interfere with the app's use of OpenAL.
+///
+/// The soundtrack is decoded a little at a time as it plays (see
+/// [Soundtrack::refill]), so long movies don't have to be decoded up front.
+struct Soundtrack {
+    device: *mut ALCdevice,
+    context: *mut ALCcontext,
+    source: ALuint,
+    buffers: [ALuint; SOUNDTRACK_BUFFER_COUNT],
+    file: audio::AudioFile,
+    format: ALenum,
+    sample_rate: ALsizei,
+    /// Size in bytes of the audio data put in each OpenAL buffer.
+    chunk_size: usize,
+    /// Byte offset of the next audio data to decode.
+    offset: u64,
+}
+impl Soundtrack {
+    fn play(file: audio::AudioFile) -> Option<Soundtrack> {
+        let description = file.audio_description();
+        let format = match description.channels_per_frame {
+            1 => al::AL_FORMAT_MONO16,
+            2 => al::AL_FORMAT_STEREO16,
+            _ => {
+                log!(
+                    "Movie soundtrack has {} channels, which isn't supported. Playing it silently.",
+                    description.channels_per_frame
+                );
+                return None;
+            }
+        };
+        let sample_rate = description.sample_rate as u32;
+        let chunk_size = (sample_rate / SOUNDTRACK_BUFFERS_PER_SECOND) as usize
+            * description.bytes_per_packet as usize;
+
+        let mut soundtrack = unsafe {
+            let device = audio::output::open_device();
+            assert!(!device.is_null());
+            let context = audio::output::create_context(device);
+            assert!(!context.is_null());
+
+            let old_context = al::alcGetCurrentContext();
+            assert!(al::alcMakeContextCurrent(context) == al::ALC_TRUE);
+            let mut buffers = [0; SOUNDTRACK_BUFFER_COUNT];
+            al::alGenBuffers(buffers.len() as ALsizei, buffers.as_mut_ptr());
+            let mut source = 0;
+            al::alGenSources(1, &mut source);
+            assert!(al::alGetError() == 0);
+            assert!(al::alcMakeContextCurrent(old_context) == al::ALC_TRUE);
+
+            Soundtrack {
+                device,
+                context,
+                source,
+                buffers,
+                file,
+                format,
+                sample_rate: sample_rate as ALsizei,
+                chunk_size,
+                offset: 0,
+            }
+        };
+        soundtrack.with_context(|soundtrack| {
+            for buffer in soundtrack.buffers {
+                soundtrack.queue_buffer(buffer);
+            }
+            unsafe {
+                al::alSourcePlay(soundtrack.source);
+                assert!(al::alGetError() == 0);
+            }
+        });
+        Some(soundtrack)
+    }
+
+    /// Run `f` with this soundtrack's OpenAL context made current.
+    fn with_context<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        unsafe {
+            let old_context = al::alcGetCurrentContext();
+            assert!(al::alcMakeContextCurrent(self.context) == al::ALC_TRUE);
+            let result = f(self);
+            assert!(al::alcMakeContextCurrent(old_context) == al::ALC_TRUE);
+            result
+        }
+    }
+
+    /// Decode the next part of the soundtrack into `buffer` and queue it.
+    /// Does nothing at the end of the soundtrack.
+    fn queue_buffer(&mut self, buffer: ALuint) {
+        let mut data = vec![0u8; self.chunk_size];
+        // Decoding errors are treated like the end of the soundtrack.
+        let bytes_read = self.file.read_bytes(self.offset, &mut data).unwrap_or(0);
+        if bytes_read == 0 {
+            return;
+        }
+        self.offset += bytes_read as u64;
+        unsafe {
+            al::alBufferData(
+                buffer,
+                self.format,
+                data.as_ptr() as *const ALvoid,
+                bytes_read.try_into().unwrap(),
+                self.sample_rate,
+            );
+            al::alSourceQueueBuffers(self.source, 1, &buffer);
+            assert!(al::alGetError() == 0);
+        }
+    }
+
+    /// Reuse OpenAL buffers that have finished playing for the next parts of
+    /// the soundtrack. This should be called regularly during playback.
+    fn refill(&mut self) {
+        self.with_context(|soundtrack| unsafe {
+            loop {
+                let mut buffers_processed = 0;
+                al::alGetSourcei(
+                    soundtrack.source,
+                    al::AL_BUFFERS_PROCESSED,
+                    &mut buffers_processed,
+                );
+                assert!(al::alGetError() == 0);
+                if buffers_processed == 0 {
+                    break;
+                }
+                let mut buffer = 0;
+                al::alSourceUnqueueBuffers(soundtrack.source, 1, &mut buffer);
+                assert!(al::alGetError() == 0);
+                soundtrack.queue_buffer(buffer);
+            }
+
+            // If touchHLE was too slow to refill the buffers, the source will
+            // have run out of data and stopped.
+            let mut buffers_queued = 0;
+            let mut source_state = 0;
+            al::alGetSourcei(
+                soundtrack.source,
+                al::AL_BUFFERS_QUEUED,
+                &mut buffers_queued,
+            );
+            al::alGetSourcei(soundtrack.source, al::AL_SOURCE_STATE, &mut source_state);
+            assert!(al::alGetError() == 0);
+            if buffers_queued > 0 && source_state == al::AL_STOPPED {
+                log_dbg!("Restarting movie soundtrack after it ran out of data");
+                al::alSourcePlay(soundtrack.source);
+            }
+        });
+    }
+}
+impl Drop for Soundtrack {
+    fn drop(&mut self) {
+        unsafe {
+            let old_context = al::alcGetCurrentContext();
+            assert!(al::alcMakeContextCurrent(self.context) == al::ALC_TRUE);
+            al::alSourceStop(self.source);
+            al::alDeleteSources(1, &self.source);
+            al::alDeleteBuffers(self.buffers.len() as ALsizei, self.buffers.as_ptr());
+            assert!(al::alGetError() == 0);
+            let old_context = if old_context == self.context {
+                std::ptr::null_mut()
+            } else {
+                old_context
+            };
+            assert!(al::alcMakeContextCurrent(old_context) == al::ALC_TRUE);
+            al::alcDestroyContext(self.context);
+            audio::output::close_device(self.device);
+        }
+    }
+}
+
+struct MPMoviePlayerControllerHostObject {
+    /// `NSURL*`
+    content_url: id,
+    /// `UIColor*`, or [nil] for the default (black).
+    background_color: id,
+    // TODO: Use this once video decoding is supported.
+    scaling_mode: MPMovieScalingMode,
+    movie_control_mode: MPMovieControlMode,
+}
+impl_HostObject_with_SaveState!(MPMoviePlayerControllerHostObject);
+impl_SaveState_for_struct!(MPMoviePlayerControllerHostObject {
+    content_url,
+    background_color,
+    scaling_mode,
+    movie_control_mode,
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(MPMoviePlayerControllerHostObject)];
+
 type MPMovieScalingMode = NSInteger;
+const MPMovieScalingModeAspectFit: MPMovieScalingMode = 1;
+
+/// Used by the undocumented `setMovieControlMode:`.
+type MPMovieControlMode = NSInteger;
+const MPMovieControlModeDefault: MPMovieControlMode = 0;
 
 // Values might not be correct, but as these are linked symbol constants, it
 // shouldn't matter.
@@ -56,17 +307,28 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @implementation MPMoviePlayerController: NSObject
 
-// TODO: actual playback
++ (id)allocWithZone:(NSZonePtr)_zone {
+    let host_object = Box::new(MPMoviePlayerControllerHostObject {
+        content_url: nil,
+        background_color: nil,
+        scaling_mode: MPMovieScalingModeAspectFit,
+        movie_control_mode: MPMovieControlModeDefault,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
 
 - (id)initWithContentURL:(id)url { // NSURL*
-    log!(
-        "TODO: [(MPMoviePlayerController*){:?} initWithContentURL:{:?} ({:?})]",
+    log_dbg!(
+        "[(MPMoviePlayerController*){:?} initWithContentURL:{:?} ({:?})]",
         this,
         url,
         ns_url::to_rust_path(env, url),
     );
+    retain(env, url);
+    env.objc.borrow_mut::<MPMoviePlayerControllerHostObject>(this).content_url = url;
 
-    // Act as if loading immediately completed (Spore Origins waits for this).
+    // The file is only loaded when playback starts, but there's no reason to
+    // make the app wait (Spore Origins waits for this).
     State::get(env).pending_notifications.push_back(
         (MPMoviePlayerContentPreloadDidFinishNotification, this)
     );
@@ -74,34 +336,62 @@ pub const CLASSES: ClassExports = objc_classes! {
     this
 }
 
-- (())setBackgroundColor:(id)_color { // UIColor*
-    // TODO
+- (())dealloc {
+    let &MPMoviePlayerControllerHostObject {
+        content_url,
+        background_color,
+        ..
+    } = env.objc.borrow(this);
+    release(env, content_url);
+    release(env, background_color);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)contentURL {
+    env.objc.borrow::<MPMoviePlayerControllerHostObject>(this).content_url
 }
 
-- (())setScalingMode:(MPMovieScalingMode)_mode {
-    // TODO
+- (id)backgroundColor {
+    env.objc.borrow::<MPMoviePlayerControllerHostObject>(this).background_color
+}
+- (())setBackgroundColor:(id)color { // UIColor*
+    retain(env, color);
+    let host_object = env.objc.borrow_mut::<MPMoviePlayerControllerHostObject>(this);
+    let old_color = std::mem::replace(&mut host_object.background_color, color);
+    release(env, old_color);
+}
+
+- (MPMovieScalingMode)scalingMode {
+    env.objc.borrow::<MPMoviePlayerControllerHostObject>(this).scaling_mode
+}
+- (())setScalingMode:(MPMovieScalingMode)mode {
+    env.objc.borrow_mut::<MPMoviePlayerControllerHostObject>(this).scaling_mode = mode;
 }
 
 // Apparently an undocumented, private API, but Spore Origins uses it.
-- (())setMovieControlMode:(NSInteger)_mode {
+- (MPMovieControlMode)movieControlMode {
+    env.objc.borrow::<MPMoviePlayerControllerHostObject>(this).movie_control_mode
+}
+- (())setMovieControlMode:(MPMovieControlMode)mode {
+    env.objc.borrow_mut::<MPMoviePlayerControllerHostObject>(this).movie_control_mode = mode;
+
     // Game-specific hack :(
     // Spore Origins subscribes to the playback finished notification 0.2s after
-    // starting playback, so it misses the notification we send. When it
-    // subscribes, it also calls this method, so this is an opportunity to send
-    // the notification again.
-    if env.bundle.bundle_identifier().starts_with("com.ea.spore") {
+    // starting playback, so it misses the notification we send if the movie
+    // can't be played. When it subscribes, it also calls this method, so this
+    // is an opportunity to send the notification again.
+    if env.bundle.bundle_identifier().starts_with("com.ea.spore")
+        && State::get(env).playback.is_none()
+    {
         log!("Applying game-specific hack for Spore Origins: sending MPMoviePlayerPlaybackDidFinishNotification again.");
         State::get(env).pending_notifications.push_back(
             (MPMoviePlayerPlaybackDidFinishNotification, this)
         );
     }
-    // As this is undocumented and we don't have real video playback yet, let's
-    // ignore it otherwise.
 }
 
 // MPMediaPlayback implementation
 - (())play {
-    log!("TODO: [(MPMoviePlayerController*){:?} play]", this);
     if let Some(old) = env.framework_state.media_player.movie_player.active_player {
         let _: () = msg![env; old stop];
     }
@@ -110,15 +400,37 @@ pub const CLASSES: ClassExports = objc_classes! {
     retain(env, this);
     env.framework_state.media_player.movie_player.active_player = Some(this);
 
-    // Act as if playback immediately completed (various apps wait for this).
-    State::get(env).pending_notifications.push_back(
-        (MPMoviePlayerPlaybackDidFinishNotification, this)
+    let url = env.objc.borrow::<MPMoviePlayerControllerHostObject>(this).content_url;
+    let path = ns_url::to_rust_path(env, url);
+    let Ok(movie) = Movie::open_for_reading(&path, &env.fs) else {
+        // Act as if playback immediately completed (various apps wait for
+        // this).
+        State::get(env).pending_notifications.push_back(
+            (MPMoviePlayerPlaybackDidFinishNotification, this)
+        );
+        return;
+    };
+    log!(
+        "Playing movie {:?} ({:?} long). Video decoding is not supported, so only the soundtrack will play.",
+        path,
+        movie.duration,
     );
+    let soundtrack = movie.soundtrack.and_then(Soundtrack::play);
+    let ends_at = env.clock.now() + movie.duration;
+    State::get(env).playback = Some(Playback {
+        player: this,
+        ends_at,
+        soundtrack,
+    });
 }
 
 - (())stop {
-    log!("TODO: [(MPMoviePlayerController*){:?} stop]", this);
-    assert!(this == env.framework_state.media_player.movie_player.active_player.take().unwrap());
+    log_dbg!("[(MPMoviePlayerController*){:?} stop]", this);
+    let state = State::get(env);
+    if state.playback.as_ref().is_some_and(|playback| playback.player == this) {
+        state.playback = None;
+    }
+    assert!(this == state.active_player.take().unwrap());
     release(env, this);
 }
 
@@ -126,9 +438,35 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 };
 
+/// End the current movie's playback and notify the app.
+fn finish_playback(env: &mut Environment) {
+    let Playback { player, .. } = State::get(env).playback.take().unwrap();
+    State::get(env)
+        .pending_notifications
+        .push_back((MPMoviePlayerPlaybackDidFinishNotification, player));
+}
+
 /// For use by `NSRunLoop` via [super::handle_players]: check movie players'
 /// status, send notifications if necessary.
 pub(super) fn handle_players(env: &mut Environment) {
+    if let Some(soundtrack) = State::get(env)
+        .playback
+        .as_mut()
+        .and_then(|playback| playback.soundtrack.as_mut())
+    {
+        soundtrack.refill();
+    }
+
+    let now = env.clock.now();
+    if State::get(env)
+        .playback
+        .as_ref()
+        .is_some_and(|playback| now >= playback.ends_at)
+    {
+        log_dbg!("Movie playback finished");
+        finish_playback(env);
+    }
+
     while let Some(notif) = State::get(env).pending_notifications.pop_front() {
         let (name, object) = notif;
         let name = ns_string::get_static_str(env, name);
@@ -137,3 +475,52 @@ pub(super) fn handle_players(env: &mut Environment) {
         let _: () = msg![env; center postNotificationName:name object:object];
     }
 }
+
+/// Whether a movie is playing, and therefore covering the screen.
+pub fn is_playing(env: &Environment) -> bool {
+    env.framework_state
+        .media_player
+        .movie_player
+        .playback
+        .is_some()
+}
+
+/// For use by the compositor: if a movie is playing, get the color it should
+/// fill the screen with, in place of the app's layers.
+pub fn fullscreen_background_color(env: &mut Environment) -> Option<(f32, f32, f32, f32)> {
+    let player = State::get(env).playback.as_ref()?.player;
+    let color = env
+        .objc
+        .borrow::<MPMoviePlayerControllerHostObject>(player)
+        .background_color;
+    Some(if color == nil {
+        (0.0, 0.0, 0.0, 1.0)
+    } else {
+        ui_color::get_rgba(&env.objc, color)
+    })
+}
+
+/// For use by UIKit's event handling: while a movie is playing, it covers the
+/// screen, so touches go to it rather than to the app. Tapping skips the movie,
+/// unless the player's controls are hidden. Returns [true] if the event was
+/// consumed.
+pub fn handle_touch_event(env: &mut Environment, event: &Event) -> bool {
+    let Some(player) = State::get(env)
+        .playback
+        .as_ref()
+        .map(|playback| playback.player)
+    else {
+        return false;
+    };
+    if let Event::TouchesUp(_) = event {
+        let control_mode = env
+            .objc
+            .borrow::<MPMoviePlayerControllerHostObject>(player)
+            .movie_control_mode;
+        if control_mode == MPMovieControlModeDefault {
+            log!("Movie skipped by tapping.");
+            finish_playback(env);
+        }
+    }
+    true
+}
@@ -11,6 +11,11 @@
 //! - [OpenAL 1.1 specification](https://www.openal.org/documentation/openal-1.1-specification.pdf)
 //! - Apple's [Technical Note TN2199: OpenAL FAQ for iPhone OS](https://web.archive.org/web/20090826202158/http://developer.apple.com/iPhone/library/technotes/tn2008/tn2199.html) (also available [here](https://developer.apple.com/library/archive/technotes/tn2199/_index.html))
 
+mod save_state;
+
+pub use save_state::recreate_after_load;
+
+use crate::audio;
 use crate::audio::openal as al;
 use crate::audio::openal::al_types::*;
 use crate::audio::openal::alc_types::*;
@@ -26,11 +31,41 @@ use touchHLE_openal_soft_wrapper::ALC_DEVICE_SPECIFIER;
 pub struct State {
     devices: HashMap<MutPtr<GuestALCdevice>, *mut ALCdevice>,
     contexts: HashMap<MutPtr<GuestALCcontext>, *mut ALCcontext>,
+    /// Used for save states.
+    record: save_state::Record,
+    /// OpenAL state from a loaded save state, waiting to be re-created.
+    pending_restore: Option<save_state::SavedState>,
 }
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
         &mut env.framework_state.openal
     }
+
+    /// Get the guest pointer for the current context, if any.
+    fn current_context(&self) -> Option<MutPtr<GuestALCcontext>> {
+        let host_context = unsafe { al::alcGetCurrentContext() };
+        self.contexts
+            .iter()
+            .find(|&(_, &host)| host == host_context)
+            .map(|(&guest, _)| guest)
+    }
+}
+impl Drop for State {
+    fn drop(&mut self) {
+        // This matters when a save state is loaded: the old devices and
+        // contexts have to go away before the new ones are created.
+        unsafe {
+            if !self.contexts.is_empty() {
+                al::alcMakeContextCurrent(std::ptr::null_mut());
+            }
+            for &context in self.contexts.values() {
+                al::alcDestroyContext(context);
+            }
+            for &device in self.devices.values() {
+                audio::output::close_device(device);
+            }
+        }
+    }
 }
 
 /// Opaque type in guest memory standing in for [ALCdevice] in host memory.
@@ -56,7 +91,7 @@ fn alcOpenDevice(env: &mut Environment, devicename: ConstPtr<u8>) -> MutPtr<Gues
         env.mem.free(d_name.cast_mut().cast());
     }
 
-    let res = unsafe { al::alcOpenDevice(std::ptr::null()) };
+    let res = unsafe { audio::output::open_device() };
     if res.is_null() {
         log_dbg!("alcOpenDevice(NULL) returned NULL");
         return Ptr::null();
@@ -64,13 +99,15 @@ fn alcOpenDevice(env: &mut Environment, devicename: ConstPtr<u8>) -> MutPtr<Gues
 
     let guest_res = env.mem.alloc_and_write(GuestALCdevice { _filler: 0 });
     State::get(env).devices.insert(guest_res, res);
+    State::get(env).record.open_device(guest_res);
     log_dbg!("alcOpenDevice(NULL) => {:?} (host: {:?})", guest_res, res,);
     guest_res
 }
 fn alcCloseDevice(env: &mut Environment, device: MutPtr<GuestALCdevice>) -> bool {
     let host_device = State::get(env).devices.remove(&device).unwrap();
+    State::get(env).record.close_device(device);
     env.mem.free(device.cast());
-    let res = unsafe { al::alcCloseDevice(host_device) };
+    let res = unsafe { audio::output::close_device(host_device) };
     log_dbg!("alcCloseDevice({:?}) => {:?}", device, res,);
     res != al::ALC_FALSE
 }
@@ -106,7 +143,7 @@ fn alcCreateContext(
 
     let &host_device = State::get(env).devices.get(&device).unwrap();
 
-    let res = unsafe { al::alcCreateContext(host_device, std::ptr::null()) };
+    let res = unsafe { audio::output::create_context(host_device) };
     if res.is_null() {
         log_dbg!("alcCreateContext({:?}, NULL) returned NULL", device);
         return Ptr::null();
@@ -114,6 +151,7 @@ fn alcCreateContext(
 
     let guest_res = env.mem.alloc_and_write(GuestALCcontext { _filler: 0 });
     State::get(env).contexts.insert(guest_res, res);
+    State::get(env).record.create_context(guest_res, device);
     log_dbg!(
         "alcCreateContext({:?}, NULL) => {:?} (host: {:?})",
         device,
@@ -124,6 +162,7 @@ fn alcCreateContext(
 }
 fn alcDestroyContext(env: &mut Environment, context: MutPtr<GuestALCcontext>) {
     let host_context = State::get(env).contexts.remove(&context).unwrap();
+    State::get(env).record.destroy_context(context);
     env.mem.free(context.cast());
     unsafe { al::alcDestroyContext(host_context) };
     log_dbg!("alcDestroyContext({:?})", context);
@@ -202,6 +241,11 @@ fn alcGetProcAddress(
 
 // === al.h ===
 
+/// Read an array of buffer or source names, for [save_state::Record].
+fn read_names(env: &Environment, names: ConstPtr<ALuint>, n: GuestUSize) -> Vec<ALuint> {
+    (0..n).map(|i| env.mem.read(names + i)).collect()
+}
+
 fn alGetError(_env: &mut Environment) -> i32 {
     // Super Monkey Ball tries to use this function (rather than alcGetError) to
     // figure out whether opening the device succeeded. This is not correct and
@@ -319,13 +363,19 @@ fn alGetListeneriv(env: &mut Environment, param: ALenum, values: MutPtr<ALint>)
 
 fn alGenSources(env: &mut Environment, n: ALsizei, sources: MutPtr<ALuint>) {
     let n_usize: GuestUSize = n.try_into().unwrap();
-    let sources = env.mem.ptr_at_mut(sources, n_usize);
-    unsafe { al::alGenSources(n, sources) };
+    let sources_slice = env.mem.ptr_at_mut(sources, n_usize);
+    unsafe { al::alGenSources(n, sources_slice) };
+    let names = read_names(env, sources.cast_const(), n_usize);
+    let state = State::get(env);
+    state.record.gen_sources(state.current_context(), &names);
 }
 fn alDeleteSources(env: &mut Environment, n: ALsizei, sources: ConstPtr<ALuint>) {
     let n_usize: GuestUSize = n.try_into().unwrap();
+    let names = read_names(env, sources, n_usize);
     let sources = env.mem.ptr_at(sources, n_usize);
     unsafe { al::alDeleteSources(n, sources) };
+    let state = State::get(env);
+    state.record.delete_sources(state.current_context(), &names);
 }
 
 fn alSourcef(_env: &mut Environment, source: ALuint, param: ALenum, value: ALfloat) {
@@ -346,8 +396,15 @@ fn alSource3f(
 ) {
     unsafe { al::alSource3f(source, param, value1, value2, value3) };
 }
-fn alSourcei(_env: &mut Environment, source: ALuint, param: ALenum, value: ALint) {
+fn alSourcei(env: &mut Environment, source: ALuint, param: ALenum, value: ALint) {
     unsafe { al::alSourcei(source, param, value) };
+    if param == al::AL_BUFFER {
+        let state = State::get(env);
+        let context = state.current_context();
+        state
+            .record
+            .set_source_buffer(context, source, value as ALuint);
+    }
 }
 fn alSource3i(
     _env: &mut Environment,
@@ -360,8 +417,16 @@ fn alSource3i(
     unsafe { al::alSource3i(source, param, value1, value2, value3) };
 }
 fn alSourceiv(env: &mut Environment, source: ALuint, param: ALenum, values: ConstPtr<ALint>) {
+    let value = env.mem.read(values);
     let values = env.mem.ptr_at(values, 3); // upper bound
     unsafe { al::alSourceiv(source, param, values) };
+    if param == al::AL_BUFFER {
+        let state = State::get(env);
+        let context = state.current_context();
+        state
+            .record
+            .set_source_buffer(context, source, value as ALuint);
+    }
 }
 
 fn alGetSourcef(env: &mut Environment, source: ALuint, param: ALenum, value: MutPtr<ALfloat>) {
@@ -443,8 +508,12 @@ fn alSourceQueueBuffers(
     buffers: ConstPtr<ALuint>,
 ) {
     let nb_usize: GuestUSize = nb.try_into().unwrap();
+    let names = read_names(env, buffers, nb_usize);
     let buffers = env.mem.ptr_at(buffers, nb_usize);
-    unsafe { al::alSourceQueueBuffers(source, nb, buffers) }
+    unsafe { al::alSourceQueueBuffers(source, nb, buffers) };
+    let state = State::get(env);
+    let context = state.current_context();
+    state.record.queue_buffers(context, source, &names);
 }
 fn alSourceUnqueueBuffers(
     env: &mut Environment,
@@ -481,18 +550,29 @@ fn alSourceUnqueueBuffers(
 
     let nb_usize: GuestUSize = nb.try_into().unwrap();
     let buffers = env.mem.ptr_at_mut(buffers, nb_usize);
-    unsafe { al::alSourceUnqueueBuffers(source, nb, buffers) }
+    unsafe { al::alSourceUnqueueBuffers(source, nb, buffers) };
+    let state = State::get(env);
+    let context = state.current_context();
+    state
+        .record
+        .unqueue_buffers(context, source, nb_usize as usize);
 }
 
 fn alGenBuffers(env: &mut Environment, n: ALsizei, buffers: MutPtr<ALuint>) {
     let n_usize: GuestUSize = n.try_into().unwrap();
-    let buffers = env.mem.ptr_at_mut(buffers, n_usize);
-    unsafe { al::alGenBuffers(n, buffers) };
+    let buffers_slice = env.mem.ptr_at_mut(buffers, n_usize);
+    unsafe { al::alGenBuffers(n, buffers_slice) };
+    let names = read_names(env, buffers.cast_const(), n_usize);
+    let state = State::get(env);
+    state.record.gen_buffers(state.current_context(), &names);
 }
 fn alDeleteBuffers(env: &mut Environment, n: ALsizei, buffers: ConstPtr<ALuint>) {
     let n_usize: GuestUSize = n.try_into().unwrap();
+    let names = read_names(env, buffers, n_usize);
     let buffers = env.mem.ptr_at(buffers, n_usize);
     unsafe { al::alDeleteBuffers(n, buffers) };
+    let state = State::get(env);
+    state.record.delete_buffers(state.current_context(), &names);
 }
 
 fn alBufferData(
@@ -514,6 +594,12 @@ fn alBufferData(
             samplerate,
         )
     };
+    let data = data_slice.to_vec();
+    let state = State::get(env);
+    let context = state.current_context();
+    state
+        .record
+        .buffer_data(context, buffer, format, data, samplerate);
 }
 
 /// This is an Apple extension that treats the data passed as a static buffer
@@ -0,0 +1,516 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Saving and re-creating OpenAL state for save states. See
+//! [crate::save_state].
+//!
+//! OpenAL has no way to get the contents of a buffer or the list of buffers
+//! queued on a source, so those are recorded as the app makes calls (see
+//! [Record]). Everything else is queried when saving. Loading a save state
+//! opens new devices and creates new contexts, and re-creates the buffers and
+//! sources with the same names, which works because OpenAL Soft always picks
+//! the lowest unused name.
+
+use super::{GuestALCcontext, GuestALCdevice, State};
+use crate::audio;
+use crate::audio::openal as al;
+use crate::audio::openal::al_types::*;
+use crate::mem::MutPtr;
+use crate::save_state::{impl_SaveState_for_struct, Reader, SaveState, Writer};
+use crate::Environment;
+use std::collections::BTreeMap;
+
+/// Data passed to `alBufferData`.
+#[derive(Clone)]
+pub(super) struct BufferData {
+    format: ALenum,
+    data: Vec<u8>,
+    frequency: ALsizei,
+}
+impl_SaveState_for_struct!(BufferData {
+    format,
+    data,
+    frequency
+});
+
+/// Buffers attached to a source.
+#[derive(Clone, Default)]
+pub(super) struct SourceBuffers {
+    /// Set if the buffer was attached with `AL_BUFFER` rather than queued.
+    is_static: bool,
+    queue: Vec<ALuint>,
+}
+impl_SaveState_for_struct!(SourceBuffers { is_static, queue });
+
+/// Record of OpenAL state that can't be queried.
+#[derive(Default)]
+pub(super) struct Record {
+    device_buffers: BTreeMap<MutPtr<GuestALCdevice>, BTreeMap<ALuint, Option<BufferData>>>,
+    context_devices: BTreeMap<MutPtr<GuestALCcontext>, MutPtr<GuestALCdevice>>,
+    context_sources: BTreeMap<MutPtr<GuestALCcontext>, BTreeMap<ALuint, SourceBuffers>>,
+}
+
+impl Record {
+    pub(super) fn open_device(&mut self, device: MutPtr<GuestALCdevice>) {
+        self.device_buffers.insert(device, BTreeMap::new());
+    }
+    pub(super) fn close_device(&mut self, device: MutPtr<GuestALCdevice>) {
+        self.device_buffers.remove(&device);
+    }
+    pub(super) fn create_context(
+        &mut self,
+        context: MutPtr<GuestALCcontext>,
+        device: MutPtr<GuestALCdevice>,
+    ) {
+        self.context_devices.insert(context, device);
+        self.context_sources.insert(context, BTreeMap::new());
+    }
+    pub(super) fn destroy_context(&mut self, context: MutPtr<GuestALCcontext>) {
+        self.context_devices.remove(&context);
+        self.context_sources.remove(&context);
+    }
+
+    fn buffers(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+    ) -> Option<&mut BTreeMap<ALuint, Option<BufferData>>> {
+        let device = self.context_devices.get(&context?)?;
+        self.device_buffers.get_mut(device)
+    }
+    pub(super) fn gen_buffers(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+        names: &[ALuint],
+    ) {
+        if let Some(buffers) = self.buffers(context) {
+            for &name in names {
+                buffers.insert(name, None);
+            }
+        }
+    }
+    pub(super) fn delete_buffers(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+        names: &[ALuint],
+    ) {
+        if let Some(buffers) = self.buffers(context) {
+            for name in names {
+                buffers.remove(name);
+            }
+        }
+    }
+    pub(super) fn buffer_data(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+        buffer: ALuint,
+        format: ALenum,
+        data: Vec<u8>,
+        frequency: ALsizei,
+    ) {
+        if let Some(buffers) = self.buffers(context) {
+            if let Some(buffer) = buffers.get_mut(&buffer) {
+                *buffer = Some(BufferData {
+                    format,
+                    data,
+                    frequency,
+                });
+            }
+        }
+    }
+
+    fn sources(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+    ) -> Option<&mut BTreeMap<ALuint, SourceBuffers>> {
+        self.context_sources.get_mut(&context?)
+    }
+    pub(super) fn gen_sources(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+        names: &[ALuint],
+    ) {
+        if let Some(sources) = self.sources(context) {
+            for &name in names {
+                sources.insert(name, SourceBuffers::default());
+            }
+        }
+    }
+    pub(super) fn delete_sources(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+        names: &[ALuint],
+    ) {
+        if let Some(sources) = self.sources(context) {
+            for name in names {
+                sources.remove(name);
+            }
+        }
+    }
+    /// For `alSourcei(source, AL_BUFFER, buffer)`.
+    pub(super) fn set_source_buffer(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+        source: ALuint,
+        buffer: ALuint,
+    ) {
+        if let Some(source) = self.sources(context).and_then(|s| s.get_mut(&source)) {
+            *source = SourceBuffers {
+                is_static: buffer != 0,
+                queue: if buffer != 0 {
+                    vec![buffer]
+                } else {
+                    Vec::new()
+                },
+            };
+        }
+    }
+    pub(super) fn queue_buffers(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+        source: ALuint,
+        buffers: &[ALuint],
+    ) {
+        if let Some(source) = self.sources(context).and_then(|s| s.get_mut(&source)) {
+            source.is_static = false;
+            source.queue.extend_from_slice(buffers);
+        }
+    }
+    pub(super) fn unqueue_buffers(
+        &mut self,
+        context: Option<MutPtr<GuestALCcontext>>,
+        source: ALuint,
+        count: usize,
+    ) {
+        if let Some(source) = self.sources(context).and_then(|s| s.get_mut(&source)) {
+            let count = count.min(source.queue.len());
+            source.queue.drain(..count);
+        }
+    }
+}
+
+const SOURCE_FLOAT_PARAMS: [ALenum; 10] = [
+    al::AL_PITCH,
+    al::AL_GAIN,
+    al::AL_MIN_GAIN,
+    al::AL_MAX_GAIN,
+    al::AL_MAX_DISTANCE,
+    al::AL_ROLLOFF_FACTOR,
+    al::AL_CONE_OUTER_GAIN,
+    al::AL_CONE_INNER_ANGLE,
+    al::AL_CONE_OUTER_ANGLE,
+    al::AL_REFERENCE_DISTANCE,
+];
+const SOURCE_VECTOR_PARAMS: [ALenum; 3] = [al::AL_POSITION, al::AL_VELOCITY, al::AL_DIRECTION];
+const SOURCE_INT_PARAMS: [ALenum; 2] = [al::AL_SOURCE_RELATIVE, al::AL_LOOPING];
+
+struct SavedSource {
+    buffers: SourceBuffers,
+    floats: [ALfloat; SOURCE_FLOAT_PARAMS.len()],
+    vectors: [[ALfloat; 3]; SOURCE_VECTOR_PARAMS.len()],
+    ints: [ALint; SOURCE_INT_PARAMS.len()],
+    state: ALint,
+    sample_offset: ALint,
+}
+impl_SaveState_for_struct!(SavedSource {
+    buffers,
+    floats,
+    vectors,
+    ints,
+    state,
+    sample_offset
+});
+
+struct SavedContext {
+    device: MutPtr<GuestALCdevice>,
+    distance_model: ALint,
+    doppler_factor: ALfloat,
+    doppler_velocity: ALfloat,
+    speed_of_sound: ALfloat,
+    listener_gain: ALfloat,
+    listener_position: [ALfloat; 3],
+    listener_velocity: [ALfloat; 3],
+    listener_orientation: [ALfloat; 6],
+    sources: BTreeMap<ALuint, SavedSource>,
+}
+impl_SaveState_for_struct!(SavedContext {
+    device,
+    distance_model,
+    doppler_factor,
+    doppler_velocity,
+    speed_of_sound,
+    listener_gain,
+    listener_position,
+    listener_velocity,
+    listener_orientation,
+    sources
+});
+
+/// OpenAL state from a save state that hasn't been re-created yet.
+pub(super) struct SavedState {
+    devices: BTreeMap<MutPtr<GuestALCdevice>, BTreeMap<ALuint, Option<BufferData>>>,
+    contexts: BTreeMap<MutPtr<GuestALCcontext>, SavedContext>,
+    current_context: Option<MutPtr<GuestALCcontext>>,
+}
+impl_SaveState_for_struct!(SavedState {
+    devices,
+    contexts,
+    current_context
+});
+
+unsafe fn save_source(source: ALuint, buffers: &SourceBuffers) -> SavedSource {
+    let mut saved = SavedSource {
+        buffers: buffers.clone(),
+        floats: Default::default(),
+        vectors: Default::default(),
+        ints: Default::default(),
+        state: 0,
+        sample_offset: 0,
+    };
+    for (&param, value) in SOURCE_FLOAT_PARAMS.iter().zip(saved.floats.iter_mut()) {
+        al::alGetSourcef(source, param, value);
+    }
+    for (&param, value) in SOURCE_VECTOR_PARAMS.iter().zip(saved.vectors.iter_mut()) {
+        al::alGetSourcefv(source, param, value.as_mut_ptr());
+    }
+    for (&param, value) in SOURCE_INT_PARAMS.iter().zip(saved.ints.iter_mut()) {
+        al::alGetSourcei(source, param, value);
+    }
+    al::alGetSourcei(source, al::AL_SOURCE_STATE, &mut saved.state);
+    al::alGetSourcei(source, al::AL_SAMPLE_OFFSET, &mut saved.sample_offset);
+    saved
+}
+
+unsafe fn save_context(
+    device: MutPtr<GuestALCdevice>,
+    sources: &BTreeMap<ALuint, SourceBuffers>,
+) -> SavedContext {
+    let mut saved = SavedContext {
+        device,
+        distance_model: al::alGetInteger(al::AL_DISTANCE_MODEL),
+        doppler_factor: al::alGetFloat(al::AL_DOPPLER_FACTOR),
+        doppler_velocity: al::alGetFloat(al::AL_DOPPLER_VELOCITY),
+        speed_of_sound: al::alGetFloat(al::AL_SPEED_OF_SOUND),
+        listener_gain: 0.0,
+        listener_position: Default::default(),
+        listener_velocity: Default::default(),
+        listener_orientation: Default::default(),
+        sources: sources
+            .iter()
+            .map(|(&source, buffers)| (source, save_source(source, buffers)))
+            .collect(),
+    };
+    al::alGetListenerf(al::AL_GAIN, &mut saved.listener_gain);
+    al::alGetListenerfv(al::AL_POSITION, saved.listener_position.as_mut_ptr());
+    al::alGetListenerfv(al::AL_VELOCITY, saved.listener_velocity.as_mut_ptr());
+    al::alGetListenerfv(al::AL_ORIENTATION, saved.listener_orientation.as_mut_ptr());
+    saved
+}
+
+impl State {
+    /// Save the state of OpenAL. This has to make each context current in
+    /// order to query it, but the current context is restored afterwards.
+    pub fn save_state(&self, w: &mut Writer) {
+        let record = &self.record;
+        let old_host_context = unsafe { al::alcGetCurrentContext() };
+
+        let contexts: BTreeMap<MutPtr<GuestALCcontext>, SavedContext> = record
+            .context_sources
+            .iter()
+            .map(|(&context, sources)| unsafe {
+                al::alcMakeContextCurrent(self.contexts[&context]);
+                let device = record.context_devices[&context];
+                (context, save_context(device, sources))
+            })
+            .collect();
+        unsafe { al::alcMakeContextCurrent(old_host_context) };
+
+        let current_context = self
+            .contexts
+            .iter()
+            .find(|&(_, &host)| host == old_host_context)
+            .map(|(&guest, _)| guest);
+
+        record.device_buffers.save(w);
+        contexts.save(w);
+        current_context.save(w);
+    }
+
+    /// Restore the state saved by [Self::save_state]. Nothing is re-created
+    /// until [recreate_after_load] is called.
+    pub fn restore_state(r: &mut Reader) -> Result<State, String> {
+        Ok(State {
+            devices: Default::default(),
+            contexts: Default::default(),
+            record: Default::default(),
+            pending_restore: Some(SavedState::restore(r)?),
+        })
+    }
+}
+
+/// Generate OpenAL names (of buffers or sources) so that exactly `names`
+/// exist, assuming that the implementation picks the lowest unused name.
+unsafe fn gen_exact_names(
+    names: &[ALuint],
+    gen: unsafe extern "C" fn(ALsizei, *mut ALuint),
+    delete: unsafe extern "C" fn(ALsizei, *const ALuint),
+) -> Result<(), String> {
+    let Some(&max) = names.iter().max() else {
+        return Ok(());
+    };
+    let mut generated = vec![0; max as usize];
+    gen(max as ALsizei, generated.as_mut_ptr());
+    if generated.iter().copied().ne(1..=max) {
+        delete(max as ALsizei, generated.as_ptr());
+        return Err(format!("OpenAL generated unexpected names {:?}", generated));
+    }
+    let extra: Vec<ALuint> = generated
+        .into_iter()
+        .filter(|name| !names.contains(name))
+        .collect();
+    delete(extra.len() as ALsizei, extra.as_ptr());
+    Ok(())
+}
+
+unsafe fn restore_source(source: ALuint, saved: &SavedSource) {
+    for (&param, &value) in SOURCE_FLOAT_PARAMS.iter().zip(saved.floats.iter()) {
+        al::alSourcef(source, param, value);
+    }
+    for (&param, value) in SOURCE_VECTOR_PARAMS.iter().zip(saved.vectors.iter()) {
+        al::alSourcefv(source, param, value.as_ptr());
+    }
+    for (&param, &value) in SOURCE_INT_PARAMS.iter().zip(saved.ints.iter()) {
+        al::alSourcei(source, param, value);
+    }
+    if saved.buffers.is_static {
+        al::alSourcei(source, al::AL_BUFFER, saved.buffers.queue[0] as ALint);
+    } else if !saved.buffers.queue.is_empty() {
+        let queue = &saved.buffers.queue;
+        al::alSourceQueueBuffers(source, queue.len() as ALsizei, queue.as_ptr());
+    }
+    // The offset can only be set on a source that has buffers, and it gets
+    // reset when the source is stopped, so playing the source first is
+    // necessary in all cases except the initial state.
+    match saved.state {
+        al::AL_PLAYING => {
+            al::alSourcePlay(source);
+        }
+        al::AL_PAUSED => {
+            al::alSourcePlay(source);
+            al::alSourcePause(source);
+        }
+        al::AL_STOPPED => {
+            al::alSourcePlay(source);
+            al::alSourceStop(source);
+        }
+        _ => (),
+    }
+    if saved.sample_offset != 0 && saved.state != al::AL_STOPPED {
+        al::alSourcei(source, al::AL_SAMPLE_OFFSET, saved.sample_offset);
+    }
+}
+
+/// Re-create the OpenAL devices, contexts, buffers and sources from a loaded
+/// save state. Problems are logged rather than treated as fatal, since the app
+/// may well be able to carry on without sound.
+pub fn recreate_after_load(env: &mut Environment) {
+    let Some(saved) = env.framework_state.openal.pending_restore.take() else {
+        return;
+    };
+    if let Err(e) = unsafe { recreate(&mut env.framework_state.openal, saved) } {
+        log!("Warning: couldn't fully re-create OpenAL state: {}", e);
+    }
+}
+
+unsafe fn recreate(state: &mut State, saved: SavedState) -> Result<(), String> {
+    let mut result = Ok(());
+
+    for (device, buffers) in saved.devices {
+        let host_device = audio::output::open_device();
+        if host_device.is_null() {
+            return Err("alcOpenDevice() failed".to_string());
+        }
+        state.devices.insert(device, host_device);
+
+        let mut device_contexts = saved
+            .contexts
+            .iter()
+            .filter(|(_, context)| context.device == device)
+            .peekable();
+        if device_contexts.peek().is_none() && !buffers.is_empty() {
+            // Buffers can't be created without a context.
+            result = Err(format!("Device {:?} has buffers but no context", device));
+        }
+
+        let mut first = true;
+        for (&context, saved_context) in device_contexts {
+            let host_context = audio::output::create_context(host_device);
+            if host_context.is_null() {
+                return Err("alcCreateContext() failed".to_string());
+            }
+            state.contexts.insert(context, host_context);
+            state.record.context_devices.insert(context, device);
+            al::alcMakeContextCurrent(host_context);
+
+            // Buffers belong to the device, so they're re-created along with
+            // its first context.
+            if first {
+                first = false;
+                let names: Vec<ALuint> = buffers.keys().copied().collect();
+                gen_exact_names(&names, al::alGenBuffers, al::alDeleteBuffers)?;
+                for (&buffer, data) in &buffers {
+                    if let Some(data) = data {
+                        al::alBufferData(
+                            buffer,
+                            data.format,
+                            data.data.as_ptr().cast(),
+                            data.data.len() as ALsizei,
+                            data.frequency,
+                        );
+                    }
+                }
+            }
+
+            al::alDistanceModel(saved_context.distance_model);
+            al::alDopplerFactor(saved_context.doppler_factor);
+            al::alDopplerVelocity(saved_context.doppler_velocity);
+            al::alSpeedOfSound(saved_context.speed_of_sound);
+            al::alListenerf(al::AL_GAIN, saved_context.listener_gain);
+            al::alListenerfv(al::AL_POSITION, saved_context.listener_position.as_ptr());
+            al::alListenerfv(al::AL_VELOCITY, saved_context.listener_velocity.as_ptr());
+            al::alListenerfv(
+                al::AL_ORIENTATION,
+                saved_context.listener_orientation.as_ptr(),
+            );
+
+            let names: Vec<ALuint> = saved_context.sources.keys().copied().collect();
+            gen_exact_names(&names, al::alGenSources, al::alDeleteSources)?;
+            for (&source, saved_source) in &saved_context.sources {
+                restore_source(source, saved_source);
+            }
+            state.record.context_sources.insert(
+                context,
+                saved_context
+                    .sources
+                    .iter()
+                    .map(|(&source, saved_source)| (source, saved_source.buffers.clone()))
+                    .collect(),
+            );
+
+            let error = al::alGetError();
+            if error != al::AL_NO_ERROR {
+                result = Err(format!("OpenAL error {:#x}", error));
+            }
+        }
+
+        state.record.device_buffers.insert(device, buffers);
+    }
+
+    let current_context = saved
+        .current_context
+        .map_or(std::ptr::null_mut(), |context| state.contexts[&context]);
+    al::alcMakeContextCurrent(current_context);
+
+    result
+}
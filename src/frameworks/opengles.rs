@@ -11,9 +11,14 @@
 
 pub mod eagl;
 mod gles_guest;
+mod gles_record;
 
+use crate::gles::GLES;
 use crate::mem::ConstPtr;
+use crate::save_state::{Reader, SaveState, Writer};
+use crate::Environment;
 pub use gles_guest::FUNCTIONS;
+use gles_record::GlesRecord;
 use touchHLE_gl_bindings::gles11::types::GLenum;
 
 #[derive(Default)]
@@ -31,15 +36,42 @@ impl State {
     }
 }
 
+/// The context's thread isn't saved, so that [sync_context] makes the context
+/// current again after loading.
+impl SaveState for State {
+    fn save(&self, w: &mut Writer) {
+        self.current_ctxs.save(w);
+        self.strings_cache.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(State {
+            current_ctxs: SaveState::restore(r)?,
+            current_ctx_thread: None,
+            strings_cache: SaveState::restore(r)?,
+        })
+    }
+}
+
 fn sync_context<'a>(
     state: &mut State,
     objc: &'a mut crate::objc::ObjC,
     window: &mut crate::window::Window,
     current_thread: crate::ThreadId,
-) -> &'a mut dyn crate::gles::GLES {
+) -> &'a mut dyn GLES {
+    sync_context_and_record(state, objc, window, current_thread).0
+}
+
+/// Like [sync_context], but also provides the context's [GlesRecord].
+fn sync_context_and_record<'a>(
+    state: &mut State,
+    objc: &'a mut crate::objc::ObjC,
+    window: &mut crate::window::Window,
+    current_thread: crate::ThreadId,
+) -> (&'a mut dyn GLES, &'a mut GlesRecord) {
     let current_ctx = state.current_ctx_for_thread(current_thread);
     let host_obj = objc.borrow_mut::<eagl::EAGLContextHostObject>(current_ctx.unwrap());
     let gles_ctx = host_obj.gles_ctx.as_deref_mut().unwrap();
+    let record = host_obj.record.as_mut().unwrap();
 
     if window.is_app_gl_ctx_no_longer_current() || state.current_ctx_thread != Some(current_thread)
     {
@@ -50,5 +82,69 @@ fn sync_context<'a>(
         gles_ctx.make_current(window);
     }
 
-    gles_ctx
+    (gles_ctx, record)
+}
+
+/// Query the state of each context that isn't recorded as the app makes calls,
+/// so that it's up-to-date in a save state. See [gles_record].
+pub fn prepare_for_save_state(env: &mut Environment) {
+    let Some(window) = env.window.as_mut() else {
+        return;
+    };
+    for context in env
+        .objc
+        .objects_with_host_object::<eagl::EAGLContextHostObject>()
+    {
+        let host_obj = env.objc.borrow_mut::<eagl::EAGLContextHostObject>(context);
+        let (Some(gles_ctx), Some(record)) = (&mut host_obj.gles_ctx, &mut host_obj.record) else {
+            continue;
+        };
+        gles_ctx.make_current(window);
+        unsafe { record.prepare_for_save(&mut **gles_ctx) };
+    }
+    // Make sure the right context is made current again.
+    env.framework_state.opengles.current_ctx_thread = None;
+}
+
+/// Create new contexts for the `EAGLContext`s from a loaded save state, and
+/// replay their recorded state. See [gles_record].
+pub fn recreate_contexts_after_load(env: &mut Environment) {
+    let contexts = env
+        .objc
+        .objects_with_host_object::<eagl::EAGLContextHostObject>();
+    if contexts.is_empty() {
+        return;
+    }
+    let window = env
+        .window
+        .as_mut()
+        .expect("OpenGL ES is not supported in headless mode");
+    for context in contexts {
+        let host_obj = env.objc.borrow_mut::<eagl::EAGLContextHostObject>(context);
+        let Some(ref record) = host_obj.record else {
+            continue;
+        };
+        let mut gles_ctx = crate::gles::create_gles1_ctx(window, &env.options);
+        gles_ctx.make_current(window);
+        unsafe {
+            record.replay(&mut *gles_ctx, &env.mem);
+            // Errors are drained so the app doesn't see them.
+            let mut errors = Vec::new();
+            loop {
+                match gles_ctx.GetError() {
+                    0 => break,
+                    error => errors.push(error),
+                }
+            }
+            if !errors.is_empty() {
+                log!(
+                    "Warning: re-creating OpenGL ES context for {:?} caused errors {:x?}, rendering may be wrong",
+                    context,
+                    errors
+                );
+            }
+        }
+        host_obj.gles_ctx = Some(gles_ctx);
+    }
+    env.framework_state.opengles.current_ctx_thread = None;
 }
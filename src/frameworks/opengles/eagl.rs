@@ -5,18 +5,24 @@
  */
 //! EAGL.
 
+use super::gles_record::GlesRecord;
 use crate::dyld::{ConstantExports, HostConstant};
 use crate::frameworks::core_animation::ca_eagl_layer::{
     find_fullscreen_eagl_layer, get_pixels_vec_for_presenting, present_pixels,
 };
 use crate::frameworks::foundation::ns_string::get_static_str;
 use crate::frameworks::foundation::NSUInteger;
+use crate::frameworks::media_player::movie_player;
 use crate::gles::gles11_raw as gles11; // constants only
 use crate::gles::gles11_raw::types::*;
 use crate::gles::present::{present_frame, FpsCounter};
 use crate::gles::{create_gles1_ctx, gles1_on_gl2, GLES};
-use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports, HostObject};
+use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports};
 use crate::options::Options;
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, HostObjectRestorers, Reader, SaveState,
+    Writer,
+};
 use crate::window::Window;
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
@@ -57,13 +63,38 @@ const kEAGLRenderingAPIOpenGLES3: EAGLRenderingAPI = 3;
 
 pub(super) struct EAGLContextHostObject {
     pub(super) gles_ctx: Option<Box<dyn GLES>>,
+    /// Record of the context's state for save states. This is [Some] once the
+    /// context has been created, even if `gles_ctx` hasn't been re-created
+    /// yet after loading a save state.
+    pub(super) record: Option<GlesRecord>,
     /// Mapping of OpenGL ES renderbuffer names to `EAGLDrawable` instances
     /// (always `CAEAGLLayer*`). Retains the instance so it won't dangle.
     renderbuffer_drawable_bindings: HashMap<GLuint, id>,
     fps_counter: Option<FpsCounter>,
     next_frame_due: Option<Instant>,
 }
-impl HostObject for EAGLContextHostObject {}
+impl_HostObject_with_SaveState!(EAGLContextHostObject);
+/// The context itself is re-created from the record by
+/// [super::recreate_contexts_after_load].
+impl SaveState for EAGLContextHostObject {
+    fn save(&self, w: &mut Writer) {
+        self.record.save(w);
+        self.renderbuffer_drawable_bindings.save(w);
+        self.next_frame_due.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(EAGLContextHostObject {
+            gles_ctx: None,
+            record: SaveState::restore(r)?,
+            renderbuffer_drawable_bindings: SaveState::restore(r)?,
+            fps_counter: None,
+            next_frame_due: SaveState::restore(r)?,
+        })
+    }
+}
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(EAGLContextHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -74,6 +105,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 + (id)alloc {
     let host_object = Box::new(EAGLContextHostObject {
         gles_ctx: None,
+        record: None,
         renderbuffer_drawable_bindings: HashMap::new(),
         fps_counter: None,
         next_frame_due: None,
@@ -125,7 +157,9 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.framework_state.opengles.current_ctx_thread = None;
     log!("Driver info: {}", unsafe { gles1_ctx.driver_description() });
 
-    env.objc.borrow_mut::<EAGLContextHostObject>(this).gles_ctx = Some(gles1_ctx);
+    let host_obj = env.objc.borrow_mut::<EAGLContextHostObject>(this);
+    host_obj.gles_ctx = Some(gles1_ctx);
+    host_obj.record = Some(GlesRecord::default());
 
     this
 }
@@ -172,9 +206,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 
     // Unclear from documentation if this method requires an appropriate context
     // to already be active, but that seems to be the case in practice?
-    let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, window, env.current_thread);
+    let (gles, record) = super::sync_context_and_record(&mut env.framework_state.opengles, &mut env.objc, window, env.current_thread);
     let renderbuffer: GLuint = unsafe {
-        gles.RenderbufferStorageOES(target, internalformat, width.try_into().unwrap(), height.try_into().unwrap());
+        let (width, height) = (width.try_into().unwrap(), height.try_into().unwrap());
+        gles.RenderbufferStorageOES(target, internalformat, width, height);
+        record.renderbuffer_storage(gles, internalformat, width, height);
         let mut renderbuffer = 0;
         gles.GetIntegerv(gles11::RENDERBUFFER_BINDING_OES, &mut renderbuffer);
         renderbuffer as _
@@ -197,7 +233,8 @@ pub const CLASSES: ClassExports = objc_classes! {
 
     // The presented frame should be displayed ASAP, but the next one must be
     // delayed, so this needs to be checked before returning.
-    let sleep_for = limit_framerate(&mut env.objc.borrow_mut::<EAGLContextHostObject>(this).next_frame_due, &env.options);
+    let now = env.clock.now();
+    let sleep_for = limit_framerate(&mut env.objc.borrow_mut::<EAGLContextHostObject>(this).next_frame_due, now, &env.options);
 
     if env.options.print_fps {
         env
@@ -228,6 +265,18 @@ pub const CLASSES: ClassExports = objc_classes! {
         .get(&renderbuffer)
         .expect("Can't present a renderbuffer not bound to a drawable!");
 
+    // A movie that is playing covers the app, so the output wouldn't be seen.
+    if movie_player::is_playing(env) {
+        log_dbg!(
+            "A movie is playing, skipping presentation of renderbuffer {:?}.",
+            renderbuffer,
+        );
+        if let Some(sleep_for) = sleep_for {
+            env.sleep(sleep_for, /* tail_call: */ false);
+        }
+        return true;
+    }
+
     // We're presenting to the opaque CAEAGLLayer that covers the screen.
     // We can use the fast path where we skip composition and present directly.
     if drawable == fullscreen_layer {
@@ -236,10 +285,11 @@ pub const CLASSES: ClassExports = objc_classes! {
             drawable,
             renderbuffer,
         );
+        let uptime = env.clock.uptime();
         // re-borrow
         let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, env.window.as_mut().unwrap(), env.current_thread);
         unsafe {
-            present_renderbuffer(gles, env.window.as_mut().unwrap());
+            present_renderbuffer(gles, env.window.as_mut().unwrap(), uptime);
         }
     } else {
         if fullscreen_layer != nil {
@@ -307,7 +357,11 @@ pub const CLASSES: ClassExports = objc_classes! {
 /// an interval's worth of accumulated slop. Allowing infinite accumulation of
 /// slop is not desirable, because if the game is running slowly for a long time
 /// and suddenly speeds back up, it will then run too fast for a long time.
-fn limit_framerate(next_frame_due: &mut Option<Instant>, options: &Options) -> Option<Duration> {
+fn limit_framerate(
+    next_frame_due: &mut Option<Instant>,
+    now: Instant,
+    options: &Options,
+) -> Option<Duration> {
     let interval = if let Some(fps) = options.fps_limit {
         1.0 / fps
     } else {
@@ -317,11 +371,10 @@ fn limit_framerate(next_frame_due: &mut Option<Instant>, options: &Options) -> O
 
     let &mut Some(current_frame_due) = next_frame_due else {
         // First frame presented: no delay yet.
-        *next_frame_due = Some(Instant::now() + interval_rust);
+        *next_frame_due = Some(now + interval_rust);
         return None;
     };
 
-    let now = Instant::now();
     *next_frame_due = if now > current_frame_due + interval_rust {
         // Too much slop has accumulated. Make the next frame wait for the next
         // interval.
@@ -474,7 +527,7 @@ unsafe fn read_renderbuffer(gles: &mut dyn GLES, mut pixel_buffer: Vec<u8>) -> (
 /// doing so. The front and back buffers are then swapped.
 ///
 /// The provided context must be current.
-unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window) {
+unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window, uptime: Duration) {
     // We can't directly copy the content of the renderbuffer to the default
     // framebuffer (the window), but if we attach it to a framebuffer object, we
     // can use glCopyTexImage2D() to copy it to a texture, which we can then
@@ -665,7 +718,10 @@ unsafe fn present_renderbuffer(gles: &mut dyn GLES, window: &mut Window) {
     );
 
     // SDL2's documentation warns 0 should be bound to the draw framebuffer
-    // when swapping the window, so this is the perfect moment.
+    // when swapping the window, so this is the perfect moment. Frame dumping
+    // also needs it to be bound.
+    window.dump_frame(gles, Some(uptime));
+    window.app_frame_presented(Some(gles));
     window.swap_window();
 
     // Restore the other bindings
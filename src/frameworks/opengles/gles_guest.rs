@@ -12,10 +12,13 @@
 //! depending on the value of `pname`, using the upper bound (4 in this case)
 //! every time is never going to cause a problem in practice.
 
+use super::gles_record::{
+    image_data_size, Attachment, GlesRecord, Params, StateCall, TextureImage,
+};
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::gles::gles11_raw as gles11; // constants only
 use crate::gles::GLES;
-use crate::mem::{ConstPtr, ConstVoidPtr, GuestISize, GuestUSize, Mem, MutPtr};
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestISize, GuestUSize, Mem, MutPtr, Ptr, SafeRead};
 use crate::Environment;
 
 // These types are the same size in guest code (32-bit) and host code (64-bit).
@@ -32,7 +35,16 @@ fn with_ctx_and_mem<T, U>(env: &mut Environment, f: T) -> U
 where
     T: FnOnce(&mut dyn GLES, &mut Mem) -> U,
 {
-    let gles = super::sync_context(
+    with_ctx_record_and_mem(env, |gles, _record, mem| f(gles, mem))
+}
+
+/// Like [with_ctx_and_mem], but also provides the context's [GlesRecord], for
+/// functions that change state that needs to be kept for save states.
+fn with_ctx_record_and_mem<T, U>(env: &mut Environment, f: T) -> U
+where
+    T: FnOnce(&mut dyn GLES, &mut GlesRecord, &mut Mem) -> U,
+{
+    let (gles, record) = super::sync_context_and_record(
         &mut env.framework_state.opengles,
         &mut env.objc,
         env.window
@@ -42,12 +54,24 @@ where
     );
 
     //panic_on_gl_errors(&mut **gles);
-    let res = f(gles, &mut env.mem);
+    let res = f(gles, record, &mut env.mem);
     //panic_on_gl_errors(&mut **gles);
     #[allow(clippy::let_and_return)]
     res
 }
 
+/// Copy the values of a `glFoofv`-style parameter for the [GlesRecord]. Like
+/// elsewhere, the upper bound of four values is used.
+fn read_params<T: SafeRead>(mem: &Mem, params: ConstPtr<T>) -> Vec<T> {
+    (0..4).map(|i| mem.read(params + i)).collect()
+}
+
+/// Copy the names written or read by a `glGenFoos` or `glDeleteFoos` call for
+/// the [GlesRecord].
+fn read_names<const MUT: bool>(mem: &Mem, names: Ptr<GLuint, MUT>, n: GuestUSize) -> Vec<GLuint> {
+    (0..n).map(|i| mem.read(names + i)).collect()
+}
+
 /// Useful for debugging
 #[allow(dead_code)]
 fn panic_on_gl_errors(gles: &mut dyn GLES) {
@@ -136,7 +160,10 @@ fn glGetPointerv(env: &mut Environment, pname: GLenum, params: MutPtr<ConstPtr<G
     })
 }
 fn glHint(env: &mut Environment, target: GLenum, mode: GLenum) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.Hint(target, mode) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Hint(target, mode);
+        record.state(gles, StateCall::Hint(target, mode));
+    })
 }
 fn glFlush(env: &mut Environment) {
     with_ctx_and_mem(env, |gles, _mem| unsafe { gles.Flush() })
@@ -176,14 +203,21 @@ fn glGetString(env: &mut Environment, name: GLenum) -> ConstPtr<GLubyte> {
 
 // Other state manipulation
 fn glAlphaFunc(env: &mut Environment, func: GLenum, ref_: GLclampf) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.AlphaFunc(func, ref_) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.AlphaFunc(func, ref_);
+        record.state(gles, StateCall::AlphaFunc(func, Params::Float(vec![ref_])));
+    })
 }
 fn glAlphaFuncx(env: &mut Environment, func: GLenum, ref_: GLclampx) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.AlphaFuncx(func, ref_) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.AlphaFuncx(func, ref_);
+        record.state(gles, StateCall::AlphaFunc(func, Params::Fixed(vec![ref_])));
+    })
 }
 fn glBlendFunc(env: &mut Environment, sfactor: GLenum, dfactor: GLenum) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.BlendFunc(sfactor, dfactor)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.BlendFunc(sfactor, dfactor);
+        record.state(gles, StateCall::BlendFunc(sfactor, dfactor));
     })
 }
 fn glColorMask(
@@ -193,40 +227,70 @@ fn glColorMask(
     blue: GLboolean,
     alpha: GLboolean,
 ) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.ColorMask(red, green, blue, alpha)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.ColorMask(red, green, blue, alpha);
+        record.state(gles, StateCall::ColorMask(red, green, blue, alpha));
     })
 }
 fn glCullFace(env: &mut Environment, mode: GLenum) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.CullFace(mode) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.CullFace(mode);
+        record.state(gles, StateCall::CullFace(mode));
+    })
 }
 fn glDepthFunc(env: &mut Environment, func: GLenum) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.DepthFunc(func) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.DepthFunc(func);
+        record.state(gles, StateCall::DepthFunc(func));
+    })
 }
 fn glDepthMask(env: &mut Environment, flag: GLboolean) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.DepthMask(flag) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.DepthMask(flag);
+        record.state(gles, StateCall::DepthMask(flag));
+    })
 }
 fn glDepthRangef(env: &mut Environment, near: GLclampf, far: GLclampf) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.DepthRangef(near, far) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.DepthRangef(near, far);
+        record.state(gles, StateCall::DepthRange(Params::Float(vec![near, far])));
+    })
 }
 fn glDepthRangex(env: &mut Environment, near: GLclampx, far: GLclampx) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.DepthRangex(near, far) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.DepthRangex(near, far);
+        record.state(gles, StateCall::DepthRange(Params::Fixed(vec![near, far])));
+    })
 }
 fn glFrontFace(env: &mut Environment, mode: GLenum) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.FrontFace(mode) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.FrontFace(mode);
+        record.state(gles, StateCall::FrontFace(mode));
+    })
 }
 fn glPolygonOffset(env: &mut Environment, factor: GLfloat, units: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.PolygonOffset(factor, units)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.PolygonOffset(factor, units);
+        record.state(
+            gles,
+            StateCall::PolygonOffset(Params::Float(vec![factor, units])),
+        );
     })
 }
 fn glPolygonOffsetx(env: &mut Environment, factor: GLfixed, units: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.PolygonOffsetx(factor, units)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.PolygonOffsetx(factor, units);
+        record.state(
+            gles,
+            StateCall::PolygonOffset(Params::Fixed(vec![factor, units])),
+        );
     })
 }
 fn glShadeModel(env: &mut Environment, mode: GLenum) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.ShadeModel(mode) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.ShadeModel(mode);
+        record.state(gles, StateCall::ShadeModel(mode));
+    })
 }
 fn glScissor(env: &mut Environment, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
     // apply scale hack: assume framebuffer's size is larger than the app thinks
@@ -234,8 +298,9 @@ fn glScissor(env: &mut Environment, x: GLint, y: GLint, width: GLsizei, height:
     let factor = env.options.scale_hack.get() as GLsizei;
     let (x, y) = (x * factor, y * factor);
     let (width, height) = (width * factor, height * factor);
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.Scissor(x, y, width, height)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Scissor(x, y, width, height);
+        record.state(gles, StateCall::Scissor(x, y, width, height));
     })
 }
 fn glViewport(env: &mut Environment, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
@@ -244,136 +309,239 @@ fn glViewport(env: &mut Environment, x: GLint, y: GLint, width: GLsizei, height:
     let factor = env.options.scale_hack.get() as GLsizei;
     let (x, y) = (x * factor, y * factor);
     let (width, height) = (width * factor, height * factor);
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.Viewport(x, y, width, height)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Viewport(x, y, width, height);
+        record.state(gles, StateCall::Viewport(x, y, width, height));
     })
 }
 fn glLineWidth(env: &mut Environment, val: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.LineWidth(val) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.LineWidth(val);
+        record.state(gles, StateCall::LineWidth(Params::Float(vec![val])));
+    })
 }
 fn glLineWidthx(env: &mut Environment, val: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.LineWidthx(val) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.LineWidthx(val);
+        record.state(gles, StateCall::LineWidth(Params::Fixed(vec![val])));
+    })
 }
 // Points
 fn glPointSize(env: &mut Environment, size: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.PointSize(size) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.PointSize(size);
+        record.state(gles, StateCall::PointSize(Params::Float(vec![size])));
+    })
 }
 fn glPointSizex(env: &mut Environment, size: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.PointSizex(size) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.PointSizex(size);
+        record.state(gles, StateCall::PointSize(Params::Fixed(vec![size])));
+    })
 }
 fn glPointParameterf(env: &mut Environment, pname: GLenum, param: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.PointParameterf(pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.PointParameterf(pname, param);
+        record.state(
+            gles,
+            StateCall::PointParameter(pname, Params::Float(vec![param])),
+        );
     })
 }
 fn glPointParameterx(env: &mut Environment, pname: GLenum, param: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.PointParameterx(pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.PointParameterx(pname, param);
+        record.state(
+            gles,
+            StateCall::PointParameter(pname, Params::Fixed(vec![param])),
+        );
     })
 }
 fn glPointParameterfv(env: &mut Environment, pname: GLenum, params: ConstPtr<GLfloat>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.PointParameterfv(pname, params) }
+        unsafe {
+            gles.PointParameterfv(pname, params);
+            record.state(
+                gles,
+                StateCall::PointParameter(pname, Params::Float(values)),
+            );
+        }
     })
 }
 fn glPointParameterxv(env: &mut Environment, pname: GLenum, params: ConstPtr<GLfixed>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.PointParameterxv(pname, params) }
+        unsafe {
+            gles.PointParameterxv(pname, params);
+            record.state(
+                gles,
+                StateCall::PointParameter(pname, Params::Fixed(values)),
+            );
+        }
     })
 }
 
 // Lighting and materials
 fn glFogf(env: &mut Environment, pname: GLenum, param: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.Fogf(pname, param) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Fogf(pname, param);
+        record.state(gles, StateCall::Fog(pname, Params::Float(vec![param])));
+    })
 }
 fn glFogx(env: &mut Environment, pname: GLenum, param: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.Fogx(pname, param) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Fogx(pname, param);
+        record.state(gles, StateCall::Fog(pname, Params::Fixed(vec![param])));
+    })
 }
 fn glFogfv(env: &mut Environment, pname: GLenum, params: ConstPtr<GLfloat>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.Fogfv(pname, params) }
+        unsafe {
+            gles.Fogfv(pname, params);
+            record.state(gles, StateCall::Fog(pname, Params::Float(values)));
+        }
     })
 }
 fn glFogxv(env: &mut Environment, pname: GLenum, params: ConstPtr<GLfixed>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.Fogxv(pname, params) }
+        unsafe {
+            gles.Fogxv(pname, params);
+            record.state(gles, StateCall::Fog(pname, Params::Fixed(values)));
+        }
     })
 }
 fn glLightf(env: &mut Environment, light: GLenum, pname: GLenum, param: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.Lightf(light, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Lightf(light, pname, param);
+        record.state(
+            gles,
+            StateCall::Light(light, pname, Params::Float(vec![param])),
+        );
     })
 }
 fn glLightx(env: &mut Environment, light: GLenum, pname: GLenum, param: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.Lightx(light, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Lightx(light, pname, param);
+        record.state(
+            gles,
+            StateCall::Light(light, pname, Params::Fixed(vec![param])),
+        );
     })
 }
 fn glLightfv(env: &mut Environment, light: GLenum, pname: GLenum, params: ConstPtr<GLfloat>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.Lightfv(light, pname, params) }
+        unsafe {
+            gles.Lightfv(light, pname, params);
+            record.state(gles, StateCall::Light(light, pname, Params::Float(values)));
+        }
     })
 }
 fn glLightxv(env: &mut Environment, light: GLenum, pname: GLenum, params: ConstPtr<GLfixed>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.Lightxv(light, pname, params) }
+        unsafe {
+            gles.Lightxv(light, pname, params);
+            record.state(gles, StateCall::Light(light, pname, Params::Fixed(values)));
+        }
     })
 }
 fn glLightModelf(env: &mut Environment, pname: GLenum, param: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.LightModelf(pname, param) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.LightModelf(pname, param);
+        record.state(
+            gles,
+            StateCall::LightModel(pname, Params::Float(vec![param])),
+        );
+    })
 }
 fn glLightModelfv(env: &mut Environment, pname: GLenum, params: ConstPtr<GLfloat>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.LightModelfv(pname, params) }
+        unsafe {
+            gles.LightModelfv(pname, params);
+            record.state(gles, StateCall::LightModel(pname, Params::Float(values)));
+        }
     })
 }
 fn glMaterialf(env: &mut Environment, face: GLenum, pname: GLenum, param: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.Materialf(face, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Materialf(face, pname, param);
+        record.state(
+            gles,
+            StateCall::Material(face, pname, Params::Float(vec![param])),
+        );
     })
 }
 fn glMaterialx(env: &mut Environment, face: GLenum, pname: GLenum, param: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.Materialx(face, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Materialx(face, pname, param);
+        record.state(
+            gles,
+            StateCall::Material(face, pname, Params::Fixed(vec![param])),
+        );
     })
 }
 fn glMaterialfv(env: &mut Environment, face: GLenum, pname: GLenum, params: ConstPtr<GLfloat>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.Materialfv(face, pname, params) }
+        unsafe {
+            gles.Materialfv(face, pname, params);
+            record.state(
+                gles,
+                StateCall::Material(face, pname, Params::Float(values)),
+            );
+        }
     })
 }
 fn glMaterialxv(env: &mut Environment, face: GLenum, pname: GLenum, params: ConstPtr<GLfixed>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.Materialxv(face, pname, params) }
+        unsafe {
+            gles.Materialxv(face, pname, params);
+            record.state(
+                gles,
+                StateCall::Material(face, pname, Params::Fixed(values)),
+            );
+        }
     })
 }
 
 // Textures
 fn glGenBuffers(env: &mut Environment, n: GLsizei, buffers: MutPtr<GLuint>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
         let n_usize: GuestUSize = n.try_into().unwrap();
+        let guest_buffers = buffers;
         let buffers = mem.ptr_at_mut(buffers, n_usize);
-        unsafe { gles.GenBuffers(n, buffers) }
+        unsafe { gles.GenBuffers(n, buffers) };
+        record.gen_buffers(&read_names(mem, guest_buffers, n_usize));
     })
 }
 fn glDeleteBuffers(env: &mut Environment, n: GLsizei, buffers: ConstPtr<GLuint>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
         let n_usize: GuestUSize = n.try_into().unwrap();
+        record.delete_buffers(&read_names(mem, buffers, n_usize));
         let buffers = mem.ptr_at(buffers, n_usize);
         unsafe { gles.DeleteBuffers(n, buffers) }
     })
 }
 fn glBindBuffer(env: &mut Environment, target: GLenum, buffer: GLuint) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.BindBuffer(target, buffer) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| {
+        unsafe { gles.BindBuffer(target, buffer) };
+        record.bind_buffer(buffer);
+    })
 }
 fn glBufferData(
     env: &mut Environment,
@@ -382,14 +550,19 @@ fn glBufferData(
     data: ConstPtr<GLvoid>,
     usage: GLenum,
 ) {
-    with_ctx_and_mem(env, |gles, mem| unsafe {
-        let data = if data.is_null() {
-            std::ptr::null()
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        let size_usize: GuestUSize = size.try_into().unwrap();
+        let (data, data_copy) = if data.is_null() {
+            (std::ptr::null(), vec![0; size_usize as usize])
         } else {
-            mem.ptr_at(data.cast::<u8>(), size.try_into().unwrap())
-                .cast()
+            let data = data.cast::<u8>();
+            (
+                mem.ptr_at(data, size_usize).cast(),
+                mem.bytes_at(data, size_usize).to_vec(),
+            )
         };
-        gles.BufferData(target, size as HostGLsizeiptr, data, usage)
+        gles.BufferData(target, size as HostGLsizeiptr, data, usage);
+        record.buffer_data(gles, target, data_copy, usage);
     })
 }
 
@@ -400,12 +573,15 @@ fn glBufferSubData(
     size: GuestGLsizeiptr,
     data: ConstPtr<GLvoid>,
 ) {
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
         let data = if data.is_null() {
             std::ptr::null()
         } else {
-            mem.ptr_at(data.cast::<u8>(), size.try_into().unwrap())
-                .cast()
+            let data = data.cast::<u8>();
+            let size = size.try_into().unwrap();
+            let offset = offset.try_into().unwrap();
+            record.buffer_sub_data(gles, target, offset, mem.bytes_at(data, size));
+            mem.ptr_at(data, size).cast()
         };
         gles.BufferSubData(target, offset as HostGLintptr, size as HostGLsizeiptr, data)
     })
@@ -413,25 +589,48 @@ fn glBufferSubData(
 
 // Non-pointers
 fn glColor4f(env: &mut Environment, red: GLfloat, green: GLfloat, blue: GLfloat, alpha: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.Color4f(red, green, blue, alpha)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Color4f(red, green, blue, alpha);
+        record.state(
+            gles,
+            StateCall::Color(Params::Float(vec![red, green, blue, alpha])),
+        );
     })
 }
 fn glColor4x(env: &mut Environment, red: GLfixed, green: GLfixed, blue: GLfixed, alpha: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.Color4x(red, green, blue, alpha)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Color4x(red, green, blue, alpha);
+        record.state(
+            gles,
+            StateCall::Color(Params::Fixed(vec![red, green, blue, alpha])),
+        );
     })
 }
 fn glColor4ub(env: &mut Environment, red: GLubyte, green: GLubyte, blue: GLubyte, alpha: GLubyte) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.Color4ub(red, green, blue, alpha)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Color4ub(red, green, blue, alpha);
+        record.state(
+            gles,
+            StateCall::Color(Params::Int(vec![
+                red.into(),
+                green.into(),
+                blue.into(),
+                alpha.into(),
+            ])),
+        );
     })
 }
 fn glNormal3f(env: &mut Environment, nx: GLfloat, ny: GLfloat, nz: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.Normal3f(nx, ny, nz) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Normal3f(nx, ny, nz);
+        record.state(gles, StateCall::Normal(Params::Float(vec![nx, ny, nz])));
+    })
 }
 fn glNormal3x(env: &mut Environment, nx: GLfixed, ny: GLfixed, nz: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.Normal3x(nx, ny, nz) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.Normal3x(nx, ny, nz);
+        record.state(gles, StateCall::Normal(Params::Fixed(vec![nx, ny, nz])));
+    })
 }
 
 // Pointers
@@ -465,13 +664,15 @@ fn glColorPointer(
     stride: GLsizei,
     pointer: ConstVoidPtr,
 ) {
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        record.pointer(gles, gles11::COLOR_ARRAY, size, type_, stride, pointer);
         let pointer = translate_pointer_or_offset(gles, mem, pointer, gles11::ARRAY_BUFFER_BINDING);
         gles.ColorPointer(size, type_, stride, pointer)
     })
 }
 fn glNormalPointer(env: &mut Environment, type_: GLenum, stride: GLsizei, pointer: ConstVoidPtr) {
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        record.pointer(gles, gles11::NORMAL_ARRAY, 3, type_, stride, pointer);
         let pointer = translate_pointer_or_offset(gles, mem, pointer, gles11::ARRAY_BUFFER_BINDING);
         gles.NormalPointer(type_, stride, pointer)
     })
@@ -483,7 +684,15 @@ fn glTexCoordPointer(
     stride: GLsizei,
     pointer: ConstVoidPtr,
 ) {
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        record.pointer(
+            gles,
+            gles11::TEXTURE_COORD_ARRAY,
+            size,
+            type_,
+            stride,
+            pointer,
+        );
         let pointer = translate_pointer_or_offset(gles, mem, pointer, gles11::ARRAY_BUFFER_BINDING);
         gles.TexCoordPointer(size, type_, stride, pointer)
     })
@@ -495,7 +704,8 @@ fn glVertexPointer(
     stride: GLsizei,
     pointer: ConstVoidPtr,
 ) {
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        record.pointer(gles, gles11::VERTEX_ARRAY, size, type_, stride, pointer);
         let pointer = translate_pointer_or_offset(gles, mem, pointer, gles11::ARRAY_BUFFER_BINDING);
         gles.VertexPointer(size, type_, stride, pointer)
     })
@@ -532,8 +742,12 @@ fn glClearColor(
     blue: GLclampf,
     alpha: GLclampf,
 ) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.ClearColor(red, green, blue, alpha)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.ClearColor(red, green, blue, alpha);
+        record.state(
+            gles,
+            StateCall::ClearColor(Params::Float(vec![red, green, blue, alpha])),
+        );
     });
 }
 fn glClearColorx(
@@ -543,18 +757,31 @@ fn glClearColorx(
     blue: GLclampx,
     alpha: GLclampx,
 ) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.ClearColorx(red, green, blue, alpha)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.ClearColorx(red, green, blue, alpha);
+        record.state(
+            gles,
+            StateCall::ClearColor(Params::Fixed(vec![red, green, blue, alpha])),
+        );
     });
 }
 fn glClearDepthf(env: &mut Environment, depth: GLclampf) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.ClearDepthf(depth) });
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.ClearDepthf(depth);
+        record.state(gles, StateCall::ClearDepth(Params::Float(vec![depth])));
+    });
 }
 fn glClearDepthx(env: &mut Environment, depth: GLclampx) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.ClearDepthx(depth) });
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.ClearDepthx(depth);
+        record.state(gles, StateCall::ClearDepth(Params::Fixed(vec![depth])));
+    });
 }
 fn glClearStencil(env: &mut Environment, s: GLint) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.ClearStencil(s) });
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.ClearStencil(s);
+        record.state(gles, StateCall::ClearStencil(s));
+    });
 }
 
 // Matrix stack operations
@@ -687,18 +914,24 @@ fn glTranslatex(env: &mut Environment, x: GLfixed, y: GLfixed, z: GLfixed) {
 
 // Textures
 fn glPixelStorei(env: &mut Environment, pname: GLenum, param: GLint) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.PixelStorei(pname, param) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.PixelStorei(pname, param);
+        record.state(gles, StateCall::PixelStore(pname, param));
+    })
 }
 fn glGenTextures(env: &mut Environment, n: GLsizei, textures: MutPtr<GLuint>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
         let n_usize: GuestUSize = n.try_into().unwrap();
+        let guest_textures = textures;
         let textures = mem.ptr_at_mut(textures, n_usize);
-        unsafe { gles.GenTextures(n, textures) }
+        unsafe { gles.GenTextures(n, textures) };
+        record.gen_textures(&read_names(mem, guest_textures, n_usize));
     })
 }
 fn glDeleteTextures(env: &mut Environment, n: GLsizei, textures: ConstPtr<GLuint>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
         let n_usize: GuestUSize = n.try_into().unwrap();
+        record.delete_textures(&read_names(mem, textures, n_usize));
         let textures = mem.ptr_at(textures, n_usize);
         unsafe { gles.DeleteTextures(n, textures) }
     })
@@ -707,8 +940,9 @@ fn glActiveTexture(env: &mut Environment, texture: GLenum) {
     with_ctx_and_mem(env, |gles, _mem| unsafe { gles.ActiveTexture(texture) })
 }
 fn glBindTexture(env: &mut Environment, target: GLenum, texture: GLuint) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.BindTexture(target, texture)
+    with_ctx_record_and_mem(env, |gles, record, _mem| {
+        unsafe { gles.BindTexture(target, texture) };
+        record.bind_texture(texture);
     })
 }
 fn glTexParameteri(env: &mut Environment, target: GLenum, pname: GLenum, param: GLint) {
@@ -717,8 +951,9 @@ fn glTexParameteri(env: &mut Environment, target: GLenum, pname: GLenum, param:
     if pname == gles11::TEXTURE_CROP_RECT_OES {
         return;
     }
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.TexParameteri(target, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.TexParameteri(target, pname, param);
+        record.tex_parameter(gles, pname, Params::Int(vec![param]));
     })
 }
 fn glTexParameterf(env: &mut Environment, target: GLenum, pname: GLenum, param: GLfloat) {
@@ -726,8 +961,9 @@ fn glTexParameterf(env: &mut Environment, target: GLenum, pname: GLenum, param:
     if pname == gles11::TEXTURE_CROP_RECT_OES {
         return;
     }
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.TexParameterf(target, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.TexParameterf(target, pname, param);
+        record.tex_parameter(gles, pname, Params::Float(vec![param]));
     })
 }
 fn glTexParameterx(env: &mut Environment, target: GLenum, pname: GLenum, param: GLfixed) {
@@ -735,8 +971,9 @@ fn glTexParameterx(env: &mut Environment, target: GLenum, pname: GLenum, param:
     if pname == gles11::TEXTURE_CROP_RECT_OES {
         return;
     }
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.TexParameterx(target, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.TexParameterx(target, pname, param);
+        record.tex_parameter(gles, pname, Params::Fixed(vec![param]));
     })
 }
 fn glTexParameteriv(env: &mut Environment, target: GLenum, pname: GLenum, params: ConstPtr<GLint>) {
@@ -744,9 +981,11 @@ fn glTexParameteriv(env: &mut Environment, target: GLenum, pname: GLenum, params
     if pname == gles11::TEXTURE_CROP_RECT_OES {
         return;
     }
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        let value = mem.read(params);
         let params = mem.ptr_at(params, 1 /* upper bound */);
-        gles.TexParameteriv(target, pname, params)
+        gles.TexParameteriv(target, pname, params);
+        record.tex_parameter(gles, pname, Params::Int(vec![value]));
     })
 }
 fn glTexParameterfv(
@@ -759,9 +998,11 @@ fn glTexParameterfv(
     if pname == gles11::TEXTURE_CROP_RECT_OES {
         return;
     }
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        let value = mem.read(params);
         let params = mem.ptr_at(params, 1 /* upper bound */);
-        gles.TexParameterfv(target, pname, params)
+        gles.TexParameterfv(target, pname, params);
+        record.tex_parameter(gles, pname, Params::Float(vec![value]));
     })
 }
 fn glTexParameterxv(
@@ -774,9 +1015,11 @@ fn glTexParameterxv(
     if pname == gles11::TEXTURE_CROP_RECT_OES {
         return;
     }
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        let value = mem.read(params);
         let params = mem.ptr_at(params, 1 /* upper bound */);
-        gles.TexParameterxv(target, pname, params)
+        gles.TexParameterxv(target, pname, params);
+        record.tex_parameter(gles, pname, Params::Fixed(vec![value]));
     })
 }
 fn image_size_estimate(pixel_count: GuestUSize, format: GLenum, type_: GLenum) -> GuestUSize {
@@ -809,7 +1052,14 @@ fn glTexImage2D(
     type_: GLenum,
     pixels: ConstVoidPtr,
 ) {
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        let mut unpack_alignment = 0;
+        gles.GetIntegerv(gles11::UNPACK_ALIGNMENT, &mut unpack_alignment);
+        let pixels_copy = (!pixels.is_null()).then(|| {
+            let bytes_per_pixel = image_size_estimate(1, format, type_);
+            let size = image_data_size(width, height, bytes_per_pixel, unpack_alignment);
+            mem.bytes_at(pixels.cast::<u8>(), size).to_vec()
+        });
         let pixels = if pixels.is_null() {
             std::ptr::null()
         } else {
@@ -827,7 +1077,21 @@ fn glTexImage2D(
             format,
             type_,
             pixels,
-        )
+        );
+        record.tex_image(
+            gles,
+            TextureImage::Image {
+                level,
+                internalformat,
+                width,
+                height,
+                border,
+                format,
+                type_,
+                unpack_alignment,
+                pixels: pixels_copy,
+            },
+        );
     })
 }
 fn glTexSubImage2D(
@@ -842,13 +1106,32 @@ fn glTexSubImage2D(
     type_: GLenum,
     pixels: ConstVoidPtr,
 ) {
-    with_ctx_and_mem(env, |gles, mem| unsafe {
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        let mut unpack_alignment = 0;
+        gles.GetIntegerv(gles11::UNPACK_ALIGNMENT, &mut unpack_alignment);
+        let bytes_per_pixel = image_size_estimate(1, format, type_);
+        let size = image_data_size(width, height, bytes_per_pixel, unpack_alignment);
+        let pixels_copy = mem.bytes_at(pixels.cast::<u8>(), size).to_vec();
         let pixel_count: GuestUSize = width.checked_mul(height).unwrap().try_into().unwrap();
         let size = image_size_estimate(pixel_count, format, type_);
         let pixels = mem.ptr_at(pixels.cast::<u8>(), size).cast::<GLvoid>();
         gles.TexSubImage2D(
             target, level, xoffset, yoffset, width, height, format, type_, pixels,
-        )
+        );
+        record.tex_image(
+            gles,
+            TextureImage::SubImage {
+                level,
+                xoffset,
+                yoffset,
+                width,
+                height,
+                format,
+                type_,
+                unpack_alignment,
+                pixels: pixels_copy,
+            },
+        );
     })
 }
 fn glCompressedTexImage2D(
@@ -862,10 +1145,10 @@ fn glCompressedTexImage2D(
     image_size: GLsizei,
     data: ConstVoidPtr,
 ) {
-    with_ctx_and_mem(env, |gles, mem| unsafe {
-        let data = mem
-            .ptr_at(data.cast::<u8>(), image_size.try_into().unwrap())
-            .cast();
+    with_ctx_record_and_mem(env, |gles, record, mem| unsafe {
+        let image_size_usize: GuestUSize = image_size.try_into().unwrap();
+        let data_copy = mem.bytes_at(data.cast::<u8>(), image_size_usize).to_vec();
+        let data = mem.ptr_at(data.cast::<u8>(), image_size_usize).cast();
         gles.CompressedTexImage2D(
             target,
             level,
@@ -875,7 +1158,18 @@ fn glCompressedTexImage2D(
             border,
             image_size,
             data,
-        )
+        );
+        record.tex_image(
+            gles,
+            TextureImage::Compressed {
+                level,
+                internalformat,
+                width,
+                height,
+                border,
+                data: data_copy,
+            },
+        );
     })
 }
 fn glCopyTexImage2D(
@@ -889,8 +1183,9 @@ fn glCopyTexImage2D(
     height: GLsizei,
     border: GLint,
 ) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.CopyTexImage2D(target, level, internalformat, x, y, width, height, border)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.CopyTexImage2D(target, level, internalformat, x, y, width, height, border);
+        record.copy_tex_image(gles, level, Some((width, height)));
     })
 }
 fn glCopyTexSubImage2D(
@@ -904,73 +1199,113 @@ fn glCopyTexSubImage2D(
     width: GLsizei,
     height: GLsizei,
 ) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.CopyTexSubImage2D(target, level, xoffset, yoffset, x, y, width, height)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.CopyTexSubImage2D(target, level, xoffset, yoffset, x, y, width, height);
+        record.copy_tex_image(gles, level, None);
     })
 }
 fn glTexEnvf(env: &mut Environment, target: GLenum, pname: GLenum, param: GLfloat) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.TexEnvf(target, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.TexEnvf(target, pname, param);
+        record.state(
+            gles,
+            StateCall::TexEnv(0, target, pname, Params::Float(vec![param])),
+        );
     })
 }
 fn glTexEnvx(env: &mut Environment, target: GLenum, pname: GLenum, param: GLfixed) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.TexEnvx(target, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.TexEnvx(target, pname, param);
+        record.state(
+            gles,
+            StateCall::TexEnv(0, target, pname, Params::Fixed(vec![param])),
+        );
     })
 }
 fn glTexEnvi(env: &mut Environment, target: GLenum, pname: GLenum, param: GLint) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.TexEnvi(target, pname, param)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.TexEnvi(target, pname, param);
+        record.state(
+            gles,
+            StateCall::TexEnv(0, target, pname, Params::Int(vec![param])),
+        );
     })
 }
 fn glTexEnvfv(env: &mut Environment, target: GLenum, pname: GLenum, params: ConstPtr<GLfloat>) {
     // TODO: GL_POINT_SPRITE_OES
     assert!(target == gles11::TEXTURE_ENV);
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.TexEnvfv(target, pname, params) }
+        unsafe {
+            gles.TexEnvfv(target, pname, params);
+            record.state(
+                gles,
+                StateCall::TexEnv(0, target, pname, Params::Float(values)),
+            );
+        }
     })
 }
 fn glTexEnvxv(env: &mut Environment, target: GLenum, pname: GLenum, params: ConstPtr<GLfixed>) {
     // TODO: GL_POINT_SPRITE_OES
     assert!(target == gles11::TEXTURE_ENV);
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.TexEnvxv(target, pname, params) }
+        unsafe {
+            gles.TexEnvxv(target, pname, params);
+            record.state(
+                gles,
+                StateCall::TexEnv(0, target, pname, Params::Fixed(values)),
+            );
+        }
     })
 }
 fn glTexEnviv(env: &mut Environment, target: GLenum, pname: GLenum, params: ConstPtr<GLint>) {
     // TODO: GL_POINT_SPRITE_OES
     assert!(target == gles11::TEXTURE_ENV);
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
+        let values = read_params(mem, params);
         let params = mem.ptr_at(params, 4 /* upper bound */);
-        unsafe { gles.TexEnviv(target, pname, params) }
+        unsafe {
+            gles.TexEnviv(target, pname, params);
+            record.state(
+                gles,
+                StateCall::TexEnv(0, target, pname, Params::Int(values)),
+            );
+        }
     })
 }
 
 // OES_framebuffer_object
 fn glGenFramebuffersOES(env: &mut Environment, n: GLsizei, framebuffers: MutPtr<GLuint>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
         let n_usize: GuestUSize = n.try_into().unwrap();
+        let guest_framebuffers = framebuffers;
         let framebuffers = mem.ptr_at_mut(framebuffers, n_usize);
-        unsafe { gles.GenFramebuffersOES(n, framebuffers) }
+        unsafe { gles.GenFramebuffersOES(n, framebuffers) };
+        record.gen_framebuffers(&read_names(mem, guest_framebuffers, n_usize));
     })
 }
 fn glGenRenderbuffersOES(env: &mut Environment, n: GLsizei, renderbuffers: MutPtr<GLuint>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
         let n_usize: GuestUSize = n.try_into().unwrap();
+        let guest_renderbuffers = renderbuffers;
         let renderbuffers = mem.ptr_at_mut(renderbuffers, n_usize);
-        unsafe { gles.GenRenderbuffersOES(n, renderbuffers) }
+        unsafe { gles.GenRenderbuffersOES(n, renderbuffers) };
+        record.gen_renderbuffers(&read_names(mem, guest_renderbuffers, n_usize));
     })
 }
 fn glBindFramebufferOES(env: &mut Environment, target: GLenum, framebuffer: GLuint) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.BindFramebufferOES(target, framebuffer)
+    with_ctx_record_and_mem(env, |gles, record, _mem| {
+        unsafe { gles.BindFramebufferOES(target, framebuffer) };
+        record.bind_framebuffer(framebuffer);
     })
 }
 fn glBindRenderbufferOES(env: &mut Environment, target: GLenum, renderbuffer: GLuint) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.BindRenderbufferOES(target, renderbuffer)
+    with_ctx_record_and_mem(env, |gles, record, _mem| {
+        unsafe { gles.BindRenderbufferOES(target, renderbuffer) };
+        record.bind_renderbuffer(renderbuffer);
     })
 }
 fn glRenderbufferStorageOES(
@@ -983,8 +1318,9 @@ fn glRenderbufferStorageOES(
     // apply scale hack: give the app a larger framebuffer than it asked for
     let factor = env.options.scale_hack.get() as GLsizei;
     let (width, height) = (width * factor, height * factor);
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.RenderbufferStorageOES(target, internalformat, width, height)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.RenderbufferStorageOES(target, internalformat, width, height);
+        record.renderbuffer_storage(gles, internalformat, width, height);
     })
 }
 fn glFramebufferRenderbufferOES(
@@ -994,8 +1330,10 @@ fn glFramebufferRenderbufferOES(
     renderbuffertarget: GLenum,
     renderbuffer: GLuint,
 ) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.FramebufferRenderbufferOES(target, attachment, renderbuffertarget, renderbuffer)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.FramebufferRenderbufferOES(target, attachment, renderbuffertarget, renderbuffer);
+        let new = (renderbuffer != 0).then_some(Attachment::Renderbuffer(renderbuffer));
+        record.framebuffer_attachment(gles, attachment, new);
     })
 }
 fn glFramebufferTexture2DOES(
@@ -1006,8 +1344,10 @@ fn glFramebufferTexture2DOES(
     texture: GLuint,
     level: i32,
 ) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe {
-        gles.FramebufferTexture2DOES(target, attachment, textarget, texture, level)
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.FramebufferTexture2DOES(target, attachment, textarget, texture, level);
+        let new = (texture != 0).then_some(Attachment::Texture(texture, level));
+        record.framebuffer_attachment(gles, attachment, new);
     })
 }
 fn glGetRenderbufferParameterivOES(
@@ -1033,21 +1373,26 @@ fn glCheckFramebufferStatusOES(env: &mut Environment, target: GLenum) -> GLenum
     })
 }
 fn glDeleteFramebuffersOES(env: &mut Environment, n: GLsizei, framebuffers: ConstPtr<GLuint>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
         let n_usize: GuestUSize = n.try_into().unwrap();
+        record.delete_framebuffers(&read_names(mem, framebuffers, n_usize));
         let framebuffers = mem.ptr_at(framebuffers, n_usize);
         unsafe { gles.DeleteFramebuffersOES(n, framebuffers) }
     })
 }
 fn glDeleteRenderbuffersOES(env: &mut Environment, n: GLsizei, renderbuffers: ConstPtr<GLuint>) {
-    with_ctx_and_mem(env, |gles, mem| {
+    with_ctx_record_and_mem(env, |gles, record, mem| {
         let n_usize: GuestUSize = n.try_into().unwrap();
+        record.delete_renderbuffers(&read_names(mem, renderbuffers, n_usize));
         let renderbuffers = mem.ptr_at(renderbuffers, n_usize);
         unsafe { gles.DeleteRenderbuffersOES(n, renderbuffers) }
     })
 }
 fn glGenerateMipmapOES(env: &mut Environment, target: GLenum) {
-    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.GenerateMipmapOES(target) })
+    with_ctx_record_and_mem(env, |gles, record, _mem| unsafe {
+        gles.GenerateMipmapOES(target);
+        record.tex_image(gles, TextureImage::GenerateMipmap);
+    })
 }
 
 pub const FUNCTIONS: FunctionExports = &[
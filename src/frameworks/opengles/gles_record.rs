@@ -0,0 +1,1189 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Recording of the state of a guest app's OpenGL ES context, so that it can
+//! be re-created when a save state is loaded.
+//!
+//! Much of the state of an OpenGL ES 1.1 context can't be queried, at least
+//! not through the [GLES] trait (lights, materials, texture parameters, the
+//! contents of textures and buffers…), so the wrappers in
+//! [super::gles_guest] report each state-changing call to the context's
+//! [GlesRecord]. Only the latest value of each piece of state is kept, so the
+//! record doesn't grow as the app runs. The state that _can_ be queried easily
+//! (matrix stacks, bindings, enabled capabilities) is instead read from the
+//! context just before saving, by [GlesRecord::prepare_for_save].
+//!
+//! Loading a save state creates a new context and replays the record into it
+//! with [GlesRecord::replay].
+//!
+//! Known limitations:
+//! - The contents of renderbuffers are not kept. Apps usually redraw the whole
+//!   frame every time, so this shouldn't matter much.
+//! - For textures written by the GPU (`glCopyTexImage2D` or rendering to a
+//!   texture), only the base level is kept, and it is read back as RGBA.
+
+use crate::gles::gles11_raw as gles11; // constants only
+use crate::gles::gles11_raw::types::*;
+use crate::gles::{gles1_on_gl2, GLES};
+use crate::mem::{ConstVoidPtr, Mem};
+use crate::save_state::{
+    impl_SaveState_for_enum, impl_SaveState_for_struct, save_iter, Reader, SaveState, Writer,
+};
+use std::collections::BTreeMap;
+
+/// iPhone OS devices supported by touchHLE only have two texture units, and
+/// that's all apps will use.
+const TEXTURE_UNITS: GLenum = 2;
+
+/// Parameter values, in the form the app provided them, so that they can be
+/// passed to the same variant of the function when replaying.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Params {
+    Float(Vec<GLfloat>),
+    Fixed(Vec<GLfixed>),
+    Int(Vec<GLint>),
+}
+impl_SaveState_for_enum!(Params {
+    Float(v),
+    Fixed(v),
+    Int(v)
+});
+
+/// A call that sets some piece of state, entirely replacing its old value.
+#[derive(Clone, Debug)]
+pub enum StateCall {
+    AlphaFunc(GLenum, Params),
+    BlendFunc(GLenum, GLenum),
+    ColorMask(GLboolean, GLboolean, GLboolean, GLboolean),
+    CullFace(GLenum),
+    DepthFunc(GLenum),
+    DepthMask(GLboolean),
+    DepthRange(Params),
+    FrontFace(GLenum),
+    PolygonOffset(Params),
+    ShadeModel(GLenum),
+    Scissor(GLint, GLint, GLsizei, GLsizei),
+    Viewport(GLint, GLint, GLsizei, GLsizei),
+    LineWidth(Params),
+    PointSize(Params),
+    PointParameter(GLenum, Params),
+    Fog(GLenum, Params),
+    /// Light, pname, params. Positions and directions are in eye coordinates,
+    /// so they must be replayed with an identity modelview matrix.
+    Light(GLenum, GLenum, Params),
+    LightModel(GLenum, Params),
+    Material(GLenum, GLenum, Params),
+    /// `Params::Int` is used for `glColor4ub`.
+    Color(Params),
+    Normal(Params),
+    ClearColor(Params),
+    ClearDepth(Params),
+    ClearStencil(GLint),
+    Hint(GLenum, GLenum),
+    PixelStore(GLenum, GLint),
+    /// Texture unit, target, pname, params.
+    TexEnv(GLenum, GLenum, GLenum, Params),
+}
+impl_SaveState_for_enum!(StateCall {
+    AlphaFunc(a, b),
+    BlendFunc(a, b),
+    ColorMask(a, b, c, d),
+    CullFace(a),
+    DepthFunc(a),
+    DepthMask(a),
+    DepthRange(a),
+    FrontFace(a),
+    PolygonOffset(a),
+    ShadeModel(a),
+    Scissor(a, b, c, d),
+    Viewport(a, b, c, d),
+    LineWidth(a),
+    PointSize(a),
+    PointParameter(a, b),
+    Fog(a, b),
+    Light(a, b, c),
+    LightModel(a, b),
+    Material(a, b, c),
+    Color(a),
+    Normal(a),
+    ClearColor(a),
+    ClearDepth(a),
+    ClearStencil(a),
+    Hint(a, b),
+    PixelStore(a, b),
+    TexEnv(a, b, c, d),
+});
+
+/// Identifies which piece of state a [StateCall] sets.
+type StateKey = (&'static str, [GLenum; 3]);
+
+impl StateCall {
+    fn key(&self) -> StateKey {
+        match *self {
+            StateCall::AlphaFunc(..) => ("AlphaFunc", [0; 3]),
+            StateCall::BlendFunc(..) => ("BlendFunc", [0; 3]),
+            StateCall::ColorMask(..) => ("ColorMask", [0; 3]),
+            StateCall::CullFace(..) => ("CullFace", [0; 3]),
+            StateCall::DepthFunc(..) => ("DepthFunc", [0; 3]),
+            StateCall::DepthMask(..) => ("DepthMask", [0; 3]),
+            StateCall::DepthRange(..) => ("DepthRange", [0; 3]),
+            StateCall::FrontFace(..) => ("FrontFace", [0; 3]),
+            StateCall::PolygonOffset(..) => ("PolygonOffset", [0; 3]),
+            StateCall::ShadeModel(..) => ("ShadeModel", [0; 3]),
+            StateCall::Scissor(..) => ("Scissor", [0; 3]),
+            StateCall::Viewport(..) => ("Viewport", [0; 3]),
+            StateCall::LineWidth(..) => ("LineWidth", [0; 3]),
+            StateCall::PointSize(..) => ("PointSize", [0; 3]),
+            StateCall::PointParameter(pname, _) => ("PointParameter", [pname, 0, 0]),
+            StateCall::Fog(pname, _) => ("Fog", [pname, 0, 0]),
+            StateCall::Light(light, pname, _) => ("Light", [light, pname, 0]),
+            StateCall::LightModel(pname, _) => ("LightModel", [pname, 0, 0]),
+            StateCall::Material(face, pname, _) => ("Material", [face, pname, 0]),
+            StateCall::Color(..) => ("Color", [0; 3]),
+            StateCall::Normal(..) => ("Normal", [0; 3]),
+            StateCall::ClearColor(..) => ("ClearColor", [0; 3]),
+            StateCall::ClearDepth(..) => ("ClearDepth", [0; 3]),
+            StateCall::ClearStencil(..) => ("ClearStencil", [0; 3]),
+            StateCall::Hint(target, _) => ("Hint", [target, 0, 0]),
+            StateCall::PixelStore(pname, _) => ("PixelStore", [pname, 0, 0]),
+            StateCall::TexEnv(unit, target, pname, _) => ("TexEnv", [unit, target, pname]),
+        }
+    }
+
+    /// The provided context must be current.
+    unsafe fn replay(&self, gles: &mut dyn GLES) {
+        use Params::{Fixed, Float, Int};
+        match self {
+            &StateCall::AlphaFunc(func, ref params) => match params {
+                Float(v) => gles.AlphaFunc(func, v[0]),
+                Fixed(v) => gles.AlphaFuncx(func, v[0]),
+                Int(_) => unreachable!(),
+            },
+            &StateCall::BlendFunc(sfactor, dfactor) => gles.BlendFunc(sfactor, dfactor),
+            &StateCall::ColorMask(r, g, b, a) => gles.ColorMask(r, g, b, a),
+            &StateCall::CullFace(mode) => gles.CullFace(mode),
+            &StateCall::DepthFunc(func) => gles.DepthFunc(func),
+            &StateCall::DepthMask(flag) => gles.DepthMask(flag),
+            StateCall::DepthRange(params) => match params {
+                Float(v) => gles.DepthRangef(v[0], v[1]),
+                Fixed(v) => gles.DepthRangex(v[0], v[1]),
+                Int(_) => unreachable!(),
+            },
+            &StateCall::FrontFace(mode) => gles.FrontFace(mode),
+            StateCall::PolygonOffset(params) => match params {
+                Float(v) => gles.PolygonOffset(v[0], v[1]),
+                Fixed(v) => gles.PolygonOffsetx(v[0], v[1]),
+                Int(_) => unreachable!(),
+            },
+            &StateCall::ShadeModel(mode) => gles.ShadeModel(mode),
+            &StateCall::Scissor(x, y, w, h) => gles.Scissor(x, y, w, h),
+            &StateCall::Viewport(x, y, w, h) => gles.Viewport(x, y, w, h),
+            StateCall::LineWidth(params) => match params {
+                Float(v) => gles.LineWidth(v[0]),
+                Fixed(v) => gles.LineWidthx(v[0]),
+                Int(_) => unreachable!(),
+            },
+            StateCall::PointSize(params) => match params {
+                Float(v) => gles.PointSize(v[0]),
+                Fixed(v) => gles.PointSizex(v[0]),
+                Int(_) => unreachable!(),
+            },
+            &StateCall::PointParameter(pname, ref params) => match params {
+                Float(v) => gles.PointParameterfv(pname, v.as_ptr()),
+                Fixed(v) => gles.PointParameterxv(pname, v.as_ptr()),
+                Int(_) => unreachable!(),
+            },
+            &StateCall::Fog(pname, ref params) => match params {
+                Float(v) => gles.Fogfv(pname, v.as_ptr()),
+                Fixed(v) => gles.Fogxv(pname, v.as_ptr()),
+                Int(_) => unreachable!(),
+            },
+            &StateCall::Light(light, pname, ref params) => match params {
+                Float(v) => gles.Lightfv(light, pname, v.as_ptr()),
+                Fixed(v) => gles.Lightxv(light, pname, v.as_ptr()),
+                Int(_) => unreachable!(),
+            },
+            &StateCall::LightModel(pname, ref params) => match params {
+                Float(v) => gles.LightModelfv(pname, v.as_ptr()),
+                Fixed(_) | Int(_) => unreachable!(),
+            },
+            &StateCall::Material(face, pname, ref params) => match params {
+                Float(v) => gles.Materialfv(face, pname, v.as_ptr()),
+                Fixed(v) => gles.Materialxv(face, pname, v.as_ptr()),
+                Int(_) => unreachable!(),
+            },
+            StateCall::Color(params) => match params {
+                Float(v) => gles.Color4f(v[0], v[1], v[2], v[3]),
+                Fixed(v) => gles.Color4x(v[0], v[1], v[2], v[3]),
+                Int(v) => gles.Color4ub(v[0] as _, v[1] as _, v[2] as _, v[3] as _),
+            },
+            StateCall::Normal(params) => match params {
+                Float(v) => gles.Normal3f(v[0], v[1], v[2]),
+                Fixed(v) => gles.Normal3x(v[0], v[1], v[2]),
+                Int(_) => unreachable!(),
+            },
+            StateCall::ClearColor(params) => match params {
+                Float(v) => gles.ClearColor(v[0], v[1], v[2], v[3]),
+                Fixed(v) => gles.ClearColorx(v[0], v[1], v[2], v[3]),
+                Int(_) => unreachable!(),
+            },
+            StateCall::ClearDepth(params) => match params {
+                Float(v) => gles.ClearDepthf(v[0]),
+                Fixed(v) => gles.ClearDepthx(v[0]),
+                Int(_) => unreachable!(),
+            },
+            &StateCall::ClearStencil(s) => gles.ClearStencil(s),
+            &StateCall::Hint(target, mode) => gles.Hint(target, mode),
+            &StateCall::PixelStore(pname, param) => gles.PixelStorei(pname, param),
+            &StateCall::TexEnv(unit, target, pname, ref params) => {
+                gles.ActiveTexture(unit);
+                match params {
+                    Float(v) => gles.TexEnvfv(target, pname, v.as_ptr()),
+                    Fixed(v) => gles.TexEnvxv(target, pname, v.as_ptr()),
+                    Int(v) => gles.TexEnviv(target, pname, v.as_ptr()),
+                }
+            }
+        }
+    }
+}
+
+/// An operation that defines (part of) the contents of a texture.
+#[derive(Debug)]
+pub enum TextureImage {
+    Image {
+        level: GLint,
+        internalformat: GLint,
+        width: GLsizei,
+        height: GLsizei,
+        border: GLint,
+        format: GLenum,
+        type_: GLenum,
+        unpack_alignment: GLint,
+        pixels: Option<Vec<u8>>,
+    },
+    SubImage {
+        level: GLint,
+        xoffset: GLint,
+        yoffset: GLint,
+        width: GLsizei,
+        height: GLsizei,
+        format: GLenum,
+        type_: GLenum,
+        unpack_alignment: GLint,
+        pixels: Vec<u8>,
+    },
+    Compressed {
+        level: GLint,
+        internalformat: GLenum,
+        width: GLsizei,
+        height: GLsizei,
+        border: GLint,
+        data: Vec<u8>,
+    },
+    GenerateMipmap,
+}
+impl_SaveState_for_enum!(TextureImage {
+    Image {
+        level,
+        internalformat,
+        width,
+        height,
+        border,
+        format,
+        type_,
+        unpack_alignment,
+        pixels
+    },
+    SubImage {
+        level,
+        xoffset,
+        yoffset,
+        width,
+        height,
+        format,
+        type_,
+        unpack_alignment,
+        pixels
+    },
+    Compressed {
+        level,
+        internalformat,
+        width,
+        height,
+        border,
+        data
+    },
+    GenerateMipmap,
+});
+
+impl TextureImage {
+    fn level(&self) -> Option<GLint> {
+        match *self {
+            TextureImage::Image { level, .. }
+            | TextureImage::SubImage { level, .. }
+            | TextureImage::Compressed { level, .. } => Some(level),
+            TextureImage::GenerateMipmap => None,
+        }
+    }
+
+    /// Whether this operation completely replaces the contents of a level.
+    fn defines_level(&self) -> bool {
+        matches!(
+            self,
+            TextureImage::Image { .. } | TextureImage::Compressed { .. }
+        )
+    }
+
+    /// Whether this is a sub-image operation whose rectangle is entirely
+    /// covered by `other`'s, so it's no longer needed.
+    fn is_covered_by(&self, other: &TextureImage) -> bool {
+        let &TextureImage::SubImage {
+            level,
+            xoffset,
+            yoffset,
+            width,
+            height,
+            ..
+        } = self
+        else {
+            return false;
+        };
+        let &TextureImage::SubImage {
+            level: other_level,
+            xoffset: other_x,
+            yoffset: other_y,
+            width: other_width,
+            height: other_height,
+            ..
+        } = other
+        else {
+            return false;
+        };
+        level == other_level
+            && xoffset >= other_x
+            && yoffset >= other_y
+            && xoffset + width <= other_x + other_width
+            && yoffset + height <= other_y + other_height
+    }
+
+    /// The provided context must be current.
+    unsafe fn replay(&self, gles: &mut dyn GLES) {
+        match self {
+            &TextureImage::Image {
+                level,
+                internalformat,
+                width,
+                height,
+                border,
+                format,
+                type_,
+                unpack_alignment,
+                ref pixels,
+            } => {
+                gles.PixelStorei(gles11::UNPACK_ALIGNMENT, unpack_alignment);
+                let pixels = pixels
+                    .as_ref()
+                    .map_or(std::ptr::null(), |pixels| pixels.as_ptr());
+                gles.TexImage2D(
+                    gles11::TEXTURE_2D,
+                    level,
+                    internalformat,
+                    width,
+                    height,
+                    border,
+                    format,
+                    type_,
+                    pixels.cast(),
+                )
+            }
+            &TextureImage::SubImage {
+                level,
+                xoffset,
+                yoffset,
+                width,
+                height,
+                format,
+                type_,
+                unpack_alignment,
+                ref pixels,
+            } => {
+                gles.PixelStorei(gles11::UNPACK_ALIGNMENT, unpack_alignment);
+                gles.TexSubImage2D(
+                    gles11::TEXTURE_2D,
+                    level,
+                    xoffset,
+                    yoffset,
+                    width,
+                    height,
+                    format,
+                    type_,
+                    pixels.as_ptr().cast(),
+                )
+            }
+            &TextureImage::Compressed {
+                level,
+                internalformat,
+                width,
+                height,
+                border,
+                ref data,
+            } => gles.CompressedTexImage2D(
+                gles11::TEXTURE_2D,
+                level,
+                internalformat,
+                width,
+                height,
+                border,
+                data.len().try_into().unwrap(),
+                data.as_ptr().cast(),
+            ),
+            TextureImage::GenerateMipmap => gles.GenerateMipmapOES(gles11::TEXTURE_2D),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TextureRecord {
+    params: BTreeMap<GLenum, Params>,
+    images: Vec<TextureImage>,
+    /// Size of the base level, if known.
+    size: Option<(GLsizei, GLsizei)>,
+    /// Set if the GPU has written to the texture, in which case the contents
+    /// must be read back before saving.
+    gpu_written: bool,
+}
+impl_SaveState_for_struct!(TextureRecord {
+    params,
+    images,
+    size,
+    gpu_written
+});
+
+#[derive(Default)]
+pub struct BufferRecord {
+    usage: GLenum,
+    data: Option<Vec<u8>>,
+}
+impl_SaveState_for_struct!(BufferRecord { usage, data });
+
+pub enum Attachment {
+    Renderbuffer(GLuint),
+    Texture(GLuint, GLint),
+}
+impl_SaveState_for_enum!(Attachment {
+    Renderbuffer(renderbuffer),
+    Texture(texture, level)
+});
+
+pub struct PointerRecord {
+    size: GLint,
+    type_: GLenum,
+    stride: GLsizei,
+    /// Value of `GL_ARRAY_BUFFER_BINDING` at the time of the call.
+    buffer: GLuint,
+    /// Guest pointer, or offset if `buffer` is not zero.
+    pointer: ConstVoidPtr,
+}
+impl_SaveState_for_struct!(PointerRecord {
+    size,
+    type_,
+    stride,
+    buffer,
+    pointer
+});
+
+/// State that is queried by [GlesRecord::prepare_for_save] rather than being
+/// recorded.
+#[derive(Default)]
+pub struct QueriedState {
+    /// Matrix mode, texture unit, and the stack (bottom first).
+    matrices: Vec<(GLenum, GLenum, Vec<[GLfloat; 16]>)>,
+    /// Capability, texture unit (only relevant for `GL_TEXTURE_2D`), and
+    /// whether it is enabled.
+    capabilities: Vec<(GLenum, GLenum, bool)>,
+    /// Array, client texture unit (only relevant for
+    /// `GL_TEXTURE_COORD_ARRAY`), and whether it is enabled.
+    client_states: Vec<(GLenum, GLenum, bool)>,
+    /// `GL_TEXTURE_BINDING_2D` for each texture unit.
+    texture_bindings: Vec<GLuint>,
+    /// Other `glGetIntegerv` bindings and modes, see [BINDINGS].
+    bindings: Vec<GLint>,
+}
+impl_SaveState_for_struct!(QueriedState {
+    matrices,
+    capabilities,
+    client_states,
+    texture_bindings,
+    bindings
+});
+
+/// Bindings and modes that are restored after everything else.
+const BINDINGS: &[GLenum] = &[
+    gles11::ARRAY_BUFFER_BINDING,
+    gles11::ELEMENT_ARRAY_BUFFER_BINDING,
+    gles11::FRAMEBUFFER_BINDING_OES,
+    gles11::RENDERBUFFER_BINDING_OES,
+    gles11::ACTIVE_TEXTURE,
+    gles11::CLIENT_ACTIVE_TEXTURE,
+    gles11::MATRIX_MODE,
+];
+
+/// See the module documentation.
+#[derive(Default)]
+pub struct GlesRecord {
+    state_calls: BTreeMap<StateKey, StateCall>,
+    textures: BTreeMap<GLuint, TextureRecord>,
+    buffers: BTreeMap<GLuint, BufferRecord>,
+    /// Internal format, width and height.
+    renderbuffers: BTreeMap<GLuint, Option<(GLenum, GLsizei, GLsizei)>>,
+    framebuffers: BTreeMap<GLuint, BTreeMap<GLenum, Attachment>>,
+    /// Keyed by array and client texture unit (only relevant for
+    /// `GL_TEXTURE_COORD_ARRAY`).
+    pointers: BTreeMap<(GLenum, GLenum), PointerRecord>,
+    queried: QueriedState,
+}
+
+unsafe fn get_int(gles: &mut dyn GLES, pname: GLenum) -> GLint {
+    let mut value = 0;
+    gles.GetIntegerv(pname, &mut value);
+    value
+}
+
+unsafe fn get_matrix(gles: &mut dyn GLES, pname: GLenum) -> [GLfloat; 16] {
+    let mut matrix = [0.0; 16];
+    gles.GetFloatv(pname, matrix.as_mut_ptr());
+    matrix
+}
+
+/// Size in bytes of image data as read by `glTexImage2D`, taking the unpack
+/// alignment into account.
+pub fn image_data_size(
+    width: GLsizei,
+    height: GLsizei,
+    bytes_per_pixel: u32,
+    alignment: GLint,
+) -> u32 {
+    let width: u32 = width.try_into().unwrap();
+    let height: u32 = height.try_into().unwrap();
+    let alignment: u32 = alignment.try_into().unwrap();
+    if width == 0 || height == 0 {
+        return 0;
+    }
+    let row_size = width * bytes_per_pixel;
+    let padded_row_size = row_size.div_ceil(alignment) * alignment;
+    padded_row_size * (height - 1) + row_size
+}
+
+impl GlesRecord {
+    /// Record a [StateCall]. The provided context must be current, since some
+    /// state depends on other state.
+    pub unsafe fn state(&mut self, gles: &mut dyn GLES, call: StateCall) {
+        let call = match call {
+            StateCall::Light(light, pname, params)
+                if pname == gles11::POSITION || pname == gles11::SPOT_DIRECTION =>
+            {
+                // These are transformed by the modelview matrix when they are
+                // set, so the eye-space value must be saved.
+                let mut v: Vec<GLfloat> = match params {
+                    Params::Float(v) => v,
+                    Params::Fixed(v) => v.iter().map(|&x| x as GLfloat / 65536.0).collect(),
+                    Params::Int(_) => unreachable!(),
+                };
+                v.resize(4, 0.0);
+                if pname == gles11::SPOT_DIRECTION {
+                    v[3] = 0.0;
+                }
+                let m = get_matrix(gles, gles11::MODELVIEW_MATRIX);
+                let transformed = (0..4)
+                    .map(|row| (0..4).map(|col| m[col * 4 + row] * v[col]).sum())
+                    .collect();
+                StateCall::Light(light, pname, Params::Float(transformed))
+            }
+            StateCall::Material(face, gles11::AMBIENT_AND_DIFFUSE, params) => {
+                self.state(
+                    gles,
+                    StateCall::Material(face, gles11::AMBIENT, params.clone()),
+                );
+                StateCall::Material(face, gles11::DIFFUSE, params)
+            }
+            StateCall::TexEnv(_, target, pname, params) => {
+                let unit = get_int(gles, gles11::ACTIVE_TEXTURE) as GLenum;
+                StateCall::TexEnv(unit, target, pname, params)
+            }
+            call => call,
+        };
+        self.state_calls.insert(call.key(), call);
+    }
+
+    pub fn gen_textures(&mut self, names: &[GLuint]) {
+        for &name in names {
+            self.textures.entry(name).or_default();
+        }
+    }
+    pub fn delete_textures(&mut self, names: &[GLuint]) {
+        for name in names {
+            self.textures.remove(name);
+        }
+        for attachments in self.framebuffers.values_mut() {
+            attachments.retain(|_, attachment| {
+                !matches!(attachment, Attachment::Texture(texture, _)
+                          if names.contains(texture))
+            });
+        }
+    }
+    pub fn bind_texture(&mut self, texture: GLuint) {
+        self.gen_textures(&[texture]);
+    }
+
+    unsafe fn bound_texture(&mut self, gles: &mut dyn GLES) -> Option<&mut TextureRecord> {
+        let texture = get_int(gles, gles11::TEXTURE_BINDING_2D) as GLuint;
+        if texture == 0 {
+            // The default texture can't be re-created by binding it, and apps
+            // have no reason to use it.
+            return None;
+        }
+        Some(self.textures.entry(texture).or_default())
+    }
+
+    pub unsafe fn tex_parameter(&mut self, gles: &mut dyn GLES, pname: GLenum, params: Params) {
+        if let Some(texture) = self.bound_texture(gles) {
+            texture.params.insert(pname, params);
+        }
+    }
+
+    pub unsafe fn tex_image(&mut self, gles: &mut dyn GLES, image: TextureImage) {
+        let Some(texture) = self.bound_texture(gles) else {
+            return;
+        };
+        match image {
+            TextureImage::Image {
+                level: 0,
+                width,
+                height,
+                ..
+            }
+            | TextureImage::Compressed {
+                level: 0,
+                width,
+                height,
+                ..
+            } => texture.size = Some((width, height)),
+            _ => (),
+        }
+        if image.defines_level() {
+            let level = image.level();
+            texture.images.retain(|other| other.level() != level);
+        } else if let TextureImage::GenerateMipmap = image {
+            texture
+                .images
+                .retain(|other| !matches!(other, TextureImage::GenerateMipmap));
+        } else {
+            texture.images.retain(|other| !other.is_covered_by(&image));
+        }
+        texture.images.push(image);
+    }
+
+    /// For `glCopyTexImage2D` and `glCopyTexSubImage2D`. `size` should be
+    /// provided for the former.
+    pub unsafe fn copy_tex_image(
+        &mut self,
+        gles: &mut dyn GLES,
+        level: GLint,
+        size: Option<(GLsizei, GLsizei)>,
+    ) {
+        let Some(texture) = self.bound_texture(gles) else {
+            return;
+        };
+        texture.gpu_written = true;
+        if let Some(size) = size {
+            texture.images.retain(|other| other.level() != Some(level));
+            if level == 0 {
+                texture.size = Some(size);
+            }
+        }
+    }
+
+    pub fn gen_buffers(&mut self, names: &[GLuint]) {
+        for &name in names {
+            self.buffers.entry(name).or_default();
+        }
+    }
+    pub fn delete_buffers(&mut self, names: &[GLuint]) {
+        for name in names {
+            self.buffers.remove(name);
+        }
+    }
+    pub fn bind_buffer(&mut self, buffer: GLuint) {
+        self.gen_buffers(&[buffer]);
+    }
+
+    unsafe fn bound_buffer(
+        &mut self,
+        gles: &mut dyn GLES,
+        target: GLenum,
+    ) -> Option<&mut BufferRecord> {
+        let binding = match target {
+            gles11::ARRAY_BUFFER => gles11::ARRAY_BUFFER_BINDING,
+            gles11::ELEMENT_ARRAY_BUFFER => gles11::ELEMENT_ARRAY_BUFFER_BINDING,
+            _ => return None,
+        };
+        let buffer = get_int(gles, binding) as GLuint;
+        if buffer == 0 {
+            return None;
+        }
+        Some(self.buffers.entry(buffer).or_default())
+    }
+
+    pub unsafe fn buffer_data(
+        &mut self,
+        gles: &mut dyn GLES,
+        target: GLenum,
+        data: Vec<u8>,
+        usage: GLenum,
+    ) {
+        if let Some(buffer) = self.bound_buffer(gles, target) {
+            *buffer = BufferRecord {
+                usage,
+                data: Some(data),
+            };
+        }
+    }
+
+    pub unsafe fn buffer_sub_data(
+        &mut self,
+        gles: &mut dyn GLES,
+        target: GLenum,
+        offset: usize,
+        new_data: &[u8],
+    ) {
+        let Some(buffer) = self.bound_buffer(gles, target) else {
+            return;
+        };
+        let Some(ref mut data) = buffer.data else {
+            return;
+        };
+        if let Some(range) = data.get_mut(offset..offset + new_data.len()) {
+            range.copy_from_slice(new_data);
+        }
+    }
+
+    pub fn gen_renderbuffers(&mut self, names: &[GLuint]) {
+        for &name in names {
+            self.renderbuffers.entry(name).or_default();
+        }
+    }
+    pub fn delete_renderbuffers(&mut self, names: &[GLuint]) {
+        for name in names {
+            self.renderbuffers.remove(name);
+        }
+        for attachments in self.framebuffers.values_mut() {
+            attachments.retain(|_, attachment| {
+                !matches!(attachment, Attachment::Renderbuffer(renderbuffer)
+                          if names.contains(renderbuffer))
+            });
+        }
+    }
+    pub fn bind_renderbuffer(&mut self, renderbuffer: GLuint) {
+        self.gen_renderbuffers(&[renderbuffer]);
+    }
+
+    pub unsafe fn renderbuffer_storage(
+        &mut self,
+        gles: &mut dyn GLES,
+        internalformat: GLenum,
+        width: GLsizei,
+        height: GLsizei,
+    ) {
+        let renderbuffer = get_int(gles, gles11::RENDERBUFFER_BINDING_OES) as GLuint;
+        if renderbuffer != 0 {
+            self.renderbuffers
+                .insert(renderbuffer, Some((internalformat, width, height)));
+        }
+    }
+
+    pub fn gen_framebuffers(&mut self, names: &[GLuint]) {
+        for &name in names {
+            self.framebuffers.entry(name).or_default();
+        }
+    }
+    pub fn delete_framebuffers(&mut self, names: &[GLuint]) {
+        for name in names {
+            self.framebuffers.remove(name);
+        }
+    }
+    pub fn bind_framebuffer(&mut self, framebuffer: GLuint) {
+        self.gen_framebuffers(&[framebuffer]);
+    }
+
+    pub unsafe fn framebuffer_attachment(
+        &mut self,
+        gles: &mut dyn GLES,
+        attachment: GLenum,
+        new: Option<Attachment>,
+    ) {
+        let framebuffer = get_int(gles, gles11::FRAMEBUFFER_BINDING_OES) as GLuint;
+        if framebuffer == 0 {
+            return;
+        }
+        if let Some(Attachment::Texture(texture, _)) = new {
+            if let Some(texture) = self.textures.get_mut(&texture) {
+                texture.gpu_written = true;
+            }
+        }
+        let attachments = self.framebuffers.entry(framebuffer).or_default();
+        if let Some(new) = new {
+            attachments.insert(attachment, new);
+        } else {
+            attachments.remove(&attachment);
+        }
+    }
+
+    /// Record a `gl*Pointer` call. `pointer` is the guest's pointer or offset.
+    pub unsafe fn pointer(
+        &mut self,
+        gles: &mut dyn GLES,
+        array: GLenum,
+        size: GLint,
+        type_: GLenum,
+        stride: GLsizei,
+        pointer: ConstVoidPtr,
+    ) {
+        let unit = if array == gles11::TEXTURE_COORD_ARRAY {
+            get_int(gles, gles11::CLIENT_ACTIVE_TEXTURE) as GLenum
+        } else {
+            0
+        };
+        let buffer = get_int(gles, gles11::ARRAY_BUFFER_BINDING) as GLuint;
+        self.pointers.insert(
+            (array, unit),
+            PointerRecord {
+                size,
+                type_,
+                stride,
+                buffer,
+                pointer,
+            },
+        );
+    }
+
+    /// Query the state that isn't recorded, and read back textures written by
+    /// the GPU. The provided context must be current.
+    pub unsafe fn prepare_for_save(&mut self, gles: &mut dyn GLES) {
+        let mut queried = QueriedState {
+            bindings: BINDINGS.iter().map(|&pname| get_int(gles, pname)).collect(),
+            ..Default::default()
+        };
+
+        let arrays: Vec<GLenum> = gles1_on_gl2::ARRAYS.iter().map(|info| info.name).collect();
+        for &cap in gles1_on_gl2::CAPABILITIES {
+            if arrays.contains(&cap) {
+                continue;
+            }
+            let units = if cap == gles11::TEXTURE_2D {
+                TEXTURE_UNITS
+            } else {
+                1
+            };
+            for unit in (0..units).map(|i| gles11::TEXTURE0 + i) {
+                gles.ActiveTexture(unit);
+                let enabled = gles.IsEnabled(cap) != gles11::FALSE;
+                queried.capabilities.push((cap, unit, enabled));
+            }
+        }
+        for &array in &arrays {
+            let units = if array == gles11::TEXTURE_COORD_ARRAY {
+                TEXTURE_UNITS
+            } else {
+                1
+            };
+            for unit in (0..units).map(|i| gles11::TEXTURE0 + i) {
+                gles.ClientActiveTexture(unit);
+                let enabled = gles.IsEnabled(array) != gles11::FALSE;
+                queried.client_states.push((array, unit, enabled));
+            }
+        }
+
+        for unit in (0..TEXTURE_UNITS).map(|i| gles11::TEXTURE0 + i) {
+            gles.ActiveTexture(unit);
+            let texture = get_int(gles, gles11::TEXTURE_BINDING_2D) as GLuint;
+            queried.texture_bindings.push(texture);
+
+            let mut modes = vec![(
+                gles11::TEXTURE,
+                gles11::TEXTURE_MATRIX,
+                gles11::TEXTURE_STACK_DEPTH,
+            )];
+            if unit == gles11::TEXTURE0 {
+                modes.push((
+                    gles11::MODELVIEW,
+                    gles11::MODELVIEW_MATRIX,
+                    gles11::MODELVIEW_STACK_DEPTH,
+                ));
+                modes.push((
+                    gles11::PROJECTION,
+                    gles11::PROJECTION_MATRIX,
+                    gles11::PROJECTION_STACK_DEPTH,
+                ));
+            }
+            for (mode, matrix_pname, depth_pname) in modes {
+                gles.MatrixMode(mode);
+                // The stack can only be read by popping it, so it has to be
+                // pushed back afterwards.
+                let depth = get_int(gles, depth_pname).max(1);
+                let mut stack = vec![get_matrix(gles, matrix_pname)];
+                for _ in 1..depth {
+                    gles.PopMatrix();
+                    stack.push(get_matrix(gles, matrix_pname));
+                }
+                stack.reverse();
+                for matrix in &stack[1..] {
+                    gles.PushMatrix();
+                    gles.LoadMatrixf(matrix.as_ptr());
+                }
+                queried.matrices.push((mode, unit, stack));
+            }
+        }
+
+        let old_pack_alignment = get_int(gles, gles11::PACK_ALIGNMENT);
+        gles.PixelStorei(gles11::PACK_ALIGNMENT, 4);
+        for (&name, texture) in self.textures.iter_mut() {
+            if !texture.gpu_written {
+                continue;
+            }
+            let Some((width, height)) = texture.size else {
+                continue;
+            };
+            let mut pixels = vec![0u8; image_data_size(width, height, 4, 4) as usize];
+            let mut framebuffer = 0;
+            gles.GenFramebuffersOES(1, &mut framebuffer);
+            gles.BindFramebufferOES(gles11::FRAMEBUFFER_OES, framebuffer);
+            gles.FramebufferTexture2DOES(
+                gles11::FRAMEBUFFER_OES,
+                gles11::COLOR_ATTACHMENT0_OES,
+                gles11::TEXTURE_2D,
+                name,
+                0,
+            );
+            gles.ReadPixels(
+                0,
+                0,
+                width,
+                height,
+                gles11::RGBA,
+                gles11::UNSIGNED_BYTE,
+                pixels.as_mut_ptr().cast(),
+            );
+            gles.DeleteFramebuffersOES(1, &framebuffer);
+            texture.images = vec![TextureImage::Image {
+                level: 0,
+                internalformat: gles11::RGBA as _,
+                width,
+                height,
+                border: 0,
+                format: gles11::RGBA,
+                type_: gles11::UNSIGNED_BYTE,
+                unpack_alignment: 4,
+                pixels: Some(pixels),
+            }];
+        }
+        gles.PixelStorei(gles11::PACK_ALIGNMENT, old_pack_alignment);
+
+        // Undo the changes to the bindings and modes.
+        self.queried = queried;
+        self.replay_bindings(gles);
+    }
+
+    /// Replay the record into a new context. The provided context must be
+    /// current.
+    pub unsafe fn replay(&self, gles: &mut dyn GLES, mem: &Mem) {
+        for (&name, buffer) in &self.buffers {
+            gles.BindBuffer(gles11::ARRAY_BUFFER, name);
+            if let Some(ref data) = buffer.data {
+                gles.BufferData(
+                    gles11::ARRAY_BUFFER,
+                    data.len() as _,
+                    data.as_ptr().cast(),
+                    buffer.usage,
+                );
+            }
+        }
+
+        gles.ActiveTexture(gles11::TEXTURE0);
+        for (&name, texture) in &self.textures {
+            gles.BindTexture(gles11::TEXTURE_2D, name);
+            for image in &texture.images {
+                image.replay(gles);
+            }
+            for (&pname, params) in &texture.params {
+                match params {
+                    Params::Float(v) => gles.TexParameterfv(gles11::TEXTURE_2D, pname, v.as_ptr()),
+                    Params::Fixed(v) => gles.TexParameterxv(gles11::TEXTURE_2D, pname, v.as_ptr()),
+                    Params::Int(v) => gles.TexParameteriv(gles11::TEXTURE_2D, pname, v.as_ptr()),
+                }
+            }
+        }
+
+        for (&name, &storage) in &self.renderbuffers {
+            gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, name);
+            if let Some((internalformat, width, height)) = storage {
+                gles.RenderbufferStorageOES(
+                    gles11::RENDERBUFFER_OES,
+                    internalformat,
+                    width,
+                    height,
+                );
+            }
+        }
+
+        for (&name, attachments) in &self.framebuffers {
+            gles.BindFramebufferOES(gles11::FRAMEBUFFER_OES, name);
+            for (&attachment, target) in attachments {
+                match *target {
+                    Attachment::Renderbuffer(renderbuffer) => gles.FramebufferRenderbufferOES(
+                        gles11::FRAMEBUFFER_OES,
+                        attachment,
+                        gles11::RENDERBUFFER_OES,
+                        renderbuffer,
+                    ),
+                    Attachment::Texture(texture, level) => gles.FramebufferTexture2DOES(
+                        gles11::FRAMEBUFFER_OES,
+                        attachment,
+                        gles11::TEXTURE_2D,
+                        texture,
+                        level,
+                    ),
+                }
+            }
+        }
+
+        // Light positions and directions were recorded in eye coordinates.
+        gles.MatrixMode(gles11::MODELVIEW);
+        gles.LoadIdentity();
+        for call in self.state_calls.values() {
+            call.replay(gles);
+        }
+
+        for &(cap, unit, enabled) in &self.queried.capabilities {
+            gles.ActiveTexture(unit);
+            if enabled {
+                gles.Enable(cap);
+            } else {
+                gles.Disable(cap);
+            }
+        }
+        for &(array, unit, enabled) in &self.queried.client_states {
+            gles.ClientActiveTexture(unit);
+            if enabled {
+                gles.EnableClientState(array);
+            } else {
+                gles.DisableClientState(array);
+            }
+        }
+
+        for (&(array, unit), pointer) in &self.pointers {
+            if array == gles11::TEXTURE_COORD_ARRAY {
+                gles.ClientActiveTexture(unit);
+            }
+            gles.BindBuffer(gles11::ARRAY_BUFFER, pointer.buffer);
+            let host_pointer = if pointer.buffer != 0 {
+                pointer.pointer.to_bits() as usize as *const GLvoid
+            } else if pointer.pointer.is_null() {
+                std::ptr::null()
+            } else {
+                mem.ptr_at(pointer.pointer.cast::<u8>(), 0).cast()
+            };
+            let PointerRecord {
+                size,
+                type_,
+                stride,
+                ..
+            } = *pointer;
+            match array {
+                gles11::COLOR_ARRAY => gles.ColorPointer(size, type_, stride, host_pointer),
+                gles11::NORMAL_ARRAY => gles.NormalPointer(type_, stride, host_pointer),
+                gles11::TEXTURE_COORD_ARRAY => {
+                    gles.TexCoordPointer(size, type_, stride, host_pointer)
+                }
+                gles11::VERTEX_ARRAY => gles.VertexPointer(size, type_, stride, host_pointer),
+                _ => unreachable!(),
+            }
+        }
+
+        for (mode, unit, stack) in &self.queried.matrices {
+            gles.ActiveTexture(*unit);
+            gles.MatrixMode(*mode);
+            gles.LoadMatrixf(stack[0].as_ptr());
+            for matrix in &stack[1..] {
+                gles.PushMatrix();
+                gles.LoadMatrixf(matrix.as_ptr());
+            }
+        }
+
+        self.replay_bindings(gles);
+    }
+
+    unsafe fn replay_bindings(&self, gles: &mut dyn GLES) {
+        for (i, &texture) in self.queried.texture_bindings.iter().enumerate() {
+            gles.ActiveTexture(gles11::TEXTURE0 + i as GLenum);
+            gles.BindTexture(gles11::TEXTURE_2D, texture);
+        }
+        for (&pname, &value) in BINDINGS.iter().zip(self.queried.bindings.iter()) {
+            let value = value as GLuint;
+            match pname {
+                gles11::ARRAY_BUFFER_BINDING => gles.BindBuffer(gles11::ARRAY_BUFFER, value),
+                gles11::ELEMENT_ARRAY_BUFFER_BINDING => {
+                    gles.BindBuffer(gles11::ELEMENT_ARRAY_BUFFER, value)
+                }
+                gles11::FRAMEBUFFER_BINDING_OES => {
+                    gles.BindFramebufferOES(gles11::FRAMEBUFFER_OES, value)
+                }
+                gles11::RENDERBUFFER_BINDING_OES => {
+                    gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, value)
+                }
+                gles11::ACTIVE_TEXTURE => gles.ActiveTexture(value),
+                gles11::CLIENT_ACTIVE_TEXTURE => gles.ClientActiveTexture(value),
+                gles11::MATRIX_MODE => gles.MatrixMode(value),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+impl SaveState for GlesRecord {
+    fn save(&self, w: &mut Writer) {
+        let GlesRecord {
+            state_calls,
+            textures,
+            buffers,
+            renderbuffers,
+            framebuffers,
+            pointers,
+            queried,
+        } = self;
+        // The keys of state calls are derived from the calls.
+        save_iter(w, state_calls.len(), state_calls.values());
+        textures.save(w);
+        buffers.save(w);
+        renderbuffers.save(w);
+        framebuffers.save(w);
+        pointers.save(w);
+        queried.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        let state_calls: Vec<StateCall> = SaveState::restore(r)?;
+        Ok(GlesRecord {
+            state_calls: state_calls
+                .into_iter()
+                .map(|call| (call.key(), call))
+                .collect(),
+            textures: SaveState::restore(r)?,
+            buffers: SaveState::restore(r)?,
+            renderbuffers: SaveState::restore(r)?,
+            framebuffers: SaveState::restore(r)?,
+            pointers: SaveState::restore(r)?,
+            queried: SaveState::restore(r)?,
+        })
+    }
+}
@@ -9,6 +9,8 @@
 //! likely to use UIKit in very simple and limited ways, so this implementation
 //! will probably take a lot of shortcuts.
 
+use crate::frameworks::media_player::movie_player;
+use crate::save_state::impl_SaveState_for_struct;
 use crate::Environment;
 use std::time::Instant;
 
@@ -42,6 +44,17 @@ pub struct State {
     ui_touch: ui_touch::State,
     pub ui_view: ui_view::State,
 }
+impl_SaveState_for_struct!(State {
+    ui_accelerometer,
+    ui_application,
+    ui_color,
+    ui_device,
+    ui_font,
+    ui_graphics,
+    ui_screen,
+    ui_touch,
+    ui_view
+});
 
 /// For use by `NSRunLoop`: handles any events that have queued up.
 ///
@@ -50,9 +63,29 @@ pub struct State {
 pub fn handle_events(env: &mut Environment) -> Option<Instant> {
     use crate::window::Event;
 
+    if let Some(ref mut recording) = env.input_recording {
+        recording.next_iteration();
+    }
+
     loop {
         // NSRunLoop will never call this function in headless mode.
-        let Some(event) = env.window.as_mut().unwrap().pop_event() else {
+        let event = if let Some(event) = env.window.as_mut().unwrap().pop_event() {
+            // When replaying input, live input other than quitting and
+            // debugging is discarded.
+            match env.input_recording {
+                Some(ref mut recording) => match recording.filter_live_event(event) {
+                    Some(event) => event,
+                    None => continue,
+                },
+                None => event,
+            }
+        } else if let Some(event) = env
+            .input_recording
+            .as_mut()
+            .and_then(|recording| recording.pop_replayed_event())
+        {
+            event
+        } else {
             break;
         };
 
@@ -62,7 +95,9 @@ pub fn handle_events(env: &mut Environment) -> Option<Instant> {
                 ui_application::exit(env);
             }
             Event::TouchesDown(..) | Event::TouchesMove(..) | Event::TouchesUp(..) => {
-                ui_touch::handle_event(env, event)
+                if !movie_player::handle_touch_event(env, &event) {
+                    ui_touch::handle_event(env, event)
+                }
             }
             Event::AppWillResignActive => {
                 // Getting this event means touchHLE is becoming inactive, e.g.
@@ -89,13 +124,23 @@ pub fn handle_events(env: &mut Environment) -> Option<Instant> {
             }
             Event::EnterDebugger => {
                 if env.is_debugging_enabled() {
-                    log!("Handling EnterDebugger event: entering debugger.");
-                    let step = env.enter_debugger(/* reason: */ None);
-                    assert!(!step, "Can't step right now!"); // TODO?
+                    env.enter_debugger_from_host(
+                        "Handling EnterDebugger event: entering debugger.",
+                    );
                 } else {
                     log!("Ignoring EnterDebugger event: no debugger connected.");
                 }
             }
+            Event::SaveState => {
+                if let Err(e) = env.save_state() {
+                    echo!("Couldn't make save state: {}", e);
+                }
+            }
+            Event::LoadState => {
+                if let Err(e) = env.load_state() {
+                    echo!("Couldn't load save state: {}", e);
+                }
+            }
         }
     }
 
@@ -10,8 +10,12 @@
 
 use crate::frameworks::foundation::NSTimeInterval;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, HostObject,
-    NSZonePtr, TrivialHostObject,
+    autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, NSZonePtr,
+    TrivialHostObject,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 use std::time::{Duration, Instant};
@@ -25,6 +29,12 @@ pub struct State {
     update_interval: Option<NSTimeInterval>,
     due_by: Option<Instant>,
 }
+impl_SaveState_for_struct!(State {
+    shared_accelerometer,
+    delegate,
+    update_interval,
+    due_by
+});
 
 type UIAccelerationValue = f64;
 
@@ -36,7 +46,11 @@ struct UIAccelerationHostObject {
     z: UIAccelerationValue,
     timestamp: NSTimeInterval,
 }
-impl HostObject for UIAccelerationHostObject {}
+impl_HostObject_with_SaveState!(UIAccelerationHostObject);
+impl_SaveState_for_struct!(UIAccelerationHostObject { x, y, z, timestamp });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(UIAccelerationHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -130,7 +144,7 @@ pub(super) fn handle_accelerometer(env: &mut Environment) -> Option<Instant> {
     let ns_interval = state.update_interval.unwrap_or(DEFAULT_UPDATE_INTERVAL);
     let rust_interval = Duration::from_secs_f64(ns_interval);
 
-    let now = Instant::now();
+    let now = env.clock.now();
     let new_due_by = if let Some(due_by) = state.due_by {
         if due_by > now {
             return Some(due_by);
@@ -157,7 +171,11 @@ pub(super) fn handle_accelerometer(env: &mut Environment) -> Option<Instant> {
     // UIKit creates and drains autorelease pools when handling events.
     let pool: id = msg_class![env; NSAutoreleasePool new];
 
-    let (x, y, z) = env.window().get_acceleration(&env.options);
+    let live = env.window().get_acceleration(&env.options);
+    let (x, y, z) = match env.input_recording {
+        Some(ref mut recording) => recording.filter_acceleration(live),
+        None => live,
+    };
     let timestamp: NSTimeInterval = msg_class![env; NSProcessInfo systemUptime];
     let acceleration: id = msg_class![env; UIAcceleration alloc];
     *env.objc.borrow_mut(acceleration) = UIAccelerationHostObject {
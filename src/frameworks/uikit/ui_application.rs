@@ -7,12 +7,15 @@
 
 use super::ui_device::*;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::frameworks::foundation::{ns_array, ns_string};
+use crate::frameworks::foundation::{ns_array, ns_string, ns_user_defaults};
 use crate::frameworks::uikit::ui_nib::load_main_nib_file;
 use crate::mem::MutPtr;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::window::DeviceOrientation;
 use crate::Environment;
@@ -23,12 +26,23 @@ pub struct State {
     shared_application: Option<id>,
     pub(super) status_bar_hidden: bool,
 }
+impl_SaveState_for_struct!(State {
+    shared_application,
+    status_bar_hidden
+});
 
 struct UIApplicationHostObject {
     delegate: id,
     delegate_is_retained: bool,
 }
-impl HostObject for UIApplicationHostObject {}
+impl_HostObject_with_SaveState!(UIApplicationHostObject);
+impl_SaveState_for_struct!(UIApplicationHostObject {
+    delegate,
+    delegate_is_retained
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(UIApplicationHostObject)];
 
 type UIInterfaceOrientation = UIDeviceOrientation;
 
@@ -300,7 +314,8 @@ pub(super) fn exit(env: &mut Environment) {
         let _: () = msg![env; pool drain];
     };
 
-    std::process::exit(0);
+    ns_user_defaults::synchronize_standard_defaults(env);
+    env.exit(0);
 }
 
 pub const FUNCTIONS: FunctionExports = &[export_c_func!(UIApplicationMain(_, _, _, _))];
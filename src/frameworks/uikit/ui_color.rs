@@ -8,7 +8,11 @@
 use crate::frameworks::core_graphics::CGFloat;
 use crate::mem::MutPtr;
 use crate::objc::{
-    autorelease, id, msg, msg_class, objc_classes, ClassExports, HostObject, NSZonePtr, ObjC, SEL,
+    autorelease, id, msg, msg_class, objc_classes, ClassExports, NSZonePtr, ObjC, SEL,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 use std::collections::HashMap;
@@ -17,6 +21,7 @@ use std::collections::HashMap;
 pub struct State {
     standard_colors: HashMap<SEL, id>,
 }
+impl_SaveState_for_struct!(State { standard_colors });
 
 fn get_standard_color(
     env: &mut Environment,
@@ -43,7 +48,10 @@ fn get_standard_color(
 struct UIColorHostObject {
     rgba: (CGFloat, CGFloat, CGFloat, CGFloat),
 }
-impl HostObject for UIColorHostObject {}
+impl_HostObject_with_SaveState!(UIColorHostObject);
+impl_SaveState_for_struct!(UIColorHostObject { rgba });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(UIColorHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
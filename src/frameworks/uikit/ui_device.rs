@@ -10,6 +10,7 @@ use crate::dyld::HostConstant;
 use crate::frameworks::foundation::ns_string;
 use crate::frameworks::foundation::NSInteger;
 use crate::objc::{id, objc_classes, ClassExports, TrivialHostObject};
+use crate::save_state::impl_SaveState_for_struct;
 use crate::window::DeviceOrientation;
 
 pub const UIDeviceOrientationDidChangeNotification: &str =
@@ -32,6 +33,7 @@ pub const UIDeviceOrientationFaceDown: UIDeviceOrientation = 6;
 pub struct State {
     current_device: Option<id>,
 }
+impl_SaveState_for_struct!(State { current_device });
 
 pub const CONSTANTS: ConstantExports = &[(
     "_UIDeviceOrientationDidChangeNotification",
@@ -9,7 +9,11 @@ use super::ui_touch::UITouchHostObject;
 use crate::frameworks::foundation::NSUInteger;
 use crate::mem::MutVoidPtr;
 use crate::objc::{
-    id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+    id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 
@@ -17,7 +21,10 @@ pub(super) struct UIEventHostObject {
     /// `NSSet<UITouch*>*`
     touches: id,
 }
-impl HostObject for UIEventHostObject {}
+impl_HostObject_with_SaveState!(UIEventHostObject);
+impl_SaveState_for_struct!(UIEventHostObject { touches });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(UIEventHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
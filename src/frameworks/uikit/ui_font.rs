@@ -10,7 +10,11 @@ use crate::font::{Font, TextAlignment, WrapMode};
 use crate::frameworks::core_graphics::cg_bitmap_context::CGBitmapContextDrawer;
 use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
 use crate::frameworks::foundation::NSInteger;
-use crate::objc::{autorelease, id, objc_classes, ClassExports, HostObject};
+use crate::objc::{autorelease, id, objc_classes, ClassExports};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_enum,
+    impl_SaveState_for_struct, HostObjectRestorers, Reader, SaveState, Writer,
+};
 use crate::Environment;
 use std::ops::Range;
 
@@ -22,6 +26,13 @@ pub(super) struct State {
     regular_ja: Option<Font>,
     bold_ja: Option<Font>,
 }
+/// The fonts are loaded on first use, so there's no need to save them.
+impl SaveState for State {
+    fn save(&self, _w: &mut Writer) {}
+    fn restore(_r: &mut Reader) -> Result<Self, String> {
+        Ok(State::default())
+    }
+}
 
 #[derive(Copy, Clone)]
 enum FontKind {
@@ -34,7 +45,16 @@ struct UIFontHostObject {
     size: CGFloat,
     kind: FontKind,
 }
-impl HostObject for UIFontHostObject {}
+impl_HostObject_with_SaveState!(UIFontHostObject);
+impl_SaveState_for_struct!(UIFontHostObject { size, kind });
+
+impl_SaveState_for_enum!(FontKind {
+    Regular,
+    Bold,
+    Italic
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(UIFontHostObject)];
 
 /// Line break mode.
 ///
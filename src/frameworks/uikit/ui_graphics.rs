@@ -10,12 +10,14 @@ use crate::frameworks::core_graphics::cg_context::{
     CGContextRef, CGContextRelease, CGContextRetain,
 };
 use crate::objc::nil;
+use crate::save_state::impl_SaveState_for_struct;
 use crate::Environment;
 
 #[derive(Default)]
 pub(super) struct State {
     pub(super) context_stack: Vec<CGContextRef>,
 }
+impl_SaveState_for_struct!(State { context_stack });
 
 pub fn UIGraphicsPushContext(env: &mut Environment, context: CGContextRef) {
     CGContextRetain(env, context);
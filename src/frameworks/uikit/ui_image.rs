@@ -13,14 +13,20 @@ use crate::frameworks::uikit::ui_graphics::UIGraphicsGetCurrentContext;
 use crate::fs::GuestPath;
 use crate::image::Image;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 
 struct UIImageHostObject {
     cg_image: CGImageRef,
 }
-impl HostObject for UIImageHostObject {}
+impl_HostObject_with_SaveState!(UIImageHostObject);
+impl_SaveState_for_struct!(UIImageHostObject { cg_image });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(UIImageHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -13,8 +13,11 @@ use crate::frameworks::foundation::ns_string::{get_static_str, to_rust_string};
 use crate::frameworks::foundation::{ns_string, NSUInteger};
 use crate::fs::GuestPathBuf;
 use crate::objc::{
-    id, impl_HostObject_with_superclass, msg, msg_class, msg_super, nil, objc_classes, release,
-    retain, ClassExports, HostObject,
+    id, msg, msg_class, msg_super, nil, objc_classes, release, retain, ClassExports,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 
@@ -23,7 +26,12 @@ struct UIRuntimeConnectionHostObject {
     label: id,
     source: id,
 }
-impl HostObject for UIRuntimeConnectionHostObject {}
+impl_HostObject_with_SaveState!(UIRuntimeConnectionHostObject);
+impl_SaveState_for_struct!(UIRuntimeConnectionHostObject {
+    destination,
+    label,
+    source
+});
 impl Default for UIRuntimeConnectionHostObject {
     fn default() -> Self {
         UIRuntimeConnectionHostObject {
@@ -39,7 +47,16 @@ struct UIRuntimeEventConnectionHostObject {
     superclass: UIRuntimeConnectionHostObject,
     eventMask: i32,
 }
-impl_HostObject_with_superclass!(UIRuntimeEventConnectionHostObject);
+impl_HostObject_with_SaveState!(UIRuntimeEventConnectionHostObject, superclass);
+impl_SaveState_for_struct!(UIRuntimeEventConnectionHostObject {
+    superclass,
+    eventMask
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[
+    host_object_restorer!(UIRuntimeConnectionHostObject),
+    host_object_restorer!(UIRuntimeEventConnectionHostObject),
+];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -7,11 +7,13 @@
 
 use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
 use crate::objc::{id, msg, objc_classes, ClassExports, TrivialHostObject};
+use crate::save_state::impl_SaveState_for_struct;
 
 #[derive(Default)]
 pub struct State {
     main_screen: Option<id>,
 }
+impl_SaveState_for_struct!(State { main_screen });
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -10,8 +10,11 @@ use crate::frameworks::core_graphics::{CGPoint, CGRect};
 use crate::frameworks::foundation::{NSInteger, NSTimeInterval, NSUInteger};
 use crate::mem::MutVoidPtr;
 use crate::objc::{
-    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::window::{Coords, Event, FingerId};
 use crate::Environment;
@@ -28,6 +31,7 @@ pub const UITouchPhaseEnded: UITouchPhase = 3;
 pub struct State {
     current_touches: HashMap<FingerId, id>,
 }
+impl_SaveState_for_struct!(State { current_touches });
 
 pub(super) struct UITouchHostObject {
     /// Strong reference to the `UIView`
@@ -42,7 +46,17 @@ pub(super) struct UITouchHostObject {
     timestamp: NSTimeInterval,
     phase: UITouchPhase,
 }
-impl HostObject for UITouchHostObject {}
+impl_HostObject_with_SaveState!(UITouchHostObject);
+impl_SaveState_for_struct!(UITouchHostObject {
+    view,
+    window,
+    location,
+    previous_location,
+    timestamp,
+    phase
+});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(UITouchHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -21,8 +21,11 @@ use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect};
 use crate::frameworks::foundation::ns_string::get_static_str;
 use crate::frameworks::foundation::{ns_array, NSInteger, NSUInteger};
 use crate::objc::{
-    autorelease, id, msg, nil, objc_classes, release, retain, Class, ClassExports, HostObject,
-    NSZonePtr,
+    autorelease, id, msg, nil, objc_classes, release, retain, Class, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 
@@ -32,6 +35,7 @@ pub struct State {
     pub(super) views: Vec<id>,
     pub ui_window: ui_window::State,
 }
+impl_SaveState_for_struct!(State { views, ui_window });
 
 pub(super) struct UIViewHostObject {
     /// CALayer or subclass.
@@ -44,7 +48,15 @@ pub(super) struct UIViewHostObject {
     user_interaction_enabled: bool,
     multiple_touch_enabled: bool,
 }
-impl HostObject for UIViewHostObject {}
+impl_HostObject_with_SaveState!(UIViewHostObject);
+impl_SaveState_for_struct!(UIViewHostObject {
+    layer,
+    subviews,
+    superview,
+    clears_context_before_drawing,
+    user_interaction_enabled,
+    multiple_touch_enabled
+});
 impl Default for UIViewHostObject {
     fn default() -> UIViewHostObject {
         // The Default trait is implemented so subclasses will get the same
@@ -60,6 +72,8 @@ impl Default for UIViewHostObject {
     }
 }
 
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(UIViewHostObject)];
+
 /// Shared parts of `initWithCoder:` and `initWithFrame:`. These can't call
 /// `init`: the subclass may have overridden `init` and will not expect to be
 /// called here.
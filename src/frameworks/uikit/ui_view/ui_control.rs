@@ -14,8 +14,11 @@ pub mod ui_text_field;
 use crate::frameworks::core_graphics::CGPoint;
 use crate::frameworks::foundation::NSUInteger;
 use crate::objc::{
-    id, impl_HostObject_with_superclass, msg, msg_send, msg_super, nil, objc_classes, release,
-    retain, ClassExports, NSZonePtr, SEL,
+    id, msg, msg_send, msg_super, nil, objc_classes, release, retain, ClassExports, NSZonePtr, SEL,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 
@@ -41,7 +44,16 @@ struct UIControlHostObject {
     /// reference!
     action_targets: Vec<(id, SEL, UIControlEvents)>,
 }
-impl_HostObject_with_superclass!(UIControlHostObject);
+impl_HostObject_with_SaveState!(UIControlHostObject, superclass);
+impl_SaveState_for_struct!(UIControlHostObject {
+    superclass,
+    enabled,
+    selected,
+    highlighted,
+    tracked_touch,
+    tracking,
+    action_targets
+});
 impl Default for UIControlHostObject {
     fn default() -> Self {
         UIControlHostObject {
@@ -56,6 +68,9 @@ impl Default for UIControlHostObject {
     }
 }
 
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(UIControlHostObject)];
+
 type UIControlState = NSUInteger;
 pub const UIControlStateNormal: UIControlState = 0;
 const UIControlStateHighlighted: UIControlState = 1 << 0;
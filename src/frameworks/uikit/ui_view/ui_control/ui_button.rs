@@ -10,8 +10,12 @@ use crate::frameworks::core_graphics::{CGPoint, CGRect};
 use crate::frameworks::foundation::NSInteger;
 use crate::frameworks::uikit::ui_font::UITextAlignmentCenter;
 use crate::objc::{
-    autorelease, id, impl_HostObject_with_superclass, msg, msg_class, msg_super, nil, objc_classes,
-    release, retain, ClassExports, NSZonePtr,
+    autorelease, id, msg, msg_class, msg_super, nil, objc_classes, release, retain, ClassExports,
+    NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 use crate::Environment;
 use std::collections::HashMap;
@@ -46,7 +50,18 @@ pub struct UIButtonHostObject {
     /// Values are `UIImage*`
     background_images_for_states: HashMap<UIControlState, id>,
 }
-impl_HostObject_with_superclass!(UIButtonHostObject);
+impl_HostObject_with_SaveState!(UIButtonHostObject, superclass);
+impl_SaveState_for_struct!(UIButtonHostObject {
+    superclass,
+    type_,
+    title_label,
+    image_view,
+    background_image_view,
+    titles_for_states,
+    title_colors_for_states,
+    images_for_states,
+    background_images_for_states
+});
 impl Default for UIButtonHostObject {
     fn default() -> Self {
         UIButtonHostObject {
@@ -63,6 +78,8 @@ impl Default for UIButtonHostObject {
     }
 }
 
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(UIButtonHostObject)];
+
 fn update(env: &mut Environment, this: id) {
     let title_label: id = msg![env; this titleLabel];
     let title: id = msg![env; this currentTitle];
@@ -7,9 +7,10 @@
 
 use crate::frameworks::core_graphics::cg_image::CGImageRef;
 use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
-use crate::objc::{
-    id, impl_HostObject_with_superclass, msg, msg_super, objc_classes, release, retain,
-    ClassExports, NSZonePtr,
+use crate::objc::{id, msg, msg_super, objc_classes, release, retain, ClassExports, NSZonePtr};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 
 #[derive(Default)]
@@ -18,7 +19,11 @@ struct UIImageViewHostObject {
     /// `UIImage*`
     image: id,
 }
-impl_HostObject_with_superclass!(UIImageViewHostObject);
+impl_HostObject_with_SaveState!(UIImageViewHostObject, superclass);
+impl_SaveState_for_struct!(UIImageViewHostObject { superclass, image });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(UIImageViewHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
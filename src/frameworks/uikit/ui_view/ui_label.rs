@@ -15,8 +15,11 @@ use crate::frameworks::uikit::ui_font::{
 };
 use crate::frameworks::uikit::ui_graphics::UIGraphicsGetCurrentContext;
 use crate::objc::{
-    id, impl_HostObject_with_superclass, msg, msg_class, msg_super, nil, objc_classes, release,
-    retain, ClassExports, NSZonePtr,
+    id, msg, msg_class, msg_super, nil, objc_classes, release, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 
 pub struct UILabelHostObject {
@@ -31,7 +34,16 @@ pub struct UILabelHostObject {
     line_break_mode: UILineBreakMode,
     number_of_lines: NSInteger,
 }
-impl_HostObject_with_superclass!(UILabelHostObject);
+impl_HostObject_with_SaveState!(UILabelHostObject, superclass);
+impl_SaveState_for_struct!(UILabelHostObject {
+    superclass,
+    text,
+    font,
+    text_color,
+    text_alignment,
+    line_break_mode,
+    number_of_lines
+});
 impl Default for UILabelHostObject {
     fn default() -> Self {
         UILabelHostObject {
@@ -46,6 +58,8 @@ impl Default for UILabelHostObject {
     }
 }
 
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(UILabelHostObject)];
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
@@ -7,6 +7,7 @@
 
 use crate::frameworks::core_graphics::CGRect;
 use crate::objc::{id, msg, msg_super, objc_classes, ClassExports};
+use crate::save_state::impl_SaveState_for_struct;
 
 #[derive(Default)]
 pub struct State {
@@ -15,6 +16,7 @@ pub struct State {
     /// This is public because Core Animation also uses it.
     pub visible_windows: Vec<id>,
 }
+impl_SaveState_for_struct!(State { visible_windows });
 
 pub const CLASSES: ClassExports = objc_classes! {
 
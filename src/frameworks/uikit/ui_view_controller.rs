@@ -7,14 +7,22 @@
 
 use crate::frameworks::foundation::ns_string::get_static_str;
 use crate::objc::{
-    id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject, NSZonePtr,
+    id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, NSZonePtr,
+};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers,
 };
 
 #[derive(Default)]
 struct UIViewControllerHostObject {
     view: id,
 }
-impl HostObject for UIViewControllerHostObject {}
+impl_HostObject_with_SaveState!(UIViewControllerHostObject);
+impl_SaveState_for_struct!(UIViewControllerHostObject { view });
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers =
+    &[host_object_restorer!(UIViewControllerHostObject)];
 
 pub const CLASSES: ClassExports = objc_classes! {
 
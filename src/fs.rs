@@ -30,6 +30,7 @@ pub use bundle::BundleData;
 
 use crate::fs::bundle::{IpaFile, IpaFileRef};
 use crate::paths;
+use crate::save_state::{impl_SaveState_for_struct, Reader, SaveState, Writer};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Seek, Write};
@@ -54,10 +55,12 @@ enum FsNode {
         writeable: bool,
     },
     Directory {
-        children: HashMap<String, FsNode>,
+        children: FsChildren,
         writeable: Option<PathBuf>,
     },
 }
+/// The contents of a directory, by name.
+type FsChildren = HashMap<String, FsNode>;
 impl FsNode {
     fn from_host_dir(host_path: &Path, writeable: bool) -> Self {
         let mut children = HashMap::new();
@@ -210,6 +213,14 @@ impl ToOwned for GuestPath {
 /// Like [PathBuf] but for the virtual filesystem.
 #[derive(Debug, Clone)]
 pub struct GuestPathBuf(String);
+impl SaveState for GuestPathBuf {
+    fn save(&self, w: &mut Writer) {
+        self.0.save(w)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(GuestPathBuf(SaveState::restore(r)?))
+    }
+}
 impl From<String> for GuestPathBuf {
     fn from(string: String) -> GuestPathBuf {
         GuestPathBuf(string)
@@ -284,9 +295,22 @@ fn resolve_path<'a>(path: &'a GuestPath, relative_to: Option<&'a GuestPath>) ->
     components
 }
 
+/// Inverse of [resolve_path].
+fn join_path_components(components: &[&str]) -> GuestPathBuf {
+    if components.is_empty() {
+        return GuestPathBuf::from(String::from("/"));
+    }
+    let mut path = String::with_capacity(components.iter().map(|c| c.len() + 1).sum());
+    for component in components {
+        path.push('/');
+        path.push_str(component);
+    }
+    GuestPathBuf::from(path)
+}
+
 /// Like [std::fs::OpenOptions] but for the guest filesystem.
 /// TODO: `create_new`.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GuestOpenOptions {
     read: bool,
     write: bool,
@@ -324,7 +348,23 @@ impl GuestOpenOptions {
         self.truncate = true;
         self
     }
+    /// Get the options for opening an already-opened file again, i.e. without
+    /// creating or truncating it.
+    pub fn for_reopening(&self) -> GuestOpenOptions {
+        GuestOpenOptions {
+            create: false,
+            truncate: false,
+            ..self.clone()
+        }
+    }
 }
+impl_SaveState_for_struct!(GuestOpenOptions {
+    read,
+    write,
+    append,
+    create,
+    truncate
+});
 
 /// Handles host I/O errors by panicking. This is intended specifically for
 /// opening files. The assumption is that the guest filesystem contains all the
@@ -447,8 +487,8 @@ impl Fs {
     ///
     /// The `bundle_id` argument should be some value that uniquely identifies
     /// the app. This will be used to construct the host path for the app's
-    /// sandbox directory, where documents can be stored. A directory will be
-    /// created at that path if it does not already exist.
+    /// sandbox directory, where documents and preferences can be stored. A
+    /// directory will be created at that path if it does not already exist.
     ///
     /// `read_only_mode` can be used when the app won't actually be run, just
     /// just inspected (e.g. to retrieve display name and icon), so no user data
@@ -467,16 +507,25 @@ impl Fs {
 
         let bundle_guest_path = home_directory.join(&bundle_dir_name);
 
-        let documents_host_path = if !read_only_mode {
+        // Writeable directories in the app's sandbox, and the subdirectories
+        // that should exist within them from the start.
+        const SANDBOX_DIRS: &[(&str, &[&str])] =
+            &[("Documents", &[]), ("Library", &["Preferences"])];
+
+        let sandbox_host_path = if !read_only_mode {
             let path = paths::user_data_base_path()
                 .join(paths::SANDBOX_DIR)
-                .join(bundle_id)
-                .join("Documents");
-            if let Err(e) = std::fs::create_dir_all(&path) {
-                panic!(
-                    "Could not create documents directory for app at {:?}: {:?}",
-                    path, e
-                );
+                .join(bundle_id);
+            for &(dir, subdirs) in SANDBOX_DIRS {
+                for subdir in std::iter::once("").chain(subdirs.iter().copied()) {
+                    let path = path.join(dir).join(subdir);
+                    if let Err(e) = std::fs::create_dir_all(&path) {
+                        panic!(
+                            "Could not create sandbox directory for app at {:?}: {:?}",
+                            path, e
+                        );
+                    }
+                }
             }
             Some(path)
         } else {
@@ -502,11 +551,13 @@ impl Fs {
 
         let mut app_dir_children = HashMap::new();
         app_dir_children.insert(bundle_dir_name, app_bundle.into_fs_node());
-        if let Some(documents_host_path) = documents_host_path {
-            app_dir_children.insert(
-                "Documents".to_string(),
-                FsNode::from_host_dir(&documents_host_path, /* writeable: */ true),
-            );
+        if let Some(sandbox_host_path) = sandbox_host_path {
+            for &(dir, _) in SANDBOX_DIRS {
+                app_dir_children.insert(
+                    dir.to_string(),
+                    FsNode::from_host_dir(&sandbox_host_path.join(dir), /* writeable: */ true),
+                );
+            }
         }
 
         let root = FsNode::dir()
@@ -553,6 +604,11 @@ impl Fs {
         &self.home_directory
     }
 
+    /// Set the working directory without checking it, for loading a save state.
+    pub fn set_working_directory_unchecked(&mut self, path: GuestPathBuf) {
+        self.working_directory = path;
+    }
+
     /// Get the absolute path of the current working directory. The resulting
     /// path may be invalid if the directory was moved or deleted.
     pub fn working_directory(&self) -> &GuestPath {
@@ -568,20 +624,16 @@ impl Fs {
         ) {
             return Err(());
         }
-        let new_path = if resolved.is_empty() {
-            String::from("/")
-        } else {
-            let mut new_path = String::with_capacity(resolved.iter().map(|c| c.len() + 1).sum());
-            for component in resolved {
-                new_path.push('/');
-                new_path.push_str(component);
-            }
-            new_path
-        };
-        self.working_directory = GuestPathBuf::from(new_path);
+        self.working_directory = join_path_components(&resolved);
         Ok(&self.working_directory)
     }
 
+    /// Get the absolute form of a path, relative to the working directory.
+    /// Whether anything exists at the path isn't checked.
+    pub fn absolute_path(&self, path: &GuestPath) -> GuestPathBuf {
+        join_path_components(&resolve_path(path, Some(&self.working_directory)))
+    }
+
     /// [Self::lookup_node] with a pre-resolved path.
     fn lookup_node_inner(&self, resolved_path_components: &[&str]) -> Option<&FsNode> {
         let mut node = &self.root;
@@ -626,6 +678,47 @@ impl Fs {
         Some((parent, final_component.to_string()))
     }
 
+    /// Find the directory containing the file at `path`, for opening or
+    /// creating the file. Returns the directory's children and the file name,
+    /// plus, if the file doesn't exist and `create` is [true], the host path
+    /// to create it at. Fails if the directory is read-only and the file would
+    /// have to be created.
+    fn lookup_file_for_create(
+        &mut self,
+        path: &GuestPath,
+        create: bool,
+    ) -> Result<(&mut FsChildren, String, Option<PathBuf>), ()> {
+        let (parent_node, file_name) = self.lookup_parent_node(path).ok_or(())?;
+        let FsNode::Directory {
+            children,
+            writeable: dir_host_path,
+        } = parent_node
+        else {
+            return Err(());
+        };
+
+        if !create || children.contains_key(&file_name) {
+            return Ok((children, file_name, None));
+        }
+
+        let Some(dir_host_path) = dir_host_path else {
+            log!(
+                "Warning: attempt to create file at path {:?}, but directory is read-only",
+                path
+            );
+            return Err(());
+        };
+
+        for c in file_name.chars() {
+            if std::path::is_separator(c) {
+                panic!("Attempt to create file at path {:?}, but filename contains path separator character {:?}!", path, c);
+            }
+        }
+
+        let host_path = dir_host_path.join(&file_name);
+        Ok((children, file_name, Some(host_path)))
+    }
+
     /// Like [Path::exists] but for the guest filesystem.
     pub fn exists(&self, path: &GuestPath) -> bool {
         self.lookup_node(path).is_some()
@@ -723,6 +816,62 @@ impl Fs {
             .map_err(|_| ())
     }
 
+    /// Like [Self::write], but the data is first written to a temporary file
+    /// which then replaces the file at `path`, so that the file is never left
+    /// partially written. This is what `writeToFile:atomically:` does.
+    pub fn write_atomically<P: AsRef<GuestPath>>(
+        &mut self,
+        path: P,
+        data: &[u8],
+    ) -> Result<(), ()> {
+        let path = path.as_ref();
+
+        let (children, file_name, new_host_path) = self.lookup_file_for_create(path, true)?;
+
+        let host_path = match children.get(&file_name) {
+            Some(FsNode::File {
+                location: FileLocation::Path(host_path),
+                writeable: true,
+            }) => host_path.clone(),
+            Some(_) => {
+                log!(
+                    "Warning: attempt to write to read-only file or directory at path {:?}",
+                    path
+                );
+                return Err(());
+            }
+            None => new_host_path.unwrap(),
+        };
+
+        // The temporary file is in the same host directory, so that renaming
+        // it over the real file is atomic.
+        let mut temp_file_name = host_path.file_name().unwrap().to_owned();
+        temp_file_name.push(".tmp");
+        let temp_host_path = host_path.with_file_name(temp_file_name);
+        if let Err(e) = std::fs::write(&temp_host_path, data)
+            .and_then(|()| std::fs::rename(&temp_host_path, &host_path))
+        {
+            log!(
+                "Warning: couldn't write file at path {:?} (host path: {:?}): {}",
+                path,
+                host_path,
+                e
+            );
+            let _ = std::fs::remove_file(&temp_host_path);
+            return Err(());
+        }
+        log_dbg!(
+            "Atomically wrote file at path {:?} (host path: {:?})",
+            path,
+            host_path
+        );
+        children.entry(file_name).or_insert(FsNode::File {
+            location: FileLocation::Path(host_path),
+            writeable: true,
+        });
+        Ok(())
+    }
+
     /// Like [File::open] but for the guest filesystem.
     #[allow(dead_code)]
     pub fn open<P: AsRef<GuestPath>>(&self, path: P) -> Result<GuestFile, ()> {
@@ -762,14 +911,7 @@ impl Fs {
 
         let path = path.as_ref();
 
-        let (parent_node, new_filename) = self.lookup_parent_node(path).ok_or(())?;
-        let FsNode::Directory {
-            children,
-            writeable: dir_host_path,
-        } = parent_node
-        else {
-            return Err(());
-        };
+        let (children, new_filename, new_host_path) = self.lookup_file_for_create(path, create)?;
 
         // Open an existing file if possible
 
@@ -817,26 +959,10 @@ impl Fs {
 
         // Create a new file otherwise
 
-        if !create {
-            return Err(());
-        }
-
-        let Some(dir_host_path) = dir_host_path else {
-            log!(
-                "Warning: attempt to create file at path {:?}, but directory is read-only",
-                path
-            );
+        let Some(host_path) = new_host_path else {
             return Err(());
         };
 
-        for c in new_filename.chars() {
-            if std::path::is_separator(c) {
-                panic!("Attempt to create file at path {:?}, but filename contains path separator character {:?}!", path, c);
-            }
-        }
-
-        let host_path = dir_host_path.join(&new_filename);
-
         let file = handle_open_err(
             File::options()
                 .read(read)
@@ -243,6 +243,14 @@ pub struct IpaFile {
     file: std::io::Cursor<Vec<u8>>,
 }
 
+impl IpaFile {
+    /// Get the in-memory contents of the file, for libraries that can't use
+    /// [IpaFile] itself.
+    pub fn into_cursor(self) -> std::io::Cursor<Vec<u8>> {
+        self.file
+    }
+}
+
 impl Debug for IpaFile {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("IpaFile")
@@ -12,8 +12,13 @@
 //!   - `include/gdb/signals.def` for the meanings of signal numbers
 //!   - `gdb/arch/arm.h` for ARMv6 register numbers
 
-use crate::cpu::{Cpu, CpuError};
+pub mod symbol_file;
+
+use crate::cpu::{Cpu, CpuError, Watchpoint, WatchpointKind};
+use crate::environment::Thread;
+use crate::mach_o::MachO;
 use crate::mem::{GuestUSize, Mem, Ptr};
+use crate::ThreadId;
 use std::fmt::Write as _;
 use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
 use std::net::TcpStream;
@@ -27,15 +32,113 @@ const TARGET_XML: &str = r#"
 </target>
 "#;
 
+/// Build the GDB library list XML for the dynamic libraries (i.e. all binaries
+/// other than the app binary). The binaries aren't relocated, so the address of
+/// the first segment is just where it was always meant to be.
+fn library_list_xml(bins: &[MachO]) -> String {
+    fn escape(s: &str) -> String {
+        let mut res = String::with_capacity(s.len());
+        for c in s.chars() {
+            match c {
+                '&' => res.push_str("&amp;"),
+                '<' => res.push_str("&lt;"),
+                '>' => res.push_str("&gt;"),
+                '"' => res.push_str("&quot;"),
+                // Keeping the XML ASCII-only makes it easier to send.
+                c if !c.is_ascii() => write!(res, "&#{};", c as u32).unwrap(),
+                c => res.push(c),
+            }
+        }
+        res
+    }
+
+    let mut xml = String::from("<library-list>\n");
+    for bin in &bins[1..] {
+        let Some(addr) = bin.text_segment_base else {
+            continue;
+        };
+        writeln!(
+            xml,
+            "    <library name=\"{}\"><segment address=\"{:#x}\"/></library>",
+            escape(&bin.name),
+            addr
+        )
+        .unwrap();
+    }
+    xml.push_str("</library-list>\n");
+    xml
+}
+
+/// GDB thread IDs must be positive, so they are offset by one from
+/// [ThreadId]s.
+fn thread_to_gdb(thread: ThreadId) -> String {
+    format!("{:x}", thread + 1)
+}
+
+/// Thread ID in a packet from the debugger.
+enum GdbThreadId {
+    /// `-1`
+    All,
+    /// `0`
+    Any,
+    Thread(ThreadId),
+}
+
+fn thread_from_gdb(id: &str) -> Option<GdbThreadId> {
+    if id == "-1" {
+        return Some(GdbThreadId::All);
+    }
+    match usize::from_str_radix(id, 16).ok()? {
+        0 => Some(GdbThreadId::Any),
+        id => Some(GdbThreadId::Thread(id - 1)),
+    }
+}
+
+/// Parse the `type,addr,kind` part of a `Z` or `z` packet as a watchpoint.
+/// Returns [None] for other kinds of breakpoint.
+fn parse_watchpoint(params: &str) -> Option<Watchpoint> {
+    let mut params = params.split(',');
+    let kind = match params.next()? {
+        "2" => WatchpointKind::Write,
+        "3" => WatchpointKind::Read,
+        "4" => WatchpointKind::Access,
+        _ => return None,
+    };
+    let addr = GuestUSize::from_str_radix(params.next()?, 16).ok()?;
+    let size = GuestUSize::from_str_radix(params.next()?, 16).ok()?;
+    Some(Watchpoint { kind, addr, size })
+}
+
+/// Run `f` with the CPU's registers being those of `thread`, which might not
+/// be the current thread.
+pub fn with_thread_regs<T>(
+    cpu: &mut Cpu,
+    threads: &mut [Thread],
+    current_thread: ThreadId,
+    thread: ThreadId,
+    f: impl FnOnce(&mut Cpu) -> T,
+) -> T {
+    if thread == current_thread {
+        return f(cpu);
+    }
+    let context = threads[thread].context_mut().unwrap();
+    cpu.swap_context(context);
+    let res = f(cpu);
+    cpu.swap_context(context);
+    res
+}
+
 /// GDB Remote Serial Protocol handler, implementing a server.
 pub struct GdbServer {
     reader: BufReader<TcpStream>,
     first_halt: bool,
+    library_list_xml: String,
 }
 
 impl GdbServer {
-    /// Create the handler from a TCP connection.
-    pub fn new(mut connection: TcpStream) -> GdbServer {
+    /// Create the handler from a TCP connection. `bins` are the loaded
+    /// binaries, as in [crate::Environment::bins].
+    pub fn new(mut connection: TcpStream, bins: &[MachO]) -> GdbServer {
         connection
             .set_read_timeout(Some(Duration::from_secs(3)))
             .unwrap();
@@ -54,6 +157,7 @@ impl GdbServer {
         GdbServer {
             reader: BufReader::with_capacity(4096, connection),
             first_halt: true,
+            library_list_xml: library_list_xml(bins),
         }
     }
 
@@ -126,18 +230,69 @@ impl GdbServer {
         log_dbg!("Sent packet: {:?}", body);
     }
 
+    /// Reply to a `qXfer:...:read` packet with part of some ASCII data.
+    fn send_xfer_reply(&mut self, data: &str, offset: usize, length: usize) {
+        let bytes = data.as_bytes();
+        if offset > bytes.len() {
+            // Invalid offset
+            self.send_packet("E00");
+            return;
+        }
+        let bytes = &bytes[offset..];
+        let length_read = length.min(bytes.len());
+        let mut packet = String::with_capacity(1 + length_read);
+        if length_read < length {
+            // Read data, none left
+            packet.push('l');
+        } else {
+            // Read data, more may remain
+            packet.push('m');
+        }
+        // This packet uses the modern style of binary data where most bytes
+        // are unescaped. These ones must be escaped.
+        for &byte in &bytes[..length_read] {
+            assert!(byte.is_ascii());
+            if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+                packet.push('}');
+                packet.push((byte ^ 0x20) as char);
+            } else {
+                packet.push(byte as char);
+            }
+        }
+        self.send_packet(&packet);
+    }
+
+    /// Send a stop reply packet with the signal number and the thread that
+    /// stopped, plus optional extra `name:value;` pairs.
+    fn send_stop_reply(&mut self, signal: u8, extra: &str, thread: ThreadId) {
+        self.send_packet(&format!(
+            "T{:02x}{}thread:{};",
+            signal,
+            extra,
+            thread_to_gdb(thread)
+        ));
+    }
+
     /// Communciates with the debugger, returning only once it requests
     /// execution should continue. Returns [true] if the CPU should step and
     /// then resume debugging, or [false] if it should resume normal execution.
+    ///
+    /// Execution always resumes on the current thread, but the debugger can
+    /// inspect and modify the registers of any thread.
     #[must_use]
     pub fn wait_for_debugger(
         &mut self,
         stop_reason: Option<CpuError>,
         cpu: &mut Cpu,
         mem: &mut Mem,
+        threads: &mut [Thread],
+        current_thread: ThreadId,
     ) -> bool {
         echo!("Waiting for debugger to continue.");
 
+        // Thread used for register access ('Hg').
+        let mut general_thread = current_thread;
+
         // Send reply to continue/step packet that gdb sent earlier, so it knows
         // why execution was stopped.
         match stop_reason {
@@ -149,7 +304,7 @@ impl GdbServer {
                 } else {
                     // The debugger previously requested stepping and no errors
                     // occurred.
-                    self.send_packet("S05"); // SIGTRAP
+                    self.send_stop_reply(0x05, "", current_thread); // SIGTRAP
                 }
             }
             // GDB uses an undefined instruction for software breakpoints in
@@ -157,10 +312,19 @@ impl GdbServer {
             // It apparently expects SIGTRAP instead of SIGILL even in the
             // former case.
             Some(CpuError::UndefinedInstruction) | Some(CpuError::Breakpoint) => {
-                self.send_packet("S05"); // SIGTRAP
+                self.send_stop_reply(0x05, "", current_thread); // SIGTRAP
             }
             Some(CpuError::MemoryError) => {
-                self.send_packet("S0b"); // SIGSEGV
+                self.send_stop_reply(0x0b, "", current_thread); // SIGSEGV
+            }
+            Some(CpuError::Watchpoint(watchpoint)) => {
+                let name = match watchpoint.kind {
+                    WatchpointKind::Write => "watch",
+                    WatchpointKind::Read => "rwatch",
+                    WatchpointKind::Access => "awatch",
+                };
+                let extra = format!("{}:{:x};", name, watchpoint.addr);
+                self.send_stop_reply(0x05, &extra, current_thread); // SIGTRAP
             }
         }
 
@@ -177,12 +341,16 @@ impl GdbServer {
                 // Query for target halt reason when first connecting
                 b'?' => {
                     assert!(stop_reason.is_none());
-                    self.send_packet("S00"); // no signal
+                    self.send_stop_reply(0x00, "", current_thread); // no signal
                 }
                 // Read general registers
                 b'g' => {
+                    let regs =
+                        with_thread_regs(cpu, threads, current_thread, general_thread, |cpu| {
+                            *cpu.regs()
+                        });
                     let mut packet = String::with_capacity(16 * 4 * 2);
-                    for reg in cpu.regs() {
+                    for reg in regs {
                         // Rust always prints in big-endian, but GDB expects
                         // little-endian.
                         let reg = u32::from_be_bytes(reg.to_le_bytes());
@@ -193,29 +361,34 @@ impl GdbServer {
                 // Write general registers
                 b'G' => {
                     let data = &p[1..];
-                    let regs = cpu.regs_mut();
-                    assert!(data.len() == regs.len() * 4 * 2);
-                    for (i, reg) in regs.iter_mut().enumerate() {
-                        let word = &data[i * 4 * 2..][..4 * 2];
-                        let word = u32::from_str_radix(word, 16).unwrap();
-                        // Rust decodes in big-endian, but GDB supplies
-                        // little-endian.
-                        let word = u32::from_le_bytes(word.to_be_bytes());
-                        *reg = word;
-                    }
+                    with_thread_regs(cpu, threads, current_thread, general_thread, |cpu| {
+                        let regs = cpu.regs_mut();
+                        assert!(data.len() == regs.len() * 4 * 2);
+                        for (i, reg) in regs.iter_mut().enumerate() {
+                            let word = &data[i * 4 * 2..][..4 * 2];
+                            let word = u32::from_str_radix(word, 16).unwrap();
+                            // Rust decodes in big-endian, but GDB supplies
+                            // little-endian.
+                            let word = u32::from_le_bytes(word.to_be_bytes());
+                            *reg = word;
+                        }
+                    });
                     self.send_packet("OK");
                 }
                 // Read single register by number
                 b'p' => {
                     let num = usize::from_str_radix(&p[1..], 16).unwrap();
-                    let reg = if num < 16 {
-                        Some(cpu.regs()[num])
-                    } else if num == 25 {
-                        Some(cpu.cpsr())
-                    // TODO: FPSCR, VFP registers
-                    } else {
-                        None
-                    };
+                    let reg =
+                        with_thread_regs(cpu, threads, current_thread, general_thread, |cpu| {
+                            if num < 16 {
+                                Some(cpu.regs()[num])
+                            } else if num == 25 {
+                                Some(cpu.cpsr())
+                            // TODO: FPSCR, VFP registers
+                            } else {
+                                None
+                            }
+                        });
                     if let Some(reg) = reg {
                         // Rust always prints in big-endian, but GDB expects
                         // little-endian.
@@ -234,13 +407,21 @@ impl GdbServer {
                     // Rust decodes in big-endian, but GDB supplies
                     // little-endian.
                     let word = u32::from_le_bytes(word.to_be_bytes());
-                    if num < 16 {
-                        cpu.regs_mut()[num] = word;
-                        self.send_packet("OK");
-                    } else if num == 25 {
-                        cpu.set_cpsr(word);
+                    let written =
+                        with_thread_regs(cpu, threads, current_thread, general_thread, |cpu| {
+                            if num < 16 {
+                                cpu.regs_mut()[num] = word;
+                                true
+                            } else if num == 25 {
+                                cpu.set_cpsr(word);
+                                true
+                            // TODO: FPSCR, VFP registers
+                            } else {
+                                false
+                            }
+                        });
+                    if written {
                         self.send_packet("OK");
-                    // TODO: FPSCR, VFP registers
                     } else {
                         // Error 0
                         self.send_packet("E00");
@@ -294,7 +475,8 @@ impl GdbServer {
                 b'c' | b's' => {
                     let addr = &p[1..];
                     if !addr.is_empty() {
-                        todo!("TODO: Resume at {}", addr);
+                        let addr = u32::from_str_radix(addr, 16).unwrap();
+                        cpu.regs_mut()[Cpu::PC] = addr;
                     }
                     break p.as_bytes()[0] == b's';
                 }
@@ -303,10 +485,73 @@ impl GdbServer {
                 b'C' | b'S' => {
                     // Signal is just ignored for now (TODO?)
                     if let Some((_signal, addr)) = p[1..].split_once(';') {
-                        todo!("TODO: Resume at {}", addr);
+                        let addr = u32::from_str_radix(addr, 16).unwrap();
+                        cpu.regs_mut()[Cpu::PC] = addr;
                     }
                     break p.as_bytes()[0] == b'S';
                 }
+                // Set thread for subsequent operations
+                b'H' => {
+                    let thread = match thread_from_gdb(&p[2..]) {
+                        Some(GdbThreadId::All) | Some(GdbThreadId::Any) => Some(current_thread),
+                        Some(GdbThreadId::Thread(thread))
+                            if threads.get(thread).is_some_and(|t| t.active) =>
+                        {
+                            Some(thread)
+                        }
+                        _ => None,
+                    };
+                    match (p.as_bytes()[1], thread) {
+                        (b'g', Some(thread)) => {
+                            general_thread = thread;
+                            self.send_packet("OK");
+                        }
+                        (b'c', Some(thread)) => {
+                            if thread != current_thread {
+                                log!(
+                                    "Debugger wants to resume thread {}, but only the current thread ({}) can be resumed.",
+                                    thread,
+                                    current_thread
+                                );
+                            }
+                            self.send_packet("OK");
+                        }
+                        _ => {
+                            // Error 0
+                            self.send_packet("E00");
+                        }
+                    }
+                }
+                // Query whether thread is alive
+                b'T' => {
+                    let alive = match thread_from_gdb(&p[1..]) {
+                        Some(GdbThreadId::Thread(thread)) => {
+                            threads.get(thread).is_some_and(|t| t.active)
+                        }
+                        _ => false,
+                    };
+                    if alive {
+                        self.send_packet("OK");
+                    } else {
+                        // Error 0
+                        self.send_packet("E00");
+                    }
+                }
+                // Insert or remove breakpoint or watchpoint
+                b'Z' | b'z' => {
+                    // Software breakpoints are left to GDB (see below).
+                    if let Some(watchpoint) = parse_watchpoint(&p[1..]) {
+                        if p.as_bytes()[0] == b'Z' {
+                            cpu.add_watchpoint(watchpoint);
+                        } else {
+                            cpu.remove_watchpoint(watchpoint);
+                        }
+                        self.send_packet("OK");
+                    } else {
+                        // Unsupported
+                        self.send_packet("");
+                    }
+                }
                 // Kill
                 b'k' => {
                     panic!("Debugger requested kill.");
@@ -317,43 +562,54 @@ impl GdbServer {
                     if p == "qAttached" {
                         // New process
                         self.send_packet("0");
+                    // Query current thread
+                    } else if p == "qC" {
+                        self.send_packet(&format!("QC{}", thread_to_gdb(current_thread)));
+                    // List threads. The list fits in one reply, so the
+                    // follow-up query gets an empty one.
+                    } else if p == "qfThreadInfo" {
+                        let ids: Vec<String> = threads
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, thread)| thread.active)
+                            .map(|(id, _)| thread_to_gdb(id))
+                            .collect();
+                        self.send_packet(&format!("m{}", ids.join(",")));
+                    } else if p == "qsThreadInfo" {
+                        self.send_packet("l");
                     // Query for supported features
                     } else if p == "qSupported" || p.starts_with("qSupported:") {
-                        // Tell GDB we can send it an XML target description.
-                        self.send_packet("qXfer:features:read+");
-                    // Read XML target description
-                    } else if let Some(params) = p.strip_prefix("qXfer:features:read:") {
-                        let (annex, params) = params.split_once(':').unwrap();
-                        let (offset, length) = params.split_once(',').unwrap();
+                        // Tell GDB we can send it an XML target description and
+                        // an XML list of libraries.
+                        self.send_packet("qXfer:features:read+;qXfer:libraries:read+");
+                    // Read XML target description or list of libraries
+                    } else if let Some(params) = p.strip_prefix("qXfer:") {
+                        let mut params = params.splitn(4, ':');
+                        let object = params.next().unwrap();
+                        let operation = params.next().unwrap();
+                        let annex = params.next().unwrap();
+                        let (offset, length) = params.next().unwrap().split_once(',').unwrap();
                         let offset = usize::from_str_radix(offset, 16).unwrap();
                         let length = usize::from_str_radix(length, 16).unwrap();
-                        let bytes = TARGET_XML.as_bytes();
-                        if annex == "target.xml" && offset <= bytes.len() {
-                            let bytes = &bytes[offset..];
-                            let length_read = length.min(bytes.len());
-                            let mut packet = String::with_capacity(1 + length_read);
-                            if length_read < length {
-                                // Read data, more remains
-                                packet.push('l');
-                            } else {
-                                // Read data, none left
-                                packet.push('m');
+                        match (object, operation, annex) {
+                            ("features", "read", "target.xml") => {
+                                self.send_xfer_reply(TARGET_XML, offset, length);
+                            }
+                            ("libraries", "read", "") => {
+                                let xml = std::mem::take(&mut self.library_list_xml);
+                                self.send_xfer_reply(&xml, offset, length);
+                                self.library_list_xml = xml;
+                            }
+                            _ => {
+                                // Unsupported object or annex
+                                self.send_packet("E00");
                             }
-                            // This packet uses the modern style of binary
-                            // data where most bytes are unescaped.
-                            // We happen to know none of the bytes in the XML
-                            // need escaping, and that they're all ASCII.
-                            packet.push_str(std::str::from_utf8(&bytes[..length_read]).unwrap());
-                            self.send_packet(&packet);
-                        } else {
-                            // Unsupported annex or invalid offset
-                            self.send_packet("E00");
                         }
                     } else {
                         log_dbg!("Unhandled packet.");
                         // Tell GDB we don't understand this packet.
                         // In some cases this causes convenient fallbacks:
-                        // Since we don't support 'Z', GDB will implement
+                        // Since we don't support 'Z0', GDB will implement
                         // software breakpoints for us with trap instructions.
                         self.send_packet("");
                     }
@@ -0,0 +1,316 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Symbol file for the debugger (`--gdb-symbols=`).
+//!
+//! GDB's Mach-O support only goes so far, and it knows nothing about the
+//! Objective-C methods of the guest app, which are often the only meaningful
+//! names in an app binary. This module writes an ELF file that contains no
+//! code or data, only empty sections at the same addresses as the Mach-O
+//! sections of the guest binaries, and a symbol table with:
+//!
+//! - The symbols defined by each binary, both exported and local.
+//! - The symbol stubs, named after the function they call, e.g. `malloc$stub`.
+//! - The Objective-C methods implemented by guest code, e.g.
+//!   `-[GameScene update:]`.
+//!
+//! It can be loaded with `add-symbol-file`.
+//!
+//! Useful resources:
+//! - [ELF specification](https://refspecs.linuxfoundation.org/elf/elf.pdf)
+//! - [ELF for the Arm Architecture](https://github.com/ARM-software/abi-aa/blob/main/aaelf32/aaelf32.rst)
+
+use crate::abi::GuestFunction;
+use crate::mach_o::{MachO, SectionType};
+use crate::mem::Mem;
+use crate::objc::ObjC;
+use std::path::Path;
+
+struct ElfSection {
+    name: String,
+    addr: u32,
+    size: u32,
+    is_code: bool,
+}
+
+struct ElfSymbol {
+    /// Has the Thumb bit set for Thumb functions, which is also the convention
+    /// in ELF.
+    addr: u32,
+    name: String,
+}
+
+/// Write the symbol file. This should be done after linking, so that the
+/// Objective-C classes are known.
+pub fn write(path: &Path, bins: &[MachO], objc: &ObjC, mem: &Mem) -> Result<(), String> {
+    let (sections, symbols) = collect(bins, objc, mem);
+    std::fs::write(path, build_elf(&sections, &symbols))
+        .map_err(|e| format!("Could not write symbol file {}: {}", path.display(), e))?;
+    echo!(
+        "Wrote {} symbols to {}. Load them in GDB with: add-symbol-file {}",
+        symbols.len(),
+        path.display(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn collect(bins: &[MachO], objc: &ObjC, mem: &Mem) -> (Vec<ElfSection>, Vec<ElfSymbol>) {
+    // Mach-O symbols for C functions and variables have a leading underscore,
+    // but ELF ones don't.
+    fn symbol(addr: u32, name: &str) -> ElfSymbol {
+        let name = name.strip_prefix('_').unwrap_or(name).to_string();
+        ElfSymbol { addr, name }
+    }
+
+    let mut sections = Vec::new();
+    let mut symbols = Vec::new();
+    for bin in bins {
+        for section in &bin.sections {
+            if section.size == 0 {
+                continue;
+            }
+            let is_stubs = section.type_ == SectionType::SymbolStubs;
+            sections.push(ElfSection {
+                name: section.name.clone(),
+                addr: section.addr,
+                size: section.size,
+                is_code: is_stubs || section.name == "__text" || section.name == "__stub_helper",
+            });
+            if !is_stubs {
+                continue;
+            }
+            let info = section.dyld_indirect_symbol_info.as_ref().unwrap();
+            for (i, name) in info.indirect_undef_symbols.iter().enumerate() {
+                if let Some(name) = name {
+                    let addr = section.addr + i as u32 * info.entry_size;
+                    symbols.push(symbol(addr, &format!("{}$stub", name)));
+                }
+            }
+        }
+        for (name, &addr) in &bin.exported_symbols {
+            symbols.push(symbol(addr, name));
+        }
+        for (addr, name) in &bin.local_symbols {
+            symbols.push(symbol(*addr, name));
+        }
+    }
+    for (addr, name) in objc.guest_method_symbols(mem) {
+        symbols.push(ElfSymbol { addr, name });
+    }
+    symbols.sort_by(|a, b| (a.addr, &a.name).cmp(&(b.addr, &b.name)));
+    symbols.dedup_by(|a, b| (a.addr, &a.name) == (b.addr, &b.name));
+    (sections, symbols)
+}
+
+fn build_elf(sections: &[ElfSection], symbols: &[ElfSymbol]) -> Vec<u8> {
+    const HEADER_SIZE: u32 = 52;
+    const SECTION_HEADER_SIZE: u32 = 40;
+    const SYMBOL_SIZE: u32 = 16;
+
+    const SHT_SYMTAB: u32 = 2;
+    const SHT_STRTAB: u32 = 3;
+    const SHT_NOBITS: u32 = 8;
+    const SHF_ALLOC: u32 = 0x2;
+    const SHF_EXECINSTR: u32 = 0x4;
+    const SHN_ABS: u16 = 0xfff1;
+    const STB_GLOBAL: u8 = 1;
+    const STT_OBJECT: u8 = 1;
+    const STT_FUNC: u8 = 2;
+
+    fn add_string(table: &mut Vec<u8>, s: &str) -> u32 {
+        let offset = table.len() as u32;
+        table.extend_from_slice(s.as_bytes());
+        table.push(b'\0');
+        offset
+    }
+    fn align4(data: &mut Vec<u8>) {
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+
+    // Section 0 is the null section, then there's one section for each Mach-O
+    // section, then the symbol table and the string tables.
+    let symtab_index = sections.len() as u32 + 1;
+    let strtab_index = symtab_index + 1;
+    let shstrtab_index = strtab_index + 1;
+    let section_count = shstrtab_index + 1;
+
+    let mut shstrtab = vec![0u8];
+    let section_names: Vec<u32> = sections
+        .iter()
+        .map(|section| add_string(&mut shstrtab, &section.name))
+        .collect();
+    let symtab_name = add_string(&mut shstrtab, ".symtab");
+    let strtab_name = add_string(&mut shstrtab, ".strtab");
+    let shstrtab_name = add_string(&mut shstrtab, ".shstrtab");
+
+    let mut strtab = vec![0u8];
+    // Symbol 0 is the null symbol.
+    let mut symtab = vec![0u8; SYMBOL_SIZE as usize];
+    for symbol in symbols {
+        let name = add_string(&mut strtab, &symbol.name);
+        let addr = symbol.addr & !GuestFunction::THUMB_BIT;
+        let section = sections
+            .iter()
+            .position(|section| (section.addr..section.addr + section.size).contains(&addr));
+        let is_function = symbol.addr & GuestFunction::THUMB_BIT != 0
+            || section.is_some_and(|index| sections[index].is_code);
+        symtab.extend_from_slice(&name.to_le_bytes());
+        symtab.extend_from_slice(&symbol.addr.to_le_bytes());
+        symtab.extend_from_slice(&0u32.to_le_bytes()); // size (unknown)
+        symtab.push((STB_GLOBAL << 4) | if is_function { STT_FUNC } else { STT_OBJECT });
+        symtab.push(0); // visibility
+        let section_index = section.map_or(SHN_ABS, |index| index as u16 + 1);
+        symtab.extend_from_slice(&section_index.to_le_bytes());
+    }
+
+    let mut data = vec![0u8; HEADER_SIZE as usize];
+    let symtab_offset = data.len() as u32;
+    data.extend_from_slice(&symtab);
+    let strtab_offset = data.len() as u32;
+    data.extend_from_slice(&strtab);
+    let shstrtab_offset = data.len() as u32;
+    data.extend_from_slice(&shstrtab);
+    align4(&mut data);
+    let section_headers_offset = data.len() as u32;
+
+    let mut section_header = |fields: [u32; 10]| {
+        for field in fields {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+    };
+    // Fields: name, type, flags, addr, offset, size, link, info, addralign,
+    // entsize.
+    section_header([0; 10]);
+    for (section, &name) in sections.iter().zip(&section_names) {
+        let flags = if section.is_code {
+            SHF_ALLOC | SHF_EXECINSTR
+        } else {
+            SHF_ALLOC
+        };
+        let (addr, size) = (section.addr, section.size);
+        section_header([name, SHT_NOBITS, flags, addr, 0, size, 0, 0, 1, 0]);
+    }
+    section_header([
+        symtab_name,
+        SHT_SYMTAB,
+        0,
+        0,
+        symtab_offset,
+        symtab.len() as u32,
+        strtab_index,
+        1, // index of first non-local symbol
+        4,
+        SYMBOL_SIZE,
+    ]);
+    let strtab_size = strtab.len() as u32;
+    section_header([
+        strtab_name,
+        SHT_STRTAB,
+        0,
+        0,
+        strtab_offset,
+        strtab_size,
+        0,
+        0,
+        1,
+        0,
+    ]);
+    let shstrtab_size = shstrtab.len() as u32;
+    section_header([
+        shstrtab_name,
+        SHT_STRTAB,
+        0,
+        0,
+        shstrtab_offset,
+        shstrtab_size,
+        0,
+        0,
+        1,
+        0,
+    ]);
+
+    let header = &mut data[..HEADER_SIZE as usize];
+    header[..16].copy_from_slice(&[
+        0x7f, b'E', b'L', b'F', 1, // ELFCLASS32
+        1, // ELFDATA2LSB
+        1, // EV_CURRENT
+        0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ]);
+    let mut offset = 16;
+    let mut field = |bytes: &[u8]| {
+        header[offset..][..bytes.len()].copy_from_slice(bytes);
+        offset += bytes.len();
+    };
+    field(&2u16.to_le_bytes()); // e_type: ET_EXEC
+    field(&40u16.to_le_bytes()); // e_machine: EM_ARM
+    field(&1u32.to_le_bytes()); // e_version: EV_CURRENT
+    field(&0u32.to_le_bytes()); // e_entry
+    field(&0u32.to_le_bytes()); // e_phoff
+    field(&section_headers_offset.to_le_bytes()); // e_shoff
+    field(&0x05000000u32.to_le_bytes()); // e_flags: EF_ARM_EABI_VER5
+    field(&(HEADER_SIZE as u16).to_le_bytes()); // e_ehsize
+    field(&0u16.to_le_bytes()); // e_phentsize
+    field(&0u16.to_le_bytes()); // e_phnum
+    field(&(SECTION_HEADER_SIZE as u16).to_le_bytes()); // e_shentsize
+    field(&(section_count as u16).to_le_bytes()); // e_shnum
+    field(&(shstrtab_index as u16).to_le_bytes()); // e_shstrndx
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..][..4].try_into().unwrap())
+    }
+
+    #[test]
+    fn elf_layout() {
+        let sections = [ElfSection {
+            name: "__text".to_string(),
+            addr: 0x2000,
+            size: 0x100,
+            is_code: true,
+        }];
+        let symbols = [
+            ElfSymbol {
+                addr: 0x2011,
+                name: "-[Foo bar]".to_string(),
+            },
+            ElfSymbol {
+                addr: 0x8000,
+                name: "baz".to_string(),
+            },
+        ];
+        let elf = build_elf(&sections, &symbols);
+        assert_eq!(&elf[..4], b"\x7fELF");
+
+        let shoff = u32_at(&elf, 32) as usize;
+        let shnum = u16::from_le_bytes([elf[48], elf[49]]);
+        assert_eq!(shnum, 5);
+        assert_eq!(elf.len(), shoff + 5 * 40);
+
+        // The Mach-O section keeps its address.
+        assert_eq!(u32_at(&elf, shoff + 40 + 12), 0x2000);
+
+        // The symbol table has the null symbol plus ours.
+        let symtab_header = shoff + 2 * 40;
+        let symtab_offset = u32_at(&elf, symtab_header + 16) as usize;
+        assert_eq!(u32_at(&elf, symtab_header + 20), 3 * 16);
+        let first = symtab_offset + 16;
+        assert_eq!(u32_at(&elf, first + 4), 0x2011);
+        assert_eq!(elf[first + 12], 0x12); // global function
+        assert_eq!(u16::from_le_bytes([elf[first + 14], elf[first + 15]]), 1);
+        let second = first + 16;
+        assert_eq!(elf[second + 12], 0x11); // global object
+        assert_eq!(
+            u16::from_le_bytes([elf[second + 14], elf[second + 15]]),
+            0xfff1
+        );
+    }
+}
@@ -24,6 +24,8 @@
 //!   - [gles1_native] passes through native OpenGL ES 1.1.
 //!   - [gles1_on_gl2] provides an implementation of OpenGL ES 1.1 using OpenGL
 //!     2.1 compatibility profile.
+//!   - [gles1_software] provides an implementation of OpenGL ES 1.1 that
+//!     renders on the CPU, without using the host's OpenGL at all.
 //!   - There might be more in future.
 //! - [gles11_raw] provides raw bindings for OpenGL ES 1.1 generated from the
 //!   Khronos API headers. **The function bindings are only for use within this
@@ -62,6 +64,7 @@
 
 pub mod gles1_native;
 pub mod gles1_on_gl2;
+pub mod gles1_software;
 mod gles_generic;
 pub mod present;
 mod util;
@@ -71,6 +74,7 @@ pub use touchHLE_gl_bindings::gles11 as gles11_raw;
 
 use gles1_native::GLES1Native;
 use gles1_on_gl2::GLES1OnGL2;
+use gles1_software::GLES1Software;
 pub use gles_generic::GLES;
 
 /// Labels for [GLES] implementations and an abstraction for constructing them.
@@ -80,6 +84,9 @@ pub enum GLESImplementation {
     GLES1Native,
     /// [GLES1OnGL2].
     GLES1OnGL2,
+    /// [GLES1Software]. This is never chosen automatically, because it needs
+    /// the window to be created differently.
+    GLES1Software,
 }
 impl GLESImplementation {
     /// List of OpenGL ES 1.1 implementations in order of preference.
@@ -90,6 +97,7 @@ impl GLESImplementation {
         match name {
             "gles1_on_gl2" => Ok(Self::GLES1OnGL2),
             "gles1_native" => Ok(Self::GLES1Native),
+            "software" => Ok(Self::GLES1Software),
             _ => Err(()),
         }
     }
@@ -98,6 +106,7 @@ impl GLESImplementation {
         match self {
             Self::GLES1Native => GLES1Native::description(),
             Self::GLES1OnGL2 => GLES1OnGL2::description(),
+            Self::GLES1Software => GLES1Software::description(),
         }
     }
     /// See [GLES::new].
@@ -108,6 +117,7 @@ impl GLESImplementation {
         match self {
             Self::GLES1Native => GLES1Native::new(window).map(boxer),
             Self::GLES1OnGL2 => GLES1OnGL2::new(window).map(boxer),
+            Self::GLES1Software => GLES1Software::new(window).map(boxer),
         }
     }
 }
@@ -22,7 +22,7 @@ use super::gl21compat_raw as gl21;
 use super::gl21compat_raw::types::*;
 use super::gles11_raw as gles11; // constants only
 use super::util::{
-    fixed_to_float, matrix_fixed_to_float, try_decode_pvrtc, PalettedTextureFormat, ParamTable,
+    fixed_to_float, matrix_fixed_to_float, try_decode_paletted, try_decode_pvrtc, ParamTable,
     ParamType,
 };
 use super::GLES;
@@ -283,7 +283,7 @@ const GET_PARAMS: ParamTable = ParamTable(&[
     (gl21::MAX_VERTEX_UNITS_ARB, ParamType::Int, 1),
 ]);
 
-const POINT_PARAMS: ParamTable = ParamTable(&[
+pub(super) const POINT_PARAMS: ParamTable = ParamTable(&[
     (gl21::POINT_SIZE_MIN, ParamType::Float, 1),
     (gl21::POINT_SIZE_MAX, ParamType::Float, 1),
     (gl21::POINT_DISTANCE_ATTENUATION, ParamType::Float, 3),
@@ -292,7 +292,7 @@ const POINT_PARAMS: ParamTable = ParamTable(&[
 ]);
 
 /// Table of `glFog` parameters shared by OpenGL ES 1.1 and OpenGL 2.1.
-const FOG_PARAMS: ParamTable = ParamTable(&[
+pub(super) const FOG_PARAMS: ParamTable = ParamTable(&[
     // Despite only having f, fv, x and xv setters in OpenGL ES 1.1, this is
     // an integer! (You're meant to use the x/xv setter.)
     (gl21::FOG_MODE, ParamType::Int, 1),
@@ -303,7 +303,7 @@ const FOG_PARAMS: ParamTable = ParamTable(&[
 ]);
 
 /// Table of `glLight` parameters shared by OpenGL ES 1.1 and OpenGL 2.1.
-const LIGHT_PARAMS: ParamTable = ParamTable(&[
+pub(super) const LIGHT_PARAMS: ParamTable = ParamTable(&[
     (gl21::AMBIENT, ParamType::Float, 4),
     (gl21::DIFFUSE, ParamType::Float, 4),
     (gl21::SPECULAR, ParamType::Float, 4),
@@ -317,7 +317,7 @@ const LIGHT_PARAMS: ParamTable = ParamTable(&[
 ]);
 
 /// Table of `glMaterial` parameters shared by OpenGL ES 1.1 and OpenGL 2.1.
-const MATERIAL_PARAMS: ParamTable = ParamTable(&[
+pub(super) const MATERIAL_PARAMS: ParamTable = ParamTable(&[
     (gl21::AMBIENT, ParamType::Float, 4),
     (gl21::DIFFUSE, ParamType::Float, 4),
     (gl21::SPECULAR, ParamType::Float, 4),
@@ -330,7 +330,7 @@ const MATERIAL_PARAMS: ParamTable = ParamTable(&[
 
 /// Table of `glTexEnv` parameters for the `GL_TEXTURE_ENV` target shared by
 /// OpenGL ES 1.1 and OpenGL 2.1.
-const TEX_ENV_PARAMS: ParamTable = ParamTable(&[
+pub(super) const TEX_ENV_PARAMS: ParamTable = ParamTable(&[
     (gl21::TEXTURE_ENV_MODE, ParamType::Int, 1),
     (gl21::COORD_REPLACE, ParamType::Int, 1),
     (gl21::COMBINE_RGB, ParamType::Int, 1),
@@ -353,7 +353,7 @@ const TEX_ENV_PARAMS: ParamTable = ParamTable(&[
 ]);
 
 /// Table of `glTexParameter` parameters.
-const TEX_PARAMS: ParamTable = ParamTable(&[
+pub(super) const TEX_PARAMS: ParamTable = ParamTable(&[
     (gl21::TEXTURE_MIN_FILTER, ParamType::Int, 1),
     (gl21::TEXTURE_MAG_FILTER, ParamType::Int, 1),
     (gl21::TEXTURE_WRAP_S, ParamType::Int, 1),
@@ -1377,68 +1377,17 @@ impl GLES for GLES1OnGL2 {
             log_dbg!("Decoded PVRTC");
         // OES_compressed_paletted_texture is only in OpenGL ES, so we'll need
         // to decompress those formats.
-        } else if let Some(PalettedTextureFormat {
-            index_is_nibble,
-            palette_entry_format,
-            palette_entry_type,
-        }) = PalettedTextureFormat::get_info(internalformat)
-        {
-            // This should be invalid use? (TODO)
-            assert!(border == 0);
-
-            let palette_entry_size = match palette_entry_type {
-                gl21::UNSIGNED_BYTE => match palette_entry_format {
-                    gl21::RGB => 3,
-                    gl21::RGBA => 4,
-                    _ => unreachable!(),
-                },
-                gl21::UNSIGNED_SHORT_5_6_5
-                | gl21::UNSIGNED_SHORT_4_4_4_4
-                | gl21::UNSIGNED_SHORT_5_5_5_1 => 2,
-                _ => unreachable!(),
-            };
-            let palette_entry_count = match index_is_nibble {
-                true => 16,
-                false => 256,
-            };
-            let palette_size = palette_entry_size * palette_entry_count;
-
-            let index_count = width as usize * height as usize;
-            let (index_word_size, index_word_count) = match index_is_nibble {
-                true => (1, (index_count + 1) / 2),
-                false => (4, (index_count + 3) / 4),
-            };
-            let indices_size = index_word_size * index_word_count;
-
-            // TODO: support multiple miplevels in one image
-            assert!(level == 0);
-            assert_eq!(data.len(), palette_size + indices_size);
-            let (palette, indices) = data.split_at(palette_size);
-
-            let mut decoded = Vec::<u8>::with_capacity(palette_entry_size * index_count);
-            for i in 0..index_count {
-                let index = if index_is_nibble {
-                    (indices[i / 2] >> ((1 - (i % 2)) * 4)) & 0xf
-                } else {
-                    indices[i]
-                } as usize;
-                let palette_entry = &palette[index * palette_entry_size..][..palette_entry_size];
-                decoded.extend_from_slice(palette_entry);
-            }
-            assert!(decoded.len() == palette_entry_size * index_count);
-
+        } else if try_decode_paletted(
+            self,
+            target,
+            level,
+            internalformat,
+            width,
+            height,
+            border,
+            data,
+        ) {
             log_dbg!("Decoded paletted texture");
-            gl21::TexImage2D(
-                target,
-                level,
-                palette_entry_format as _,
-                width,
-                height,
-                border,
-                palette_entry_format,
-                palette_entry_type,
-                decoded.as_ptr() as *const _,
-            )
         } else {
             unimplemented!("CompressedTexImage2D internalformat: {:#x}", internalformat);
         }
@@ -0,0 +1,2627 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Implementation of OpenGL ES 1.1 that renders entirely on the CPU.
+//!
+//! This doesn't need any graphics driver, so it's useful on machines where
+//! the host OpenGL implementation is missing or broken, and since it doesn't
+//! depend on the host GPU, its output is reproducible bit-for-bit. It's much
+//! slower than the other implementations, of course.
+//!
+//! Rather than rendering to an OpenGL surface, the default framebuffer is a
+//! [DefaultFramebuffer] owned by the window, which copies it to the screen
+//! when presenting (see [crate::window::Window::swap_window]).
+//!
+//! Only the functionality exposed by [GLES] is implemented. In particular,
+//! stencil and logic op state can't be changed from their defaults, so they
+//! have no effect.
+
+mod raster;
+mod texture;
+
+use super::gles11_raw as gles11; // constants only
+use super::gles11_raw::types::*;
+use super::gles1_on_gl2::{
+    ArrayInfo, ARRAYS, CAPABILITIES, FOG_PARAMS, LIGHT_PARAMS, MATERIAL_PARAMS, POINT_PARAMS,
+    TEX_ENV_PARAMS, TEX_PARAMS,
+};
+use super::util::{
+    fixed_to_float, matrix_fixed_to_float, try_decode_paletted, try_decode_pvrtc, ParamTable,
+    ParamType,
+};
+use super::GLES;
+use crate::window::Window;
+use raster::{Matrix, Target, IDENTITY};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use texture::{Plane, Rgba, Texture, TextureLevel};
+
+const TEXTURE_UNITS: usize = 2;
+const MAX_LIGHTS: usize = 8;
+const MAX_MODELVIEW_STACK_DEPTH: usize = 16;
+const MAX_PROJECTION_STACK_DEPTH: usize = 2;
+const MAX_TEXTURE_STACK_DEPTH: usize = 2;
+const MAX_TEXTURE_SIZE: u32 = 2048;
+const MAX_POINT_SIZE: f32 = 64.0;
+const MAX_LINE_WIDTH: f32 = 64.0;
+
+// Constants missing from the generated bindings.
+const POINT_SPRITE_OES: GLenum = 0x8861;
+const COORD_REPLACE_OES: GLenum = 0x8862;
+const DEPTH_COMPONENT24_OES: GLenum = 0x81A6;
+const STENCIL_INDEX8_OES: GLenum = 0x8D48;
+const DEPTH24_STENCIL8_OES: GLenum = 0x88F0;
+const IMPLEMENTATION_COLOR_READ_TYPE_OES: GLenum = 0x8B9A;
+const IMPLEMENTATION_COLOR_READ_FORMAT_OES: GLenum = 0x8B9B;
+
+/// The window's framebuffer when using the software renderer. It is shared by
+/// all contexts.
+pub struct DefaultFramebuffer {
+    color: Plane<Rgba>,
+    depth: Plane<f32>,
+    stencil: Plane<u8>,
+}
+impl DefaultFramebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        DefaultFramebuffer {
+            color: Plane::new(width, height),
+            depth: Plane::filled(width, height, 1.0),
+            stencil: Plane::new(width, height),
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.color.width, self.color.height)
+    }
+
+    /// Change the size of the framebuffer. The contents are lost.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if self.size() != (width, height) {
+            *self = Self::new(width, height);
+        }
+    }
+
+    /// Get the color data as RGBA8 pixels in top-to-bottom row order.
+    pub fn rgba8_top_to_bottom(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.color.data.len() * 4);
+        for row in self.color.data.chunks(self.color.width as usize).rev() {
+            pixels.extend(row.iter().flatten());
+        }
+        pixels
+    }
+}
+
+/// State of one of the client-side vertex arrays.
+#[derive(Clone, Copy)]
+struct ArrayState {
+    enabled: bool,
+    size: GLint,
+    type_: GLenum,
+    stride: GLsizei,
+    /// Offset into the buffer if [Self::buffer] is non-zero.
+    pointer: *const GLvoid,
+    buffer: GLuint,
+}
+impl ArrayState {
+    fn new(size: GLint) -> Self {
+        ArrayState {
+            enabled: false,
+            size,
+            type_: gles11::FLOAT,
+            stride: 0,
+            pointer: std::ptr::null(),
+            buffer: 0,
+        }
+    }
+}
+
+/// `glTexEnv` state for a texture unit.
+#[derive(Clone)]
+struct TexEnv {
+    mode: GLenum,
+    color: [f32; 4],
+    combine_rgb: GLenum,
+    combine_alpha: GLenum,
+    src_rgb: [GLenum; 3],
+    src_alpha: [GLenum; 3],
+    operand_rgb: [GLenum; 3],
+    operand_alpha: [GLenum; 3],
+    rgb_scale: f32,
+    alpha_scale: f32,
+}
+impl Default for TexEnv {
+    fn default() -> Self {
+        TexEnv {
+            mode: gles11::MODULATE,
+            color: [0.0; 4],
+            combine_rgb: gles11::MODULATE,
+            combine_alpha: gles11::MODULATE,
+            src_rgb: [gles11::TEXTURE, gles11::PREVIOUS, gles11::CONSTANT],
+            src_alpha: [gles11::TEXTURE, gles11::PREVIOUS, gles11::CONSTANT],
+            operand_rgb: [gles11::SRC_COLOR, gles11::SRC_COLOR, gles11::SRC_ALPHA],
+            operand_alpha: [gles11::SRC_ALPHA; 3],
+            rgb_scale: 1.0,
+            alpha_scale: 1.0,
+        }
+    }
+}
+
+struct TextureUnit {
+    /// `GL_TEXTURE_2D` capability.
+    enabled: bool,
+    binding: GLuint,
+    env: TexEnv,
+    matrix_stack: Vec<Matrix>,
+    coord_array: ArrayState,
+    current_coords: [f32; 4],
+    /// `GL_TEXTURE_LOD_BIAS_EXT`
+    lod_bias: f32,
+    /// `GL_COORD_REPLACE_OES`
+    coord_replace: bool,
+}
+impl Default for TextureUnit {
+    fn default() -> Self {
+        TextureUnit {
+            enabled: false,
+            binding: 0,
+            env: TexEnv::default(),
+            matrix_stack: vec![IDENTITY],
+            coord_array: ArrayState::new(4),
+            current_coords: [0.0, 0.0, 0.0, 1.0],
+            lod_bias: 0.0,
+            coord_replace: false,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Light {
+    ambient: [f32; 4],
+    diffuse: [f32; 4],
+    specular: [f32; 4],
+    /// In eye co-ordinates.
+    position: [f32; 4],
+    /// In eye co-ordinates.
+    spot_direction: [f32; 3],
+    spot_exponent: f32,
+    spot_cutoff: f32,
+    /// Constant, linear and quadratic attenuation.
+    attenuation: [f32; 3],
+}
+impl Light {
+    fn new(index: usize) -> Self {
+        // GL_LIGHT0 is special.
+        let color = if index == 0 {
+            [1.0, 1.0, 1.0, 1.0]
+        } else {
+            [0.0, 0.0, 0.0, 1.0]
+        };
+        Light {
+            ambient: [0.0, 0.0, 0.0, 1.0],
+            diffuse: color,
+            specular: color,
+            position: [0.0, 0.0, 1.0, 0.0],
+            spot_direction: [0.0, 0.0, -1.0],
+            spot_exponent: 0.0,
+            spot_cutoff: 180.0,
+            attenuation: [1.0, 0.0, 0.0],
+        }
+    }
+}
+
+struct Material {
+    ambient: [f32; 4],
+    diffuse: [f32; 4],
+    specular: [f32; 4],
+    emission: [f32; 4],
+    shininess: f32,
+}
+
+#[derive(Default)]
+struct Buffer {
+    data: Vec<u8>,
+}
+
+/// A renderbuffer. Which of the planes are used depends on the format.
+#[derive(Default)]
+struct Renderbuffer {
+    internalformat: GLenum,
+    width: u32,
+    height: u32,
+    color: Plane<Rgba>,
+    depth: Plane<f32>,
+    stencil: Plane<u8>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Attachment {
+    #[default]
+    None,
+    Renderbuffer(GLuint),
+    /// Texture name and level.
+    Texture(GLuint, usize),
+}
+
+#[derive(Clone, Copy, Default)]
+struct Framebuffer {
+    color: Attachment,
+    depth: Attachment,
+    stencil: Attachment,
+}
+
+/// Get the red, green, blue, alpha, depth and stencil sizes for a renderbuffer
+/// format, or [None] if it's not valid.
+fn renderbuffer_format_bits(internalformat: GLenum) -> Option<[GLint; 6]> {
+    match internalformat {
+        gles11::RGBA8_OES => Some([8, 8, 8, 8, 0, 0]),
+        gles11::RGB8_OES => Some([8, 8, 8, 0, 0, 0]),
+        gles11::RGB565_OES => Some([5, 6, 5, 0, 0, 0]),
+        gles11::RGBA4_OES => Some([4, 4, 4, 4, 0, 0]),
+        gles11::RGB5_A1_OES => Some([5, 5, 5, 1, 0, 0]),
+        gles11::DEPTH_COMPONENT16_OES => Some([0, 0, 0, 0, 16, 0]),
+        DEPTH_COMPONENT24_OES => Some([0, 0, 0, 0, 24, 0]),
+        STENCIL_INDEX8_OES => Some([0, 0, 0, 0, 0, 8]),
+        DEPTH24_STENCIL8_OES => Some([0, 0, 0, 0, 24, 8]),
+        _ => None,
+    }
+}
+
+/// Get the value of a scalar parameter given in fixed-point, converting it to
+/// floating-point only if the parameter isn't an integer.
+fn fixed_param(table: &ParamTable, pname: GLenum, param: GLfixed) -> GLfloat {
+    table.assert_component_count(pname, 1);
+    match table.get_type_info(pname).0 {
+        ParamType::Float | ParamType::FloatSpecial => fixed_to_float(param),
+        _ => param as GLfloat,
+    }
+}
+
+/// Vector version of [fixed_param].
+unsafe fn fixed_params(table: &ParamTable, pname: GLenum, params: *const GLfixed) -> Vec<GLfloat> {
+    let (type_, count) = table.get_type_info(pname);
+    (0..usize::from(count))
+        .map(|i| {
+            let param = params.add(i).read();
+            match type_ {
+                ParamType::Float | ParamType::FloatSpecial => fixed_to_float(param),
+                _ => param as GLfloat,
+            }
+        })
+        .collect()
+}
+
+unsafe fn float_params(table: &ParamTable, pname: GLenum, params: *const GLfloat) -> Vec<GLfloat> {
+    let (_type, count) = table.get_type_info(pname);
+    std::slice::from_raw_parts(params, usize::from(count)).to_vec()
+}
+
+/// Implements the `glGen*` functions.
+unsafe fn gen_names<T: Default>(objects: &mut HashMap<GLuint, T>, n: GLsizei, names: *mut GLuint) {
+    let mut next = 1;
+    for i in 0..n.max(0) as usize {
+        while objects.contains_key(&next) {
+            next += 1;
+        }
+        objects.insert(next, T::default());
+        names.add(i).write(next);
+    }
+}
+
+pub struct GLES1Software {
+    default_framebuffer: Rc<RefCell<DefaultFramebuffer>>,
+    error: GLenum,
+    /// Enabled capabilities, other than `GL_TEXTURE_2D`, which is per-unit.
+    capabilities: HashSet<GLenum>,
+    hints: HashMap<GLenum, GLenum>,
+
+    vertex_array: ArrayState,
+    normal_array: ArrayState,
+    color_array: ArrayState,
+    active_texture: usize,
+    client_active_texture: usize,
+    texture_units: [TextureUnit; TEXTURE_UNITS],
+    current_color: [f32; 4],
+    current_normal: [f32; 3],
+
+    buffers: HashMap<GLuint, Buffer>,
+    array_buffer_binding: GLuint,
+    element_array_buffer_binding: GLuint,
+    textures: HashMap<GLuint, Texture>,
+    renderbuffers: HashMap<GLuint, Renderbuffer>,
+    renderbuffer_binding: GLuint,
+    framebuffers: HashMap<GLuint, Framebuffer>,
+    framebuffer_binding: GLuint,
+
+    alpha_func: GLenum,
+    alpha_ref: f32,
+    blend_sfactor: GLenum,
+    blend_dfactor: GLenum,
+    color_mask: [bool; 4],
+    cull_face_mode: GLenum,
+    front_face: GLenum,
+    depth_func: GLenum,
+    depth_mask: bool,
+    depth_range: (f32, f32),
+    polygon_offset: (f32, f32),
+    shade_model: GLenum,
+    scissor_box: [GLint; 4],
+    viewport: [GLint; 4],
+    line_width: f32,
+    clear_color: [f32; 4],
+    clear_depth: f32,
+    clear_stencil: GLint,
+    pack_alignment: GLint,
+    unpack_alignment: GLint,
+
+    point_size: f32,
+    point_size_min: f32,
+    point_size_max: f32,
+    point_distance_attenuation: [f32; 3],
+    point_fade_threshold_size: f32,
+
+    fog_mode: GLenum,
+    fog_density: f32,
+    fog_start: f32,
+    fog_end: f32,
+    fog_color: [f32; 4],
+    lights: [Light; MAX_LIGHTS],
+    light_model_ambient: [f32; 4],
+    light_model_two_side: bool,
+    material: Material,
+
+    matrix_mode: GLenum,
+    modelview_stack: Vec<Matrix>,
+    projection_stack: Vec<Matrix>,
+}
+
+impl GLES1Software {
+    fn with_framebuffer(default_framebuffer: Rc<RefCell<DefaultFramebuffer>>) -> Self {
+        let (width, height) = default_framebuffer.borrow().size();
+        let size = [0, 0, width as GLint, height as GLint];
+        GLES1Software {
+            default_framebuffer,
+            error: gles11::NO_ERROR,
+            capabilities: HashSet::from([gles11::DITHER, gles11::MULTISAMPLE]),
+            hints: HashMap::new(),
+            vertex_array: ArrayState::new(4),
+            normal_array: ArrayState::new(3),
+            color_array: ArrayState::new(4),
+            active_texture: 0,
+            client_active_texture: 0,
+            texture_units: Default::default(),
+            current_color: [1.0; 4],
+            current_normal: [0.0, 0.0, 1.0],
+            buffers: HashMap::new(),
+            array_buffer_binding: 0,
+            element_array_buffer_binding: 0,
+            // Texture 0 is the default texture, which always exists.
+            textures: HashMap::from([(0, Texture::default())]),
+            renderbuffers: HashMap::new(),
+            renderbuffer_binding: 0,
+            framebuffers: HashMap::new(),
+            framebuffer_binding: 0,
+            alpha_func: gles11::ALWAYS,
+            alpha_ref: 0.0,
+            blend_sfactor: gles11::ONE,
+            blend_dfactor: gles11::ZERO,
+            color_mask: [true; 4],
+            cull_face_mode: gles11::BACK,
+            front_face: gles11::CCW,
+            depth_func: gles11::LESS,
+            depth_mask: true,
+            depth_range: (0.0, 1.0),
+            polygon_offset: (0.0, 0.0),
+            shade_model: gles11::SMOOTH,
+            scissor_box: size,
+            viewport: size,
+            line_width: 1.0,
+            clear_color: [0.0; 4],
+            clear_depth: 1.0,
+            clear_stencil: 0,
+            pack_alignment: 4,
+            unpack_alignment: 4,
+            point_size: 1.0,
+            point_size_min: 0.0,
+            point_size_max: MAX_POINT_SIZE,
+            point_distance_attenuation: [1.0, 0.0, 0.0],
+            point_fade_threshold_size: 1.0,
+            fog_mode: gles11::EXP,
+            fog_density: 1.0,
+            fog_start: 0.0,
+            fog_end: 1.0,
+            fog_color: [0.0; 4],
+            lights: std::array::from_fn(Light::new),
+            light_model_ambient: [0.2, 0.2, 0.2, 1.0],
+            light_model_two_side: false,
+            material: Material {
+                ambient: [0.2, 0.2, 0.2, 1.0],
+                diffuse: [0.8, 0.8, 0.8, 1.0],
+                specular: [0.0, 0.0, 0.0, 1.0],
+                emission: [0.0, 0.0, 0.0, 1.0],
+                shininess: 0.0,
+            },
+            matrix_mode: gles11::MODELVIEW,
+            modelview_stack: vec![IDENTITY],
+            projection_stack: vec![IDENTITY],
+        }
+    }
+
+    /// Record an error for `glGetError`. Only the first error is kept.
+    fn set_error(&mut self, error: GLenum) {
+        if self.error == gles11::NO_ERROR {
+            self.error = error;
+        }
+    }
+
+    fn is_enabled(&self, cap: GLenum) -> bool {
+        self.capabilities.contains(&cap)
+    }
+
+    fn array(&self, array: GLenum) -> &ArrayState {
+        match array {
+            gles11::VERTEX_ARRAY => &self.vertex_array,
+            gles11::NORMAL_ARRAY => &self.normal_array,
+            gles11::COLOR_ARRAY => &self.color_array,
+            gles11::TEXTURE_COORD_ARRAY => {
+                &self.texture_units[self.client_active_texture].coord_array
+            }
+            _ => panic!("Unhandled array: {:#x}", array),
+        }
+    }
+    fn array_mut(&mut self, array: GLenum) -> &mut ArrayState {
+        match array {
+            gles11::VERTEX_ARRAY => &mut self.vertex_array,
+            gles11::NORMAL_ARRAY => &mut self.normal_array,
+            gles11::COLOR_ARRAY => &mut self.color_array,
+            gles11::TEXTURE_COORD_ARRAY => {
+                &mut self.texture_units[self.client_active_texture].coord_array
+            }
+            _ => panic!("Unhandled array: {:#x}", array),
+        }
+    }
+    fn set_array_pointer(
+        &mut self,
+        array: GLenum,
+        size: GLint,
+        type_: GLenum,
+        stride: GLsizei,
+        pointer: *const GLvoid,
+    ) {
+        if stride < 0 {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let buffer = self.array_buffer_binding;
+        let array = self.array_mut(array);
+        array.size = size;
+        array.type_ = type_;
+        array.stride = stride;
+        array.pointer = pointer;
+        array.buffer = buffer;
+    }
+
+    fn matrix_stack_mut(&mut self) -> (&mut Vec<Matrix>, usize) {
+        match self.matrix_mode {
+            gles11::MODELVIEW => (&mut self.modelview_stack, MAX_MODELVIEW_STACK_DEPTH),
+            gles11::PROJECTION => (&mut self.projection_stack, MAX_PROJECTION_STACK_DEPTH),
+            gles11::TEXTURE => (
+                &mut self.texture_units[self.active_texture].matrix_stack,
+                MAX_TEXTURE_STACK_DEPTH,
+            ),
+            _ => unreachable!(),
+        }
+    }
+    fn current_matrix_mut(&mut self) -> &mut Matrix {
+        self.matrix_stack_mut().0.last_mut().unwrap()
+    }
+    fn mult_matrix(&mut self, m: &Matrix) {
+        let current = self.current_matrix_mut();
+        *current = raster::multiply(current, m);
+    }
+
+    fn bound_texture_mut(&mut self) -> &mut Texture {
+        let binding = self.texture_units[self.active_texture].binding;
+        self.textures.get_mut(&binding).unwrap()
+    }
+
+    fn texture_level_size(&self, level: GLint) -> Option<(u32, u32)> {
+        let binding = self.texture_units[self.active_texture].binding;
+        let level = self.textures[&binding].level(level as usize)?;
+        Some((level.texels.width, level.texels.height))
+    }
+
+    fn attachment_bits(&self, attachment: Attachment) -> [GLint; 6] {
+        match attachment {
+            Attachment::None => [0; 6],
+            Attachment::Renderbuffer(name) => self
+                .renderbuffers
+                .get(&name)
+                .and_then(|renderbuffer| renderbuffer_format_bits(renderbuffer.internalformat))
+                .unwrap_or([0; 6]),
+            Attachment::Texture(..) => [8, 8, 8, 8, 0, 0],
+        }
+    }
+
+    /// Get the red, green, blue, alpha, depth and stencil sizes for the current
+    /// framebuffer.
+    fn framebuffer_bits(&self) -> [GLint; 6] {
+        if self.framebuffer_binding == 0 {
+            return [8, 8, 8, 8, 24, 8];
+        }
+        let framebuffer = &self.framebuffers[&self.framebuffer_binding];
+        let color = self.attachment_bits(framebuffer.color);
+        let depth = self.attachment_bits(framebuffer.depth);
+        let stencil = self.attachment_bits(framebuffer.stencil);
+        [color[0], color[1], color[2], color[3], depth[4], stencil[5]]
+    }
+
+    fn framebuffer_status(&self) -> GLenum {
+        if self.framebuffer_binding == 0 {
+            return gles11::FRAMEBUFFER_COMPLETE_OES;
+        }
+        let framebuffer = &self.framebuffers[&self.framebuffer_binding];
+        let mut sizes = Vec::new();
+        // Which of the bits from renderbuffer_format_bits() must be non-zero.
+        for (attachment, bits_range) in [
+            (framebuffer.color, 0..4),
+            (framebuffer.depth, 4..5),
+            (framebuffer.stencil, 5..6),
+        ] {
+            match attachment {
+                Attachment::None => continue,
+                Attachment::Renderbuffer(name) => {
+                    let Some(renderbuffer) = self.renderbuffers.get(&name) else {
+                        return gles11::FRAMEBUFFER_INCOMPLETE_ATTACHMENT_OES;
+                    };
+                    let Some(bits) = renderbuffer_format_bits(renderbuffer.internalformat) else {
+                        return gles11::FRAMEBUFFER_INCOMPLETE_ATTACHMENT_OES;
+                    };
+                    if bits[bits_range].iter().all(|&bits| bits == 0)
+                        || renderbuffer.width == 0
+                        || renderbuffer.height == 0
+                    {
+                        return gles11::FRAMEBUFFER_INCOMPLETE_ATTACHMENT_OES;
+                    }
+                    sizes.push((renderbuffer.width, renderbuffer.height));
+                }
+                Attachment::Texture(name, level) => {
+                    let Some(level) = self.textures.get(&name).and_then(|t| t.level(level)) else {
+                        return gles11::FRAMEBUFFER_INCOMPLETE_ATTACHMENT_OES;
+                    };
+                    if level.texels.is_empty() {
+                        return gles11::FRAMEBUFFER_INCOMPLETE_ATTACHMENT_OES;
+                    }
+                    sizes.push((level.texels.width, level.texels.height));
+                }
+            }
+        }
+        if sizes.is_empty() {
+            gles11::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT_OES
+        } else if sizes.iter().any(|&size| size != sizes[0]) {
+            gles11::FRAMEBUFFER_INCOMPLETE_DIMENSIONS_OES
+        } else {
+            gles11::FRAMEBUFFER_COMPLETE_OES
+        }
+    }
+
+    /// Take the buffers of the current framebuffer out of their owners so they
+    /// can be drawn to. [Self::put_back_target] must be called afterwards.
+    /// Returns [None] and sets an error if the framebuffer is incomplete.
+    fn take_target(&mut self) -> Option<Target> {
+        if self.framebuffer_binding == 0 {
+            let mut framebuffer = self.default_framebuffer.borrow_mut();
+            return Some(Target {
+                color: Some(std::mem::take(&mut framebuffer.color)),
+                depth: Some(std::mem::take(&mut framebuffer.depth)),
+                stencil: Some(std::mem::take(&mut framebuffer.stencil)),
+                color_has_alpha: true,
+            });
+        }
+        if self.framebuffer_status() != gles11::FRAMEBUFFER_COMPLETE_OES {
+            self.set_error(gles11::INVALID_FRAMEBUFFER_OPERATION_OES);
+            return None;
+        }
+        let framebuffer = self.framebuffers[&self.framebuffer_binding];
+        let color_has_alpha = self.attachment_bits(framebuffer.color)[3] != 0;
+        let color = match framebuffer.color {
+            Attachment::None => None,
+            Attachment::Renderbuffer(name) => Some(std::mem::take(
+                &mut self.renderbuffers.get_mut(&name).unwrap().color,
+            )),
+            Attachment::Texture(name, level) => {
+                let texture = self.textures.get_mut(&name).unwrap();
+                Some(std::mem::take(
+                    &mut texture.level_mut(level).unwrap().texels,
+                ))
+            }
+        };
+        let depth = match framebuffer.depth {
+            Attachment::Renderbuffer(name) => Some(std::mem::take(
+                &mut self.renderbuffers.get_mut(&name).unwrap().depth,
+            )),
+            _ => None,
+        };
+        let stencil = match framebuffer.stencil {
+            Attachment::Renderbuffer(name) => Some(std::mem::take(
+                &mut self.renderbuffers.get_mut(&name).unwrap().stencil,
+            )),
+            _ => None,
+        };
+        Some(Target {
+            color,
+            depth,
+            stencil,
+            color_has_alpha,
+        })
+    }
+
+    /// Counterpart to [Self::take_target].
+    fn put_back_target(&mut self, target: Target) {
+        let Target {
+            color,
+            depth,
+            stencil,
+            color_has_alpha: _,
+        } = target;
+        if self.framebuffer_binding == 0 {
+            let mut framebuffer = self.default_framebuffer.borrow_mut();
+            framebuffer.color = color.unwrap();
+            framebuffer.depth = depth.unwrap();
+            framebuffer.stencil = stencil.unwrap();
+            return;
+        }
+        let framebuffer = self.framebuffers[&self.framebuffer_binding];
+        match framebuffer.color {
+            Attachment::None => (),
+            Attachment::Renderbuffer(name) => {
+                self.renderbuffers.get_mut(&name).unwrap().color = color.unwrap();
+            }
+            Attachment::Texture(name, level) => {
+                let texture = self.textures.get_mut(&name).unwrap();
+                texture.level_mut(level).unwrap().texels = color.unwrap();
+            }
+        }
+        if let Attachment::Renderbuffer(name) = framebuffer.depth {
+            self.renderbuffers.get_mut(&name).unwrap().depth = depth.unwrap();
+        }
+        if let Attachment::Renderbuffer(name) = framebuffer.stencil {
+            self.renderbuffers.get_mut(&name).unwrap().stencil = stencil.unwrap();
+        }
+    }
+
+    /// Read a rectangle of the current framebuffer's color buffer. Pixels
+    /// outside the framebuffer are transparent black.
+    fn read_color_region(&mut self, x: GLint, y: GLint, width: u32, height: u32) -> Plane<Rgba> {
+        let mut region = Plane::new(width, height);
+        let Some(target) = self.take_target() else {
+            return region;
+        };
+        if let Some(ref color) = target.color {
+            for row in 0..height {
+                for col in 0..width {
+                    let (src_x, src_y) = (x as i64 + col as i64, y as i64 + row as i64);
+                    if (0..color.width as i64).contains(&src_x)
+                        && (0..color.height as i64).contains(&src_y)
+                    {
+                        region.set(col, row, color.get(src_x as u32, src_y as u32));
+                    }
+                }
+            }
+        }
+        self.put_back_target(target);
+        region
+    }
+
+    /// Detach an object from the current framebuffer when it's deleted.
+    fn detach_from_framebuffer(&mut self, attachment: Attachment) {
+        if self.framebuffer_binding == 0 {
+            return;
+        }
+        let framebuffer = self
+            .framebuffers
+            .get_mut(&self.framebuffer_binding)
+            .unwrap();
+        for slot in [
+            &mut framebuffer.color,
+            &mut framebuffer.depth,
+            &mut framebuffer.stencil,
+        ] {
+            let matches = match (*slot, attachment) {
+                (Attachment::Renderbuffer(a), Attachment::Renderbuffer(b)) => a == b,
+                (Attachment::Texture(a, _), Attachment::Texture(b, _)) => a == b,
+                _ => false,
+            };
+            if matches {
+                *slot = Attachment::None;
+            }
+        }
+    }
+
+    /// Get the value of a `glGet` parameter, along with its type.
+    fn get_param(&mut self, pname: GLenum) -> (ParamType, Vec<f64>) {
+        use ParamType::{Boolean, Float, FloatSpecial, Int};
+
+        let bool_ = |value: bool| (Boolean, vec![value as u8 as f64]);
+        let int = |value: i64| (Int, vec![value as f64]);
+        let ints = |values: &[i64]| (Int, values.iter().map(|&v| v as f64).collect());
+        let float = |value: f32| (Float, vec![value as f64]);
+        let floats = |values: &[f32]| (Float, values.iter().map(|&v| v as f64).collect());
+        let special = |values: &[f32]| (FloatSpecial, values.iter().map(|&v| v as f64).collect());
+        let array_param = |array: &ArrayState, which: GLenum| -> i64 {
+            match which {
+                gles11::ARRAY_BUFFER_BINDING => array.buffer as i64,
+                gles11::VERTEX_ARRAY_SIZE => array.size as i64,
+                gles11::VERTEX_ARRAY_STRIDE => array.stride as i64,
+                gles11::VERTEX_ARRAY_TYPE => array.type_ as i64,
+                _ => unreachable!(),
+            }
+        };
+
+        let unit = &self.texture_units[self.active_texture];
+        let client_unit = &self.texture_units[self.client_active_texture];
+        let bits = self.framebuffer_bits();
+        match pname {
+            gles11::ACTIVE_TEXTURE => int((gles11::TEXTURE0 as usize + self.active_texture) as _),
+            gles11::CLIENT_ACTIVE_TEXTURE => {
+                int((gles11::TEXTURE0 as usize + self.client_active_texture) as _)
+            }
+            gles11::ALIASED_POINT_SIZE_RANGE | gles11::SMOOTH_POINT_SIZE_RANGE => {
+                floats(&[1.0, MAX_POINT_SIZE])
+            }
+            gles11::ALIASED_LINE_WIDTH_RANGE | gles11::SMOOTH_LINE_WIDTH_RANGE => {
+                floats(&[1.0, MAX_LINE_WIDTH])
+            }
+            gles11::RED_BITS => int(bits[0].into()),
+            gles11::GREEN_BITS => int(bits[1].into()),
+            gles11::BLUE_BITS => int(bits[2].into()),
+            gles11::ALPHA_BITS => int(bits[3].into()),
+            gles11::DEPTH_BITS => int(bits[4].into()),
+            gles11::STENCIL_BITS => int(bits[5].into()),
+            gles11::ALPHA_TEST_FUNC => int(self.alpha_func.into()),
+            gles11::ALPHA_TEST_REF => special(&[self.alpha_ref]),
+            gles11::ARRAY_BUFFER_BINDING => int(self.array_buffer_binding.into()),
+            gles11::ELEMENT_ARRAY_BUFFER_BINDING => int(self.element_array_buffer_binding.into()),
+            gles11::BLEND_SRC => int(self.blend_sfactor.into()),
+            gles11::BLEND_DST => int(self.blend_dfactor.into()),
+            gles11::COLOR_ARRAY_BUFFER_BINDING => {
+                int(array_param(&self.color_array, gles11::ARRAY_BUFFER_BINDING))
+            }
+            gles11::COLOR_ARRAY_SIZE => {
+                int(array_param(&self.color_array, gles11::VERTEX_ARRAY_SIZE))
+            }
+            gles11::COLOR_ARRAY_STRIDE => {
+                int(array_param(&self.color_array, gles11::VERTEX_ARRAY_STRIDE))
+            }
+            gles11::COLOR_ARRAY_TYPE => {
+                int(array_param(&self.color_array, gles11::VERTEX_ARRAY_TYPE))
+            }
+            gles11::NORMAL_ARRAY_BUFFER_BINDING => int(array_param(
+                &self.normal_array,
+                gles11::ARRAY_BUFFER_BINDING,
+            )),
+            gles11::NORMAL_ARRAY_STRIDE => {
+                int(array_param(&self.normal_array, gles11::VERTEX_ARRAY_STRIDE))
+            }
+            gles11::NORMAL_ARRAY_TYPE => {
+                int(array_param(&self.normal_array, gles11::VERTEX_ARRAY_TYPE))
+            }
+            gles11::TEXTURE_COORD_ARRAY_BUFFER_BINDING => int(array_param(
+                &client_unit.coord_array,
+                gles11::ARRAY_BUFFER_BINDING,
+            )),
+            gles11::TEXTURE_COORD_ARRAY_SIZE => int(array_param(
+                &client_unit.coord_array,
+                gles11::VERTEX_ARRAY_SIZE,
+            )),
+            gles11::TEXTURE_COORD_ARRAY_STRIDE => int(array_param(
+                &client_unit.coord_array,
+                gles11::VERTEX_ARRAY_STRIDE,
+            )),
+            gles11::TEXTURE_COORD_ARRAY_TYPE => int(array_param(
+                &client_unit.coord_array,
+                gles11::VERTEX_ARRAY_TYPE,
+            )),
+            gles11::VERTEX_ARRAY_BUFFER_BINDING => int(array_param(
+                &self.vertex_array,
+                gles11::ARRAY_BUFFER_BINDING,
+            )),
+            gles11::VERTEX_ARRAY_SIZE | gles11::VERTEX_ARRAY_STRIDE | gles11::VERTEX_ARRAY_TYPE => {
+                int(array_param(&self.vertex_array, pname))
+            }
+            gles11::COLOR_CLEAR_VALUE => special(&self.clear_color),
+            gles11::COLOR_WRITEMASK => (
+                Boolean,
+                self.color_mask.iter().map(|&b| b as u8 as f64).collect(),
+            ),
+            gles11::NUM_COMPRESSED_TEXTURE_FORMATS => int(COMPRESSED_TEXTURE_FORMATS.len() as _),
+            gles11::COMPRESSED_TEXTURE_FORMATS => (
+                Int,
+                COMPRESSED_TEXTURE_FORMATS
+                    .iter()
+                    .map(|&f| f as f64)
+                    .collect(),
+            ),
+            gles11::CULL_FACE_MODE => int(self.cull_face_mode.into()),
+            gles11::CURRENT_COLOR => special(&self.current_color),
+            gles11::CURRENT_NORMAL => special(&self.current_normal),
+            gles11::CURRENT_TEXTURE_COORDS => floats(&unit.current_coords),
+            gles11::DEPTH_CLEAR_VALUE => special(&[self.clear_depth]),
+            gles11::DEPTH_FUNC => int(self.depth_func.into()),
+            gles11::DEPTH_RANGE => special(&[self.depth_range.0, self.depth_range.1]),
+            gles11::DEPTH_WRITEMASK => bool_(self.depth_mask),
+            gles11::FOG_COLOR => special(&self.fog_color),
+            gles11::FOG_MODE => int(self.fog_mode.into()),
+            gles11::FOG_DENSITY => float(self.fog_density),
+            gles11::FOG_START => float(self.fog_start),
+            gles11::FOG_END => float(self.fog_end),
+            gles11::FOG_HINT
+            | gles11::GENERATE_MIPMAP_HINT
+            | gles11::LINE_SMOOTH_HINT
+            | gles11::PERSPECTIVE_CORRECTION_HINT
+            | gles11::POINT_SMOOTH_HINT => int(self
+                .hints
+                .get(&pname)
+                .copied()
+                .unwrap_or(gles11::DONT_CARE)
+                .into()),
+            gles11::FRONT_FACE => int(self.front_face.into()),
+            gles11::LIGHT_MODEL_AMBIENT => special(&self.light_model_ambient),
+            gles11::LIGHT_MODEL_TWO_SIDE => bool_(self.light_model_two_side),
+            gles11::LINE_WIDTH => float(self.line_width),
+            gles11::LOGIC_OP_MODE => int(gles11::COPY.into()),
+            gles11::MATRIX_MODE => int(self.matrix_mode.into()),
+            gles11::MAX_CLIP_PLANES => int(1),
+            gles11::MAX_LIGHTS => int(MAX_LIGHTS as _),
+            gles11::MAX_MODELVIEW_STACK_DEPTH => int(MAX_MODELVIEW_STACK_DEPTH as _),
+            gles11::MAX_PROJECTION_STACK_DEPTH => int(MAX_PROJECTION_STACK_DEPTH as _),
+            gles11::MAX_TEXTURE_STACK_DEPTH => int(MAX_TEXTURE_STACK_DEPTH as _),
+            gles11::MAX_TEXTURE_SIZE | gles11::MAX_RENDERBUFFER_SIZE_OES => {
+                int(MAX_TEXTURE_SIZE.into())
+            }
+            gles11::MAX_TEXTURE_UNITS => int(TEXTURE_UNITS as _),
+            gles11::MAX_VIEWPORT_DIMS => ints(&[MAX_TEXTURE_SIZE.into(), MAX_TEXTURE_SIZE.into()]),
+            gles11::MAX_TEXTURE_MAX_ANISOTROPY_EXT => float(1.0),
+            gles11::MAX_TEXTURE_LOD_BIAS_EXT => float(16.0),
+            gles11::MODELVIEW_MATRIX => floats(self.modelview_stack.last().unwrap()),
+            gles11::PROJECTION_MATRIX => floats(self.projection_stack.last().unwrap()),
+            gles11::TEXTURE_MATRIX => floats(unit.matrix_stack.last().unwrap()),
+            gles11::MODELVIEW_STACK_DEPTH => int(self.modelview_stack.len() as _),
+            gles11::PROJECTION_STACK_DEPTH => int(self.projection_stack.len() as _),
+            gles11::TEXTURE_STACK_DEPTH => int(unit.matrix_stack.len() as _),
+            gles11::PACK_ALIGNMENT => int(self.pack_alignment.into()),
+            gles11::UNPACK_ALIGNMENT => int(self.unpack_alignment.into()),
+            gles11::POINT_DISTANCE_ATTENUATION => floats(&self.point_distance_attenuation),
+            gles11::POINT_FADE_THRESHOLD_SIZE => float(self.point_fade_threshold_size),
+            gles11::POINT_SIZE => float(self.point_size),
+            gles11::POINT_SIZE_MIN => float(self.point_size_min),
+            gles11::POINT_SIZE_MAX => float(self.point_size_max),
+            gles11::POLYGON_OFFSET_FACTOR => float(self.polygon_offset.0),
+            gles11::POLYGON_OFFSET_UNITS => float(self.polygon_offset.1),
+            gles11::SAMPLE_BUFFERS | gles11::SAMPLES => int(0),
+            gles11::SAMPLE_COVERAGE_VALUE => float(1.0),
+            gles11::SAMPLE_COVERAGE_INVERT => bool_(false),
+            gles11::SCISSOR_BOX => ints(&self.scissor_box.map(i64::from)),
+            gles11::VIEWPORT => ints(&self.viewport.map(i64::from)),
+            gles11::SHADE_MODEL => int(self.shade_model.into()),
+            gles11::STENCIL_CLEAR_VALUE => int(self.clear_stencil.into()),
+            gles11::STENCIL_FUNC => int(gles11::ALWAYS.into()),
+            gles11::STENCIL_FAIL
+            | gles11::STENCIL_PASS_DEPTH_FAIL
+            | gles11::STENCIL_PASS_DEPTH_PASS => int(gles11::KEEP.into()),
+            gles11::STENCIL_REF => int(0),
+            gles11::STENCIL_VALUE_MASK | gles11::STENCIL_WRITEMASK => int(-1),
+            gles11::SUBPIXEL_BITS => int(8),
+            gles11::TEXTURE_BINDING_2D => int(unit.binding.into()),
+            gles11::TEXTURE_2D => bool_(unit.enabled),
+            gles11::VERTEX_ARRAY
+            | gles11::NORMAL_ARRAY
+            | gles11::COLOR_ARRAY
+            | gles11::TEXTURE_COORD_ARRAY => bool_(self.array(pname).enabled),
+            gles11::FRAMEBUFFER_BINDING_OES => int(self.framebuffer_binding.into()),
+            gles11::RENDERBUFFER_BINDING_OES => int(self.renderbuffer_binding.into()),
+            IMPLEMENTATION_COLOR_READ_FORMAT_OES => int(gles11::RGBA.into()),
+            IMPLEMENTATION_COLOR_READ_TYPE_OES => int(gles11::UNSIGNED_BYTE.into()),
+            _ if CAPABILITIES.contains(&pname) => bool_(self.is_enabled(pname)),
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        }
+    }
+
+    fn tex_parameter(&mut self, target: GLenum, pname: GLenum, param: GLfloat) {
+        assert!(target == gles11::TEXTURE_2D);
+        TEX_PARAMS.assert_known_param(pname);
+        let enum_param = param as GLenum;
+        let texture = self.bound_texture_mut();
+        match pname {
+            gles11::TEXTURE_MIN_FILTER => {
+                assert!([
+                    gles11::NEAREST,
+                    gles11::LINEAR,
+                    gles11::NEAREST_MIPMAP_NEAREST,
+                    gles11::LINEAR_MIPMAP_NEAREST,
+                    gles11::NEAREST_MIPMAP_LINEAR,
+                    gles11::LINEAR_MIPMAP_LINEAR
+                ]
+                .contains(&enum_param));
+                texture.min_filter = enum_param;
+            }
+            gles11::TEXTURE_MAG_FILTER => {
+                assert!(enum_param == gles11::NEAREST || enum_param == gles11::LINEAR);
+                texture.mag_filter = enum_param;
+            }
+            gles11::TEXTURE_WRAP_S | gles11::TEXTURE_WRAP_T => {
+                assert!(enum_param == gles11::REPEAT || enum_param == gles11::CLAMP_TO_EDGE);
+                if pname == gles11::TEXTURE_WRAP_S {
+                    texture.wrap_s = enum_param;
+                } else {
+                    texture.wrap_t = enum_param;
+                }
+            }
+            gles11::GENERATE_MIPMAP => texture.generate_mipmap = param != 0.0,
+            gles11::TEXTURE_MAX_ANISOTROPY_EXT => texture.max_anisotropy = param.max(1.0),
+            _ => {
+                log_dbg!("Ignoring glTexParameter({:#x}, {})", pname, param);
+            }
+        }
+    }
+
+    fn tex_env(&mut self, target: GLenum, pname: GLenum, params: &[GLfloat]) {
+        let unit = &mut self.texture_units[self.active_texture];
+        match target {
+            gles11::TEXTURE_ENV => {}
+            gles11::TEXTURE_FILTER_CONTROL_EXT => {
+                assert!(pname == gles11::TEXTURE_LOD_BIAS_EXT);
+                unit.lod_bias = params[0];
+                return;
+            }
+            POINT_SPRITE_OES => {
+                assert!(pname == COORD_REPLACE_OES);
+                unit.coord_replace = params[0] != 0.0;
+                return;
+            }
+            gles11::TEXTURE_2D => {
+                // This is not a valid target, but we are tolerating it for a
+                // Rayman 2 case. It has no effect.
+                assert!(pname == gles11::TEXTURE_ENV_MODE);
+                log_dbg!(
+                    "Tolerating glTexEnv(GL_TEXTURE_2D, TEXTURE_ENV_MODE, {})",
+                    params[0]
+                );
+                return;
+            }
+            _ => unimplemented!("target {:#x}, pname {:#x}", target, pname),
+        }
+        TEX_ENV_PARAMS.assert_known_param(pname);
+        let env = &mut unit.env;
+        let enum_param = params[0] as GLenum;
+        match pname {
+            gles11::TEXTURE_ENV_MODE => {
+                assert!([
+                    gles11::REPLACE,
+                    gles11::MODULATE,
+                    gles11::DECAL,
+                    gles11::BLEND,
+                    gles11::ADD,
+                    gles11::COMBINE
+                ]
+                .contains(&enum_param));
+                env.mode = enum_param;
+            }
+            gles11::TEXTURE_ENV_COLOR => env.color.copy_from_slice(&params[..4]),
+            gles11::COMBINE_RGB => env.combine_rgb = enum_param,
+            gles11::COMBINE_ALPHA => env.combine_alpha = enum_param,
+            gles11::SRC0_RGB => env.src_rgb[0] = enum_param,
+            gles11::SRC1_RGB => env.src_rgb[1] = enum_param,
+            gles11::SRC2_RGB => env.src_rgb[2] = enum_param,
+            gles11::SRC0_ALPHA => env.src_alpha[0] = enum_param,
+            gles11::SRC1_ALPHA => env.src_alpha[1] = enum_param,
+            gles11::SRC2_ALPHA => env.src_alpha[2] = enum_param,
+            gles11::OPERAND0_RGB => env.operand_rgb[0] = enum_param,
+            gles11::OPERAND1_RGB => env.operand_rgb[1] = enum_param,
+            gles11::OPERAND2_RGB => env.operand_rgb[2] = enum_param,
+            gles11::OPERAND0_ALPHA => env.operand_alpha[0] = enum_param,
+            gles11::OPERAND1_ALPHA => env.operand_alpha[1] = enum_param,
+            gles11::OPERAND2_ALPHA => env.operand_alpha[2] = enum_param,
+            gles11::RGB_SCALE => env.rgb_scale = params[0],
+            gles11::ALPHA_SCALE => env.alpha_scale = params[0],
+            // GL_COORD_REPLACE_OES is in the table, but belongs to another
+            // target.
+            _ => unimplemented!("TexEnv pname {:#x}", pname),
+        }
+    }
+
+    fn fog(&mut self, pname: GLenum, params: &[GLfloat]) {
+        match pname {
+            gles11::FOG_MODE => {
+                let mode = params[0] as GLenum;
+                assert!([gles11::LINEAR, gles11::EXP, gles11::EXP2].contains(&mode));
+                self.fog_mode = mode;
+            }
+            gles11::FOG_DENSITY => self.fog_density = params[0],
+            gles11::FOG_START => self.fog_start = params[0],
+            gles11::FOG_END => self.fog_end = params[0],
+            gles11::FOG_COLOR => self.fog_color.copy_from_slice(&params[..4]),
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        }
+    }
+
+    fn light(&mut self, light: GLenum, pname: GLenum, params: &[GLfloat]) {
+        let index = light.wrapping_sub(gles11::LIGHT0) as usize;
+        assert!(index < MAX_LIGHTS);
+        let modelview = *self.modelview_stack.last().unwrap();
+        let light = &mut self.lights[index];
+        match pname {
+            gles11::AMBIENT => light.ambient.copy_from_slice(&params[..4]),
+            gles11::DIFFUSE => light.diffuse.copy_from_slice(&params[..4]),
+            gles11::SPECULAR => light.specular.copy_from_slice(&params[..4]),
+            gles11::POSITION => {
+                let position = params[..4].try_into().unwrap();
+                light.position = raster::transform(&modelview, position);
+            }
+            gles11::SPOT_DIRECTION => {
+                let direction = [params[0], params[1], params[2], 0.0];
+                let [x, y, z, _] = raster::transform(&modelview, direction);
+                light.spot_direction = [x, y, z];
+            }
+            gles11::SPOT_EXPONENT => light.spot_exponent = params[0],
+            gles11::SPOT_CUTOFF => light.spot_cutoff = params[0],
+            gles11::CONSTANT_ATTENUATION => light.attenuation[0] = params[0],
+            gles11::LINEAR_ATTENUATION => light.attenuation[1] = params[0],
+            gles11::QUADRATIC_ATTENUATION => light.attenuation[2] = params[0],
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        }
+    }
+
+    fn light_model(&mut self, pname: GLenum, params: &[GLfloat]) {
+        match pname {
+            gles11::LIGHT_MODEL_AMBIENT => self.light_model_ambient.copy_from_slice(&params[..4]),
+            gles11::LIGHT_MODEL_TWO_SIDE => self.light_model_two_side = params[0] != 0.0,
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        }
+    }
+
+    fn material(&mut self, face: GLenum, pname: GLenum, params: &[GLfloat]) {
+        // OpenGL ES 1.1 only supports the same material for both faces.
+        assert!(face == gles11::FRONT_AND_BACK);
+        let material = &mut self.material;
+        match pname {
+            gles11::AMBIENT => material.ambient.copy_from_slice(&params[..4]),
+            gles11::DIFFUSE => material.diffuse.copy_from_slice(&params[..4]),
+            gles11::AMBIENT_AND_DIFFUSE => {
+                material.ambient.copy_from_slice(&params[..4]);
+                material.diffuse.copy_from_slice(&params[..4]);
+            }
+            gles11::SPECULAR => material.specular.copy_from_slice(&params[..4]),
+            gles11::EMISSION => material.emission.copy_from_slice(&params[..4]),
+            gles11::SHININESS => material.shininess = params[0],
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        }
+    }
+
+    fn point_parameter(&mut self, pname: GLenum, params: &[GLfloat]) {
+        match pname {
+            gles11::POINT_SIZE_MIN => self.point_size_min = params[0],
+            gles11::POINT_SIZE_MAX => self.point_size_max = params[0],
+            gles11::POINT_DISTANCE_ATTENUATION => self
+                .point_distance_attenuation
+                .copy_from_slice(&params[..3]),
+            gles11::POINT_FADE_THRESHOLD_SIZE => self.point_fade_threshold_size = params[0],
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        }
+    }
+
+    fn clear(&mut self, mask: GLbitfield) {
+        let Some(mut target) = self.take_target() else {
+            return;
+        };
+        let (width, height) = target.size();
+        let (x0, y0, x1, y1) = if self.is_enabled(gles11::SCISSOR_TEST) {
+            let [x, y, w, h] = self.scissor_box;
+            (
+                x.clamp(0, width as GLint) as u32,
+                y.clamp(0, height as GLint) as u32,
+                (x + w).clamp(0, width as GLint) as u32,
+                (y + h).clamp(0, height as GLint) as u32,
+            )
+        } else {
+            (0, 0, width, height)
+        };
+        let clear_color = texture::float_to_rgba(self.clear_color);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if mask & gles11::COLOR_BUFFER_BIT != 0 {
+                    if let Some(ref mut color) = target.color {
+                        let mut pixel = color.get(x, y);
+                        for i in 0..4 {
+                            if self.color_mask[i] {
+                                pixel[i] = clear_color[i];
+                            }
+                        }
+                        color.set(x, y, pixel);
+                    }
+                }
+                if mask & gles11::DEPTH_BUFFER_BIT != 0 && self.depth_mask {
+                    if let Some(ref mut depth) = target.depth {
+                        depth.set(x, y, self.clear_depth);
+                    }
+                }
+                if mask & gles11::STENCIL_BUFFER_BIT != 0 {
+                    if let Some(ref mut stencil) = target.stencil {
+                        stencil.set(x, y, self.clear_stencil as u8);
+                    }
+                }
+            }
+        }
+        self.put_back_target(target);
+    }
+}
+
+const COMPRESSED_TEXTURE_FORMATS: &[GLenum] = &[
+    gles11::COMPRESSED_RGB_PVRTC_4BPPV1_IMG,
+    gles11::COMPRESSED_RGBA_PVRTC_4BPPV1_IMG,
+    gles11::COMPRESSED_RGB_PVRTC_2BPPV1_IMG,
+    gles11::COMPRESSED_RGBA_PVRTC_2BPPV1_IMG,
+    gles11::PALETTE4_RGB8_OES,
+    gles11::PALETTE4_RGBA8_OES,
+    gles11::PALETTE4_R5_G6_B5_OES,
+    gles11::PALETTE4_RGBA4_OES,
+    gles11::PALETTE4_RGB5_A1_OES,
+    gles11::PALETTE8_RGB8_OES,
+    gles11::PALETTE8_RGBA8_OES,
+    gles11::PALETTE8_R5_G6_B5_OES,
+    gles11::PALETTE8_RGBA4_OES,
+    gles11::PALETTE8_RGB5_A1_OES,
+];
+
+impl GLES for GLES1Software {
+    fn description() -> &'static str {
+        "OpenGL ES 1.1 via touchHLE software renderer"
+    }
+
+    fn new(window: &mut Window) -> Result<Self, String> {
+        let framebuffer = window
+            .software_framebuffer()
+            .ok_or_else(|| "The window was not created for software rendering".to_string())?;
+        Ok(Self::with_framebuffer(framebuffer))
+    }
+
+    fn make_current(&self, _window: &Window) {
+        // There's no global state to update.
+    }
+
+    unsafe fn driver_description(&self) -> String {
+        "touchHLE software renderer".to_string()
+    }
+
+    // Generic state manipulation
+    unsafe fn GetError(&mut self) -> GLenum {
+        std::mem::replace(&mut self.error, gles11::NO_ERROR)
+    }
+    unsafe fn Enable(&mut self, cap: GLenum) {
+        if cap == gles11::TEXTURE_2D {
+            self.texture_units[self.active_texture].enabled = true;
+        } else if ARRAYS.iter().any(|&ArrayInfo { name, .. }| name == cap) {
+            log_dbg!("Tolerating glEnable({:#x}) of client state", cap);
+        } else {
+            assert!(CAPABILITIES.contains(&cap));
+            self.capabilities.insert(cap);
+        }
+    }
+    unsafe fn IsEnabled(&mut self, cap: GLenum) -> GLboolean {
+        assert!(CAPABILITIES.contains(&cap));
+        let (_type, values) = self.get_param(cap);
+        (values[0] != 0.0) as GLboolean
+    }
+    unsafe fn Disable(&mut self, cap: GLenum) {
+        if cap == gles11::TEXTURE_2D {
+            self.texture_units[self.active_texture].enabled = false;
+        } else if ARRAYS.iter().any(|&ArrayInfo { name, .. }| name == cap) {
+            log_dbg!("Tolerating glDisable({:#x}) of client state", cap);
+        } else {
+            assert!(CAPABILITIES.contains(&cap));
+            self.capabilities.remove(&cap);
+        }
+    }
+    unsafe fn ClientActiveTexture(&mut self, texture: GLenum) {
+        let index = texture.wrapping_sub(gles11::TEXTURE0) as usize;
+        if index >= TEXTURE_UNITS {
+            self.set_error(gles11::INVALID_ENUM);
+            return;
+        }
+        self.client_active_texture = index;
+    }
+    unsafe fn EnableClientState(&mut self, array: GLenum) {
+        self.array_mut(array).enabled = true;
+    }
+    unsafe fn DisableClientState(&mut self, array: GLenum) {
+        self.array_mut(array).enabled = false;
+    }
+    unsafe fn GetBooleanv(&mut self, pname: GLenum, params: *mut GLboolean) {
+        let (_type, values) = self.get_param(pname);
+        for (i, value) in values.into_iter().enumerate() {
+            params.add(i).write((value != 0.0) as GLboolean);
+        }
+    }
+    // TODO: GetFixedv
+    unsafe fn GetFloatv(&mut self, pname: GLenum, params: *mut GLfloat) {
+        let (_type, values) = self.get_param(pname);
+        for (i, value) in values.into_iter().enumerate() {
+            params.add(i).write(value as GLfloat);
+        }
+    }
+    unsafe fn GetIntegerv(&mut self, pname: GLenum, params: *mut GLint) {
+        let (type_, values) = self.get_param(pname);
+        for (i, value) in values.into_iter().enumerate() {
+            let value = match type_ {
+                // Colors and similar are mapped to the full integer range.
+                ParamType::FloatSpecial => (value * GLint::MAX as f64).round() as GLint,
+                _ => value.round() as GLint,
+            };
+            params.add(i).write(value);
+        }
+    }
+    unsafe fn GetTexEnviv(&mut self, target: GLenum, pname: GLenum, params: *mut GLint) {
+        let unit = &self.texture_units[self.active_texture];
+        if target == POINT_SPRITE_OES {
+            assert!(pname == COORD_REPLACE_OES);
+            params.write(unit.coord_replace as GLint);
+            return;
+        }
+        assert_eq!(target, gles11::TEXTURE_ENV);
+        let env = &unit.env;
+        let value = match pname {
+            gles11::TEXTURE_ENV_MODE => env.mode,
+            gles11::TEXTURE_ENV_COLOR => {
+                for (i, &c) in env.color.iter().enumerate() {
+                    params
+                        .add(i)
+                        .write((c as f64 * GLint::MAX as f64).round() as GLint);
+                }
+                return;
+            }
+            gles11::COMBINE_RGB => env.combine_rgb,
+            gles11::COMBINE_ALPHA => env.combine_alpha,
+            gles11::SRC0_RGB => env.src_rgb[0],
+            gles11::SRC1_RGB => env.src_rgb[1],
+            gles11::SRC2_RGB => env.src_rgb[2],
+            gles11::SRC0_ALPHA => env.src_alpha[0],
+            gles11::SRC1_ALPHA => env.src_alpha[1],
+            gles11::SRC2_ALPHA => env.src_alpha[2],
+            gles11::OPERAND0_RGB => env.operand_rgb[0],
+            gles11::OPERAND1_RGB => env.operand_rgb[1],
+            gles11::OPERAND2_RGB => env.operand_rgb[2],
+            gles11::OPERAND0_ALPHA => env.operand_alpha[0],
+            gles11::OPERAND1_ALPHA => env.operand_alpha[1],
+            gles11::OPERAND2_ALPHA => env.operand_alpha[2],
+            gles11::RGB_SCALE => env.rgb_scale as GLenum,
+            gles11::ALPHA_SCALE => env.alpha_scale as GLenum,
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        };
+        params.write(value as GLint);
+    }
+    unsafe fn GetPointerv(&mut self, pname: GLenum, params: *mut *const GLvoid) {
+        let array = match pname {
+            gles11::VERTEX_ARRAY_POINTER => gles11::VERTEX_ARRAY,
+            gles11::NORMAL_ARRAY_POINTER => gles11::NORMAL_ARRAY,
+            gles11::COLOR_ARRAY_POINTER => gles11::COLOR_ARRAY,
+            gles11::TEXTURE_COORD_ARRAY_POINTER => gles11::TEXTURE_COORD_ARRAY,
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        };
+        params.write(self.array(array).pointer);
+    }
+    unsafe fn Hint(&mut self, target: GLenum, mode: GLenum) {
+        assert!([
+            gles11::FOG_HINT,
+            gles11::GENERATE_MIPMAP_HINT,
+            gles11::LINE_SMOOTH_HINT,
+            gles11::PERSPECTIVE_CORRECTION_HINT,
+            gles11::POINT_SMOOTH_HINT
+        ]
+        .contains(&target));
+        assert!([gles11::FASTEST, gles11::NICEST, gles11::DONT_CARE].contains(&mode));
+        self.hints.insert(target, mode);
+    }
+    unsafe fn Flush(&mut self) {
+        // Rendering is always synchronous.
+    }
+    unsafe fn GetString(&mut self, name: GLenum) -> *const GLubyte {
+        let string: &'static [u8] = match name {
+            gles11::VENDOR => b"touchHLE\0",
+            gles11::RENDERER => b"touchHLE software renderer\0",
+            gles11::VERSION => b"OpenGL ES-CM 1.1\0",
+            gles11::EXTENSIONS => {
+                b"\
+                GL_OES_framebuffer_object \
+                GL_OES_rgb8_rgba8 \
+                GL_OES_depth24 \
+                GL_OES_stencil8 \
+                GL_OES_packed_depth_stencil \
+                GL_OES_point_sprite \
+                GL_OES_compressed_paletted_texture \
+                GL_IMG_texture_compression_pvrtc \
+                GL_EXT_texture_format_BGRA8888 \
+                GL_EXT_texture_lod_bias \
+                GL_EXT_texture_filter_anisotropic\0"
+            }
+            _ => {
+                self.set_error(gles11::INVALID_ENUM);
+                return std::ptr::null();
+            }
+        };
+        string.as_ptr()
+    }
+
+    // Other state manipulation
+    unsafe fn AlphaFunc(&mut self, func: GLenum, ref_: GLclampf) {
+        assert!([
+            gles11::NEVER,
+            gles11::LESS,
+            gles11::EQUAL,
+            gles11::LEQUAL,
+            gles11::GREATER,
+            gles11::NOTEQUAL,
+            gles11::GEQUAL,
+            gles11::ALWAYS
+        ]
+        .contains(&func));
+        self.alpha_func = func;
+        self.alpha_ref = ref_.clamp(0.0, 1.0);
+    }
+    unsafe fn AlphaFuncx(&mut self, func: GLenum, ref_: GLclampx) {
+        self.AlphaFunc(func, fixed_to_float(ref_))
+    }
+    unsafe fn BlendFunc(&mut self, sfactor: GLenum, dfactor: GLenum) {
+        let factors = [
+            gles11::ZERO,
+            gles11::ONE,
+            gles11::SRC_COLOR,
+            gles11::ONE_MINUS_SRC_COLOR,
+            gles11::DST_COLOR,
+            gles11::ONE_MINUS_DST_COLOR,
+            gles11::SRC_ALPHA,
+            gles11::ONE_MINUS_SRC_ALPHA,
+            gles11::DST_ALPHA,
+            gles11::ONE_MINUS_DST_ALPHA,
+        ];
+        assert!(factors.contains(&sfactor) || sfactor == gles11::SRC_ALPHA_SATURATE);
+        assert!(factors.contains(&dfactor));
+        self.blend_sfactor = sfactor;
+        self.blend_dfactor = dfactor;
+    }
+    unsafe fn ColorMask(
+        &mut self,
+        red: GLboolean,
+        green: GLboolean,
+        blue: GLboolean,
+        alpha: GLboolean,
+    ) {
+        self.color_mask = [red, green, blue, alpha].map(|b| b != gles11::FALSE);
+    }
+    unsafe fn CullFace(&mut self, mode: GLenum) {
+        assert!([gles11::FRONT, gles11::BACK, gles11::FRONT_AND_BACK].contains(&mode));
+        self.cull_face_mode = mode;
+    }
+    unsafe fn DepthFunc(&mut self, func: GLenum) {
+        assert!([
+            gles11::NEVER,
+            gles11::LESS,
+            gles11::EQUAL,
+            gles11::LEQUAL,
+            gles11::GREATER,
+            gles11::NOTEQUAL,
+            gles11::GEQUAL,
+            gles11::ALWAYS
+        ]
+        .contains(&func));
+        self.depth_func = func;
+    }
+    unsafe fn DepthMask(&mut self, flag: GLboolean) {
+        self.depth_mask = flag != gles11::FALSE;
+    }
+    unsafe fn FrontFace(&mut self, mode: GLenum) {
+        assert!(mode == gles11::CW || mode == gles11::CCW);
+        self.front_face = mode;
+    }
+    unsafe fn DepthRangef(&mut self, near: GLclampf, far: GLclampf) {
+        self.depth_range = (near.clamp(0.0, 1.0), far.clamp(0.0, 1.0));
+    }
+    unsafe fn DepthRangex(&mut self, near: GLclampx, far: GLclampx) {
+        self.DepthRangef(fixed_to_float(near), fixed_to_float(far))
+    }
+    unsafe fn PolygonOffset(&mut self, factor: GLfloat, units: GLfloat) {
+        self.polygon_offset = (factor, units);
+    }
+    unsafe fn PolygonOffsetx(&mut self, factor: GLfixed, units: GLfixed) {
+        self.PolygonOffset(fixed_to_float(factor), fixed_to_float(units))
+    }
+    unsafe fn ShadeModel(&mut self, mode: GLenum) {
+        assert!(mode == gles11::FLAT || mode == gles11::SMOOTH);
+        self.shade_model = mode;
+    }
+    unsafe fn Scissor(&mut self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        if width < 0 || height < 0 {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        self.scissor_box = [x, y, width, height];
+    }
+    unsafe fn Viewport(&mut self, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+        if width < 0 || height < 0 {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let max = MAX_TEXTURE_SIZE as GLsizei;
+        self.viewport = [x, y, width.min(max), height.min(max)];
+    }
+    unsafe fn LineWidth(&mut self, val: GLfloat) {
+        if val <= 0.0 {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        self.line_width = val;
+    }
+    unsafe fn LineWidthx(&mut self, val: GLfixed) {
+        self.LineWidth(fixed_to_float(val))
+    }
+
+    // Points
+    unsafe fn PointSize(&mut self, size: GLfloat) {
+        if size <= 0.0 {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        self.point_size = size;
+    }
+    unsafe fn PointSizex(&mut self, size: GLfixed) {
+        self.PointSize(fixed_to_float(size))
+    }
+    unsafe fn PointParameterf(&mut self, pname: GLenum, param: GLfloat) {
+        POINT_PARAMS.assert_component_count(pname, 1);
+        self.point_parameter(pname, &[param]);
+    }
+    unsafe fn PointParameterx(&mut self, pname: GLenum, param: GLfixed) {
+        let param = fixed_param(&POINT_PARAMS, pname, param);
+        self.point_parameter(pname, &[param]);
+    }
+    unsafe fn PointParameterfv(&mut self, pname: GLenum, params: *const GLfloat) {
+        let params = float_params(&POINT_PARAMS, pname, params);
+        self.point_parameter(pname, &params);
+    }
+    unsafe fn PointParameterxv(&mut self, pname: GLenum, params: *const GLfixed) {
+        let params = fixed_params(&POINT_PARAMS, pname, params);
+        self.point_parameter(pname, &params);
+    }
+
+    // Lighting and materials
+    unsafe fn Fogf(&mut self, pname: GLenum, param: GLfloat) {
+        FOG_PARAMS.assert_component_count(pname, 1);
+        self.fog(pname, &[param]);
+    }
+    unsafe fn Fogx(&mut self, pname: GLenum, param: GLfixed) {
+        let param = fixed_param(&FOG_PARAMS, pname, param);
+        self.fog(pname, &[param]);
+    }
+    unsafe fn Fogfv(&mut self, pname: GLenum, params: *const GLfloat) {
+        let params = float_params(&FOG_PARAMS, pname, params);
+        self.fog(pname, &params);
+    }
+    unsafe fn Fogxv(&mut self, pname: GLenum, params: *const GLfixed) {
+        let params = fixed_params(&FOG_PARAMS, pname, params);
+        self.fog(pname, &params);
+    }
+    unsafe fn Lightf(&mut self, light: GLenum, pname: GLenum, param: GLfloat) {
+        LIGHT_PARAMS.assert_component_count(pname, 1);
+        self.light(light, pname, &[param]);
+    }
+    unsafe fn Lightx(&mut self, light: GLenum, pname: GLenum, param: GLfixed) {
+        let param = fixed_param(&LIGHT_PARAMS, pname, param);
+        self.light(light, pname, &[param]);
+    }
+    unsafe fn Lightfv(&mut self, light: GLenum, pname: GLenum, params: *const GLfloat) {
+        let params = float_params(&LIGHT_PARAMS, pname, params);
+        self.light(light, pname, &params);
+    }
+    unsafe fn Lightxv(&mut self, light: GLenum, pname: GLenum, params: *const GLfixed) {
+        let params = fixed_params(&LIGHT_PARAMS, pname, params);
+        self.light(light, pname, &params);
+    }
+    unsafe fn LightModelf(&mut self, pname: GLenum, param: GLfloat) {
+        assert!(pname == gles11::LIGHT_MODEL_TWO_SIDE);
+        self.light_model(pname, &[param]);
+    }
+    unsafe fn LightModelfv(&mut self, pname: GLenum, params: *const GLfloat) {
+        let count = match pname {
+            gles11::LIGHT_MODEL_AMBIENT => 4,
+            _ => 1,
+        };
+        self.light_model(pname, std::slice::from_raw_parts(params, count));
+    }
+    unsafe fn Materialf(&mut self, face: GLenum, pname: GLenum, param: GLfloat) {
+        MATERIAL_PARAMS.assert_component_count(pname, 1);
+        self.material(face, pname, &[param]);
+    }
+    unsafe fn Materialx(&mut self, face: GLenum, pname: GLenum, param: GLfixed) {
+        let param = fixed_param(&MATERIAL_PARAMS, pname, param);
+        self.material(face, pname, &[param]);
+    }
+    unsafe fn Materialfv(&mut self, face: GLenum, pname: GLenum, params: *const GLfloat) {
+        let params = float_params(&MATERIAL_PARAMS, pname, params);
+        self.material(face, pname, &params);
+    }
+    unsafe fn Materialxv(&mut self, face: GLenum, pname: GLenum, params: *const GLfixed) {
+        let params = fixed_params(&MATERIAL_PARAMS, pname, params);
+        self.material(face, pname, &params);
+    }
+
+    // Buffers
+    unsafe fn GenBuffers(&mut self, n: GLsizei, buffers: *mut GLuint) {
+        gen_names(&mut self.buffers, n, buffers)
+    }
+    unsafe fn DeleteBuffers(&mut self, n: GLsizei, buffers: *const GLuint) {
+        for i in 0..n.max(0) as usize {
+            let name = buffers.add(i).read();
+            if name == 0 || self.buffers.remove(&name).is_none() {
+                continue;
+            }
+            for binding in [
+                &mut self.array_buffer_binding,
+                &mut self.element_array_buffer_binding,
+                &mut self.vertex_array.buffer,
+                &mut self.normal_array.buffer,
+                &mut self.color_array.buffer,
+            ] {
+                if *binding == name {
+                    *binding = 0;
+                }
+            }
+            for unit in self.texture_units.iter_mut() {
+                if unit.coord_array.buffer == name {
+                    unit.coord_array.buffer = 0;
+                }
+            }
+        }
+    }
+    unsafe fn BindBuffer(&mut self, target: GLenum, buffer: GLuint) {
+        match target {
+            gles11::ARRAY_BUFFER => self.array_buffer_binding = buffer,
+            gles11::ELEMENT_ARRAY_BUFFER => self.element_array_buffer_binding = buffer,
+            _ => panic!("Unhandled buffer target: {:#x}", target),
+        }
+        if buffer != 0 {
+            self.buffers.entry(buffer).or_default();
+        }
+    }
+    unsafe fn BufferData(
+        &mut self,
+        target: GLenum,
+        size: GLsizeiptr,
+        data: *const GLvoid,
+        _usage: GLenum,
+    ) {
+        let binding = match target {
+            gles11::ARRAY_BUFFER => self.array_buffer_binding,
+            gles11::ELEMENT_ARRAY_BUFFER => self.element_array_buffer_binding,
+            _ => panic!("Unhandled buffer target: {:#x}", target),
+        };
+        if binding == 0 {
+            self.set_error(gles11::INVALID_OPERATION);
+            return;
+        }
+        let size = size as usize;
+        let data = if data.is_null() {
+            vec![0; size]
+        } else {
+            std::slice::from_raw_parts(data.cast::<u8>(), size).to_vec()
+        };
+        *self.buffers.get_mut(&binding).unwrap() = Buffer { data };
+    }
+    unsafe fn BufferSubData(
+        &mut self,
+        target: GLenum,
+        offset: GLintptr,
+        size: GLsizeiptr,
+        data: *const GLvoid,
+    ) {
+        let binding = match target {
+            gles11::ARRAY_BUFFER => self.array_buffer_binding,
+            gles11::ELEMENT_ARRAY_BUFFER => self.element_array_buffer_binding,
+            _ => panic!("Unhandled buffer target: {:#x}", target),
+        };
+        if binding == 0 {
+            self.set_error(gles11::INVALID_OPERATION);
+            return;
+        }
+        let buffer = self.buffers.get_mut(&binding).unwrap();
+        let (offset, size) = (offset as usize, size as usize);
+        if offset + size > buffer.data.len() {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        buffer.data[offset..][..size]
+            .copy_from_slice(std::slice::from_raw_parts(data.cast::<u8>(), size));
+    }
+
+    // Non-pointers
+    unsafe fn Color4f(&mut self, red: GLfloat, green: GLfloat, blue: GLfloat, alpha: GLfloat) {
+        self.current_color = [red, green, blue, alpha];
+    }
+    unsafe fn Color4x(&mut self, red: GLfixed, green: GLfixed, blue: GLfixed, alpha: GLfixed) {
+        self.Color4f(
+            fixed_to_float(red),
+            fixed_to_float(green),
+            fixed_to_float(blue),
+            fixed_to_float(alpha),
+        )
+    }
+    unsafe fn Color4ub(&mut self, red: GLubyte, green: GLubyte, blue: GLubyte, alpha: GLubyte) {
+        self.current_color = texture::rgba_to_float([red, green, blue, alpha]);
+    }
+    unsafe fn Normal3f(&mut self, nx: GLfloat, ny: GLfloat, nz: GLfloat) {
+        self.current_normal = [nx, ny, nz];
+    }
+    unsafe fn Normal3x(&mut self, nx: GLfixed, ny: GLfixed, nz: GLfixed) {
+        self.Normal3f(fixed_to_float(nx), fixed_to_float(ny), fixed_to_float(nz))
+    }
+
+    // Pointers
+    unsafe fn ColorPointer(
+        &mut self,
+        size: GLint,
+        type_: GLenum,
+        stride: GLsizei,
+        pointer: *const GLvoid,
+    ) {
+        assert!(size == 4);
+        assert!([gles11::UNSIGNED_BYTE, gles11::FIXED, gles11::FLOAT].contains(&type_));
+        self.set_array_pointer(gles11::COLOR_ARRAY, size, type_, stride, pointer);
+    }
+    unsafe fn NormalPointer(&mut self, type_: GLenum, stride: GLsizei, pointer: *const GLvoid) {
+        assert!([gles11::BYTE, gles11::SHORT, gles11::FIXED, gles11::FLOAT].contains(&type_));
+        self.set_array_pointer(gles11::NORMAL_ARRAY, 3, type_, stride, pointer);
+    }
+    unsafe fn TexCoordPointer(
+        &mut self,
+        size: GLint,
+        type_: GLenum,
+        stride: GLsizei,
+        pointer: *const GLvoid,
+    ) {
+        assert!((2..=4).contains(&size));
+        assert!([gles11::BYTE, gles11::SHORT, gles11::FIXED, gles11::FLOAT].contains(&type_));
+        self.set_array_pointer(gles11::TEXTURE_COORD_ARRAY, size, type_, stride, pointer);
+    }
+    unsafe fn VertexPointer(
+        &mut self,
+        size: GLint,
+        type_: GLenum,
+        stride: GLsizei,
+        pointer: *const GLvoid,
+    ) {
+        assert!((2..=4).contains(&size));
+        assert!([gles11::BYTE, gles11::SHORT, gles11::FIXED, gles11::FLOAT].contains(&type_));
+        self.set_array_pointer(gles11::VERTEX_ARRAY, size, type_, stride, pointer);
+    }
+
+    // Drawing
+    unsafe fn DrawArrays(&mut self, mode: GLenum, first: GLint, count: GLsizei) {
+        if first < 0 || count < 0 {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let indices: Vec<u32> = (first as u32..).take(count as usize).collect();
+        self.draw(mode, &indices);
+    }
+    unsafe fn DrawElements(
+        &mut self,
+        mode: GLenum,
+        count: GLsizei,
+        type_: GLenum,
+        indices: *const GLvoid,
+    ) {
+        if count < 0 {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let count = count as usize;
+        let index_size = match type_ {
+            gles11::UNSIGNED_BYTE => 1,
+            gles11::UNSIGNED_SHORT => 2,
+            _ => panic!("Unhandled index type: {:#x}", type_),
+        };
+        let bytes: &[u8] = if self.element_array_buffer_binding != 0 {
+            // The pointer is an offset into the buffer.
+            let buffer = &self.buffers[&self.element_array_buffer_binding].data;
+            let offset = indices as usize;
+            if offset + count * index_size > buffer.len() {
+                log!("Warning: Out-of-bounds glDrawElements() ignored.");
+                return;
+            }
+            &buffer[offset..][..count * index_size]
+        } else {
+            std::slice::from_raw_parts(indices.cast(), count * index_size)
+        };
+        let indices: Vec<u32> = match type_ {
+            gles11::UNSIGNED_BYTE => bytes.iter().map(|&i| i.into()).collect(),
+            _ => bytes
+                .chunks_exact(2)
+                .map(|i| u16::from_ne_bytes([i[0], i[1]]).into())
+                .collect(),
+        };
+        self.draw(mode, &indices);
+    }
+
+    // Clearing
+    unsafe fn Clear(&mut self, mask: GLbitfield) {
+        self.clear(mask)
+    }
+    unsafe fn ClearColor(
+        &mut self,
+        red: GLclampf,
+        green: GLclampf,
+        blue: GLclampf,
+        alpha: GLclampf,
+    ) {
+        self.clear_color = [red, green, blue, alpha].map(|c| c.clamp(0.0, 1.0));
+    }
+    unsafe fn ClearColorx(
+        &mut self,
+        red: GLclampx,
+        green: GLclampx,
+        blue: GLclampx,
+        alpha: GLclampx,
+    ) {
+        self.ClearColor(
+            fixed_to_float(red),
+            fixed_to_float(green),
+            fixed_to_float(blue),
+            fixed_to_float(alpha),
+        )
+    }
+    unsafe fn ClearDepthf(&mut self, depth: GLclampf) {
+        self.clear_depth = depth.clamp(0.0, 1.0);
+    }
+    unsafe fn ClearDepthx(&mut self, depth: GLclampx) {
+        self.ClearDepthf(fixed_to_float(depth))
+    }
+    unsafe fn ClearStencil(&mut self, s: GLint) {
+        self.clear_stencil = s;
+    }
+
+    // Textures
+    unsafe fn PixelStorei(&mut self, pname: GLenum, param: GLint) {
+        assert!(param == 1 || param == 2 || param == 4 || param == 8);
+        match pname {
+            gles11::PACK_ALIGNMENT => self.pack_alignment = param,
+            gles11::UNPACK_ALIGNMENT => self.unpack_alignment = param,
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        }
+    }
+    unsafe fn ReadPixels(
+        &mut self,
+        x: GLint,
+        y: GLint,
+        width: GLsizei,
+        height: GLsizei,
+        format: GLenum,
+        type_: GLenum,
+        pixels: *mut GLvoid,
+    ) {
+        assert!(type_ == gles11::UNSIGNED_BYTE);
+        assert!(format == gles11::RGBA || format == gles11::BGRA_EXT);
+        if width < 0 || height < 0 {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let (width, height) = (width as u32, height as u32);
+        let region = self.read_color_region(x, y, width, height);
+        let row_size = texture::row_size(width, 4, self.pack_alignment);
+        let pixels: *mut u8 = pixels.cast();
+        for (row_index, row) in region.data.chunks(width.max(1) as usize).enumerate() {
+            let out =
+                std::slice::from_raw_parts_mut(pixels.add(row_index * row_size), row.len() * 4);
+            for (&[r, g, b, a], out) in row.iter().zip(out.chunks_exact_mut(4)) {
+                out.copy_from_slice(&match format {
+                    gles11::RGBA => [r, g, b, a],
+                    _ => [b, g, r, a],
+                });
+            }
+        }
+    }
+    unsafe fn GenTextures(&mut self, n: GLsizei, textures: *mut GLuint) {
+        gen_names(&mut self.textures, n, textures)
+    }
+    unsafe fn DeleteTextures(&mut self, n: GLsizei, textures: *const GLuint) {
+        for i in 0..n.max(0) as usize {
+            let name = textures.add(i).read();
+            if name == 0 || self.textures.remove(&name).is_none() {
+                continue;
+            }
+            for unit in self.texture_units.iter_mut() {
+                if unit.binding == name {
+                    unit.binding = 0;
+                }
+            }
+            self.detach_from_framebuffer(Attachment::Texture(name, 0));
+        }
+    }
+    unsafe fn ActiveTexture(&mut self, texture: GLenum) {
+        let index = texture.wrapping_sub(gles11::TEXTURE0) as usize;
+        if index >= TEXTURE_UNITS {
+            self.set_error(gles11::INVALID_ENUM);
+            return;
+        }
+        self.active_texture = index;
+    }
+    unsafe fn BindTexture(&mut self, target: GLenum, texture: GLuint) {
+        assert!(target == gles11::TEXTURE_2D);
+        self.textures.entry(texture).or_default();
+        self.texture_units[self.active_texture].binding = texture;
+    }
+    unsafe fn TexParameteri(&mut self, target: GLenum, pname: GLenum, param: GLint) {
+        self.tex_parameter(target, pname, param as GLfloat);
+    }
+    unsafe fn TexParameterf(&mut self, target: GLenum, pname: GLenum, param: GLfloat) {
+        self.tex_parameter(target, pname, param);
+    }
+    unsafe fn TexParameterx(&mut self, target: GLenum, pname: GLenum, param: GLfixed) {
+        let param = fixed_param(&TEX_PARAMS, pname, param);
+        self.tex_parameter(target, pname, param);
+    }
+    unsafe fn TexParameteriv(&mut self, target: GLenum, pname: GLenum, params: *const GLint) {
+        self.tex_parameter(target, pname, params.read() as GLfloat);
+    }
+    unsafe fn TexParameterfv(&mut self, target: GLenum, pname: GLenum, params: *const GLfloat) {
+        self.tex_parameter(target, pname, params.read());
+    }
+    unsafe fn TexParameterxv(&mut self, target: GLenum, pname: GLenum, params: *const GLfixed) {
+        let params = fixed_params(&TEX_PARAMS, pname, params);
+        self.tex_parameter(target, pname, params[0]);
+    }
+    unsafe fn TexImage2D(
+        &mut self,
+        target: GLenum,
+        level: GLint,
+        internalformat: GLint,
+        width: GLsizei,
+        height: GLsizei,
+        border: GLint,
+        format: GLenum,
+        type_: GLenum,
+        pixels: *const GLvoid,
+    ) {
+        assert!(target == gles11::TEXTURE_2D);
+        assert!(level >= 0);
+        assert!(border == 0);
+        let base_format = texture::base_format_for_internalformat(internalformat as GLenum)
+            .unwrap_or_else(|| panic!("Unhandled internalformat: {:#x}", internalformat));
+        assert!(
+            texture::bytes_per_pixel(format, type_).is_some(),
+            "Unhandled format/type: {:#x}/{:#x}",
+            format,
+            type_
+        );
+        if width < 0
+            || height < 0
+            || width as u32 > MAX_TEXTURE_SIZE
+            || height as u32 > MAX_TEXTURE_SIZE
+        {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let (width, height) = (width as u32, height as u32);
+        let mut texels = Plane::new(width, height);
+        if !pixels.is_null() {
+            texels.data =
+                texture::unpack_pixels(width, height, format, type_, self.unpack_alignment, pixels)
+                    .into_iter()
+                    .map(|texel| texture::convert_to_base_format(texel, base_format))
+                    .collect();
+        }
+        self.bound_texture_mut().set_level(
+            level as usize,
+            TextureLevel {
+                base_format,
+                texels,
+            },
+        );
+    }
+    unsafe fn TexSubImage2D(
+        &mut self,
+        target: GLenum,
+        level: GLint,
+        xoffset: GLint,
+        yoffset: GLint,
+        width: GLsizei,
+        height: GLsizei,
+        format: GLenum,
+        type_: GLenum,
+        pixels: *const GLvoid,
+    ) {
+        assert!(target == gles11::TEXTURE_2D);
+        assert!(level >= 0);
+        assert!(
+            texture::bytes_per_pixel(format, type_).is_some(),
+            "Unhandled format/type: {:#x}/{:#x}",
+            format,
+            type_
+        );
+        let Some((level_width, level_height)) = self.texture_level_size(level) else {
+            self.set_error(gles11::INVALID_OPERATION);
+            return;
+        };
+        if xoffset < 0
+            || yoffset < 0
+            || width < 0
+            || height < 0
+            || (xoffset + width) as u32 > level_width
+            || (yoffset + height) as u32 > level_height
+        {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let (width, height) = (width as u32, height as u32);
+        let new_texels =
+            texture::unpack_pixels(width, height, format, type_, self.unpack_alignment, pixels);
+        let texture = self.bound_texture_mut();
+        let level_data = texture.level_mut(level as usize).unwrap();
+        let base_format = level_data.base_format;
+        for y in 0..height {
+            for x in 0..width {
+                let texel = new_texels[(y * width + x) as usize];
+                level_data.texels.set(
+                    xoffset as u32 + x,
+                    yoffset as u32 + y,
+                    texture::convert_to_base_format(texel, base_format),
+                );
+            }
+        }
+        if level == 0 && texture.generate_mipmap {
+            texture.generate_mipmaps();
+        }
+    }
+    unsafe fn CompressedTexImage2D(
+        &mut self,
+        target: GLenum,
+        level: GLint,
+        internalformat: GLenum,
+        width: GLsizei,
+        height: GLsizei,
+        border: GLint,
+        image_size: GLsizei,
+        data: *const GLvoid,
+    ) {
+        let data = unsafe { std::slice::from_raw_parts(data.cast::<u8>(), image_size as usize) };
+        if try_decode_pvrtc(
+            self,
+            target,
+            level,
+            internalformat,
+            width,
+            height,
+            border,
+            data,
+        ) {
+            log_dbg!("Decoded PVRTC");
+        } else if try_decode_paletted(
+            self,
+            target,
+            level,
+            internalformat,
+            width,
+            height,
+            border,
+            data,
+        ) {
+            log_dbg!("Decoded paletted texture");
+        } else {
+            unimplemented!("CompressedTexImage2D internalformat: {:#x}", internalformat);
+        }
+    }
+    unsafe fn CopyTexImage2D(
+        &mut self,
+        target: GLenum,
+        level: GLint,
+        internalformat: GLenum,
+        x: GLint,
+        y: GLint,
+        width: GLsizei,
+        height: GLsizei,
+        border: GLint,
+    ) {
+        assert!(target == gles11::TEXTURE_2D);
+        assert!(level >= 0);
+        assert!(border == 0);
+        let base_format = texture::base_format_for_internalformat(internalformat)
+            .unwrap_or_else(|| panic!("Unhandled internalformat: {:#x}", internalformat));
+        if width < 0 || height < 0 {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let mut texels = self.read_color_region(x, y, width as u32, height as u32);
+        for texel in texels.data.iter_mut() {
+            *texel = texture::convert_to_base_format(*texel, base_format);
+        }
+        self.bound_texture_mut().set_level(
+            level as usize,
+            TextureLevel {
+                base_format,
+                texels,
+            },
+        );
+    }
+    unsafe fn CopyTexSubImage2D(
+        &mut self,
+        target: GLenum,
+        level: GLint,
+        xoffset: GLint,
+        yoffset: GLint,
+        x: GLint,
+        y: GLint,
+        width: GLsizei,
+        height: GLsizei,
+    ) {
+        assert!(target == gles11::TEXTURE_2D);
+        assert!(level >= 0);
+        let Some((level_width, level_height)) = self.texture_level_size(level) else {
+            self.set_error(gles11::INVALID_OPERATION);
+            return;
+        };
+        if xoffset < 0
+            || yoffset < 0
+            || width < 0
+            || height < 0
+            || (xoffset + width) as u32 > level_width
+            || (yoffset + height) as u32 > level_height
+        {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let region = self.read_color_region(x, y, width as u32, height as u32);
+        let texture = self.bound_texture_mut();
+        let level_data = texture.level_mut(level as usize).unwrap();
+        let base_format = level_data.base_format;
+        for row in 0..region.height {
+            for col in 0..region.width {
+                level_data.texels.set(
+                    xoffset as u32 + col,
+                    yoffset as u32 + row,
+                    texture::convert_to_base_format(region.get(col, row), base_format),
+                );
+            }
+        }
+        if level == 0 && texture.generate_mipmap {
+            texture.generate_mipmaps();
+        }
+    }
+    unsafe fn TexEnvf(&mut self, target: GLenum, pname: GLenum, param: GLfloat) {
+        self.tex_env(target, pname, &[param]);
+    }
+    unsafe fn TexEnvx(&mut self, target: GLenum, pname: GLenum, param: GLfixed) {
+        let param = match target {
+            gles11::TEXTURE_ENV => fixed_param(&TEX_ENV_PARAMS, pname, param),
+            gles11::TEXTURE_FILTER_CONTROL_EXT => fixed_to_float(param),
+            _ => param as GLfloat,
+        };
+        self.tex_env(target, pname, &[param]);
+    }
+    unsafe fn TexEnvi(&mut self, target: GLenum, pname: GLenum, param: GLint) {
+        self.tex_env(target, pname, &[param as GLfloat]);
+    }
+    unsafe fn TexEnvfv(&mut self, target: GLenum, pname: GLenum, params: *const GLfloat) {
+        let params = match target {
+            gles11::TEXTURE_ENV => float_params(&TEX_ENV_PARAMS, pname, params),
+            _ => vec![params.read()],
+        };
+        self.tex_env(target, pname, &params);
+    }
+    unsafe fn TexEnvxv(&mut self, target: GLenum, pname: GLenum, params: *const GLfixed) {
+        match target {
+            gles11::TEXTURE_ENV => {
+                let params = fixed_params(&TEX_ENV_PARAMS, pname, params);
+                self.tex_env(target, pname, &params);
+            }
+            _ => self.TexEnvx(target, pname, params.read()),
+        }
+    }
+    unsafe fn TexEnviv(&mut self, target: GLenum, pname: GLenum, params: *const GLint) {
+        if target == gles11::TEXTURE_ENV && pname == gles11::TEXTURE_ENV_COLOR {
+            // Integer colors are mapped from the full integer range.
+            let color: Vec<GLfloat> = std::slice::from_raw_parts(params, 4)
+                .iter()
+                .map(|&c| (c as f64 / GLint::MAX as f64) as GLfloat)
+                .collect();
+            self.tex_env(target, pname, &color);
+        } else {
+            self.TexEnvi(target, pname, params.read());
+        }
+    }
+
+    // Matrix stack operations
+    unsafe fn MatrixMode(&mut self, mode: GLenum) {
+        assert!(mode == gles11::MODELVIEW || mode == gles11::PROJECTION || mode == gles11::TEXTURE);
+        self.matrix_mode = mode;
+    }
+    unsafe fn LoadIdentity(&mut self) {
+        *self.current_matrix_mut() = IDENTITY;
+    }
+    unsafe fn LoadMatrixf(&mut self, m: *const GLfloat) {
+        *self.current_matrix_mut() = m.cast::<Matrix>().read_unaligned();
+    }
+    unsafe fn LoadMatrixx(&mut self, m: *const GLfixed) {
+        *self.current_matrix_mut() = matrix_fixed_to_float(m);
+    }
+    unsafe fn MultMatrixf(&mut self, m: *const GLfloat) {
+        self.mult_matrix(&m.cast::<Matrix>().read_unaligned());
+    }
+    unsafe fn MultMatrixx(&mut self, m: *const GLfixed) {
+        self.mult_matrix(&matrix_fixed_to_float(m));
+    }
+    unsafe fn PushMatrix(&mut self) {
+        let (stack, max_depth) = self.matrix_stack_mut();
+        if stack.len() == max_depth {
+            self.set_error(gles11::STACK_OVERFLOW);
+            return;
+        }
+        stack.push(*stack.last().unwrap());
+    }
+    unsafe fn PopMatrix(&mut self) {
+        let (stack, _) = self.matrix_stack_mut();
+        if stack.len() == 1 {
+            self.set_error(gles11::STACK_UNDERFLOW);
+            return;
+        }
+        stack.pop();
+    }
+    unsafe fn Orthof(
+        &mut self,
+        left: GLfloat,
+        right: GLfloat,
+        bottom: GLfloat,
+        top: GLfloat,
+        near: GLfloat,
+        far: GLfloat,
+    ) {
+        if left == right || bottom == top || near == far {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let (w, h, d) = (right - left, top - bottom, far - near);
+        #[rustfmt::skip]
+        let matrix = [
+            2.0 / w, 0.0, 0.0, 0.0,
+            0.0, 2.0 / h, 0.0, 0.0,
+            0.0, 0.0, -2.0 / d, 0.0,
+            -(right + left) / w, -(top + bottom) / h, -(far + near) / d, 1.0,
+        ];
+        self.mult_matrix(&matrix);
+    }
+    unsafe fn Orthox(
+        &mut self,
+        left: GLfixed,
+        right: GLfixed,
+        bottom: GLfixed,
+        top: GLfixed,
+        near: GLfixed,
+        far: GLfixed,
+    ) {
+        self.Orthof(
+            fixed_to_float(left),
+            fixed_to_float(right),
+            fixed_to_float(bottom),
+            fixed_to_float(top),
+            fixed_to_float(near),
+            fixed_to_float(far),
+        );
+    }
+    unsafe fn Frustumf(
+        &mut self,
+        left: GLfloat,
+        right: GLfloat,
+        bottom: GLfloat,
+        top: GLfloat,
+        near: GLfloat,
+        far: GLfloat,
+    ) {
+        if near <= 0.0 || far <= 0.0 || left == right || bottom == top || near == far {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let (w, h, d) = (right - left, top - bottom, far - near);
+        #[rustfmt::skip]
+        let matrix = [
+            2.0 * near / w, 0.0, 0.0, 0.0,
+            0.0, 2.0 * near / h, 0.0, 0.0,
+            (right + left) / w, (top + bottom) / h, -(far + near) / d, -1.0,
+            0.0, 0.0, -2.0 * far * near / d, 0.0,
+        ];
+        self.mult_matrix(&matrix);
+    }
+    unsafe fn Frustumx(
+        &mut self,
+        left: GLfixed,
+        right: GLfixed,
+        bottom: GLfixed,
+        top: GLfixed,
+        near: GLfixed,
+        far: GLfixed,
+    ) {
+        self.Frustumf(
+            fixed_to_float(left),
+            fixed_to_float(right),
+            fixed_to_float(bottom),
+            fixed_to_float(top),
+            fixed_to_float(near),
+            fixed_to_float(far),
+        );
+    }
+    unsafe fn Rotatef(&mut self, angle: GLfloat, x: GLfloat, y: GLfloat, z: GLfloat) {
+        let length = (x * x + y * y + z * z).sqrt();
+        if length == 0.0 {
+            return;
+        }
+        let (x, y, z) = (x / length, y / length, z / length);
+        let (s, c) = angle.to_radians().sin_cos();
+        let ic = 1.0 - c;
+        #[rustfmt::skip]
+        let matrix = [
+            x * x * ic + c, y * x * ic + z * s, x * z * ic - y * s, 0.0,
+            x * y * ic - z * s, y * y * ic + c, y * z * ic + x * s, 0.0,
+            x * z * ic + y * s, y * z * ic - x * s, z * z * ic + c, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ];
+        self.mult_matrix(&matrix);
+    }
+    unsafe fn Rotatex(&mut self, angle: GLfixed, x: GLfixed, y: GLfixed, z: GLfixed) {
+        self.Rotatef(
+            fixed_to_float(angle),
+            fixed_to_float(x),
+            fixed_to_float(y),
+            fixed_to_float(z),
+        );
+    }
+    unsafe fn Scalef(&mut self, x: GLfloat, y: GLfloat, z: GLfloat) {
+        #[rustfmt::skip]
+        let matrix = [
+            x, 0.0, 0.0, 0.0,
+            0.0, y, 0.0, 0.0,
+            0.0, 0.0, z, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ];
+        self.mult_matrix(&matrix);
+    }
+    unsafe fn Scalex(&mut self, x: GLfixed, y: GLfixed, z: GLfixed) {
+        self.Scalef(fixed_to_float(x), fixed_to_float(y), fixed_to_float(z));
+    }
+    unsafe fn Translatef(&mut self, x: GLfloat, y: GLfloat, z: GLfloat) {
+        #[rustfmt::skip]
+        let matrix = [
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            x, y, z, 1.0,
+        ];
+        self.mult_matrix(&matrix);
+    }
+    unsafe fn Translatex(&mut self, x: GLfixed, y: GLfixed, z: GLfixed) {
+        self.Translatef(fixed_to_float(x), fixed_to_float(y), fixed_to_float(z));
+    }
+
+    // OES_framebuffer_object
+    unsafe fn GenFramebuffersOES(&mut self, n: GLsizei, framebuffers: *mut GLuint) {
+        gen_names(&mut self.framebuffers, n, framebuffers)
+    }
+    unsafe fn GenRenderbuffersOES(&mut self, n: GLsizei, renderbuffers: *mut GLuint) {
+        gen_names(&mut self.renderbuffers, n, renderbuffers)
+    }
+    unsafe fn BindFramebufferOES(&mut self, target: GLenum, framebuffer: GLuint) {
+        assert!(target == gles11::FRAMEBUFFER_OES);
+        if framebuffer != 0 {
+            self.framebuffers.entry(framebuffer).or_default();
+        }
+        self.framebuffer_binding = framebuffer;
+    }
+    unsafe fn BindRenderbufferOES(&mut self, target: GLenum, renderbuffer: GLuint) {
+        assert!(target == gles11::RENDERBUFFER_OES);
+        if renderbuffer != 0 {
+            self.renderbuffers.entry(renderbuffer).or_default();
+        }
+        self.renderbuffer_binding = renderbuffer;
+    }
+    unsafe fn RenderbufferStorageOES(
+        &mut self,
+        target: GLenum,
+        internalformat: GLenum,
+        width: GLsizei,
+        height: GLsizei,
+    ) {
+        assert!(target == gles11::RENDERBUFFER_OES);
+        let bits = renderbuffer_format_bits(internalformat)
+            .unwrap_or_else(|| panic!("Unhandled internalformat: {:#x}", internalformat));
+        if self.renderbuffer_binding == 0 {
+            self.set_error(gles11::INVALID_OPERATION);
+            return;
+        }
+        if width < 0
+            || height < 0
+            || width as u32 > MAX_TEXTURE_SIZE
+            || height as u32 > MAX_TEXTURE_SIZE
+        {
+            self.set_error(gles11::INVALID_VALUE);
+            return;
+        }
+        let (width, height) = (width as u32, height as u32);
+        let plane_size = |used: bool| if used { (width, height) } else { (0, 0) };
+        let (color_w, color_h) = plane_size(bits[..4].iter().any(|&b| b != 0));
+        let (depth_w, depth_h) = plane_size(bits[4] != 0);
+        let (stencil_w, stencil_h) = plane_size(bits[5] != 0);
+        *self
+            .renderbuffers
+            .get_mut(&self.renderbuffer_binding)
+            .unwrap() = Renderbuffer {
+            internalformat,
+            width,
+            height,
+            color: Plane::new(color_w, color_h),
+            depth: Plane::filled(depth_w, depth_h, 1.0),
+            stencil: Plane::new(stencil_w, stencil_h),
+        };
+    }
+    unsafe fn FramebufferRenderbufferOES(
+        &mut self,
+        target: GLenum,
+        attachment: GLenum,
+        renderbuffertarget: GLenum,
+        renderbuffer: GLuint,
+    ) {
+        assert!(target == gles11::FRAMEBUFFER_OES);
+        assert!(renderbuffertarget == gles11::RENDERBUFFER_OES);
+        if self.framebuffer_binding == 0 {
+            self.set_error(gles11::INVALID_OPERATION);
+            return;
+        }
+        let value = if renderbuffer == 0 {
+            Attachment::None
+        } else {
+            Attachment::Renderbuffer(renderbuffer)
+        };
+        let framebuffer = self
+            .framebuffers
+            .get_mut(&self.framebuffer_binding)
+            .unwrap();
+        match attachment {
+            gles11::COLOR_ATTACHMENT0_OES => framebuffer.color = value,
+            gles11::DEPTH_ATTACHMENT_OES => framebuffer.depth = value,
+            gles11::STENCIL_ATTACHMENT_OES => framebuffer.stencil = value,
+            _ => panic!("Unhandled attachment: {:#x}", attachment),
+        }
+    }
+    unsafe fn FramebufferTexture2DOES(
+        &mut self,
+        target: GLenum,
+        attachment: GLenum,
+        textarget: GLenum,
+        texture: GLuint,
+        level: i32,
+    ) {
+        assert!(target == gles11::FRAMEBUFFER_OES);
+        assert!(textarget == gles11::TEXTURE_2D);
+        // Depth and stencil textures don't exist in OpenGL ES 1.1.
+        assert!(attachment == gles11::COLOR_ATTACHMENT0_OES);
+        if self.framebuffer_binding == 0 || level < 0 {
+            self.set_error(gles11::INVALID_OPERATION);
+            return;
+        }
+        let value = if texture == 0 {
+            Attachment::None
+        } else {
+            Attachment::Texture(texture, level as usize)
+        };
+        self.framebuffers
+            .get_mut(&self.framebuffer_binding)
+            .unwrap()
+            .color = value;
+    }
+    unsafe fn GetRenderbufferParameterivOES(
+        &mut self,
+        target: GLenum,
+        pname: GLenum,
+        params: *mut GLint,
+    ) {
+        assert!(target == gles11::RENDERBUFFER_OES);
+        if self.renderbuffer_binding == 0 {
+            self.set_error(gles11::INVALID_OPERATION);
+            return;
+        }
+        let renderbuffer = &self.renderbuffers[&self.renderbuffer_binding];
+        let bits = renderbuffer_format_bits(renderbuffer.internalformat).unwrap_or([0; 6]);
+        let value = match pname {
+            gles11::RENDERBUFFER_WIDTH_OES => renderbuffer.width as GLint,
+            gles11::RENDERBUFFER_HEIGHT_OES => renderbuffer.height as GLint,
+            gles11::RENDERBUFFER_INTERNAL_FORMAT_OES => renderbuffer.internalformat as GLint,
+            gles11::RENDERBUFFER_RED_SIZE_OES => bits[0],
+            gles11::RENDERBUFFER_GREEN_SIZE_OES => bits[1],
+            gles11::RENDERBUFFER_BLUE_SIZE_OES => bits[2],
+            gles11::RENDERBUFFER_ALPHA_SIZE_OES => bits[3],
+            gles11::RENDERBUFFER_DEPTH_SIZE_OES => bits[4],
+            gles11::RENDERBUFFER_STENCIL_SIZE_OES => bits[5],
+            _ => panic!("Unhandled parameter name: {:#x}", pname),
+        };
+        params.write(value);
+    }
+    unsafe fn CheckFramebufferStatusOES(&mut self, target: GLenum) -> GLenum {
+        assert!(target == gles11::FRAMEBUFFER_OES);
+        self.framebuffer_status()
+    }
+    unsafe fn DeleteFramebuffersOES(&mut self, n: GLsizei, framebuffers: *const GLuint) {
+        for i in 0..n.max(0) as usize {
+            let name = framebuffers.add(i).read();
+            if name == 0 || self.framebuffers.remove(&name).is_none() {
+                continue;
+            }
+            if self.framebuffer_binding == name {
+                self.framebuffer_binding = 0;
+            }
+        }
+    }
+    unsafe fn DeleteRenderbuffersOES(&mut self, n: GLsizei, renderbuffers: *const GLuint) {
+        for i in 0..n.max(0) as usize {
+            let name = renderbuffers.add(i).read();
+            if name == 0 || self.renderbuffers.remove(&name).is_none() {
+                continue;
+            }
+            if self.renderbuffer_binding == name {
+                self.renderbuffer_binding = 0;
+            }
+            self.detach_from_framebuffer(Attachment::Renderbuffer(name));
+        }
+    }
+    unsafe fn GenerateMipmapOES(&mut self, target: GLenum) {
+        assert!(target == gles11::TEXTURE_2D);
+        self.bound_texture_mut().generate_mipmaps();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_context(width: u32, height: u32) -> GLES1Software {
+        let framebuffer = Rc::new(RefCell::new(DefaultFramebuffer::new(width, height)));
+        GLES1Software::with_framebuffer(framebuffer)
+    }
+
+    fn pixel(gles: &GLES1Software, x: u32, y: u32) -> Rgba {
+        gles.default_framebuffer.borrow().color.get(x, y)
+    }
+
+    #[test]
+    fn clear_and_triangle() {
+        let mut gles = new_context(4, 4);
+        let vertices: [f32; 6] = [-1.0, -1.0, 1.0, -1.0, -1.0, 1.0];
+        unsafe {
+            gles.ClearColor(0.0, 0.0, 1.0, 1.0);
+            gles.Clear(gles11::COLOR_BUFFER_BIT);
+            gles.Color4ub(255, 0, 0, 255);
+            gles.EnableClientState(gles11::VERTEX_ARRAY);
+            gles.VertexPointer(2, gles11::FLOAT, 0, vertices.as_ptr().cast());
+            gles.DrawArrays(gles11::TRIANGLES, 0, 3);
+        }
+        // The triangle covers the bottom-left half, excluding the diagonal
+        // (which isn't a left or top edge).
+        assert_eq!(pixel(&gles, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&gles, 2, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&gles, 3, 3), [0, 0, 255, 255]);
+        assert_eq!(pixel(&gles, 1, 2), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn textured_quad_to_framebuffer_object() {
+        let mut gles = new_context(1, 1);
+        let texels: [u8; 16] = [
+            255, 0, 0, 255, 0, 255, 0, 255, //
+            0, 0, 255, 255, 255, 255, 255, 255,
+        ];
+        let vertices: [f32; 8] = [-1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0];
+        let tex_coords: [f32; 8] = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        let mut read_back = [0u8; 16];
+        unsafe {
+            let mut texture = 0;
+            gles.GenTextures(1, &mut texture);
+            gles.BindTexture(gles11::TEXTURE_2D, texture);
+            gles.TexImage2D(
+                gles11::TEXTURE_2D,
+                0,
+                gles11::RGBA as _,
+                2,
+                2,
+                0,
+                gles11::RGBA,
+                gles11::UNSIGNED_BYTE,
+                texels.as_ptr().cast(),
+            );
+            gles.TexParameteri(
+                gles11::TEXTURE_2D,
+                gles11::TEXTURE_MIN_FILTER,
+                gles11::NEAREST as _,
+            );
+            gles.TexParameteri(
+                gles11::TEXTURE_2D,
+                gles11::TEXTURE_MAG_FILTER,
+                gles11::NEAREST as _,
+            );
+
+            let (mut framebuffer, mut renderbuffer) = (0, 0);
+            gles.GenFramebuffersOES(1, &mut framebuffer);
+            gles.BindFramebufferOES(gles11::FRAMEBUFFER_OES, framebuffer);
+            gles.GenRenderbuffersOES(1, &mut renderbuffer);
+            gles.BindRenderbufferOES(gles11::RENDERBUFFER_OES, renderbuffer);
+            gles.RenderbufferStorageOES(gles11::RENDERBUFFER_OES, gles11::RGBA8_OES, 2, 2);
+            gles.FramebufferRenderbufferOES(
+                gles11::FRAMEBUFFER_OES,
+                gles11::COLOR_ATTACHMENT0_OES,
+                gles11::RENDERBUFFER_OES,
+                renderbuffer,
+            );
+            assert_eq!(
+                gles.CheckFramebufferStatusOES(gles11::FRAMEBUFFER_OES),
+                gles11::FRAMEBUFFER_COMPLETE_OES
+            );
+            gles.Viewport(0, 0, 2, 2);
+
+            gles.Enable(gles11::TEXTURE_2D);
+            gles.TexEnvi(
+                gles11::TEXTURE_ENV,
+                gles11::TEXTURE_ENV_MODE,
+                gles11::REPLACE as _,
+            );
+            gles.EnableClientState(gles11::VERTEX_ARRAY);
+            gles.VertexPointer(2, gles11::FLOAT, 0, vertices.as_ptr().cast());
+            gles.EnableClientState(gles11::TEXTURE_COORD_ARRAY);
+            gles.TexCoordPointer(2, gles11::FLOAT, 0, tex_coords.as_ptr().cast());
+            gles.DrawArrays(gles11::TRIANGLE_STRIP, 0, 4);
+
+            gles.ReadPixels(
+                0,
+                0,
+                2,
+                2,
+                gles11::RGBA,
+                gles11::UNSIGNED_BYTE,
+                read_back.as_mut_ptr().cast(),
+            );
+            assert_eq!(gles.GetError(), gles11::NO_ERROR);
+        }
+        assert_eq!(read_back, texels);
+    }
+
+    #[test]
+    fn blending_and_depth_test() {
+        let mut gles = new_context(1, 1);
+        let near: [f32; 6] = [-3.0, -3.0, 5.0, -3.0, -3.0, 5.0];
+        let far: [f32; 9] = [-3.0, -3.0, 0.5, 5.0, -3.0, 0.5, -3.0, 5.0, 0.5];
+        unsafe {
+            gles.Clear(gles11::COLOR_BUFFER_BIT | gles11::DEPTH_BUFFER_BIT);
+            gles.Enable(gles11::DEPTH_TEST);
+            gles.EnableClientState(gles11::VERTEX_ARRAY);
+
+            gles.Color4f(1.0, 0.0, 0.0, 1.0);
+            gles.VertexPointer(2, gles11::FLOAT, 0, near.as_ptr().cast());
+            gles.DrawArrays(gles11::TRIANGLES, 0, 3);
+
+            // Hidden behind the first triangle
+            gles.Color4f(0.0, 1.0, 0.0, 1.0);
+            gles.VertexPointer(3, gles11::FLOAT, 0, far.as_ptr().cast());
+            gles.DrawArrays(gles11::TRIANGLES, 0, 3);
+            assert_eq!(pixel(&gles, 0, 0), [255, 0, 0, 255]);
+
+            gles.Disable(gles11::DEPTH_TEST);
+            gles.Enable(gles11::BLEND);
+            gles.BlendFunc(gles11::SRC_ALPHA, gles11::ONE_MINUS_SRC_ALPHA);
+            gles.Color4f(0.0, 0.0, 1.0, 0.5);
+            gles.DrawArrays(gles11::TRIANGLES, 0, 3);
+        }
+        assert_eq!(pixel(&gles, 0, 0), [128, 0, 128, 191]);
+    }
+}
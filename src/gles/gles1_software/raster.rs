@@ -0,0 +1,1092 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Vertex processing, clipping, rasterization and per-fragment operations for
+//! the software renderer.
+//!
+//! Everything here follows the OpenGL ES 1.1 specification's description of
+//! the fixed-function pipeline fairly literally, favoring simplicity and
+//! determinism over speed.
+
+use super::super::gles11_raw as gles11; // constants only
+use super::super::gles11_raw::types::*;
+use super::super::util::fixed_to_float;
+use super::texture::{float_to_rgba, rgba_to_float, Plane, Rgba, Texture};
+use super::{ArrayState, GLES1Software, TexEnv, POINT_SPRITE_OES, TEXTURE_UNITS};
+use std::collections::HashMap;
+
+/// 4-by-4 column-major matrix, as used by OpenGL.
+pub type Matrix = [f32; 16];
+
+#[rustfmt::skip]
+pub const IDENTITY: Matrix = [
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+];
+
+pub fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|i| {
+        let (col, row) = (i / 4, i % 4);
+        (0..4).map(|k| a[k * 4 + row] * b[col * 4 + k]).sum()
+    })
+}
+
+pub fn transform(m: &Matrix, v: [f32; 4]) -> [f32; 4] {
+    std::array::from_fn(|row| (0..4).map(|k| m[k * 4 + row] * v[k]).sum())
+}
+
+/// Inverse transpose of the upper-left 3-by-3 part of a matrix, used to
+/// transform normals. Rows are the outer array.
+fn normal_matrix(m: &Matrix) -> [[f32; 3]; 3] {
+    let a = |r: usize, c: usize| m[c * 4 + r];
+    // Thanks to the cyclic indexing, the signs come out right by themselves.
+    let cofactor = |r: usize, c: usize| {
+        let (r1, r2) = ((r + 1) % 3, (r + 2) % 3);
+        let (c1, c2) = ((c + 1) % 3, (c + 2) % 3);
+        a(r1, c1) * a(r2, c2) - a(r1, c2) * a(r2, c1)
+    };
+    let det: f32 = (0..3).map(|c| a(0, c) * cofactor(0, c)).sum();
+    let scale = if det == 0.0 { 1.0 } else { 1.0 / det };
+    std::array::from_fn(|r| std::array::from_fn(|c| cofactor(r, c) * scale))
+}
+
+fn dot3(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize3(v: [f32; 3]) -> [f32; 3] {
+    let length = dot3(v, v).sqrt();
+    if length == 0.0 {
+        v
+    } else {
+        v.map(|c| c / length)
+    }
+}
+
+fn xyz(v: [f32; 4]) -> [f32; 3] {
+    [v[0], v[1], v[2]]
+}
+
+/// The buffers of the framebuffer being drawn to. These are temporarily taken
+/// from their owners for the duration of a drawing operation, see
+/// [GLES1Software::take_target].
+#[derive(Default)]
+pub(super) struct Target {
+    pub color: Option<Plane<Rgba>>,
+    pub depth: Option<Plane<f32>>,
+    pub stencil: Option<Plane<u8>>,
+    /// Whether the color buffer stores alpha. If not, it reads as 1.
+    pub color_has_alpha: bool,
+}
+impl Target {
+    pub fn size(&self) -> (u32, u32) {
+        if let Some(ref color) = self.color {
+            (color.width, color.height)
+        } else if let Some(ref depth) = self.depth {
+            (depth.width, depth.height)
+        } else if let Some(ref stencil) = self.stencil {
+            (stencil.width, stencil.height)
+        } else {
+            (0, 0)
+        }
+    }
+}
+
+/// A vertex after transformation and lighting, in clip co-ordinates.
+#[derive(Clone, Copy, Default)]
+pub(super) struct Vertex {
+    clip: [f32; 4],
+    /// Front and back colors. These are only different when two-sided
+    /// lighting is in use.
+    colors: [[f32; 4]; 2],
+    tex_coords: [[f32; 4]; TEXTURE_UNITS],
+    /// Distance from the eye used for fog.
+    fog_coord: f32,
+    point_size: f32,
+}
+impl Vertex {
+    fn lerp(&self, other: &Vertex, t: f32) -> Vertex {
+        let lerp4 = |a: [f32; 4], b: [f32; 4]| -> [f32; 4] {
+            std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+        };
+        Vertex {
+            clip: lerp4(self.clip, other.clip),
+            colors: [
+                lerp4(self.colors[0], other.colors[0]),
+                lerp4(self.colors[1], other.colors[1]),
+            ],
+            tex_coords: std::array::from_fn(|i| lerp4(self.tex_coords[i], other.tex_coords[i])),
+            fog_coord: self.fog_coord + (other.fog_coord - self.fog_coord) * t,
+            point_size: self.point_size,
+        }
+    }
+
+    /// Signed distances from each of the six clip planes, which are
+    /// non-negative when the vertex is inside the plane.
+    fn clip_distances(&self) -> [f32; 6] {
+        let [x, y, z, w] = self.clip;
+        [w + x, w - x, w + y, w - y, w + z, w - z]
+    }
+}
+
+/// Color, texture co-ordinates and fog co-ordinate, flattened for
+/// interpolation.
+const ATTRIB_COUNT: usize = 4 + 4 * TEXTURE_UNITS + 1;
+const FOG_ATTRIB: usize = ATTRIB_COUNT - 1;
+
+/// A vertex in window co-ordinates, ready for rasterization. Attributes are
+/// stored divided by the clip-space W so they can be interpolated linearly in
+/// screen space (perspective correction).
+#[derive(Clone, Copy)]
+struct WindowVertex {
+    x: f64,
+    y: f64,
+    z: f32,
+    inv_w: f32,
+    attribs: [f32; ATTRIB_COUNT],
+}
+
+/// A fragment produced by rasterization.
+struct Fragment {
+    x: u32,
+    y: u32,
+    z: f32,
+    color: [f32; 4],
+    /// Texture co-ordinates after division by q.
+    tex_coords: [[f32; 2]; TEXTURE_UNITS],
+    /// Texture level-of-detail parameter (before bias).
+    lambdas: [f32; TEXTURE_UNITS],
+    fog_coord: f32,
+}
+
+impl GLES1Software {
+    /// Reads one element of a vertex array and converts it to floating-point,
+    /// filling in missing components from `default`.
+    unsafe fn fetch_attribute(
+        &self,
+        array: &ArrayState,
+        index: u32,
+        normalize: bool,
+        default: [f32; 4],
+    ) -> [f32; 4] {
+        let type_size = match array.type_ {
+            gles11::BYTE | gles11::UNSIGNED_BYTE => 1,
+            gles11::SHORT => 2,
+            gles11::FIXED | gles11::FLOAT => 4,
+            _ => unreachable!(),
+        };
+        let size = array.size as usize;
+        let stride = if array.stride == 0 {
+            type_size * size
+        } else {
+            array.stride as usize
+        };
+        let offset = stride * index as usize;
+        let ptr: *const u8 = if array.buffer != 0 {
+            let Some(buffer) = self.buffers.get(&array.buffer) else {
+                return default;
+            };
+            let start = array.pointer as usize + offset;
+            if start + type_size * size > buffer.data.len() {
+                log_dbg!("Out-of-bounds vertex buffer access, ignoring");
+                return default;
+            }
+            buffer.data.as_ptr().add(start)
+        } else {
+            array.pointer.cast::<u8>().add(offset)
+        };
+
+        let mut res = default;
+        for (i, component) in res.iter_mut().enumerate().take(size) {
+            let ptr = ptr.add(i * type_size);
+            *component = match array.type_ {
+                gles11::BYTE => {
+                    let value = ptr.cast::<i8>().read() as f32;
+                    if normalize {
+                        (2.0 * value + 1.0) / 255.0
+                    } else {
+                        value
+                    }
+                }
+                gles11::UNSIGNED_BYTE => {
+                    let value = ptr.read() as f32;
+                    if normalize {
+                        value / 255.0
+                    } else {
+                        value
+                    }
+                }
+                gles11::SHORT => {
+                    let value = ptr.cast::<i16>().read_unaligned() as f32;
+                    if normalize {
+                        (2.0 * value + 1.0) / 65535.0
+                    } else {
+                        value
+                    }
+                }
+                gles11::FIXED => fixed_to_float(ptr.cast::<GLfixed>().read_unaligned()),
+                gles11::FLOAT => ptr.cast::<f32>().read_unaligned(),
+                _ => unreachable!(),
+            };
+        }
+        res
+    }
+
+    /// Computes the lit color of a vertex for one face. Positions and normals
+    /// are in eye co-ordinates.
+    fn light_vertex(&self, eye: [f32; 4], normal: [f32; 3], color: [f32; 4]) -> [f32; 4] {
+        let material = &self.material;
+        let (ambient, diffuse) = if self.is_enabled(gles11::COLOR_MATERIAL) {
+            (color, color)
+        } else {
+            (material.ambient, material.diffuse)
+        };
+        let eye_pos = if eye[3] != 0.0 {
+            [eye[0] / eye[3], eye[1] / eye[3], eye[2] / eye[3]]
+        } else {
+            xyz(eye)
+        };
+
+        let mut res: [f32; 3] = std::array::from_fn(|i| {
+            material.emission[i] + ambient[i] * self.light_model_ambient[i]
+        });
+        for (i, light) in self.lights.iter().enumerate() {
+            if !self.is_enabled(gles11::LIGHT0 + i as GLenum) {
+                continue;
+            }
+            let (to_light, attenuation) = if light.position[3] != 0.0 {
+                let position = xyz(light.position).map(|c| c / light.position[3]);
+                let to_light: [f32; 3] = std::array::from_fn(|i| position[i] - eye_pos[i]);
+                let distance = dot3(to_light, to_light).sqrt();
+                let attenuation = 1.0
+                    / (light.attenuation[0]
+                        + light.attenuation[1] * distance
+                        + light.attenuation[2] * distance * distance);
+                (normalize3(to_light), attenuation)
+            } else {
+                (normalize3(xyz(light.position)), 1.0)
+            };
+            let spot = if light.spot_cutoff != 180.0 {
+                let direction = normalize3(light.spot_direction);
+                let cos = -dot3(to_light, direction);
+                if cos >= light.spot_cutoff.to_radians().cos() {
+                    cos.max(0.0).powf(light.spot_exponent)
+                } else {
+                    0.0
+                }
+            } else {
+                1.0
+            };
+            let factor = attenuation * spot;
+            if factor == 0.0 {
+                continue;
+            }
+
+            let n_dot_l = dot3(normal, to_light).max(0.0);
+            let half = normalize3([to_light[0], to_light[1], to_light[2] + 1.0]);
+            let specular = if n_dot_l != 0.0 {
+                dot3(normal, half).max(0.0).powf(material.shininess)
+            } else {
+                0.0
+            };
+            for c in 0..3 {
+                res[c] += factor
+                    * (ambient[c] * light.ambient[c]
+                        + n_dot_l * diffuse[c] * light.diffuse[c]
+                        + specular * material.specular[c] * light.specular[c]);
+            }
+        }
+        [
+            res[0].clamp(0.0, 1.0),
+            res[1].clamp(0.0, 1.0),
+            res[2].clamp(0.0, 1.0),
+            diffuse[3].clamp(0.0, 1.0),
+        ]
+    }
+
+    /// Fetches and transforms the vertices with the given indices.
+    unsafe fn process_vertices(&self, indices: &[u32]) -> Vec<Vertex> {
+        let modelview = self.modelview_stack.last().unwrap();
+        let projection = self.projection_stack.last().unwrap();
+        let normal_matrix = normal_matrix(modelview);
+        let lighting = self.is_enabled(gles11::LIGHTING);
+        let normalize =
+            self.is_enabled(gles11::NORMALIZE) || self.is_enabled(gles11::RESCALE_NORMAL);
+
+        let mut cache: HashMap<u32, Vertex> = HashMap::new();
+        let mut vertices = Vec::with_capacity(indices.len());
+        for &index in indices {
+            if let Some(&vertex) = cache.get(&index) {
+                vertices.push(vertex);
+                continue;
+            }
+
+            let position =
+                self.fetch_attribute(&self.vertex_array, index, false, [0.0, 0.0, 0.0, 1.0]);
+            let color = if self.color_array.enabled {
+                self.fetch_attribute(&self.color_array, index, true, [0.0, 0.0, 0.0, 1.0])
+            } else {
+                self.current_color
+            };
+            let eye = transform(modelview, position);
+            let clip = transform(projection, eye);
+
+            let colors = if lighting {
+                let normal = if self.normal_array.enabled {
+                    xyz(self.fetch_attribute(&self.normal_array, index, true, [0.0; 4]))
+                } else {
+                    self.current_normal
+                };
+                let mut normal: [f32; 3] = std::array::from_fn(|r| dot3(normal_matrix[r], normal));
+                if normalize {
+                    normal = normalize3(normal);
+                }
+                let front = self.light_vertex(eye, normal, color);
+                let back = if self.light_model_two_side {
+                    self.light_vertex(eye, normal.map(|c| -c), color)
+                } else {
+                    front
+                };
+                [front, back]
+            } else {
+                let color = color.map(|c| c.clamp(0.0, 1.0));
+                [color, color]
+            };
+
+            let tex_coords = std::array::from_fn(|i| {
+                let unit = &self.texture_units[i];
+                let coords = if unit.coord_array.enabled {
+                    self.fetch_attribute(&unit.coord_array, index, false, [0.0, 0.0, 0.0, 1.0])
+                } else {
+                    unit.current_coords
+                };
+                transform(unit.matrix_stack.last().unwrap(), coords)
+            });
+
+            let distance = dot3(xyz(eye), xyz(eye)).sqrt();
+            let [a, b, c] = self.point_distance_attenuation;
+            let point_size =
+                self.point_size * (1.0 / (a + b * distance + c * distance * distance)).sqrt();
+            let point_size = point_size.clamp(self.point_size_min, self.point_size_max);
+
+            let vertex = Vertex {
+                clip,
+                colors,
+                tex_coords,
+                fog_coord: eye[2].abs(),
+                point_size,
+            };
+            cache.insert(index, vertex);
+            vertices.push(vertex);
+        }
+        vertices
+    }
+
+    /// Implementation of `glDrawArrays` and `glDrawElements` once the indices
+    /// of the vertices are known.
+    pub(super) unsafe fn draw(&mut self, mode: GLenum, indices: &[u32]) {
+        if !self.vertex_array.enabled || indices.is_empty() {
+            return;
+        }
+        let vertices = self.process_vertices(indices);
+        let Some(mut target) = self.take_target() else {
+            return;
+        };
+        DrawState::new(self, &target).draw_primitives(&mut target, mode, &vertices);
+        self.put_back_target(target);
+    }
+}
+
+/// State that is constant for the duration of a drawing operation.
+struct DrawState<'a> {
+    gl: &'a GLES1Software,
+    /// Texture and complete level count for each enabled texture unit.
+    textures: [Option<(&'a Texture, usize)>; TEXTURE_UNITS],
+    /// Bounds of the region that may be drawn to: x and y (inclusive), x and
+    /// y (exclusive).
+    clip_rect: [i64; 4],
+    flat: bool,
+}
+impl<'a> DrawState<'a> {
+    fn new(gl: &'a GLES1Software, target: &Target) -> Self {
+        let textures = std::array::from_fn(|i| {
+            let unit = &gl.texture_units[i];
+            if !unit.enabled {
+                return None;
+            }
+            let texture = gl.textures.get(&unit.binding)?;
+            match texture.complete_level_count() {
+                0 => None,
+                level_count => Some((texture, level_count)),
+            }
+        });
+        let (width, height) = target.size();
+        let mut clip_rect = [0, 0, width as i64, height as i64];
+        if gl.is_enabled(gles11::SCISSOR_TEST) {
+            let [x, y, w, h] = gl.scissor_box.map(|c| c as i64);
+            clip_rect = [
+                clip_rect[0].max(x),
+                clip_rect[1].max(y),
+                clip_rect[2].min(x + w),
+                clip_rect[3].min(y + h),
+            ];
+        }
+        DrawState {
+            gl,
+            textures,
+            clip_rect,
+            flat: gl.shade_model == gles11::FLAT,
+        }
+    }
+
+    fn draw_primitives(&self, target: &mut Target, mode: GLenum, vertices: &[Vertex]) {
+        let count = vertices.len();
+        let v = |i: usize| vertices[i];
+        match mode {
+            gles11::POINTS => {
+                for &vertex in vertices {
+                    self.draw_point(target, vertex);
+                }
+            }
+            gles11::LINES => {
+                for i in (0..count.saturating_sub(1)).step_by(2) {
+                    self.draw_line(target, [v(i), v(i + 1)]);
+                }
+            }
+            gles11::LINE_STRIP | gles11::LINE_LOOP => {
+                for i in 0..count.saturating_sub(1) {
+                    self.draw_line(target, [v(i), v(i + 1)]);
+                }
+                if mode == gles11::LINE_LOOP && count >= 2 {
+                    self.draw_line(target, [v(count - 1), v(0)]);
+                }
+            }
+            gles11::TRIANGLES => {
+                for i in (0..count.saturating_sub(2)).step_by(3) {
+                    self.draw_triangle(target, [v(i), v(i + 1), v(i + 2)]);
+                }
+            }
+            gles11::TRIANGLE_STRIP => {
+                for i in 0..count.saturating_sub(2) {
+                    // Every second triangle has the opposite winding order,
+                    // which must be corrected.
+                    if i % 2 == 0 {
+                        self.draw_triangle(target, [v(i), v(i + 1), v(i + 2)]);
+                    } else {
+                        self.draw_triangle(target, [v(i + 1), v(i), v(i + 2)]);
+                    }
+                }
+            }
+            gles11::TRIANGLE_FAN => {
+                for i in 1..count.saturating_sub(1) {
+                    self.draw_triangle(target, [v(0), v(i), v(i + 1)]);
+                }
+            }
+            _ => unimplemented!("Primitive mode: {:#x}", mode),
+        }
+    }
+
+    /// With flat shading, the last vertex of a primitive provides the color
+    /// for all of it.
+    fn apply_shade_model<const N: usize>(&self, vertices: &mut [Vertex; N]) {
+        if self.flat {
+            let colors = vertices[N - 1].colors;
+            for vertex in vertices.iter_mut() {
+                vertex.colors = colors;
+            }
+        }
+    }
+
+    fn to_window(&self, vertex: &Vertex, color_index: usize) -> WindowVertex {
+        let gl = self.gl;
+        let [x, y, z, w] = vertex.clip;
+        let inv_w = 1.0 / w;
+        let [vx, vy, vw, vh] = gl.viewport.map(|c| c as f64);
+        let (near, far) = gl.depth_range;
+        let mut attribs = [0.0; ATTRIB_COUNT];
+        attribs[..4].copy_from_slice(&vertex.colors[color_index]);
+        for (i, coords) in vertex.tex_coords.iter().enumerate() {
+            attribs[4 + i * 4..][..4].copy_from_slice(coords);
+        }
+        attribs[FOG_ATTRIB] = vertex.fog_coord;
+        WindowVertex {
+            x: vx + (x as f64 * inv_w as f64 + 1.0) * vw / 2.0,
+            y: vy + (y as f64 * inv_w as f64 + 1.0) * vh / 2.0,
+            z: near + (z * inv_w + 1.0) * (far - near) / 2.0,
+            inv_w,
+            attribs: attribs.map(|a| a * inv_w),
+        }
+    }
+
+    fn draw_triangle(&self, target: &mut Target, mut vertices: [Vertex; 3]) {
+        self.apply_shade_model(&mut vertices);
+        let polygon = clip_polygon(vertices.to_vec());
+        if polygon.len() < 3 {
+            return;
+        }
+
+        // Determine the facing from the signed area of the whole polygon.
+        let window: Vec<WindowVertex> = polygon.iter().map(|v| self.to_window(v, 0)).collect();
+        let mut area = 0.0;
+        for i in 0..window.len() {
+            let (a, b) = (&window[i], &window[(i + 1) % window.len()]);
+            area += a.x * b.y - b.x * a.y;
+        }
+        if area == 0.0 {
+            return;
+        }
+        let gl = self.gl;
+        let front_facing = (area > 0.0) == (gl.front_face == gles11::CCW);
+        if gl.is_enabled(gles11::CULL_FACE) {
+            let culled = match gl.cull_face_mode {
+                gles11::FRONT => front_facing,
+                gles11::BACK => !front_facing,
+                _ => true,
+            };
+            if culled {
+                return;
+            }
+        }
+        let window: Vec<WindowVertex> = if !front_facing && gl.light_model_two_side {
+            polygon.iter().map(|v| self.to_window(v, 1)).collect()
+        } else {
+            window
+        };
+
+        for i in 1..window.len() - 1 {
+            self.rasterize_triangle(target, [&window[0], &window[i], &window[i + 1]]);
+        }
+    }
+
+    fn rasterize_triangle(&self, target: &mut Target, vertices: [&WindowVertex; 3]) {
+        let [v0, mut v1, mut v2] = vertices;
+        let edge = |a: &WindowVertex, b: &WindowVertex, x: f64, y: f64| {
+            (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+        };
+        let mut area = edge(v0, v1, v2.x, v2.y);
+        if area == 0.0 {
+            return;
+        }
+        // Use counter-clockwise order so the edge functions are positive
+        // inside the triangle.
+        if area < 0.0 {
+            std::mem::swap(&mut v1, &mut v2);
+            area = -area;
+        }
+        let vs = [v0, v1, v2];
+        // Edge i is the one opposite vertex i.
+        let edges = [(v1, v2), (v2, v0), (v0, v1)];
+        // Fill rule: pixels exactly on an edge are only drawn if it's a left
+        // edge or a top edge, so shared edges aren't drawn twice.
+        let includes_boundary = edges.map(|(a, b)| {
+            let (dx, dy) = (b.x - a.x, b.y - a.y);
+            dy < 0.0 || (dy == 0.0 && dx < 0.0)
+        });
+
+        let polygon_offset = if self.gl.is_enabled(gles11::POLYGON_OFFSET_FILL) {
+            // Depth slope of the plane of the triangle.
+            let dzdx = ((v1.z - v0.z) as f64 * (v2.y - v0.y)
+                - (v2.z - v0.z) as f64 * (v1.y - v0.y))
+                / area;
+            let dzdy = ((v2.z - v0.z) as f64 * (v1.x - v0.x)
+                - (v1.z - v0.z) as f64 * (v2.x - v0.x))
+                / area;
+            let max_slope = dzdx.abs().max(dzdy.abs()) as f32;
+            let (factor, units) = self.gl.polygon_offset;
+            max_slope * factor + units * (1.0 / (1 << 24) as f32)
+        } else {
+            0.0
+        };
+
+        let [clip_x0, clip_y0, clip_x1, clip_y1] = self.clip_rect;
+        let min_x = vs.iter().map(|v| v.x).fold(f64::INFINITY, f64::min);
+        let max_x = vs.iter().map(|v| v.x).fold(f64::NEG_INFINITY, f64::max);
+        let min_y = vs.iter().map(|v| v.y).fold(f64::INFINITY, f64::min);
+        let max_y = vs.iter().map(|v| v.y).fold(f64::NEG_INFINITY, f64::max);
+        let x0 = ((min_x - 0.5).floor() as i64).max(clip_x0);
+        let x1 = ((max_x + 0.5).ceil() as i64).min(clip_x1);
+        let y0 = ((min_y - 0.5).floor() as i64).max(clip_y0);
+        let y1 = ((max_y + 0.5).ceil() as i64).min(clip_y1);
+
+        // Change in each barycentric co-ordinate per pixel step.
+        let dbdx = edges.map(|(a, b)| -(b.y - a.y) / area);
+        let dbdy = edges.map(|(a, b)| (b.x - a.x) / area);
+
+        for y in y0..y1 {
+            let py = y as f64 + 0.5;
+            for x in x0..x1 {
+                let px = x as f64 + 0.5;
+                let w = edges.map(|(a, b)| edge(a, b, px, py));
+                if (0..3).any(|i| w[i] < 0.0 || (w[i] == 0.0 && !includes_boundary[i])) {
+                    continue;
+                }
+                let b = w.map(|w| w / area);
+                let z = (b[0] * v0.z as f64 + b[1] * v1.z as f64 + b[2] * v2.z as f64) as f32;
+                let z = (z + polygon_offset).clamp(0.0, 1.0);
+                let attribs = interpolate(vs, b);
+                let tex_x = self.tex_coords_at(&interpolate(vs, add3(b, dbdx)));
+                let tex_y = self.tex_coords_at(&interpolate(vs, add3(b, dbdy)));
+                self.shade_fragment(target, x as u32, y as u32, z, &attribs, &tex_x, &tex_y);
+            }
+        }
+    }
+
+    fn draw_line(&self, target: &mut Target, mut vertices: [Vertex; 2]) {
+        self.apply_shade_model(&mut vertices);
+        let Some([a, b]) = clip_line(vertices) else {
+            return;
+        };
+        let (a, b) = (self.to_window(&a, 0), self.to_window(&b, 0));
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        if dx == 0.0 && dy == 0.0 {
+            return;
+        }
+        let x_major = dx.abs() >= dy.abs();
+        let (major_a, major_delta) = if x_major { (a.x, dx) } else { (a.y, dy) };
+        let width = self.gl.line_width.round().max(1.0) as i64;
+
+        // Pixel centers along the major axis from the start point (inclusive)
+        // to the end point (exclusive).
+        let (start, end) = if major_delta > 0.0 {
+            ((major_a - 0.5).ceil(), (major_a + major_delta - 0.5).ceil())
+        } else {
+            ((major_a + major_delta - 0.5).ceil(), (major_a - 0.5).ceil())
+        };
+        let step = 1.0 / major_delta.abs();
+        let [clip_x0, clip_y0, clip_x1, clip_y1] = self.clip_rect;
+        for major in start as i64..end as i64 {
+            let t = ((major as f64 + 0.5 - major_a) / major_delta).clamp(0.0, 1.0);
+            let minor = if x_major { a.y + t * dy } else { a.x + t * dx };
+            let minor_start = (minor - width as f64 / 2.0).round() as i64;
+            let at = |t: f64| interpolate([&a, &b, &b], [1.0 - t, t, 0.0]);
+            let attribs = at(t);
+            let next = self.tex_coords_at(&at(t + step));
+            for minor in minor_start..minor_start + width {
+                let (x, y) = if x_major {
+                    (major, minor)
+                } else {
+                    (minor, major)
+                };
+                if x < clip_x0 || x >= clip_x1 || y < clip_y0 || y >= clip_y1 {
+                    continue;
+                }
+                let z = (a.z as f64 + t * (b.z - a.z) as f64) as f32;
+                self.shade_fragment(target, x as u32, y as u32, z, &attribs, &next, &next);
+            }
+        }
+    }
+
+    fn draw_point(&self, target: &mut Target, vertex: Vertex) {
+        if vertex.clip_distances().iter().any(|&d| d < 0.0) {
+            return;
+        }
+        let gl = self.gl;
+        let mut vertex = vertex;
+        let fade_threshold = gl.point_fade_threshold_size;
+        let size = if vertex.point_size < fade_threshold {
+            let fade = vertex.point_size / fade_threshold;
+            for color in vertex.colors.iter_mut() {
+                color[3] *= fade * fade;
+            }
+            fade_threshold
+        } else {
+            vertex.point_size
+        };
+        let window = self.to_window(&vertex, 0);
+        let attribs = interpolate([&window, &window, &window], [1.0, 0.0, 0.0]);
+
+        let size_int = size.round().max(1.0) as i64;
+        let (start_x, start_y) = if size_int % 2 == 1 {
+            (
+                window.x.floor() as i64 - (size_int - 1) / 2,
+                window.y.floor() as i64 - (size_int - 1) / 2,
+            )
+        } else {
+            (
+                (window.x + 0.5).floor() as i64 - size_int / 2,
+                (window.y + 0.5).floor() as i64 - size_int / 2,
+            )
+        };
+        let point_sprite = gl.is_enabled(POINT_SPRITE_OES);
+        let [clip_x0, clip_y0, clip_x1, clip_y1] = self.clip_rect;
+        for y in start_y.max(clip_y0)..(start_y + size_int).min(clip_y1) {
+            for x in start_x.max(clip_x0)..(start_x + size_int).min(clip_x1) {
+                let mut attribs = attribs;
+                let mut next_x = self.tex_coords_at(&attribs);
+                let mut next_y = next_x;
+                for (i, unit) in gl.texture_units.iter().enumerate() {
+                    if !(point_sprite && unit.coord_replace) {
+                        continue;
+                    }
+                    let size = size as f64;
+                    let s = |x: f64| (0.5 + (x + 0.5 - window.x) / size) as f32;
+                    let t = |y: f64| (0.5 - (y + 0.5 - window.y) / size) as f32;
+                    let (x, y) = (x as f64, y as f64);
+                    attribs[4 + i * 4..][..4].copy_from_slice(&[s(x), t(y), 0.0, 1.0]);
+                    next_x[i] = [s(x + 1.0), t(y)];
+                    next_y[i] = [s(x), t(y + 1.0)];
+                }
+                self.shade_fragment(
+                    target, x as u32, y as u32, window.z, &attribs, &next_x, &next_y,
+                );
+            }
+        }
+    }
+
+    /// Texture co-ordinates after division by q from interpolated attributes.
+    fn tex_coords_at(&self, attribs: &[f32; ATTRIB_COUNT]) -> [[f32; 2]; TEXTURE_UNITS] {
+        std::array::from_fn(|i| {
+            let [s, t, _r, q] = attribs[4 + i * 4..][..4].try_into().unwrap();
+            if q != 0.0 && q != 1.0 {
+                [s / q, t / q]
+            } else {
+                [s, t]
+            }
+        })
+    }
+
+    /// Compute the final color of a fragment and perform the per-fragment
+    /// operations. `next_x` and `next_y` are the texture co-ordinates at the
+    /// neighboring pixels, used to choose the mipmap level.
+    #[allow(clippy::too_many_arguments)]
+    fn shade_fragment(
+        &self,
+        target: &mut Target,
+        x: u32,
+        y: u32,
+        z: f32,
+        attribs: &[f32; ATTRIB_COUNT],
+        next_x: &[[f32; 2]; TEXTURE_UNITS],
+        next_y: &[[f32; 2]; TEXTURE_UNITS],
+    ) {
+        let tex_coords = self.tex_coords_at(attribs);
+        let lambdas = std::array::from_fn(|i| {
+            let Some((texture, _)) = self.textures[i] else {
+                return 0.0;
+            };
+            let base = &texture.level(0).unwrap().texels;
+            let (width, height) = (base.width as f32, base.height as f32);
+            let [s, t] = tex_coords[i];
+            let (dudx, dvdx) = ((next_x[i][0] - s) * width, (next_x[i][1] - t) * height);
+            let (dudy, dvdy) = ((next_y[i][0] - s) * width, (next_y[i][1] - t) * height);
+            let rho = (dudx * dudx + dvdx * dvdx)
+                .sqrt()
+                .max((dudy * dudy + dvdy * dvdy).sqrt());
+            rho.log2()
+        });
+        let fragment = Fragment {
+            x,
+            y,
+            z,
+            color: attribs[..4].try_into().unwrap(),
+            tex_coords,
+            lambdas,
+            fog_coord: attribs[FOG_ATTRIB],
+        };
+        self.process_fragment(target, fragment);
+    }
+
+    fn process_fragment(&self, target: &mut Target, fragment: Fragment) {
+        let gl = self.gl;
+        let primary = fragment.color.map(|c| c.clamp(0.0, 1.0));
+
+        // Texturing
+        let mut color = primary;
+        for (i, texture) in self.textures.iter().enumerate() {
+            let Some((texture, level_count)) = *texture else {
+                continue;
+            };
+            let unit = &gl.texture_units[i];
+            let [s, t] = fragment.tex_coords[i];
+            let lambda = fragment.lambdas[i] + unit.lod_bias;
+            let texel = texture.sample(s, t, lambda, level_count);
+            let base_format = texture.level(0).unwrap().base_format;
+            color = apply_tex_env(&unit.env, base_format, color, primary, texel);
+        }
+
+        // Fog
+        if gl.is_enabled(gles11::FOG) {
+            let c = fragment.fog_coord;
+            let f = match gl.fog_mode {
+                gles11::LINEAR => {
+                    // Avoid a division by zero when start and end are equal,
+                    // like the GLES1-on-GL2 layer does (needed by Doom 2 RPG).
+                    let end = gl.fog_end;
+                    let start = if gl.fog_start == end {
+                        end - 0.001
+                    } else {
+                        gl.fog_start
+                    };
+                    (end - c) / (end - start)
+                }
+                gles11::EXP => (-gl.fog_density * c).exp(),
+                gles11::EXP2 => (-(gl.fog_density * c).powi(2)).exp(),
+                _ => unreachable!(),
+            }
+            .clamp(0.0, 1.0);
+            for (c, fog_c) in color.iter_mut().zip(gl.fog_color).take(3) {
+                *c = f * *c + (1.0 - f) * fog_c;
+            }
+        }
+
+        // Alpha test
+        if gl.is_enabled(gles11::ALPHA_TEST)
+            && !compare(gl.alpha_func, color[3], gl.alpha_ref.clamp(0.0, 1.0))
+        {
+            return;
+        }
+
+        // Depth test
+        if gl.is_enabled(gles11::DEPTH_TEST) {
+            if let Some(ref mut depth) = target.depth {
+                let index = depth.index(fragment.x, fragment.y);
+                if !compare(gl.depth_func, fragment.z, depth.data[index]) {
+                    return;
+                }
+                if gl.depth_mask {
+                    depth.data[index] = fragment.z;
+                }
+            }
+        }
+
+        let color_has_alpha = target.color_has_alpha;
+        let Some(ref mut color_buffer) = target.color else {
+            return;
+        };
+        let index = color_buffer.index(fragment.x, fragment.y);
+        let dst = color_buffer.data[index];
+
+        // Blending
+        if gl.is_enabled(gles11::BLEND) {
+            let dst = rgba_to_float(dst);
+            let src_factor = blend_factor(gl.blend_sfactor, color, dst);
+            let dst_factor = blend_factor(gl.blend_dfactor, color, dst);
+            color = std::array::from_fn(|i| color[i] * src_factor[i] + dst[i] * dst_factor[i]);
+        }
+
+        let color = float_to_rgba(color);
+        let mut result = dst;
+        for i in 0..4 {
+            if gl.color_mask[i] {
+                result[i] = color[i];
+            }
+        }
+        if !color_has_alpha {
+            result[3] = 255;
+        }
+        color_buffer.data[index] = result;
+    }
+}
+
+fn add3(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+/// Perspective-correct interpolation of attributes using screen-space
+/// barycentric co-ordinates.
+fn interpolate(vertices: [&WindowVertex; 3], b: [f64; 3]) -> [f32; ATTRIB_COUNT] {
+    let [v0, v1, v2] = vertices;
+    let [b0, b1, b2] = b.map(|b| b as f32);
+    let inv_w = b0 * v0.inv_w + b1 * v1.inv_w + b2 * v2.inv_w;
+    std::array::from_fn(|i| (b0 * v0.attribs[i] + b1 * v1.attribs[i] + b2 * v2.attribs[i]) / inv_w)
+}
+
+/// Sutherland–Hodgman clipping of a polygon against the clip volume.
+fn clip_polygon(mut polygon: Vec<Vertex>) -> Vec<Vertex> {
+    // Fast path: most triangles are entirely inside.
+    if polygon
+        .iter()
+        .all(|v| v.clip_distances().iter().all(|&d| d >= 0.0))
+    {
+        return polygon;
+    }
+    for plane in 0..6 {
+        if polygon.is_empty() {
+            break;
+        }
+        let mut output = Vec::with_capacity(polygon.len() + 1);
+        for i in 0..polygon.len() {
+            let current = &polygon[i];
+            let next = &polygon[(i + 1) % polygon.len()];
+            let d_current = current.clip_distances()[plane];
+            let d_next = next.clip_distances()[plane];
+            if d_current >= 0.0 {
+                output.push(*current);
+            }
+            if (d_current >= 0.0) != (d_next >= 0.0) {
+                let t = d_current / (d_current - d_next);
+                output.push(current.lerp(next, t));
+            }
+        }
+        polygon = output;
+    }
+    polygon
+}
+
+/// Clipping of a line segment against the clip volume.
+fn clip_line([a, b]: [Vertex; 2]) -> Option<[Vertex; 2]> {
+    let (da, db) = (a.clip_distances(), b.clip_distances());
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for plane in 0..6 {
+        let (da, db) = (da[plane], db[plane]);
+        if da < 0.0 && db < 0.0 {
+            return None;
+        } else if da < 0.0 {
+            t0 = t0.max(da / (da - db));
+        } else if db < 0.0 {
+            t1 = t1.min(da / (da - db));
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    Some([a.lerp(&b, t0), a.lerp(&b, t1)])
+}
+
+fn compare(func: GLenum, value: f32, reference: f32) -> bool {
+    match func {
+        gles11::NEVER => false,
+        gles11::LESS => value < reference,
+        gles11::EQUAL => value == reference,
+        gles11::LEQUAL => value <= reference,
+        gles11::GREATER => value > reference,
+        gles11::NOTEQUAL => value != reference,
+        gles11::GEQUAL => value >= reference,
+        gles11::ALWAYS => true,
+        _ => unreachable!(),
+    }
+}
+
+fn blend_factor(factor: GLenum, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+    match factor {
+        gles11::ZERO => [0.0; 4],
+        gles11::ONE => [1.0; 4],
+        gles11::SRC_COLOR => src,
+        gles11::ONE_MINUS_SRC_COLOR => src.map(|c| 1.0 - c),
+        gles11::DST_COLOR => dst,
+        gles11::ONE_MINUS_DST_COLOR => dst.map(|c| 1.0 - c),
+        gles11::SRC_ALPHA => [src[3]; 4],
+        gles11::ONE_MINUS_SRC_ALPHA => [1.0 - src[3]; 4],
+        gles11::DST_ALPHA => [dst[3]; 4],
+        gles11::ONE_MINUS_DST_ALPHA => [1.0 - dst[3]; 4],
+        gles11::SRC_ALPHA_SATURATE => {
+            let f = src[3].min(1.0 - dst[3]);
+            [f, f, f, 1.0]
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Apply a texture environment function. `previous` is the result of the
+/// previous texture unit (or the primary color), and `texel` is the sampled
+/// texture color.
+fn apply_tex_env(
+    env: &TexEnv,
+    base_format: GLenum,
+    previous: [f32; 4],
+    primary: [f32; 4],
+    texel: [f32; 4],
+) -> [f32; 4] {
+    let [cp @ .., ap] = previous;
+    let [cs @ .., at] = texel;
+    let cc = [env.color[0], env.color[1], env.color[2]];
+    let ls = cs[0];
+    let rgb = |f: &dyn Fn(usize) -> f32| -> [f32; 3] { std::array::from_fn(f) };
+    let (rgb, alpha): ([f32; 3], f32) = match (env.mode, base_format) {
+        (gles11::REPLACE, gles11::ALPHA) => (cp, at),
+        (gles11::REPLACE, gles11::LUMINANCE) => ([ls; 3], ap),
+        (gles11::REPLACE, gles11::LUMINANCE_ALPHA) => ([ls; 3], at),
+        (gles11::REPLACE, gles11::RGB) => (cs, ap),
+        (gles11::REPLACE, gles11::RGBA) => (cs, at),
+        (gles11::MODULATE, gles11::ALPHA) => (cp, ap * at),
+        (gles11::MODULATE, gles11::LUMINANCE) => (rgb(&|i| cp[i] * ls), ap),
+        (gles11::MODULATE, gles11::LUMINANCE_ALPHA) => (rgb(&|i| cp[i] * ls), ap * at),
+        (gles11::MODULATE, gles11::RGB) => (rgb(&|i| cp[i] * cs[i]), ap),
+        (gles11::MODULATE, gles11::RGBA) => (rgb(&|i| cp[i] * cs[i]), ap * at),
+        (gles11::DECAL, gles11::RGB) => (cs, ap),
+        (gles11::DECAL, gles11::RGBA) => (rgb(&|i| cp[i] * (1.0 - at) + cs[i] * at), ap),
+        // The result is undefined for other formats.
+        (gles11::DECAL, _) => (cp, ap),
+        (gles11::BLEND, gles11::ALPHA) => (cp, ap * at),
+        (gles11::BLEND, gles11::LUMINANCE) => (rgb(&|i| cp[i] * (1.0 - ls) + cc[i] * ls), ap),
+        (gles11::BLEND, gles11::LUMINANCE_ALPHA) => {
+            (rgb(&|i| cp[i] * (1.0 - ls) + cc[i] * ls), ap * at)
+        }
+        (gles11::BLEND, gles11::RGB) => (rgb(&|i| cp[i] * (1.0 - cs[i]) + cc[i] * cs[i]), ap),
+        (gles11::BLEND, gles11::RGBA) => (rgb(&|i| cp[i] * (1.0 - cs[i]) + cc[i] * cs[i]), ap * at),
+        (gles11::ADD, gles11::ALPHA) => (cp, ap * at),
+        (gles11::ADD, gles11::LUMINANCE) => (rgb(&|i| cp[i] + ls), ap),
+        (gles11::ADD, gles11::LUMINANCE_ALPHA) => (rgb(&|i| cp[i] + ls), ap * at),
+        (gles11::ADD, gles11::RGB) => (rgb(&|i| cp[i] + cs[i]), ap),
+        (gles11::ADD, gles11::RGBA) => (rgb(&|i| cp[i] + cs[i]), ap * at),
+        (gles11::COMBINE, _) => return apply_combine(env, previous, primary, texel),
+        _ => unreachable!(),
+    };
+    [rgb[0], rgb[1], rgb[2], alpha].map(|c| c.clamp(0.0, 1.0))
+}
+
+/// `GL_COMBINE` texture environment function.
+fn apply_combine(env: &TexEnv, previous: [f32; 4], primary: [f32; 4], texel: [f32; 4]) -> [f32; 4] {
+    let source = |src: GLenum| match src {
+        gles11::TEXTURE => texel,
+        gles11::CONSTANT => env.color,
+        gles11::PRIMARY_COLOR => primary,
+        gles11::PREVIOUS => previous,
+        _ => unreachable!(),
+    };
+    let rgb_args: [[f32; 3]; 3] = std::array::from_fn(|i| {
+        let [r, g, b, a] = source(env.src_rgb[i]);
+        match env.operand_rgb[i] {
+            gles11::SRC_COLOR => [r, g, b],
+            gles11::ONE_MINUS_SRC_COLOR => [1.0 - r, 1.0 - g, 1.0 - b],
+            gles11::SRC_ALPHA => [a; 3],
+            gles11::ONE_MINUS_SRC_ALPHA => [1.0 - a; 3],
+            _ => unreachable!(),
+        }
+    });
+    let alpha_args: [f32; 3] = std::array::from_fn(|i| {
+        let a = source(env.src_alpha[i])[3];
+        match env.operand_alpha[i] {
+            gles11::SRC_ALPHA => a,
+            gles11::ONE_MINUS_SRC_ALPHA => 1.0 - a,
+            _ => unreachable!(),
+        }
+    });
+
+    let [a0, a1, a2] = rgb_args;
+    let mut rgb: [f32; 3] = std::array::from_fn(|i| match env.combine_rgb {
+        gles11::REPLACE => a0[i],
+        gles11::MODULATE => a0[i] * a1[i],
+        gles11::ADD => a0[i] + a1[i],
+        gles11::ADD_SIGNED => a0[i] + a1[i] - 0.5,
+        gles11::INTERPOLATE => a0[i] * a2[i] + a1[i] * (1.0 - a2[i]),
+        gles11::SUBTRACT => a0[i] - a1[i],
+        gles11::DOT3_RGB | gles11::DOT3_RGBA => {
+            4.0 * ((a0[0] - 0.5) * (a1[0] - 0.5)
+                + (a0[1] - 0.5) * (a1[1] - 0.5)
+                + (a0[2] - 0.5) * (a1[2] - 0.5))
+        }
+        _ => unreachable!(),
+    });
+    let [b0, b1, b2] = alpha_args;
+    let mut alpha = match env.combine_alpha {
+        gles11::REPLACE => b0,
+        gles11::MODULATE => b0 * b1,
+        gles11::ADD => b0 + b1,
+        gles11::ADD_SIGNED => b0 + b1 - 0.5,
+        gles11::INTERPOLATE => b0 * b2 + b1 * (1.0 - b2),
+        gles11::SUBTRACT => b0 - b1,
+        _ => unreachable!(),
+    };
+    rgb = rgb.map(|c| c * env.rgb_scale);
+    if env.combine_rgb == gles11::DOT3_RGBA {
+        alpha = rgb[0];
+    } else {
+        alpha *= env.alpha_scale;
+    }
+    [rgb[0], rgb[1], rgb[2], alpha].map(|c| c.clamp(0.0, 1.0))
+}
@@ -0,0 +1,435 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Pixel storage, texture objects, pixel format conversion and texture
+//! sampling for the software renderer.
+
+use super::super::gles11_raw as gles11; // constants only
+use super::super::gles11_raw::types::*;
+
+/// RGBA color with 8 bits per channel, the format used for all color data.
+pub type Rgba = [u8; 4];
+
+/// 2D array of pixels, with rows in bottom-to-top order like OpenGL.
+#[derive(Clone, Default)]
+pub struct Plane<T> {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<T>,
+}
+impl<T: Copy + Default> Plane<T> {
+    pub fn new(width: u32, height: u32) -> Self {
+        Plane {
+            width,
+            height,
+            data: vec![T::default(); width as usize * height as usize],
+        }
+    }
+    pub fn filled(width: u32, height: u32, value: T) -> Self {
+        Plane {
+            width,
+            height,
+            data: vec![value; width as usize * height as usize],
+        }
+    }
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+    pub fn get(&self, x: u32, y: u32) -> T {
+        self.data[self.index(x, y)]
+    }
+    pub fn set(&mut self, x: u32, y: u32, value: T) {
+        let index = self.index(x, y);
+        self.data[index] = value;
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+pub fn rgba_to_float(rgba: Rgba) -> [f32; 4] {
+    rgba.map(|c| c as f32 / 255.0)
+}
+pub fn float_to_rgba(color: [f32; 4]) -> Rgba {
+    color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// Convert a color to the form it would have if stored in a texture with a
+/// particular base format, e.g. `GL_LUMINANCE` only keeps the red channel.
+pub fn convert_to_base_format(rgba: Rgba, base_format: GLenum) -> Rgba {
+    let [r, g, b, a] = rgba;
+    match base_format {
+        gles11::ALPHA => [0, 0, 0, a],
+        gles11::LUMINANCE => [r, r, r, 255],
+        gles11::LUMINANCE_ALPHA => [r, r, r, a],
+        gles11::RGB => [r, g, b, 255],
+        gles11::RGBA => [r, g, b, a],
+        _ => unreachable!(),
+    }
+}
+
+/// Convert an internal format from `glTexImage2D` or similar to a base format,
+/// or return [None] if it's not valid.
+pub fn base_format_for_internalformat(internalformat: GLenum) -> Option<GLenum> {
+    match internalformat {
+        gles11::ALPHA
+        | gles11::LUMINANCE
+        | gles11::LUMINANCE_ALPHA
+        | gles11::RGB
+        | gles11::RGBA => Some(internalformat),
+        // EXT_texture_format_BGRA8888: the storage order is our business.
+        gles11::BGRA_EXT => Some(gles11::RGBA),
+        _ => None,
+    }
+}
+
+/// Get the size in bytes of a pixel with a `glTexImage2D`-style format and
+/// type, or return [None] if the combination isn't valid.
+pub fn bytes_per_pixel(format: GLenum, type_: GLenum) -> Option<usize> {
+    match (format, type_) {
+        (gles11::ALPHA | gles11::LUMINANCE, gles11::UNSIGNED_BYTE) => Some(1),
+        (gles11::LUMINANCE_ALPHA, gles11::UNSIGNED_BYTE) => Some(2),
+        (gles11::RGB, gles11::UNSIGNED_BYTE) => Some(3),
+        (gles11::RGBA | gles11::BGRA_EXT, gles11::UNSIGNED_BYTE) => Some(4),
+        (gles11::RGB, gles11::UNSIGNED_SHORT_5_6_5) => Some(2),
+        (gles11::RGBA, gles11::UNSIGNED_SHORT_4_4_4_4 | gles11::UNSIGNED_SHORT_5_5_5_1) => Some(2),
+        _ => None,
+    }
+}
+
+/// Size in bytes of a row of pixels in client memory, including padding.
+pub fn row_size(width: u32, bytes_per_pixel: usize, alignment: GLint) -> usize {
+    let alignment = alignment as usize;
+    (width as usize * bytes_per_pixel).div_ceil(alignment) * alignment
+}
+
+/// Convert pixels in client memory to RGBA. `pixels` must be valid for the
+/// size implied by the other arguments (see [bytes_per_pixel] and
+/// [row_size]). The format and type must be valid.
+pub unsafe fn unpack_pixels(
+    width: u32,
+    height: u32,
+    format: GLenum,
+    type_: GLenum,
+    alignment: GLint,
+    pixels: *const GLvoid,
+) -> Vec<Rgba> {
+    let bpp = bytes_per_pixel(format, type_).unwrap();
+    let row_size = row_size(width, bpp, alignment);
+    let pixels: *const u8 = pixels.cast();
+    let mut res = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height as usize {
+        let row = std::slice::from_raw_parts(pixels.add(y * row_size), width as usize * bpp);
+        for p in row.chunks_exact(bpp) {
+            let u16_value = || u16::from_ne_bytes([p[0], p[1]]);
+            // Scale an n-bit value to 8 bits.
+            let scale = |value: u16, bits: u32| -> u8 {
+                let max = (1u32 << bits) - 1;
+                ((value as u32 * 255 + max / 2) / max) as u8
+            };
+            res.push(match (format, type_) {
+                (gles11::ALPHA, _) => [0, 0, 0, p[0]],
+                (gles11::LUMINANCE, _) => [p[0], p[0], p[0], 255],
+                (gles11::LUMINANCE_ALPHA, _) => [p[0], p[0], p[0], p[1]],
+                (gles11::RGB, gles11::UNSIGNED_BYTE) => [p[0], p[1], p[2], 255],
+                (gles11::RGBA, gles11::UNSIGNED_BYTE) => [p[0], p[1], p[2], p[3]],
+                (gles11::BGRA_EXT, _) => [p[2], p[1], p[0], p[3]],
+                (_, gles11::UNSIGNED_SHORT_5_6_5) => {
+                    let v = u16_value();
+                    [
+                        scale(v >> 11, 5),
+                        scale((v >> 5) & 0x3f, 6),
+                        scale(v & 0x1f, 5),
+                        255,
+                    ]
+                }
+                (_, gles11::UNSIGNED_SHORT_4_4_4_4) => {
+                    let v = u16_value();
+                    [
+                        scale(v >> 12, 4),
+                        scale((v >> 8) & 0xf, 4),
+                        scale((v >> 4) & 0xf, 4),
+                        scale(v & 0xf, 4),
+                    ]
+                }
+                (_, gles11::UNSIGNED_SHORT_5_5_5_1) => {
+                    let v = u16_value();
+                    [
+                        scale(v >> 11, 5),
+                        scale((v >> 6) & 0x1f, 5),
+                        scale((v >> 1) & 0x1f, 5),
+                        scale(v & 1, 1),
+                    ]
+                }
+                _ => unreachable!(),
+            });
+        }
+    }
+    res
+}
+
+/// One mipmap level of a texture.
+#[derive(Clone)]
+pub struct TextureLevel {
+    pub base_format: GLenum,
+    pub texels: Plane<Rgba>,
+}
+
+/// A texture object. Only `GL_TEXTURE_2D` exists in OpenGL ES 1.1.
+pub struct Texture {
+    pub levels: Vec<Option<TextureLevel>>,
+    pub min_filter: GLenum,
+    pub mag_filter: GLenum,
+    pub wrap_s: GLenum,
+    pub wrap_t: GLenum,
+    pub generate_mipmap: bool,
+    /// Accepted for EXT_texture_filter_anisotropic, but not used.
+    pub max_anisotropy: GLfloat,
+}
+impl Default for Texture {
+    fn default() -> Self {
+        Texture {
+            levels: Vec::new(),
+            min_filter: gles11::NEAREST_MIPMAP_LINEAR,
+            mag_filter: gles11::LINEAR,
+            wrap_s: gles11::REPEAT,
+            wrap_t: gles11::REPEAT,
+            generate_mipmap: false,
+            max_anisotropy: 1.0,
+        }
+    }
+}
+impl Texture {
+    pub fn level(&self, level: usize) -> Option<&TextureLevel> {
+        self.levels.get(level).and_then(|level| level.as_ref())
+    }
+    pub fn level_mut(&mut self, level: usize) -> Option<&mut TextureLevel> {
+        self.levels.get_mut(level).and_then(|level| level.as_mut())
+    }
+
+    pub fn set_level(&mut self, level: usize, data: TextureLevel) {
+        if self.levels.len() <= level {
+            self.levels.resize(level + 1, None);
+        }
+        self.levels[level] = Some(data);
+        if level == 0 && self.generate_mipmap {
+            self.generate_mipmaps();
+        }
+    }
+
+    fn uses_mipmaps(&self) -> bool {
+        !matches!(self.min_filter, gles11::NEAREST | gles11::LINEAR)
+    }
+
+    /// Number of levels that are used for sampling, or 0 if the texture is
+    /// incomplete (in which case texturing is disabled for the unit it's bound
+    /// to).
+    pub fn complete_level_count(&self) -> usize {
+        let Some(base) = self.level(0) else {
+            return 0;
+        };
+        let (mut width, mut height) = (base.texels.width, base.texels.height);
+        if width == 0 || height == 0 {
+            return 0;
+        }
+        if !self.uses_mipmaps() {
+            return 1;
+        }
+        let mut count = 1;
+        while width > 1 || height > 1 {
+            width = (width / 2).max(1);
+            height = (height / 2).max(1);
+            match self.level(count) {
+                Some(level)
+                    if level.base_format == base.base_format
+                        && level.texels.width == width
+                        && level.texels.height == height => {}
+                _ => return 0,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Replace all levels other than the base level with ones generated by
+    /// downscaling it.
+    pub fn generate_mipmaps(&mut self) {
+        let Some(base) = self.level(0).cloned() else {
+            return;
+        };
+        self.levels.truncate(1);
+        let mut prev = base;
+        while prev.texels.width > 1 || prev.texels.height > 1 {
+            let width = (prev.texels.width / 2).max(1);
+            let height = (prev.texels.height / 2).max(1);
+            let mut texels = Plane::new(width, height);
+            for y in 0..height {
+                for x in 0..width {
+                    let mut sum = [0u32; 4];
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let sx = (x * 2 + dx).min(prev.texels.width - 1);
+                        let sy = (y * 2 + dy).min(prev.texels.height - 1);
+                        let texel = prev.texels.get(sx, sy);
+                        for i in 0..4 {
+                            sum[i] += texel[i] as u32;
+                        }
+                    }
+                    texels.set(x, y, sum.map(|c| ((c + 2) / 4) as u8));
+                }
+            }
+            let level = TextureLevel {
+                base_format: prev.base_format,
+                texels,
+            };
+            self.levels.push(Some(level.clone()));
+            prev = level;
+        }
+    }
+
+    /// Sample the texture at normalized co-ordinates `(s, t)` with a level of
+    /// detail `lambda` (log2 of the scale factor). `level_count` is from
+    /// [Self::complete_level_count] and must be non-zero.
+    pub fn sample(&self, s: f32, t: f32, lambda: f32, level_count: usize) -> [f32; 4] {
+        // The level-of-detail threshold between magnification and
+        // minification.
+        let c = if self.mag_filter == gles11::LINEAR
+            && matches!(
+                self.min_filter,
+                gles11::NEAREST_MIPMAP_NEAREST | gles11::NEAREST_MIPMAP_LINEAR
+            ) {
+            0.5
+        } else {
+            0.0
+        };
+        if lambda <= c {
+            return self.sample_level(0, s, t, self.mag_filter == gles11::LINEAR);
+        }
+        let max_level = (level_count - 1) as f32;
+        match self.min_filter {
+            gles11::NEAREST => self.sample_level(0, s, t, false),
+            gles11::LINEAR => self.sample_level(0, s, t, true),
+            gles11::NEAREST_MIPMAP_NEAREST | gles11::LINEAR_MIPMAP_NEAREST => {
+                let level = if lambda <= 0.5 {
+                    0.0
+                } else {
+                    ((lambda + 0.5).ceil() - 1.0).min(max_level)
+                };
+                let linear = self.min_filter == gles11::LINEAR_MIPMAP_NEAREST;
+                self.sample_level(level as usize, s, t, linear)
+            }
+            gles11::NEAREST_MIPMAP_LINEAR | gles11::LINEAR_MIPMAP_LINEAR => {
+                let linear = self.min_filter == gles11::LINEAR_MIPMAP_LINEAR;
+                let lambda = lambda.min(max_level);
+                let level1 = lambda.floor();
+                let level2 = (level1 + 1.0).min(max_level);
+                let color1 = self.sample_level(level1 as usize, s, t, linear);
+                if level2 == level1 {
+                    return color1;
+                }
+                let color2 = self.sample_level(level2 as usize, s, t, linear);
+                let frac = lambda - level1;
+                std::array::from_fn(|i| color1[i] * (1.0 - frac) + color2[i] * frac)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn sample_level(&self, level: usize, s: f32, t: f32, linear: bool) -> [f32; 4] {
+        let texels = &self.level(level).unwrap().texels;
+        let u = s * texels.width as f32;
+        let v = t * texels.height as f32;
+        if !linear {
+            let x = wrap(u.floor() as i64, texels.width, self.wrap_s);
+            let y = wrap(v.floor() as i64, texels.height, self.wrap_t);
+            return rgba_to_float(texels.get(x, y));
+        }
+        let (u, v) = (u - 0.5, v - 0.5);
+        let (x0, y0) = (u.floor(), v.floor());
+        let (alpha, beta) = (u - x0, v - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let x1 = wrap(x0 + 1, texels.width, self.wrap_s);
+        let y1 = wrap(y0 + 1, texels.height, self.wrap_t);
+        let x0 = wrap(x0, texels.width, self.wrap_s);
+        let y0 = wrap(y0, texels.height, self.wrap_t);
+        let t00 = rgba_to_float(texels.get(x0, y0));
+        let t10 = rgba_to_float(texels.get(x1, y0));
+        let t01 = rgba_to_float(texels.get(x0, y1));
+        let t11 = rgba_to_float(texels.get(x1, y1));
+        std::array::from_fn(|i| {
+            (1.0 - alpha) * (1.0 - beta) * t00[i]
+                + alpha * (1.0 - beta) * t10[i]
+                + (1.0 - alpha) * beta * t01[i]
+                + alpha * beta * t11[i]
+        })
+    }
+}
+
+fn wrap(coord: i64, size: u32, mode: GLenum) -> u32 {
+    match mode {
+        gles11::REPEAT => coord.rem_euclid(size as i64) as u32,
+        gles11::CLAMP_TO_EDGE => coord.clamp(0, size as i64 - 1) as u32,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpack_565() {
+        let pixels: [u16; 2] = [0xf800, 0x07e0];
+        let rgba = unsafe {
+            unpack_pixels(
+                2,
+                1,
+                gles11::RGB,
+                gles11::UNSIGNED_SHORT_5_6_5,
+                4,
+                pixels.as_ptr().cast(),
+            )
+        };
+        assert_eq!(rgba, vec![[255, 0, 0, 255], [0, 255, 0, 255]]);
+    }
+
+    #[test]
+    fn mipmaps_and_sampling() {
+        let mut texture = Texture {
+            min_filter: gles11::NEAREST_MIPMAP_NEAREST,
+            mag_filter: gles11::NEAREST,
+            ..Default::default()
+        };
+        let mut texels = Plane::new(2, 2);
+        texels.data = vec![
+            [0, 0, 0, 255],
+            [255, 255, 255, 255],
+            [255, 255, 255, 255],
+            [0, 0, 0, 255],
+        ];
+        texture.set_level(
+            0,
+            TextureLevel {
+                base_format: gles11::RGBA,
+                texels,
+            },
+        );
+        assert_eq!(texture.complete_level_count(), 0);
+        texture.generate_mipmaps();
+        assert_eq!(texture.complete_level_count(), 2);
+        let level_count = 2;
+        assert_eq!(
+            texture.sample(0.75, 0.25, 0.0, level_count),
+            [1.0, 1.0, 1.0, 1.0]
+        );
+        // Averaged level 1, rounded to nearest.
+        let expected = [128.0 / 255.0, 128.0 / 255.0, 128.0 / 255.0, 1.0];
+        assert_eq!(texture.sample(0.75, 0.25, 1.0, level_count), expected);
+        // Wrapping
+        assert_eq!(
+            texture.sample(1.75, -0.75, 0.0, level_count),
+            [1.0, 1.0, 1.0, 1.0]
+        );
+    }
+}
@@ -9,6 +9,10 @@
 use super::gles11_raw as gles11; // constants and types only
 use super::GLES;
 use crate::matrix::Matrix;
+use std::fs::File;
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
 pub struct FpsCounter {
@@ -38,6 +42,112 @@ impl FpsCounter {
     }
 }
 
+/// Writes presented frames to PNG files, for `--dump-frames=`.
+///
+/// An index, `frames.txt`, is also written, with a line for each dumped frame
+/// giving the file name and the app's uptime (see
+/// [crate::clock::Clock::uptime]) in seconds when it was presented, or `-` for
+/// frames presented before the app started (the splash screen). This lets
+/// frames be found by time, which is useful with `--virtual-clock`.
+pub struct FrameDumper {
+    dir: PathBuf,
+    interval: NonZeroU32,
+    frames: u32,
+    index: Option<File>,
+}
+impl FrameDumper {
+    pub fn new(dir: PathBuf, interval: NonZeroU32) -> Self {
+        FrameDumper {
+            dir,
+            interval,
+            frames: 0,
+            index: None,
+        }
+    }
+
+    /// Count a frame that has just been presented with [present_frame] and, if
+    /// it's one that should be dumped, read the area within the viewport back
+    /// from the default framebuffer and write it to a PNG file.
+    ///
+    /// The provided context must be current and the default framebuffer must
+    /// be bound.
+    pub unsafe fn count_frame(
+        &mut self,
+        gles: &mut dyn GLES,
+        viewport: (u32, u32, u32, u32),
+        uptime: Option<Duration>,
+    ) {
+        let frame = self.frames;
+        self.frames += 1;
+        if frame % self.interval != 0 {
+            return;
+        }
+
+        let (_, _, width, height) = viewport;
+        let flipped = read_frame(gles, viewport);
+
+        let name = format!("frame_{:06}.png", frame);
+        let path = self.dir.join(&name);
+        let result = crate::image::encode_png(&flipped, (width, height)).and_then(|png| {
+            std::fs::create_dir_all(&self.dir)
+                .and_then(|_| std::fs::write(&path, png))
+                .and_then(|_| {
+                    let index = match self.index {
+                        Some(ref mut index) => index,
+                        None => self
+                            .index
+                            .insert(File::create(self.dir.join("frames.txt"))?),
+                    };
+                    match uptime {
+                        Some(uptime) => writeln!(index, "{} {:.6}", name, uptime.as_secs_f64()),
+                        None => writeln!(index, "{} -", name),
+                    }
+                })
+                .map_err(|e| e.to_string())
+        });
+        match result {
+            Ok(()) => {
+                log_dbg!("Dumped frame to {}", path.display());
+            }
+            Err(e) => {
+                log!("Warning: Couldn't dump frame to {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Read the area within the viewport back from the default framebuffer, as
+/// RGBA pixels in top-to-bottom row order. The alpha channel isn't meaningful
+/// for the window, so it's always 255.
+///
+/// The provided context must be current and the default framebuffer must be
+/// bound.
+pub unsafe fn read_frame(gles: &mut dyn GLES, viewport: (u32, u32, u32, u32)) -> Vec<u8> {
+    let (x, y, width, height) = viewport;
+    let mut old_pack_alignment = 0;
+    gles.GetIntegerv(gles11::PACK_ALIGNMENT, &mut old_pack_alignment);
+    gles.PixelStorei(gles11::PACK_ALIGNMENT, 1);
+    let row_size = width as usize * 4;
+    let mut pixels = vec![0u8; row_size * height as usize];
+    gles.ReadPixels(
+        x as _,
+        y as _,
+        width as _,
+        height as _,
+        gles11::RGBA,
+        gles11::UNSIGNED_BYTE,
+        pixels.as_mut_ptr() as *mut _,
+    );
+    gles.PixelStorei(gles11::PACK_ALIGNMENT, old_pack_alignment);
+
+    // OpenGL ES returns rows in bottom-to-top order.
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for row in pixels.chunks_exact(row_size).rev() {
+        flipped.extend(row.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2], 255]));
+    }
+    flipped
+}
+
 /// Present the the latest frame (e.g. the app's splash screen or rendering
 /// output), provided as a texture bound to `GL_TEXTURE_2D`, by drawing it on
 /// the window. It may be rotated, scaled and/or letterboxed as necessary. The
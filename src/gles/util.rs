@@ -181,6 +181,91 @@ pub fn try_decode_pvrtc(
     true
 }
 
+/// Helper for implementing `glCompressedTexImage2D`: if `internalformat` is
+/// one of the `OES_compressed_paletted_texture` formats, decode it and call
+/// `glTexImage2D`. Returns `true` if this is done.
+///
+/// Note that this panics rather than create GL errors for invalid use (TODO?)
+#[allow(clippy::too_many_arguments)]
+pub fn try_decode_paletted(
+    gles: &mut dyn GLES,
+    target: GLenum,
+    level: GLint,
+    internalformat: GLenum,
+    width: GLsizei,
+    height: GLsizei,
+    border: GLint,
+    data: &[u8],
+) -> bool {
+    let Some(PalettedTextureFormat {
+        index_is_nibble,
+        palette_entry_format,
+        palette_entry_type,
+    }) = PalettedTextureFormat::get_info(internalformat)
+    else {
+        return false;
+    };
+
+    // This should be invalid use? (TODO)
+    assert!(border == 0);
+
+    let palette_entry_size = match palette_entry_type {
+        gles11::UNSIGNED_BYTE => match palette_entry_format {
+            gles11::RGB => 3,
+            gles11::RGBA => 4,
+            _ => unreachable!(),
+        },
+        gles11::UNSIGNED_SHORT_5_6_5
+        | gles11::UNSIGNED_SHORT_4_4_4_4
+        | gles11::UNSIGNED_SHORT_5_5_5_1 => 2,
+        _ => unreachable!(),
+    };
+    let palette_entry_count = match index_is_nibble {
+        true => 16,
+        false => 256,
+    };
+    let palette_size = palette_entry_size * palette_entry_count;
+
+    let index_count = width as usize * height as usize;
+    let (index_word_size, index_word_count) = match index_is_nibble {
+        true => (1, (index_count + 1) / 2),
+        false => (4, (index_count + 3) / 4),
+    };
+    let indices_size = index_word_size * index_word_count;
+
+    // TODO: support multiple miplevels in one image
+    assert!(level == 0);
+    assert_eq!(data.len(), palette_size + indices_size);
+    let (palette, indices) = data.split_at(palette_size);
+
+    let mut decoded = Vec::<u8>::with_capacity(palette_entry_size * index_count);
+    for i in 0..index_count {
+        let index = if index_is_nibble {
+            (indices[i / 2] >> ((1 - (i % 2)) * 4)) & 0xf
+        } else {
+            indices[i]
+        } as usize;
+        let palette_entry = &palette[index * palette_entry_size..][..palette_entry_size];
+        decoded.extend_from_slice(palette_entry);
+    }
+    assert!(decoded.len() == palette_entry_size * index_count);
+
+    unsafe {
+        gles.TexImage2D(
+            target,
+            level,
+            palette_entry_format as _,
+            width,
+            height,
+            border,
+            palette_entry_format,
+            palette_entry_type,
+            decoded.as_ptr() as *const _,
+        )
+    };
+    true
+}
+
 pub struct PalettedTextureFormat {
     /// * `true` for 4-bit (nibble) index, 16-color palette.
     /// * `false` for 8-bit (byte) index, 256-color palette.
@@ -4,6 +4,8 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! Image decoding. Currently only supports PNG files (treated as 8-bit sRGB).
+//! There is also a PNG encoder, used for touchHLE's own output (e.g. frame
+//! dumps), not for anything the app can see.
 //!
 //! Implemented as a wrapper around the C library stb_image, since it supports
 //! "CgBI" PNG files (an Apple proprietary extension used in iPhone OS apps).
@@ -12,7 +14,8 @@
 //! format, implementing as a wrapper around their decoder from the PowerVR
 //! SDK.
 
-use std::ffi::{c_int, c_uchar, CStr};
+use crate::save_state::{Reader, SaveState, Writer};
+use std::ffi::{c_int, c_uchar, c_void, CStr};
 
 use touchHLE_pvrt_decompress_wrapper::*;
 use touchHLE_stb_image_wrapper::*;
@@ -176,6 +179,22 @@ impl Image {
     }
 }
 
+/// Images are restored as pixel data, regardless of how they were created.
+impl SaveState for Image {
+    fn save(&self, w: &mut Writer) {
+        self.dimensions.save(w);
+        self.pixels().to_vec().save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        let dimensions: (u32, u32) = SaveState::restore(r)?;
+        let pixels: Vec<u8> = SaveState::restore(r)?;
+        if dimensions.0 as usize * 4 * dimensions.1 as usize != pixels.len() {
+            return Err("Image has wrong size in save state".to_string());
+        }
+        Ok(Image::from_pixel_vec(pixels, dimensions))
+    }
+}
+
 impl Drop for Image {
     fn drop(&mut self) {
         match self.pixels {
@@ -185,6 +204,35 @@ impl Drop for Image {
     }
 }
 
+/// Encode RGBA pixel data (8 bits per channel, rows in top-to-bottom order) as
+/// a PNG file. The pixels are written as-is, so any premultiplication is kept.
+pub fn encode_png(pixels: &[u8], dimensions: (u32, u32)) -> Result<Vec<u8>, String> {
+    let (width, height) = dimensions;
+    assert!(width as usize * 4 * height as usize == pixels.len());
+
+    unsafe extern "C" fn write_to_vec(context: *mut c_void, data: *mut c_void, size: c_int) {
+        let vec = &mut *(context as *mut Vec<u8>);
+        vec.extend_from_slice(std::slice::from_raw_parts(data as *const u8, size as usize));
+    }
+
+    let mut png = Vec::new();
+    let success = unsafe {
+        stbi_write_png_to_func(
+            write_to_vec,
+            &mut png as *mut Vec<u8> as *mut c_void,
+            width.try_into().unwrap(),
+            height.try_into().unwrap(),
+            4,
+            pixels.as_ptr() as *const c_void,
+            (width * 4).try_into().unwrap(),
+        )
+    };
+    if success == 0 {
+        return Err("Could not encode PNG".to_string());
+    }
+    Ok(png)
+}
+
 /// Approximate implementation of sRGB gamma encoding.
 pub fn gamma_encode(intensity: f32) -> f32 {
     // TODO: This doesn't implement the linear section near zero.
@@ -0,0 +1,401 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Recording and deterministic replay of input events.
+//!
+//! When recording (`--record-input=`), every [Event] that reaches the app
+//! (touches, resign/terminate requests) and every accelerometer sample is
+//! written to a text file, tagged with the number of the run loop iteration
+//! (roughly: the frame) in which it was delivered. When replaying
+//! (`--replay-input=`), live input is ignored and the recorded events are
+//! delivered in the same iterations instead.
+//!
+//! The file format is line-based and meant to be human-readable and editable.
+//! Each line is the iteration number, the kind of event, and its parameters,
+//! separated by spaces. Lines starting with `#` are comments. For example:
+//!
+//! ```text
+//! # touchHLE input recording
+//! 120 TouchesDown Mouse 160 240
+//! 121 Acceleration 0 0 -1
+//! 125 TouchesUp Mouse 161.5 240
+//! 300 TouchesMove Touch:1 10 20 Touch:2 30 40
+//! ```
+//!
+//! Finger IDs are `Mouse`, `VirtualCursor`, `Touch:<number>` or
+//! `ButtonToTouch:<button name>`.
+//!
+//! Replay is only deterministic if the app itself is. Many apps are not unless
+//! `--virtual-clock` is also used, because they measure time.
+
+use crate::options::{Button, Options};
+use crate::window::{Coords, Event, FingerId};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// A recorded input, not including its iteration number.
+#[derive(Debug, PartialEq)]
+enum Input {
+    Event(Event),
+    Acceleration((f32, f32, f32)),
+}
+
+/// State for either recording input to a file or replaying it from one.
+pub enum InputRecording {
+    Recording {
+        file: BufWriter<File>,
+        iteration: u64,
+    },
+    Replaying {
+        events: VecDeque<(u64, Event)>,
+        accelerations: VecDeque<(u64, (f32, f32, f32))>,
+        iteration: u64,
+        last_acceleration: (f32, f32, f32),
+        finished: bool,
+    },
+}
+
+impl InputRecording {
+    /// Set up recording or replaying if the options request it.
+    pub fn new_from_options(options: &Options) -> Result<Option<Self>, String> {
+        match (&options.record_input_path, &options.replay_input_path) {
+            (Some(_), Some(_)) => {
+                Err("Input can't be recorded and replayed at the same time".to_string())
+            }
+            (Some(path), None) => Self::new_recording(path).map(Some),
+            (None, Some(path)) => Self::new_replaying(path).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn new_recording(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| {
+            format!(
+                "Could not create input recording file {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let mut file = BufWriter::new(file);
+        writeln!(file, "# touchHLE input recording").unwrap();
+        echo!("Recording input to {}.", path.display());
+        Ok(InputRecording::Recording { file, iteration: 0 })
+    }
+
+    fn new_replaying(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| {
+            format!(
+                "Could not open input recording file {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let inputs = parse_recording(BufReader::new(file))
+            .map_err(|e| format!("Invalid input recording {}: {}", path.display(), e))?;
+        echo!(
+            "Replaying {} recorded input(s) from {}. Live input will be ignored.",
+            inputs.len(),
+            path.display()
+        );
+        let mut events = VecDeque::new();
+        let mut accelerations = VecDeque::new();
+        for (iteration, input) in inputs {
+            match input {
+                Input::Event(event) => events.push_back((iteration, event)),
+                Input::Acceleration(acceleration) => {
+                    accelerations.push_back((iteration, acceleration))
+                }
+            }
+        }
+        Ok(InputRecording::Replaying {
+            events,
+            accelerations,
+            iteration: 0,
+            // Device lying flat on its back, see Window::get_acceleration().
+            last_acceleration: (0.0, 0.0, -1.0),
+            finished: false,
+        })
+    }
+
+    /// Advance to the next run loop iteration. Must be called once per
+    /// iteration, before any events are handled.
+    pub fn next_iteration(&mut self) {
+        match self {
+            InputRecording::Recording { file, iteration } => {
+                // Flushing once per iteration means little is lost if the app
+                // exits abruptly (e.g. with std::process::exit()).
+                file.flush().unwrap();
+                *iteration += 1;
+            }
+            InputRecording::Replaying {
+                events,
+                iteration,
+                finished,
+                ..
+            } => {
+                *iteration += 1;
+                if events.is_empty() && !*finished {
+                    *finished = true;
+                    echo!("Input replay finished at iteration {}.", iteration);
+                }
+            }
+        }
+    }
+
+    /// Handle an event coming from the window. Returns the event if it should
+    /// be handled by the app. In recording mode, the event is recorded if
+    /// relevant. In replay mode, live input events are discarded, but other
+    /// events (e.g. quitting) are let through.
+    pub fn filter_live_event(&mut self, event: Event) -> Option<Event> {
+        if !is_recordable(&event) {
+            return Some(event);
+        }
+        match self {
+            InputRecording::Recording { file, iteration } => {
+                write_input(file, *iteration, &Input::Event(event.clone()));
+                Some(event)
+            }
+            InputRecording::Replaying { .. } => {
+                log_dbg!("Ignoring live event during replay: {:?}", event);
+                None
+            }
+        }
+    }
+
+    /// In replay mode, get the next recorded event due in this iteration.
+    pub fn pop_replayed_event(&mut self) -> Option<Event> {
+        let InputRecording::Replaying {
+            events, iteration, ..
+        } = self
+        else {
+            return None;
+        };
+        let &(due, _) = events.front()?;
+        if due > *iteration {
+            return None;
+        }
+        if due < *iteration {
+            log!(
+                "Warning: Replaying event from iteration {} late, at iteration {}.",
+                due,
+                iteration
+            );
+        }
+        events.pop_front().map(|(_, event)| event)
+    }
+
+    /// Record an accelerometer sample, or in replay mode, substitute the
+    /// recorded sample for this iteration (the live sample is then ignored).
+    pub fn filter_acceleration(&mut self, live: (f32, f32, f32)) -> (f32, f32, f32) {
+        match self {
+            InputRecording::Recording { file, iteration } => {
+                write_input(file, *iteration, &Input::Acceleration(live));
+                live
+            }
+            InputRecording::Replaying {
+                accelerations,
+                iteration,
+                last_acceleration,
+                ..
+            } => {
+                // Accelerometer updates are driven by a timer, so they might
+                // not happen in exactly the same iterations they were recorded
+                // in. Use the most recent sample that is due.
+                while let Some(&(due, acceleration)) = accelerations.front() {
+                    if due > *iteration {
+                        break;
+                    }
+                    *last_acceleration = acceleration;
+                    accelerations.pop_front();
+                }
+                *last_acceleration
+            }
+        }
+    }
+}
+
+/// Whether an event is input that should be recorded and replayed.
+fn is_recordable(event: &Event) -> bool {
+    match event {
+        Event::TouchesDown(_)
+        | Event::TouchesMove(_)
+        | Event::TouchesUp(_)
+        | Event::AppWillResignActive
+        | Event::AppWillTerminate => true,
+        Event::Quit | Event::EnterDebugger | Event::SaveState | Event::LoadState => false,
+    }
+}
+
+fn write_input(file: &mut BufWriter<File>, iteration: u64, input: &Input) {
+    let line = format_input(iteration, input);
+    writeln!(file, "{}", line).expect("Could not write to input recording file");
+}
+
+fn format_finger_id(finger_id: FingerId) -> String {
+    match finger_id {
+        FingerId::Mouse => "Mouse".to_string(),
+        FingerId::VirtualCursor => "VirtualCursor".to_string(),
+        FingerId::Touch(id) => format!("Touch:{}", id),
+        FingerId::ButtonToTouch(button) => format!("ButtonToTouch:{:?}", button),
+    }
+}
+
+fn parse_finger_id(s: &str) -> Result<FingerId, String> {
+    match s.split_once(':') {
+        None if s == "Mouse" => Ok(FingerId::Mouse),
+        None if s == "VirtualCursor" => Ok(FingerId::VirtualCursor),
+        Some(("Touch", id)) => id
+            .parse()
+            .map(FingerId::Touch)
+            .map_err(|_| format!("Invalid touch ID {:?}", id)),
+        Some(("ButtonToTouch", name)) => Button::from_name(name)
+            .map(FingerId::ButtonToTouch)
+            .ok_or_else(|| format!("Invalid button {:?}", name)),
+        _ => Err(format!("Invalid finger ID {:?}", s)),
+    }
+}
+
+fn format_input(iteration: u64, input: &Input) -> String {
+    fn format_touches(kind: &str, map: &HashMap<FingerId, Coords>) -> String {
+        // Sorted so the output doesn't depend on hash map iteration order.
+        let mut touches: Vec<_> = map.iter().collect();
+        touches.sort_by_key(|&(&finger_id, _)| format_finger_id(finger_id));
+        let mut res = kind.to_string();
+        for (&finger_id, &(x, y)) in touches {
+            res.push_str(&format!(" {} {} {}", format_finger_id(finger_id), x, y));
+        }
+        res
+    }
+
+    let input = match input {
+        Input::Event(Event::TouchesDown(map)) => format_touches("TouchesDown", map),
+        Input::Event(Event::TouchesMove(map)) => format_touches("TouchesMove", map),
+        Input::Event(Event::TouchesUp(map)) => format_touches("TouchesUp", map),
+        Input::Event(Event::AppWillResignActive) => "AppWillResignActive".to_string(),
+        Input::Event(Event::AppWillTerminate) => "AppWillTerminate".to_string(),
+        Input::Event(event) => panic!("Unexpected event in recording: {:?}", event),
+        Input::Acceleration((x, y, z)) => format!("Acceleration {} {} {}", x, y, z),
+    };
+    format!("{} {}", iteration, input)
+}
+
+fn parse_input(line: &str) -> Result<(u64, Input), String> {
+    fn parse_f32(s: Option<&str>) -> Result<f32, String> {
+        let s = s.ok_or_else(|| "Missing number".to_string())?;
+        s.parse().map_err(|_| format!("Invalid number {:?}", s))
+    }
+    fn parse_touches<'a>(
+        mut parts: impl Iterator<Item = &'a str>,
+    ) -> Result<HashMap<FingerId, Coords>, String> {
+        let mut map = HashMap::new();
+        while let Some(finger_id) = parts.next() {
+            let finger_id = parse_finger_id(finger_id)?;
+            let x = parse_f32(parts.next())?;
+            let y = parse_f32(parts.next())?;
+            map.insert(finger_id, (x, y));
+        }
+        if map.is_empty() {
+            return Err("Touch event has no touches".to_string());
+        }
+        Ok(map)
+    }
+
+    let mut parts = line.split_ascii_whitespace();
+    let iteration = parts.next().unwrap();
+    let iteration: u64 = iteration
+        .parse()
+        .map_err(|_| format!("Invalid iteration number {:?}", iteration))?;
+    let kind = parts
+        .next()
+        .ok_or_else(|| "Missing event kind".to_string())?;
+    let input = match kind {
+        "TouchesDown" => Input::Event(Event::TouchesDown(parse_touches(&mut parts)?)),
+        "TouchesMove" => Input::Event(Event::TouchesMove(parse_touches(&mut parts)?)),
+        "TouchesUp" => Input::Event(Event::TouchesUp(parse_touches(&mut parts)?)),
+        "AppWillResignActive" => Input::Event(Event::AppWillResignActive),
+        "AppWillTerminate" => Input::Event(Event::AppWillTerminate),
+        "Acceleration" => {
+            let x = parse_f32(parts.next())?;
+            let y = parse_f32(parts.next())?;
+            let z = parse_f32(parts.next())?;
+            Input::Acceleration((x, y, z))
+        }
+        _ => return Err(format!("Unknown event kind {:?}", kind)),
+    };
+    if let Some(extra) = parts.next() {
+        return Err(format!("Unexpected {:?} at end of line", extra));
+    }
+    Ok((iteration, input))
+}
+
+fn parse_recording<R: BufRead>(reader: R) -> Result<VecDeque<(u64, Input)>, String> {
+    let mut inputs = VecDeque::new();
+    let mut last_iteration = 0;
+    for (line_no, line) in reader.lines().enumerate() {
+        // Line numbering usually starts from 1
+        let line_no = line_no + 1;
+
+        let line = line.map_err(|e| format!("Error while reading line {}: {}", line_no, e))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (iteration, input) =
+            parse_input(line).map_err(|e| format!("Line {}: {}", line_no, e))?;
+        if iteration < last_iteration {
+            return Err(format!("Line {}: Iteration numbers go backwards", line_no));
+        }
+        last_iteration = iteration;
+        inputs.push_back((iteration, input));
+    }
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let inputs = [
+            (
+                3,
+                Input::Event(Event::TouchesDown(HashMap::from([(
+                    FingerId::Mouse,
+                    (160.0, 240.5),
+                )]))),
+            ),
+            (4, Input::Acceleration((0.1, -0.25, -1.0))),
+            (
+                4,
+                Input::Event(Event::TouchesMove(HashMap::from([
+                    (FingerId::Touch(-7), (1.0, 2.0)),
+                    (FingerId::ButtonToTouch(Button::LeftShoulder), (3.0, 4.0)),
+                    (FingerId::VirtualCursor, (0.1, 1e-7)),
+                ]))),
+            ),
+            (9, Input::Event(Event::AppWillTerminate)),
+        ];
+        let mut text = "# comment\n\n".to_string();
+        for (iteration, input) in &inputs {
+            text.push_str(&format_input(*iteration, input));
+            text.push('\n');
+        }
+        let parsed = parse_recording(text.as_bytes()).unwrap();
+        assert!(parsed.into_iter().eq(inputs));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse_recording("1 TouchesDown".as_bytes()).is_err());
+        assert!(parse_recording("1 TouchesDown Mouse 1".as_bytes()).is_err());
+        assert!(parse_recording("1 TouchesUp Pen 1 2".as_bytes()).is_err());
+        assert!(parse_recording("x Acceleration 0 0 0".as_bytes()).is_err());
+        assert!(parse_recording("1 Acceleration 0 0 0 0".as_bytes()).is_err());
+        assert!(parse_recording("2 AppWillTerminate\n1 AppWillTerminate".as_bytes()).is_err());
+    }
+}
@@ -30,10 +30,17 @@ mod log;
 mod abi;
 mod app_picker;
 mod audio;
+mod batch_compat;
+mod breakpoints;
 mod bundle;
+mod c_api;
+mod clock;
+mod compat_report;
 mod cpu;
 mod debug;
+mod debug_console;
 mod dyld;
+pub mod embedding;
 mod environment;
 mod font;
 mod frameworks;
@@ -41,6 +48,7 @@ mod fs;
 mod gdb;
 mod gles;
 mod image;
+mod input_recording;
 mod libc;
 mod licenses;
 mod mach_o;
@@ -49,6 +57,7 @@ mod mem;
 mod objc;
 mod options;
 mod paths;
+mod save_state;
 mod stack;
 mod window;
 
@@ -114,8 +123,35 @@ Special options:
 
     --info
         Print basic information about the app bundle without running the app.
+
+    --compat-report
+        Print a JSON report of the functions, symbols, Objective-C classes and
+        selectors the app uses that touchHLE doesn't implement, without running
+        the app. The report is printed to standard output, and everything else
+        to standard error.
+
+    --batch-compat=...
+        Run every app in the specified directory, one at a time, each in a
+        separate touchHLE process with --offscreen, and print a table
+        summarizing whether each reached its first frame, crashed (and with
+        which panic message), called a missing function, or couldn't be loaded
+        because it's encrypted or for an unsupported architecture. The table is
+        printed to standard output, and everything else to standard error. Any
+        other options are passed on to each app.
+
+    --batch-compat-seconds=...
+        How long each app may run for with --batch-compat=, in seconds, before
+        it is stopped. The default is 30.
 ";
 
+/// Open an app bundle (`.app` directory or `.ipa` file) for running it.
+fn open_bundle(bundle_path: &std::path::Path) -> Result<(bundle::Bundle, fs::Fs), String> {
+    let bundle_data = fs::BundleData::open_any(bundle_path)
+        .map_err(|e| format!("Could not open app bundle: {e}"))?;
+    bundle::Bundle::new_bundle_and_fs_from_host_path(bundle_data, /* read_only_mode: */ false)
+        .map_err(|err| format!("Application bundle error: {err}. Check that the path is to an .app directory or an .ipa file."))
+}
+
 pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
     echo!("touchHLE {} — https://touchhle.org/", VERSION);
     echo!();
@@ -132,6 +168,9 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
 
     let mut bundle_path: Option<PathBuf> = None;
     let mut just_info = false;
+    let mut just_compat_report = false;
+    let mut batch_compat_dir: Option<PathBuf> = None;
+    let mut batch_compat_seconds = batch_compat::DEFAULT_TIME_BUDGET_SECS;
     let mut option_args = Vec::new();
 
     for arg in args {
@@ -144,6 +183,14 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
             return Ok(());
         } else if arg == "--info" {
             just_info = true;
+        } else if arg == "--compat-report" {
+            just_compat_report = true;
+        } else if let Some(value) = arg.strip_prefix("--batch-compat=") {
+            batch_compat_dir = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--batch-compat-seconds=") {
+            batch_compat_seconds = value
+                .parse()
+                .map_err(|_| "Invalid --batch-compat-seconds= value".to_string())?;
         // Parse an option but discard the value, to test whether it's valid.
         // We don't want to apply it immediately, because then options loaded
         // from a file would take precedence over options from the command line.
@@ -158,6 +205,17 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
         }
     }
 
+    if let Some(batch_compat_dir) = batch_compat_dir {
+        if bundle_path.is_some() {
+            return Err("--batch-compat= can't be used with an app path.".to_string());
+        }
+        return batch_compat::run(
+            &batch_compat_dir,
+            std::time::Duration::from_secs(batch_compat_seconds),
+            &option_args,
+        );
+    }
+
     let (bundle_path, env_for_salvage) = if let Some(bundle_path) = bundle_path {
         (bundle_path, None)
     } else {
@@ -167,7 +225,7 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
             let parse_result = options.parse_argument(option_arg);
             assert!(parse_result == Ok(true));
         }
-        if options.headless {
+        if options.headless || options.offscreen {
             return Err(
                 "No app specified. Use the --help flag to see command-line usage.".to_string(),
             );
@@ -187,17 +245,7 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
         log!("Warning: The bundle path has a trailing quotation mark! This often happens accidentally on Windows when tab-completing, because '\\\"' gets interpreted by Rust in the wrong way. Did you meant to write {:?}?", fixed);
     }
 
-    let bundle_data = fs::BundleData::open_any(&bundle_path)
-        .map_err(|e| format!("Could not open app bundle: {e}"))?;
-    let (bundle, fs) = match bundle::Bundle::new_bundle_and_fs_from_host_path(
-        bundle_data,
-        /* read_only_mode: */ false,
-    ) {
-        Ok(bundle) => bundle,
-        Err(err) => {
-            return Err(format!("Application bundle error: {err}. Check that the path is to an .app directory or an .ipa file."));
-        }
-    };
+    let (bundle, fs) = open_bundle(&bundle_path)?;
 
     let app_id = bundle.bundle_identifier();
     let minimum_os_version = bundle.minimum_os_version();
@@ -233,6 +281,12 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
         return Ok(());
     }
 
+    if just_compat_report {
+        let report = compat_report::generate(&bundle, &fs)?;
+        println!("{}", report);
+        return Ok(());
+    }
+
     let mut options = options::Options::default();
 
     // Apply options from files
@@ -290,6 +344,8 @@ pub fn main<T: Iterator<Item = String>>(mut args: T) -> Result<(), String> {
         assert!(parse_result == Ok(true));
     }
 
+    audio::output::set_capture_sink_from_options(&options)?;
+
     let mut env = Environment::new(bundle, fs, options, env_for_salvage)?;
     env.run();
     Ok(())
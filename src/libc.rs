@@ -11,6 +11,9 @@
 
 mod generic_char;
 
+use crate::fs::Fs;
+use crate::save_state::{Reader, SaveState, Writer};
+
 pub mod clocale;
 pub mod ctype;
 pub mod cxxabi;
@@ -53,3 +56,32 @@ pub struct State {
     errno: errno::State,
     clocale: clocale::State,
 }
+impl State {
+    /// See [crate::save_state]. Open files need the filesystem to be restored.
+    pub fn save_state(&mut self, w: &mut Writer) {
+        self.dirent.save(w);
+        self.keymgr.save(w);
+        self.posix_io.save_state(w);
+        self.pthread.save(w);
+        self.semaphore.save(w);
+        self.stdlib.save(w);
+        self.string.save(w);
+        self.time.save(w);
+        self.errno.save(w);
+        self.clocale.save(w);
+    }
+    pub fn restore_state(r: &mut Reader, fs: &mut Fs) -> Result<State, String> {
+        Ok(State {
+            dirent: SaveState::restore(r)?,
+            keymgr: SaveState::restore(r)?,
+            posix_io: posix_io::State::restore_state(r, fs)?,
+            pthread: SaveState::restore(r)?,
+            semaphore: SaveState::restore(r)?,
+            stdlib: SaveState::restore(r)?,
+            string: SaveState::restore(r)?,
+            time: SaveState::restore(r)?,
+            errno: SaveState::restore(r)?,
+            clocale: SaveState::restore(r)?,
+        })
+    }
+}
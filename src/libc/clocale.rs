@@ -11,6 +11,7 @@ use crate::dyld::FunctionExports;
 use crate::environment::Environment;
 use crate::export_c_func;
 use crate::mem::{ConstPtr, MutPtr};
+use crate::save_state::impl_SaveState_for_struct;
 
 pub type LocaleCategory = i32;
 pub const LC_ALL: LocaleCategory = 0;
@@ -25,6 +26,7 @@ pub const LC_MESSAGES: LocaleCategory = 6;
 pub struct State {
     locale: std::collections::HashMap<LocaleCategory, MutPtr<u8>>,
 }
+impl_SaveState_for_struct!(State { locale });
 
 pub fn setlocale(
     env: &mut Environment,
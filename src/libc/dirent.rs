@@ -8,6 +8,7 @@
 use crate::dyld::FunctionExports;
 use crate::fs::GuestPath;
 use crate::mem::{ConstPtr, MutPtr, Ptr, SafeRead};
+use crate::save_state::impl_SaveState_for_struct;
 use crate::{export_c_func, impl_GuestRet_for_large_struct, Environment};
 use std::collections::HashMap;
 
@@ -42,6 +43,10 @@ pub struct State {
     open_dirs: HashMap<MutPtr<DIR>, Vec<String>>,
     read_dirs: HashMap<MutPtr<DIR>, Vec<MutPtr<dirent>>>,
 }
+impl_SaveState_for_struct!(State {
+    open_dirs,
+    read_dirs
+});
 impl State {
     fn get_mut(env: &mut Environment) -> &mut Self {
         &mut env.libc_state.dirent
@@ -8,6 +8,7 @@
 use crate::dyld::FunctionExports;
 use crate::export_c_func;
 use crate::mem::{ConstPtr, MutPtr};
+use crate::save_state::impl_SaveState_for_struct;
 use crate::Environment;
 use std::io::Write;
 
@@ -20,6 +21,7 @@ pub const EINVAL: i32 = 22;
 pub struct State {
     errnos: std::collections::HashMap<crate::ThreadId, MutPtr<i32>>,
 }
+impl_SaveState_for_struct!(State { errnos });
 impl State {
     fn errno_for_thread(
         &mut self,
@@ -9,6 +9,7 @@
 
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::mem::{MutPtr, MutVoidPtr, Ptr};
+use crate::save_state::impl_SaveState_for_struct;
 use crate::{Environment, ThreadId};
 use std::collections::hash_map::{Entry, HashMap};
 
@@ -16,6 +17,7 @@ use std::collections::hash_map::{Entry, HashMap};
 pub struct State {
     processwide_ptrs: HashMap<i32, (MutVoidPtr, Option<ThreadId>)>,
 }
+impl_SaveState_for_struct!(State { processwide_ptrs });
 
 fn get_and_lock_processwide_ptr_inner(env: &mut Environment, key: i32) -> Result<MutVoidPtr, i32> {
     match env.libc_state.keymgr.processwide_ptrs.entry(key) {
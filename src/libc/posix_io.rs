@@ -9,8 +9,9 @@ pub mod stat;
 
 use crate::abi::DotDotDot;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::fs::{GuestFile, GuestOpenOptions, GuestPath};
+use crate::fs::{Fs, GuestFile, GuestOpenOptions, GuestPath, GuestPathBuf};
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestISize, GuestUSize, MutPtr, MutVoidPtr, Ptr};
+use crate::save_state::{Reader, SaveState, Writer};
 use crate::Environment;
 use std::io::{Read, Seek, SeekFrom, Write};
 
@@ -30,6 +31,63 @@ impl State {
 struct PosixFileHostObject {
     file: GuestFile,
     reached_eof: bool,
+    /// The absolute path and the options the file was opened with, so that it
+    /// can be opened again when loading a save state.
+    path: GuestPathBuf,
+    options: GuestOpenOptions,
+}
+
+/// Save states only contain each file's path and position, not its contents.
+/// The files are opened again when restoring.
+impl State {
+    pub fn save_state(&mut self, w: &mut Writer) {
+        self.files.len().save(w);
+        for file in &mut self.files {
+            file.is_some().save(w);
+            let Some(PosixFileHostObject {
+                file,
+                reached_eof,
+                path,
+                options,
+            }) = file
+            else {
+                continue;
+            };
+            path.save(w);
+            options.save(w);
+            reached_eof.save(w);
+            match file.stream_position() {
+                Ok(position) => position.save(w),
+                Err(_) => w.unsupported(format!("Position in file {:?}", path)),
+            }
+        }
+    }
+    pub fn restore_state(r: &mut Reader, fs: &mut Fs) -> Result<State, String> {
+        let count = usize::restore(r)?;
+        let mut files = Vec::with_capacity(count);
+        for _ in 0..count {
+            if !bool::restore(r)? {
+                files.push(None);
+                continue;
+            }
+            let path = GuestPathBuf::restore(r)?;
+            let options = GuestOpenOptions::restore(r)?;
+            let reached_eof = bool::restore(r)?;
+            let position = u64::restore(r)?;
+            let mut file = fs
+                .open_with_options(&path, options.for_reopening())
+                .map_err(|()| format!("Couldn't open {:?} again", path))?;
+            file.seek(SeekFrom::Start(position))
+                .map_err(|e| format!("Couldn't seek in {:?}: {}", path, e))?;
+            files.push(Some(PosixFileHostObject {
+                file,
+                reached_eof,
+                path,
+                options,
+            }));
+        }
+        Ok(State { files })
+    }
 }
 
 // TODO: stdin/stdout/stderr handling somehow
@@ -140,12 +198,14 @@ pub fn open_direct(env: &mut Environment, path: ConstPtr<u8>, flags: i32) -> Fil
     }
     let res = match env
         .fs
-        .open_with_options(GuestPath::new(&path_string), options)
+        .open_with_options(GuestPath::new(&path_string), options.clone())
     {
         Ok(file) => {
             let host_object = PosixFileHostObject {
                 file,
                 reached_eof: false,
+                path: env.fs.absolute_path(GuestPath::new(&path_string)),
+                options,
             };
 
             let idx = if let Some(free_idx) = env
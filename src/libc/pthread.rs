@@ -33,8 +33,11 @@ pub mod mutex;
 pub mod once;
 pub mod thread;
 
+use crate::save_state::impl_SaveState_for_struct;
+
 #[derive(Default)]
 pub struct State {
     key: key::State,
     thread: thread::State,
 }
+impl_SaveState_for_struct!(State { key, thread });
@@ -9,6 +9,7 @@ use crate::abi::GuestFunction;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::libc::errno::{EDEADLK, EINVAL};
 use crate::mem::{ConstPtr, MutPtr, MutVoidPtr, SafeRead};
+use crate::save_state::impl_SaveState_for_struct;
 use crate::{Environment, ThreadId};
 use std::collections::HashMap;
 
@@ -17,6 +18,10 @@ pub struct State {
     threads: HashMap<pthread_t, ThreadHostObject>,
     main_thread_object_created: bool,
 }
+impl_SaveState_for_struct!(State {
+    threads,
+    main_thread_object_created
+});
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
         &mut env.libc_state.pthread.thread
@@ -34,6 +39,11 @@ pub struct pthread_attr_t {
     _unused: [u32; 8],
 }
 unsafe impl SafeRead for pthread_attr_t {}
+impl_SaveState_for_struct!(packed pthread_attr_t {
+    magic,
+    detachstate,
+    _unused
+});
 
 const DEFAULT_ATTR: pthread_attr_t = pthread_attr_t {
     magic: MAGIC_ATTR,
@@ -57,6 +67,11 @@ struct ThreadHostObject {
     joined_by: Option<ThreadId>,
     _attr: pthread_attr_t,
 }
+impl_SaveState_for_struct!(ThreadHostObject {
+    thread_id,
+    joined_by,
+    _attr
+});
 
 /// Arbitrarily-chosen magic number for `pthread_attr_t` (not Apple's).
 const MAGIC_ATTR: u32 = u32::from_be_bytes(*b"ThAt");
@@ -9,6 +9,7 @@ use crate::dyld::{export_c_func, FunctionExports};
 use crate::libc::posix_io::stat::mode_t;
 use crate::libc::posix_io::{O_CREAT, O_EXCL};
 use crate::mem::{ConstPtr, MutPtr};
+use crate::save_state::{impl_SaveState_for_struct, Reader, SaveState, Writer};
 use crate::{Environment, ThreadId};
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
@@ -39,6 +40,73 @@ pub struct SemaphoreHostObject {
     pub waiting: HashSet<ThreadId>,
     guest_sem: Option<MutPtr<sem_t>>,
 }
+impl_SaveState_for_struct!(SemaphoreHostObject {
+    value,
+    waiting,
+    guest_sem
+});
+
+/// Named semaphores can be shared between the two maps, so each semaphore is
+/// saved once and the maps refer to it by index.
+impl SaveState for State {
+    fn save(&self, w: &mut Writer) {
+        let mut semaphores: Vec<&Rc<RefCell<SemaphoreHostObject>>> = Vec::new();
+        let mut index_of = |semaphore| {
+            if let Some(idx) = semaphores.iter().position(|&s| Rc::ptr_eq(s, semaphore)) {
+                idx
+            } else {
+                semaphores.push(semaphore);
+                semaphores.len() - 1
+            }
+        };
+        let named: Vec<(&String, usize)> = self
+            .named_semaphores
+            .iter()
+            .map(|(name, semaphore)| (name, index_of(semaphore)))
+            .collect();
+        let open: Vec<(MutPtr<sem_t>, usize)> = self
+            .open_semaphores
+            .iter()
+            .map(|(&sem, semaphore)| (sem, index_of(semaphore)))
+            .collect();
+
+        semaphores.len().save(w);
+        for semaphore in semaphores {
+            semaphore.borrow().save(w);
+        }
+        named.len().save(w);
+        for (name, idx) in named {
+            name.save(w);
+            idx.save(w);
+        }
+        open.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        let semaphores: Vec<Rc<RefCell<SemaphoreHostObject>>> =
+            Vec::<SemaphoreHostObject>::restore(r)?
+                .into_iter()
+                .map(|semaphore| Rc::new(RefCell::new(semaphore)))
+                .collect();
+        let get = |idx: usize| {
+            semaphores
+                .get(idx)
+                .cloned()
+                .ok_or_else(|| "Invalid semaphore in save state".to_string())
+        };
+        let named_semaphores = Vec::<(String, usize)>::restore(r)?
+            .into_iter()
+            .map(|(name, idx)| Ok((name, get(idx)?)))
+            .collect::<Result<_, String>>()?;
+        let open_semaphores = Vec::<(MutPtr<sem_t>, usize)>::restore(r)?
+            .into_iter()
+            .map(|(sem, idx)| Ok((sem, get(idx)?)))
+            .collect::<Result<_, String>>()?;
+        Ok(State {
+            named_semaphores,
+            open_semaphores,
+        })
+    }
+}
 
 fn sem_open(
     env: &mut Environment,
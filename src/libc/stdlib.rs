@@ -7,9 +7,11 @@
 
 use crate::abi::{CallFromHost, GuestFunction};
 use crate::dyld::{export_c_func, export_c_func_aliased, FunctionExports};
+use crate::frameworks::foundation::ns_user_defaults;
 use crate::libc::posix_io::getcwd;
 use crate::libc::string::{strcpy, strlen};
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr};
+use crate::save_state::impl_SaveState_for_struct;
 use crate::Environment;
 use std::collections::HashMap;
 use std::str::FromStr;
@@ -23,25 +25,44 @@ pub struct State {
     arc4random: u32,
     env: HashMap<Vec<u8>, MutPtr<u8>>,
 }
+impl_SaveState_for_struct!(State {
+    rand,
+    random,
+    arc4random,
+    env
+});
 
 // Sizes of zero are implementation-defined. macOS will happily give you back
 // an allocation for any of these, so presumably iPhone OS does too.
 // (touchHLE's allocator will round up allocations to at least 16 bytes.)
 
 fn malloc(env: &mut Environment, size: GuestUSize) -> MutVoidPtr {
-    env.mem.alloc(size)
+    let ptr = env.mem.alloc(size);
+    env.heap_note_alloc(ptr);
+    ptr
 }
 
 fn calloc(env: &mut Environment, count: GuestUSize, size: GuestUSize) -> MutVoidPtr {
     let total = size.checked_mul(count).unwrap();
-    env.mem.alloc(total)
+    let ptr = env.mem.alloc(total);
+    env.heap_note_alloc(ptr);
+    ptr
 }
 
 fn realloc(env: &mut Environment, ptr: MutVoidPtr, size: GuestUSize) -> MutVoidPtr {
     if ptr.is_null() {
         return malloc(env, size);
     }
-    env.mem.realloc(ptr, size)
+    if !env.heap_check_free(ptr, "realloc") {
+        log!("Treating realloc() of bad pointer {:?} as malloc().", ptr);
+        return malloc(env, size);
+    }
+    let new_ptr = env.mem.realloc(ptr, size);
+    if new_ptr != ptr {
+        env.heap_note_free(ptr);
+    }
+    env.heap_note_alloc(new_ptr);
+    new_ptr
 }
 
 fn free(env: &mut Environment, ptr: MutVoidPtr) {
@@ -49,7 +70,11 @@ fn free(env: &mut Environment, ptr: MutVoidPtr) {
         // "If ptr is a NULL pointer, no operation is performed."
         return;
     }
+    if !env.heap_check_free(ptr, "free") {
+        return;
+    }
     env.mem.free(ptr);
+    env.heap_note_free(ptr);
 }
 
 fn atexit(
@@ -181,9 +206,10 @@ fn setenv(env: &mut Environment, name: ConstPtr<u8>, value: ConstPtr<u8>, overwr
     0 // success
 }
 
-fn exit(_env: &mut Environment, exit_code: i32) {
+fn exit(env: &mut Environment, exit_code: i32) {
     echo!("App called exit(), exiting.");
-    std::process::exit(exit_code);
+    ns_user_defaults::synchronize_standard_defaults(env);
+    env.exit(exit_code);
 }
 
 fn bsearch(
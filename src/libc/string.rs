@@ -7,6 +7,7 @@
 
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr};
+use crate::save_state::impl_SaveState_for_struct;
 use crate::Environment;
 use std::cmp::Ordering;
 
@@ -16,6 +17,7 @@ use super::generic_char::GenericChar;
 pub struct State {
     strtok: Option<MutPtr<u8>>,
 }
+impl_SaveState_for_struct!(State { strtok });
 
 fn strtok(env: &mut Environment, s: MutPtr<u8>, sep: ConstPtr<u8>) -> MutPtr<u8> {
     let s = if s.is_null() {
@@ -7,8 +7,9 @@
 
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::mem::{guest_size_of, ConstPtr, MutPtr, Ptr, SafeRead};
+use crate::save_state::impl_SaveState_for_struct;
 use crate::Environment;
-use std::time::{Duration, Instant, SystemTime};
+use std::time::{Duration, SystemTime};
 
 #[derive(Default)]
 pub struct State {
@@ -17,6 +18,10 @@ pub struct State {
     /// `localtime`. The standard allows calls to either to overwrite it.
     gmtime_tmp: Option<MutPtr<tm>>,
 }
+impl_SaveState_for_struct!(State {
+    y2k38_warned,
+    gmtime_tmp
+});
 
 // time.h (C)
 
@@ -30,14 +35,13 @@ type clock_t = u64;
 const CLOCKS_PER_SEC: clock_t = 1000000;
 
 fn clock(env: &mut Environment) -> clock_t {
-    Instant::now()
-        .duration_since(env.startup_time)
-        .as_secs()
-        .wrapping_mul(CLOCKS_PER_SEC)
+    env.clock.uptime().as_secs().wrapping_mul(CLOCKS_PER_SEC)
 }
 
 fn time(env: &mut Environment, out: MutPtr<time_t>) -> time_t {
-    let time64 = SystemTime::now()
+    let time64 = env
+        .clock
+        .system_time()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs();
@@ -315,7 +319,9 @@ fn gettimeofday(
         return 0; // success
     }
 
-    let time = SystemTime::now()
+    let time = env
+        .clock
+        .system_time()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
 
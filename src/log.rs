@@ -27,6 +27,19 @@ pub fn get_log_file() -> &'static std::fs::File {
     unsafe { LOG_FILE.as_ref().unwrap() }
 }
 
+/// A function that receives all touchHLE output instead of it being printed.
+/// See [set_hook].
+pub type Hook = Box<dyn Fn(&str) + Send + Sync>;
+
+static HOOK: std::sync::RwLock<Option<Hook>> = std::sync::RwLock::new(None);
+
+/// Set a function that will receive every line of touchHLE output (i.e.
+/// everything printed with [echo], [log] or [log_dbg]) instead of it being
+/// printed, or remove it if [None] is passed. This affects the whole process.
+pub fn set_hook(hook: Option<Hook>) {
+    *HOOK.write().unwrap() = hook;
+}
+
 /// Prints a log message unconditionally. Use this for errors or warnings.
 ///
 /// The message is prefixed with the module path, so it is clear where it comes
@@ -54,32 +67,31 @@ macro_rules! log_dbg {
 /// Prefer use [log] or [log_dbg] for errors and warnings during emulation.
 macro_rules! echo {
     ($($arg:tt)+) => {
-        {
-            #[cfg(target_os = "android")]
-            {
-                let formatted_str = format!($($arg)+);
-                sdl2::log::log(&formatted_str);
-                use std::io::Write;
-                let mut log_file = $crate::log::get_log_file();
-                let _ = log_file.write_all(formatted_str.as_bytes());
-                let _ = log_file.write_all(b"\n");
-            }
-            #[cfg(not(target_os = "android"))]
-            eprintln!($($arg)+);
-        }
+        $crate::log::echo_args(format_args!($($arg)+))
     };
     () => {
-        {
-            #[cfg(target_os = "android")]
-            {
-                sdl2::log::log("");
-                use std::io::Write;
-                let _ = $crate::log::get_log_file().write_all(b"\n");
-            }
-            #[cfg(not(target_os = "android"))]
-            eprintln!("");
-        }
+        $crate::log::echo_args(format_args!(""))
+    }
+}
+
+/// Only for internal use by [echo].
+pub fn echo_args(args: std::fmt::Arguments) {
+    if let Some(ref hook) = *HOOK.read().unwrap() {
+        hook(&args.to_string());
+        return;
+    }
+
+    #[cfg(target_os = "android")]
+    {
+        let formatted_str = args.to_string();
+        sdl2::log::log(&formatted_str);
+        use std::io::Write;
+        let mut log_file = get_log_file();
+        let _ = log_file.write_all(formatted_str.as_bytes());
+        let _ = log_file.write_all(b"\n");
     }
+    #[cfg(not(target_os = "android"))]
+    eprintln!("{}", args);
 }
 
 /// Put modules to enable [log_dbg] for here, e.g. "touchHLE::mem" to see when
@@ -46,11 +46,17 @@ pub struct MachO {
     /// can look things up quickly. Thumb function symbols always have the Thumb
     /// bit set.
     pub exported_symbols: HashMap<String, u32>,
+    /// Addresses and names of symbols that are defined by the binary but not
+    /// exported. These are only useful for debugging. Thumb function symbols
+    /// always have the Thumb bit set.
+    pub local_symbols: Vec<(u32, String)>,
     /// List of addresses and names of external relocations for the dynamic
     /// linker to resolve.
     pub external_relocations: Vec<(u32, String)>,
     /// Address/program counter value for the entry point.
     pub entry_point_pc: Option<u32>,
+    /// Address of the `__TEXT` segment, which starts with the Mach-O header.
+    pub text_segment_base: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -306,6 +312,7 @@ impl MachO {
         // Info used for the result
         let mut dynamic_libraries = Vec::new();
         let mut exported_symbols = HashMap::new();
+        let mut local_symbols = Vec::new();
         let mut indirect_undef_symbols: Vec<Option<String>> = Vec::new();
         let mut external_relocations: Vec<(u32, String)> = Vec::new();
         let mut entry_point_pc: Option<u32> = None;
@@ -399,7 +406,7 @@ impl MachO {
                             }
                             if let Symbol::Defined {
                                 name: Some(name),
-                                external: true,
+                                external,
                                 entry,
                                 desc,
                                 ..
@@ -411,7 +418,11 @@ impl MachO {
                                 } else {
                                     entry
                                 };
-                                exported_symbols.insert(name.to_string(), entry);
+                                if external {
+                                    exported_symbols.insert(name.to_string(), entry);
+                                } else {
+                                    local_symbols.push((entry, name.to_string()));
+                                }
                             };
                         }
                     }
@@ -611,8 +622,10 @@ impl MachO {
             dynamic_libraries,
             sections,
             exported_symbols,
+            local_symbols,
             external_relocations,
             entry_point_pc,
+            text_segment_base,
         })
     }
 
@@ -15,9 +15,12 @@
 //! * [Memory Usage Performance Guidelines](https://developer.apple.com/library/archive/documentation/Performance/Conceptual/ManagingMemory/ManagingMemory.html)
 
 use crate::libc::wchar::wchar_t;
+use crate::save_state::{Reader, SaveState, Writer};
 
 mod allocator;
 
+pub use allocator::{AllocationState, FreedAllocation, HeapStats};
+
 /// Equivalent of `usize` for guest memory.
 pub type GuestUSize = u32;
 
@@ -58,6 +61,16 @@ impl<T, const MUT: bool> PartialEq for Ptr<T, MUT> {
     }
 }
 impl<T, const MUT: bool> Eq for Ptr<T, MUT> {}
+impl<T, const MUT: bool> PartialOrd for Ptr<T, MUT> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T, const MUT: bool> Ord for Ptr<T, MUT> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
 impl<T, const MUT: bool> std::hash::Hash for Ptr<T, MUT> {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.0.hash(state);
@@ -201,6 +214,13 @@ impl<T: SafeRead> SafeWrite for T {}
 
 type Bytes = [u8; 1 << 32];
 
+/// Memory state read from a save state by [Mem::read_state].
+pub struct SavedMem<'a> {
+    null_segment_size: VAddr,
+    allocator: allocator::Allocator,
+    contents: Vec<(VAddr, &'a [u8])>,
+}
+
 /// The type that owns the guest memory and provides accessors for it.
 pub struct Mem {
     /// This array is 4GiB in size so that it can cover the entire 32-bit
@@ -341,7 +361,8 @@ impl Mem {
     }
 
     /// Special version of [Self::bytes_at] that returns [None] rather than
-    /// panicking on failure. Only for use by [crate::gdb::GdbServer].
+    /// panicking on failure. Only for use by debuggers
+    /// ([crate::gdb::GdbServer] and [crate::debug_console]).
     pub fn get_bytes_fallible(&self, addr: ConstVoidPtr, count: GuestUSize) -> Option<&[u8]> {
         if addr.to_bits() < self.null_segment_size {
             return None;
@@ -492,6 +513,34 @@ impl Mem {
         log_dbg!("Freed {:?} ({:#x} bytes)", ptr, size);
     }
 
+    /// Enable extra bookkeeping for heap diagnostics (`--heap-diagnostics`).
+    /// Only allocations made from now on are tracked.
+    pub fn enable_heap_diagnostics(&mut self) {
+        self.allocator.enable_diagnostics();
+    }
+
+    /// Record the call site (guest return address) of an allocation made by
+    /// the app, for heap diagnostics. Does nothing if they're not enabled.
+    pub fn set_alloc_call_site(&mut self, ptr: MutVoidPtr, call_site: GuestUSize) {
+        self.allocator.set_alloc_call_site(ptr.to_bits(), call_site);
+    }
+
+    /// Record the call site (guest return address) of a free done by the app,
+    /// for heap diagnostics. Does nothing if they're not enabled.
+    pub fn set_free_call_site(&mut self, ptr: MutVoidPtr, call_site: GuestUSize) {
+        self.allocator.set_free_call_site(ptr.to_bits(), call_site);
+    }
+
+    /// Check whether `ptr` is an allocation that can be freed.
+    pub fn allocation_state(&self, ptr: ConstVoidPtr) -> AllocationState {
+        self.allocator.allocation_state(ptr.to_bits())
+    }
+
+    /// Get heap statistics. Panics if heap diagnostics aren't enabled.
+    pub fn heap_stats(&self) -> HeapStats {
+        self.allocator.stats()
+    }
+
     /// Allocate memory large enough for a value of type `T` and write the value
     /// to it. Equivalent to [Self::alloc] + [Self::write].
     pub fn alloc_and_write<T>(&mut self, value: T) -> MutPtr<T>
@@ -543,6 +592,61 @@ impl Mem {
         String::from_iter(iter)
     }
 
+    /// Save the allocator state and the contents of every allocation. Memory
+    /// outside of allocations isn't saved.
+    pub fn save_state(&self, w: &mut Writer) {
+        self.null_segment_size.save(w);
+        self.allocator.save(w);
+        for allocator::Chunk { base, size } in self.allocator.used_chunks() {
+            w.bytes(&self.bytes()[base as usize..][..size.get() as usize]);
+        }
+    }
+
+    /// Read the state saved by [Self::save_state], without applying it yet.
+    /// This is separate from [Self::restore_state] so that the rest of a save
+    /// state can be checked before memory is overwritten.
+    pub fn read_state<'a>(r: &mut Reader<'a>) -> Result<SavedMem<'a>, String> {
+        let null_segment_size = SaveState::restore(r)?;
+        let allocator: allocator::Allocator = SaveState::restore(r)?;
+        let contents = allocator
+            .used_chunks()
+            .map(|chunk| Ok((chunk.base, r.bytes(chunk.size.get() as usize)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(SavedMem {
+            null_segment_size,
+            allocator,
+            contents,
+        })
+    }
+
+    /// Restore a state read by [Self::read_state]. This is done in-place
+    /// rather than by creating a new instance, because the CPU may have a
+    /// pointer to the memory (see [Self::direct_memory_access_ptr]). Any
+    /// existing allocations are zeroed first.
+    pub fn restore_state(&mut self, saved: SavedMem) {
+        let SavedMem {
+            null_segment_size,
+            allocator,
+            contents,
+        } = saved;
+
+        let diagnostics_enabled = self.allocator.diagnostics_enabled();
+        let old_used_chunks = self.allocator.reset_and_drain_used_chunks();
+        for allocator::Chunk { base, size } in old_used_chunks {
+            self.bytes_mut()[base as usize..][..size.get() as usize].fill(0);
+        }
+
+        for (base, bytes) in contents {
+            self.bytes_mut()[base as usize..][..bytes.len()].copy_from_slice(bytes);
+        }
+        self.null_segment_size = null_segment_size;
+        self.allocator = allocator;
+        if diagnostics_enabled {
+            // The old bookkeeping doesn't apply to the restored allocations.
+            self.allocator.enable_diagnostics();
+        }
+    }
+
     /// Permanently mark a region of address space as being unusable to the
     /// memory allocator.
     pub fn reserve(&mut self, base: VAddr, size: GuestUSize) {
@@ -4,7 +4,8 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 use super::{GuestUSize, Mem, VAddr};
-use std::collections::BTreeMap;
+use crate::save_state::{restore_iter, save_iter, Reader, SaveState, Writer};
+use std::collections::{BTreeMap, HashMap};
 use std::num::NonZeroU32;
 
 /// iPhone OS's allocator always aligns to 16 bytes at minimum, and this
@@ -72,7 +73,7 @@ impl std::fmt::Debug for Chunk {
 
 #[cfg(test)]
 mod chunk_tests {
-    use super::Chunk;
+    use super::{AllocationState, Allocator, Chunk, FreedAllocation, MIN_CHUNK_SIZE};
     #[test]
     fn test() {
         assert!(Chunk::new(2, 4).contains(2));
@@ -94,6 +95,117 @@ mod chunk_tests {
         assert_eq!(Chunk::new(2, 4).trisect_by(Chunk::new(1, 2)), None);
         assert_eq!(Chunk::new(2, 4).trisect_by(Chunk::new(5, 2)), None);
     }
+
+    /// An allocator with diagnostics enabled and, like in [super::Mem], the
+    /// null page reserved.
+    fn allocator_with_diagnostics() -> Allocator {
+        let mut allocator = Allocator::new();
+        allocator.reserve(Chunk::new(0, 0x1000));
+        allocator.enable_diagnostics();
+        allocator
+    }
+
+    #[test]
+    fn diagnostics_detect_double_free() {
+        let mut allocator = allocator_with_diagnostics();
+        let a = allocator.alloc(100);
+        allocator.set_alloc_call_site(a, 0x1000);
+        assert!(matches!(
+            allocator.allocation_state(a),
+            AllocationState::Allocated
+        ));
+
+        assert_eq!(allocator.free(a), 112);
+        allocator.set_free_call_site(a, 0x2000);
+        assert!(matches!(
+            allocator.allocation_state(a),
+            AllocationState::Freed(FreedAllocation {
+                alloc_call_site: Some(0x1000),
+                free_call_site: Some(0x2000),
+            })
+        ));
+        // Freeing it again does nothing, and it's still known to be freed.
+        assert_eq!(allocator.free(a), 0);
+        assert_eq!(allocator.stats().free_count, 1);
+        assert!(matches!(
+            allocator.allocation_state(a),
+            AllocationState::Freed(_)
+        ));
+        assert!(matches!(
+            allocator.allocation_state(a + MIN_CHUNK_SIZE),
+            AllocationState::Unknown
+        ));
+    }
+
+    #[test]
+    fn diagnostics_forget_reused_frees() {
+        let mut allocator = allocator_with_diagnostics();
+        let a = allocator.alloc(16);
+        let b = allocator.alloc(16);
+        let _guard = allocator.alloc(16);
+        assert_eq!(b, a + 16);
+        let _ = allocator.free(a);
+        let _ = allocator.free(b);
+        assert!(matches!(
+            allocator.allocation_state(b),
+            AllocationState::Freed(_)
+        ));
+
+        // The new allocation covers both old ones, so neither free is
+        // remembered any more.
+        let c = allocator.alloc(32);
+        assert_eq!(c, a);
+        assert!(matches!(
+            allocator.allocation_state(a),
+            AllocationState::Allocated
+        ));
+        assert!(matches!(
+            allocator.allocation_state(b),
+            AllocationState::Unknown
+        ));
+        // So freeing the new allocation isn't mistaken for a double free.
+        assert_eq!(allocator.free(c), 32);
+        assert_eq!(allocator.stats().free_count, 3);
+    }
+
+    #[test]
+    fn diagnostics_stats() {
+        let mut allocator = Allocator::new();
+        allocator.reserve(Chunk::new(0, 0x1000));
+        // Allocations made before diagnostics are enabled aren't counted.
+        let untracked = allocator.alloc(16);
+        allocator.enable_diagnostics();
+        let initial_free_bytes = allocator.stats().free_bytes;
+
+        let a = allocator.alloc(100);
+        allocator.set_alloc_call_site(a, 0x1000);
+        let b = allocator.alloc(16);
+        allocator.set_alloc_call_site(b, 0x2000);
+        // A realloc that has to move, done the way [super::Mem::realloc] does.
+        let b2 = allocator.alloc(48);
+        allocator.set_alloc_call_site(b2, 0x3000);
+        let _ = allocator.free(b);
+        let stats = allocator.stats();
+        assert_eq!(stats.live_allocations, 2);
+        assert_eq!(stats.live_bytes, 112 + 48);
+        assert_eq!(stats.alloc_count, 3);
+        assert_eq!(stats.free_count, 1);
+        assert_eq!(stats.free_bytes, initial_free_bytes - 112 - 48);
+        assert_eq!(
+            stats.call_sites,
+            vec![(Some(0x1000), 1, 112), (Some(0x3000), 1, 48)]
+        );
+
+        let _ = allocator.free(a);
+        let _ = allocator.free(untracked);
+        let stats = allocator.stats();
+        assert_eq!(stats.live_allocations, 1);
+        assert_eq!(stats.live_bytes, 48);
+        assert_eq!(stats.alloc_count, 3);
+        assert_eq!(stats.free_count, 2);
+        assert_eq!(stats.free_bytes, initial_free_bytes + 16 - 48);
+        assert!(stats.largest_free_chunk <= stats.free_bytes as u32);
+    }
 }
 
 /// Specialized collection types. They're kept in their own module so the
@@ -134,6 +246,11 @@ mod collections {
         pub fn get_size_with_base(&self, base: VAddr) -> Option<NonZeroU32> {
             self.chunks.get(&base).copied()
         }
+        pub fn iter(&self) -> impl Iterator<Item = Chunk> + '_ {
+            self.chunks
+                .iter()
+                .map(|(&base, &size)| Chunk { base, size })
+        }
     }
 
     #[derive(Default, Debug)]
@@ -255,6 +372,53 @@ use collections::{ChunkMap, SizeBucketedChunkMap};
 pub struct Allocator {
     used_chunks: ChunkMap,
     unused_chunks: SizeBucketedChunkMap,
+    /// Present when heap diagnostics are enabled (`--heap-diagnostics`).
+    diagnostics: Option<Box<Diagnostics>>,
+}
+
+/// Extra bookkeeping for heap diagnostics. Call sites are guest return
+/// addresses, which are only known for allocations made by the app with
+/// `malloc()` and friends, and are [None] for allocations made by touchHLE
+/// itself.
+#[derive(Debug, Default)]
+struct Diagnostics {
+    /// Call site of each allocation made since diagnostics were enabled.
+    call_sites: HashMap<VAddr, Option<VAddr>>,
+    /// Allocations that have been freed, with the call sites of the allocation
+    /// and of the free. These are forgotten when the memory is allocated
+    /// again, but until then they're used to detect double frees.
+    freed: BTreeMap<VAddr, FreedAllocation>,
+    alloc_count: u64,
+    free_count: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct FreedAllocation {
+    pub alloc_call_site: Option<VAddr>,
+    pub free_call_site: Option<VAddr>,
+}
+
+/// Whether an address is the base of an allocation, see
+/// [Allocator::allocation_state].
+pub enum AllocationState {
+    Allocated,
+    /// Only reported when diagnostics are enabled.
+    Freed(FreedAllocation),
+    Unknown,
+}
+
+/// Heap statistics, see [Allocator::stats]. Only allocations made since
+/// diagnostics were enabled are counted, but the free space is always exact.
+pub struct HeapStats {
+    pub live_allocations: usize,
+    pub live_bytes: u64,
+    /// Call sites with live allocations, and their allocation count and total
+    /// size, largest total first.
+    pub call_sites: Vec<(Option<VAddr>, usize, u64)>,
+    pub alloc_count: u64,
+    pub free_count: u64,
+    pub free_bytes: u64,
+    pub largest_free_chunk: GuestUSize,
 }
 
 impl Allocator {
@@ -272,9 +436,18 @@ impl Allocator {
         Allocator {
             used_chunks,
             unused_chunks,
+            diagnostics: None,
         }
     }
 
+    pub fn enable_diagnostics(&mut self) {
+        self.diagnostics.get_or_insert_with(Default::default);
+    }
+
+    pub fn diagnostics_enabled(&self) -> bool {
+        self.diagnostics.is_some()
+    }
+
     pub fn reserve(&mut self, chunk: Chunk) {
         let mut to_trisect = None;
         for unused_chunk in self.unused_chunks.iter() {
@@ -315,9 +488,92 @@ impl Allocator {
         };
         self.used_chunks.insert(alloc);
 
+        if let Some(ref mut diagnostics) = self.diagnostics {
+            diagnostics.call_sites.insert(alloc.base, None);
+            let end = alloc.last_byte();
+            let reused: Vec<VAddr> = diagnostics
+                .freed
+                .range(alloc.base..=end)
+                .map(|(&base, _)| base)
+                .collect();
+            for base in reused {
+                diagnostics.freed.remove(&base);
+            }
+            diagnostics.alloc_count += 1;
+        }
+
         alloc.base
     }
 
+    /// Record the call site of an allocation, if diagnostics are enabled.
+    pub fn set_alloc_call_site(&mut self, base: VAddr, call_site: VAddr) {
+        if let Some(ref mut diagnostics) = self.diagnostics {
+            if let Some(entry) = diagnostics.call_sites.get_mut(&base) {
+                *entry = Some(call_site);
+            }
+        }
+    }
+
+    /// Record the call site of a free, if diagnostics are enabled.
+    pub fn set_free_call_site(&mut self, base: VAddr, call_site: VAddr) {
+        if let Some(ref mut diagnostics) = self.diagnostics {
+            if let Some(freed) = diagnostics.freed.get_mut(&base) {
+                freed.free_call_site = Some(call_site);
+            }
+        }
+    }
+
+    pub fn allocation_state(&self, base: VAddr) -> AllocationState {
+        if self.used_chunks.get_size_with_base(base).is_some() {
+            return AllocationState::Allocated;
+        }
+        match self
+            .diagnostics
+            .as_ref()
+            .and_then(|diagnostics| diagnostics.freed.get(&base))
+        {
+            Some(&freed) => AllocationState::Freed(freed),
+            None => AllocationState::Unknown,
+        }
+    }
+
+    /// Get heap statistics. Panics if diagnostics aren't enabled.
+    pub fn stats(&self) -> HeapStats {
+        let diagnostics = self.diagnostics.as_ref().unwrap();
+        let mut live_bytes = 0;
+        let mut call_sites: HashMap<Option<VAddr>, (usize, u64)> = HashMap::new();
+        for (&base, &call_site) in &diagnostics.call_sites {
+            let size = u64::from(self.used_chunks.get_size_with_base(base).unwrap().get());
+            live_bytes += size;
+            let entry = call_sites.entry(call_site).or_default();
+            entry.0 += 1;
+            entry.1 += size;
+        }
+        let mut call_sites: Vec<(Option<VAddr>, usize, u64)> = call_sites
+            .into_iter()
+            .map(|(call_site, (count, bytes))| (call_site, count, bytes))
+            .collect();
+        call_sites.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        HeapStats {
+            live_allocations: diagnostics.call_sites.len(),
+            live_bytes,
+            call_sites,
+            alloc_count: diagnostics.alloc_count,
+            free_count: diagnostics.free_count,
+            free_bytes: self
+                .unused_chunks
+                .iter()
+                .map(|chunk| u64::from(chunk.size.get()))
+                .sum(),
+            largest_free_chunk: self
+                .unused_chunks
+                .iter()
+                .map(|chunk| chunk.size.get())
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
     /// This is used for realloc
     pub fn find_allocated_size(&mut self, base: VAddr) -> GuestUSize {
         let Some(size) = self.used_chunks.get_size_with_base(base) else {
@@ -334,6 +590,20 @@ impl Allocator {
             return 0;
         };
 
+        if let Some(ref mut diagnostics) = self.diagnostics {
+            // Allocations made before diagnostics were enabled aren't tracked.
+            if let Some(alloc_call_site) = diagnostics.call_sites.remove(&base) {
+                diagnostics.freed.insert(
+                    base,
+                    FreedAllocation {
+                        alloc_call_site,
+                        free_call_site: None,
+                    },
+                );
+                diagnostics.free_count += 1;
+            }
+        }
+
         if let Some(adjacent) = self
             .unused_chunks
             .remove_with_base(freed.last_byte() + 1)
@@ -351,9 +621,53 @@ impl Allocator {
         freed.size.get()
     }
 
+    /// Iterate over the allocations in address order.
+    pub(super) fn used_chunks(&self) -> impl Iterator<Item = Chunk> + '_ {
+        self.used_chunks.iter()
+    }
+
     pub(super) fn reset_and_drain_used_chunks(&mut self) -> impl Iterator<Item = Chunk> {
         let chunks = std::mem::take(&mut self.used_chunks);
         *self = Allocator::new();
         chunks.drain()
     }
 }
+
+impl SaveState for Chunk {
+    fn save(&self, w: &mut Writer) {
+        self.base.save(w);
+        self.size.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(Chunk {
+            base: SaveState::restore(r)?,
+            size: SaveState::restore(r)?,
+        })
+    }
+}
+
+/// The unused chunks are saved in the allocator's internal order, so that it
+/// makes the same choices after restoring. Diagnostics aren't saved.
+impl SaveState for Allocator {
+    fn save(&self, w: &mut Writer) {
+        let used_chunks: Vec<Chunk> = self.used_chunks.iter().collect();
+        let unused_chunks: Vec<Chunk> = self.unused_chunks.iter().collect();
+        save_iter(w, used_chunks.len(), used_chunks.iter());
+        save_iter(w, unused_chunks.len(), unused_chunks.iter());
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        let mut used_chunks: ChunkMap = Default::default();
+        for chunk in restore_iter::<Chunk, Vec<_>>(r)? {
+            used_chunks.insert(chunk);
+        }
+        let mut unused_chunks: SizeBucketedChunkMap = Default::default();
+        for chunk in restore_iter::<Chunk, Vec<_>>(r)? {
+            unused_chunks.insert(chunk);
+        }
+        Ok(Allocator {
+            used_chunks,
+            unused_chunks,
+            diagnostics: None,
+        })
+    }
+}
@@ -19,25 +19,26 @@
 //! categories and dynamic class editing).
 
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::ConstPtr;
 use crate::MutexId;
 use std::collections::HashMap;
 
 mod classes;
+pub mod message_trace;
 mod messages;
 mod methods;
 mod objects;
 mod properties;
+mod save_state;
 mod selectors;
 mod synchronization;
 
-pub use classes::{objc_classes, Class, ClassExports, ClassTemplate};
+pub use classes::{objc_classes, Class, ClassExports, ClassTemplate, IvarInfo};
 pub use messages::{
     autorelease, msg, msg_class, msg_send, msg_send_super2, msg_super, objc_super, release, retain,
 };
 pub use methods::{HostIMP, IMP};
-pub use objects::{
-    id, impl_HostObject_with_superclass, nil, AnyHostObject, HostObject, TrivialHostObject,
-};
+pub use objects::{id, nil, AnyHostObject, HostObject, TrivialHostObject, HOST_OBJECT_RESTORERS};
 pub use selectors::{selector, SEL};
 
 use classes::{ClassHostObject, FakeClass, UnimplementedClass, CLASS_LISTS};
@@ -78,6 +79,10 @@ pub struct ObjC {
     /// Type information isn't part of the `objc_msgSend` ABI, so an alternative
     /// channel is needed.
     message_type_info: Option<(std::any::TypeId, &'static str)>,
+
+    /// Type encoding strings of guest methods, keyed by the address of their
+    /// [methods::GuestIMP] (with Thumb bit). Only used for tracing, see [message_trace].
+    guest_method_types: HashMap<u32, ConstPtr<u8>>,
 }
 
 impl ObjC {
@@ -88,6 +93,7 @@ impl ObjC {
             classes: HashMap::new(),
             sync_mutexes: HashMap::new(),
             message_type_info: None,
+            guest_method_types: HashMap::new(),
         }
     }
 }
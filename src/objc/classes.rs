@@ -94,12 +94,44 @@ struct class_rw_t {
     name: ConstPtr<u8>,
     base_methods: ConstPtr<method_list_t>,
     _base_protocols: ConstVoidPtr, // protocol list (TODO)
-    _ivars: ConstVoidPtr,          // ivar list (TODO)
+    ivars: ConstPtr<ivar_list_t>,
     _weak_ivar_layout: u32,
     _base_properties: ConstVoidPtr, // property list (TODO)
 }
 unsafe impl SafeRead for class_rw_t {}
 
+/// The layout of an instance variable list in an app binary.
+///
+/// The name and field names are based on Apple's runtime.
+#[repr(C, packed)]
+struct ivar_list_t {
+    entsize: GuestUSize,
+    count: GuestUSize,
+}
+unsafe impl SafeRead for ivar_list_t {}
+
+/// The layout of an instance variable in an app binary.
+///
+/// The name and field names are based on Apple's runtime.
+#[repr(C, packed)]
+struct ivar_t {
+    offset: ConstPtr<GuestUSize>,
+    name: ConstPtr<u8>,
+    type_: ConstPtr<u8>,
+    _alignment: u32,
+    size: GuestUSize,
+}
+unsafe impl SafeRead for ivar_t {}
+
+/// An instance variable of a class from an app binary, see
+/// [ObjC::guest_class_ivars].
+pub struct IvarInfo {
+    pub name: String,
+    pub type_encoding: String,
+    pub offset: GuestUSize,
+    pub size: GuestUSize,
+}
+
 /// The layout of a category in an app binary.
 ///
 /// The name, field names and field layout are based on what Ghidra outputs.
@@ -457,10 +489,15 @@ impl ObjC {
         })
     }
 
-    fn find_template(name: &str) -> Option<&'static ClassTemplate> {
+    pub(super) fn find_template(name: &str) -> Option<&'static ClassTemplate> {
         crate::dyld::search_lists(CLASS_LISTS, name).map(|&(_name, ref template)| template)
     }
 
+    /// Check whether there is a host implementation of a class.
+    pub fn has_host_class(name: &str) -> bool {
+        Self::find_template(name).is_some()
+    }
+
     /// For use by [crate::dyld]: get the class or metaclass referenced by an
     /// external relocation in the app binary. If we don't have an
     /// implementation of the class, a placeholder is used.
@@ -702,16 +739,124 @@ impl ObjC {
         }
     }
 
-    pub fn get_class_name(&self, class: Class) -> &str {
-        let host_object = self.get_host_object(class).unwrap();
+    /// Get the superclass of a class. This is `nil` for root classes.
+    pub fn get_superclass(&self, class: Class) -> Class {
+        let &ClassHostObject { superclass, .. } = self.borrow(class);
+        superclass
+    }
+
+    /// Like [ObjC::get_superclass], but returns [None] rather than panicking
+    /// if `class` isn't a known, implemented class. This is for debugging
+    /// purposes, where the pointer can't be trusted.
+    pub fn try_get_superclass(&self, class: Class) -> Option<Class> {
+        let host_object = self.get_host_object(class)?;
+        let &ClassHostObject { superclass, .. } = host_object.as_any().downcast_ref()?;
+        Some(superclass)
+    }
+
+    /// Get the addresses and names (e.g. `-[GameScene update:]`) of all
+    /// methods implemented by guest code, for debugging purposes. The
+    /// addresses have the Thumb bit set if applicable.
+    pub fn guest_method_symbols(&self, mem: &Mem) -> Vec<(u32, String)> {
+        let mut symbols = Vec::new();
+        for (name, &class) in &self.classes {
+            let metaclass = Self::read_isa(class, mem);
+            for (class, kind) in [(class, '-'), (metaclass, '+')] {
+                let Some(ClassHostObject { methods, .. }) = self
+                    .get_host_object(class)
+                    .and_then(|host_object| host_object.as_any().downcast_ref())
+                else {
+                    continue;
+                };
+                for (&sel, &imp) in methods {
+                    if let IMP::Guest(imp) = imp {
+                        symbols.push((
+                            imp.addr_with_thumb_bit(),
+                            format!("{}[{} {}]", kind, name, sel.as_str(mem)),
+                        ));
+                    }
+                }
+            }
+        }
+        symbols
+    }
+
+    /// Get the instance variables declared by a class from an app binary (not
+    /// including those of its superclasses), for debugging purposes. Returns
+    /// [None] if `class` isn't a class from one of the binaries.
+    pub fn guest_class_ivars(
+        &self,
+        class: Class,
+        bins: &[MachO],
+        mem: &Mem,
+    ) -> Option<Vec<IvarInfo>> {
+        // Host classes don't have a class_t, so it's important to check this.
+        let is_guest_class = bins.iter().any(|bin| {
+            bin.get_section("__objc_classlist").is_some_and(|list| {
+                let base: ConstPtr<Class> = Ptr::from_bits(list.addr);
+                (0..(list.size / 4)).any(|i| mem.read(base + i) == class)
+            })
+        });
+        if !is_guest_class {
+            return None;
+        }
+
+        let class_t { data, .. } = mem.read(class.cast());
+        let mut res = Vec::new();
+        if data.is_null() {
+            return Some(res);
+        }
+        let class_rw_t { ivars, .. } = mem.read(data);
+        if ivars.is_null() {
+            return Some(res);
+        }
+        let ivar_list_t { entsize, count } = mem.read(ivars);
+        let ivars_base_ptr: ConstPtr<ivar_t> = (ivars + 1).cast();
+        for i in 0..count {
+            let ivar_ptr: ConstPtr<ivar_t> = Ptr::from_bits(ivars_base_ptr.to_bits() + i * entsize);
+            let ivar_t {
+                offset,
+                name,
+                type_,
+                size,
+                ..
+            } = mem.read(ivar_ptr);
+            // Unnamed bitfield padding may have no offset variable.
+            if offset.is_null() || name.is_null() {
+                continue;
+            }
+            res.push(IvarInfo {
+                name: String::from_utf8_lossy(mem.cstr_at(name)).into_owned(),
+                type_encoding: if type_.is_null() {
+                    String::new()
+                } else {
+                    String::from_utf8_lossy(mem.cstr_at(type_)).into_owned()
+                },
+                // The offset is indirect so that the runtime can adjust it.
+                offset: mem.read(offset),
+                size,
+            });
+        }
+        Some(res)
+    }
+
+    /// Like [ObjC::get_class_name], but returns [None] rather than panicking
+    /// if `class` isn't a known class. This is for debugging purposes, where
+    /// the pointer can't be trusted.
+    pub fn try_get_class_name(&self, class: Class) -> Option<&str> {
+        let host_object = self.get_host_object(class)?;
         if let Some(ClassHostObject { name, .. }) = host_object.as_any().downcast_ref() {
-            name
+            Some(name)
         } else if let Some(UnimplementedClass { name, .. }) = host_object.as_any().downcast_ref() {
-            name
+            Some(name)
         } else if let Some(FakeClass { name, .. }) = host_object.as_any().downcast_ref() {
-            name
+            Some(name)
         } else {
-            panic!();
+            None
         }
     }
+
+    pub fn get_class_name(&self, class: Class) -> &str {
+        self.try_get_class_name(class).unwrap()
+    }
 }
@@ -33,6 +33,7 @@ pub const CLASS_LISTS: &[super::ClassExports] = &[
     foundation::ns_enumerator::CLASSES,
     foundation::ns_error::CLASSES,
     foundation::ns_file_manager::CLASSES,
+    foundation::ns_keyed_archiver::CLASSES,
     foundation::ns_keyed_unarchiver::CLASSES,
     foundation::ns_locale::CLASSES,
     foundation::ns_lock::CLASSES,
@@ -41,6 +42,7 @@ pub const CLASS_LISTS: &[super::ClassExports] = &[
     foundation::ns_null::CLASSES,
     foundation::ns_object::CLASSES,
     foundation::ns_process_info::CLASSES,
+    foundation::ns_property_list_serialization::CLASSES,
     foundation::ns_run_loop::CLASSES,
     foundation::ns_set::CLASSES,
     foundation::ns_string::CLASSES,
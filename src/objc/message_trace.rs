@@ -0,0 +1,684 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Tracing of Objective-C messages (`--trace-objc=`).
+//!
+//! Every message dispatched by `objc_msgSend` and friends (including messages
+//! sent by host code) that passes the filters is written to the trace file as
+//! a line like:
+//!
+//! ```text
+//! -[<MyView 0x0012a3c0> setFrame:{{0.0, 0.0}, {320.0, 480.0}}] (host, UIView)
+//!   +[<UIColor> whiteColor] (host)
+//!   => <UIColor 0x0012b040>
+//! ```
+//!
+//! Messages sent while a traced method is running are indented. The part in
+//! brackets at the end says whether the method is implemented by touchHLE
+//! (host) or the app (guest), and names the class it was found on if that
+//! isn't the receiver's class.
+//!
+//! Host method arguments and return values are shown using the Rust type of the
+//! implementation. Guest method arguments and return values are decoded using
+//! the Objective-C type encoding string of the method from the app binary.
+//!
+//! Resources:
+//! - Apple's [Type Encodings](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjCRuntimeGuide/Articles/ocrtTypeEncodings.html)
+
+use super::{id, nil, Class, ClassHostObject, ObjC, IMP, SEL};
+use crate::abi::{CallTracer, GuestArg};
+use crate::cpu::Cpu;
+use crate::environment::ThreadId;
+use crate::mem::{ConstPtr, MutVoidPtr, Ptr};
+use crate::options::{glob_match, Options};
+use crate::Environment;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+
+/// Kind of method implementation, for `--trace-objc-only=`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MethodKind {
+    Host,
+    Guest,
+}
+impl MethodKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "host" => Some(Self::Host),
+            "guest" => Some(Self::Guest),
+            _ => None,
+        }
+    }
+}
+
+/// State for writing the message trace.
+pub struct MessageTracer {
+    /// Lines are written straight away, so that the trace is complete even if
+    /// touchHLE crashes.
+    file: LineWriter<File>,
+    class_globs: Vec<String>,
+    selector_globs: Vec<String>,
+    only: Option<MethodKind>,
+    /// Nesting depth of traced messages for each thread.
+    depths: HashMap<ThreadId, usize>,
+}
+
+impl MessageTracer {
+    /// Set up tracing if the options request it.
+    pub fn new_from_options(options: &Options) -> Result<Option<Self>, String> {
+        let Some(ref path) = options.trace_objc_path else {
+            return Ok(None);
+        };
+        let file = File::create(path).map_err(|e| {
+            format!(
+                "Could not create Objective-C trace file {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(Some(MessageTracer {
+            file: LineWriter::new(file),
+            class_globs: options.trace_objc_classes.clone(),
+            selector_globs: options.trace_objc_selectors.clone(),
+            only: options.trace_objc_only,
+            depths: HashMap::new(),
+        }))
+    }
+
+    fn should_trace(&self, class_names: &[&str], selector: &str, kind: MethodKind) -> bool {
+        fn any_match(globs: &[String], names: &[&str]) -> bool {
+            globs.is_empty()
+                || globs
+                    .iter()
+                    .any(|glob| names.iter().any(|name| glob_match(glob, name)))
+        }
+        self.only.is_none_or(|only| only == kind)
+            && any_match(&self.class_globs, class_names)
+            && any_match(&self.selector_globs, &[selector])
+    }
+
+    fn write_line(&mut self, thread: ThreadId, line: &str) {
+        let depth = self.depths.get(&thread).copied().unwrap_or(0);
+        let thread_prefix = if thread == 0 {
+            String::new()
+        } else {
+            format!("[thread {}] ", thread)
+        };
+        // A failure to write the trace shouldn't stop the app.
+        let _ = writeln!(
+            self.file,
+            "{}{:indent$}{}",
+            thread_prefix,
+            "",
+            line,
+            indent = depth * 2
+        );
+    }
+
+    fn begin(&mut self, thread: ThreadId, line: &str) {
+        self.write_line(thread, line);
+        *self.depths.entry(thread).or_insert(0) += 1;
+    }
+
+    fn end(&mut self, thread: ThreadId, retval: Option<&str>) {
+        let depth = self.depths.entry(thread).or_insert(1);
+        *depth = depth.saturating_sub(1);
+        if let Some(retval) = retval {
+            self.write_line(thread, &format!("=> {}", retval));
+        }
+    }
+}
+
+/// Format a message send like `-[<NSObject 0x00001000> foo:1 bar:2]`.
+/// Arguments beyond those named by the selector (i.e. variadic ones) are
+/// appended with commas.
+fn format_message(receiver_desc: &str, is_class: bool, selector: &str, args: &[String]) -> String {
+    let mut res = format!("{}[{} ", if is_class { '+' } else { '-' }, receiver_desc);
+    let mut args_iter = args.iter();
+    if selector.contains(':') {
+        for (i, keyword) in selector.split_terminator(':').enumerate() {
+            if i > 0 {
+                res.push(' ');
+            }
+            res.push_str(keyword);
+            res.push(':');
+            res.push_str(args_iter.next().map_or("?", |arg| arg.as_str()));
+        }
+    } else {
+        res.push_str(selector);
+    }
+    for arg in args_iter {
+        res.push_str(", ");
+        res.push_str(arg);
+    }
+    res.push(']');
+    res
+}
+
+fn class_info(objc: &ObjC, class: Class) -> (&str, bool) {
+    let host_object = objc.get_host_object(class).unwrap();
+    match host_object.as_any().downcast_ref() {
+        Some(ClassHostObject {
+            name, is_metaclass, ..
+        }) => (name, *is_metaclass),
+        None => (objc.get_class_name(class), false),
+    }
+}
+
+fn describe_object(env: &Environment, object: id) -> String {
+    if object == nil {
+        "nil".to_string()
+    } else if env.objc.get_host_object(object).is_some() {
+        let class = ObjC::read_isa(object, &env.mem);
+        let (name, is_metaclass) = class_info(&env.objc, class);
+        if is_metaclass {
+            // The object is a class.
+            format!("<{}>", name)
+        } else {
+            format!("<{} {:?}>", name, object)
+        }
+    } else {
+        format!("{:?}", object)
+    }
+}
+
+/// [CallTracer] for a message handled by a host method.
+struct HostMessage {
+    thread: ThreadId,
+    receiver_desc: String,
+    is_class: bool,
+    selector: String,
+    suffix: String,
+}
+impl CallTracer for HostMessage {
+    fn trace_args(&mut self, env: &mut Environment, args: &[&dyn Debug]) {
+        // The first two arguments are always the receiver and the selector.
+        let args: Vec<String> = args
+            .iter()
+            .skip(2)
+            .map(|arg| format!("{:?}", arg))
+            .collect();
+        let line = format_message(&self.receiver_desc, self.is_class, &self.selector, &args);
+        let line = format!("{} {}", line, self.suffix);
+        env.message_tracer
+            .as_mut()
+            .unwrap()
+            .begin(self.thread, &line);
+    }
+    fn trace_return(&mut self, env: &mut Environment, retval: &dyn Debug) {
+        let retval = format!("{:?}", retval);
+        let retval = (retval != "()").then_some(retval.as_str());
+        env.message_tracer
+            .as_mut()
+            .unwrap()
+            .end(self.thread, retval);
+    }
+}
+
+/// Call a method implementation like `objc_msgSend` would, but trace the
+/// message if it passes the filters. `found_on` is the class the method was
+/// found on, `stret` is the struct return pointer, if there is one.
+pub(super) fn call_traced(
+    env: &mut Environment,
+    receiver: id,
+    selector: SEL,
+    found_on: Class,
+    imp: IMP,
+    stret: Option<MutVoidPtr>,
+) {
+    let kind = match imp {
+        IMP::Host(_) => MethodKind::Host,
+        IMP::Guest(_) => MethodKind::Guest,
+    };
+    let (receiver_desc, is_class, suffix, should_trace) = {
+        let class = ObjC::read_isa(receiver, &env.mem);
+        let (class_name, is_class) = class_info(&env.objc, class);
+        let (found_on_name, _) = class_info(&env.objc, found_on);
+        let selector = selector.as_str(&env.mem);
+        let should_trace = env.message_tracer.as_ref().unwrap().should_trace(
+            &[class_name, found_on_name],
+            selector,
+            kind,
+        );
+        let kind_name = match kind {
+            MethodKind::Host => "host",
+            MethodKind::Guest => "guest",
+        };
+        let suffix = if found_on_name == class_name {
+            format!("({})", kind_name)
+        } else {
+            format!("({}, {})", kind_name, found_on_name)
+        };
+        let receiver_desc = if is_class {
+            format!("<{}>", class_name)
+        } else {
+            format!("<{} {:?}>", class_name, receiver)
+        };
+        (receiver_desc, is_class, suffix, should_trace)
+    };
+
+    let thread = env.current_thread;
+    match imp {
+        IMP::Host(host_imp) if should_trace => {
+            let mut tracer = HostMessage {
+                thread,
+                receiver_desc,
+                is_class,
+                selector: selector.as_str(&env.mem).to_string(),
+                suffix,
+            };
+            host_imp.call_from_guest_traced(env, Some(&mut tracer))
+        }
+        IMP::Host(host_imp) => host_imp.call_from_guest(env),
+        IMP::Guest(guest_imp) if should_trace => {
+            let types = env
+                .objc
+                .guest_method_types
+                .get(&guest_imp.addr_with_thumb_bit())
+                .copied();
+            let types = types.and_then(|types| match env.mem.cstr_at_utf8(types) {
+                Ok(types) => parse_method_types(types),
+                Err(_) => None,
+            });
+
+            let args = match types {
+                Some((_, ref arg_types)) => decode_guest_args(env, arg_types, stret.is_some()),
+                None => vec!["?".to_string()],
+            };
+            let line = format_message(&receiver_desc, is_class, selector.as_str(&env.mem), &args);
+            let line = format!("{} {}", line, suffix);
+            env.message_tracer.as_mut().unwrap().begin(thread, &line);
+
+            // We can't create a new stack frame, because that would
+            // interfere with pass-through of stack arguments.
+            guest_imp.call_without_pushing_stack_frame(env);
+
+            let retval = match types {
+                Some((EncodedType::Void, _)) => None,
+                Some((ref ret_type, _)) => Some(decode_guest_return(env, ret_type, stret)),
+                None => Some("?".to_string()),
+            };
+            env.message_tracer
+                .as_mut()
+                .unwrap()
+                .end(thread, retval.as_deref());
+        }
+        IMP::Guest(guest_imp) => guest_imp.call_without_pushing_stack_frame(env),
+    }
+}
+
+/// Simplified representation of an Objective-C type encoding.
+#[derive(Debug, Clone, PartialEq)]
+enum EncodedType {
+    Void,
+    Bool,
+    Int {
+        size: u32,
+        signed: bool,
+    },
+    Float,
+    Double,
+    Object,
+    Class,
+    Selector,
+    CString,
+    /// Any pointer other than the above, including function pointers and
+    /// blocks.
+    Pointer,
+    Struct(Vec<EncodedType>),
+    Union(Vec<EncodedType>),
+    Array(u32, Box<EncodedType>),
+    /// Something we can't decode, e.g. a bitfield.
+    Unknown,
+}
+
+impl EncodedType {
+    /// Size and alignment. Note that 64-bit types only have 4-byte alignment
+    /// in Apple's ARMv6 ABI.
+    fn size_and_align(&self) -> (u32, u32) {
+        match self {
+            EncodedType::Void => (0, 1),
+            EncodedType::Bool => (1, 1),
+            &EncodedType::Int { size, .. } => (size, size.min(4)),
+            EncodedType::Float => (4, 4),
+            EncodedType::Double => (8, 4),
+            EncodedType::Object
+            | EncodedType::Class
+            | EncodedType::Selector
+            | EncodedType::CString
+            | EncodedType::Pointer
+            | EncodedType::Unknown => (4, 4),
+            EncodedType::Struct(fields) => {
+                let mut size = 0u32;
+                let mut align = 1;
+                for field in fields {
+                    let (field_size, field_align) = field.size_and_align();
+                    size = size.next_multiple_of(field_align) + field_size;
+                    align = align.max(field_align);
+                }
+                (size.next_multiple_of(align), align)
+            }
+            EncodedType::Union(fields) => {
+                let (size, align) = fields.iter().fold((0u32, 1u32), |(size, align), field| {
+                    let (field_size, field_align) = field.size_and_align();
+                    (size.max(field_size), align.max(field_align))
+                });
+                (size.next_multiple_of(align), align)
+            }
+            EncodedType::Array(count, item) => {
+                let (size, align) = item.size_and_align();
+                (size * count, align)
+            }
+        }
+    }
+}
+
+/// Parse a single type from the start of `s`, advancing it.
+fn parse_type(s: &mut &str) -> Option<EncodedType> {
+    // Skip method type qualifiers (const, in, inout, out, bycopy, byref,
+    // oneway) and atomic.
+    *s = s.trim_start_matches(['r', 'n', 'N', 'o', 'O', 'R', 'V', 'A']);
+
+    let first = s.chars().next()?;
+    *s = &s[1..];
+    let int = |size, signed| Some(EncodedType::Int { size, signed });
+    match first {
+        'v' => Some(EncodedType::Void),
+        'B' => Some(EncodedType::Bool),
+        'c' => int(1, true),
+        'C' => int(1, false),
+        's' => int(2, true),
+        'S' => int(2, false),
+        'i' | 'l' => int(4, true),
+        'I' | 'L' => int(4, false),
+        'q' => int(8, true),
+        'Q' => int(8, false),
+        'f' => Some(EncodedType::Float),
+        'd' => Some(EncodedType::Double),
+        '#' => Some(EncodedType::Class),
+        ':' => Some(EncodedType::Selector),
+        '*' => Some(EncodedType::CString),
+        '?' => Some(EncodedType::Unknown),
+        '@' => {
+            if let Some(rest) = s.strip_prefix('?') {
+                // Block
+                *s = rest;
+                Some(EncodedType::Pointer)
+            } else {
+                // The class name may follow in quotes.
+                skip_quoted(s)?;
+                Some(EncodedType::Object)
+            }
+        }
+        '^' => {
+            parse_type(s)?;
+            Some(EncodedType::Pointer)
+        }
+        'b' => {
+            skip_number(s);
+            Some(EncodedType::Unknown)
+        }
+        '[' => {
+            let count_len = s.find(|c: char| !c.is_ascii_digit())?;
+            let count = s[..count_len].parse().ok()?;
+            *s = &s[count_len..];
+            let item = parse_type(s)?;
+            *s = s.strip_prefix(']')?;
+            Some(EncodedType::Array(count, Box::new(item)))
+        }
+        '{' | '(' => {
+            let close = if first == '{' { '}' } else { ')' };
+            // The name is followed by `=` and the fields, unless the type is
+            // opaque.
+            let name_end = s.find(['=', close])?;
+            let has_fields = s[name_end..].starts_with('=');
+            *s = &s[name_end + 1..];
+            let mut fields = Vec::new();
+            if has_fields {
+                loop {
+                    if let Some(rest) = s.strip_prefix(close) {
+                        *s = rest;
+                        break;
+                    }
+                    // Field names may be present in ivar type encodings.
+                    skip_quoted(s)?;
+                    fields.push(parse_type(s)?);
+                }
+            }
+            Some(if first == '{' {
+                EncodedType::Struct(fields)
+            } else {
+                EncodedType::Union(fields)
+            })
+        }
+        _ => None,
+    }
+}
+
+fn skip_quoted(s: &mut &str) -> Option<()> {
+    if let Some(rest) = s.strip_prefix('"') {
+        let end = rest.find('"')?;
+        *s = &rest[end + 1..];
+    }
+    Some(())
+}
+
+fn skip_number(s: &mut &str) {
+    *s = s.strip_prefix('-').unwrap_or(s);
+    *s = s.trim_start_matches(|c: char| c.is_ascii_digit());
+}
+
+/// Parse a method type encoding string, e.g. `v12@0:4f8`, into the return
+/// type and the argument types (including the receiver and selector).
+fn parse_method_types(mut s: &str) -> Option<(EncodedType, Vec<EncodedType>)> {
+    let ret = parse_type(&mut s)?;
+    skip_number(&mut s);
+    let mut args = Vec::new();
+    while !s.is_empty() {
+        args.push(parse_type(&mut s)?);
+        skip_number(&mut s);
+    }
+    Some((ret, args))
+}
+
+/// Format a value that doesn't need any information from the environment to
+/// be understood. Returns [None] for other values.
+fn format_plain_value(ty: &EncodedType, bytes: &[u8]) -> Option<String> {
+    let word = |bytes: &[u8]| u32::from_le_bytes(bytes[..4].try_into().unwrap());
+    let dword = |bytes: &[u8]| u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    Some(match *ty {
+        EncodedType::Void => "void".to_string(),
+        EncodedType::Bool => (bytes[0] != 0).to_string(),
+        EncodedType::Int { size: 8, signed } => {
+            let value = dword(bytes);
+            if signed {
+                (value as i64).to_string()
+            } else {
+                value.to_string()
+            }
+        }
+        EncodedType::Int { size, signed } => {
+            let mut padded = [0u8; 4];
+            padded[..size as usize].copy_from_slice(&bytes[..size as usize]);
+            let value = u32::from_le_bytes(padded);
+            if signed {
+                // sign-extend
+                let shift = 32 - size * 8;
+                (((value << shift) as i32) >> shift).to_string()
+            } else {
+                value.to_string()
+            }
+        }
+        EncodedType::Float => format!("{:?}", f32::from_bits(word(bytes))),
+        EncodedType::Double => format!("{:?}", f64::from_bits(dword(bytes))),
+        EncodedType::Pointer | EncodedType::Unknown => format!("{:#010x}", word(bytes)),
+        _ => return None,
+    })
+}
+
+/// Format a value of a type from its in-memory representation.
+fn format_value(env: &Environment, ty: &EncodedType, bytes: &[u8]) -> String {
+    if let Some(plain) = format_plain_value(ty, bytes) {
+        return plain;
+    }
+    let word = || u32::from_le_bytes(bytes[..4].try_into().unwrap());
+    match ty {
+        EncodedType::Object | EncodedType::Class => describe_object(env, Ptr::from_bits(word())),
+        EncodedType::Selector => {
+            let sel: SEL = GuestArg::from_regs(&[word()]);
+            if sel.is_null() {
+                "NULL".to_string()
+            } else {
+                format!("@selector({})", sel.as_str(&env.mem))
+            }
+        }
+        EncodedType::CString => {
+            let ptr: ConstPtr<u8> = Ptr::from_bits(word());
+            if ptr.is_null() {
+                "NULL".to_string()
+            } else {
+                format!("{:?}", String::from_utf8_lossy(env.mem.cstr_at(ptr)))
+            }
+        }
+        EncodedType::Struct(fields) => {
+            let mut offset = 0u32;
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|field| {
+                    let (size, align) = field.size_and_align();
+                    offset = offset.next_multiple_of(align);
+                    let field_bytes = &bytes[offset as usize..];
+                    offset += size;
+                    format_value(env, field, field_bytes)
+                })
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        EncodedType::Array(count, item) => {
+            let (size, _) = item.size_and_align();
+            let items: Vec<String> = (0..*count)
+                .map(|i| format_value(env, item, &bytes[(i * size) as usize..]))
+                .collect();
+            format!("[{}]", items.join(", "))
+        }
+        EncodedType::Union(_) => "(union)".to_string(),
+        _ => unreachable!(),
+    }
+}
+
+/// Decode the arguments to a guest method (other than the receiver and
+/// selector) from the registers and stack, as they are at the time of the
+/// `objc_msgSend` call.
+fn decode_guest_args(env: &Environment, arg_types: &[EncodedType], stret: bool) -> Vec<String> {
+    let regs = env.cpu.regs();
+    let sp: ConstPtr<u32> = Ptr::from_bits(regs[Cpu::SP]);
+    let read_word = |offset: u32| {
+        if offset < 4 {
+            regs[offset as usize]
+        } else {
+            env.mem.read(sp + (offset - 4))
+        }
+    };
+
+    // Skip the struct return pointer, the receiver and the selector.
+    let mut reg_offset = u32::from(stret) + 2;
+    let mut res = Vec::new();
+    for ty in arg_types.iter().skip(2) {
+        let (size, _) = ty.size_and_align();
+        let word_count = size.div_ceil(4).max(1);
+        let bytes: Vec<u8> = (reg_offset..reg_offset + word_count)
+            .flat_map(|offset| read_word(offset).to_le_bytes())
+            .collect();
+        reg_offset += word_count;
+        res.push(format_value(env, ty, &bytes));
+    }
+    res
+}
+
+/// Decode the return value of a guest method after it has returned.
+fn decode_guest_return(
+    env: &Environment,
+    ret_type: &EncodedType,
+    stret: Option<MutVoidPtr>,
+) -> String {
+    let (size, _) = ret_type.size_and_align();
+    let bytes: Vec<u8> = if matches!(ret_type, EncodedType::Struct(_)) && size > 4 {
+        match stret {
+            Some(stret) => env.mem.bytes_at(stret.cast(), size).to_vec(),
+            None => return "?".to_string(),
+        }
+    } else {
+        let regs = env.cpu.regs();
+        [regs[0], regs[1]]
+            .iter()
+            .flat_map(|reg| reg.to_le_bytes())
+            .collect()
+    };
+    format_value(env, ret_type, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|&s| s.to_owned()).collect() };
+        assert_eq!(format_message("<A>", true, "new", &[]), "+[<A> new]");
+        assert_eq!(
+            format_message("<A 0x1>", false, "foo:bar:", &args(&["1", "2"])),
+            "-[<A 0x1> foo:1 bar:2]"
+        );
+        assert_eq!(
+            format_message("<A>", true, "arrayWithObjects:", &args(&["1", "2"])),
+            "+[<A> arrayWithObjects:1, 2]"
+        );
+        assert_eq!(format_message("<A>", true, "foo:", &[]), "+[<A> foo:?]");
+    }
+
+    #[test]
+    fn type_encodings() {
+        let cg_rect = EncodedType::Struct(vec![
+            EncodedType::Struct(vec![EncodedType::Float, EncodedType::Float]),
+            EncodedType::Struct(vec![EncodedType::Float, EncodedType::Float]),
+        ]);
+        assert_eq!(
+            parse_method_types("v24@0:4{CGRect={CGPoint=ff}{CGSize=ff}}8"),
+            Some((
+                EncodedType::Void,
+                vec![EncodedType::Object, EncodedType::Selector, cg_rect.clone()]
+            ))
+        );
+        assert_eq!(cg_rect.size_and_align(), (16, 4));
+        assert_eq!(
+            parse_method_types("@16@0:4r^{__CFString=}8@\"NSString\"12"),
+            Some((
+                EncodedType::Object,
+                vec![
+                    EncodedType::Object,
+                    EncodedType::Selector,
+                    EncodedType::Pointer,
+                    EncodedType::Object,
+                ]
+            ))
+        );
+        assert_eq!(
+            parse_method_types("Vv16@0:4q8").map(|(_, args)| args[2].size_and_align()),
+            Some((8, 4))
+        );
+        assert_eq!(
+            format_plain_value(
+                &EncodedType::Int {
+                    size: 2,
+                    signed: true
+                },
+                &[0xfe, 0xff]
+            ),
+            Some("-2".to_string())
+        );
+    }
+}
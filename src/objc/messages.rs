@@ -11,12 +11,25 @@
 //! - Mike Ash's [objc_msgSend's New Prototype](https://www.mikeash.com/pyblog/objc_msgsends-new-prototype.html)
 //! - Peter Steinberger's [Calling Super at Runtime in Swift](https://steipete.com/posts/calling-super-at-runtime/) explains `objc_msgSendSuper2`
 
-use super::{id, nil, Class, ObjC, IMP, SEL};
+use super::{id, message_trace, nil, Class, ObjC, IMP, SEL};
 use crate::abi::{CallFromHost, GuestRet};
+use crate::breakpoints;
 use crate::mem::{ConstPtr, MutVoidPtr, SafeRead};
+use crate::options::glob_match;
 use crate::Environment;
 use std::any::TypeId;
 
+/// Check whether `--stub-unimplemented-methods=` allows a message that has no
+/// implementation to be treated as if it were sent to nil. A pattern can match
+/// either the class name or the selector.
+fn may_stub_method(env: &Environment, class_name: &str, selector: SEL) -> bool {
+    let selector = selector.as_str(&env.mem);
+    env.options
+        .stub_unimplemented_methods
+        .iter()
+        .any(|glob| glob_match(glob, class_name) || glob_match(glob, selector))
+}
+
 /// The core implementation of `objc_msgSend`, the main function of Objective-C.
 ///
 /// Note that while only two parameters (usually receiver and selector) are
@@ -29,8 +42,17 @@ use std::any::TypeId;
 /// Similarly, the return value of `objc_msgSend` is whatever value is returned
 /// by the method implementation. We are relying on CallFromGuest not
 /// overwriting it.
+///
+/// `stret` is the struct return pointer passed to `objc_msgSend_stret`. It is
+/// only used for tracing (see [super::message_trace]).
 #[allow(non_snake_case)]
-fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2: Option<Class>) {
+fn objc_msgSend_inner(
+    env: &mut Environment,
+    receiver: id,
+    selector: SEL,
+    super2: Option<Class>,
+    stret: Option<MutVoidPtr>,
+) {
     let message_type_info = env.objc.message_type_info.take();
 
     if receiver == nil {
@@ -57,6 +79,21 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
                 ..
             } = class_host_object.as_any().downcast_ref().unwrap();
 
+            if may_stub_method(env, name, selector) {
+                log!(
+                    "{} {:?} ({}class \"{}\", {:?}) does not respond to selector \"{}\". \
+                     Behaving as if message was sent to nil because it is stubbed.",
+                    if is_metaclass { "Class" } else { "Object" },
+                    receiver,
+                    if is_metaclass { "meta" } else { "" },
+                    name,
+                    orig_class,
+                    selector.as_str(&env.mem),
+                );
+                env.cpu.regs_mut()[0..2].fill(0);
+                return;
+            }
+
             panic!(
                 "{} {:?} ({}class \"{}\", {:?}){} does not respond to selector \"{}\"!",
                 if is_metaclass { "Class" } else { "Object" },
@@ -76,6 +113,8 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
         let host_object = env.objc.get_host_object(class).unwrap();
 
         if let Some(&super::ClassHostObject {
+            ref name,
+            is_metaclass,
             superclass,
             ref methods,
             ..
@@ -88,32 +127,40 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
                 continue;
             }
 
-            if let Some(imp) = methods.get(&selector) {
-                match imp {
-                    IMP::Host(host_imp) => {
-                        // TODO: do type checks when calling GuestIMPs too.
-                        // That requires using Objective-C type strings, rather
-                        // than Rust types, and should probably warn rather than
-                        // panicking, because apps might rely on type punning.
-                        if let Some((sent_type_id, sent_type_desc)) = message_type_info {
-                            let (expected_type_id, expected_type_desc) = host_imp.type_info();
-                            if sent_type_id != expected_type_id {
-                                panic!(
-                                    "\
+            if let Some(&imp) = methods.get(&selector) {
+                if let IMP::Host(host_imp) = imp {
+                    // TODO: do type checks when calling GuestIMPs too.
+                    // That requires using Objective-C type strings, rather
+                    // than Rust types, and should probably warn rather than
+                    // panicking, because apps might rely on type punning.
+                    if let Some((sent_type_id, sent_type_desc)) = message_type_info {
+                        let (expected_type_id, expected_type_desc) = host_imp.type_info();
+                        if sent_type_id != expected_type_id {
+                            panic!(
+                                "\
 Type mismatch when sending message {} to {:?}!
 - Message has type: {:?} / {}
 - Method expects type: {:?} / {}",
-                                    selector.as_str(&env.mem),
-                                    receiver,
-                                    sent_type_id,
-                                    sent_type_desc,
-                                    expected_type_id,
-                                    expected_type_desc
-                                );
-                            }
+                                selector.as_str(&env.mem),
+                                receiver,
+                                sent_type_id,
+                                sent_type_desc,
+                                expected_type_id,
+                                expected_type_desc
+                            );
                         }
-                        host_imp.call_from_guest(env)
                     }
+                }
+                if !env.breakpoints.is_empty() {
+                    let name = name.clone();
+                    breakpoints::check_method(env, receiver, &name, is_metaclass, selector);
+                }
+                if env.message_tracer.is_some() {
+                    message_trace::call_traced(env, receiver, selector, class, imp, stret);
+                    return;
+                }
+                match imp {
+                    IMP::Host(host_imp) => host_imp.call_from_guest(env),
                     // We can't create a new stack frame, because that would
                     // interfere with pass-through of stack arguments.
                     IMP::Guest(guest_imp) => guest_imp.call_without_pushing_stack_frame(env),
@@ -127,6 +174,18 @@ Type mismatch when sending message {} to {:?}!
             is_metaclass,
         }) = host_object.as_any().downcast_ref()
         {
+            if may_stub_method(env, name, selector) {
+                log!(
+                    "Call to unimplemented class \"{}\" ({:?}) {} method \"{}\". \
+                     Behaving as if message was sent to nil because it is stubbed.",
+                    name,
+                    class,
+                    if is_metaclass { "class" } else { "instance" },
+                    selector.as_str(&env.mem),
+                );
+                env.cpu.regs_mut()[0..2].fill(0);
+                return;
+            }
             panic!(
                 "Class \"{}\" ({:?}) is unimplemented. Call to {} method \"{}\".",
                 name,
@@ -160,7 +219,9 @@ Type mismatch when sending message {} to {:?}!
 /// Standard variant of `objc_msgSend`. See [objc_msgSend_inner].
 #[allow(non_snake_case)]
 pub(super) fn objc_msgSend(env: &mut Environment, receiver: id, selector: SEL) {
-    objc_msgSend_inner(env, receiver, selector, /* super2: */ None)
+    objc_msgSend_inner(
+        env, receiver, selector, /* super2: */ None, /* stret: */ None,
+    )
 }
 
 /// Variant of `objc_msgSend` for methods that return a struct via a pointer.
@@ -174,11 +235,17 @@ pub(super) fn objc_msgSend(env: &mut Environment, receiver: id, selector: SEL) {
 /// appropriate `objc_msgSend` variant depending on the method it wants to call.
 pub(super) fn objc_msgSend_stret(
     env: &mut Environment,
-    _stret: MutVoidPtr,
+    stret: MutVoidPtr,
     receiver: id,
     selector: SEL,
 ) {
-    objc_msgSend_inner(env, receiver, selector, /* super2: */ None)
+    objc_msgSend_inner(
+        env,
+        receiver,
+        selector,
+        /* super2: */ None,
+        Some(stret),
+    )
 }
 
 #[repr(C, packed)]
@@ -212,7 +279,13 @@ pub(super) fn objc_msgSendSuper2(
     // Rewrite first argument to match the normal ABI.
     crate::abi::write_next_arg(&mut 0, env.cpu.regs_mut(), &mut env.mem, receiver);
 
-    objc_msgSend_inner(env, receiver, selector, /* super2: */ Some(class))
+    objc_msgSend_inner(
+        env,
+        receiver,
+        selector,
+        /* super2: */ Some(class),
+        /* stret: */ None,
+    )
 }
 
 /// Trait that assists with type-checking of [msg_send]'s arguments.
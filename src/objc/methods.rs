@@ -24,6 +24,7 @@ use std::any::TypeId;
 /// "guest methods" (functions in the guest app). Either way, the function needs
 /// to conform to the same ABI: [id] and [SEL] must be its first two parameters.
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone)]
 pub enum IMP {
     Host(&'static dyn HostIMP),
     Guest(GuestIMP),
@@ -126,12 +127,10 @@ impl ClassHostObject {
             let method_ptr: ConstPtr<method_t> =
                 Ptr::from_bits(methods_base_ptr.to_bits() + i * entsize);
 
-            // TODO: support type strings
-            let method_t {
-                name,
-                types: _,
-                imp,
-            } = mem.read(method_ptr);
+            let method_t { name, types, imp } = mem.read(method_ptr);
+            // The types aren't needed for dispatch, only for tracing.
+            objc.guest_method_types
+                .insert(imp.addr_with_thumb_bit(), types);
 
             // There is no guarantee this string is unique or known.
             // We must deduplicate it like any other.
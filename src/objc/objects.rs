@@ -22,6 +22,10 @@
 
 use super::{Class, ClassHostObject};
 use crate::mem::{guest_size_of, GuestUSize, Mem, MutPtr, Ptr, SafeRead};
+use crate::save_state::{
+    host_object_restorer, impl_HostObject_with_SaveState, impl_SaveState_for_struct,
+    HostObjectRestorers, Writer,
+};
 use std::any::Any;
 use std::num::NonZeroU32;
 
@@ -60,8 +64,8 @@ pub const nil: id = Ptr::null();
 /// If the `refcount` is `None`, that means this object has a static duration
 /// and should not be reference-counted, e.g. it is a class.
 pub(super) struct HostObjectEntry {
-    host_object: Box<dyn AnyHostObject>,
-    refcount: Option<NonZeroU32>,
+    pub(super) host_object: Box<dyn AnyHostObject>,
+    pub(super) refcount: Option<NonZeroU32>,
 }
 
 /// Type for host objects.
@@ -85,43 +89,14 @@ pub trait HostObject: Any + 'static {
     fn as_superclass_mut<'a>(&'a mut self) -> Option<&'a mut (dyn AnyHostObject + 'static)> {
         None
     }
+    /// Save the host object's name and data for a save state, or return
+    /// [false] if this kind of host object can't be saved. Don't implement this
+    /// yourself, use [crate::save_state::impl_HostObject_with_SaveState].
+    fn save_state(&self, _w: &mut Writer) -> bool {
+        false
+    }
 }
 
-/// Convenience macro for implementing [HostObject] where the host object type
-/// extends some superclass's host object type. The superclass's host object
-/// must be in a struct member named `superclass`.
-///
-/// Example usage, if `Bar` extends `Foo`:
-///
-/// ```ignore
-/// struct BarHostObject {
-///     superclass: FooHostObject,
-///     some_extra_data: id,
-/// }
-/// impl_HostObject_with_superclass!(BarHostObject);
-/// ```
-///
-/// If the superclass doesn't have a meaningful host object (e.g. `NSObject`
-/// uses [TrivialHostObject]), `impl HostObject for FooHostObject {}` suffices.
-#[macro_export]
-macro_rules! impl_HostObject_with_superclass {
-    ( $ty:ty ) => {
-        impl $crate::objc::HostObject for $ty {
-            fn as_superclass<'a>(
-                &'a self,
-            ) -> Option<&'a (dyn $crate::objc::AnyHostObject + 'static)> {
-                Some(&self.superclass)
-            }
-            fn as_superclass_mut<'a>(
-                &'a mut self,
-            ) -> Option<&'a mut (dyn $crate::objc::AnyHostObject + 'static)> {
-                Some(&mut self.superclass)
-            }
-        }
-    };
-}
-pub use crate::impl_HostObject_with_superclass; // #[macro_export] is weird...
-
 /// Trait wrapping [HostObject] with a blanket implementation to make
 /// downcasting work. Don't implement it yourself.
 ///
@@ -130,8 +105,13 @@ pub use crate::impl_HostObject_with_superclass; // #[macro_export] is weird...
 pub trait AnyHostObject: HostObject {
     fn as_any<'a>(&'a self) -> &'a (dyn Any + 'static);
     fn as_any_mut<'a>(&'a mut self) -> &'a mut (dyn Any + 'static);
+    /// Name of the Rust type, for debugging purposes.
+    fn type_name(&self) -> &'static str;
 }
 impl<T: HostObject> AnyHostObject for T {
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
     fn as_any<'a>(&'a self) -> &'a (dyn Any + 'static) {
         self
     }
@@ -142,7 +122,10 @@ impl<T: HostObject> AnyHostObject for T {
 
 /// Empty host object used by `[NSObject alloc]`.
 pub struct TrivialHostObject;
-impl HostObject for TrivialHostObject {}
+impl_HostObject_with_SaveState!(TrivialHostObject);
+impl_SaveState_for_struct!(TrivialHostObject {});
+
+pub const HOST_OBJECT_RESTORERS: HostObjectRestorers = &[host_object_restorer!(TrivialHostObject)];
 
 impl super::ObjC {
     /// Read the all-important `isa`.
@@ -275,6 +258,44 @@ impl super::ObjC {
         }
     }
 
+    /// Find all objects whose host object is exactly of type `T` (subclasses
+    /// are not included), in order of address. This is slow, so it's only for
+    /// things like save states.
+    pub fn objects_with_host_object<T: AnyHostObject + 'static>(&self) -> Vec<id> {
+        let mut objects: Vec<id> = self
+            .objects
+            .iter()
+            .filter(|(_, entry)| entry.host_object.as_any().is::<T>())
+            .map(|(&object, _)| object)
+            .collect();
+        objects.sort_by_key(|object| object.to_bits());
+        objects
+    }
+
+    /// Get every object that currently exists, with its refcount ([None] for
+    /// static objects, e.g. classes), in order of address. This is slow, so
+    /// it's only for debugging.
+    pub fn all_objects(&self) -> Vec<(id, Option<u32>)> {
+        let mut objects: Vec<(id, Option<u32>)> = self
+            .objects
+            .iter()
+            .map(|(&object, entry)| (object, entry.refcount.map(NonZeroU32::get)))
+            .collect();
+        objects.sort_by_key(|&(object, _)| object.to_bits());
+        objects
+    }
+
+    /// Get the refcount ([None] for static objects) and the name of the host
+    /// object's type for an object, or [None] if there is no such object. This
+    /// is for debugging.
+    pub fn describe_host_object(&self, object: id) -> Option<(Option<u32>, &'static str)> {
+        let entry = self.objects.get(&object)?;
+        Some((
+            entry.refcount.map(NonZeroU32::get),
+            entry.host_object.type_name(),
+        ))
+    }
+
     /// Increase the refcount of a reference-counted object. Do not call this
     /// directly unless you're implementing `release` on `NSObject`. That method
     /// may be overridden.
@@ -0,0 +1,205 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Saving and restoring the runtime's state for save states. See
+//! [crate::save_state].
+
+use super::{id, ClassHostObject, FakeClass, HostObjectEntry, ObjC, UnimplementedClass, IMP, SEL};
+use crate::mem::Mem;
+use crate::save_state::{find_host_object_restorer, Reader, SaveState, Writer};
+use std::collections::HashMap;
+
+/// Kinds of object entries in a save state. Classes are handled by the runtime
+/// itself, since their host objects contain references to host code.
+#[repr(u8)]
+enum EntryKind {
+    Class = 0,
+    UnimplementedClass = 1,
+    FakeClass = 2,
+    Other = 3,
+}
+
+impl ObjC {
+    /// Save the runtime's state, including all the host objects. Objects whose
+    /// host objects can't be saved are reported with [Writer::unsupported].
+    pub fn save_state(&self, mem: &Mem, w: &mut Writer) {
+        let ObjC {
+            selectors,
+            objects,
+            classes,
+            sync_mutexes,
+            message_type_info,
+            guest_method_types,
+        } = self;
+        assert!(message_type_info.is_none());
+
+        selectors.save(w);
+        classes.save(w);
+        sync_mutexes.save(w);
+        guest_method_types.save(w);
+
+        // Sorting isn't necessary, but it means saving the same state twice
+        // produces the same file.
+        let mut objects: Vec<(&id, &HostObjectEntry)> = objects.iter().collect();
+        objects.sort_by_key(|&(object, _)| object.to_bits());
+
+        objects.len().save(w);
+        for (&object, entry) in objects {
+            object.save(w);
+            entry.refcount.save(w);
+
+            let host_object = entry.host_object.as_any();
+            if let Some(class) = host_object.downcast_ref::<ClassHostObject>() {
+                (EntryKind::Class as u8).save(w);
+                save_class(class, w);
+            } else if let Some(class) = host_object.downcast_ref::<UnimplementedClass>() {
+                (EntryKind::UnimplementedClass as u8).save(w);
+                class.name.save(w);
+                class.is_metaclass.save(w);
+            } else if let Some(class) = host_object.downcast_ref::<FakeClass>() {
+                (EntryKind::FakeClass as u8).save(w);
+                class.name.save(w);
+                class.is_metaclass.save(w);
+            } else {
+                (EntryKind::Other as u8).save(w);
+                if !entry.host_object.save_state(w) {
+                    let class = ObjC::read_isa(object, mem);
+                    w.unsupported(format!(
+                        "Object {:?} (class {})",
+                        object,
+                        self.get_class_name(class)
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Restore the runtime's state saved by [Self::save_state].
+    pub fn restore_state(r: &mut Reader) -> Result<ObjC, String> {
+        let selectors: HashMap<String, SEL> = SaveState::restore(r)?;
+        let classes = SaveState::restore(r)?;
+        let sync_mutexes = SaveState::restore(r)?;
+        let guest_method_types = SaveState::restore(r)?;
+
+        let selector_names: HashMap<SEL, &str> = selectors
+            .iter()
+            .map(|(name, &sel)| (sel, name.as_str()))
+            .collect();
+
+        let object_count = usize::restore(r)?;
+        let mut objects = HashMap::with_capacity(object_count);
+        for _ in 0..object_count {
+            let object: id = SaveState::restore(r)?;
+            let refcount = SaveState::restore(r)?;
+            let host_object: Box<dyn super::AnyHostObject> = match u8::restore(r)? {
+                k if k == EntryKind::Class as u8 => Box::new(restore_class(r, &selector_names)?),
+                k if k == EntryKind::UnimplementedClass as u8 => Box::new(UnimplementedClass {
+                    name: SaveState::restore(r)?,
+                    is_metaclass: SaveState::restore(r)?,
+                }),
+                k if k == EntryKind::FakeClass as u8 => Box::new(FakeClass {
+                    name: SaveState::restore(r)?,
+                    is_metaclass: SaveState::restore(r)?,
+                }),
+                k if k == EntryKind::Other as u8 => {
+                    let name = String::restore(r)?;
+                    let restorer = find_host_object_restorer(&name)
+                        .ok_or_else(|| format!("Unknown host object type {:?}", name))?;
+                    restorer(r)?
+                }
+                _ => return Err("Invalid object in save state".to_string()),
+            };
+            objects.insert(
+                object,
+                HostObjectEntry {
+                    host_object,
+                    refcount,
+                },
+            );
+        }
+
+        Ok(ObjC {
+            selectors,
+            objects,
+            classes,
+            sync_mutexes,
+            message_type_info: None,
+            guest_method_types,
+        })
+    }
+}
+
+/// Host method implementations are saved as just a flag, and found again in
+/// the class template when restoring.
+fn save_class(class: &ClassHostObject, w: &mut Writer) {
+    let ClassHostObject {
+        name,
+        is_metaclass,
+        superclass,
+        methods,
+        _instance_start,
+        instance_size,
+    } = class;
+    name.save(w);
+    is_metaclass.save(w);
+    superclass.save(w);
+    methods.len().save(w);
+    for (sel, imp) in methods {
+        sel.save(w);
+        match imp {
+            IMP::Host(_) => false.save(w),
+            IMP::Guest(guest_imp) => {
+                true.save(w);
+                guest_imp.save(w);
+            }
+        }
+    }
+    _instance_start.save(w);
+    instance_size.save(w);
+}
+
+fn restore_class(
+    r: &mut Reader,
+    selector_names: &HashMap<SEL, &str>,
+) -> Result<ClassHostObject, String> {
+    let name: String = SaveState::restore(r)?;
+    let is_metaclass: bool = SaveState::restore(r)?;
+    let superclass = SaveState::restore(r)?;
+
+    let method_count = usize::restore(r)?;
+    let mut methods = HashMap::with_capacity(method_count);
+    for _ in 0..method_count {
+        let sel: SEL = SaveState::restore(r)?;
+        let imp = if bool::restore(r)? {
+            IMP::Guest(SaveState::restore(r)?)
+        } else {
+            let &sel_name = selector_names
+                .get(&sel)
+                .ok_or("Unknown selector in save state")?;
+            let template = ObjC::find_template(&name)
+                .ok_or_else(|| format!("Unknown host class {:?} in save state", name))?;
+            let list = if is_metaclass {
+                template.class_methods
+            } else {
+                template.instance_methods
+            };
+            let &(_, host_imp) = list
+                .iter()
+                .find(|&&(other_name, _)| other_name == sel_name)
+                .ok_or_else(|| format!("Unknown host method {} {:?}", name, sel_name))?;
+            IMP::Host(host_imp)
+        };
+        methods.insert(sel, imp);
+    }
+
+    Ok(ClassHostObject {
+        name,
+        is_metaclass,
+        superclass,
+        methods,
+        _instance_start: SaveState::restore(r)?,
+        instance_size: SaveState::restore(r)?,
+    })
+}
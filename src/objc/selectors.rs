@@ -14,11 +14,13 @@
 //! Resources:
 //! - Apple's [The Objective-C Programming Language](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjectiveC/Chapters/ocSelectors.html)
 
-use super::ObjC;
+use super::{ClassHostObject, ObjC};
 use crate::abi::{GuestArg, GuestRet};
 use crate::mach_o::MachO;
 use crate::mem::{ConstPtr, Mem, MutPtr, Ptr};
+use crate::save_state::{Reader, SaveState, Writer};
 use crate::Environment;
+use std::collections::HashSet;
 
 /// Create a string literal for a selector from Objective-C message syntax
 /// components. Useful for [super::objc_classes] and for [super::msg].
@@ -57,6 +59,15 @@ impl GuestRet for SEL {
     }
 }
 
+impl SaveState for SEL {
+    fn save(&self, w: &mut Writer) {
+        self.0.save(w)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(SEL(SaveState::restore(r)?))
+    }
+}
+
 impl SEL {
     pub fn as_str(self, mem: &Mem) -> &str {
         // selectors are probably always UTF-8 but this hasn't been verified
@@ -138,6 +149,41 @@ impl ObjC {
             mem.write(selref, sel.0);
         }
     }
+
+    /// For use by [crate::compat_report]: get the names of the selectors
+    /// referenced by a binary that no host class implements, excluding those
+    /// implemented by the app's own classes. This is only meaningful after the
+    /// binary's selectors and classes have been registered.
+    pub fn find_unimplemented_selectors(&self, bin: &MachO, mem: &Mem) -> Vec<String> {
+        let Some(selrefs) = bin.get_section("__objc_selrefs") else {
+            return Vec::new();
+        };
+
+        let mut implemented: HashSet<&str> = HashSet::new();
+        for &class_list in super::CLASS_LISTS {
+            for (_name, template) in class_list {
+                for method_list in [template.class_methods, template.instance_methods] {
+                    implemented.extend(method_list.iter().map(|&(name, _imp)| name));
+                }
+            }
+        }
+        for class in self.objects_with_host_object::<ClassHostObject>() {
+            let class_host_object = self.get_host_object(class).unwrap();
+            let ClassHostObject { methods, .. } =
+                class_host_object.as_any().downcast_ref().unwrap();
+            implemented.extend(methods.keys().map(|&sel| sel.as_str(mem)));
+        }
+
+        let base: ConstPtr<ConstPtr<u8>> = Ptr::from_bits(selrefs.addr);
+        let mut unimplemented: Vec<String> = (0..(selrefs.size / 4))
+            .map(|i| mem.cstr_at_utf8(mem.read(base + i)).unwrap())
+            .filter(|name| !implemented.contains(name))
+            .map(ToOwned::to_owned)
+            .collect();
+        unimplemented.sort();
+        unimplemented.dedup();
+        unimplemented
+    }
 }
 
 /// Standard Objective-C runtime function for selector registration.
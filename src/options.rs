@@ -5,12 +5,16 @@
  */
 //! Parsing and management of user-configurable options, e.g. for input methods.
 
+use crate::breakpoints::Breakpoint;
 use crate::gles::GLESImplementation;
+use crate::objc::message_trace::MethodKind;
 use crate::window::DeviceOrientation;
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Read};
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::Duration;
 
 pub const OPTIONS_HELP: &str =
     include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/OPTIONS_HELP.txt"));
@@ -29,6 +33,38 @@ pub enum Button {
     Y,
     LeftShoulder,
 }
+crate::save_state::impl_SaveState_for_enum!(Button {
+    DPadLeft,
+    DPadUp,
+    DPadRight,
+    DPadDown,
+    Start,
+    A,
+    B,
+    X,
+    Y,
+    LeftShoulder,
+});
+
+impl Button {
+    /// Parse the name of a button as used in `--button-to-touch=`. The names
+    /// are the same as the [std::fmt::Debug] representation.
+    pub fn from_name(name: &str) -> Option<Button> {
+        match name {
+            "DPadLeft" => Some(Button::DPadLeft),
+            "DPadUp" => Some(Button::DPadUp),
+            "DPadRight" => Some(Button::DPadRight),
+            "DPadDown" => Some(Button::DPadDown),
+            "Start" => Some(Button::Start),
+            "A" => Some(Button::A),
+            "B" => Some(Button::B),
+            "X" => Some(Button::X),
+            "Y" => Some(Button::Y),
+            "LeftShoulder" => Some(Button::LeftShoulder),
+            _ => None,
+        }
+    }
+}
 
 /// Struct containing all user-configurable options.
 pub struct Options {
@@ -45,10 +81,31 @@ pub struct Options {
     pub gles1_implementation: Option<GLESImplementation>,
     pub direct_memory_access: bool,
     pub gdb_listen_addrs: Option<Vec<SocketAddr>>,
+    pub gdb_symbols_path: Option<PathBuf>,
+    pub debug_console: bool,
+    pub debug_console_listen_addrs: Option<Vec<SocketAddr>>,
+    pub break_on: Vec<Breakpoint>,
+    pub heap_diagnostics: bool,
+    pub heap_report_interval: Option<Duration>,
     pub preferred_languages: Option<Vec<String>>,
     pub headless: bool,
+    pub offscreen: bool,
+    pub dump_frames_dir: Option<PathBuf>,
+    pub dump_frames_interval: NonZeroU32,
     pub print_fps: bool,
     pub fps_limit: Option<f64>,
+    pub record_input_path: Option<PathBuf>,
+    pub replay_input_path: Option<PathBuf>,
+    pub virtual_clock: bool,
+    pub capture_audio_path: Option<PathBuf>,
+    pub null_audio: bool,
+    pub trace_objc_path: Option<PathBuf>,
+    pub trace_objc_classes: Vec<String>,
+    pub trace_objc_selectors: Vec<String>,
+    pub trace_objc_only: Option<MethodKind>,
+    pub trace_host_calls_path: Option<PathBuf>,
+    pub stub_unimplemented_functions: Vec<String>,
+    pub stub_unimplemented_methods: Vec<String>,
 }
 
 impl Default for Options {
@@ -67,10 +124,31 @@ impl Default for Options {
             gles1_implementation: None,
             direct_memory_access: true,
             gdb_listen_addrs: None,
+            gdb_symbols_path: None,
+            debug_console: false,
+            debug_console_listen_addrs: None,
+            break_on: Vec::new(),
+            heap_diagnostics: false,
+            heap_report_interval: Some(Duration::from_secs(10)),
             preferred_languages: None,
             headless: false,
+            offscreen: false,
+            dump_frames_dir: None,
+            dump_frames_interval: NonZeroU32::new(1).unwrap(),
             print_fps: false,
             fps_limit: Some(60.0), // Original iPhone is 60Hz and uses v-sync
+            record_input_path: None,
+            replay_input_path: None,
+            virtual_clock: false,
+            capture_audio_path: None,
+            null_audio: false,
+            trace_objc_path: None,
+            trace_objc_classes: Vec::new(),
+            trace_objc_selectors: Vec::new(),
+            trace_objc_only: None,
+            trace_host_calls_path: None,
+            stub_unimplemented_functions: Vec::new(),
+            stub_unimplemented_methods: Vec::new(),
         }
     }
 }
@@ -117,19 +195,8 @@ impl Options {
             let (x, y) = coords
                 .split_once(',')
                 .ok_or_else(|| "--button-to-touch= requires three values".to_string())?;
-            let button = match button {
-                "DPadLeft" => Ok(Button::DPadLeft),
-                "DPadUp" => Ok(Button::DPadUp),
-                "DPadRight" => Ok(Button::DPadRight),
-                "DPadDown" => Ok(Button::DPadDown),
-                "Start" => Ok(Button::Start),
-                "A" => Ok(Button::A),
-                "B" => Ok(Button::B),
-                "X" => Ok(Button::X),
-                "Y" => Ok(Button::Y),
-                "LeftShoulder" => Ok(Button::LeftShoulder),
-                _ => Err("Invalid button for --button-to-touch=".to_string()),
-            }?;
+            let button = Button::from_name(button)
+                .ok_or_else(|| "Invalid button for --button-to-touch=".to_string())?;
             let x: f32 = x
                 .parse()
                 .map_err(|_| "Invalid X co-ordinate for --button-to-touch=".to_string())?;
@@ -169,10 +236,43 @@ impl Options {
                 .map_err(|e| format!("Could not resolve GDB server listen address: {}", e))?
                 .collect();
             self.gdb_listen_addrs = Some(addrs);
+        } else if let Some(value) = arg.strip_prefix("--gdb-symbols=") {
+            self.gdb_symbols_path = Some(PathBuf::from(value));
+        } else if arg == "--debug-console" {
+            self.debug_console = true;
+        } else if let Some(address) = arg.strip_prefix("--debug-console=") {
+            let addrs = address
+                .to_socket_addrs()
+                .map_err(|e| format!("Could not resolve debug console listen address: {}", e))?
+                .collect();
+            self.debug_console = true;
+            self.debug_console_listen_addrs = Some(addrs);
+        } else if let Some(value) = arg.strip_prefix("--break-on=") {
+            // This adds to the list rather than replacing it, like
+            // --stub-unimplemented-functions=.
+            for spec in value.split(',') {
+                self.break_on.push(Breakpoint::parse(spec)?);
+            }
+        } else if arg == "--heap-diagnostics" {
+            self.heap_diagnostics = true;
+        } else if let Some(value) = arg.strip_prefix("--heap-diagnostics=") {
+            let seconds: u64 = value
+                .parse()
+                .map_err(|_| "Invalid value for --heap-diagnostics=".to_string())?;
+            self.heap_diagnostics = true;
+            self.heap_report_interval = (seconds != 0).then(|| Duration::from_secs(seconds));
         } else if let Some(value) = arg.strip_prefix("--preferred-languages=") {
             self.preferred_languages = Some(value.split(',').map(ToOwned::to_owned).collect());
         } else if arg == "--headless" {
             self.headless = true;
+        } else if arg == "--offscreen" {
+            self.offscreen = true;
+        } else if let Some(value) = arg.strip_prefix("--dump-frames=") {
+            self.dump_frames_dir = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--dump-frames-every=") {
+            self.dump_frames_interval = value
+                .parse()
+                .map_err(|_| "Invalid value for --dump-frames-every=".to_string())?;
         } else if arg == "--print-fps" {
             self.print_fps = true;
         } else if let Some(value) = arg.strip_prefix("--fps-limit=") {
@@ -186,6 +286,37 @@ impl Options {
                     .ok_or_else(|| "Invalid value for --fps-limit=".to_string())?;
                 self.fps_limit = Some(limit);
             }
+        } else if let Some(value) = arg.strip_prefix("--record-input=") {
+            self.record_input_path = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--replay-input=") {
+            self.replay_input_path = Some(PathBuf::from(value));
+        } else if arg == "--virtual-clock" {
+            self.virtual_clock = true;
+        } else if let Some(value) = arg.strip_prefix("--capture-audio=") {
+            self.capture_audio_path = Some(PathBuf::from(value));
+        } else if arg == "--null-audio" {
+            self.null_audio = true;
+        } else if let Some(value) = arg.strip_prefix("--trace-objc=") {
+            self.trace_objc_path = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--trace-objc-classes=") {
+            self.trace_objc_classes = value.split(',').map(ToOwned::to_owned).collect();
+        } else if let Some(value) = arg.strip_prefix("--trace-objc-selectors=") {
+            self.trace_objc_selectors = value.split(',').map(ToOwned::to_owned).collect();
+        } else if let Some(value) = arg.strip_prefix("--trace-objc-only=") {
+            self.trace_objc_only = Some(
+                MethodKind::from_name(value)
+                    .ok_or_else(|| "Unrecognized --trace-objc-only= value".to_string())?,
+            );
+        } else if let Some(value) = arg.strip_prefix("--trace-host-calls=") {
+            self.trace_host_calls_path = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--stub-unimplemented-functions=") {
+            // These add to the list rather than replacing it, so that both the
+            // options file and the command line can contribute.
+            self.stub_unimplemented_functions
+                .extend(value.split(',').map(ToOwned::to_owned));
+        } else if let Some(value) = arg.strip_prefix("--stub-unimplemented-methods=") {
+            self.stub_unimplemented_methods
+                .extend(value.split(',').map(ToOwned::to_owned));
         } else {
             return Ok(false);
         };
@@ -235,3 +366,37 @@ pub fn get_options_from_file<F: Read>(file: F, app_id: &str) -> Result<Option<St
     }
     Ok(None)
 }
+
+/// Match a string against a glob pattern, where `*` matches any number of
+/// characters and `?` matches exactly one.
+pub fn glob_match(glob: &str, string: &str) -> bool {
+    fn inner(glob: &[char], string: &[char]) -> bool {
+        match glob.split_first() {
+            None => string.is_empty(),
+            Some(('*', rest)) => (0..=string.len()).any(|i| inner(rest, &string[i..])),
+            Some((&g, rest)) => match string.split_first() {
+                Some((&s, string_rest)) if g == '?' || g == s => inner(rest, string_rest),
+                _ => false,
+            },
+        }
+    }
+    let glob: Vec<char> = glob.chars().collect();
+    let string: Vec<char> = string.chars().collect();
+    inner(&glob, &string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("UI*", "UIView"));
+        assert!(glob_match("*View", "UIView"));
+        assert!(glob_match("init*:", "initWithFrame:"));
+        assert!(glob_match("?IView", "UIView"));
+        assert!(!glob_match("UI*", "NSObject"));
+        assert!(!glob_match("UIView", "UIViewController"));
+    }
+}
@@ -0,0 +1,719 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Save states: snapshots of the whole emulated process.
+//!
+//! A save state contains everything that is needed to resume the app later
+//! from the same point and which can change while the app is running: guest
+//! memory, the CPU state of each thread, and the host-side state of the
+//! runtime, libc and frameworks (Objective-C objects, mutexes, open files,
+//! etc). It doesn't contain things which are the same on every run, like the
+//! app binary or the options, nor does it contain the app's files on disk.
+//!
+//! Save states can only be made and loaded while the main thread is in its run
+//! loop, because the host call stack can't be saved. The user makes one with
+//! F5 and loads it with F9. See [crate::Environment::save_state].
+//!
+//! OpenGL ES and OpenAL objects can't be saved directly, so they are re-created
+//! when loading from state that is recorded as the app makes calls and queried
+//! when saving (see `frameworks/opengles/gles_record.rs` and
+//! `frameworks/openal/save_state.rs`). This is not perfect: for example, the
+//! contents of renderbuffers are lost, so the screen may be black until the
+//! app draws the next frame.
+//!
+//! Not every kind of host object can be saved yet, so saving fails with an
+//! error if the app is using one that can't. Each kind of host object has to
+//! opt in by implementing
+//! [HostObject::save_state][crate::objc::HostObject::save_state] and being
+//! listed in [HOST_OBJECT_RESTORER_LISTS].
+//!
+//! The file is a ZIP archive containing a single file in a simple binary
+//! format (see [Writer] and [Reader]). Save states are not compatible between
+//! different versions of touchHLE.
+
+mod restorer_lists;
+
+pub use restorer_lists::HOST_OBJECT_RESTORER_LISTS;
+
+use crate::abi::GuestFunction;
+use crate::mem::Ptr;
+use crate::objc::AnyHostObject;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::io::{Read, Write};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Name of the file within the ZIP archive.
+const STATE_FILE_NAME: &str = "touchHLE_state.bin";
+
+/// Changed whenever the format changes, so that old save states are rejected.
+const FORMAT_MAGIC: &[u8] = b"touchHLE save state, format 3\0";
+
+/// Serializer for save states. Rather than making every method fallible, the
+/// first problem (e.g. some state that can't be saved yet) is recorded with
+/// [Writer::unsupported] and reported at the end.
+pub struct Writer {
+    data: Vec<u8>,
+    now: Instant,
+    error: Option<String>,
+}
+
+impl Writer {
+    /// `now` is used as the reference point for saving [Instant]s.
+    pub fn new(now: Instant) -> Writer {
+        let mut writer = Writer {
+            data: Vec::new(),
+            now,
+            error: None,
+        };
+        writer.bytes(FORMAT_MAGIC);
+        writer
+    }
+
+    /// Record that some state can't be saved. Only the first such error is
+    /// kept, since later ones may be a consequence of it.
+    pub fn unsupported(&mut self, what: impl std::fmt::Display) {
+        if self.error.is_none() {
+            self.error = Some(format!("{} can't be saved yet", what));
+        }
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Get the serialized data, or the first error that was encountered.
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.data),
+        }
+    }
+}
+
+/// Deserializer for save states.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    now: Instant,
+}
+
+impl<'a> Reader<'a> {
+    /// `now` is used as the reference point for restoring [Instant]s.
+    pub fn new(data: &'a [u8], now: Instant) -> Result<Reader<'a>, String> {
+        let mut reader = Reader { data, now };
+        if reader.bytes(FORMAT_MAGIC.len())? != FORMAT_MAGIC {
+            return Err("Not a save state for this version of touchHLE".to_string());
+        }
+        Ok(reader)
+    }
+
+    /// Change the reference point for restoring [Instant]s, e.g. because the
+    /// clock itself was restored from the save state.
+    pub fn set_now(&mut self, now: Instant) {
+        self.now = now;
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if count > self.data.len() {
+            return Err("Save state is truncated".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Check that there's no unexpected trailing data.
+    pub fn finish(self) -> Result<(), String> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err("Save state has unexpected trailing data".to_string())
+        }
+    }
+}
+
+/// Trait for types that can be saved in and restored from a save state.
+pub trait SaveState: Sized {
+    fn save(&self, w: &mut Writer);
+    fn restore(r: &mut Reader) -> Result<Self, String>;
+}
+
+macro_rules! impl_SaveState_for_number {
+    ($($ty:ty),*) => {
+        $(
+            impl SaveState for $ty {
+                fn save(&self, w: &mut Writer) {
+                    w.bytes(&self.to_le_bytes());
+                }
+                fn restore(r: &mut Reader) -> Result<Self, String> {
+                    let bytes = r.bytes(std::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    }
+}
+impl_SaveState_for_number!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl SaveState for bool {
+    fn save(&self, w: &mut Writer) {
+        (*self as u8).save(w)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        match u8::restore(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err("Invalid boolean in save state".to_string()),
+        }
+    }
+}
+
+impl SaveState for usize {
+    fn save(&self, w: &mut Writer) {
+        u64::try_from(*self).unwrap().save(w)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        usize::try_from(u64::restore(r)?).map_err(|_| "Size too large".to_string())
+    }
+}
+
+impl SaveState for NonZeroU32 {
+    fn save(&self, w: &mut Writer) {
+        self.get().save(w)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        NonZeroU32::new(u32::restore(r)?).ok_or_else(|| "Unexpected zero".to_string())
+    }
+}
+
+/// Save a string that can be restored as a [String]. Useful for
+/// `&'static str`s.
+pub fn save_str(w: &mut Writer, str: &str) {
+    str.len().save(w);
+    w.bytes(str.as_bytes());
+}
+
+impl SaveState for String {
+    fn save(&self, w: &mut Writer) {
+        save_str(w, self)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        let len = usize::restore(r)?;
+        String::from_utf8(r.bytes(len)?.to_vec()).map_err(|_| "Invalid string".to_string())
+    }
+}
+
+/// Borrowed strings are restored as owned strings.
+impl SaveState for Cow<'static, str> {
+    fn save(&self, w: &mut Writer) {
+        save_str(w, self)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        String::restore(r).map(Cow::Owned)
+    }
+}
+
+/// Static strings are restored by leaking a copy of the string. This is only
+/// suitable for small strings that are rarely restored, e.g. names.
+impl SaveState for &'static str {
+    fn save(&self, w: &mut Writer) {
+        save_str(w, self)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        String::restore(r).map(|string| &*Box::leak(string.into_boxed_str()))
+    }
+}
+
+impl SaveState for Duration {
+    fn save(&self, w: &mut Writer) {
+        self.as_secs().save(w);
+        self.subsec_nanos().save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        let secs = u64::restore(r)?;
+        let nanos = u32::restore(r)?;
+        Ok(Duration::new(secs, nanos))
+    }
+}
+
+/// Instants are saved relative to the current time, so that e.g. a timer that
+/// was due in one second is still due in one second after loading.
+impl SaveState for Instant {
+    fn save(&self, w: &mut Writer) {
+        let (ahead, distance) = if *self >= w.now {
+            (true, self.duration_since(w.now))
+        } else {
+            (false, w.now.duration_since(*self))
+        };
+        ahead.save(w);
+        distance.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        let ahead = bool::restore(r)?;
+        let distance = Duration::restore(r)?;
+        if ahead {
+            r.now.checked_add(distance)
+        } else {
+            r.now.checked_sub(distance)
+        }
+        .ok_or_else(|| "Time out of range".to_string())
+    }
+}
+
+impl<T, const MUT: bool> SaveState for Ptr<T, MUT> {
+    fn save(&self, w: &mut Writer) {
+        self.to_bits().save(w)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(Ptr::from_bits(u32::restore(r)?))
+    }
+}
+
+impl SaveState for GuestFunction {
+    fn save(&self, w: &mut Writer) {
+        self.addr_with_thumb_bit().save(w)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(GuestFunction::from_addr_with_thumb_bit(u32::restore(r)?))
+    }
+}
+
+impl<T: SaveState> SaveState for Option<T> {
+    fn save(&self, w: &mut Writer) {
+        self.is_some().save(w);
+        if let Some(value) = self {
+            value.save(w);
+        }
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok(if bool::restore(r)? {
+            Some(T::restore(r)?)
+        } else {
+            None
+        })
+    }
+}
+
+impl<T: SaveState> SaveState for Box<T> {
+    fn save(&self, w: &mut Writer) {
+        (**self).save(w)
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        T::restore(r).map(Box::new)
+    }
+}
+
+impl<T: SaveState, const N: usize> SaveState for [T; N] {
+    fn save(&self, w: &mut Writer) {
+        for item in self {
+            item.save(w);
+        }
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        let items = (0..N)
+            .map(|_| T::restore(r))
+            .collect::<Result<Vec<T>, String>>()?;
+        Ok(items.try_into().ok().unwrap())
+    }
+}
+
+impl<A: SaveState, B: SaveState> SaveState for (A, B) {
+    fn save(&self, w: &mut Writer) {
+        self.0.save(w);
+        self.1.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok((A::restore(r)?, B::restore(r)?))
+    }
+}
+
+impl<A: SaveState, B: SaveState, C: SaveState> SaveState for (A, B, C) {
+    fn save(&self, w: &mut Writer) {
+        self.0.save(w);
+        self.1.save(w);
+        self.2.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok((A::restore(r)?, B::restore(r)?, C::restore(r)?))
+    }
+}
+
+impl<A: SaveState, B: SaveState, C: SaveState, D: SaveState> SaveState for (A, B, C, D) {
+    fn save(&self, w: &mut Writer) {
+        self.0.save(w);
+        self.1.save(w);
+        self.2.save(w);
+        self.3.save(w);
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Ok((
+            A::restore(r)?,
+            B::restore(r)?,
+            C::restore(r)?,
+            D::restore(r)?,
+        ))
+    }
+}
+
+/// Save a sequence of items, prefixed by its length.
+pub fn save_iter<'a, T: SaveState + 'a>(
+    w: &mut Writer,
+    len: usize,
+    iter: impl Iterator<Item = &'a T>,
+) {
+    len.save(w);
+    for item in iter {
+        item.save(w);
+    }
+}
+
+/// Restore a sequence of items saved with [save_iter].
+pub fn restore_iter<T: SaveState, C: FromIterator<T>>(r: &mut Reader) -> Result<C, String> {
+    let len = usize::restore(r)?;
+    (0..len).map(|_| T::restore(r)).collect()
+}
+
+impl<T: SaveState> SaveState for Vec<T> {
+    fn save(&self, w: &mut Writer) {
+        save_iter(w, self.len(), self.iter())
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        restore_iter(r)
+    }
+}
+
+/// Iterators are restored with only the items that haven't been consumed yet.
+impl<T: SaveState> SaveState for std::vec::IntoIter<T> {
+    fn save(&self, w: &mut Writer) {
+        save_iter(w, self.len(), self.as_slice().iter())
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        Vec::restore(r).map(Vec::into_iter)
+    }
+}
+
+impl<T: SaveState> SaveState for VecDeque<T> {
+    fn save(&self, w: &mut Writer) {
+        save_iter(w, self.len(), self.iter())
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        restore_iter(r)
+    }
+}
+
+impl<T: SaveState + Eq + Hash> SaveState for HashSet<T> {
+    fn save(&self, w: &mut Writer) {
+        save_iter(w, self.len(), self.iter())
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        restore_iter(r)
+    }
+}
+
+impl<K: SaveState + Eq + Hash, V: SaveState> SaveState for HashMap<K, V> {
+    fn save(&self, w: &mut Writer) {
+        self.len().save(w);
+        for (key, value) in self {
+            key.save(w);
+            value.save(w);
+        }
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        restore_iter::<(K, V), _>(r)
+    }
+}
+
+impl<K: SaveState + Ord, V: SaveState> SaveState for BTreeMap<K, V> {
+    fn save(&self, w: &mut Writer) {
+        self.len().save(w);
+        for (key, value) in self {
+            key.save(w);
+            value.save(w);
+        }
+    }
+    fn restore(r: &mut Reader) -> Result<Self, String> {
+        restore_iter::<(K, V), _>(r)
+    }
+}
+
+/// Implement [SaveState] for a struct by saving each of the listed fields in
+/// order. All fields must be listed.
+///
+/// ```ignore
+/// struct Foo {
+///     bar: u32,
+///     baz: Vec<id>,
+/// }
+/// impl_SaveState_for_struct!(Foo { bar, baz });
+/// ```
+///
+/// For a `#[repr(packed)]` struct, where the fields can't be borrowed, use
+/// `impl_SaveState_for_struct!(packed Foo { bar, baz })`. This copies each
+/// field, so they must all be [Copy].
+#[macro_export]
+macro_rules! impl_SaveState_for_struct {
+    (packed $ty:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::save_state::SaveState for $ty {
+            fn save(&self, _w: &mut $crate::save_state::Writer) {
+                $( $crate::save_state::SaveState::save(&{ self.$field }, _w); )*
+            }
+            fn restore(
+                _r: &mut $crate::save_state::Reader,
+            ) -> Result<Self, String> {
+                Ok($ty {
+                    $( $field: $crate::save_state::SaveState::restore(_r)?, )*
+                })
+            }
+        }
+    };
+    ($ty:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::save_state::SaveState for $ty {
+            fn save(&self, _w: &mut $crate::save_state::Writer) {
+                $( $crate::save_state::SaveState::save(&self.$field, _w); )*
+            }
+            fn restore(
+                _r: &mut $crate::save_state::Reader,
+            ) -> Result<Self, String> {
+                Ok($ty {
+                    $( $field: $crate::save_state::SaveState::restore(_r)?, )*
+                })
+            }
+        }
+    };
+}
+pub use crate::impl_SaveState_for_struct; // #[macro_export] is weird...
+
+/// Implement [SaveState] for an enum. All variants and their fields must be
+/// listed. Fields of tuple variants can be given any name.
+///
+/// ```ignore
+/// enum Foo {
+///     Bar,
+///     Baz(u32, id),
+///     Qux { quux: bool },
+/// }
+/// impl_SaveState_for_enum!(Foo { Bar, Baz(a, b), Qux { quux } });
+/// ```
+#[macro_export]
+macro_rules! impl_SaveState_for_enum {
+    ($ty:ident { $(
+        $variant:ident
+        $( ( $($tfield:ident),* ) )?
+        $( { $($sfield:ident),* } )?
+    ),* $(,)? }) => {
+        impl $crate::save_state::SaveState for $ty {
+            fn save(&self, w: &mut $crate::save_state::Writer) {
+                match self {
+                    $(
+                        $ty::$variant
+                        $( ( $($tfield),* ) )?
+                        $( { $($sfield),* } )?
+                        => {
+                            $crate::save_state::save_str(
+                                w,
+                                stringify!($variant),
+                            );
+                            $($( $crate::save_state::SaveState::save(
+                                $tfield,
+                                w,
+                            ); )*)?
+                            $($( $crate::save_state::SaveState::save(
+                                $sfield,
+                                w,
+                            ); )*)?
+                        }
+                    )*
+                }
+            }
+            fn restore(
+                r: &mut $crate::save_state::Reader,
+            ) -> Result<Self, String> {
+                let variant: String = $crate::save_state::SaveState::restore(r)?;
+                $(
+                    if variant == stringify!($variant) {
+                        return Ok($ty::$variant
+                            $( ( $({
+                                let _ = stringify!($tfield);
+                                $crate::save_state::SaveState::restore(r)?
+                            }),* ) )?
+                            $( { $(
+                                $sfield: $crate::save_state::SaveState::restore(
+                                    r,
+                                )?
+                            ),* } )?
+                        );
+                    }
+                )*
+                Err(format!(
+                    "Unknown variant {} of {} in save state",
+                    variant,
+                    stringify!($ty),
+                ))
+            }
+        }
+    };
+}
+pub use crate::impl_SaveState_for_enum; // #[macro_export] is weird...
+
+/// Implement [HostObject][crate::objc::HostObject] for a type that also
+/// implements [SaveState], so that objects with this host object type can be
+/// saved. The type must also be added to a [HostObjectRestorers] list with
+/// [host_object_restorer].
+///
+/// If the type extends a superclass's host object, which must be in a struct
+/// member named `superclass`, use
+/// `impl_HostObject_with_SaveState!(FooHostObject, superclass)`.
+#[macro_export]
+macro_rules! impl_HostObject_with_SaveState {
+    ( $ty:ident ) => {
+        impl $crate::objc::HostObject for $ty {
+            fn save_state(&self, w: &mut $crate::save_state::Writer) -> bool {
+                $crate::save_state::save_str(w, stringify!($ty));
+                $crate::save_state::SaveState::save(self, w);
+                true
+            }
+        }
+    };
+    ( $ty:ident, superclass ) => {
+        impl $crate::objc::HostObject for $ty {
+            fn as_superclass<'a>(
+                &'a self,
+            ) -> Option<&'a (dyn $crate::objc::AnyHostObject + 'static)> {
+                Some(&self.superclass)
+            }
+            fn as_superclass_mut<'a>(
+                &'a mut self,
+            ) -> Option<&'a mut (dyn $crate::objc::AnyHostObject + 'static)> {
+                Some(&mut self.superclass)
+            }
+            fn save_state(&self, w: &mut $crate::save_state::Writer) -> bool {
+                $crate::save_state::save_str(w, stringify!($ty));
+                $crate::save_state::SaveState::save(self, w);
+                true
+            }
+        }
+    };
+}
+pub use crate::impl_HostObject_with_SaveState; // #[macro_export] is weird...
+
+/// Create an entry for a [HostObjectRestorers] list, for a type that uses
+/// [impl_HostObject_with_SaveState].
+#[macro_export]
+macro_rules! host_object_restorer {
+    ( $ty:ident ) => {
+        (
+            stringify!($ty),
+            $crate::save_state::restore_host_object::<$ty>,
+        )
+    };
+}
+pub use crate::host_object_restorer; // #[macro_export] is weird...
+
+/// Function that restores a particular type of host object.
+pub type HostObjectRestorer = fn(&mut Reader) -> Result<Box<dyn AnyHostObject>, String>;
+
+/// Type for lists of host object types that can be restored. The string is the
+/// name saved by [HostObject::save_state][crate::objc::HostObject::save_state].
+pub type HostObjectRestorers = &'static [(&'static str, HostObjectRestorer)];
+
+/// Convenience function for use in [HostObjectRestorers] lists, e.g.
+/// `("FooHostObject", restore_host_object::<FooHostObject>)`.
+pub fn restore_host_object<T: SaveState + AnyHostObject>(
+    r: &mut Reader,
+) -> Result<Box<dyn AnyHostObject>, String> {
+    Ok(Box::new(T::restore(r)?))
+}
+
+pub fn find_host_object_restorer(name: &str) -> Option<HostObjectRestorer> {
+    HOST_OBJECT_RESTORER_LISTS
+        .iter()
+        .flat_map(|list| list.iter())
+        .find(|&&(other_name, _)| other_name == name)
+        .map(|&(_, restorer)| restorer)
+}
+
+/// Write the data from a [Writer] to a save state file.
+pub fn write_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Couldn't create {}: {}", parent.display(), e))?;
+    }
+    // Write to a temporary file first, so that an existing save state isn't
+    // lost if something goes wrong.
+    let temp_path = path.with_extension("tmp");
+    let file = std::fs::File::create(&temp_path)
+        .map_err(|e| format!("Couldn't create {}: {}", temp_path.display(), e))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file(STATE_FILE_NAME, options)
+        .and_then(|()| zip.write_all(data).map_err(Into::into))
+        .and_then(|()| zip.finish().map(|_| ()))
+        .map_err(|e| format!("Couldn't write {}: {}", temp_path.display(), e))?;
+    std::fs::rename(&temp_path, path)
+        .map_err(|e| format!("Couldn't write {}: {}", path.display(), e))
+}
+
+/// Read the data for a [Reader] from a save state file.
+pub fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Couldn't open {}: {}", path.display(), e))?;
+    let mut zip = zip::ZipArchive::new(file)
+        .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    let mut file = zip
+        .by_name(STATE_FILE_NAME)
+        .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestValue = (Vec<Option<String>>, HashMap<u32, (bool, f64)>, Instant);
+
+    #[test]
+    fn test_round_trip() {
+        let now = Instant::now();
+        let mut w = Writer::new(now);
+        let value: TestValue = (
+            vec![Some("hello".to_string()), None],
+            HashMap::from([(1, (true, 0.5)), (2, (false, -1.0))]),
+            now - Duration::from_secs(1),
+        );
+        value.save(&mut w);
+        let data = w.finish().unwrap();
+
+        let later = now + Duration::from_secs(10);
+        let mut r = Reader::new(&data, later).unwrap();
+        let restored: TestValue = SaveState::restore(&mut r).unwrap();
+        r.finish().unwrap();
+        assert_eq!(restored.0, value.0);
+        assert_eq!(restored.1, value.1);
+        assert_eq!(restored.2, later - Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut w = Writer::new(Instant::now());
+        w.unsupported("Foo");
+        w.unsupported("Bar");
+        assert_eq!(w.finish(), Err("Foo can't be saved yet".to_string()));
+
+        let mut w = Writer::new(Instant::now());
+        1234u32.save(&mut w);
+        let data = w.finish().unwrap();
+        let mut r = Reader::new(&data[..data.len() - 1], Instant::now()).unwrap();
+        assert!(u32::restore(&mut r).is_err());
+
+        assert!(Reader::new(b"something else", Instant::now()).is_err());
+    }
+}
@@ -0,0 +1,62 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Separate module just for the host object restorer lists, like the class
+//! lists.
+
+use crate::frameworks::{
+    av_audio, core_animation, core_foundation, core_graphics, foundation, media_player, opengles,
+    uikit,
+};
+
+/// All the lists of host object types that can be restored from a save state.
+pub const HOST_OBJECT_RESTORER_LISTS: &[super::HostObjectRestorers] = &[
+    crate::objc::HOST_OBJECT_RESTORERS, // Not a framework! TrivialHostObject.
+    av_audio::av_audio_player::HOST_OBJECT_RESTORERS,
+    core_animation::ca_layer::HOST_OBJECT_RESTORERS,
+    core_foundation::cf_run_loop_timer::HOST_OBJECT_RESTORERS,
+    core_graphics::cg_color_space::HOST_OBJECT_RESTORERS,
+    core_graphics::cg_context::HOST_OBJECT_RESTORERS,
+    core_graphics::cg_data_provider::HOST_OBJECT_RESTORERS,
+    core_graphics::cg_image::HOST_OBJECT_RESTORERS,
+    foundation::ns_array::HOST_OBJECT_RESTORERS,
+    foundation::ns_autorelease_pool::HOST_OBJECT_RESTORERS,
+    foundation::ns_bundle::HOST_OBJECT_RESTORERS,
+    foundation::ns_character_set::HOST_OBJECT_RESTORERS,
+    foundation::ns_data::HOST_OBJECT_RESTORERS,
+    foundation::ns_date::HOST_OBJECT_RESTORERS,
+    foundation::ns_date_formatter::HOST_OBJECT_RESTORERS,
+    foundation::ns_dictionary::HOST_OBJECT_RESTORERS,
+    foundation::ns_error::HOST_OBJECT_RESTORERS,
+    foundation::ns_file_manager::HOST_OBJECT_RESTORERS,
+    foundation::ns_locale::HOST_OBJECT_RESTORERS,
+    foundation::ns_lock::HOST_OBJECT_RESTORERS,
+    foundation::ns_notification::HOST_OBJECT_RESTORERS,
+    foundation::ns_notification_center::HOST_OBJECT_RESTORERS,
+    foundation::ns_run_loop::HOST_OBJECT_RESTORERS,
+    foundation::ns_set::HOST_OBJECT_RESTORERS,
+    foundation::ns_string::HOST_OBJECT_RESTORERS,
+    foundation::ns_thread::HOST_OBJECT_RESTORERS,
+    foundation::ns_timer::HOST_OBJECT_RESTORERS,
+    foundation::ns_url::HOST_OBJECT_RESTORERS,
+    foundation::ns_user_defaults::HOST_OBJECT_RESTORERS,
+    foundation::ns_value::HOST_OBJECT_RESTORERS,
+    media_player::movie_player::HOST_OBJECT_RESTORERS,
+    opengles::eagl::HOST_OBJECT_RESTORERS,
+    uikit::ui_accelerometer::HOST_OBJECT_RESTORERS,
+    uikit::ui_application::HOST_OBJECT_RESTORERS,
+    uikit::ui_color::HOST_OBJECT_RESTORERS,
+    uikit::ui_event::HOST_OBJECT_RESTORERS,
+    uikit::ui_font::HOST_OBJECT_RESTORERS,
+    uikit::ui_image::HOST_OBJECT_RESTORERS,
+    uikit::ui_nib::HOST_OBJECT_RESTORERS,
+    uikit::ui_touch::HOST_OBJECT_RESTORERS,
+    uikit::ui_view::HOST_OBJECT_RESTORERS,
+    uikit::ui_view::ui_control::HOST_OBJECT_RESTORERS,
+    uikit::ui_view::ui_control::ui_button::HOST_OBJECT_RESTORERS,
+    uikit::ui_view::ui_image_view::HOST_OBJECT_RESTORERS,
+    uikit::ui_view::ui_label::HOST_OBJECT_RESTORERS,
+    uikit::ui_view_controller::HOST_OBJECT_RESTORERS,
+];
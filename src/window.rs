@@ -12,18 +12,22 @@
 //! window system interaction in general, because it is assumed only one window
 //! will be needed for the runtime of the app.
 
-use crate::gles::present::present_frame;
-use crate::gles::{create_gles1_ctx, GLES};
+use crate::embedding::{EmbedderLink, Frame, InputEvent};
+use crate::gles::gles1_software::DefaultFramebuffer;
+use crate::gles::present::{present_frame, read_frame, FrameDumper};
+use crate::gles::{create_gles1_ctx, GLESImplementation, GLES};
 use crate::image::Image;
 use crate::matrix::Matrix;
 use crate::options::Options;
 use sdl2::mouse::MouseButton;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::surface::Surface;
+use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
 use std::env;
 use std::f32::consts::FRAC_PI_2;
 use std::num::NonZeroU32;
+use std::rc::Rc;
 use std::time::{Duration, Instant};
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -75,9 +79,15 @@ pub enum FingerId {
     VirtualCursor,
     ButtonToTouch(crate::options::Button),
 }
+crate::save_state::impl_SaveState_for_enum!(FingerId {
+    Mouse,
+    Touch(id),
+    VirtualCursor,
+    ButtonToTouch(button),
+});
 pub type Coords = (f32, f32);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
     /// User requested quit.
     Quit,
@@ -93,6 +103,10 @@ pub enum Event {
     /// User pressed F12, requesting that execution be paused and the debugger
     /// take over.
     EnterDebugger,
+    /// User pressed F5, requesting a save state be made.
+    SaveState,
+    /// User pressed F9, requesting the save state be loaded.
+    LoadState,
 }
 
 pub enum GLVersion {
@@ -154,6 +168,13 @@ pub struct Window {
     accelerometer: Option<sdl2::sensor::Sensor>,
     virtual_cursor_last: Option<(f32, f32, bool, bool)>,
     virtual_cursor_last_unsticky: Option<(f32, f32, Instant)>,
+    frame_dumper: Option<FrameDumper>,
+    /// Present if `--gles1=software` is in use, in which case the window has
+    /// no OpenGL surface.
+    software_framebuffer: Option<Rc<RefCell<DefaultFramebuffer>>>,
+    presented_app_frame: bool,
+    /// Present if touchHLE is embedded (see [crate::embedding]).
+    embedder: Option<EmbedderLink>,
 }
 impl Window {
     /// Returns [true] if touchHLE is running on a device where we should always
@@ -169,6 +190,11 @@ impl Window {
         launch_image: Option<Image>,
         options: &Options,
     ) -> Window {
+        if options.offscreen {
+            // This has to be set before SDL2's video subsystem is initialized.
+            sdl2::hint::set("SDL_VIDEODRIVER", "offscreen");
+        }
+
         let sdl_ctx = sdl2::init().unwrap();
         let video_ctx = sdl_ctx.video().unwrap();
 
@@ -202,39 +228,39 @@ impl Window {
         // TODO: some apps specify their orientation in Info.plist, we could use
         // that here.
         let device_orientation = options.initial_orientation;
-        let fullscreen = options.fullscreen;
+        // Fullscreen would be pointless for an invisible window.
+        let fullscreen = options.fullscreen && !options.offscreen;
+
+        // The software renderer draws to its own framebuffer, which is copied
+        // to the window surface when presenting.
+        let software_rendering = matches!(
+            options.gles1_implementation,
+            Some(GLESImplementation::GLES1Software)
+        );
 
-        let mut window = if Self::rotatable_fullscreen() {
+        let mut window_builder = if Self::rotatable_fullscreen() {
             // Without this, SDL will force fullscreen mode to be portrait.
             set_sdl2_orientation(device_orientation);
             let screen_size = video_ctx.display_bounds(0).unwrap().size();
             let (width, height) = rotate_fullscreen_size(device_orientation, screen_size);
-            let window = video_ctx
-                .window(title, width, height)
-                .fullscreen()
-                .opengl()
-                .build()
-                .unwrap();
-            window
+            let mut window_builder = video_ctx.window(title, width, height);
+            window_builder.fullscreen();
+            window_builder
         } else if fullscreen {
             let (width, height) = video_ctx.display_bounds(0).unwrap().size();
-            let window = video_ctx
-                .window(title, width, height)
-                .fullscreen_desktop()
-                .opengl()
-                .build()
-                .unwrap();
-            window
+            let mut window_builder = video_ctx.window(title, width, height);
+            window_builder.fullscreen_desktop();
+            window_builder
         } else {
             let (width, height) = size_for_orientation(device_orientation, scale_hack);
-            let window = video_ctx
-                .window(title, width, height)
-                .position_centered()
-                .opengl()
-                .build()
-                .unwrap();
-            window
+            let mut window_builder = video_ctx.window(title, width, height);
+            window_builder.position_centered();
+            window_builder
         };
+        if !software_rendering {
+            window_builder.opengl();
+        }
+        let mut window = window_builder.build().unwrap();
 
         if env::consts::OS == "android" {
             // Sanity check
@@ -293,7 +319,20 @@ impl Window {
             accelerometer,
             virtual_cursor_last: None,
             virtual_cursor_last_unsticky: None,
+            frame_dumper: options
+                .dump_frames_dir
+                .clone()
+                .map(|dir| FrameDumper::new(dir, options.dump_frames_interval)),
+            software_framebuffer: None,
+            presented_app_frame: false,
+            embedder: None,
         };
+        if software_rendering {
+            let (width, height) = window.window.size();
+            window.software_framebuffer = Some(Rc::new(RefCell::new(DefaultFramebuffer::new(
+                width, height,
+            ))));
+        }
 
         // Set up OpenGL ES context used for splash screen and app UI rendering
         // (see src/frameworks/core_animation/composition.rs). OpenGL ES is used
@@ -368,7 +407,7 @@ impl Window {
             }
         }
         fn finger_absolute_coords(window: &Window, (x, y): (f32, f32)) -> (f32, f32) {
-            let (screen_width, screen_height) = window.window.drawable_size();
+            let (screen_width, screen_height) = window.drawable_size();
             (screen_width as f32 * x, screen_height as f32 * y)
         }
 
@@ -575,6 +614,16 @@ impl Window {
                     echo!("F12 pressed, EnterDebugger event queued.");
                     Event::EnterDebugger
                 }
+                E::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F5),
+                    repeat: false,
+                    ..
+                } => Event::SaveState,
+                E::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F9),
+                    repeat: false,
+                    ..
+                } => Event::LoadState,
                 _ => continue,
             })
         }
@@ -649,6 +698,14 @@ impl Window {
     /// Get the real or simulated accelerometer output.
     /// See also [crate::frameworks::uikit::ui_accelerometer].
     pub fn get_acceleration(&self, options: &Options) -> (f32, f32, f32) {
+        if let Some(acceleration) = self
+            .embedder
+            .as_ref()
+            .and_then(|embedder| embedder.acceleration())
+        {
+            return acceleration;
+        }
+
         if self.controllers.is_empty() {
             if let Some(ref accelerometer) = self.accelerometer {
                 let data = accelerometer.get_data().unwrap();
@@ -953,18 +1010,124 @@ impl Window {
             );
 
             gl_ctx.DeleteTextures(1, &texture);
+
+            // The splash screen isn't part of the app's timeline.
+            self.dump_internal_frame(None);
         };
 
-        self.window.gl_swap_window();
+        self.swap_window();
 
         // hold onto GL context so the image doesn't disappear, and hold
         // onto image so we can rotate later if necessary
     }
 
+    /// If `--dump-frames=` is in use, count a frame that was just presented
+    /// using the provided context, and maybe save it to a file (see
+    /// [FrameDumper::count_frame]). This must be called before
+    /// [Self::swap_window]. `uptime` is the app's uptime, if it has started.
+    pub unsafe fn dump_frame(&mut self, gles: &mut dyn GLES, uptime: Option<Duration>) {
+        let viewport = self.viewport();
+        if let Some(ref mut frame_dumper) = self.frame_dumper {
+            frame_dumper.count_frame(gles, viewport, uptime);
+        }
+    }
+
+    /// Like [Self::dump_frame], but for a frame presented using the internal
+    /// OpenGL ES context.
+    pub unsafe fn dump_internal_frame(&mut self, uptime: Option<Duration>) {
+        let viewport = self.viewport();
+        if let Some(ref mut frame_dumper) = self.frame_dumper {
+            frame_dumper.count_frame(
+                self.internal_gl_ctx.as_deref_mut().unwrap(),
+                viewport,
+                uptime,
+            );
+        }
+    }
+
+    /// Call this when a frame rendered by the app (rather than e.g. the launch
+    /// image) is about to be presented, using the provided context, or the
+    /// internal context if [None]. This must be called before
+    /// [Self::swap_window], with the default framebuffer bound.
+    ///
+    /// The first time, a message is logged that the batch compatibility runner
+    /// looks for. If touchHLE is embedded, this hands the frame to the
+    /// embedder and waits until it asks for the next one.
+    pub unsafe fn app_frame_presented(&mut self, gles: Option<&mut dyn GLES>) {
+        if !self.presented_app_frame {
+            self.presented_app_frame = true;
+            log!("{}", crate::batch_compat::FIRST_FRAME_MESSAGE);
+        }
+
+        if self.embedder.is_none() {
+            return;
+        }
+        let viewport = self.viewport();
+        let gles = match gles {
+            Some(gles) => gles,
+            None => self.internal_gl_ctx.as_deref_mut().unwrap(),
+        };
+        let frame = Frame {
+            width: viewport.2,
+            height: viewport.3,
+            pixels: read_frame(gles, viewport),
+        };
+        let events = self.embedder.as_mut().unwrap().present_frame(frame);
+        for event in events {
+            let touch = |finger: u32, x: f32, y: f32| {
+                HashMap::from([(FingerId::Touch(finger.into()), (x, y))])
+            };
+            self.event_queue.push_back(match event {
+                InputEvent::TouchDown { finger, x, y } => Event::TouchesDown(touch(finger, x, y)),
+                InputEvent::TouchMove { finger, x, y } => Event::TouchesMove(touch(finger, x, y)),
+                InputEvent::TouchUp { finger, x, y } => Event::TouchesUp(touch(finger, x, y)),
+                InputEvent::Acceleration { .. } => continue,
+                InputEvent::Quit => Event::Quit,
+            });
+        }
+    }
+
+    /// Connect the window to an embedder (see [crate::embedding]).
+    pub(crate) fn set_embedder(&mut self, embedder: EmbedderLink) {
+        self.embedder = Some(embedder);
+    }
+
+    pub fn is_embedded(&self) -> bool {
+        self.embedder.is_some()
+    }
+
     /// Swap front-buffer and back-buffer so the result of OpenGL rendering is
     /// presented.
     pub fn swap_window(&self) {
-        self.window.gl_swap_window();
+        let Some(ref framebuffer) = self.software_framebuffer else {
+            self.window.gl_swap_window();
+            return;
+        };
+        let framebuffer = framebuffer.borrow();
+        let (width, height) = framebuffer.size();
+        let mut pixels = framebuffer.rgba8_top_to_bottom();
+        let mut surface = Surface::from_data(
+            &mut pixels,
+            width,
+            height,
+            width * 4,
+            PixelFormatEnum::RGBA32,
+        )
+        .unwrap();
+        surface
+            .set_blend_mode(sdl2::render::BlendMode::None)
+            .unwrap();
+        let mut window_surface = self.window.surface(&self.event_pump).unwrap();
+        surface
+            .blit_scaled(None, &mut window_surface, None)
+            .unwrap();
+        window_surface.update_window().unwrap();
+    }
+
+    /// Get the framebuffer shared by software renderer contexts, if the window
+    /// was created for `--gles1=software`.
+    pub fn software_framebuffer(&self) -> Option<Rc<RefCell<DefaultFramebuffer>>> {
+        self.software_framebuffer.clone()
     }
 
     /// Consider the emulated device to be rotated to a particular orientation.
@@ -1023,6 +1186,11 @@ impl Window {
                 .unwrap();
         }
 
+        if let Some(ref framebuffer) = self.software_framebuffer {
+            let (width, height) = self.window.size();
+            framebuffer.borrow_mut().resize(width, height);
+        }
+
         self.device_orientation = new_orientation;
 
         if self.splash_image.is_some() {
@@ -1064,7 +1232,7 @@ impl Window {
             return (0, 0, app_width, app_height);
         }
 
-        let (screen_width, screen_height) = self.window.drawable_size();
+        let (screen_width, screen_height) = self.drawable_size();
 
         let app_aspect = app_width as f32 / app_height as f32;
         let screen_aspect = screen_width as f32 / screen_height as f32;
@@ -1084,12 +1252,23 @@ impl Window {
         (x, y, scaled_width, scaled_height)
     }
 
+    /// Get the size in pixels of the window's framebuffer.
+    fn drawable_size(&self) -> (u32, u32) {
+        if let Some(ref framebuffer) = self.software_framebuffer {
+            framebuffer.borrow().size()
+        } else {
+            self.window.drawable_size()
+        }
+    }
+
     /// Special offset to add to y co-ordinates, only when drawing to screen.
     pub fn viewport_y_offset(&self) -> u32 {
+        // The software renderer's framebuffer is always the window's size.
         #[cfg(target_os = "macos")]
-        return self.viewport_y_offset;
-        #[cfg(not(target_os = "macos"))]
-        return 0;
+        if self.software_framebuffer.is_none() {
+            return self.viewport_y_offset;
+        }
+        0
     }
 
     /// Transformation matrix for texture co-ordinates when sampling the
@@ -0,0 +1,106 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// This is a main file for the GraphicsTestApp which is used for golden-image
+// integration testing. Unlike TestApp, it runs a UIKit event loop and draws
+// things on the screen, which tests/integration.rs captures and compares with
+// the reference images in tests/golden/. See also tests/README.md.
+//
+// The app has two visual states: the initial one, and one after a timer fires
+// at 0.25s, which changes some of the views. It exits after 0.5s. touchHLE is
+// run with the virtual clock so that the frames produced are deterministic.
+
+#include "system_headers_uikit.h"
+
+// A view that draws overlapping rectangles into its CGBitmapContext, to test
+// Core Graphics blending.
+@interface BlendView : UIView
+@end
+
+@implementation BlendView
+- (void)drawRect:(CGRect)rect {
+  CGContextRef context = UIGraphicsGetCurrentContext();
+  CGContextSetRGBFillColor(context, 1.0, 0.0, 0.0, 1.0);
+  CGContextFillRect(context, CGRectMake(0, 0, 80, 80));
+  CGContextSetRGBFillColor(context, 0.0, 0.0, 1.0, 0.5);
+  CGContextFillRect(context, CGRectMake(40, 40, 80, 80));
+  CGContextSetRGBFillColor(context, 0.0, 1.0, 0.0, 0.25);
+  CGContextFillRect(context, CGRectMake(20, 60, 100, 20));
+}
+@end
+
+@interface AppDelegate : NSObject {
+  UIWindow *window;
+  UILabel *label;
+  UIView *translucentView;
+  int ticks;
+}
+@end
+
+@implementation AppDelegate
+- (void)applicationDidFinishLaunching:(UIApplication *)application {
+  window = [[UIWindow alloc] initWithFrame:CGRectMake(0, 0, 320, 480)];
+  [window setBackgroundColor:[UIColor colorWithRed:0.2
+                                             green:0.3
+                                              blue:0.4
+                                             alpha:1.0]];
+
+  BlendView *blendView =
+      [[BlendView alloc] initWithFrame:CGRectMake(20, 20, 120, 120)];
+  [blendView setOpaque:false];
+  [blendView setBackgroundColor:[UIColor clearColor]];
+  [window addSubview:blendView];
+  [blendView release];
+
+  // A semi-transparent view overlapping another view, to test CALayer
+  // compositing with opacity.
+  UIView *opaqueView =
+      [[UIView alloc] initWithFrame:CGRectMake(180, 20, 100, 100)];
+  [opaqueView setBackgroundColor:[UIColor whiteColor]];
+  [window addSubview:opaqueView];
+  [opaqueView release];
+  translucentView = [[UIView alloc] initWithFrame:CGRectMake(220, 60, 80, 80)];
+  [translucentView setBackgroundColor:[UIColor colorWithRed:1.0
+                                                      green:0.5
+                                                       blue:0.0
+                                                      alpha:1.0]];
+  [translucentView setAlpha:0.5];
+  [window addSubview:translucentView];
+
+  label = [[UILabel alloc] initWithFrame:CGRectMake(20, 200, 280, 40)];
+  [label setBackgroundColor:[UIColor blackColor]];
+  [label setTextColor:[UIColor whiteColor]];
+  [label setFont:[UIFont boldSystemFontOfSize:24]];
+  [label setText:@"Hello, touchHLE!"];
+  [window addSubview:label];
+
+  [window makeKeyAndVisible];
+
+  [NSTimer scheduledTimerWithTimeInterval:0.25
+                                   target:self
+                                 selector:@selector(tick:)
+                                 userInfo:nil
+                                  repeats:true];
+}
+
+- (void)tick:(NSTimer *)timer {
+  ticks++;
+  if (ticks == 1) {
+    [label setText:@"Goodbye!"];
+    [translucentView setCenter:(CGPoint){160, 360}];
+    [translucentView setAlpha:0.75];
+  } else {
+    exit(0);
+  }
+}
+@end
+
+// See TestApp's main.m for why this is the entry point.
+int main() {
+  UIApplicationMain(0, NULL, nil, @"AppDelegate");
+  // UIApplicationMain never returns.
+  exit(1);
+}
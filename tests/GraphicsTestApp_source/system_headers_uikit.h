@@ -0,0 +1,96 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+#ifndef TOUCHHLE_UIKIT_SYSTEM_H
+#define TOUCHHLE_UIKIT_SYSTEM_H
+
+#include "../TestApp_source/system_headers_objc.h"
+
+#define nil ((id)0)
+
+// Core Graphics:
+typedef float CGFloat;
+typedef struct {
+  CGFloat x, y;
+} CGPoint;
+typedef struct {
+  CGFloat width, height;
+} CGSize;
+typedef struct {
+  CGPoint origin;
+  CGSize size;
+} CGRect;
+typedef struct CGContext *CGContextRef;
+
+static inline CGRect CGRectMake(CGFloat x, CGFloat y, CGFloat width,
+                                CGFloat height) {
+  CGRect rect = {{x, y}, {width, height}};
+  return rect;
+}
+
+void CGContextSetRGBFillColor(CGContextRef, CGFloat, CGFloat, CGFloat,
+                              CGFloat);
+void CGContextFillRect(CGContextRef, CGRect);
+
+// Foundation:
+typedef double NSTimeInterval;
+
+@interface NSString : NSObject
+@end
+
+@interface NSTimer : NSObject
++ (id)scheduledTimerWithTimeInterval:(NSTimeInterval)interval
+                              target:(id)target
+                            selector:(SEL)selector
+                            userInfo:(id)userInfo
+                             repeats:(BOOL)repeats;
+@end
+
+// UIKit:
+int UIApplicationMain(int, char **, NSString *, NSString *);
+CGContextRef UIGraphicsGetCurrentContext(void);
+
+@interface UIColor : NSObject
++ (UIColor *)colorWithRed:(CGFloat)red
+                    green:(CGFloat)green
+                     blue:(CGFloat)blue
+                    alpha:(CGFloat)alpha;
++ (UIColor *)clearColor;
++ (UIColor *)blackColor;
++ (UIColor *)whiteColor;
+@end
+
+@interface UIFont : NSObject
++ (UIFont *)systemFontOfSize:(CGFloat)size;
++ (UIFont *)boldSystemFontOfSize:(CGFloat)size;
+@end
+
+@interface UIView : NSObject
++ (id)alloc;
+- (id)initWithFrame:(CGRect)frame;
+- (void)release;
+- (void)addSubview:(UIView *)view;
+- (void)setBackgroundColor:(UIColor *)color;
+- (void)setAlpha:(CGFloat)alpha;
+- (void)setOpaque:(BOOL)opaque;
+- (void)setHidden:(BOOL)hidden;
+- (void)setCenter:(CGPoint)center;
+- (void)setNeedsDisplay;
+@end
+
+@interface UIWindow : UIView
+- (void)makeKeyAndVisible;
+@end
+
+@interface UILabel : UIView
+- (void)setText:(NSString *)text;
+- (void)setTextColor:(UIColor *)color;
+- (void)setFont:(UIFont *)font;
+@end
+
+@interface UIApplication : NSObject
+@end
+
+#endif // TOUCHHLE_UIKIT_SYSTEM_H
@@ -707,6 +707,229 @@ int test_realpath() {
   return 0;
 }
 
+// Plays a tone, which test_app_audio_capture in integration.rs looks for in
+// the captured audio, and waits for it to finish. The tone is a 441Hz square
+// wave, sampled at 22050Hz, that lasts 0.2s.
+#define TONE_SAMPLE_RATE 22050
+#define TONE_SAMPLES 4410
+int test_OpenAL() {
+  ALCdevice *device = alcOpenDevice(NULL);
+  if (device == NULL)
+    return -1;
+  ALCcontext *context = alcCreateContext(device, NULL);
+  if (context == NULL || !alcMakeContextCurrent(context))
+    return -2;
+
+  static short samples[TONE_SAMPLES];
+  for (int i = 0; i < TONE_SAMPLES; i++) {
+    samples[i] = (i % 50) < 25 ? 8000 : -8000;
+  }
+  ALuint buffer, source;
+  alGenBuffers(1, &buffer);
+  alBufferData(buffer, AL_FORMAT_MONO16, samples, sizeof(samples),
+               TONE_SAMPLE_RATE);
+  alGenSources(1, &source);
+  alSourcei(source, AL_BUFFER, buffer);
+  alSourcePlay(source);
+  int res = alGetError() == AL_NO_ERROR ? 0 : -3;
+
+  // Give up after 2 seconds.
+  ALint state = AL_PLAYING;
+  for (int i = 0; res == 0 && state == AL_PLAYING && i < 200; i++) {
+    usleep(10000);
+    alGetSourcei(source, AL_SOURCE_STATE, &state);
+  }
+  if (res == 0 && state != AL_STOPPED)
+    res = -4;
+
+  alDeleteSources(1, &source);
+  alDeleteBuffers(1, &buffer);
+  alcMakeContextCurrent(NULL);
+  alcDestroyContext(context);
+  alcCloseDevice(device);
+  return res;
+}
+
+// An object graph node with one of each kind of value, for
+// test_NSKeyedArchiver.
+@interface ArchiveTester : NSObject {
+@public
+  NSInteger number;
+  double fraction;
+  CGPoint point;
+  NSString *name;
+  NSArray *children;
+}
+@end
+
+@implementation ArchiveTester
+- (void)encodeWithCoder:(NSCoder *)coder {
+  [coder encodeInteger:number forKey:@"number"];
+  [coder encodeDouble:fraction forKey:@"fraction"];
+  [coder encodeCGPoint:point forKey:@"point"];
+  [coder encodeObject:name forKey:@"name"];
+  [coder encodeObject:children forKey:@"children"];
+}
+- (id)initWithCoder:(NSCoder *)coder {
+  self = [super init];
+  number = [coder decodeIntegerForKey:@"number"];
+  fraction = [coder decodeDoubleForKey:@"fraction"];
+  point = [coder decodeCGPointForKey:@"point"];
+  name = [[coder decodeObjectForKey:@"name"] retain];
+  children = [[coder decodeObjectForKey:@"children"] retain];
+  return self;
+}
+- (void)dealloc {
+  [name release];
+  [children release];
+  [super dealloc];
+}
+@end
+
+int check_unarchived_graph(ArchiveTester *original, ArchiveTester *root) {
+  if (root == nil || root == original)
+    return -1;
+  if (root->number != 42 || root->fraction != 0.5 || root->point.x != 1.5 ||
+      root->point.y != -2 || ![root->name isEqualToString:@"root"])
+    return -2;
+  if ([root->children count] != 3)
+    return -3;
+  ArchiveTester *child = [root->children objectAtIndex:0];
+  if (child->number != 7 || ![child->name isEqualToString:@"child"] ||
+      child->children != nil)
+    return -4;
+  // An object that was archived twice is unarchived once.
+  if ([root->children objectAtIndex:1] != child)
+    return -5;
+  if ([[root->children objectAtIndex:2] longLongValue] != 1234567890123LL)
+    return -6;
+  return 0;
+}
+
+int test_NSKeyedArchiver() {
+  NSAutoreleasePool *pool = [NSAutoreleasePool new];
+
+  ArchiveTester *child = [ArchiveTester new];
+  child->number = 7;
+  child->name = [@"child" retain];
+  ArchiveTester *root = [ArchiveTester new];
+  root->number = 42;
+  root->fraction = 0.5;
+  root->point = (CGPoint){1.5, -2};
+  root->name = [@"root" retain];
+  NSNumber *big_number = [NSNumber numberWithLongLong:1234567890123LL];
+  root->children =
+      [[NSArray arrayWithObjects:child, child, big_number, nil] retain];
+  [child release];
+
+  NSData *data = [NSKeyedArchiver archivedDataWithRootObject:root];
+  ArchiveTester *copy = [NSKeyedUnarchiver unarchiveObjectWithData:data];
+  int res = check_unarchived_graph(root, copy);
+
+  // The same, but through a file.
+  NSString *path = [NSHomeDirectory()
+      stringByAppendingPathComponent:@"Documents/archive_test.plist"];
+  if (res == 0 && ![NSKeyedArchiver archiveRootObject:root toFile:path]) {
+    res = -10;
+  }
+  if (res == 0) {
+    data = [NSData dataWithContentsOfFile:path];
+    copy = [NSKeyedUnarchiver unarchiveObjectWithData:data];
+    res = check_unarchived_graph(root, copy);
+    if (res != 0) {
+      res -= 10;
+    }
+  }
+
+  [root release];
+  [pool drain];
+  return res;
+}
+
+static int check_user_defaults(NSString *path) {
+  // The saved defaults are loaded when they're first used, so this must run
+  // before anything else touches them.
+  NSDictionary *saved =
+      [NSDictionary dictionaryWithObjectsAndKeys:@"saved", @"Saved", nil];
+  if (![saved writeToFile:path atomically:true]) {
+    return -1;
+  }
+  NSUserDefaults *defaults = [NSUserDefaults standardUserDefaults];
+  if (![[defaults stringForKey:@"Saved"] isEqualToString:@"saved"]) {
+    return -2;
+  }
+
+  // Registered defaults are used when there's no saved value.
+  [defaults registerDefaults:[NSDictionary dictionaryWithObjectsAndKeys:
+                                               @"registered", @"Saved",
+                                               [NSNumber numberWithLongLong:5],
+                                               @"Registered", nil]];
+  if ([defaults integerForKey:@"Registered"] != 5 ||
+      ![[defaults stringForKey:@"Saved"] isEqualToString:@"saved"]) {
+    return -3;
+  }
+  [defaults setInteger:7 forKey:@"Registered"];
+  if ([defaults integerForKey:@"Registered"] != 7) {
+    return -4;
+  }
+  [defaults removeObjectForKey:@"Registered"];
+  [defaults removeObjectForKey:@"Saved"];
+  if ([defaults integerForKey:@"Registered"] != 5 ||
+      ![[defaults stringForKey:@"Saved"] isEqualToString:@"registered"]) {
+    return -5;
+  }
+
+  // Typed accessors.
+  [defaults setBool:true forKey:@"Bool"];
+  [defaults setFloat:1.5f forKey:@"Float"];
+  [defaults setDouble:0.25 forKey:@"Double"];
+  [defaults setObject:@"42" forKey:@"String"];
+  [defaults setObject:[NSArray arrayWithObjects:@"a", @"b", nil]
+               forKey:@"Array"];
+  if (![defaults boolForKey:@"Bool"] ||
+      [defaults floatForKey:@"Float"] != 1.5f ||
+      [defaults doubleForKey:@"Double"] != 0.25 ||
+      [[defaults arrayForKey:@"Array"] count] != 2) {
+    return -6;
+  }
+  // Scalars can be read from strings, but the object accessors check the
+  // class.
+  if ([defaults integerForKey:@"String"] != 42 ||
+      [defaults arrayForKey:@"String"] != nil ||
+      [defaults stringForKey:@"Array"] != nil) {
+    return -7;
+  }
+  if ([defaults boolForKey:@"Missing"] ||
+      [defaults integerForKey:@"Missing"] != 0 ||
+      [defaults objectForKey:@"Missing"] != nil) {
+    return -8;
+  }
+
+  // Only the saved values are written out, not the registered ones.
+  if (![defaults synchronize]) {
+    return -9;
+  }
+  NSDictionary *reloaded = [NSDictionary dictionaryWithContentsOfFile:path];
+  if (![[reloaded objectForKey:@"Bool"] boolValue] ||
+      [[reloaded objectForKey:@"Double"] doubleValue] != 0.25 ||
+      ![[reloaded objectForKey:@"String"] isEqualToString:@"42"] ||
+      [reloaded objectForKey:@"Saved"] != nil ||
+      [reloaded objectForKey:@"Registered"] != nil) {
+    return -10;
+  }
+  return 0;
+}
+
+int test_NSUserDefaults() {
+  NSAutoreleasePool *pool = [NSAutoreleasePool new];
+  NSString *path = [NSHomeDirectory()
+      stringByAppendingPathComponent:
+          @"Library/Preferences/com.yourcompany.TestApp.plist"];
+  int res = check_user_defaults(path);
+  [pool drain];
+  return res;
+}
+
 #define FUNC_DEF(func)                                                         \
   { &func, #func }
 struct {
@@ -723,6 +946,8 @@ struct {
     FUNC_DEF(test_strtoul),  FUNC_DEF(test_dirent),
     FUNC_DEF(test_strchr),   FUNC_DEF(test_swprintf),
     FUNC_DEF(test_realpath), FUNC_DEF(test_synchronized),
+    FUNC_DEF(test_NSKeyedArchiver), FUNC_DEF(test_NSUserDefaults),
+    FUNC_DEF(test_OpenAL),
 };
 
 // Because no libc is linked into this executable, there is no libc entry point
@@ -114,6 +114,36 @@ DIR *opendir(const char *);
 struct dirent *readdir(DIR *);
 int closedir(DIR *);
 
+// <OpenAL/al.h> and <OpenAL/alc.h>
+typedef struct ALCdevice ALCdevice;
+typedef struct ALCcontext ALCcontext;
+typedef char ALCboolean;
+typedef int ALCint;
+typedef int ALint;
+typedef unsigned int ALuint;
+typedef int ALsizei;
+typedef int ALenum;
+#define AL_NO_ERROR 0
+#define AL_BUFFER 0x1009
+#define AL_SOURCE_STATE 0x1010
+#define AL_PLAYING 0x1012
+#define AL_STOPPED 0x1014
+#define AL_FORMAT_MONO16 0x1101
+ALCdevice *alcOpenDevice(const char *);
+ALCboolean alcCloseDevice(ALCdevice *);
+ALCcontext *alcCreateContext(ALCdevice *, const ALCint *);
+void alcDestroyContext(ALCcontext *);
+ALCboolean alcMakeContextCurrent(ALCcontext *);
+ALenum alGetError(void);
+void alGenSources(ALsizei, ALuint *);
+void alDeleteSources(ALsizei, const ALuint *);
+void alSourcei(ALuint, ALenum, ALint);
+void alGetSourcei(ALuint, ALenum, ALint *);
+void alSourcePlay(ALuint);
+void alGenBuffers(ALsizei, ALuint *);
+void alDeleteBuffers(ALsizei, const ALuint *);
+void alBufferData(ALuint, ALenum, const void *, ALsizei, ALsizei);
+
 // Stuff from various Core Graphics headers.
 #ifdef DEFINE_ME_WHEN_BUILDING_ON_MACOS
 typedef double CGFloat; // 64-bit definition (not supported by touchHLE)
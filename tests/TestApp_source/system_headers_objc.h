@@ -12,6 +12,7 @@ typedef struct objc_class *Class;
 typedef struct objc_object {
   Class isa;
 } *id;
+#define nil ((id)0)
 
 id objc_msgSend(id, SEL, ...);
 
@@ -20,6 +21,86 @@ id objc_msgSend(id, SEL, ...);
 }
 + (id)new;
 - (id)init;
+- (id)retain;
+- (void)release;
+- (void)dealloc;
 
+@end
+
+// Foundation:
+typedef int NSInteger;
+typedef unsigned int NSUInteger;
+
+@interface NSAutoreleasePool : NSObject
+- (void)drain;
+@end
+
+@interface NSString : NSObject
+- (BOOL)isEqualToString:(NSString *)other;
+- (NSString *)stringByAppendingPathComponent:(NSString *)component;
+@end
+NSString *NSHomeDirectory(void);
+
+@interface NSNumber : NSObject
++ (NSNumber *)numberWithLongLong:(long long)value;
+- (BOOL)boolValue;
+- (double)doubleValue;
+- (long long)longLongValue;
+@end
+
+@interface NSArray : NSObject
++ (id)arrayWithObjects:(id)first, ...;
+- (NSUInteger)count;
+- (id)objectAtIndex:(NSUInteger)index;
+@end
+
+@interface NSDictionary : NSObject
++ (id)dictionaryWithObjectsAndKeys:(id)first, ...;
++ (id)dictionaryWithContentsOfFile:(NSString *)path;
+- (id)objectForKey:(id)key;
+- (BOOL)writeToFile:(NSString *)path atomically:(BOOL)atomically;
+@end
+
+@interface NSData : NSObject
++ (id)dataWithContentsOfFile:(NSString *)path;
+@end
+
+@interface NSCoder : NSObject
+- (void)encodeObject:(id)object forKey:(NSString *)key;
+- (void)encodeInteger:(NSInteger)value forKey:(NSString *)key;
+- (void)encodeDouble:(double)value forKey:(NSString *)key;
+- (void)encodeCGPoint:(CGPoint)point forKey:(NSString *)key;
+- (id)decodeObjectForKey:(NSString *)key;
+- (NSInteger)decodeIntegerForKey:(NSString *)key;
+- (double)decodeDoubleForKey:(NSString *)key;
+- (CGPoint)decodeCGPointForKey:(NSString *)key;
+@end
+
+@interface NSKeyedArchiver : NSCoder
++ (NSData *)archivedDataWithRootObject:(id)object;
++ (BOOL)archiveRootObject:(id)object toFile:(NSString *)path;
+@end
+
+@interface NSKeyedUnarchiver : NSCoder
++ (id)unarchiveObjectWithData:(NSData *)data;
+@end
+
+@interface NSUserDefaults : NSObject
++ (NSUserDefaults *)standardUserDefaults;
+- (void)registerDefaults:(NSDictionary *)defaults;
+- (BOOL)synchronize;
+- (id)objectForKey:(NSString *)key;
+- (NSString *)stringForKey:(NSString *)key;
+- (NSArray *)arrayForKey:(NSString *)key;
+- (BOOL)boolForKey:(NSString *)key;
+- (NSInteger)integerForKey:(NSString *)key;
+- (float)floatForKey:(NSString *)key;
+- (double)doubleForKey:(NSString *)key;
+- (void)setObject:(id)object forKey:(NSString *)key;
+- (void)removeObjectForKey:(NSString *)key;
+- (void)setBool:(BOOL)value forKey:(NSString *)key;
+- (void)setInteger:(NSInteger)value forKey:(NSString *)key;
+- (void)setFloat:(float)value forKey:(NSString *)key;
+- (void)setDouble:(double)value forKey:(NSString *)key;
 @end
 #endif // TOUCHHLE_OBJC_SYSTEM_H
@@ -1,9 +1,12 @@
 use std::env;
 use std::env::current_dir;
 use std::error::Error;
+use std::ffi::{c_int, c_void};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Mutex;
+use touchHLE_stb_image_wrapper::{stbi_image_free, stbi_load_from_memory};
 
 // adapted from `assert_cmd` crate
 fn target_dir() -> PathBuf {
@@ -26,12 +29,22 @@ fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
         .position(|window| window == needle)
 }
 
+/// Names of the test apps [build_test_app] has already built. Tests run in
+/// parallel, and rebuilding an app while another test runs it would be racy,
+/// so each app is only built once per run.
+static BUILT_TEST_APPS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
 fn build_test_app(
     tests_dir: &Path,
     test_app_name: &str,
     sources: &[&Path],
     extra_compile_args: &[&str],
 ) -> Result<(), Box<dyn Error>> {
+    let mut built_test_apps = BUILT_TEST_APPS.lock().unwrap();
+    if built_test_apps.iter().any(|name| name == test_app_name) {
+        return Ok(());
+    }
+
     let clang_path = tests_dir
         .join("llvm")
         .join("bin")
@@ -78,12 +91,8 @@ fn build_test_app(
                 .join(file)
         }))
         // Needed for dyld_stub_binding_helper, which is normally provided
-        // in crt1.o but has to be added here.
-        .arg(
-            tests_dir
-                .join(format!("{}_source", test_app_name))
-                .join("crt1.c"),
-        )
+        // in crt1.o but has to be added here. All test apps share TestApp's.
+        .arg(tests_dir.join("TestApp_source").join("crt1.c"))
         // Write the output to the bundle.
         .arg("-o")
         .arg(test_bin_path)
@@ -96,6 +105,7 @@ fn build_test_app(
     assert!(output.status.success());
 
     eprintln!("Built successfully.");
+    built_test_apps.push(test_app_name.to_string());
 
     Ok(())
 }
@@ -104,13 +114,14 @@ fn build_test_app(
 // "{tests_dir}/{test_app_name}_source"
 // and binaries are output as
 // "{tests_dir}/{test_app_name}.app/{test_app_name}".
-// You also need crt1.c in "{tests_dir}/{test_app_name}_source/"
+// You also need crt1.c in "{tests_dir}/TestApp_source/"
 // and a copy of llvm/ in {tests_dir}/.
 fn run_test_app(
     tests_dir: &Path,
     test_app_name: &str,
     sources: &[&Path],
     extra_compile_args: &[&str],
+    touchhle_args: &[&str],
 ) -> Result<(), Box<dyn Error>> {
     let test_app_path = tests_dir.join(format!("{}.app", test_app_name));
     build_test_app(&tests_dir, &test_app_name, sources, extra_compile_args)?;
@@ -122,9 +133,7 @@ fn run_test_app(
 
     let output = cmd
         .arg(test_app_path)
-        // headless mode avoids a distracting window briefly appearing during
-        // testing, and works in CI.
-        .arg("--headless")
+        .args(touchhle_args)
         .output()
         .expect("failed to execute touchHLE process");
 
@@ -141,8 +150,7 @@ fn run_test_app(
     Ok(())
 }
 
-#[test]
-fn test_app() -> Result<(), Box<dyn Error>> {
+fn compile_args() -> Result<Vec<String>, Box<dyn Error>> {
     // By default this uses the system linker, which is expected to ld for Mac
     // OS. By setting TOUCHHLE_LINKER to point to a ported (Apple) ld, you can
     // also build on linux (and potentially windows).
@@ -151,23 +159,322 @@ fn test_app() -> Result<(), Box<dyn Error>> {
     });
 
     let libs_dir = "-L".to_owned() + current_dir()?.join("touchHLE_dylibs").to_str().unwrap();
-    let extra_compile_args = [
-        linker_path.as_str(),
+    Ok(vec![
+        linker_path,
         // ARC is not available until IOS 5, so it can't be used.
-        "-fno-objc-arc",
-        "-fno-objc-arc-exceptions",
+        "-fno-objc-arc".to_owned(),
+        "-fno-objc-arc-exceptions".to_owned(),
         // For some reason, we need to manually patch in the
         // libgcc_s dependency.
-        "-lgcc_s.1",
-        "-fobjc-link-runtime",
-        libs_dir.as_str(),
-        "-ObjC",
-    ];
+        "-lgcc_s.1".to_owned(),
+        "-fobjc-link-runtime".to_owned(),
+        libs_dir,
+        "-ObjC".to_owned(),
+    ])
+}
+
+/// Decode a PNG file to RGBA8 pixels.
+fn load_png(path: &Path) -> Result<(u32, u32, Vec<u8>), Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    let (mut width, mut height, mut _channels): (c_int, c_int, c_int) = (0, 0, 0);
+    unsafe {
+        let pixels = stbi_load_from_memory(
+            bytes.as_ptr(),
+            bytes.len().try_into().unwrap(),
+            &mut width,
+            &mut height,
+            &mut _channels,
+            4,
+        );
+        if pixels.is_null() {
+            return Err(format!("Couldn't decode {}", path.display()).into());
+        }
+        let len = width as usize * height as usize * 4;
+        let vec = std::slice::from_raw_parts(pixels, len).to_vec();
+        stbi_image_free(pixels as *mut c_void);
+        Ok((width as u32, height as u32, vec))
+    }
+}
+
+/// How much a color channel of a pixel may differ from the reference image
+/// before the pixel is considered to not match.
+const GOLDEN_IMAGE_TOLERANCE: u8 = 2;
+/// What fraction of pixels may not match before the comparison fails. This
+/// allows for small differences in anti-aliasing.
+const GOLDEN_IMAGE_MAX_MISMATCH: f64 = 0.001;
+
+/// Compare a frame dumped by touchHLE with a reference image in tests/golden/.
+///
+/// If the `TOUCHHLE_BLESS` environment variable is set, the frame becomes the
+/// new reference image instead. A missing reference image is a failure
+/// otherwise.
+fn compare_with_golden(frame_path: &Path, golden_path: &Path) -> Result<(), Box<dyn Error>> {
+    if env::var_os("TOUCHHLE_BLESS").is_some() {
+        eprintln!(
+            "Saving {} as the reference image {}. Please check it's correct!",
+            frame_path.display(),
+            golden_path.display()
+        );
+        std::fs::create_dir_all(golden_path.parent().unwrap())?;
+        std::fs::copy(frame_path, golden_path)?;
+        return Ok(());
+    }
+    if !golden_path.exists() {
+        return Err(format!(
+            "The reference image {} is missing. Run with TOUCHHLE_BLESS=1 to \
+             create it from {}, and check it's correct before committing it.",
+            golden_path.display(),
+            frame_path.display()
+        )
+        .into());
+    }
+
+    let (frame_width, frame_height, frame) = load_png(frame_path)?;
+    let (golden_width, golden_height, golden) = load_png(golden_path)?;
+    if (frame_width, frame_height) != (golden_width, golden_height) {
+        return Err(format!(
+            "{} is {}x{}, but the reference image {} is {}x{}",
+            frame_path.display(),
+            frame_width,
+            frame_height,
+            golden_path.display(),
+            golden_width,
+            golden_height
+        )
+        .into());
+    }
+
+    let mismatched = frame
+        .chunks_exact(4)
+        .zip(golden.chunks_exact(4))
+        .filter(|(a, b)| {
+            a.iter()
+                .zip(b.iter())
+                .any(|(&a, &b)| a.abs_diff(b) > GOLDEN_IMAGE_TOLERANCE)
+        })
+        .count();
+    let total = frame_width as usize * frame_height as usize;
+    if mismatched as f64 > total as f64 * GOLDEN_IMAGE_MAX_MISMATCH {
+        return Err(format!(
+            "{} differs from the reference image {} in {} of {} pixels",
+            frame_path.display(),
+            golden_path.display(),
+            mismatched,
+            total
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Check the header of a WAV file written by `--capture-audio=` and get its
+/// interleaved samples.
+fn read_captured_wav(path: &Path) -> Result<Vec<i16>, Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    if bytes.get(0..4) != Some(b"RIFF") || bytes.get(8..12) != Some(b"WAVE") {
+        return Err(format!("{} is not a WAV file", path.display()).into());
+    }
+    let riff_size = u32::from_le_bytes(bytes[4..8].try_into()?) as usize;
+    if riff_size + 8 != bytes.len() {
+        return Err(format!(
+            "RIFF size {} doesn't match file size {}",
+            riff_size,
+            bytes.len()
+        )
+        .into());
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut chunks = &bytes[12..];
+    while chunks.len() >= 8 {
+        let size = u32::from_le_bytes(chunks[4..8].try_into()?) as usize;
+        let chunk = chunks.get(8..8 + size).ok_or("Truncated WAV chunk")?;
+        match &chunks[0..4] {
+            b"fmt " => format = Some(chunk),
+            b"data" => data = Some(chunk),
+            _ => (),
+        }
+        // Chunks are padded to an even size.
+        chunks = &chunks[(8 + size + size % 2).min(chunks.len())..];
+    }
+    let format = format.ok_or("No format chunk in WAV file")?;
+    let data = data.ok_or("No data chunk in WAV file")?;
+
+    // 16-bit stereo 44100Hz PCM, as documented for --capture-audio=.
+    let u16_at = |offset: usize| u16::from_le_bytes([format[offset], format[offset + 1]]);
+    if format.len() < 16
+        || u16_at(0) != 1
+        || u16_at(2) != 2
+        || u32::from_le_bytes(format[4..8].try_into()?) != 44100
+        || u16_at(14) != 16
+    {
+        return Err(format!("Unexpected WAV format: {:?}", format).into());
+    }
+    Ok(data
+        .chunks_exact(2)
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect())
+}
+
+#[test]
+fn test_app() -> Result<(), Box<dyn Error>> {
+    let extra_compile_args = compile_args()?;
+    let extra_compile_args: Vec<&str> = extra_compile_args.iter().map(|s| s.as_str()).collect();
+
+    let sources = ["main.m", "SyncTester.m"].map(Path::new);
+
+    let tests_dir = current_dir()?.join("tests");
+
+    let wav_path = target_dir().join("TestApp_audio.wav");
+    if wav_path.exists() {
+        std::fs::remove_file(&wav_path)?;
+    }
+    let capture_audio_arg = format!("--capture-audio={}", wav_path.to_str().unwrap());
+
+    let test_app_name = "TestApp";
+    run_test_app(
+        &tests_dir,
+        test_app_name,
+        &sources,
+        &extra_compile_args,
+        // headless mode avoids a distracting window briefly appearing during
+        // testing, and works in CI. The same goes for capturing the audio
+        // instead of playing it.
+        &["--headless", &capture_audio_arg],
+    )?;
+
+    let samples = read_captured_wav(&wav_path)?;
+    // test_OpenAL plays a 0.2s tone. Everything else should be silent.
+    let tone_frames = 44100 / 5;
+    let frame_count = samples.len() / 2;
+    if frame_count < tone_frames {
+        return Err(format!("Only {} frames of audio were captured", frame_count).into());
+    }
+    let is_loud = |sample: &i16| sample.unsigned_abs() > 1000;
+    let first = samples
+        .iter()
+        .position(is_loud)
+        .ok_or("The tone is missing")?
+        / 2;
+    let last = samples.iter().rposition(is_loud).unwrap() / 2;
+    // Allow for a period of the tone (100 frames) plus some resampling error.
+    let tone_length = last - first + 1;
+    if tone_length.abs_diff(tone_frames) > 200 {
+        return Err(format!(
+            "The tone lasts {} frames, but it should last {}",
+            tone_length, tone_frames
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_app_compat_report() -> Result<(), Box<dyn Error>> {
+    let extra_compile_args = compile_args()?;
+    let extra_compile_args: Vec<&str> = extra_compile_args.iter().map(|s| s.as_str()).collect();
 
     let sources = ["main.m", "SyncTester.m"].map(|file| Path::new(file));
 
     let tests_dir = current_dir()?.join("tests");
 
     let test_app_name = "TestApp";
-    run_test_app(&tests_dir, &test_app_name, &sources, &extra_compile_args)
+    build_test_app(&tests_dir, test_app_name, &sources, &extra_compile_args)?;
+
+    let binary_path = target_dir().join(format!("touchHLE{}", env::consts::EXE_SUFFIX));
+    let output = Command::new(binary_path)
+        .arg(tests_dir.join(format!("{}.app", test_app_name)))
+        .arg("--compat-report")
+        .output()
+        .expect("failed to execute touchHLE process");
+
+    std::io::stderr().write_all(&output.stderr).unwrap();
+
+    assert!(output.status.success());
+    // The report should be the only thing on stdout.
+    let report = String::from_utf8(output.stdout)?;
+    assert!(report.starts_with('{') && report.trim_end().ends_with('}'));
+    assert!(report.contains(r#""bundle_identifier": "com.yourcompany.TestApp""#));
+    for key in [
+        "unimplemented_functions",
+        "unimplemented_symbols",
+        "unimplemented_classes",
+        "unimplemented_selectors",
+    ] {
+        assert!(report.contains(&format!("\"{}\": ", key)));
+    }
+
+    Ok(())
+}
+
+/// Frames of GraphicsTestApp to compare with reference images: the last frame
+/// presented before this virtual time (in seconds), and the reference image
+/// name. The app changes what it displays at 0.25s and exits at 0.5s.
+const GRAPHICS_TEST_APP_GOLDEN_FRAMES: &[(f64, &str)] = &[(0.25, "initial"), (0.5, "updated")];
+
+/// Find the last frame dumped by `--dump-frames=` that the app presented before
+/// `time` seconds of uptime, using the index file.
+fn find_frame_before(frames_dir: &Path, time: f64) -> Result<PathBuf, Box<dyn Error>> {
+    let index = std::fs::read_to_string(frames_dir.join("frames.txt"))?;
+    let mut found = None;
+    for line in index.lines() {
+        let (name, uptime) = line
+            .split_once(' ')
+            .ok_or_else(|| format!("Invalid line in frame index: {:?}", line))?;
+        // Splash screen frames aren't part of the app's timeline.
+        if uptime == "-" {
+            continue;
+        }
+        if uptime.parse::<f64>()? >= time {
+            break;
+        }
+        found = Some(name);
+    }
+    let name = found.ok_or_else(|| format!("No frames were presented before {}s", time))?;
+    Ok(frames_dir.join(name))
+}
+
+#[test]
+#[ignore = "reference images in tests/golden/ have not been blessed yet"]
+fn graphics_test_app() -> Result<(), Box<dyn Error>> {
+    let extra_compile_args = compile_args()?;
+    let extra_compile_args: Vec<&str> = extra_compile_args.iter().map(|s| s.as_str()).collect();
+
+    let sources = [Path::new("main.m")];
+
+    let tests_dir = current_dir()?.join("tests");
+
+    let frames_dir = target_dir().join("GraphicsTestApp_frames");
+    if frames_dir.exists() {
+        std::fs::remove_dir_all(&frames_dir)?;
+    }
+    let dump_frames_arg = format!("--dump-frames={}", frames_dir.to_str().unwrap());
+
+    let test_app_name = "GraphicsTestApp";
+    run_test_app(
+        &tests_dir,
+        test_app_name,
+        &sources,
+        &extra_compile_args,
+        &[
+            // The offscreen window doesn't appear and works in CI. Using the
+            // software renderer and the virtual clock makes the output
+            // independent of the host.
+            "--offscreen",
+            "--gles1=software",
+            "--virtual-clock",
+            &dump_frames_arg,
+        ],
+    )?;
+
+    for &(time, name) in GRAPHICS_TEST_APP_GOLDEN_FRAMES {
+        compare_with_golden(
+            &find_frame_before(&frames_dir, time)?,
+            &tests_dir
+                .join("golden")
+                .join(format!("{}_{}.png", test_app_name, name)),
+        )?;
+    }
+    Ok(())
 }